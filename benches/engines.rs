@@ -0,0 +1,117 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+
+use tofu_interpreter::builtins::Builtins;
+use tofu_interpreter::code::Bytecode;
+use tofu_interpreter::{compiler, peephole, vm, Evaluator, Lexer, Parser, Program, TokenKind};
+
+/// Calls and arithmetic, with little else going on.
+const FIB: &str = r#"
+let fib = fn(n) {
+    if (n < 2) { n } else { fib(n - 1) + fib(n - 2) }
+};
+
+fib(20)
+"#;
+
+/// Builds a long string a piece at a time, so the time goes on allocating
+/// and copying.
+const STRINGS: &str = r#"
+let line = fn(i) { format("line {}: ", i) + (i < 500 ? "early" : "late") + "\n" };
+
+let text = "";
+for (i in 0..1000) {
+    text += line(i);
+}
+len(text)
+"#;
+
+/// Passes arrays through builtins that call back into the program.
+const PIPELINE: &str = r#"
+let squares = map(0..2000, fn(x) { x * x });
+let even = filter(squares, fn(x) { x / 2 * 2 == x });
+reduce(even, 0, fn(total, x) { total + x })
+"#;
+
+const PROGRAMS: [(&str, &str); 3] = [("fib", FIB), ("strings", STRINGS), ("pipeline", PIPELINE)];
+
+fn lex_all(source: &str) -> usize {
+    let mut lexer = Lexer::new(source);
+    let mut count = 0;
+
+    while lexer.next_token().kind != TokenKind::Eof {
+        count += 1;
+    }
+
+    count
+}
+
+fn parse(source: &str) -> Program {
+    let mut parser = Parser::new(Lexer::new(source));
+    let program = parser.parse_program();
+    assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+    program
+}
+
+fn compile(program: &Program, builtins: &Builtins) -> Bytecode {
+    compiler::compile(program, builtins).expect("the program compiles")
+}
+
+fn bench_front_end(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lexer");
+    for (name, source) in PROGRAMS {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), source, |b, source| {
+            b.iter(|| lex_all(black_box(source)))
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("parser");
+    for (name, source) in PROGRAMS {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), source, |b, source| {
+            b.iter(|| parse(black_box(source)))
+        });
+    }
+    group.finish();
+
+    let builtins = Builtins::standard();
+    let mut group = c.benchmark_group("compiler");
+    for (name, source) in PROGRAMS {
+        let program = parse(source);
+        group.bench_with_input(BenchmarkId::from_parameter(name), &program, |b, program| {
+            b.iter(|| compile(black_box(program), &builtins))
+        });
+    }
+    group.finish();
+}
+
+fn bench_engines(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tree-walker");
+    for (name, source) in PROGRAMS {
+        let program = parse(source);
+        group.bench_with_input(BenchmarkId::from_parameter(name), &program, |b, program| {
+            b.iter(|| Evaluator::new().eval_program(black_box(program)).unwrap())
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("vm");
+    for (name, source) in PROGRAMS {
+        let bytecode = compile(&parse(source), &Builtins::standard());
+        let mut optimized = bytecode.clone();
+        peephole::optimize(&mut optimized);
+
+        group.bench_with_input(BenchmarkId::new(name, "plain"), &bytecode, |b, bytecode| {
+            b.iter(|| vm::run(&mut Evaluator::new(), black_box(bytecode)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new(name, "-O"), &optimized, |b, bytecode| {
+            b.iter(|| vm::run(&mut Evaluator::new(), black_box(bytecode)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_front_end, bench_engines);
+criterion_main!(benches);
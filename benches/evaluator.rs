@@ -0,0 +1,107 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::hint::black_box;
+
+use tofu_interpreter::{Evaluator, Lexer, Parser, Program};
+
+/// Looks names up in enclosing scopes and hashes up in a tight loop, so
+/// the time goes on finding bindings and keys rather than on arithmetic.
+const LOOKUPS: &str = r#"
+let width = 3;
+let height = 4;
+let depth = 5;
+let point = {"x": 1, "y": 2, "z": 3};
+
+let volume = fn(n) {
+    let total = 0;
+    let i = 0;
+    while (i < n) {
+        total = total + width * height * depth + point["x"] + point["y"] + point["z"];
+        i = i + 1;
+    }
+    total
+};
+
+volume(10000)
+"#;
+
+/// Calls builtins in a tight loop, so the time goes on finding them past
+/// the scopes around the call.
+const BUILTINS: &str = r#"
+let items = [1, 2, 3];
+
+let measure = fn(n) {
+    let total = 0;
+    let i = 0;
+    while (i < n) {
+        total = total + len(items) + abs(-i) + max(i, 1);
+        i = i + 1;
+    }
+    total
+};
+
+measure(10000)
+"#;
+
+/// Builds a string of `n` lines, by `s = s + piece` or with a buffer. Run
+/// at growing sizes, the buffer's time grows in step with `n`, while
+/// concatenation's grows with its square, since each `+` copies the whole
+/// string so far.
+fn build_report(n: usize, buffered: bool) -> String {
+    let body = if buffered {
+        r#"
+        let report = buf_new();
+        while (i < n) {
+            buf_push(report, "line ");
+            buf_push(report, i);
+            buf_push(report, "\n");
+            i = i + 1;
+        }
+        buf_str(report)
+        "#
+    } else {
+        r#"
+        let report = "";
+        while (i < n) {
+            report = report + "line " + format("{}", i) + "\n";
+            i = i + 1;
+        }
+        report
+        "#
+    };
+    format!("let build = fn(n) {{ let i = 0; {body} }}; len(build({n}))")
+}
+
+fn parse(source: &str) -> Program {
+    let mut parser = Parser::new(Lexer::new(source));
+    let program = parser.parse_program();
+    assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+    program
+}
+
+fn bench_evaluator(c: &mut Criterion) {
+    let lookups = parse(LOOKUPS);
+    let builtins = parse(BUILTINS);
+
+    let mut group = c.benchmark_group("evaluator");
+    group.bench_function("lookups", |b| {
+        b.iter(|| Evaluator::new().eval_program(black_box(&lookups)))
+    });
+    group.bench_function("builtins", |b| {
+        b.iter(|| Evaluator::new().eval_program(black_box(&builtins)))
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("string_building");
+    for n in [1_000, 2_000, 4_000, 8_000] {
+        for (name, buffered) in [("concat", false), ("buffer", true)] {
+            let program = parse(&build_report(n, buffered));
+            group.bench_with_input(BenchmarkId::new(name, n), &program, |b, program| {
+                b.iter(|| Evaluator::new().eval_program(black_box(program)))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_evaluator);
+criterion_main!(benches);
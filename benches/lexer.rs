@@ -0,0 +1,40 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::hint::black_box;
+
+use tofu_interpreter::{Lexer, TokenKind};
+
+const SNIPPET: &str = r#"
+// Well hello there!
+let one = 1;
+let three = 3.5e2;
+let greeting = "hello, world";
+
+let add = fn(x, y) {
+    /* sum the two */ x + y
+};
+
+if (add(one, three) >= 0xFF) { return true; } else { return false; }
+"#;
+
+fn lex_all(source: &str) -> usize {
+    let mut lexer = Lexer::new(source);
+    let mut count = 0;
+
+    while lexer.next_token().kind != TokenKind::Eof {
+        count += 1;
+    }
+
+    count
+}
+
+fn bench_lexer(c: &mut Criterion) {
+    let source = SNIPPET.repeat(1_000);
+
+    let mut group = c.benchmark_group("lexer");
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.bench_function("tokenize", |b| b.iter(|| lex_all(black_box(&source))));
+    group.finish();
+}
+
+criterion_group!(benches, bench_lexer);
+criterion_main!(benches);
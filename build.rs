@@ -0,0 +1,39 @@
+//! Records what plugins have to be built the same way as, and writes the
+//! C header for the `capi` feature's functions.
+
+fn main() {
+    set_build();
+    #[cfg(feature = "capi")]
+    write_header();
+}
+
+/// Sets `TOFU_BUILD`, for `plugin::BUILD`.
+fn set_build() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let version = std::process::Command::new(rustc)
+        .arg("--version")
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_default();
+    let bigint = if cfg!(feature = "bigint") {
+        " +bigint"
+    } else {
+        ""
+    };
+    let package = std::env::var("CARGO_PKG_VERSION").expect("Cargo should set the version");
+    println!("cargo:rustc-env=TOFU_BUILD=tofu {package} ({version}){bigint}");
+}
+
+#[cfg(feature = "capi")]
+fn write_header() {
+    println!("cargo:rerun-if-changed=src/capi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").expect("Cargo should set the crate's path");
+    let config = cbindgen::Config::from_root_or_default(&crate_dir);
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("Should have generated the C header")
+        .write_to_file(format!("{crate_dir}/include/tofu.h"));
+}
@@ -0,0 +1,151 @@
+#ifndef TOFU_H
+#define TOFU_H
+
+/* Generated from src/capi.rs by build.rs; don't edit it by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// A Tofu interpreter, from [`tofu_new`].
+typedef struct TofuInterpreter TofuInterpreter;
+
+// A Tofu value, from [`tofu_eval`] or one of the `tofu_value_` functions
+// that make them.
+typedef struct TofuValue TofuValue;
+
+// A function the application provides to Tofu scripts, registered with
+// [`tofu_register_fn`]. It's given the `data` it was registered with and
+// the `count` arguments of the call, which it only borrows. It returns a
+// new value, which Tofu takes, or else `NULL` after setting `*error` to a
+// message, which is copied before the function is called again.
+typedef struct TofuValue *(*TofuHostFn)(void *data,
+                                        const struct TofuValue *const *arguments,
+                                        size_t count,
+                                        const char **error);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// A new interpreter with the standard builtins and the prelude loaded, to
+// free with [`tofu_free`].
+struct TofuInterpreter *tofu_new(void);
+
+// Frees an interpreter from [`tofu_new`]. Values from it stay valid.
+//
+// # Safety
+//
+// `interpreter` must be from [`tofu_new`] and not already freed, or
+// `NULL`, which is ignored.
+void tofu_free(struct TofuInterpreter *interpreter);
+
+// Runs `source` in `interpreter`, keeping what it binds at the top level
+// for the source run after it, and returns the value of its last
+// statement. If it fails, returns `NULL` and, unless `error` is `NULL`,
+// sets `*error` to a message to free with [`tofu_string_free`].
+//
+// # Safety
+//
+// `interpreter` must be from [`tofu_new`], `source` a NUL-terminated
+// string, and `error` `NULL` or valid to write a pointer to.
+struct TofuValue *tofu_eval(struct TofuInterpreter *interpreter, const char *source, char **error);
+
+// Makes `function` callable from Tofu as `name`, with `data` passed to
+// each call. Calls must have `arity` arguments, or any number if it's
+// negative. Returns whether `name` could be used, which it can't if it's
+// not valid UTF-8.
+//
+// # Safety
+//
+// `interpreter` must be from [`tofu_new`] and `name` a NUL-terminated
+// string. `data` must stay valid for `function` as long as the
+// interpreter does.
+bool tofu_register_fn(struct TofuInterpreter *interpreter,
+                      const char *name,
+                      int arity,
+                      TofuHostFn function,
+                      void *data);
+
+// `value` as `print` would show it, to free with [`tofu_string_free`].
+//
+// # Safety
+//
+// `value` must be a valid value.
+char *tofu_value_to_string(const struct TofuValue *value);
+
+// The name of the type of `value`, like `"Int"`, which isn't to be freed.
+//
+// # Safety
+//
+// `value` must be a valid value.
+const char *tofu_value_type(const struct TofuValue *value);
+
+// Sets `*out` to `value` and returns `true` if it's an `Int` that fits in
+// 64 bits, or returns `false`.
+//
+// # Safety
+//
+// `value` must be a valid value and `out` valid to write to.
+bool tofu_value_as_int(const struct TofuValue *value, int64_t *out);
+
+// Sets `*out` to `value` and returns `true` if it's a number, or returns
+// `false`.
+//
+// # Safety
+//
+// `value` must be a valid value and `out` valid to write to.
+bool tofu_value_as_float(const struct TofuValue *value, double *out);
+
+// Sets `*out` to `value` and returns `true` if it's a `Bool`, or returns
+// `false`.
+//
+// # Safety
+//
+// `value` must be a valid value and `out` valid to write to.
+bool tofu_value_as_bool(const struct TofuValue *value, bool *out);
+
+// A new `Int`, to free with [`tofu_value_free`] unless it's given to Tofu.
+struct TofuValue *tofu_value_int(int64_t value);
+
+// A new `Float`, to free with [`tofu_value_free`] unless it's given to
+// Tofu.
+struct TofuValue *tofu_value_float(double value);
+
+// A new `Bool`, to free with [`tofu_value_free`] unless it's given to Tofu.
+struct TofuValue *tofu_value_bool(bool value);
+
+// A new `null`, to free with [`tofu_value_free`] unless it's given to Tofu.
+struct TofuValue *tofu_value_null(void);
+
+// A new `Str` with a copy of `text`, to free with [`tofu_value_free`]
+// unless it's given to Tofu. Invalid UTF-8 in `text` is replaced.
+//
+// # Safety
+//
+// `text` must be a NUL-terminated string.
+struct TofuValue *tofu_value_str(const char *text);
+
+// Frees a value.
+//
+// # Safety
+//
+// `value` must be a value this module made that hasn't been freed or
+// given to Tofu, or `NULL`, which is ignored.
+void tofu_value_free(struct TofuValue *value);
+
+// Frees a string this module returned.
+//
+// # Safety
+//
+// `text` must be a string from this module that hasn't been freed, or
+// `NULL`, which is ignored.
+void tofu_string_free(char *text);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TOFU_H */
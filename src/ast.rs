@@ -0,0 +1,1124 @@
+use std::cell::Cell;
+use std::fmt::{self, Display, Formatter};
+use std::rc::Rc;
+
+use serde::{Serialize, Serializer};
+
+use crate::symbol::Symbol;
+use crate::token::Span;
+
+/// A generous allowance of Rust stack for one level of any walk over an
+/// expression, enough for an unoptimized build.
+const STACK_PER_LEVEL: usize = 64 * 1024;
+
+/// How much stack a walk over an expression moves onto when what's left
+/// of the one it's on gets short.
+const STACK_SEGMENT: usize = 256 * STACK_PER_LEVEL;
+
+/// Runs `walk`, which recurses into an expression's operands, moving onto
+/// more stack first if it's running short. The parser limits how deeply
+/// expressions nest, but not how long a chain like `a + b + c` or `f()()`
+/// is, and each operator in one holds what came before it a level deeper,
+/// so anything that recurses into expressions does it through here.
+pub(crate) fn with_stack<R>(walk: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(STACK_PER_LEVEL, STACK_SEGMENT, walk)
+}
+
+/// Serializes what a chain of operators continues from through
+/// [`with_stack`], since the chain can be as deep as it is long.
+fn serialize_operand<T: Serialize, S: Serializer>(
+    operand: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    with_stack(|| operand.serialize(serializer))
+}
+
+#[derive(PartialEq, Debug, Clone, Default, Serialize)]
+pub struct Program {
+    pub statements: Vec<Statement>,
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub enum Statement {
+    Let(LetStatement),
+    Return(Option<Expression>),
+    Expression(Expression),
+    While {
+        condition: Expression,
+        body: BlockStatement,
+    },
+    For {
+        pattern: Pattern,
+        iterable: Expression,
+        body: BlockStatement,
+    },
+    Break,
+    Continue,
+    Import(Import),
+    /// `throw value`, which raises `value` for a `try` to catch.
+    Throw {
+        value: Expression,
+        span: Span,
+    },
+    /// `struct Point { x, y }`, which binds `name` to a constructor for
+    /// values with those fields. The span is the name's.
+    Struct {
+        name: Symbol,
+        fields: Vec<Symbol>,
+        span: Span,
+    },
+    /// `enum Shape { Circle(r), Rect(w, h), Empty }`, which binds each
+    /// variant's name: to a constructor if it has fields, and otherwise to
+    /// the one value of it there is. The span is the enum's name's.
+    Enum {
+        name: Symbol,
+        variants: Vec<VariantDeclaration>,
+        span: Span,
+    },
+}
+
+/// One of the variants an `enum` declares, with the names of its fields.
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct VariantDeclaration {
+    pub name: Symbol,
+    pub fields: Vec<Symbol>,
+}
+
+/// `import utils` or `import "lib/utils.tofu"`, which runs the module's file
+/// and binds a hash of its top-level bindings to `name`.
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct Import {
+    pub source: ImportSource,
+    /// The module's name, or its file name without the extension.
+    pub name: Symbol,
+    pub span: Span,
+}
+
+/// How an `import` names its module.
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub enum ImportSource {
+    /// `import utils`, which looks for `utils.tofu`.
+    Name(String),
+    /// `import "lib/utils.tofu"`, which looks for that file.
+    Path(String),
+}
+
+impl ImportSource {
+    /// The file to look for.
+    pub fn file(&self) -> String {
+        match self {
+            ImportSource::Name(name) => format!("{name}.tofu"),
+            ImportSource::Path(path) => path.clone(),
+        }
+    }
+}
+
+/// A `let` or `const` binding. Any `///` doc comments written directly above
+/// it are kept in `docs`, one entry per line.
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct LetStatement {
+    pub kind: DeclarationKind,
+    pub pattern: Pattern,
+    pub value: Expression,
+    pub docs: Vec<String>,
+    /// The type written after the pattern, as in `let age: int = 3`.
+    pub annotation: Option<TypeAnnotation>,
+    /// Where the pattern is.
+    pub span: Span,
+}
+
+/// A type written in the source, like `int`, `array<str>` or
+/// `fn(int) -> bool`. Annotations are optional, and the evaluator ignores
+/// them.
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub enum TypeAnnotation {
+    /// A type's name, with the types it's applied to in `<...>`, if any.
+    Named {
+        name: String,
+        arguments: Vec<TypeAnnotation>,
+        span: Span,
+    },
+    /// `fn(parameters) -> result`.
+    Function {
+        parameters: Vec<TypeAnnotation>,
+        result: Box<TypeAnnotation>,
+    },
+}
+
+/// A function's parameter list, `(a, b = 1, ...rest)`.
+#[derive(PartialEq, Debug, Clone, Default, Serialize)]
+pub struct Parameters {
+    /// The parameters before any rest parameter. Once one has a default,
+    /// so do all the ones after it.
+    pub positional: Vec<Parameter>,
+    /// The name after `...`, which is bound to an array of the arguments
+    /// left over once the positional parameters have theirs.
+    pub rest: Option<Symbol>,
+}
+
+impl Parameters {
+    /// How many arguments a call must pass, which is one for each
+    /// parameter without a default.
+    pub fn required(&self) -> usize {
+        self.positional
+            .iter()
+            .take_while(|parameter| parameter.default.is_none())
+            .count()
+    }
+}
+
+/// A parameter, with the value it takes when a call leaves it out, if any.
+/// The default is evaluated at each such call, in the scope of the
+/// function's body, so it can refer to the parameters before it.
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct Parameter {
+    pub name: Symbol,
+    /// The type written after the name, as in `fn(x: int)`.
+    pub annotation: Option<TypeAnnotation>,
+    pub default: Option<Expression>,
+    pub span: Span,
+}
+
+/// A `{ ... }` block. Its value is the value of its last statement.
+/// What a `let` or a `for` loop binds its value to.
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub enum Pattern {
+    Identifier(Symbol),
+    /// `[a, b]`, which matches an array of exactly that many elements.
+    Array(Vec<Pattern>),
+    /// `(a, b)`, which matches a tuple of exactly that many elements.
+    Tuple(Vec<Pattern>),
+    /// `{name, age: years}`, which binds the hash's `"name"` entry to `name`
+    /// and its `"age"` entry to `years`. Missing entries bind `null`.
+    Hash(Vec<(String, Pattern)>),
+    /// `Point { x, y: py }`, which matches only a `Point` struct, binding
+    /// its fields like a hash pattern. Naming a field it doesn't have is an
+    /// error rather than `null`.
+    Struct {
+        name: Symbol,
+        fields: Vec<(String, Pattern)>,
+    },
+    /// `Circle(r)`, which matches only an enum's `Circle` variant, binding
+    /// its fields in order. A variant without fields is matched by its bare
+    /// name in a `match`.
+    Variant {
+        name: Symbol,
+        fields: Vec<Pattern>,
+    },
+    /// `_`, which matches anything and binds nothing.
+    Wildcard,
+    /// `1`, `"one"`, `'1'`, `true` or `null`, which matches only a value
+    /// equal to it.
+    Literal(Literal),
+}
+
+/// What a [`Pattern::Literal`] matches.
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub enum Literal {
+    Int(i64),
+    Str(String),
+    Char(char),
+    Bool(bool),
+    Null,
+}
+
+#[derive(PartialEq, Debug, Clone, Default, Serialize)]
+pub struct BlockStatement {
+    pub statements: Vec<Statement>,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize)]
+pub enum DeclarationKind {
+    Let,
+    Const,
+}
+
+/// Where a name a scope around its use declares is bound: `depth` scopes
+/// out from the use, at `index` among the names that scope declares, in
+/// the order it declares them.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Slot {
+    pub depth: u32,
+    pub index: u32,
+}
+
+impl Slot {
+    /// The same slot, counted from one scope further out.
+    pub(crate) fn out(self) -> Slot {
+        Slot {
+            depth: self.depth - 1,
+            ..self
+        }
+    }
+}
+
+/// An expression. The kinds that can fail at runtime carry the span of the
+/// token that introduced them (the name, the operator, or the `(` or `[`) so
+/// errors can point at it.
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub enum Expression {
+    Identifier {
+        name: Symbol,
+        span: Span,
+        /// Whether no scope around the name declares it, so it can only be
+        /// bound in the global environment or be a builtin. Set by
+        /// [`crate::resolve`].
+        #[serde(skip)]
+        global: Cell<bool>,
+        /// Where the declaration the name refers to puts it, if a scope
+        /// around it declares it. Set by [`crate::resolve`].
+        #[serde(skip)]
+        slot: Cell<Option<Slot>>,
+    },
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Char(char),
+    Bool(bool),
+    Null,
+    Prefix {
+        operator: PrefixOperator,
+        right: Box<Expression>,
+        span: Span,
+    },
+    Infix {
+        #[serde(serialize_with = "serialize_operand")]
+        left: Box<Expression>,
+        operator: InfixOperator,
+        right: Box<Expression>,
+        span: Span,
+    },
+    If {
+        condition: Box<Expression>,
+        consequence: BlockStatement,
+        alternative: Option<BlockStatement>,
+    },
+    /// `fn(parameters) -> return_type { body }`, where the return type is
+    /// optional. The body is shared with the functions made from it, so
+    /// they run the very statements that were parsed.
+    Function {
+        parameters: Parameters,
+        return_type: Option<TypeAnnotation>,
+        body: Rc<BlockStatement>,
+    },
+    Call {
+        #[serde(serialize_with = "serialize_operand")]
+        function: Box<Expression>,
+        /// The first may be what a pipeline continues from.
+        #[serde(serialize_with = "serialize_operand")]
+        arguments: Vec<Expression>,
+        span: Span,
+    },
+    /// `receiver.method(arguments)`, which calls the builtin the receiver's
+    /// type has as `method` with the receiver as its first argument.
+    MethodCall {
+        #[serde(serialize_with = "serialize_operand")]
+        receiver: Box<Expression>,
+        method: Symbol,
+        #[serde(serialize_with = "serialize_operand")]
+        arguments: Vec<Expression>,
+        span: Span,
+    },
+    Array(Vec<Expression>),
+    /// A `(a, b)` literal. One element needs a trailing comma, `(a,)`, and
+    /// `()` is the empty tuple.
+    Tuple(Vec<Expression>),
+    /// A `{key: value}` literal, with its pairs in source order.
+    Hash(Vec<(Expression, Expression)>),
+    /// `try { body } catch (pattern) { handler }`, whose value is the
+    /// body's, or, if the body raises an error, the handler's with the
+    /// error bound to the pattern.
+    Try {
+        body: BlockStatement,
+        pattern: Pattern,
+        handler: BlockStatement,
+    },
+    /// `...value` in a call's arguments or an array literal, which stands
+    /// for the elements of `value`.
+    Spread(Box<Expression>),
+    /// `name: value` in a call's arguments, which passes `value` to the
+    /// parameter called `name`. Named arguments come after positional ones.
+    Named {
+        name: Symbol,
+        value: Box<Expression>,
+    },
+    /// `condition ? consequence : alternative`.
+    Conditional {
+        #[serde(serialize_with = "serialize_operand")]
+        condition: Box<Expression>,
+        consequence: Box<Expression>,
+        alternative: Box<Expression>,
+    },
+    /// `left[index]`, or `left?[index]` when `optional`, which is `null`
+    /// instead of an error when `left` is `null`. `left.name` and
+    /// `left?.name` are parsed as `left["name"]` and `left?["name"]`.
+    Index {
+        #[serde(serialize_with = "serialize_operand")]
+        left: Box<Expression>,
+        index: Box<Expression>,
+        optional: bool,
+        span: Span,
+    },
+    /// `target = value`, or a compound assignment like `target += value`
+    /// when `operator` is set. The target is an identifier or an index
+    /// expression.
+    Assign {
+        #[serde(serialize_with = "serialize_operand")]
+        target: Box<Expression>,
+        operator: Option<InfixOperator>,
+        value: Box<Expression>,
+        span: Span,
+    },
+    /// `start..end`, or `start..=end` when `inclusive`. Only a range that
+    /// slices, like `xs[1..]` or `s[..n]`, can leave a bound out.
+    Range {
+        #[serde(serialize_with = "serialize_operand")]
+        start: Option<Box<Expression>>,
+        end: Option<Box<Expression>>,
+        inclusive: bool,
+        span: Span,
+    },
+    /// `match subject { pattern => body, ... }`, whose value is the body's
+    /// of the first arm whose pattern fits the subject.
+    Match {
+        subject: Box<Expression>,
+        arms: Vec<MatchArm>,
+        span: Span,
+    },
+}
+
+/// `pattern => body` in a `match`. A body that's a lone expression is kept
+/// as a block holding it.
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: BlockStatement,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize)]
+pub enum PrefixOperator {
+    Bang,
+    Minus,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize)]
+pub enum InfixOperator {
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    /// `**`, which is right associative.
+    Power,
+    Eq,
+    NotEq,
+    LessThan,
+    GreaterThan,
+    LessThanEq,
+    GreaterThanEq,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Or,
+    /// `??`: the left side, unless it is `null`.
+    Coalesce,
+}
+
+/// How tightly an operator binds, from loosest to tightest.
+#[derive(PartialEq, PartialOrd, Debug, Clone, Copy)]
+pub enum Precedence {
+    Lowest,
+    Assign,
+    Conditional,
+    /// `|>`, which is gone by the time the AST is built.
+    Pipe,
+    Range,
+    Coalesce,
+    LogicalOr,
+    LogicalAnd,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Equals,
+    LessGreater,
+    Shift,
+    Sum,
+    Product,
+    Prefix,
+    /// `**`, which binds tighter than a prefix operator before it, so
+    /// `-2 ** 2` is `-(2 ** 2)`.
+    Power,
+    /// Calls and indexing.
+    Call,
+}
+
+impl InfixOperator {
+    pub fn precedence(&self) -> Precedence {
+        match self {
+            InfixOperator::Coalesce => Precedence::Coalesce,
+            InfixOperator::Or => Precedence::LogicalOr,
+            InfixOperator::And => Precedence::LogicalAnd,
+            InfixOperator::BitOr => Precedence::BitOr,
+            InfixOperator::BitXor => Precedence::BitXor,
+            InfixOperator::BitAnd => Precedence::BitAnd,
+            InfixOperator::Eq | InfixOperator::NotEq => Precedence::Equals,
+            InfixOperator::LessThan
+            | InfixOperator::GreaterThan
+            | InfixOperator::LessThanEq
+            | InfixOperator::GreaterThanEq => Precedence::LessGreater,
+            InfixOperator::ShiftLeft | InfixOperator::ShiftRight => Precedence::Shift,
+            InfixOperator::Plus | InfixOperator::Minus => Precedence::Sum,
+            InfixOperator::Multiply | InfixOperator::Divide => Precedence::Product,
+            InfixOperator::Power => Precedence::Power,
+        }
+    }
+    /// Whether the operator is `==`, `!=`, `<`, `>`, `<=` or `>=`.
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self.precedence(),
+            Precedence::Equals | Precedence::LessGreater
+        )
+    }
+}
+
+impl Expression {
+    /// Where the expression is in the source, for the kinds that record it.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Expression::Identifier { span, .. }
+            | Expression::Prefix { span, .. }
+            | Expression::Infix { span, .. }
+            | Expression::Call { span, .. }
+            | Expression::MethodCall { span, .. }
+            | Expression::Index { span, .. }
+            | Expression::Assign { span, .. }
+            | Expression::Range { span, .. }
+            | Expression::Match { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+
+    /// How tightly the expression holds together when printed, used to
+    /// decide where parentheses are needed.
+    pub(crate) fn precedence(&self) -> Precedence {
+        match self {
+            Expression::Infix { operator, .. } => operator.precedence(),
+            Expression::Prefix { .. } => Precedence::Prefix,
+            Expression::Assign { .. } => Precedence::Assign,
+            Expression::Conditional { .. } => Precedence::Conditional,
+            Expression::Range { .. } => Precedence::Range,
+            // Calls, indexing, and anything that is closed off on both ends.
+            _ => Precedence::Call,
+        }
+    }
+
+    /// Writes `self`, wrapped in parentheses if it binds looser than
+    /// `precedence`.
+    fn fmt_operand(&self, f: &mut Formatter<'_>, precedence: Precedence) -> fmt::Result {
+        if self.precedence() < precedence {
+            write!(f, "({self})")
+        } else {
+            write!(f, "{self}")
+        }
+    }
+
+    /// Moves the operands of an operator, a call, or indexing into
+    /// `operands`, leaving `null` in their place.
+    fn take_operands(&mut self, operands: &mut Vec<Expression>) {
+        let mut take = |operand: &mut Expression| {
+            operands.push(std::mem::replace(operand, Expression::Null));
+        };
+        match self {
+            Expression::Prefix { right, .. }
+            | Expression::Spread(right)
+            | Expression::Named { value: right, .. } => take(right),
+            Expression::Infix { left, right, .. }
+            | Expression::Index {
+                left, index: right, ..
+            }
+            | Expression::Assign {
+                target: left,
+                value: right,
+                ..
+            } => {
+                take(left);
+                take(right);
+            }
+            Expression::Call {
+                function: callee,
+                arguments,
+                ..
+            }
+            | Expression::MethodCall {
+                receiver: callee,
+                arguments,
+                ..
+            } => {
+                take(callee);
+                arguments.iter_mut().for_each(take);
+            }
+            Expression::Conditional {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                take(condition);
+                take(consequence);
+                take(alternative);
+            }
+            Expression::Range { start, end, .. } => {
+                start
+                    .iter_mut()
+                    .chain(end.iter_mut())
+                    .for_each(|operand| take(operand));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Dropped a level at a time rather than recursively, as a long chain of
+/// operators is too deep to drop on the stack.
+impl Drop for Expression {
+    fn drop(&mut self) {
+        let mut operands = Vec::new();
+        self.take_operands(&mut operands);
+        while let Some(mut operand) = operands.pop() {
+            operand.take_operands(&mut operands);
+        }
+    }
+}
+
+impl Display for Program {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (index, statement) in self.statements.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{statement}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for Statement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Let(statement) => write!(f, "{statement}"),
+            Statement::Return(Some(value)) => write!(f, "return {value};"),
+            Statement::Return(None) => write!(f, "return;"),
+            Statement::Expression(expression) => write!(f, "{expression};"),
+            Statement::While { condition, body } => write!(f, "while ({condition}) {body}"),
+            Statement::For {
+                pattern,
+                iterable,
+                body,
+            } => write!(f, "for ({pattern} in {iterable}) {body}"),
+            Statement::Break => write!(f, "break;"),
+            Statement::Continue => write!(f, "continue;"),
+            Statement::Import(import) => write!(f, "{import}"),
+            Statement::Throw { value, .. } => write!(f, "throw {value};"),
+            Statement::Struct { name, fields, .. } => {
+                write!(f, "struct {name} {{")?;
+                for (index, field) in fields.iter().enumerate() {
+                    let separator = if index > 0 { ", " } else { " " };
+                    write!(f, "{separator}{field}")?;
+                }
+                if !fields.is_empty() {
+                    write!(f, " ")?;
+                }
+                write!(f, "}}")
+            }
+            Statement::Enum { name, variants, .. } => {
+                write!(f, "enum {name} {{")?;
+                for (index, variant) in variants.iter().enumerate() {
+                    let separator = if index > 0 { ", " } else { " " };
+                    write!(f, "{separator}{variant}")?;
+                }
+                if !variants.is_empty() {
+                    write!(f, " ")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl Display for LetStatement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for doc in &self.docs {
+            if doc.is_empty() {
+                writeln!(f, "///")?;
+            } else {
+                writeln!(f, "/// {doc}")?;
+            }
+        }
+
+        write!(f, "{} {}", self.kind, self.pattern)?;
+        if let Some(annotation) = &self.annotation {
+            write!(f, ": {annotation}")?;
+        }
+        write!(f, " = {};", self.value)
+    }
+}
+
+impl Display for TypeAnnotation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let list = |f: &mut Formatter<'_>, annotations: &[TypeAnnotation]| {
+            for (index, annotation) in annotations.iter().enumerate() {
+                if index > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{annotation}")?;
+            }
+            Ok(())
+        };
+        match self {
+            TypeAnnotation::Named {
+                name, arguments, ..
+            } => {
+                write!(f, "{name}")?;
+                if !arguments.is_empty() {
+                    write!(f, "<")?;
+                    list(f, arguments)?;
+                    write!(f, ">")?;
+                }
+                Ok(())
+            }
+            TypeAnnotation::Function { parameters, result } => {
+                write!(f, "fn(")?;
+                list(f, parameters)?;
+                write!(f, ") -> {result}")
+            }
+        }
+    }
+}
+
+impl Display for VariantDeclaration {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.fields.is_empty() {
+            let fields: Vec<&str> = self.fields.iter().map(Symbol::as_str).collect();
+            write!(f, "({})", fields.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Import {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            ImportSource::Name(name) => write!(f, "import {name};"),
+            ImportSource::Path(path) => write!(f, "import {path:?};"),
+        }
+    }
+}
+
+impl Display for BlockStatement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.statements.is_empty() {
+            return write!(f, "{{}}");
+        }
+
+        write!(f, "{{")?;
+        for statement in &self.statements {
+            write!(f, " {statement}")?;
+        }
+        write!(f, " }}")
+    }
+}
+
+impl Display for Parameters {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (index, parameter) in self.positional.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", parameter.name)?;
+            if let Some(annotation) = &parameter.annotation {
+                write!(f, ": {annotation}")?;
+            }
+            if let Some(default) = &parameter.default {
+                write!(f, " = {default}")?;
+            }
+        }
+        if let Some(rest) = &self.rest {
+            let comma = if self.positional.is_empty() { "" } else { ", " };
+            write!(f, "{comma}...{rest}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Identifier(name) => write!(f, "{name}"),
+            Pattern::Array(patterns) => {
+                write!(f, "[")?;
+                for (index, pattern) in patterns.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{pattern}")?;
+                }
+                write!(f, "]")
+            }
+            Pattern::Tuple(patterns) => {
+                write!(f, "(")?;
+                for (index, pattern) in patterns.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{pattern}")?;
+                }
+                if patterns.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Pattern::Hash(entries) => write_entries(f, entries),
+            Pattern::Struct { name, fields } => {
+                write!(f, "{name} ")?;
+                write_entries(f, fields)
+            }
+            Pattern::Variant { name, fields } => {
+                write!(f, "{name}(")?;
+                for (index, pattern) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{pattern}")?;
+                }
+                write!(f, ")")
+            }
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Literal(literal) => write!(f, "{literal}"),
+        }
+    }
+}
+
+impl Display for Literal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal::Int(value) => write!(f, "{value}"),
+            Literal::Str(value) => write!(f, "{value:?}"),
+            Literal::Char(value) => write!(f, "{value:?}"),
+            Literal::Bool(value) => write!(f, "{value}"),
+            Literal::Null => write!(f, "null"),
+        }
+    }
+}
+
+/// Writes a hash or struct pattern's entries, in braces.
+fn write_entries(f: &mut Formatter<'_>, entries: &[(String, Pattern)]) -> fmt::Result {
+    write!(f, "{{")?;
+    for (index, (key, pattern)) in entries.iter().enumerate() {
+        if index > 0 {
+            write!(f, ",")?;
+        }
+        match pattern {
+            Pattern::Identifier(name) if name == key.as_str() => write!(f, " {key}")?,
+            pattern => write!(f, " {key}: {pattern}")?,
+        }
+    }
+    write!(f, " }}")
+}
+
+impl Display for DeclarationKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DeclarationKind::Let => write!(f, "let"),
+            DeclarationKind::Const => write!(f, "const"),
+        }
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        with_stack(|| match self {
+            Expression::Identifier { name, .. } => write!(f, "{name}"),
+            Expression::Int(value) => write!(f, "{value}"),
+            // Debug formatting keeps the `.0` on whole floats so they read
+            // back in as floats.
+            Expression::Float(value) => write!(f, "{value:?}"),
+            // Debug escapes match the lexer's escape sequences.
+            Expression::Str(value) => write!(f, "{value:?}"),
+            Expression::Char(value) => write!(f, "{value:?}"),
+            Expression::Bool(value) => write!(f, "{value}"),
+            Expression::Null => write!(f, "null"),
+            Expression::Prefix {
+                operator, right, ..
+            } => {
+                write!(f, "{operator}")?;
+                right.fmt_operand(f, Precedence::Prefix)
+            }
+            Expression::Infix {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                // `**` is right associative, and its right operand is
+                // parsed like a prefix operator's, so it can be `-x`.
+                if *operator == InfixOperator::Power {
+                    left.fmt_operand(f, next_precedence(Precedence::Power))?;
+                    write!(f, " ** ")?;
+                    return right.fmt_operand(f, Precedence::Prefix);
+                }
+
+                // Other operators are left associative, so an equal precedence
+                // operator on the right needs parentheses to keep its grouping.
+                let precedence = operator.precedence();
+                left.fmt_operand(f, precedence)?;
+                write!(f, " {operator} ")?;
+                right.fmt_operand(f, next_precedence(precedence))
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                write!(f, "if ({condition}) {consequence}")?;
+                if let Some(alternative) = alternative {
+                    write!(f, " else {alternative}")?;
+                }
+                Ok(())
+            }
+            Expression::Function {
+                parameters,
+                return_type,
+                body,
+            } => {
+                write!(f, "fn({parameters}) ")?;
+                if let Some(return_type) = return_type {
+                    write!(f, "-> {return_type} ")?;
+                }
+                write!(f, "{body}")
+            }
+            Expression::Call {
+                function,
+                arguments,
+                ..
+            } => {
+                function.fmt_operand(f, Precedence::Call)?;
+                write!(f, "(")?;
+                fmt_list(f, arguments)?;
+                write!(f, ")")
+            }
+            Expression::MethodCall {
+                receiver,
+                method,
+                arguments,
+                ..
+            } => {
+                receiver.fmt_operand(f, Precedence::Call)?;
+                write!(f, ".{method}(")?;
+                fmt_list(f, arguments)?;
+                write!(f, ")")
+            }
+            Expression::Array(elements) => {
+                write!(f, "[")?;
+                fmt_list(f, elements)?;
+                write!(f, "]")
+            }
+            Expression::Tuple(elements) => {
+                write!(f, "(")?;
+                fmt_list(f, elements)?;
+                if elements.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Expression::Hash(pairs) => {
+                if pairs.is_empty() {
+                    return write!(f, "{{}}");
+                }
+
+                write!(f, "{{")?;
+                for (index, (key, value)) in pairs.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, " {key}: {value}")?;
+                }
+                write!(f, " }}")
+            }
+            Expression::Try {
+                body,
+                pattern,
+                handler,
+            } => write!(f, "try {body} catch ({pattern}) {handler}"),
+            Expression::Match { subject, arms, .. } => {
+                write!(f, "match {subject} {{")?;
+                for (index, arm) in arms.iter().enumerate() {
+                    let separator = if index > 0 { ", " } else { " " };
+                    write!(f, "{separator}{} => {}", arm.pattern, arm.body)?;
+                }
+                if !arms.is_empty() {
+                    write!(f, " ")?;
+                }
+                write!(f, "}}")
+            }
+            Expression::Spread(value) => write!(f, "...{value}"),
+            Expression::Named { name, value } => write!(f, "{name}: {value}"),
+            Expression::Conditional {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                // Right associative, so only the alternative can be another
+                // conditional without parentheses.
+                condition.fmt_operand(f, next_precedence(Precedence::Conditional))?;
+                write!(f, " ? {consequence} : ")?;
+                alternative.fmt_operand(f, Precedence::Conditional)
+            }
+            Expression::Index {
+                left,
+                index,
+                optional,
+                ..
+            } => {
+                left.fmt_operand(f, Precedence::Call)?;
+                write!(f, "{}[{index}]", if *optional { "?" } else { "" })
+            }
+            Expression::Assign {
+                target,
+                operator,
+                value,
+                ..
+            } => {
+                // Assignment is right associative, so the value can be
+                // another assignment without parentheses.
+                target.fmt_operand(f, Precedence::Call)?;
+                match operator {
+                    Some(operator) => write!(f, " {operator}= ")?,
+                    None => write!(f, " = ")?,
+                }
+                value.fmt_operand(f, Precedence::Assign)
+            }
+            Expression::Range {
+                start,
+                end,
+                inclusive,
+                ..
+            } => {
+                // Ranges don't chain, so both sides need to bind tighter.
+                let tighter = next_precedence(Precedence::Range);
+                if let Some(start) = start {
+                    start.fmt_operand(f, tighter)?;
+                }
+                write!(f, "{}", if *inclusive { "..=" } else { ".." })?;
+                match end {
+                    Some(end) => end.fmt_operand(f, tighter),
+                    None => Ok(()),
+                }
+            }
+        })
+    }
+}
+
+fn fmt_list(f: &mut Formatter<'_>, expressions: &[Expression]) -> fmt::Result {
+    for (index, expression) in expressions.iter().enumerate() {
+        if index > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{expression}")?;
+    }
+
+    Ok(())
+}
+
+/// The loosest precedence that binds strictly tighter than `precedence`.
+pub(crate) fn next_precedence(precedence: Precedence) -> Precedence {
+    match precedence {
+        Precedence::Lowest => Precedence::Assign,
+        Precedence::Assign => Precedence::Conditional,
+        Precedence::Conditional => Precedence::Pipe,
+        Precedence::Pipe => Precedence::Range,
+        Precedence::Range => Precedence::Coalesce,
+        Precedence::Coalesce => Precedence::LogicalOr,
+        Precedence::LogicalOr => Precedence::LogicalAnd,
+        Precedence::LogicalAnd => Precedence::BitOr,
+        Precedence::BitOr => Precedence::BitXor,
+        Precedence::BitXor => Precedence::BitAnd,
+        Precedence::BitAnd => Precedence::Equals,
+        Precedence::Equals => Precedence::LessGreater,
+        Precedence::LessGreater => Precedence::Shift,
+        Precedence::Shift => Precedence::Sum,
+        Precedence::Sum => Precedence::Product,
+        Precedence::Product => Precedence::Prefix,
+        Precedence::Prefix => Precedence::Power,
+        Precedence::Power | Precedence::Call => Precedence::Call,
+    }
+}
+
+impl Display for PrefixOperator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PrefixOperator::Bang => write!(f, "!"),
+            PrefixOperator::Minus => write!(f, "-"),
+        }
+    }
+}
+
+impl Display for InfixOperator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let operator = match self {
+            InfixOperator::Plus => "+",
+            InfixOperator::Minus => "-",
+            InfixOperator::Multiply => "*",
+            InfixOperator::Divide => "/",
+            InfixOperator::Power => "**",
+            InfixOperator::Eq => "==",
+            InfixOperator::NotEq => "!=",
+            InfixOperator::LessThan => "<",
+            InfixOperator::GreaterThan => ">",
+            InfixOperator::LessThanEq => "<=",
+            InfixOperator::GreaterThanEq => ">=",
+            InfixOperator::BitAnd => "&",
+            InfixOperator::BitOr => "|",
+            InfixOperator::BitXor => "^",
+            InfixOperator::ShiftLeft => "<<",
+            InfixOperator::ShiftRight => ">>",
+            InfixOperator::And => "&&",
+            InfixOperator::Or => "||",
+            InfixOperator::Coalesce => "??",
+        };
+
+        write!(f, "{operator}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use super::{Expression, InfixOperator, Program, Statement};
+    use crate::token::Span;
+
+    #[test]
+    fn test_serialize_to_json() {
+        let program = Program {
+            statements: vec![Statement::Expression(Expression::Infix {
+                left: Box::new(Expression::Identifier {
+                    name: "x".into(),
+                    span: Span { line: 1, column: 1 },
+                    global: Cell::new(true),
+                    slot: Cell::default(),
+                }),
+                operator: InfixOperator::Plus,
+                right: Box::new(Expression::Int(1)),
+                span: Span { line: 1, column: 3 },
+            })],
+        };
+
+        assert_eq!(
+            r#"{"statements":[{"Expression":{"Infix":{"left":{"Identifier":{"name":"x","span":{"line":1,"column":1}}},"operator":"Plus","right":{"Int":1},"span":{"line":1,"column":3}}}}]}"#,
+            serde_json::to_string(&program).unwrap()
+        );
+    }
+}
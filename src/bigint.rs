@@ -0,0 +1,181 @@
+//! Arbitrary-precision integers, behind the `bigint` feature. Integer
+//! arithmetic that would overflow produces a [`Value::BigInt`] instead, and
+//! a result that fits back in an `i64` is always an [`Value::Int`], so the
+//! two never hold the same number and Tofu code sees a single `Int` type.
+
+use std::rc::Rc;
+
+use num_bigint::{BigInt, Sign};
+use num_traits::{FromPrimitive, Pow, ToPrimitive};
+
+use crate::ast::InfixOperator;
+use crate::evaluator::{compare, RuntimeError};
+use crate::value::Value;
+
+/// `value` as an `Int` if it fits, otherwise as a `BigInt`.
+pub fn normalize(value: BigInt) -> Value {
+    match i64::try_from(&value) {
+        Ok(value) => Value::Int(value),
+        Err(_) => Value::BigInt(Rc::new(value)),
+    }
+}
+
+/// Widens an `Int` or `BigInt` value.
+pub fn to_big(value: &Value) -> BigInt {
+    match value {
+        Value::Int(value) => BigInt::from(*value),
+        Value::BigInt(value) => (**value).clone(),
+        other => unreachable!("only integers widen, not {}", other.type_name()),
+    }
+}
+
+/// The nearest `Float`, which is infinite if `value` is too big for one.
+pub fn to_float(value: &BigInt) -> f64 {
+    value.to_f64().expect("every integer converts to a float")
+}
+
+/// `value` truncated towards zero, or `None` if it is infinite or NaN.
+pub fn from_float(value: f64) -> Option<Value> {
+    BigInt::from_f64(value.trunc()).map(normalize)
+}
+
+/// `base` to the power `exponent`.
+pub fn pow(base: &BigInt, exponent: u32) -> Value {
+    normalize(Pow::pow(base, exponent))
+}
+
+pub fn eval_infix_expression(
+    operator: InfixOperator,
+    left: &BigInt,
+    right: &BigInt,
+) -> Result<Value, RuntimeError> {
+    let value = match operator {
+        InfixOperator::Plus => normalize(left + right),
+        InfixOperator::Minus => normalize(left - right),
+        InfixOperator::Multiply => normalize(left * right),
+        InfixOperator::Power if right.sign() == Sign::Minus => {
+            Value::Float(to_float(left).powf(to_float(right)))
+        }
+        InfixOperator::Power => match u32::try_from(right) {
+            Ok(exponent) => pow(left, exponent),
+            Err(_) => {
+                return Err(RuntimeError::new(format!(
+                    "integer overflow: {left} ** {right}"
+                )))
+            }
+        },
+        InfixOperator::Divide => {
+            if right.sign() == Sign::NoSign {
+                return Err(RuntimeError::new("division by zero"));
+            }
+            normalize(left / right)
+        }
+        InfixOperator::BitAnd => normalize(left & right),
+        InfixOperator::BitOr => normalize(left | right),
+        InfixOperator::BitXor => normalize(left ^ right),
+        InfixOperator::ShiftLeft | InfixOperator::ShiftRight => {
+            let Some(amount) = u32::try_from(right).ok().filter(|amount| *amount < 64) else {
+                return Err(RuntimeError::new(format!(
+                    "shift amount {right} is out of range"
+                )));
+            };
+            if operator == InfixOperator::ShiftLeft {
+                normalize(left << amount)
+            } else {
+                normalize(left >> amount)
+            }
+        }
+        InfixOperator::Eq
+        | InfixOperator::NotEq
+        | InfixOperator::LessThan
+        | InfixOperator::GreaterThan
+        | InfixOperator::LessThanEq
+        | InfixOperator::GreaterThanEq => compare(operator, left.cmp(right)),
+        InfixOperator::And | InfixOperator::Or | InfixOperator::Coalesce => {
+            unreachable!("logical operators short-circuit before reaching here")
+        }
+    };
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::builtins::test::eval;
+    use crate::value::Value;
+
+    fn eval_to_string(input: &str) -> String {
+        match eval(input) {
+            Ok(value) => value.to_string(),
+            Err(error) => panic!("{input} failed: {error}"),
+        }
+    }
+
+    const MAX: &str = "9223372036854775807";
+
+    #[test]
+    fn test_promotion() {
+        let factorial = "let f = fn(n) { if (n < 2) { 1 } else { n * f(n - 1) } };";
+
+        let tests = vec![
+            (format!("{factorial} f(25)"), "15511210043330985984000000"),
+            (format!("{MAX} + 1"), "9223372036854775808"),
+            (
+                format!("{MAX} * {MAX}"),
+                "85070591730234615847396907784232501249",
+            ),
+            (format!("-{MAX} - 2"), "-9223372036854775809"),
+            (format!("-(-{MAX} - 1)"), "9223372036854775808"),
+            (format!("(-{MAX} - 1) / -1"), "9223372036854775808"),
+            (format!("({MAX} + 1) * 2 / 4"), "4611686018427387904"),
+            (format!("[{MAX} + 1]"), "[9223372036854775808]"),
+            ("2 ** 100".to_string(), "1267650600228229401496703205376"),
+            (format!("({MAX} + 1) ** 2 / {MAX}"), "9223372036854775809"),
+            (format!("({MAX} + 1) ** -1"), "1.0842021724855044e-19"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(expected, eval_to_string(&input), "input={input}");
+        }
+    }
+
+    #[test]
+    fn test_mixed_with_ints() {
+        let tests = vec![
+            // Results that fit are ordinary `Int`s again.
+            (format!("{MAX} + 1 - 1"), Value::Int(i64::MAX)),
+            (format!("({MAX} + 1) / 2"), Value::Int(1 << 62)),
+            (format!("{MAX} + 1 > {MAX}"), Value::Bool(true)),
+            (format!("1 < {MAX} * 2"), Value::Bool(true)),
+            (format!("{MAX} + 1 == {MAX} + 1"), Value::Bool(true)),
+            (format!("{MAX} + 1 - 1 == {MAX}"), Value::Bool(true)),
+            (format!("type({MAX} + 1)"), Value::Str("Int".to_string())),
+            (
+                format!("({MAX} + 1) * 0.5"),
+                Value::Float(4611686018427387904.0),
+            ),
+            (format!("{MAX} * 2 > 1.5"), Value::Bool(true)),
+            (
+                format!("to_float({MAX} + 1)"),
+                Value::Float(9223372036854775808.0),
+            ),
+            (
+                format!("to_int(1e19) == {MAX} + 1 + 776627963145224192"),
+                Value::Bool(true),
+            ),
+            (
+                format!("let h = {{}}; h[{MAX} + 1] = 1; h[{MAX} * 2 + 2 - {MAX} - 1]"),
+                Value::Int(1),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Ok(expected), eval(&input), "input={input}");
+        }
+
+        assert_eq!(
+            "division by zero",
+            eval(&format!("({MAX} + 1) / 0")).unwrap_err().message
+        );
+    }
+}
@@ -0,0 +1,1370 @@
+//! Functions implemented in Rust and callable from Tofu code. Every
+//! evaluator starts with the [`Builtins::standard`] set, and more can be
+//! added through [`Evaluator::builtins_mut`].
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::rc::Rc;
+
+use indexmap::IndexMap;
+
+use crate::ast::InfixOperator;
+use crate::evaluator::{eval_infix_expression, ordering, ErrorKind, Evaluator, RuntimeError};
+use crate::symbol::{Symbol, SymbolMap};
+use crate::value::{HashKey, Value, ValueIter};
+
+mod env;
+mod files;
+#[cfg(feature = "http")]
+mod http;
+mod math;
+mod process;
+#[cfg(feature = "regex")]
+mod regex;
+mod sets;
+mod strings;
+mod time;
+
+/// The Rust side of a builtin. It gets the evaluator that called it, for
+/// output and for calling back into Tofu code, and arguments that have
+/// already been checked against the builtin's [`Arity`].
+pub type BuiltinFunction = dyn Fn(&mut Evaluator, Vec<Value>) -> Result<Value, RuntimeError>;
+
+/// How many arguments a builtin accepts.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+    /// From the first number to the second, inclusive.
+    Between(usize, usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Exact(expected) => count == expected,
+            Arity::AtLeast(minimum) => count >= minimum,
+            Arity::Between(minimum, maximum) => (minimum..=maximum).contains(&count),
+        }
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arity::Exact(expected) => write!(f, "{expected}"),
+            Arity::AtLeast(minimum) => write!(f, "at least {minimum}"),
+            Arity::Between(minimum, maximum) if *maximum == minimum + 1 => {
+                write!(f, "{minimum} or {maximum}")
+            }
+            Arity::Between(minimum, maximum) => write!(f, "{minimum} to {maximum}"),
+        }
+    }
+}
+
+pub struct Builtin {
+    pub name: String,
+    pub arity: Arity,
+    function: Box<BuiltinFunction>,
+}
+
+impl Builtin {
+    /// Checks the number of arguments and runs the builtin.
+    pub fn call(
+        &self,
+        evaluator: &mut Evaluator,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        if !self.arity.accepts(arguments.len()) {
+            return Err(RuntimeError::new(format!(
+                "wrong number of arguments to `{}`: expected {}, got {}",
+                self.name,
+                self.arity,
+                arguments.len()
+            )));
+        }
+
+        (self.function)(evaluator, arguments)
+    }
+}
+
+impl std::fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Builtin")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Builtins are only equal to themselves, like user-defined functions.
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Builtin) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// The builtins available by name, which are mostly functions but can be
+/// any value, like `pi`. Tofu bindings shadow them, so adding a builtin
+/// never breaks a program that already uses the name. Some are methods too,
+/// looked up by the type of the value they're called on.
+#[derive(Default)]
+pub struct Builtins {
+    values: SymbolMap<Value>,
+    methods: HashMap<String, HashMap<String, Value>>,
+}
+
+impl Builtins {
+    /// A registry with no builtins in it.
+    pub fn new() -> Builtins {
+        Builtins::default()
+    }
+
+    /// A registry with the standard builtins.
+    pub fn standard() -> Builtins {
+        let mut builtins = Builtins::new();
+
+        builtins.register("len", Arity::Exact(1), len);
+        // A struct's type is its own name, and a variant's its enum's.
+        builtins.register("type", Arity::Exact(1), |_, arguments| {
+            Ok(Value::Str(match &arguments[0] {
+                Value::Struct(instance) => instance.ty.name.clone(),
+                Value::Variant(variant) => variant.ty.enum_name.clone(),
+                value => value.type_name().to_string(),
+            }))
+        });
+        builtins.register("print", Arity::AtLeast(0), |evaluator, arguments| {
+            print(evaluator, &arguments, "")
+        });
+        builtins.register("println", Arity::AtLeast(0), |evaluator, arguments| {
+            print(evaluator, &arguments, "\n")
+        });
+        builtins.register("input", Arity::Between(0, 1), input);
+        builtins.register("exit", Arity::Between(0, 1), exit);
+        builtins.register("breakpoint", Arity::Exact(0), |evaluator, _| {
+            evaluator.breakpoint()?;
+            Ok(Value::Null)
+        });
+        builtins.register("array", Arity::Exact(1), array);
+        builtins.register("assert", Arity::Between(1, 2), assert);
+        builtins.register("assert_eq", Arity::Exact(2), assert_eq);
+        builtins.register("to_int", Arity::Exact(1), to_int);
+        builtins.register("to_float", Arity::Exact(1), to_float);
+        builtins.register("push", Arity::Exact(2), push);
+        builtins.register("pop", Arity::Exact(1), pop);
+        builtins.register("insert", Arity::Exact(3), insert);
+        builtins.register("remove", Arity::Exact(2), remove);
+        builtins.register("first", Arity::Exact(1), first);
+        builtins.register("last", Arity::Exact(1), last);
+        builtins.register("rest", Arity::Exact(1), rest);
+        builtins.register("keys", Arity::Exact(1), keys);
+        builtins.register("values", Arity::Exact(1), values);
+        builtins.register("map", Arity::Exact(2), map);
+        builtins.register("filter", Arity::Exact(2), filter);
+        builtins.register("reduce", Arity::Exact(3), reduce);
+        builtins.register("sort", Arity::Between(1, 2), sort);
+        strings::register(&mut builtins);
+        sets::register(&mut builtins);
+        math::register(&mut builtins);
+        files::register(&mut builtins);
+        env::register(&mut builtins);
+        process::register(&mut builtins);
+        time::register(&mut builtins);
+        #[cfg(feature = "regex")]
+        regex::register(&mut builtins);
+        #[cfg(feature = "http")]
+        http::register(&mut builtins);
+
+        for (type_name, names) in METHODS {
+            for name in *names {
+                let builtin = builtins.get(name).expect("methods are builtins");
+                builtins.define_method(type_name, name, builtin);
+            }
+        }
+
+        builtins
+    }
+
+    /// Adds a builtin, replacing any existing one with the same name.
+    pub fn register(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        function: impl Fn(&mut Evaluator, Vec<Value>) -> Result<Value, RuntimeError> + 'static,
+    ) {
+        let builtin = Builtin {
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+        };
+        self.define(name, Value::Builtin(Rc::new(builtin)));
+    }
+
+    /// Adds a builtin value, replacing any existing builtin with the same
+    /// name.
+    pub fn define(&mut self, name: &str, value: Value) {
+        self.values.insert(Symbol::intern(name), value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.lookup(&Symbol::intern(name))
+    }
+
+    /// Like [`Builtins::get`], for a name that's already interned, which
+    /// saves hashing the whole of it.
+    pub fn lookup(&self, name: &Symbol) -> Option<Value> {
+        self.values.get(name).cloned()
+    }
+
+    /// The names of the builtins, in no particular order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.values.keys().map(Symbol::as_str)
+    }
+
+    /// Makes `function` callable as `value.name(...)` on values whose type
+    /// is `type_name`, with the value passed as the first argument.
+    pub fn define_method(&mut self, type_name: &str, name: &str, function: Value) {
+        self.methods
+            .entry(type_name.to_string())
+            .or_default()
+            .insert(name.to_string(), function);
+    }
+
+    /// The function `value.name(...)` calls for values of type `type_name`.
+    pub fn method(&self, type_name: &str, name: &str) -> Option<Value> {
+        self.methods.get(type_name)?.get(name).cloned()
+    }
+
+    /// The function `receiver.name(...)` calls, and the receiver if it's
+    /// passed as the first argument. For a hash with a function stored
+    /// under `name`, like the `math` namespace or an imported module, it's
+    /// that function, which is passed just the arguments, so a module can
+    /// export a `len` of its own. Otherwise it's the method of the
+    /// receiver's type.
+    pub fn method_call(
+        &self,
+        receiver: Value,
+        name: &str,
+    ) -> Result<(Value, Option<Value>), RuntimeError> {
+        if let Value::Hash(pairs) = &receiver {
+            let member = pairs.borrow().get(&HashKey::Str(name.to_string())).cloned();
+            if let Some(
+                function @ (Value::Function(_)
+                | Value::Closure(_)
+                | Value::Builtin(_)
+                | Value::StructType(_)
+                | Value::VariantType(_)),
+            ) = member
+            {
+                return Ok((function, None));
+            }
+        }
+        if let Some(method) = self.method(receiver.type_name(), name) {
+            return Ok((method, Some(receiver)));
+        }
+
+        Err(RuntimeError::new(format!(
+            "{} has no method `{name}`",
+            receiver.type_name()
+        )))
+    }
+}
+
+/// The standard builtins that are also methods of each type, as in
+/// `xs.len()` for `len(xs)`.
+const METHODS: &[(&str, &[&str])] = &[
+    ("Int", &["abs", "pow", "to_int", "to_float"]),
+    (
+        "Float",
+        &["abs", "floor", "ceil", "sqrt", "pow", "to_int", "to_float"],
+    ),
+    (
+        "Str",
+        &[
+            "len", "split", "trim", "replace", "contains", "format", "upper", "lower", "chars",
+            "bytes", "to_int", "to_float",
+        ],
+    ),
+    (
+        "Array",
+        &[
+            "len", "push", "pop", "insert", "remove", "first", "last", "rest", "map", "filter",
+            "reduce", "sort", "join",
+        ],
+    ),
+    ("Hash", &["len", "keys", "values"]),
+    (
+        "Set",
+        &["len", "add", "remove", "contains", "union", "intersect"],
+    ),
+    ("Buffer", &["len", "buf_push", "buf_str"]),
+    ("Range", &["len", "map", "filter", "reduce", "sort"]),
+];
+
+/// `len(x)`: the number of characters in a string or buffer, elements in
+/// an array or set, entries in a hash, or integers in a range.
+fn len(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let length = match &arguments[0] {
+        Value::Str(value) => value.chars().count(),
+        Value::Array(elements) => elements.borrow().len(),
+        Value::Hash(pairs) => pairs.borrow().len(),
+        Value::Set(elements) => elements.borrow().len(),
+        Value::Buffer(text) => text.borrow().chars().count(),
+        Value::Range(range) => {
+            let end = range.end_exclusive().unwrap_or(i64::MAX);
+            return Ok(Value::Int(end.saturating_sub(range.start).max(0)));
+        }
+        other => {
+            return Err(RuntimeError::new(format!(
+                "`len` expects a Str, Array, Hash or Range, got {}",
+                other.type_name()
+            )))
+        }
+    };
+
+    // Nothing in memory can have more than `i64::MAX` elements.
+    Ok(Value::Int(length as i64))
+}
+
+/// `print(...)` and `println(...)`: writes the arguments separated by
+/// spaces, followed by `end`. Given more than one argument and a first one
+/// that's a string with braces in it, they write what
+/// [`format`](strings::format) would make of them instead.
+fn print(evaluator: &mut Evaluator, arguments: &[Value], end: &str) -> Result<Value, RuntimeError> {
+    let builtin = if end.is_empty() { "print" } else { "println" };
+    let line = match arguments {
+        [Value::Str(template), rest @ ..] if !rest.is_empty() && template.contains(['{', '}']) => {
+            strings::format(builtin, template, rest)?
+        }
+        arguments => arguments
+            .iter()
+            .map(Value::to_string)
+            .collect::<Vec<_>>()
+            .join(" "),
+    };
+
+    write!(evaluator.output(), "{line}{end}")
+        .and_then(|()| evaluator.output().flush())
+        .map_err(output_error)?;
+
+    Ok(Value::Null)
+}
+
+/// What writing output failing with `error` fails with. Output nothing is
+/// reading any more, as in `tofu script.tofu | head`, ends the program as
+/// `exit()` would, since nothing else it writes can be seen.
+pub(crate) fn output_error(error: std::io::Error) -> RuntimeError {
+    if error.kind() == std::io::ErrorKind::BrokenPipe {
+        RuntimeError {
+            kind: ErrorKind::Exit(0),
+            ..RuntimeError::new("output closed")
+        }
+    } else {
+        RuntimeError::new(format!("could not write output: {error}"))
+    }
+}
+
+/// `exit()` or `exit(status)`: ends the program, with `status` as the
+/// process's exit status, or 0 without one. It can't be caught, and the
+/// REPL takes it as the end of the session.
+fn exit(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let status = match arguments.first() {
+        None => 0,
+        Some(Value::Int(status)) => u8::try_from(*status).map_err(|_| {
+            RuntimeError::new(format!(
+                "`exit` expects a status from 0 to 255, got {status}"
+            ))
+        })?,
+        Some(other) => {
+            return Err(RuntimeError::new(format!(
+                "`exit` expects an Int status, got {}",
+                other.type_name()
+            )))
+        }
+    };
+
+    Err(RuntimeError {
+        kind: ErrorKind::Exit(status),
+        ..RuntimeError::new(format!("exited with status {status}"))
+    })
+}
+
+/// `input()` or `input(prompt)`: writes `prompt`, then reads a line and
+/// returns it without its line ending, or `null` at the end of the input.
+fn input(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    if let Some(prompt) = arguments.first() {
+        write!(evaluator.output(), "{prompt}")
+            .and_then(|()| evaluator.output().flush())
+            .map_err(output_error)?;
+    }
+
+    let mut line = String::new();
+    let read = evaluator
+        .read_line(&mut line)
+        .map_err(|error| RuntimeError::new(format!("could not read input: {error}")))?;
+
+    if read == 0 {
+        return Ok(Value::Null);
+    }
+
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+
+    Ok(Value::Str(line))
+}
+
+/// `array(x)`: a new array of the values a `for` loop over `x` would visit.
+fn array(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let elements = expect_iterable("array", &arguments[0])?;
+    if let (_, Some(len)) = elements.size_hint() {
+        evaluator.check_memory(len.saturating_mul(std::mem::size_of::<Value>()))?;
+    }
+    Ok(Value::array(elements.collect()))
+}
+
+/// `assert(condition)` or `assert(condition, message)`: fails unless
+/// `condition` is truthy, with `message` if there is one.
+fn assert(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let failure = (!arguments[0].is_truthy()).then(|| match arguments.get(1) {
+        Some(message) => format!("assertion failed: {message}"),
+        None => "assertion failed".to_string(),
+    });
+
+    evaluator.check_assertion(failure)?;
+    Ok(Value::Null)
+}
+
+/// `assert_eq(a, b)`: fails unless `a == b`.
+fn assert_eq(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let [left, right] = <[Value; 2]>::try_from(arguments).expect("arity is checked");
+    let equal = eval_infix_expression(
+        InfixOperator::Eq,
+        left.clone(),
+        right.clone(),
+        evaluator.overflow(),
+    )?;
+
+    // Strings are quoted, so `assert_eq("1", 1)` doesn't read as `1 != 1`.
+    let shown = |value: &Value| match value {
+        Value::Str(value) => format!("{value:?}"),
+        Value::Char(value) => format!("{value:?}"),
+        value => value.to_string(),
+    };
+    let failure = (!equal.is_truthy())
+        .then(|| format!("assertion failed: {} != {}", shown(&left), shown(&right)));
+
+    evaluator.check_assertion(failure)?;
+    Ok(Value::Null)
+}
+
+/// `to_int(x)`: an Int, a Float truncated towards zero, or a string
+/// holding a whole number, as an Int.
+fn to_int(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let cannot_convert = || {
+        let shown = match &arguments[0] {
+            Value::Str(value) => format!("{value:?}"),
+            value => value.to_string(),
+        };
+        RuntimeError::new(format!("`to_int` cannot convert {shown} to an Int"))
+    };
+
+    match &arguments[0] {
+        Value::Int(_) => Ok(arguments[0].clone()),
+        #[cfg(feature = "bigint")]
+        Value::BigInt(_) => Ok(arguments[0].clone()),
+        Value::Float(value) => float_to_int(*value).ok_or_else(cannot_convert),
+        Value::Str(value) => {
+            #[cfg(feature = "bigint")]
+            let parsed = value
+                .parse::<num_bigint::BigInt>()
+                .ok()
+                .map(crate::bigint::normalize);
+            #[cfg(not(feature = "bigint"))]
+            let parsed = value.parse().ok().map(Value::Int);
+            parsed.ok_or_else(cannot_convert)
+        }
+        other => Err(RuntimeError::new(format!(
+            "`to_int` expects an Int, Float or Str, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+/// `value` truncated towards zero, or `None` if it is infinite, NaN, or
+/// too big for an Int.
+fn float_to_int(value: f64) -> Option<Value> {
+    #[cfg(feature = "bigint")]
+    return crate::bigint::from_float(value);
+
+    #[cfg(not(feature = "bigint"))]
+    {
+        let value = value.trunc();
+        // `i64::MAX as f64` rounds up to 2^63, which is just out of range.
+        (i64::MIN as f64..i64::MAX as f64)
+            .contains(&value)
+            .then_some(Value::Int(value as i64))
+    }
+}
+
+/// `to_float(x)`: an Int, a Float, or a string holding a number, as a
+/// Float. Ints too big to represent exactly are rounded.
+fn to_float(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let value = match &arguments[0] {
+        Value::Int(value) => *value as f64,
+        #[cfg(feature = "bigint")]
+        Value::BigInt(value) => crate::bigint::to_float(value),
+        Value::Float(value) => *value,
+        Value::Str(value) => value.parse().map_err(|_| {
+            RuntimeError::new(format!("`to_float` cannot convert {value:?} to a Float"))
+        })?,
+        other => {
+            return Err(RuntimeError::new(format!(
+                "`to_float` expects an Int, Float or Str, got {}",
+                other.type_name()
+            )))
+        }
+    };
+
+    Ok(Value::Float(value))
+}
+
+// Array builtins. Arrays are shared, so `push`, `pop`, `insert` and
+// `remove` change the array in place, the same way assigning to an index
+// does, and every binding that refers to the array sees the change. `first`,
+// `last` and `rest` leave it alone.
+
+/// `push(array, value)`: appends `value` and returns the array.
+fn push(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let [array, value] = <[Value; 2]>::try_from(arguments).expect("arity is checked");
+    evaluator.track_store(&array, &value);
+    expect_array("push", &array)?.borrow_mut().push(value);
+    Ok(array)
+}
+
+/// `pop(array)`: removes and returns the last element, or `null` if the
+/// array is empty.
+fn pop(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let elements = expect_array("pop", &arguments[0])?;
+    let last = elements.borrow_mut().pop();
+    Ok(last.unwrap_or(Value::Null))
+}
+
+/// `insert(array, index, value)`: inserts `value` before the element at
+/// `index`, which may also be the array's length to append, and returns the
+/// array.
+fn insert(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let [array, index, value] = <[Value; 3]>::try_from(arguments).expect("arity is checked");
+    let elements = expect_array("insert", &array)?;
+    let len = elements.borrow().len();
+    let index = expect_index("insert", &index, len, true)?;
+
+    evaluator.track_store(&array, &value);
+    elements.borrow_mut().insert(index, value);
+    Ok(array)
+}
+
+/// `remove(array, index)`: removes and returns the element at `index`.
+/// Sets are handed to [`sets::remove`].
+fn remove(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    if let Value::Set(elements) = &arguments[0] {
+        return sets::remove(elements, &arguments[1]);
+    }
+    let elements = expect_array("remove", &arguments[0])?;
+    let len = elements.borrow().len();
+    let index = expect_index("remove", &arguments[1], len, false)?;
+
+    let removed = elements.borrow_mut().remove(index);
+    Ok(removed)
+}
+
+/// `first(array)`: the first element, or `null` if the array is empty.
+fn first(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let elements = expect_array("first", &arguments[0])?.borrow();
+    Ok(elements.first().cloned().unwrap_or(Value::Null))
+}
+
+/// `last(array)`: the last element, or `null` if the array is empty.
+fn last(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let elements = expect_array("last", &arguments[0])?.borrow();
+    Ok(elements.last().cloned().unwrap_or(Value::Null))
+}
+
+/// `rest(array)`: a new array of everything but the first element, or
+/// `null` if the array is empty.
+fn rest(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let elements = expect_array("rest", &arguments[0])?.borrow();
+    match elements.split_first() {
+        Some((_, rest)) => Ok(Value::array(rest.to_vec())),
+        None => Ok(Value::Null),
+    }
+}
+
+/// `keys(hash)`: an array of the hash's keys, in order.
+fn keys(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let pairs = expect_hash("keys", &arguments[0])?.borrow();
+    Ok(Value::array(
+        pairs.keys().cloned().map(Value::from).collect(),
+    ))
+}
+
+/// `values(hash)`: an array of the hash's values, in the order of their
+/// keys.
+fn values(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let pairs = expect_hash("values", &arguments[0])?.borrow();
+    Ok(Value::array(pairs.values().cloned().collect()))
+}
+
+// Higher-order builtins. They take anything a `for` loop can go over and
+// return a new array, calling back into Tofu code for each element.
+
+/// `map(xs, f)`: an array of `f(x)` for each `x` in `xs`.
+fn map(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let [xs, f] = <[Value; 2]>::try_from(arguments).expect("arity is checked");
+    let f = expect_function("map", f)?;
+
+    let mut mapped = Vec::new();
+    for x in expect_iterable("map", &xs)? {
+        mapped.push(evaluator.call(f.clone(), vec![x])?);
+    }
+
+    Ok(Value::array(mapped))
+}
+
+/// `filter(xs, f)`: an array of the `x` in `xs` for which `f(x)` is truthy.
+fn filter(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let [xs, f] = <[Value; 2]>::try_from(arguments).expect("arity is checked");
+    let f = expect_function("filter", f)?;
+
+    let mut kept = Vec::new();
+    for x in expect_iterable("filter", &xs)? {
+        if evaluator.call(f.clone(), vec![x.clone()])?.is_truthy() {
+            kept.push(x);
+        }
+    }
+
+    Ok(Value::array(kept))
+}
+
+/// `reduce(xs, initial, f)`: combines the elements of `xs` from the left,
+/// starting from `initial`, as `f(f(initial, x0), x1)` and so on.
+fn reduce(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let [xs, initial, f] = <[Value; 3]>::try_from(arguments).expect("arity is checked");
+    let f = expect_function("reduce", f)?;
+
+    let mut accumulator = initial;
+    for x in expect_iterable("reduce", &xs)? {
+        accumulator = evaluator.call(f.clone(), vec![accumulator, x])?;
+    }
+
+    Ok(accumulator)
+}
+
+/// `sort(xs)`, `sort(xs, key)` or `sort(xs, compare)`: a sorted array of
+/// the elements of `xs`. Without a function, elements are in their natural
+/// order, the one `<` uses. A function that takes one argument is a key:
+/// elements are in the natural order of what `key` returns for them, which
+/// it's called for once each. One that takes two compares: `compare(a, b)`
+/// returns a negative Int if `a` goes first, a positive one if `b` does,
+/// and zero if they're equal. Equal elements keep their order.
+fn sort(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let mut arguments = arguments.into_iter();
+    let xs: Vec<Value> =
+        expect_iterable("sort", &arguments.next().expect("arity is checked"))?.collect();
+    let function = arguments
+        .next()
+        .map(|function| expect_function("sort", function))
+        .transpose()?;
+
+    let sorted = match function {
+        Some(key) if !key.arity().is_some_and(|arity| arity.accepts(2)) => {
+            let keyed = xs
+                .into_iter()
+                .map(|x| Ok((evaluator.call(key.clone(), vec![x.clone()])?, x)))
+                .collect::<Result<_, RuntimeError>>()?;
+            merge_sort(keyed, &mut |(a, _), (b, _)| ordering(a, b))?
+                .into_iter()
+                .map(|(_, x)| x)
+                .collect()
+        }
+        Some(compare) => merge_sort(xs, &mut |a, b| match evaluator
+            .call(compare.clone(), vec![a.clone(), b.clone()])?
+        {
+            Value::Int(order) => Ok(order.cmp(&0)),
+            other => Err(RuntimeError::new(format!(
+                "`sort` expects its comparison function to return an Int, got {}",
+                other.type_name()
+            ))),
+        })?,
+        None => merge_sort(xs, &mut ordering)?,
+    };
+
+    Ok(Value::array(sorted))
+}
+
+/// A stable merge sort that stops at the first error from `compare`. The
+/// standard library's sorts may panic when the comparison isn't a total
+/// order, which a Tofu comparison function can't be trusted to be.
+fn merge_sort<T>(
+    mut values: Vec<T>,
+    compare: &mut impl FnMut(&T, &T) -> Result<Ordering, RuntimeError>,
+) -> Result<Vec<T>, RuntimeError> {
+    if values.len() < 2 {
+        return Ok(values);
+    }
+
+    let right = values.split_off(values.len() / 2);
+    let left = merge_sort(values, compare)?;
+    let right = merge_sort(right, compare)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // Taking from the left on ties keeps the sort stable.
+        if compare(a, b)? == Ordering::Greater {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+
+    Ok(merged)
+}
+
+fn expect_iterable(builtin: &str, value: &Value) -> Result<ValueIter, RuntimeError> {
+    value.iter().ok_or_else(|| {
+        RuntimeError::new(format!(
+            "`{builtin}` expects something iterable, got {}",
+            value.type_name()
+        ))
+    })
+}
+
+fn expect_function(builtin: &str, value: Value) -> Result<Value, RuntimeError> {
+    match value {
+        Value::Function(_)
+        | Value::Closure(_)
+        | Value::Builtin(_)
+        | Value::StructType(_)
+        | Value::VariantType(_) => Ok(value),
+        other => Err(RuntimeError::new(format!(
+            "`{builtin}` expects a function, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+fn expect_array<'a>(
+    builtin: &str,
+    value: &'a Value,
+) -> Result<&'a Rc<RefCell<Vec<Value>>>, RuntimeError> {
+    match value {
+        Value::Array(elements) => Ok(elements),
+        other => Err(RuntimeError::new(format!(
+            "`{builtin}` expects an Array, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+fn expect_hash<'a>(
+    builtin: &str,
+    value: &'a Value,
+) -> Result<&'a Rc<RefCell<IndexMap<HashKey, Value>>>, RuntimeError> {
+    match value {
+        Value::Hash(pairs) => Ok(pairs),
+        other => Err(RuntimeError::new(format!(
+            "`{builtin}` expects a Hash, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+/// `value` as a position in an array of length `len`. With `allow_end`,
+/// the position just past the last element is allowed too.
+fn expect_index(
+    builtin: &str,
+    value: &Value,
+    len: usize,
+    allow_end: bool,
+) -> Result<usize, RuntimeError> {
+    let Value::Int(index) = value else {
+        return Err(RuntimeError::new(format!(
+            "`{builtin}` expects an Int index, got {}",
+            value.type_name()
+        )));
+    };
+
+    usize::try_from(*index)
+        .ok()
+        .filter(|index| *index < len || (allow_end && *index == len))
+        .ok_or_else(|| {
+            RuntimeError::new(format!(
+                "`{builtin}` index {index} is out of bounds for an array of length {len}"
+            ))
+        })
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::rc::Rc;
+
+    use super::Arity;
+    use crate::ast::Program;
+    use crate::evaluator::{ErrorKind, Evaluator, RuntimeError};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::value::Value;
+
+    /// Collects everything written to it, for checking `print` output.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Output nothing reads any more, like a pipe whose reader has exited.
+    pub(crate) struct Closed;
+
+    impl Write for Closed {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Parses `input`, which must have no errors.
+    pub(crate) fn parse(input: &str) -> Program {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+
+        assert!(
+            parser.errors().is_empty(),
+            "parser had errors: {:?}",
+            parser.errors()
+        );
+
+        program
+    }
+
+    pub(crate) fn eval_with(evaluator: &mut Evaluator, input: &str) -> Result<Value, RuntimeError> {
+        evaluator.eval_program(&parse(input))
+    }
+
+    pub(crate) fn eval(input: &str) -> Result<Value, RuntimeError> {
+        eval_with(&mut Evaluator::new(), input)
+    }
+
+    /// Evaluates `input` after changing one of the evaluator's settings,
+    /// such as a permission, with `set`.
+    pub(super) fn eval_with_setting<T>(
+        input: &str,
+        set: fn(&mut Evaluator, T),
+        setting: T,
+    ) -> Result<Value, RuntimeError> {
+        let mut evaluator = Evaluator::new();
+        set(&mut evaluator, setting);
+        eval_with(&mut evaluator, input)
+    }
+
+    #[test]
+    fn test_len_and_type() {
+        let tests = vec![
+            (r#"len("")"#, Value::Int(0)),
+            (r#"len("tofu")"#, Value::Int(4)),
+            (r#"len("héllo")"#, Value::Int(5)),
+            ("len([1, 2, 3])", Value::Int(3)),
+            (r#"len({"a": 1})"#, Value::Int(1)),
+            ("len(0..10)", Value::Int(10)),
+            ("len(0..=10)", Value::Int(11)),
+            ("len(5..1)", Value::Int(0)),
+            ("type(1)", Value::Str("Int".to_string())),
+            ("type([])", Value::Str("Array".to_string())),
+            ("type(fn() {})", Value::Str("Function".to_string())),
+            ("type(len)", Value::Str("Builtin".to_string())),
+            (
+                "array(1..4)",
+                Value::array(vec![Value::Int(1), Value::Int(2), Value::Int(3)]),
+            ),
+            // Bindings shadow builtins.
+            ("let len = fn(x) { 42 }; len([])", Value::Int(42)),
+            ("len == len", Value::Bool(true)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Ok(expected), eval(input), "input={input}");
+        }
+    }
+
+    #[test]
+    fn test_conversions() {
+        let tests = vec![
+            ("to_int(3)", Value::Int(3)),
+            ("to_int(3.9)", Value::Int(3)),
+            ("to_int(-3.9)", Value::Int(-3)),
+            (r#"to_int("-42")"#, Value::Int(-42)),
+            ("to_float(3)", Value::Float(3.0)),
+            ("to_float(2.5)", Value::Float(2.5)),
+            (r#"to_float("2.5")"#, Value::Float(2.5)),
+            (r#"to_float("1e3")"#, Value::Float(1000.0)),
+            ("to_int(7 / 2.0) == 7 / 2", Value::Bool(true)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Ok(expected), eval(input), "input={input}");
+        }
+    }
+
+    #[test]
+    fn test_builtin_errors() {
+        let tests = vec![
+            (
+                "len(1)",
+                "`len` expects a Str, Array, Hash or Range, got Int",
+            ),
+            (
+                "len(1, 2)",
+                "wrong number of arguments to `len`: expected 1, got 2",
+            ),
+            (
+                "array(true)",
+                "`array` expects something iterable, got Bool",
+            ),
+            (
+                r#"to_int("1.5")"#,
+                r#"`to_int` cannot convert "1.5" to an Int"#,
+            ),
+            ("to_int(1.0 / 0.0)", "`to_int` cannot convert inf to an Int"),
+            (
+                "to_float(null)",
+                "`to_float` expects an Int, Float or Str, got Null",
+            ),
+            (
+                r#"to_float("tofu")"#,
+                r#"`to_float` cannot convert "tofu" to a Float"#,
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(expected, eval(input).unwrap_err().message, "input={input}");
+        }
+    }
+
+    #[test]
+    fn test_assertions() {
+        let passing = r#"assert(true); assert(1, "truthy"); assert_eq(1, 1.0); assert_eq([1, "a"], [1, "a"])"#;
+        assert_eq!(Ok(Value::Null), eval(passing));
+
+        let tests = vec![
+            ("assert(null)", "1:7: assertion failed"),
+            (
+                r#"let x = 3;
+assert(x > 5, "x is too small")"#,
+                "2:7: assertion failed: x is too small",
+            ),
+            ("assert_eq(1 + 1, 3)", "1:10: assertion failed: 2 != 3"),
+            (
+                r#"assert_eq("1", 1)"#,
+                r#"1:10: assertion failed: "1" != 1"#,
+            ),
+            (
+                "let check = fn(x) { assert_eq(x, 0); x }; check(1); 0",
+                "1:30: assertion failed: 1 != 0\n    in check, called from 1:48",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                expected,
+                eval(input).unwrap_err().to_string(),
+                "input={input}"
+            );
+        }
+
+        let mut evaluator = Evaluator::new();
+        evaluator.set_collect_assertions(true);
+        let result = eval_with(
+            &mut evaluator,
+            "assert_eq(1, 2); assert(true); assert(false, 'x'); 42",
+        );
+
+        assert_eq!(Ok(Value::Int(42)), result);
+        let assertions = evaluator.assertions();
+        assert_eq!(3, assertions.checked);
+        let failed: Vec<String> = assertions.failed.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "1:10: assertion failed: 1 != 2",
+                "1:38: assertion failed: x"
+            ],
+            failed
+        );
+    }
+
+    #[test]
+    fn test_array_builtins() {
+        let ints = |values: &[i64]| Value::array(values.iter().copied().map(Value::Int).collect());
+
+        let tests = vec![
+            ("let a = [1]; push(a, 2); a", ints(&[1, 2])),
+            ("push([], 1)", ints(&[1])),
+            // Every binding of the array sees the change.
+            ("let a = [1]; let b = a; push(b, 2); a", ints(&[1, 2])),
+            ("let a = [1, 2]; pop(a)", Value::Int(2)),
+            ("let a = [1, 2]; pop(a); a", ints(&[1])),
+            ("pop([])", Value::Null),
+            ("let a = [1, 3]; insert(a, 1, 2); a", ints(&[1, 2, 3])),
+            ("insert([1], 1, 2)", ints(&[1, 2])),
+            ("insert([], 0, 1)", ints(&[1])),
+            ("let a = [1, 2, 3]; remove(a, 0)", Value::Int(1)),
+            ("let a = [1, 2, 3]; remove(a, 1); a", ints(&[1, 3])),
+            ("first([1, 2, 3])", Value::Int(1)),
+            ("first([])", Value::Null),
+            ("last([1, 2, 3])", Value::Int(3)),
+            ("last([])", Value::Null),
+            ("rest([1, 2, 3])", ints(&[2, 3])),
+            ("rest([1])", ints(&[])),
+            ("rest([])", Value::Null),
+            ("let a = [1, 2]; rest(a); a", ints(&[1, 2])),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Ok(expected), eval(input), "input={input}");
+        }
+
+        let errors = vec![
+            ("push(1, 2)", "`push` expects an Array, got Int"),
+            ("first(\"abc\")", "`first` expects an Array, got Str"),
+            (
+                "insert([1], 2, 0)",
+                "`insert` index 2 is out of bounds for an array of length 1",
+            ),
+            (
+                "remove([1], 1)",
+                "`remove` index 1 is out of bounds for an array of length 1",
+            ),
+            (
+                "remove([1], -1)",
+                "`remove` index -1 is out of bounds for an array of length 1",
+            ),
+            (
+                "remove([1], true)",
+                "`remove` expects an Int index, got Bool",
+            ),
+        ];
+
+        for (input, expected) in errors {
+            assert_eq!(expected, eval(input).unwrap_err().message, "input={input}");
+        }
+    }
+
+    #[test]
+    fn test_arrays_that_contain_themselves() {
+        let input = "let xs = [1]; push(xs, xs); let ys = [1]; push(ys, ys);";
+        let tests = vec![
+            ("xs == xs", Value::Bool(true)),
+            ("xs == ys", Value::Bool(true)),
+            ("xs == [1, [1]]", Value::Bool(false)),
+            ("xs < ys", Value::Bool(false)),
+            ("xs <= ys", Value::Bool(true)),
+            ("[xs] < [ys, 1]", Value::Bool(true)),
+        ];
+
+        for (test, expected) in tests {
+            let input = format!("{input} {test}");
+            assert_eq!(Ok(expected), eval(&input), "input={input}");
+        }
+
+        let shown = eval(&format!("{input} [xs, xs]")).unwrap().to_string();
+        assert_eq!("[[1, [...]], [1, [...]]]", shown);
+    }
+
+    #[test]
+    fn test_higher_order_builtins() {
+        let ints = |values: &[i64]| Value::array(values.iter().copied().map(Value::Int).collect());
+        let strs = |values: &[&str]| {
+            Value::array(
+                values
+                    .iter()
+                    .map(|value| Value::Str(value.to_string()))
+                    .collect(),
+            )
+        };
+
+        let tests = vec![
+            ("map([1, 2, 3], fn(x) { x * 2 })", ints(&[2, 4, 6])),
+            ("map(1..4, fn(x) { x * x })", ints(&[1, 4, 9])),
+            ("map([], fn(x) { missing })", ints(&[])),
+            (r#"map(["a", "bc"], len)"#, ints(&[1, 2])),
+            ("filter(0..10, fn(x) { x > 6 })", ints(&[7, 8, 9])),
+            ("filter([1, null, false, 0], fn(x) { x })", ints(&[1, 0])),
+            (
+                "reduce([1, 2, 3, 4], 0, fn(sum, x) { sum + x })",
+                Value::Int(10),
+            ),
+            ("reduce([], 42, fn(sum, x) { sum + x })", Value::Int(42)),
+            (
+                r#"reduce(["a", "b"], "", fn(s, x) { x + s })"#,
+                Value::Str("ba".to_string()),
+            ),
+            ("sort([3, 1, 2])", ints(&[1, 2, 3])),
+            ("sort([])", ints(&[])),
+            (
+                "sort([2, 1.5, 1])",
+                Value::array(vec![Value::Int(1), Value::Float(1.5), Value::Int(2)]),
+            ),
+            (
+                r#"sort(["pear", "apple", "fig"])"#,
+                strs(&["apple", "fig", "pear"]),
+            ),
+            ("sort([3, 1, 2], fn(a, b) { b - a })", ints(&[3, 2, 1])),
+            // The sort is stable, so ties keep their order.
+            (
+                r#"sort(["bb", "a", "cc", "d"], fn(a, b) { len(a) - len(b) })"#,
+                strs(&["a", "d", "bb", "cc"]),
+            ),
+            ("let xs = [2, 1]; sort(xs); xs", ints(&[2, 1])),
+            // A function of one argument gives the key to sort by.
+            (
+                r#"sort(["ccc", "a", "bb", "d"], len)"#,
+                strs(&["a", "d", "bb", "ccc"]),
+            ),
+            ("sort([3, -1, 2], fn(x) { -x })", ints(&[3, 2, -1])),
+            (
+                "sort([[2, 1], [1, 5], [1]])",
+                Value::array(vec![ints(&[1]), ints(&[1, 5]), ints(&[2, 1])]),
+            ),
+            (
+                r#"sort([("b", 1), ("a", 2)], fn(pair) { pair.0 })[0].1"#,
+                Value::Int(2),
+            ),
+            // Callbacks are ordinary closures.
+            (
+                "let factor = 10; map([1, 2], fn(x) { x * factor })",
+                ints(&[10, 20]),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Ok(expected), eval(input), "input={input}");
+        }
+
+        let errors = vec![
+            (
+                "map(1, fn(x) { x })",
+                "`map` expects something iterable, got Int",
+            ),
+            ("map([1], 2)", "`map` expects a function, got Int"),
+            (
+                "filter([1], fn() { true })",
+                "wrong number of arguments: expected 0, got 1",
+            ),
+            (r#"sort([1, "a"])"#, "type mismatch: Int < Str"),
+            (r#"sort([[1], ["a"]])"#, "type mismatch: Int < Str"),
+            ("sort([1, 0.0 / 0.0])", "cannot order NaN: 1 and NaN"),
+            (
+                "sort([{}, {}], fn(x) { x })",
+                "unknown operator: Hash < Hash",
+            ),
+            (
+                "sort([1, 2], fn(a, b) { a < b })",
+                "`sort` expects its comparison function to return an Int, got Bool",
+            ),
+            (
+                "sort([1], fn(a, b) { 0 }, 3)",
+                "wrong number of arguments to `sort`: expected 1 or 2, got 3",
+            ),
+        ];
+
+        for (input, expected) in errors {
+            assert_eq!(expected, eval(input).unwrap_err().message, "input={input}");
+        }
+
+        // Errors inside a callback trace back to the builtin's call.
+        assert_eq!(
+            "1:20: type mismatch: Int + Bool\n    in anonymous function, called from 1:4",
+            eval("map([1], fn(x) { x + true })")
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_print() {
+        let output = Output::default();
+        let mut evaluator = Evaluator::new();
+        evaluator.set_output(Box::new(output.clone()));
+
+        let result = eval_with(
+            &mut evaluator,
+            r#"print("a", 1); print(); println([1, "b"], null); println();
+               println("{}: {:03}", "id", 7); println("{}"); print("{{}} {}", {})"#,
+        );
+
+        assert_eq!(Ok(Value::Null), result);
+        assert_eq!(
+            "a 1[1, \"b\"] null\n\nid: 007\n{}\n{} {}",
+            String::from_utf8(output.0.borrow().clone()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_input() {
+        let output = Output::default();
+        let mut evaluator = Evaluator::new();
+        evaluator.set_output(Box::new(output.clone()));
+        evaluator.set_input(Box::new("tofu\r\n\nlast".as_bytes()));
+
+        let result = eval_with(
+            &mut evaluator,
+            r#"[input("name? "), input(), input("> "), input(), input()]"#,
+        );
+
+        assert_eq!(
+            r#"["tofu", "", "last", null, null]"#,
+            result.unwrap().to_string()
+        );
+        assert_eq!(
+            "name? > ",
+            String::from_utf8(output.0.borrow().clone()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_exit() {
+        let tests = [
+            ("exit()", ErrorKind::Exit(0)),
+            ("exit(3); 4", ErrorKind::Exit(3)),
+            ("try { exit(255) } catch (e) { 1 }", ErrorKind::Exit(255)),
+            ("exit(256)", ErrorKind::Error),
+            ("exit(\"1\")", ErrorKind::Error),
+        ];
+
+        for (input, expected) in tests {
+            let error = eval_with(&mut Evaluator::new(), input).unwrap_err();
+            assert_eq!(expected, error.kind, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_print_to_closed_output() {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_output(Box::new(Closed));
+
+        let error = eval_with(&mut evaluator, "try { print(1) } catch (e) { 2 }").unwrap_err();
+        assert_eq!(ErrorKind::Exit(0), error.kind);
+    }
+
+    #[test]
+    fn test_register() {
+        let mut evaluator = Evaluator::new();
+        evaluator
+            .builtins_mut()
+            .register("sum", Arity::AtLeast(1), |_, arguments| {
+                let mut total = 0;
+                for argument in arguments {
+                    match argument {
+                        Value::Int(value) => total += value,
+                        other => {
+                            return Err(RuntimeError::new(format!(
+                                "cannot sum {}",
+                                other.type_name()
+                            )))
+                        }
+                    }
+                }
+                Ok(Value::Int(total))
+            });
+
+        assert_eq!(Ok(Value::Int(6)), eval_with(&mut evaluator, "sum(1, 2, 3)"));
+        assert_eq!(
+            "wrong number of arguments to `sum`: expected at least 1, got 0",
+            eval_with(&mut evaluator, "sum()").unwrap_err().message
+        );
+        assert_eq!(
+            "1:4: cannot sum Bool",
+            eval_with(&mut evaluator, "sum(true)")
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_methods() {
+        let tests = vec![
+            ("[1, 2, 3].len()", "3"),
+            (r#""tofu".upper()"#, "TOFU"),
+            (
+                r#"let h = {"a": 1, "b": 2}; [h.keys(), h.values(), h.len()]"#,
+                r#"[["a", "b"], [1, 2], 2]"#,
+            ),
+            (r#"" a,b ".trim().split(",").join("-")"#, "a-b"),
+            (
+                "(1..4).map(fn(x) { x * 2 }).filter(fn(x) { x > 2 })",
+                "[4, 6]",
+            ),
+            ("let xs = [3]; xs.push(1).sort()", "[1, 3]"),
+            ("(-2).abs() + 2.5.floor()", "4"),
+            (r#""{}-{}".format(1, 2)"#, "1-2"),
+            // Methods don't go through bindings, so shadowing a builtin
+            // leaves its method alone.
+            ("let len = fn(x) { 0 }; [len([1]), [1].len()]", "[0, 1]"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                Ok(expected.to_string()),
+                eval(input).map(|value| value.to_string()),
+                "input={input}"
+            );
+        }
+
+        let errors = vec![
+            ("1.upper()", "1:2: Int has no method `upper`"),
+            ("[].keys()", "1:3: Array has no method `keys`"),
+            (
+                "[1].first(2)",
+                "1:4: wrong number of arguments to `first`: expected 1, got 2",
+            ),
+            ("keys([])", "1:5: `keys` expects a Hash, got Array"),
+        ];
+
+        for (input, expected) in errors {
+            assert_eq!(
+                expected,
+                eval(input).unwrap_err().to_string(),
+                "input={input}"
+            );
+        }
+
+        let mut evaluator = Evaluator::new();
+        let len = evaluator.builtins_mut().get("len").unwrap();
+        evaluator.builtins_mut().define_method("Bool", "size", len);
+        assert_eq!(
+            "1:5: `len` expects a Str, Array, Hash or Range, got Bool",
+            eval_with(&mut evaluator, "true.size()")
+                .unwrap_err()
+                .to_string()
+        );
+    }
+}
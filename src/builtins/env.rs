@@ -0,0 +1,166 @@
+//! Environment variable builtins. Names and values are Strs; variables
+//! whose name or value isn't valid Unicode are left out. Like the file
+//! builtins, they all fail while environment access is turned off with
+//! [`Evaluator::set_allow_env`]. `env_set` doesn't change the process's
+//! environment, which other threads may be reading; the variables it sets
+//! are kept by the evaluator, in [`Evaluator::env_vars`], and seen over the
+//! process's by these builtins and the programs `exec` runs.
+
+use indexmap::IndexMap;
+
+use super::{Arity, Builtins};
+use crate::evaluator::{Evaluator, RuntimeError};
+use crate::value::{HashKey, Value};
+
+pub(super) fn register(builtins: &mut Builtins) {
+    builtins.register("env_get", Arity::Exact(1), env_get);
+    builtins.register("env_set", Arity::Exact(2), env_set);
+    builtins.register("env_vars", Arity::Exact(0), env_vars);
+}
+
+/// `env_get(name)`: the value of the environment variable `name`, or
+/// `null` if it isn't set.
+fn env_get(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    check_allowed("env_get", evaluator)?;
+    let name = expect_name("env_get", &arguments[0])?;
+
+    Ok(evaluator.env_var(name).map_or(Value::Null, Value::Str))
+}
+
+/// `env_set(name, value)`: sets the environment variable `name` to
+/// `value`, for the rest of the program and anything it starts.
+fn env_set(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    check_allowed("env_set", evaluator)?;
+    let name = expect_name("env_set", &arguments[0])?;
+    let value = match &arguments[1] {
+        Value::Str(value) if !value.contains('\0') => value,
+        Value::Str(_) => {
+            return Err(RuntimeError::new(
+                "`env_set` expects a value without a NUL character",
+            ))
+        }
+        other => {
+            return Err(RuntimeError::new(format!(
+                "`env_set` expects a Str value, got {}",
+                other.type_name()
+            )))
+        }
+    };
+
+    evaluator.set_env_var(name, value);
+    Ok(Value::Null)
+}
+
+/// `env_vars()`: a hash of every environment variable's value by name,
+/// sorted by name.
+fn env_vars(evaluator: &mut Evaluator, _: Vec<Value>) -> Result<Value, RuntimeError> {
+    check_allowed("env_vars", evaluator)?;
+
+    let mut vars: Vec<(String, String)> = std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .filter(|(name, _)| !evaluator.env_vars().contains_key(name))
+        .chain(evaluator.env_vars().clone())
+        .collect();
+    vars.sort();
+
+    let pairs: IndexMap<HashKey, Value> = vars
+        .into_iter()
+        .map(|(name, value)| (HashKey::Str(name), Value::Str(value)))
+        .collect();
+    Ok(Value::hash(pairs))
+}
+
+fn check_allowed(builtin: &str, evaluator: &Evaluator) -> Result<(), RuntimeError> {
+    if evaluator.allow_env() {
+        Ok(())
+    } else {
+        Err(RuntimeError::new(format!(
+            "`{builtin}` is unavailable: environment access is disabled"
+        )))
+    }
+}
+
+/// The variable name `builtin` was given, which the OS would reject if it
+/// were empty or held a `=` or a NUL.
+fn expect_name<'a>(builtin: &str, value: &'a Value) -> Result<&'a str, RuntimeError> {
+    match value {
+        Value::Str(name) if !name.is_empty() && !name.contains(['=', '\0']) => Ok(name),
+        Value::Str(name) => Err(RuntimeError::new(format!(
+            "`{builtin}` expects a variable name, got {name:?}"
+        ))),
+        other => Err(RuntimeError::new(format!(
+            "`{builtin}` expects a Str name, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::builtins::test::eval_with_setting;
+    use crate::evaluator::Evaluator;
+
+    #[test]
+    fn test_env_builtins() {
+        let input = r#"let before = env_get("TOFU_TEST_ENV_BUILTINS");
+            env_set("TOFU_TEST_ENV_BUILTINS", "tofu");
+            env_set("PATH", "/tofu");
+            let vars = env_vars();
+            [before, env_get("TOFU_TEST_ENV_BUILTINS"), vars["TOFU_TEST_ENV_BUILTINS"],
+             env_get("PATH"), vars["PATH"], len(filter(keys(vars), fn(k) { k == "PATH" }))]"#;
+        assert_eq!(
+            r#"[null, "tofu", "tofu", "/tofu", "/tofu", 1]"#,
+            eval_with_setting(input, Evaluator::set_allow_env, true)
+                .unwrap()
+                .to_string()
+        );
+        // The process's environment is left alone.
+        assert!(std::env::var_os("TOFU_TEST_ENV_BUILTINS").is_none());
+        assert_ne!(Some("/tofu".into()), std::env::var_os("PATH"));
+
+        let errors = vec![
+            ("env_get(1)", "`env_get` expects a Str name, got Int"),
+            (
+                r#"env_get("")"#,
+                r#"`env_get` expects a variable name, got """#,
+            ),
+            (
+                r#"env_set("A=B", "x")"#,
+                r#"`env_set` expects a variable name, got "A=B""#,
+            ),
+            (
+                r#"env_set("A", 1)"#,
+                "`env_set` expects a Str value, got Int",
+            ),
+        ];
+
+        for (input, expected) in errors {
+            assert_eq!(
+                expected,
+                eval_with_setting(input, Evaluator::set_allow_env, true)
+                    .unwrap_err()
+                    .message,
+                "input={input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_env_builtins_disabled() {
+        let tests = vec![
+            (r#"env_get("PATH")"#, "env_get"),
+            (r#"env_set("PATH", "x")"#, "env_set"),
+            ("env_vars()", "env_vars"),
+        ];
+
+        for (input, builtin) in tests {
+            assert_eq!(
+                format!("`{builtin}` is unavailable: environment access is disabled"),
+                eval_with_setting(input, Evaluator::set_allow_env, false)
+                    .unwrap_err()
+                    .message,
+                "input={input}"
+            );
+        }
+    }
+}
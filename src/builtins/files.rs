@@ -0,0 +1,180 @@
+//! File builtins. Paths are Strs, resolved against the working directory,
+//! and files are read and written as UTF-8 text. They all fail while
+//! filesystem access is turned off with [`Evaluator::set_allow_files`], so
+//! an untrusted script can be run without touching the disk.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use super::{Arity, Builtins};
+use crate::evaluator::{Evaluator, RuntimeError};
+use crate::value::Value;
+
+pub(super) fn register(builtins: &mut Builtins) {
+    builtins.register("read_file", Arity::Exact(1), read_file);
+    builtins.register("write_file", Arity::Exact(2), |evaluator, arguments| {
+        write("write_file", evaluator, arguments, false)
+    });
+    builtins.register("append_file", Arity::Exact(2), |evaluator, arguments| {
+        write("append_file", evaluator, arguments, true)
+    });
+    builtins.register("file_exists", Arity::Exact(1), file_exists);
+}
+
+/// `read_file(path)`: the contents of the file at `path`.
+fn read_file(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let path = expect_path("read_file", evaluator, &arguments[0])?;
+
+    std::fs::read_to_string(path)
+        .map(Value::Str)
+        .map_err(|error| RuntimeError::new(format!("could not read {path}: {error}")))
+}
+
+/// `write_file(path, contents)` and `append_file(path, contents)`: writes
+/// `contents` to the file at `path`, replacing what was there or adding to
+/// the end of it. Either creates the file if it doesn't exist.
+fn write(
+    builtin: &str,
+    evaluator: &mut Evaluator,
+    arguments: Vec<Value>,
+    append: bool,
+) -> Result<Value, RuntimeError> {
+    let path = expect_path(builtin, evaluator, &arguments[0])?;
+    let Value::Str(contents) = &arguments[1] else {
+        return Err(RuntimeError::new(format!(
+            "`{builtin}` expects Str contents, got {}",
+            arguments[1].type_name()
+        )));
+    };
+
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map(|()| Value::Null)
+        .map_err(|error| RuntimeError::new(format!("could not write {path}: {error}")))
+}
+
+/// `file_exists(path)`: whether there is a file at `path`.
+fn file_exists(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let path = expect_path("file_exists", evaluator, &arguments[0])?;
+    Ok(Value::Bool(std::path::Path::new(path).is_file()))
+}
+
+/// The path `builtin` was given, as long as filesystem access is allowed.
+fn expect_path<'a>(
+    builtin: &str,
+    evaluator: &Evaluator,
+    value: &'a Value,
+) -> Result<&'a str, RuntimeError> {
+    if !evaluator.allow_files() {
+        return Err(RuntimeError::new(format!(
+            "`{builtin}` is unavailable: filesystem access is disabled"
+        )));
+    }
+
+    match value {
+        Value::Str(path) => Ok(path),
+        other => Err(RuntimeError::new(format!(
+            "`{builtin}` expects a Str path, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::builtins::test::eval_with_setting;
+    use crate::evaluator::Evaluator;
+    use crate::value::Value;
+
+    /// A path in the temporary directory that no other test run uses.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tofu-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn test_file_builtins() {
+        let path = temp_path("notes.txt");
+        let path = path.to_str().expect("temp paths are UTF-8 here");
+        let _ = std::fs::remove_file(path);
+
+        let input = format!(
+            r#"let path = {path:?};
+            let before = file_exists(path);
+            write_file(path, "tofu\n");
+            append_file(path, "tempeh\n");
+            [before, file_exists(path), read_file(path)]"#
+        );
+        assert_eq!(
+            "[false, true, \"tofu\\ntempeh\\n\"]",
+            eval_with_setting(&input, Evaluator::set_allow_files, true)
+                .unwrap()
+                .to_string()
+        );
+
+        let input = format!(r#"write_file({path:?}, "seitan"); read_file({path:?})"#);
+        assert_eq!(
+            Ok(Value::Str("seitan".to_string())),
+            eval_with_setting(&input, Evaluator::set_allow_files, true)
+        );
+
+        std::fs::remove_file(path).unwrap();
+
+        let missing = format!("read_file({path:?})");
+        assert!(
+            eval_with_setting(&missing, Evaluator::set_allow_files, true)
+                .unwrap_err()
+                .message
+                .starts_with(&format!("could not read {path}: "))
+        );
+
+        let errors = vec![
+            ("read_file(1)", "`read_file` expects a Str path, got Int"),
+            (
+                r#"write_file("x", 1)"#,
+                "`write_file` expects Str contents, got Int",
+            ),
+        ];
+
+        for (input, expected) in errors {
+            assert_eq!(
+                expected,
+                eval_with_setting(input, Evaluator::set_allow_files, true)
+                    .unwrap_err()
+                    .message,
+                "input={input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_file_builtins_disabled() {
+        let path = temp_path("sandboxed.txt");
+        let path = path.to_str().expect("temp paths are UTF-8 here");
+
+        let tests = vec![
+            (format!("read_file({path:?})"), "read_file"),
+            (format!(r#"write_file({path:?}, "x")"#), "write_file"),
+            (format!(r#"append_file({path:?}, "x")"#), "append_file"),
+            (format!("file_exists({path:?})"), "file_exists"),
+        ];
+
+        for (input, builtin) in tests {
+            assert_eq!(
+                format!("`{builtin}` is unavailable: filesystem access is disabled"),
+                eval_with_setting(&input, Evaluator::set_allow_files, false)
+                    .unwrap_err()
+                    .message,
+                "input={input}"
+            );
+        }
+
+        assert!(!std::path::Path::new(path).exists());
+    }
+}
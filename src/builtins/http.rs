@@ -0,0 +1,255 @@
+//! HTTP builtins, with the `http` feature. Requests go through the
+//! [`ureq`](https://docs.rs/ureq) crate, over TLS for `https` URLs, and
+//! give up after [`TIMEOUT`]. They all fail while network access is turned
+//! off with [`Evaluator::set_allow_net`].
+
+use std::time::Duration;
+
+use indexmap::IndexMap;
+
+use super::{Arity, Builtins};
+use crate::evaluator::{Evaluator, RuntimeError};
+use crate::value::{HashKey, Value};
+
+/// How long a request may take, from connecting to reading the last of
+/// the body.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub(super) fn register(builtins: &mut Builtins) {
+    builtins.register("http_get", Arity::Exact(1), http_get);
+    builtins.register("http_post", Arity::Between(2, 3), http_post);
+}
+
+/// `http_get(url)`: the response to a GET request for `url`, as a hash of
+/// its `status`, `headers` and `body`. A response with an error status is
+/// still a response; only failing to get one is an error.
+fn http_get(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let url = expect_url("http_get", evaluator, &arguments[0])?;
+    respond(url, agent().get(url).call())
+}
+
+/// `http_post(url, body)` or `http_post(url, body, headers)`: the response
+/// to a POST request sending the Str `body` to `url`, with the request
+/// headers in the hash `headers`, as `http_get` gives it.
+fn http_post(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let url = expect_url("http_post", evaluator, &arguments[0])?;
+    let Value::Str(body) = &arguments[1] else {
+        return Err(RuntimeError::new(format!(
+            "`http_post` expects a Str body, got {}",
+            arguments[1].type_name()
+        )));
+    };
+
+    let mut request = agent().post(url);
+    if let Some(headers) = arguments.get(2) {
+        let Value::Hash(headers) = headers else {
+            return Err(RuntimeError::new(format!(
+                "`http_post` expects a Hash of headers, got {}",
+                headers.type_name()
+            )));
+        };
+        for (name, value) in headers.borrow().iter() {
+            let (HashKey::Str(name), Value::Str(value)) = (name, value) else {
+                return Err(RuntimeError::new(
+                    "`http_post` expects headers with Str names and values",
+                ));
+            };
+            request = request.set(name, value);
+        }
+    }
+
+    respond(url, request.send_string(body))
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new().timeout(TIMEOUT).build()
+}
+
+/// The response `result` holds, as a hash, or the error that kept a
+/// request for `url` from getting one. Header names are lowercase, and
+/// the values of a header sent more than once are joined with commas.
+fn respond(url: &str, result: Result<ureq::Response, ureq::Error>) -> Result<Value, RuntimeError> {
+    let response = match result {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(error) => return Err(RuntimeError::new(format!("could not fetch {url}: {error}"))),
+    };
+
+    let mut headers = IndexMap::new();
+    for name in response.headers_names() {
+        let value = response.all(&name).join(", ");
+        headers.insert(HashKey::Str(name.to_lowercase()), Value::Str(value));
+    }
+    let status = Value::Int(response.status().into());
+    let body = response
+        .into_string()
+        .map_err(|error| RuntimeError::new(format!("could not fetch {url}: {error}")))?;
+
+    let mut result = IndexMap::new();
+    result.insert(HashKey::Str("status".to_string()), status);
+    result.insert(HashKey::Str("headers".to_string()), Value::hash(headers));
+    result.insert(HashKey::Str("body".to_string()), Value::Str(body));
+    Ok(Value::hash(result))
+}
+
+/// The URL `builtin` was given, as long as network access is allowed.
+fn expect_url<'a>(
+    builtin: &str,
+    evaluator: &Evaluator,
+    value: &'a Value,
+) -> Result<&'a str, RuntimeError> {
+    if !evaluator.allow_net() {
+        return Err(RuntimeError::new(format!(
+            "`{builtin}` is unavailable: network access is disabled"
+        )));
+    }
+
+    match value {
+        Value::Str(url) => Ok(url),
+        other => Err(RuntimeError::new(format!(
+            "`{builtin}` expects a Str URL, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use crate::builtins::test::eval_with_setting;
+    use crate::evaluator::Evaluator;
+
+    /// Serves one request on a local port with `status`, echoing the
+    /// request's method, path, `x-token` header and body back in the
+    /// response's body. Gives the server's URL.
+    fn serve_once(status: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let (mut length, mut token) = (0, String::new());
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                let (name, value) = line.split_once(": ").unwrap();
+                match name.to_lowercase().as_str() {
+                    "content-length" => length = value.parse().unwrap(),
+                    "x-token" => token = value.to_string(),
+                    _ => {}
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let mut words = request_line.split(' ');
+            let echo = format!(
+                "{} {} {token} {}",
+                words.next().unwrap(),
+                words.next().unwrap(),
+                String::from_utf8(body).unwrap()
+            );
+            let response = format!(
+                "HTTP/1.1 {status}\r\nContent-Length: {}\r\nX-Served-By: tofu\r\n\
+                 Connection: close\r\n\r\n{echo}",
+                echo.len()
+            );
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+        });
+
+        url
+    }
+
+    #[test]
+    fn test_http_builtins() {
+        let url = serve_once("200 OK");
+        let input = format!(
+            r#"let response = http_get("{url}/items?n=1");
+            [response["status"], response["headers"]["x-served-by"], response["body"]]"#
+        );
+        assert_eq!(
+            r#"[200, "tofu", "GET /items?n=1  "]"#,
+            eval_with_setting(&input, Evaluator::set_allow_net, true)
+                .unwrap()
+                .to_string()
+        );
+
+        let url = serve_once("404 Not Found");
+        let input = format!(
+            r#"let response = http_post("{url}/items", "tofu", {{"X-Token": "secret"}});
+            [response["status"], response["body"]]"#
+        );
+        assert_eq!(
+            r#"[404, "POST /items secret tofu"]"#,
+            eval_with_setting(&input, Evaluator::set_allow_net, true)
+                .unwrap()
+                .to_string()
+        );
+
+        // Nothing is listening on a port that was just freed.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let input = format!(r#"http_get("http://127.0.0.1:{port}/")"#);
+        assert!(eval_with_setting(&input, Evaluator::set_allow_net, true)
+            .unwrap_err()
+            .message
+            .starts_with(&format!("could not fetch http://127.0.0.1:{port}/: ")));
+
+        let errors = vec![
+            ("http_get(1)", "`http_get` expects a Str URL, got Int"),
+            (
+                r#"http_post("http://localhost", 1)"#,
+                "`http_post` expects a Str body, got Int",
+            ),
+            (
+                r#"http_post("http://localhost", "", [])"#,
+                "`http_post` expects a Hash of headers, got Array",
+            ),
+            (
+                r#"http_post("http://localhost", "", {"a": 1})"#,
+                "`http_post` expects headers with Str names and values",
+            ),
+        ];
+
+        for (input, expected) in errors {
+            assert_eq!(
+                expected,
+                eval_with_setting(input, Evaluator::set_allow_net, true)
+                    .unwrap_err()
+                    .message,
+                "input={input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_http_builtins_disabled() {
+        let tests = vec![
+            (r#"http_get("http://localhost")"#, "http_get"),
+            (r#"http_post("http://localhost", "")"#, "http_post"),
+        ];
+
+        for (input, builtin) in tests {
+            assert_eq!(
+                format!("`{builtin}` is unavailable: network access is disabled"),
+                eval_with_setting(input, Evaluator::set_allow_net, false)
+                    .unwrap_err()
+                    .message,
+                "input={input}"
+            );
+        }
+    }
+}
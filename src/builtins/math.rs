@@ -0,0 +1,298 @@
+//! Math builtins. They take Ints and Floats alike. Where a result has no
+//! sensible value, like `sqrt(-1)` or `pow(0, -1)`, they raise an error
+//! instead of quietly returning NaN or infinity, and Int results follow the
+//! evaluator's overflow setting like arithmetic does. Each is also in the
+//! `math` namespace, as in `math.sqrt(2)`.
+
+use std::cmp::Ordering;
+
+use super::{float_to_int, Arity, Builtins};
+use crate::ast::PrefixOperator;
+#[cfg(feature = "bigint")]
+use crate::bigint;
+use crate::evaluator::{
+    eval_prefix_expression, ordering, square_and_multiply, Evaluator, Overflow, RuntimeError,
+};
+use crate::value::{HashKey, Value};
+
+/// The builtins the `math` namespace holds.
+const NAMES: &[&str] = &["pi", "sqrt", "pow", "abs", "floor", "ceil", "min", "max"];
+
+pub(super) fn register(builtins: &mut Builtins) {
+    builtins.define("pi", Value::Float(std::f64::consts::PI));
+    builtins.register("sqrt", Arity::Exact(1), sqrt);
+    builtins.register("pow", Arity::Exact(2), pow);
+    builtins.register("abs", Arity::Exact(1), abs);
+    builtins.register("floor", Arity::Exact(1), |_, arguments| {
+        round("floor", &arguments[0], f64::floor)
+    });
+    builtins.register("ceil", Arity::Exact(1), |_, arguments| {
+        round("ceil", &arguments[0], f64::ceil)
+    });
+    builtins.register("min", Arity::AtLeast(1), |_, arguments| {
+        extreme("min", arguments, Ordering::Less)
+    });
+    builtins.register("max", Arity::AtLeast(1), |_, arguments| {
+        extreme("max", arguments, Ordering::Greater)
+    });
+
+    let namespace = NAMES
+        .iter()
+        .map(|name| {
+            let builtin = builtins.get(name).expect("math builtins are registered");
+            (HashKey::Str(name.to_string()), builtin)
+        })
+        .collect();
+    builtins.define("math", Value::hash(namespace));
+}
+
+/// `sqrt(x)`: the square root of `x`, as a Float.
+fn sqrt(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let value = expect_number("sqrt", &arguments[0])?;
+
+    if value < 0.0 {
+        return Err(RuntimeError::new(format!(
+            "`sqrt` of a negative number: {}",
+            arguments[0]
+        )));
+    }
+
+    Ok(Value::Float(value.sqrt()))
+}
+
+/// `pow(base, exponent)`: `base` raised to `exponent`. An Int raised to a
+/// non-negative Int is an Int, and anything else is a Float.
+fn pow(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match (&arguments[0], &arguments[1]) {
+        (Value::Int(base), Value::Int(exponent)) if *exponent >= 0 => {
+            return int_pow(*base, exponent.unsigned_abs(), evaluator.overflow());
+        }
+        #[cfg(feature = "bigint")]
+        (Value::BigInt(base), Value::Int(exponent)) if *exponent >= 0 => {
+            return u32::try_from(*exponent)
+                .map(|exponent| bigint::pow(base, exponent))
+                .map_err(|_| {
+                    RuntimeError::new(format!("integer overflow: pow({base}, {exponent})"))
+                });
+        }
+        _ => {}
+    }
+
+    let base = expect_number("pow", &arguments[0])?;
+    let exponent = expect_number("pow", &arguments[1])?;
+    let result = base.powf(exponent);
+
+    if result.is_nan() || base == 0.0 && exponent < 0.0 {
+        return Err(RuntimeError::new(format!(
+            "`pow` is undefined for {} and {}",
+            arguments[0], arguments[1]
+        )));
+    }
+
+    Ok(Value::Float(result))
+}
+
+fn int_pow(base: i64, exponent: u64, overflow: Overflow) -> Result<Value, RuntimeError> {
+    let overflowed = || RuntimeError::new(format!("integer overflow: pow({base}, {exponent})"));
+
+    match overflow {
+        Overflow::Error => square_and_multiply(base, exponent, i64::checked_mul)
+            .map(Value::Int)
+            .ok_or_else(overflowed),
+        Overflow::Wrap => {
+            let result = square_and_multiply(base, exponent, |a, b| Some(a.wrapping_mul(b)));
+            Ok(Value::Int(result.expect("wrapping never fails")))
+        }
+        #[cfg(feature = "bigint")]
+        Overflow::Promote => match square_and_multiply(base, exponent, i64::checked_mul) {
+            Some(result) => Ok(Value::Int(result)),
+            None => u32::try_from(exponent)
+                .map(|exponent| bigint::pow(&base.into(), exponent))
+                .map_err(|_| overflowed()),
+        },
+    }
+}
+
+/// `abs(x)`: `x` without its sign.
+fn abs(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::Float(value) => Ok(Value::Float(value.abs())),
+        value if is_integer(value) => {
+            if ordering(value, &Value::Int(0))?.is_lt() {
+                eval_prefix_expression(PrefixOperator::Minus, value.clone(), evaluator.overflow())
+            } else {
+                Ok(value.clone())
+            }
+        }
+        other => Err(expected_number("abs", other)),
+    }
+}
+
+/// `floor(x)` and `ceil(x)`: `x` rounded down or up to an Int.
+fn round(builtin: &str, value: &Value, round: fn(f64) -> f64) -> Result<Value, RuntimeError> {
+    match value {
+        Value::Float(value) => float_to_int(round(*value)).ok_or_else(|| {
+            RuntimeError::new(format!("`{builtin}` cannot convert {value} to an Int"))
+        }),
+        value if is_integer(value) => Ok(value.clone()),
+        other => Err(expected_number(builtin, other)),
+    }
+}
+
+/// `min(...)` and `max(...)`: the first of the arguments, or of the
+/// elements of a single array argument, that no other comes before or
+/// after in `wanted` order.
+fn extreme(builtin: &str, arguments: Vec<Value>, wanted: Ordering) -> Result<Value, RuntimeError> {
+    let values = match arguments.as_slice() {
+        [Value::Array(elements)] => elements.borrow().clone(),
+        _ => arguments,
+    };
+
+    let mut values = values.into_iter();
+    let mut best = values
+        .next()
+        .ok_or_else(|| RuntimeError::new(format!("`{builtin}` of an empty array")))?;
+
+    for value in values {
+        if ordering(&value, &best)? == wanted {
+            best = value;
+        }
+    }
+
+    Ok(best)
+}
+
+fn is_integer(value: &Value) -> bool {
+    match value {
+        Value::Int(_) => true,
+        #[cfg(feature = "bigint")]
+        Value::BigInt(_) => true,
+        _ => false,
+    }
+}
+
+fn expect_number(builtin: &str, value: &Value) -> Result<f64, RuntimeError> {
+    match value {
+        Value::Int(value) => Ok(*value as f64),
+        #[cfg(feature = "bigint")]
+        Value::BigInt(value) => Ok(bigint::to_float(value)),
+        Value::Float(value) => Ok(*value),
+        other => Err(expected_number(builtin, other)),
+    }
+}
+
+fn expected_number(builtin: &str, value: &Value) -> RuntimeError {
+    RuntimeError::new(format!(
+        "`{builtin}` expects an Int or Float, got {}",
+        value.type_name()
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::builtins::test::eval_with_setting;
+    use crate::evaluator::{Evaluator, Overflow, RuntimeError};
+    use crate::value::Value;
+
+    fn eval(input: &str) -> Result<Value, RuntimeError> {
+        eval_with_setting(input, Evaluator::set_overflow, Overflow::Error)
+    }
+
+    #[test]
+    fn test_math_builtins() {
+        let tests = vec![
+            ("pi", Value::Float(std::f64::consts::PI)),
+            ("sqrt(16)", Value::Float(4.0)),
+            ("sqrt(2.25)", Value::Float(1.5)),
+            ("sqrt(0)", Value::Float(0.0)),
+            ("pow(2, 10)", Value::Int(1024)),
+            ("pow(-3, 3)", Value::Int(-27)),
+            ("pow(7, 0)", Value::Int(1)),
+            ("pow(2, -1)", Value::Float(0.5)),
+            ("pow(4, 0.5)", Value::Float(2.0)),
+            ("pow(1.5, 2)", Value::Float(2.25)),
+            ("pow(2, 62)", Value::Int(1 << 62)),
+            ("abs(-5)", Value::Int(5)),
+            ("abs(5)", Value::Int(5)),
+            ("abs(-2.5)", Value::Float(2.5)),
+            ("floor(2.7)", Value::Int(2)),
+            ("floor(-2.5)", Value::Int(-3)),
+            ("floor(4)", Value::Int(4)),
+            ("ceil(2.1)", Value::Int(3)),
+            ("ceil(-2.5)", Value::Int(-2)),
+            ("min(3, 1, 2)", Value::Int(1)),
+            ("max(3, 1, 2)", Value::Int(3)),
+            ("min([4, 2.5, 3])", Value::Float(2.5)),
+            ("max([4, 2.5, 3])", Value::Int(4)),
+            (r#"max("pear", "apple")"#, Value::Str("pear".to_string())),
+            ("min(7)", Value::Int(7)),
+            // Ties keep the first.
+            ("min(1, 1.0)", Value::Int(1)),
+            ("math.sqrt(4)", Value::Float(2.0)),
+            ("math.pow(2, 10) + math.abs(-1)", Value::Int(1025)),
+            ("math.max([math.floor(2.5), math.ceil(2.5)])", Value::Int(3)),
+            ("math.pi == pi", Value::Bool(true)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Ok(expected), eval(input), "input={input}");
+        }
+
+        assert_eq!(
+            Ok(Value::Int(0)),
+            eval_with_setting("pow(2, 64)", Evaluator::set_overflow, Overflow::Wrap),
+            "wrapping"
+        );
+
+        let errors = vec![
+            ("sqrt(-1)", "`sqrt` of a negative number: -1"),
+            (r#"sqrt("4")"#, "`sqrt` expects an Int or Float, got Str"),
+            (
+                "pow(-8, 1.0 / 3.0)",
+                "`pow` is undefined for -8 and 0.3333333333333333",
+            ),
+            ("pow(0, -1)", "`pow` is undefined for 0 and -1"),
+            ("math.pow(0.0, -0.5)", "`pow` is undefined for 0.0 and -0.5"),
+            ("pow(2, 63)", "integer overflow: pow(2, 63)"),
+            (
+                "abs(-9223372036854775807 - 1)",
+                "integer overflow: -(-9223372036854775808)",
+            ),
+            ("floor(1.0 / 0.0)", "`floor` cannot convert inf to an Int"),
+            ("ceil(null)", "`ceil` expects an Int or Float, got Null"),
+            ("min([])", "`min` of an empty array"),
+            (r#"max(1, "a")"#, "type mismatch: Str < Int"),
+        ];
+
+        for (input, expected) in errors {
+            assert_eq!(expected, eval(input).unwrap_err().message, "input={input}");
+        }
+    }
+
+    #[cfg(feature = "bigint")]
+    #[test]
+    fn test_math_builtins_promote() {
+        let power =
+            eval_with_setting("pow(2, 100)", Evaluator::set_overflow, Overflow::Promote).unwrap();
+        assert_eq!("1267650600228229401496703205376", power.to_string());
+
+        let power = eval_with_setting(
+            "pow(pow(2, 100), 2)",
+            Evaluator::set_overflow,
+            Overflow::Promote,
+        )
+        .unwrap();
+        assert_eq!(
+            "1606938044258990275541962092341162602522202993782792835301376",
+            power.to_string()
+        );
+
+        let negated = eval_with_setting(
+            "abs(-pow(2, 100))",
+            Evaluator::set_overflow,
+            Overflow::Promote,
+        )
+        .unwrap();
+        assert_eq!("1267650600228229401496703205376", negated.to_string());
+    }
+}
@@ -0,0 +1,233 @@
+//! The `exec` builtin, which runs another program. It fails while running
+//! programs is turned off with [`Evaluator::set_allow_exec`], since
+//! another program can do anything the interpreter's user can.
+
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use indexmap::IndexMap;
+
+use super::{expect_array, Arity, Builtins};
+use crate::evaluator::{Evaluator, RuntimeError};
+use crate::value::{HashKey, Value};
+
+/// How often a program given a timeout is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+pub(super) fn register(builtins: &mut Builtins) {
+    builtins.register("exec", Arity::Between(2, 3), exec);
+}
+
+/// `exec(command, args)` or `exec(command, args, timeout)`: runs `command`
+/// with the Str arguments `args`, found on the `PATH` if it has no
+/// slashes, and waits for it to finish. Its input is empty, and the result
+/// is a hash of its exit `status`, `null` if a signal ended it, and what it
+/// wrote to `stdout` and `stderr`. With a `timeout` in seconds, a program
+/// still running after that long is killed and `exec` fails.
+fn exec(evaluator: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    if !evaluator.allow_exec() {
+        return Err(RuntimeError::new(
+            "`exec` is unavailable: running programs is disabled",
+        ));
+    }
+
+    let Value::Str(program) = &arguments[0] else {
+        return Err(RuntimeError::new(format!(
+            "`exec` expects a Str command, got {}",
+            arguments[0].type_name()
+        )));
+    };
+    let args = expect_array("exec", &arguments[1])?
+        .borrow()
+        .iter()
+        .map(|argument| match argument {
+            Value::Str(argument) => Ok(argument.clone()),
+            other => Err(RuntimeError::new(format!(
+                "`exec` expects Str arguments, got {}",
+                other.type_name()
+            ))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let timeout = arguments.get(2).map(expect_timeout).transpose()?;
+
+    let mut child = Command::new(program)
+        .args(&args)
+        .envs(evaluator.env_vars())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| RuntimeError::new(format!("could not run {program}: {error}")))?;
+
+    // Both pipes are drained as the program runs, so it can't block on
+    // one filling up while it's waited for.
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let status = match timeout {
+        Some(timeout) => wait_for(&mut child, timeout)
+            .map_err(|error| RuntimeError::new(format!("could not run {program}: {error}")))?
+            .ok_or_else(|| {
+                RuntimeError::new(format!(
+                    "`exec` timed out after {}s running {program}",
+                    timeout.as_secs_f64()
+                ))
+            })?,
+        None => child
+            .wait()
+            .map_err(|error| RuntimeError::new(format!("could not run {program}: {error}")))?,
+    };
+
+    let output = |reader: thread::JoinHandle<Vec<u8>>| {
+        let bytes = reader.join().unwrap_or_default();
+        Value::Str(String::from_utf8_lossy(&bytes).into_owned())
+    };
+    let mut result = IndexMap::new();
+    result.insert(
+        HashKey::Str("status".to_string()),
+        status
+            .code()
+            .map_or(Value::Null, |code| Value::Int(code.into())),
+    );
+    result.insert(HashKey::Str("stdout".to_string()), output(stdout));
+    result.insert(HashKey::Str("stderr".to_string()), output(stderr));
+    Ok(Value::hash(result))
+}
+
+/// Reads everything from `pipe` on a thread of its own.
+fn drain(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+/// Waits up to `timeout` for `child` to finish, killing it and giving
+/// `None` if it doesn't.
+fn wait_for(
+    child: &mut Child,
+    timeout: Duration,
+) -> std::io::Result<Option<std::process::ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            // It may have finished in the meantime, which is as good.
+            let _ = child.kill();
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL.min(deadline - Instant::now()));
+    }
+}
+
+fn expect_timeout(value: &Value) -> Result<Duration, RuntimeError> {
+    let seconds = match value {
+        Value::Int(seconds) => *seconds as f64,
+        Value::Float(seconds) => *seconds,
+        other => {
+            return Err(RuntimeError::new(format!(
+                "`exec` expects a timeout in seconds, got {}",
+                other.type_name()
+            )))
+        }
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| {
+        RuntimeError::new(format!(
+            "`exec` expects a timeout of zero seconds or more, got {value}"
+        ))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::builtins::test::eval_with_setting;
+    use crate::evaluator::Evaluator;
+    use crate::value::Value;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exec() {
+        let input = r#"exec("sh", ["-c", "echo out; echo err >&2; exit 3"])"#;
+        assert_eq!(
+            r#"{"status": 3, "stdout": "out\n", "stderr": "err\n"}"#,
+            eval_with_setting(input, Evaluator::set_allow_exec, true)
+                .unwrap()
+                .to_string()
+        );
+
+        let input = r#"exec("echo", ["a b", "c"], 5)["stdout"]"#;
+        assert_eq!(
+            Ok(Value::Str("a b c\n".to_string())),
+            eval_with_setting(input, Evaluator::set_allow_exec, true)
+        );
+
+        // Programs see the variables `env_set` set, though the process
+        // doesn't.
+        let input = r#"env_set("TOFU_TEST_EXEC", "set");
+            exec("sh", ["-c", "echo $TOFU_TEST_EXEC"])["stdout"]"#;
+        assert_eq!(
+            Ok(Value::Str("set\n".to_string())),
+            eval_with_setting(input, Evaluator::set_allow_exec, true)
+        );
+        assert!(std::env::var_os("TOFU_TEST_EXEC").is_none());
+
+        let errors = vec![
+            (
+                r#"exec("sleep", ["5"], 0.05)"#,
+                "`exec` timed out after 0.05s running sleep",
+            ),
+            ("exec(1, [])", "`exec` expects a Str command, got Int"),
+            (r#"exec("echo", "a")"#, "`exec` expects an Array, got Str"),
+            (
+                r#"exec("echo", [1])"#,
+                "`exec` expects Str arguments, got Int",
+            ),
+            (
+                r#"exec("echo", [], "1")"#,
+                "`exec` expects a timeout in seconds, got Str",
+            ),
+            (
+                r#"exec("echo", [], -1)"#,
+                "`exec` expects a timeout of zero seconds or more, got -1",
+            ),
+        ];
+
+        for (input, expected) in errors {
+            assert_eq!(
+                expected,
+                eval_with_setting(input, Evaluator::set_allow_exec, true)
+                    .unwrap_err()
+                    .message,
+                "input={input}"
+            );
+        }
+
+        let missing = eval_with_setting(
+            r#"exec("tofu-no-such-program", [])"#,
+            Evaluator::set_allow_exec,
+            true,
+        );
+        assert!(missing
+            .unwrap_err()
+            .message
+            .starts_with("could not run tofu-no-such-program: "));
+    }
+
+    #[test]
+    fn test_exec_disabled() {
+        assert_eq!(
+            "`exec` is unavailable: running programs is disabled",
+            eval_with_setting(r#"exec("echo", [])"#, Evaluator::set_allow_exec, false)
+                .unwrap_err()
+                .message
+        );
+    }
+}
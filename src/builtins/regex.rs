@@ -0,0 +1,137 @@
+//! Regex builtins, with the `regex` feature. Patterns use the syntax of the
+//! [`regex`](https://docs.rs/regex) crate and are compiled on every call; a
+//! pattern that doesn't compile is a runtime error carrying the crate's
+//! explanation.
+
+use ::regex::Regex;
+
+use super::{Arity, Builtins};
+use crate::evaluator::{Evaluator, RuntimeError};
+use crate::value::Value;
+
+pub(super) fn register(builtins: &mut Builtins) {
+    builtins.register("regex_match", Arity::Exact(2), regex_match);
+    builtins.register("regex_find_all", Arity::Exact(2), regex_find_all);
+    builtins.register("regex_replace", Arity::Exact(3), regex_replace);
+}
+
+/// `regex_match(s, pattern)`: the first match of `pattern` in `s` as an
+/// array of the whole match followed by its capture groups, with `null`
+/// for groups that didn't take part, or `null` if nothing matches.
+fn regex_match(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let value = expect_str("regex_match", &arguments[0])?;
+    let regex = compile("regex_match", &arguments[1])?;
+
+    Ok(match regex.captures(value) {
+        Some(captures) => Value::array(
+            captures
+                .iter()
+                .map(|group| {
+                    group.map_or(Value::Null, |group| Value::Str(group.as_str().to_string()))
+                })
+                .collect(),
+        ),
+        None => Value::Null,
+    })
+}
+
+/// `regex_find_all(s, pattern)`: every non-overlapping match of `pattern`
+/// in `s`, from left to right.
+fn regex_find_all(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let value = expect_str("regex_find_all", &arguments[0])?;
+    let regex = compile("regex_find_all", &arguments[1])?;
+
+    Ok(Value::array(
+        regex
+            .find_iter(value)
+            .map(|found| Value::Str(found.as_str().to_string()))
+            .collect(),
+    ))
+}
+
+/// `regex_replace(s, pattern, replacement)`: `s` with every match of
+/// `pattern` replaced. `$1` or `${name}` in `replacement` stands for what a
+/// capture group matched, and `$$` for a literal `$`.
+fn regex_replace(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let value = expect_str("regex_replace", &arguments[0])?;
+    let regex = compile("regex_replace", &arguments[1])?;
+    let replacement = expect_str("regex_replace", &arguments[2])?;
+
+    Ok(Value::Str(
+        regex.replace_all(value, replacement).into_owned(),
+    ))
+}
+
+fn compile(builtin: &str, pattern: &Value) -> Result<Regex, RuntimeError> {
+    let pattern = expect_str(builtin, pattern)?;
+    Regex::new(pattern).map_err(|error| RuntimeError::new(format!("invalid regex: {error}")))
+}
+
+fn expect_str<'a>(builtin: &str, value: &'a Value) -> Result<&'a str, RuntimeError> {
+    match value {
+        Value::Str(value) => Ok(value),
+        other => Err(RuntimeError::new(format!(
+            "`{builtin}` expects a Str, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::builtins::test::eval;
+
+    #[test]
+    fn test_regex_builtins() {
+        let tests = vec![
+            (
+                r#"regex_match("tofu 2024-05-01", "(\\d+)-(\\d+)-(\\d+)")"#,
+                r#"["2024-05-01", "2024", "05", "01"]"#,
+            ),
+            (r#"regex_match("tofu", "(x)?t")"#, r#"["t", null]"#),
+            (r#"regex_match("tofu", "\\d")"#, "null"),
+            (
+                r#"regex_find_all("a1 b22 c333", "[0-9]+")"#,
+                r#"["1", "22", "333"]"#,
+            ),
+            (r#"regex_find_all("tofu", "x")"#, "[]"),
+            (r#"regex_find_all("日本語", ".")"#, r#"["日", "本", "語"]"#),
+            (
+                r#"regex_replace("2024-05-01", "(\\d+)-(\\d+)-(\\d+)", "$3/$2/$1")"#,
+                "01/05/2024",
+            ),
+            (r#"regex_replace("a  b   c", "\\s+", " ")"#, "a b c"),
+            (
+                r#"regex_replace("x=1", "(?P<key>\\w)=", "${key}: $$")"#,
+                "x: $1",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(expected, eval(input).unwrap().to_string(), "input={input}");
+        }
+
+        let errors = vec![
+            (
+                r#"regex_match(1, "a")"#,
+                "`regex_match` expects a Str, got Int",
+            ),
+            (
+                r#"regex_replace("a", "a", null)"#,
+                "`regex_replace` expects a Str, got Null",
+            ),
+        ];
+
+        for (input, expected) in errors {
+            assert_eq!(expected, eval(input).unwrap_err().message, "input={input}");
+        }
+
+        let error = eval(r#"regex_find_all("tofu", "(unclosed")"#).unwrap_err();
+        assert!(
+            error.message.starts_with("invalid regex: ")
+                && error.message.contains("unclosed group"),
+            "message={}",
+            error.message
+        );
+    }
+}
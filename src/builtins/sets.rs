@@ -0,0 +1,168 @@
+//! Set builtins. A set holds the values that can be hash keys, each at most
+//! once, in the order they were first added. Those that make a new set
+//! keep the order of the sets they came from.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use indexmap::IndexSet;
+
+use super::{expect_iterable, Arity, Builtins};
+use crate::evaluator::{Evaluator, RuntimeError};
+use crate::value::{HashKey, Value};
+
+pub(super) fn register(builtins: &mut Builtins) {
+    builtins.register("set", Arity::Between(0, 1), set);
+    builtins.register("add", Arity::Exact(2), add);
+    builtins.register("union", Arity::Exact(2), union);
+    builtins.register("intersect", Arity::Exact(2), intersect);
+}
+
+/// `set()` or `set(xs)`: a new set, empty or with the values `xs` iterates
+/// over.
+fn set(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let mut elements = IndexSet::new();
+    if let Some(values) = arguments.first() {
+        for value in expect_iterable("set", values)? {
+            elements.insert(expect_element("set", &value)?);
+        }
+    }
+    Ok(Value::set(elements))
+}
+
+/// `add(set, x)`: adds `x` to `set`, if it isn't there already, and
+/// returns the set.
+fn add(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let [set, value] = <[Value; 2]>::try_from(arguments).expect("arity is checked");
+    let element = expect_element("add", &value)?;
+    expect_set("add", &set)?.borrow_mut().insert(element);
+    Ok(set)
+}
+
+/// `remove(set, x)`, which the `remove` builtin hands sets to: takes `x`
+/// out of `set` and returns whether it was there.
+pub(super) fn remove(
+    elements: &RefCell<IndexSet<HashKey>>,
+    value: &Value,
+) -> Result<Value, RuntimeError> {
+    let element = expect_element("remove", value)?;
+    Ok(Value::Bool(elements.borrow_mut().shift_remove(&element)))
+}
+
+/// `contains(set, x)`, which the `contains` builtin hands sets to: whether
+/// `x` is in `set`.
+pub(super) fn contains(
+    elements: &RefCell<IndexSet<HashKey>>,
+    value: &Value,
+) -> Result<Value, RuntimeError> {
+    let element = expect_element("contains", value)?;
+    Ok(Value::Bool(elements.borrow().contains(&element)))
+}
+
+/// `union(a, b)`: a new set of what's in either `a` or `b`.
+fn union(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let left = expect_set("union", &arguments[0])?.borrow();
+    let right = expect_set("union", &arguments[1])?.borrow();
+    Ok(Value::set(left.union(&right).cloned().collect()))
+}
+
+/// `intersect(a, b)`: a new set of what's in both `a` and `b`.
+fn intersect(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let left = expect_set("intersect", &arguments[0])?.borrow();
+    let right = expect_set("intersect", &arguments[1])?.borrow();
+    Ok(Value::set(left.intersection(&right).cloned().collect()))
+}
+
+fn expect_set<'a>(
+    builtin: &str,
+    value: &'a Value,
+) -> Result<&'a Rc<RefCell<IndexSet<HashKey>>>, RuntimeError> {
+    match value {
+        Value::Set(elements) => Ok(elements),
+        other => Err(RuntimeError::new(format!(
+            "`{builtin}` expects a Set, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+/// `value` as an element of a set, which only values that can be hash keys
+/// can be.
+fn expect_element(builtin: &str, value: &Value) -> Result<HashKey, RuntimeError> {
+    value.hash_key().ok_or_else(|| {
+        RuntimeError::new(format!(
+            "`{builtin}` expects an element that can be a hash key, got {}",
+            value.type_name()
+        ))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::builtins::test::eval_with;
+    use crate::evaluator::{Evaluator, RuntimeError};
+    use crate::value::Value;
+
+    fn eval(input: &str) -> Result<Value, RuntimeError> {
+        let mut evaluator = Evaluator::new();
+        evaluator.load_prelude();
+        eval_with(&mut evaluator, input)
+    }
+
+    #[test]
+    fn test_set_builtins() {
+        let tests = [
+            ("set()", "set([])"),
+            (
+                r#"set([3, 1, 3, "a", true, 1])"#,
+                r#"set([3, 1, "a", true])"#,
+            ),
+            ("set(1..4)", "set([1, 2, 3])"),
+            (r#"set({"a": 1, "b": 2}.keys())"#, r#"set(["a", "b"])"#),
+            ("let s = set(); add(s, 2); s.add(1).add(2)", "set([2, 1])"),
+            (
+                "let s = set([1, 2, 3]); [remove(s, 2), s.remove(5), s]",
+                "[true, false, set([1, 3])]",
+            ),
+            (
+                "let s = set([1]); [contains(s, 1), s.contains(2)]",
+                "[true, false]",
+            ),
+            // The prelude's `contains` leaves sets to the builtin.
+            (r#"contains(set(["x"]), "x")"#, "true"),
+            ("union(set([1, 2]), set([3, 2]))", "set([1, 2, 3])"),
+            ("set([3, 2, 1]).intersect(set([1, 3, 5]))", "set([3, 1])"),
+            ("len(set([1, 1, 2]))", "2"),
+            ("let n = 0; for (x in set([1, 2, 2])) { n = n + x } n", "3"),
+            ("map(set([1, 2]), fn(x) { x * 10 })", "[10, 20]"),
+            ("set([1, 2]) == set([2, 1])", "true"),
+            ("set([1, 2]) == set([1])", "false"),
+            ("type(set())", "Set"),
+        ];
+
+        for (input, expected) in tests {
+            let value = eval(input).unwrap_or_else(|error| panic!("{input}: {error}"));
+            assert_eq!(expected, value.to_string(), "{input}");
+        }
+
+        let errors = [
+            ("set(1)", "`set` expects something iterable, got Int"),
+            (
+                "set([[1]])",
+                "`set` expects an element that can be a hash key, got Array",
+            ),
+            ("add([], 1)", "`add` expects a Set, got Array"),
+            (
+                "add(set(), null)",
+                "`add` expects an element that can be a hash key, got Null",
+            ),
+            ("union(set(), [])", "`union` expects a Set, got Array"),
+            ("intersect(1, set())", "`intersect` expects a Set, got Int"),
+        ];
+
+        for (input, expected) in errors {
+            let error = eval(input).unwrap_err();
+            assert_eq!(expected, error.message, "{input}");
+        }
+    }
+}
@@ -0,0 +1,540 @@
+//! String builtins. Strings are sequences of Unicode characters, so these
+//! never split one in half, and case changes follow Unicode's rules, where
+//! one character can become several.
+//!
+//! A character is a Unicode scalar value, what Rust's `char` holds, which
+//! is what `len` counts, indexes and slices count in, and `for` loops step
+//! through. So `len("héllo👋")` is 6, whatever the bytes in UTF-8 are, but
+//! what's seen as one letter can still be several characters, like an `e`
+//! followed by a combining accent, or a flag, which is a pair of regional
+//! indicators. [`chars`] and [`bytes`] take a string apart either way.
+
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use super::{Arity, Builtins};
+use crate::evaluator::{Evaluator, RuntimeError};
+use crate::value::Value;
+
+pub(super) fn register(builtins: &mut Builtins) {
+    builtins.register("split", Arity::Between(1, 2), split);
+    builtins.register("join", Arity::Between(1, 2), join);
+    builtins.register("trim", Arity::Exact(1), trim);
+    builtins.register("replace", Arity::Exact(3), replace);
+    builtins.register("contains", Arity::Exact(2), contains);
+    builtins.register("chars", Arity::Exact(1), chars);
+    builtins.register("bytes", Arity::Exact(1), bytes);
+    builtins.register("buf_new", Arity::Between(0, 1), buf_new);
+    builtins.register("buf_push", Arity::Exact(2), buf_push);
+    builtins.register("buf_str", Arity::Exact(1), |_, arguments| {
+        let text = expect_buffer("buf_str", &arguments[0])?;
+        Ok(Value::Str(text.borrow().clone()))
+    });
+    builtins.register("format", Arity::AtLeast(1), |_, arguments| {
+        let template = expect_str("format", &arguments[0])?;
+        Ok(Value::Str(format("format", template, &arguments[1..])?))
+    });
+    builtins.register("upper", Arity::Exact(1), |_, arguments| {
+        Ok(Value::Str(
+            expect_str("upper", &arguments[0])?.to_uppercase(),
+        ))
+    });
+    builtins.register("lower", Arity::Exact(1), |_, arguments| {
+        Ok(Value::Str(
+            expect_str("lower", &arguments[0])?.to_lowercase(),
+        ))
+    });
+}
+
+/// `split(s)` or `split(s, separator)`: the parts of `s` between
+/// occurrences of `separator`, or between runs of whitespace without one.
+/// An empty separator splits `s` into its characters.
+fn split(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let value = expect_str("split", &arguments[0])?;
+
+    let parts: Vec<&str> = match arguments.get(1) {
+        None => value.split_whitespace().collect(),
+        Some(separator) => match expect_str("split", separator)? {
+            "" => value
+                .char_indices()
+                .map(|(index, char)| &value[index..index + char.len_utf8()])
+                .collect(),
+            separator => value.split(separator).collect(),
+        },
+    };
+
+    Ok(Value::array(
+        parts
+            .into_iter()
+            .map(|part| Value::Str(part.to_string()))
+            .collect(),
+    ))
+}
+
+/// `chars(s)`: the characters of `s`, in order.
+fn chars(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let value = expect_str("chars", &arguments[0])?;
+    Ok(Value::array(value.chars().map(Value::Char).collect()))
+}
+
+/// `bytes(s)`: the bytes of `s` encoded as UTF-8, as Ints from 0 to 255.
+fn bytes(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let value = expect_str("bytes", &arguments[0])?;
+    Ok(Value::array(
+        value.bytes().map(|byte| Value::Int(byte.into())).collect(),
+    ))
+}
+
+/// `join(xs)` or `join(xs, separator)`: the elements of `xs` as they'd be
+/// printed, with `separator` between them.
+fn join(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let elements = super::expect_array("join", &arguments[0])?.borrow();
+    let separator = match arguments.get(1) {
+        Some(separator) => expect_str("join", separator)?,
+        None => "",
+    };
+
+    let parts: Vec<String> = elements.iter().map(Value::to_string).collect();
+    Ok(Value::Str(parts.join(separator)))
+}
+
+/// `trim(s)`: `s` without leading and trailing whitespace.
+fn trim(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    Ok(Value::Str(
+        expect_str("trim", &arguments[0])?.trim().to_string(),
+    ))
+}
+
+/// `replace(s, from, to)`: `s` with every occurrence of `from` replaced by
+/// `to`.
+fn replace(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let value = expect_str("replace", &arguments[0])?;
+    let from = expect_str("replace", &arguments[1])?;
+    let to = expect_str("replace", &arguments[2])?;
+
+    if from.is_empty() {
+        return Err(RuntimeError::new(
+            "`replace` cannot replace an empty string",
+        ));
+    }
+
+    Ok(Value::Str(value.replace(from, to)))
+}
+
+/// `contains(s, part)`: whether `part`, a string or a char, appears in `s`.
+/// Sets are handed to [`sets::contains`](super::sets::contains).
+fn contains(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    if let Value::Set(elements) = &arguments[0] {
+        return super::sets::contains(elements, &arguments[1]);
+    }
+    let value = expect_str("contains", &arguments[0])?;
+
+    let found = match &arguments[1] {
+        Value::Str(part) => value.contains(part.as_str()),
+        Value::Char(part) => value.contains(*part),
+        other => {
+            return Err(RuntimeError::new(format!(
+                "`contains` expects a Str or Char to look for, got {}",
+                other.type_name()
+            )))
+        }
+    };
+
+    Ok(Value::Bool(found))
+}
+
+/// `buf_new()` or `buf_new(s)`: a new buffer, empty or starting with the
+/// string `s`. Building a string with `buf_push` and taking it out with
+/// `buf_str` copies each piece once, where `s = s + piece` copies all of
+/// `s` again every time.
+fn buf_new(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let text = match arguments.first() {
+        Some(text) => expect_str("buf_new", text)?.to_string(),
+        None => String::new(),
+    };
+    Ok(Value::buffer(text))
+}
+
+/// `buf_push(buffer, x)`: appends `x` to `buffer`, as `join` would write
+/// it, and returns the buffer.
+fn buf_push(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let [buffer, value] = <[Value; 2]>::try_from(arguments).expect("arity is checked");
+    let text = expect_buffer("buf_push", &buffer)?;
+    match &value {
+        Value::Str(piece) => text.borrow_mut().push_str(piece),
+        // Copied out first, since it may be this very buffer.
+        Value::Buffer(other) => {
+            let piece = other.borrow().clone();
+            text.borrow_mut().push_str(&piece);
+        }
+        value => write!(text.borrow_mut(), "{value}").expect("writing to a String can't fail"),
+    }
+    Ok(buffer)
+}
+
+/// `format(template, ...)`: `template` with each `{}` in it replaced by
+/// the next argument, as it would be printed. A placeholder can give a
+/// minimum width, `{:5}`, padded with zeros for numbers as in `{:05}`, and
+/// a number of decimal places for numbers, `{:.2}`, or both, `{:08.3}`.
+/// Numbers are right-aligned in their width and everything else is
+/// left-aligned. `{{` and `}}` stand for literal braces.
+pub(super) fn format(
+    builtin: &str,
+    template: &str,
+    arguments: &[Value],
+) -> Result<String, RuntimeError> {
+    let mut out = String::with_capacity(template.len());
+    let mut arguments_used = 0;
+    let mut chars = template.chars();
+
+    while let Some(char) = chars.next() {
+        match char {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                out.push('{');
+            }
+            '{' => {
+                let rest = chars.as_str();
+                let end = rest.find('}').ok_or_else(|| {
+                    RuntimeError::new(format!(
+                        "`{builtin}` found an unclosed `{{`; write `{{{{` for a literal one"
+                    ))
+                })?;
+                let spec = parse_spec(builtin, &rest[..end])?;
+                chars = rest[end + 1..].chars();
+
+                if let Some(argument) = arguments.get(arguments_used) {
+                    out.push_str(&spec.apply(builtin, argument)?);
+                }
+                arguments_used += 1;
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                out.push('}');
+            }
+            '}' => {
+                return Err(RuntimeError::new(format!(
+                    "`{builtin}` found an unmatched `}}`; write `}}}}` for a literal one"
+                )))
+            }
+            char => out.push(char),
+        }
+    }
+
+    if arguments_used != arguments.len() {
+        return Err(RuntimeError::new(format!(
+            "`{builtin}` has {} but got {}",
+            plural(arguments_used, "placeholder"),
+            plural(arguments.len(), "argument")
+        )));
+    }
+
+    Ok(out)
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("1 {noun}")
+    } else {
+        format!("{count} {noun}s")
+    }
+}
+
+/// What goes between the colon and the closing brace of a placeholder like
+/// `{:08.3}`.
+#[derive(Default)]
+struct Spec {
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+/// Parses what's between a placeholder's braces, which is either nothing
+/// or `:` followed by a spec.
+fn parse_spec(builtin: &str, placeholder: &str) -> Result<Spec, RuntimeError> {
+    let invalid = || {
+        RuntimeError::new(format!(
+            "`{builtin}` found an invalid placeholder `{{{placeholder}}}`"
+        ))
+    };
+
+    let Some(spec) = placeholder.strip_prefix(':') else {
+        return if placeholder.is_empty() {
+            Ok(Spec::default())
+        } else {
+            Err(invalid())
+        };
+    };
+
+    let (zero, spec) = match spec.strip_prefix('0') {
+        Some(spec) => (true, spec),
+        None => (false, spec),
+    };
+    let (width, precision) = match spec.split_once('.') {
+        Some((width, precision)) => (width, Some(precision)),
+        None => (spec, None),
+    };
+    let number = |digits: &str| match digits {
+        "" => Ok(None),
+        digits if digits.bytes().all(|byte| byte.is_ascii_digit()) => {
+            digits.parse().map(Some).map_err(|_| invalid())
+        }
+        _ => Err(invalid()),
+    };
+
+    Ok(Spec {
+        zero,
+        width: number(width)?.unwrap_or(0),
+        precision: match precision {
+            Some(precision) => Some(number(precision)?.ok_or_else(invalid)?),
+            None => None,
+        },
+    })
+}
+
+impl Spec {
+    fn apply(&self, builtin: &str, value: &Value) -> Result<String, RuntimeError> {
+        let is_number = match value {
+            Value::Int(_) | Value::Float(_) => true,
+            #[cfg(feature = "bigint")]
+            Value::BigInt(_) => true,
+            _ => false,
+        };
+
+        let text = match (self.precision, value) {
+            (None, value) => value.to_string(),
+            (Some(precision), Value::Float(value)) => format!("{value:.precision$}"),
+            (Some(precision), Value::Int(value)) => format!("{:.precision$}", *value as f64),
+            (Some(_), other) => {
+                return Err(RuntimeError::new(format!(
+                    "`{builtin}` can only give decimal places to an Int or Float, got {}",
+                    other.type_name()
+                )))
+            }
+        };
+
+        let padding = self.width.saturating_sub(text.chars().count());
+        Ok(match (self.zero, is_number) {
+            (true, false) => {
+                return Err(RuntimeError::new(format!(
+                    "`{builtin}` can only pad numbers with zeros, got {}",
+                    value.type_name()
+                )))
+            }
+            _ if padding == 0 => text,
+            (true, true) => {
+                let (sign, digits) = match text.strip_prefix('-') {
+                    Some(digits) => ("-", digits),
+                    None => ("", text.as_str()),
+                };
+                format!("{sign}{}{digits}", "0".repeat(padding))
+            }
+            (false, true) => format!("{}{text}", " ".repeat(padding)),
+            (false, false) => format!("{text}{}", " ".repeat(padding)),
+        })
+    }
+}
+
+fn expect_buffer<'a>(
+    builtin: &str,
+    value: &'a Value,
+) -> Result<&'a Rc<RefCell<String>>, RuntimeError> {
+    match value {
+        Value::Buffer(text) => Ok(text),
+        other => Err(RuntimeError::new(format!(
+            "`{builtin}` expects a Buffer, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+fn expect_str<'a>(builtin: &str, value: &'a Value) -> Result<&'a str, RuntimeError> {
+    match value {
+        Value::Str(value) => Ok(value),
+        other => Err(RuntimeError::new(format!(
+            "`{builtin}` expects a Str, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::builtins::test::eval;
+    use crate::value::Value;
+
+    fn str(value: &str) -> Value {
+        Value::Str(value.to_string())
+    }
+
+    fn strs(values: &[&str]) -> Value {
+        Value::array(values.iter().copied().map(str).collect())
+    }
+
+    #[test]
+    fn test_string_builtins() {
+        let tests = vec![
+            (r#"split("a,b,,c", ",")"#, strs(&["a", "b", "", "c"])),
+            (
+                r#"split("  tofu \t is\ngood ")"#,
+                strs(&["tofu", "is", "good"]),
+            ),
+            (r#"split("", ",")"#, strs(&[""])),
+            (r#"split("héllo", "")"#, strs(&["h", "é", "l", "l", "o"])),
+            (r#"split("日本語の本", "の")"#, strs(&["日本語", "本"])),
+            (r#"join(["a", "b", "c"], ", ")"#, str("a, b, c")),
+            (r#"join([1, 'x', "y", null])"#, str("1xynull")),
+            (r#"join([], "-")"#, str("")),
+            (r#"join(split("a b", " "), "→")"#, str("a→b")),
+            (r#"trim("  tofu\n")"#, str("tofu")),
+            // Unicode whitespace counts too.
+            ("trim(\"\u{3000}tofu\u{a0}\")", str("tofu")),
+            (r#"replace("banana", "an", "AN")"#, str("bANANa")),
+            (r#"replace("café café", "é", "e")"#, str("cafe cafe")),
+            (r#"contains("tofu", "of")"#, Value::Bool(true)),
+            (r#"contains("tofu", "x")"#, Value::Bool(false)),
+            (r#"contains("naïve", 'ï')"#, Value::Bool(true)),
+            (r#"contains("", "")"#, Value::Bool(true)),
+            (r#"upper("tofu")"#, str("TOFU")),
+            (r#"upper("straße")"#, str("STRASSE")),
+            (r#"lower("ÀÉÎ")"#, str("àéî")),
+            (r#"format("x={}, y={}", 1, [2])"#, str("x=1, y=[2]")),
+            (r#"format("{} says {}", "tofu", "hi")"#, str("tofu says hi")),
+            (r#"format("no placeholders")"#, str("no placeholders")),
+            (r#"format("{{}} {{{}}}", 1)"#, str("{} {1}")),
+            (r#"format("[{:02}:{:02}]", 7, 30)"#, str("[07:30]")),
+            (r#"format("[{:05}]", -42)"#, str("[-0042]")),
+            (r#"format("[{:4}|{:4}]", 7, "ab")"#, str("[   7|ab  ]")),
+            (
+                r#"format("{:.2} {:.0} {:.1}", 3.14159, 2.5, 3)"#,
+                str("3.14 2 3.0"),
+            ),
+            (r#"format("[{:08.3}]", -1.5)"#, str("[-001.500]")),
+            (r#"format("[{:1}]", "wide")"#, str("[wide]")),
+            (r#"format("{:3}|", "é")"#, str("é  |")),
+            // Characters are Unicode scalar values, not bytes, and not
+            // everything that's seen as one letter.
+            (r#"len("héllo👋")"#, Value::Int(6)),
+            (r#""héllo👋"[5]"#, Value::Char('👋')),
+            (
+                r#"chars("hé👋")"#,
+                Value::array(vec![Value::Char('h'), Value::Char('é'), Value::Char('👋')]),
+            ),
+            (r#"len(chars("e\u{301}"))"#, Value::Int(2)),
+            (
+                r#"bytes("hé")"#,
+                Value::array([104, 195, 169].map(Value::Int).to_vec()),
+            ),
+            (r#"len(bytes("👋"))"#, Value::Int(4)),
+            (r#"chars("")"#, Value::array(Vec::new())),
+            (
+                r#"let n = 0; for (c in "añ👋") { n = n + 1 } n"#,
+                Value::Int(3),
+            ),
+            (
+                r#"filter("a-b", fn(c) { c != '-' })"#,
+                Value::array(vec![Value::Char('a'), Value::Char('b')]),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Ok(expected), eval(input), "input={input}");
+        }
+
+        let errors = vec![
+            ("split(1)", "`split` expects a Str, got Int"),
+            (r#"join("abc")"#, "`join` expects an Array, got Str"),
+            (r#"join(["a"], 1)"#, "`join` expects a Str, got Int"),
+            (
+                r#"replace("abc", "", "x")"#,
+                "`replace` cannot replace an empty string",
+            ),
+            (
+                r#"contains("abc", 1)"#,
+                "`contains` expects a Str or Char to look for, got Int",
+            ),
+            ("upper('a')", "`upper` expects a Str, got Char"),
+            ("chars(1)", "`chars` expects a Str, got Int"),
+            ("bytes(['a'])", "`bytes` expects a Str, got Array"),
+            ("format(1)", "`format` expects a Str, got Int"),
+            (
+                r#"format("{} {}", 1)"#,
+                "`format` has 2 placeholders but got 1 argument",
+            ),
+            (
+                r#"format("{}", 1, 2)"#,
+                "`format` has 1 placeholder but got 2 arguments",
+            ),
+            (
+                r#"format("{", 1)"#,
+                "`format` found an unclosed `{`; write `{{` for a literal one",
+            ),
+            (
+                r#"format("a } b")"#,
+                "`format` found an unmatched `}`; write `}}` for a literal one",
+            ),
+            (
+                r#"format("{x}", 1)"#,
+                "`format` found an invalid placeholder `{x}`",
+            ),
+            (
+                r#"format("{:2.}", 1)"#,
+                "`format` found an invalid placeholder `{:2.}`",
+            ),
+            (
+                r#"format("{:.2}", "a")"#,
+                "`format` can only give decimal places to an Int or Float, got Str",
+            ),
+            (
+                r#"format("{:03}", "a")"#,
+                "`format` can only pad numbers with zeros, got Str",
+            ),
+        ];
+
+        for (input, expected) in errors {
+            assert_eq!(expected, eval(input).unwrap_err().message, "input={input}");
+        }
+    }
+
+    #[test]
+    fn test_buffer_builtins() {
+        let tests = vec![
+            ("buf_str(buf_new())", str("")),
+            (
+                r#"let b = buf_new("a"); buf_push(b, "bc"); buf_push(b, 1).buf_push('x'); buf_str(b)"#,
+                str("abc1x"),
+            ),
+            (
+                "let b = buf_new(); for (i in 0..3) { buf_push(b, i); } buf_str(b)",
+                str("012"),
+            ),
+            (
+                r#"let b = buf_new("ab"); buf_str(buf_push(b, b))"#,
+                str("abab"),
+            ),
+            (r#"len(buf_new("héllo"))"#, Value::Int(5)),
+            (r#"type(buf_new())"#, str("Buffer")),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Ok(expected), eval(input), "input={input}");
+        }
+
+        let shown = eval(r#"let b = buf_new(); b.buf_push("a\"b"); b"#).unwrap();
+        assert_eq!(r#"buf_new("a\"b")"#, shown.to_string());
+
+        let errors = vec![
+            ("buf_new(1)", "`buf_new` expects a Str, got Int"),
+            (
+                r#"buf_push("a", "b")"#,
+                "`buf_push` expects a Buffer, got Str",
+            ),
+            ("buf_str([])", "`buf_str` expects a Buffer, got Array"),
+        ];
+
+        for (input, expected) in errors {
+            assert_eq!(expected, eval(input).unwrap_err().message, "input={input}");
+        }
+    }
+}
@@ -0,0 +1,663 @@
+//! Date and time builtins. A time is a hash of its UTC calendar fields,
+//! `year`, `month`, `day`, `hour`, `minute` and `second`, with the ISO
+//! `weekday`, 1 for Monday to 7 for Sunday, so its parts are read like any
+//! hash's. Hashes written by hand work too: the time fields default to 0,
+//! and `weekday` is ignored. Times are to the second, in years 0 to 9999
+//! of the proleptic Gregorian calendar.
+//!
+//! Patterns are like `strftime`'s: `%Y` is the four-digit year, `%m`,
+//! `%d`, `%H`, `%M` and `%S` the two-digit month, day, hour, minute and
+//! second, `%b` and `%B` the month's short and full English names, `%a`
+//! and `%A` the weekday's, `%j` the day of the year, `%s` the seconds since
+//! the Unix epoch, and `%%` a `%`. Anything else is written, or must be
+//! found, as it is.
+//!
+//! Each builtin is also in the `time` namespace without its prefix, as in
+//! `time.parse(s)` for `time_parse(s)`.
+
+use std::time::SystemTime;
+
+use indexmap::IndexMap;
+
+use super::{Arity, Builtins};
+use crate::evaluator::{Evaluator, RuntimeError};
+use crate::value::{HashKey, Value};
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// What `time_parse` expects without a pattern: ISO 8601, as in
+/// `2024-03-01T12:30:00`.
+const ISO_8601: &str = "%Y-%m-%dT%H:%M:%S";
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// The units `time_add` takes in a hash, with how many seconds each is.
+/// Months and years are calendar ones, so they're added separately.
+const UNITS: [(&str, i64); 5] = [
+    ("weeks", 7 * SECONDS_PER_DAY),
+    ("days", SECONDS_PER_DAY),
+    ("hours", 3_600),
+    ("minutes", 60),
+    ("seconds", 1),
+];
+
+/// The builtins the `time` namespace holds, by the names it holds them as.
+const NAMES: [(&str, &str); 7] = [
+    ("now", "time_now"),
+    ("from_epoch", "time_from_epoch"),
+    ("epoch", "time_epoch"),
+    ("parse", "time_parse"),
+    ("format", "time_format"),
+    ("add", "time_add"),
+    ("diff", "time_diff"),
+];
+
+const WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+pub(super) fn register(builtins: &mut Builtins) {
+    builtins.register("time_now", Arity::Exact(0), |_, _| {
+        let seconds = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() as i64);
+        Ok(DateTime::from_epoch(seconds).into())
+    });
+    builtins.register("time_from_epoch", Arity::Exact(1), |_, arguments| {
+        let seconds = expect_int("time_from_epoch", &arguments[0])?;
+        Ok(DateTime::checked_from_epoch("time_from_epoch", seconds)?.into())
+    });
+    builtins.register("time_epoch", Arity::Exact(1), |_, arguments| {
+        Ok(Value::Int(
+            expect_time("time_epoch", &arguments[0])?.epoch(),
+        ))
+    });
+    builtins.register("time_parse", Arity::Between(1, 2), time_parse);
+    builtins.register("time_format", Arity::Between(1, 2), time_format);
+    builtins.register("time_add", Arity::Exact(2), time_add);
+    builtins.register("time_diff", Arity::Exact(2), |_, arguments| {
+        let later = expect_time("time_diff", &arguments[0])?;
+        let earlier = expect_time("time_diff", &arguments[1])?;
+        Ok(Value::Int(later.epoch() - earlier.epoch()))
+    });
+
+    let namespace = NAMES
+        .iter()
+        .map(|(name, builtin)| {
+            let builtin = builtins.get(builtin).expect("time builtins are registered");
+            (HashKey::Str(name.to_string()), builtin)
+        })
+        .collect();
+    builtins.define("time", Value::hash(namespace));
+}
+
+/// `time_parse(s)` or `time_parse(s, pattern)`: the time `s` gives,
+/// written as `pattern` says, or in ISO 8601 without one.
+fn time_parse(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let text = expect_str("time_parse", &arguments[0])?;
+    let pattern = match arguments.get(1) {
+        Some(pattern) => expect_str("time_parse", pattern)?,
+        None => ISO_8601,
+    };
+    Ok(parse(text, pattern)?.into())
+}
+
+/// `time_format(t)` or `time_format(t, pattern)`: the time `t` written as
+/// `pattern` says, or in ISO 8601 without one.
+fn time_format(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let time = expect_time("time_format", &arguments[0])?;
+    let pattern = match arguments.get(1) {
+        Some(pattern) => expect_str("time_format", pattern)?,
+        None => ISO_8601,
+    };
+    Ok(Value::Str(format(&time, pattern)?))
+}
+
+/// `time_add(t, seconds)` or `time_add(t, amounts)`: the time `seconds`
+/// after `t`, or before it if `seconds` is negative, or the time the hash
+/// `amounts` says, as in `{"months": 1, "days": -2}`, which can have
+/// `years`, `months`, `weeks`, `days`, `hours`, `minutes` and `seconds`.
+/// Years and months are added first, moving back to the end of the month
+/// when it's shorter, so a month after January 31st is the end of
+/// February. A day is 86400 seconds; there are no leap seconds.
+fn time_add(_: &mut Evaluator, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+    let mut time = expect_time("time_add", &arguments[0])?;
+    let (months, seconds) = match &arguments[1] {
+        Value::Hash(amounts) => expect_amounts(&amounts.borrow())?,
+        seconds => (0, expect_int("time_add", seconds)?),
+    };
+
+    if months != 0 {
+        let month = (time.year * 12 + time.month - 1)
+            .checked_add(months)
+            .filter(|month| (0..10_000 * 12).contains(month))
+            .ok_or_else(|| out_of_range("time_add"))?;
+        time.year = month / 12;
+        time.month = month % 12 + 1;
+        time.day = time.day.min(days_in_month(time.year, time.month));
+    }
+    let epoch = time
+        .epoch()
+        .checked_add(seconds)
+        .ok_or_else(|| out_of_range("time_add"))?;
+    Ok(DateTime::checked_from_epoch("time_add", epoch)?.into())
+}
+
+/// The months and seconds in the hash of amounts `time_add` was given.
+fn expect_amounts(amounts: &IndexMap<HashKey, Value>) -> Result<(i64, i64), RuntimeError> {
+    let (mut months, mut seconds) = (0i64, 0i64);
+    for (unit, amount) in amounts {
+        let HashKey::Str(unit) = unit else {
+            return Err(unknown_unit(unit));
+        };
+        let amount = match amount {
+            Value::Int(amount) => *amount,
+            other => {
+                return Err(RuntimeError::new(format!(
+                    "`time_add` expects the `{unit}` to add to be an Int, got {}",
+                    other.type_name()
+                )))
+            }
+        };
+        let (total, size) = match unit.as_str() {
+            "years" => (&mut months, 12),
+            "months" => (&mut months, 1),
+            _ => match UNITS.iter().find(|(name, _)| name == unit) {
+                Some((_, size)) => (&mut seconds, *size),
+                None => return Err(unknown_unit(&HashKey::Str(unit.clone()))),
+            },
+        };
+        *total = amount
+            .checked_mul(size)
+            .and_then(|amount| total.checked_add(amount))
+            .ok_or_else(|| out_of_range("time_add"))?;
+    }
+    Ok((months, seconds))
+}
+
+fn unknown_unit(unit: &HashKey) -> RuntimeError {
+    RuntimeError::new(format!(
+        "`time_add` can't add `{}`; it adds years, months, weeks, days, hours, minutes and seconds",
+        Value::from(unit.clone())
+    ))
+}
+
+/// A moment in UTC, to the second.
+#[derive(Debug, PartialEq)]
+struct DateTime {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+}
+
+impl DateTime {
+    fn from_epoch(seconds: i64) -> DateTime {
+        let days = seconds.div_euclid(SECONDS_PER_DAY);
+        let time = seconds.rem_euclid(SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: time / 3600,
+            minute: time % 3600 / 60,
+            second: time % 60,
+        }
+    }
+
+    /// [`DateTime::from_epoch`], failing for a time outside the years
+    /// times can be in.
+    fn checked_from_epoch(builtin: &str, seconds: i64) -> Result<DateTime, RuntimeError> {
+        // Far enough either side of the supported years that the
+        // calendar arithmetic can't overflow.
+        let limit = 400_000 * 366 * SECONDS_PER_DAY;
+        if !(-limit..=limit).contains(&seconds) {
+            return Err(out_of_range(builtin));
+        }
+        let time = DateTime::from_epoch(seconds);
+        if !(0..=9999).contains(&time.year) {
+            return Err(out_of_range(builtin));
+        }
+        Ok(time)
+    }
+
+    fn epoch(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * SECONDS_PER_DAY
+            + self.hour * 3600
+            + self.minute * 60
+            + self.second
+    }
+
+    /// 1 for Monday to 7 for Sunday.
+    fn weekday(&self) -> i64 {
+        // The epoch fell on a Thursday.
+        (days_from_civil(self.year, self.month, self.day) + 3).rem_euclid(7) + 1
+    }
+
+    fn day_of_year(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) - days_from_civil(self.year, 1, 1) + 1
+    }
+
+    /// Whether the fields make a real time, like no February 30th.
+    fn is_valid(&self) -> bool {
+        (0..=9999).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month)).contains(&self.day)
+            && (0..24).contains(&self.hour)
+            && (0..60).contains(&self.minute)
+            && (0..60).contains(&self.second)
+    }
+}
+
+impl From<DateTime> for Value {
+    fn from(time: DateTime) -> Value {
+        let weekday = time.weekday();
+        let fields = [
+            ("year", time.year),
+            ("month", time.month),
+            ("day", time.day),
+            ("hour", time.hour),
+            ("minute", time.minute),
+            ("second", time.second),
+            ("weekday", weekday),
+        ];
+        Value::hash(
+            fields
+                .into_iter()
+                .map(|(name, field)| (HashKey::Str(name.to_string()), Value::Int(field)))
+                .collect::<IndexMap<_, _>>(),
+        )
+    }
+}
+
+/// The days since 1970-01-01 of a date, by Howard Hinnant's algorithm,
+/// which works in 400-year eras starting in March so leap days come last.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn format(time: &DateTime, pattern: &str) -> Result<String, RuntimeError> {
+    let mut out = String::new();
+    let mut chars = pattern.chars();
+    while let Some(char) = chars.next() {
+        if char != '%' {
+            out.push(char);
+            continue;
+        }
+        let month = MONTHS[time.month as usize - 1];
+        let weekday = WEEKDAYS[time.weekday() as usize - 1];
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", time.year)),
+            Some('m') => out.push_str(&format!("{:02}", time.month)),
+            Some('d') => out.push_str(&format!("{:02}", time.day)),
+            Some('H') => out.push_str(&format!("{:02}", time.hour)),
+            Some('M') => out.push_str(&format!("{:02}", time.minute)),
+            Some('S') => out.push_str(&format!("{:02}", time.second)),
+            Some('j') => out.push_str(&format!("{:03}", time.day_of_year())),
+            Some('s') => out.push_str(&time.epoch().to_string()),
+            Some('b') => out.push_str(&month[..3]),
+            Some('B') => out.push_str(month),
+            Some('a') => out.push_str(&weekday[..3]),
+            Some('A') => out.push_str(weekday),
+            Some('%') => out.push('%'),
+            other => return Err(unknown_directive("time_format", other)),
+        }
+    }
+    Ok(out)
+}
+
+fn parse(text: &str, pattern: &str) -> Result<DateTime, RuntimeError> {
+    let mismatch = || {
+        RuntimeError::new(format!(
+            "`time_parse` could not read {text:?} as {pattern:?}"
+        ))
+    };
+
+    let mut time = DateTime {
+        year: 1970,
+        month: 1,
+        day: 1,
+        hour: 0,
+        minute: 0,
+        second: 0,
+    };
+    let mut rest = text;
+    let mut chars = pattern.chars();
+    while let Some(char) = chars.next() {
+        if char != '%' {
+            rest = rest.strip_prefix(char).ok_or_else(mismatch)?;
+            continue;
+        }
+        let (field, digits) = match chars.next() {
+            Some('Y') => (&mut time.year, 4),
+            Some('m') => (&mut time.month, 2),
+            Some('d') => (&mut time.day, 2),
+            Some('H') => (&mut time.hour, 2),
+            Some('M') => (&mut time.minute, 2),
+            Some('S') => (&mut time.second, 2),
+            Some(name @ ('b' | 'B')) => {
+                let (index, length) = MONTHS
+                    .iter()
+                    .enumerate()
+                    .find_map(|(index, month)| {
+                        let month = if name == 'b' { &month[..3] } else { month };
+                        let found = rest.get(..month.len())?;
+                        found
+                            .eq_ignore_ascii_case(month)
+                            .then_some((index, month.len()))
+                    })
+                    .ok_or_else(mismatch)?;
+                time.month = index as i64 + 1;
+                rest = &rest[length..];
+                continue;
+            }
+            Some('%') => {
+                rest = rest.strip_prefix('%').ok_or_else(mismatch)?;
+                continue;
+            }
+            other => return Err(unknown_directive("time_parse", other)),
+        };
+        // Up to `digits` of them, so `%Y%m%d` can read `20240301`.
+        let length = rest
+            .bytes()
+            .take(digits)
+            .take_while(u8::is_ascii_digit)
+            .count();
+        *field = rest[..length].parse().map_err(|_| mismatch())?;
+        rest = &rest[length..];
+    }
+
+    if !rest.is_empty() {
+        return Err(mismatch());
+    }
+    if !time.is_valid() {
+        return Err(RuntimeError::new(format!(
+            "`time_parse` found {text:?}, which isn't a real time"
+        )));
+    }
+    Ok(time)
+}
+
+/// The time the hash `value` holds.
+fn expect_time(builtin: &str, value: &Value) -> Result<DateTime, RuntimeError> {
+    let Value::Hash(pairs) = value else {
+        return Err(RuntimeError::new(format!(
+            "`{builtin}` expects a time, got {}",
+            value.type_name()
+        )));
+    };
+    let pairs = pairs.borrow();
+    let field = |name: &str, default: Option<i64>| match (
+        pairs.get(&HashKey::Str(name.to_string())),
+        default,
+    ) {
+        (Some(Value::Int(field)), _) => Ok(*field),
+        (None, Some(default)) => Ok(default),
+        (None, None) => Err(RuntimeError::new(format!(
+            "`{builtin}` expects a time with a `{name}`"
+        ))),
+        (Some(other), _) => Err(RuntimeError::new(format!(
+            "`{builtin}` expects a time whose `{name}` is an Int, got {}",
+            other.type_name()
+        ))),
+    };
+
+    let time = DateTime {
+        year: field("year", None)?,
+        month: field("month", None)?,
+        day: field("day", None)?,
+        hour: field("hour", Some(0))?,
+        minute: field("minute", Some(0))?,
+        second: field("second", Some(0))?,
+    };
+    if !time.is_valid() {
+        return Err(RuntimeError::new(format!(
+            "`{builtin}` expects a real time, got {value}"
+        )));
+    }
+    Ok(time)
+}
+
+fn expect_int(builtin: &str, value: &Value) -> Result<i64, RuntimeError> {
+    match value {
+        Value::Int(value) => Ok(*value),
+        other => Err(RuntimeError::new(format!(
+            "`{builtin}` expects an Int, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+fn expect_str<'a>(builtin: &str, value: &'a Value) -> Result<&'a str, RuntimeError> {
+    match value {
+        Value::Str(value) => Ok(value),
+        other => Err(RuntimeError::new(format!(
+            "`{builtin}` expects a Str, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+fn unknown_directive(builtin: &str, directive: Option<char>) -> RuntimeError {
+    match directive {
+        Some(directive) => RuntimeError::new(format!(
+            "`{builtin}` found an unknown directive `%{directive}`"
+        )),
+        None => RuntimeError::new(format!(
+            "`{builtin}` found a `%` at the end of its pattern; write `%%` for a literal one"
+        )),
+    }
+}
+
+fn out_of_range(builtin: &str) -> RuntimeError {
+    RuntimeError::new(format!(
+        "`{builtin}` gave a time outside the years 0 to 9999"
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::builtins::test::eval;
+
+    use super::{civil_from_days, days_from_civil};
+
+    #[test]
+    fn test_calendar() {
+        assert_eq!(0, days_from_civil(1970, 1, 1));
+        assert_eq!(19_783, days_from_civil(2024, 3, 1));
+        assert_eq!(-719_528, days_from_civil(0, 1, 1));
+        for days in (-719_528..2_932_896).step_by(97) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days, days_from_civil(year, month, day), "days={days}");
+        }
+    }
+
+    #[test]
+    fn test_time_builtins() {
+        let tests = vec![
+            (
+                r#"time_parse("2024-02-29T13:05:09")"#,
+                r#"{"year": 2024, "month": 2, "day": 29, "hour": 13, "minute": 5, "second": 9, "weekday": 4}"#,
+            ),
+            (
+                r#"time_parse("01/Mar/2024:08:00:00", "%d/%b/%Y:%H:%M:%S")["month"]"#,
+                "3",
+            ),
+            (r#"time_parse("20240301", "%Y%m%d")["day"]"#, "1"),
+            (r#"time_parse("july 4 1776", "%B %d %Y")["month"]"#, "7"),
+            ("time_format(time_from_epoch(0))", "1970-01-01T00:00:00"),
+            (
+                r#"time_format(time_parse("2024-03-01T12:30:00"), "%a %A %b %B %j %s 100%%")"#,
+                "Fri Friday Mar March 061 1709296200 100%",
+            ),
+            (
+                r#"time_format({"year": 1999, "month": 12, "day": 31}, "%Y-%m-%d %H:%M")"#,
+                "1999-12-31 00:00",
+            ),
+            (
+                r#"time_format(time_add(time_parse("2024-02-28T23:59:59"), 1))"#,
+                "2024-02-29T00:00:00",
+            ),
+            (
+                r#"time_format(time_add(time_parse("2024-03-01T00:00:00"), -86400 * 366))"#,
+                "2023-03-01T00:00:00",
+            ),
+            (
+                r#"time_diff(time_parse("2024-03-02T00:00:00"), time_parse("2024-03-01T12:00:00"))"#,
+                "43200",
+            ),
+            (
+                r#"time_format(time_add(time_parse("2024-01-31T08:00:00"), {"months": 1}))"#,
+                "2024-02-29T08:00:00",
+            ),
+            (
+                r#"time_format(time_add(time_parse("2024-02-29T08:00:00"), {"years": -1, "months": 13}))"#,
+                "2024-03-29T08:00:00",
+            ),
+            (
+                r#"time_format(time_add(time_parse("2024-12-31T23:00:00"), {"weeks": 1, "days": -1, "hours": 2, "minutes": -60, "seconds": 5}))"#,
+                "2025-01-07T00:00:05",
+            ),
+            (
+                r#"time_add(time_parse("2024-03-01T00:00:00"), {})["day"]"#,
+                "1",
+            ),
+            (
+                r#"time.format(time.add(time.parse("01/03/2024", "%d/%m/%Y"), {"months": -3}), "%Y-%m-%d")"#,
+                "2023-12-01",
+            ),
+            ("time.epoch(time.from_epoch(60))", "60"),
+            (
+                "keys(time)",
+                r#"["now", "from_epoch", "epoch", "parse", "format", "add", "diff"]"#,
+            ),
+            ("time_epoch(time_from_epoch(-1))", "-1"),
+            ("time_from_epoch(-1)[\"year\"]", "1969"),
+            (r#"time_now()["year"] >= 2024"#, "true"),
+        ];
+
+        for (input, expected) in tests {
+            let value = eval(input).unwrap_or_else(|error| panic!("{input}: {error}"));
+            assert_eq!(expected, value.to_string(), "input={input}");
+        }
+
+        let errors = vec![
+            (
+                r#"time_parse("2024-03-01")"#,
+                r#"`time_parse` could not read "2024-03-01" as "%Y-%m-%dT%H:%M:%S""#,
+            ),
+            (
+                r#"time_parse("2023-02-29", "%Y-%m-%d")"#,
+                r#"`time_parse` found "2023-02-29", which isn't a real time"#,
+            ),
+            (
+                r#"time_parse("x", "%q")"#,
+                "`time_parse` found an unknown directive `%q`",
+            ),
+            (
+                r#"time_format(time_now(), "100%")"#,
+                "`time_format` found a `%` at the end of its pattern; write `%%` for a literal one",
+            ),
+            ("time_format(1)", "`time_format` expects a time, got Int"),
+            (
+                r#"time_format({"year": 2024, "month": 1})"#,
+                "`time_format` expects a time with a `day`",
+            ),
+            (
+                r#"time_epoch({"year": 2024, "month": 1, "day": "1"})"#,
+                "`time_epoch` expects a time whose `day` is an Int, got Str",
+            ),
+            (
+                r#"time_epoch({"year": 2024, "month": 13, "day": 1})"#,
+                r#"`time_epoch` expects a real time, got {"year": 2024, "month": 13, "day": 1}"#,
+            ),
+            (
+                r#"time_add(time_parse("9999-12-31T23:59:59"), 1)"#,
+                "`time_add` gave a time outside the years 0 to 9999",
+            ),
+            (
+                r#"time_add(time_parse("9999-12-01T00:00:00"), {"months": 1})"#,
+                "`time_add` gave a time outside the years 0 to 9999",
+            ),
+            (
+                r#"time_add(time_now(), {"years": 9223372036854775807})"#,
+                "`time_add` gave a time outside the years 0 to 9999",
+            ),
+            (
+                r#"time_add(time_now(), {"fortnights": 1})"#,
+                "`time_add` can't add `fortnights`; it adds years, months, weeks, days, hours, minutes and seconds",
+            ),
+            (
+                r#"time_add(time_now(), {"days": 1.5})"#,
+                "`time_add` expects the `days` to add to be an Int, got Float",
+            ),
+            (
+                "time_add(time_now(), 1.5)",
+                "`time_add` expects an Int, got Float",
+            ),
+            (
+                "time_from_epoch(9223372036854775807)",
+                "`time_from_epoch` gave a time outside the years 0 to 9999",
+            ),
+        ];
+
+        for (input, expected) in errors {
+            assert_eq!(expected, eval(input).unwrap_err().message, "input={input}");
+        }
+    }
+}
@@ -0,0 +1,451 @@
+//! A C interface to [`Interpreter`], for embedding Tofu in programs that
+//! aren't written in Rust, from C or from languages with a C FFI, like
+//! Python's `ctypes`. Building with the `capi` feature exports these
+//! functions from the crate's `cdylib` and writes their declarations to
+//! `include/tofu.h`.
+//!
+//! ```c
+//! #include <stdio.h>
+//! #include "tofu.h"
+//!
+//! int main(void) {
+//!     TofuInterpreter *tofu = tofu_new();
+//!     char *error = NULL;
+//!     TofuValue *value = tofu_eval(tofu, "let x = 20\nx * 2 + 2", &error);
+//!     if (value == NULL) {
+//!         fprintf(stderr, "%s\n", error);
+//!         tofu_string_free(error);
+//!     } else {
+//!         char *text = tofu_value_to_string(value);
+//!         printf("%s\n", text);
+//!         tofu_string_free(text);
+//!         tofu_value_free(value);
+//!     }
+//!     tofu_free(tofu);
+//! }
+//! ```
+//!
+//! Everything a function here returns belongs to the caller, who gives it
+//! back with the matching `_free` function. Pointers passed in are only
+//! borrowed for the call, and strings are UTF-8 and end with a NUL.
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::ptr;
+
+use crate::builtins::Arity;
+use crate::error::TofuError;
+use crate::interpreter::Interpreter;
+use crate::value::Value;
+
+/// A Tofu interpreter, from [`tofu_new`].
+pub struct TofuInterpreter(Interpreter);
+
+/// A Tofu value, from [`tofu_eval`] or one of the `tofu_value_` functions
+/// that make them.
+pub struct TofuValue(Value);
+
+/// A function the application provides to Tofu scripts, registered with
+/// [`tofu_register_fn`]. It's given the `data` it was registered with and
+/// the `count` arguments of the call, which it only borrows. It returns a
+/// new value, which Tofu takes, or else `NULL` after setting `*error` to a
+/// message, which is copied before the function is called again.
+pub type TofuHostFn = extern "C" fn(
+    data: *mut c_void,
+    arguments: *const *const TofuValue,
+    count: usize,
+    error: *mut *const c_char,
+) -> *mut TofuValue;
+
+/// `text` as a C string for the caller to free with [`tofu_string_free`],
+/// leaving out any NULs it has, which would end it early.
+fn c_string(text: String) -> *mut c_char {
+    CString::new(text.replace('\0', ""))
+        .expect("Should have removed the NULs")
+        .into_raw()
+}
+
+/// What [`Value::type_name`] returns for `value`, as a C string.
+fn type_name(value: &Value) -> &'static CStr {
+    match value {
+        Value::Int(_) => c"Int",
+        #[cfg(feature = "bigint")]
+        Value::BigInt(_) => c"Int",
+        Value::Float(_) => c"Float",
+        Value::Bool(_) => c"Bool",
+        Value::Str(_) => c"Str",
+        Value::Char(_) => c"Char",
+        Value::Null => c"Null",
+        Value::Function(_) | Value::Closure(_) => c"Function",
+        Value::Builtin(_) => c"Builtin",
+        Value::StructType(_) | Value::VariantType(_) => c"Function",
+        Value::Struct(_) => c"Struct",
+        Value::Variant(_) => c"Enum",
+        Value::Array(_) => c"Array",
+        Value::Hash(_) => c"Hash",
+        Value::Set(_) => c"Set",
+        Value::Buffer(_) => c"Buffer",
+        Value::Tuple(_) => c"Tuple",
+        Value::Range(_) => c"Range",
+    }
+}
+
+fn boxed(value: Value) -> *mut TofuValue {
+    Box::into_raw(Box::new(TofuValue(value)))
+}
+
+/// A new interpreter with the standard builtins and the prelude loaded, to
+/// free with [`tofu_free`].
+#[no_mangle]
+pub extern "C" fn tofu_new() -> *mut TofuInterpreter {
+    Box::into_raw(Box::new(TofuInterpreter(Interpreter::new())))
+}
+
+/// Frees an interpreter from [`tofu_new`]. Values from it stay valid.
+///
+/// # Safety
+///
+/// `interpreter` must be from [`tofu_new`] and not already freed, or
+/// `NULL`, which is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn tofu_free(interpreter: *mut TofuInterpreter) {
+    if !interpreter.is_null() {
+        drop(Box::from_raw(interpreter));
+    }
+}
+
+/// Runs `source` in `interpreter`, keeping what it binds at the top level
+/// for the source run after it, and returns the value of its last
+/// statement. If it fails, returns `NULL` and, unless `error` is `NULL`,
+/// sets `*error` to a message to free with [`tofu_string_free`].
+///
+/// # Safety
+///
+/// `interpreter` must be from [`tofu_new`], `source` a NUL-terminated
+/// string, and `error` `NULL` or valid to write a pointer to.
+#[no_mangle]
+pub unsafe extern "C" fn tofu_eval(
+    interpreter: *mut TofuInterpreter,
+    source: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut TofuValue {
+    let interpreter = &mut (*interpreter).0;
+    let result = match CStr::from_ptr(source).to_str() {
+        Ok(source) => interpreter.eval(source),
+        Err(_) => Err(TofuError::runtime("source is not valid UTF-8")),
+    };
+    match result {
+        Ok(value) => boxed(value),
+        Err(message) => {
+            if !error.is_null() {
+                *error = c_string(message.to_string());
+            }
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Makes `function` callable from Tofu as `name`, with `data` passed to
+/// each call. Calls must have `arity` arguments, or any number if it's
+/// negative. Returns whether `name` could be used, which it can't if it's
+/// not valid UTF-8.
+///
+/// # Safety
+///
+/// `interpreter` must be from [`tofu_new`] and `name` a NUL-terminated
+/// string. `data` must stay valid for `function` as long as the
+/// interpreter does.
+#[no_mangle]
+pub unsafe extern "C" fn tofu_register_fn(
+    interpreter: *mut TofuInterpreter,
+    name: *const c_char,
+    arity: c_int,
+    function: TofuHostFn,
+    data: *mut c_void,
+) -> bool {
+    let interpreter = &mut (*interpreter).0;
+    let Ok(name) = CStr::from_ptr(name).to_str() else {
+        return false;
+    };
+    let arity = usize::try_from(arity).map_or(Arity::AtLeast(0), Arity::Exact);
+    let function_name = name.to_string();
+    interpreter.register_fn(name, arity, move |arguments| {
+        let arguments: Vec<TofuValue> = arguments.iter().cloned().map(TofuValue).collect();
+        let pointers: Vec<*const TofuValue> = arguments.iter().map(ptr::from_ref).collect();
+        let mut error = ptr::null();
+        let result = function(data, pointers.as_ptr(), pointers.len(), &mut error);
+        if !result.is_null() {
+            // SAFETY: the host function returns values made by this module.
+            return Ok(unsafe { Box::from_raw(result) }.0);
+        }
+        let message = if error.is_null() {
+            format!("`{function_name}` failed")
+        } else {
+            // SAFETY: the host function sets `error` to a C string.
+            unsafe { CStr::from_ptr(error) }
+                .to_string_lossy()
+                .into_owned()
+        };
+        Err(TofuError::runtime(message))
+    });
+    true
+}
+
+/// `value` as `print` would show it, to free with [`tofu_string_free`].
+///
+/// # Safety
+///
+/// `value` must be a valid value.
+#[no_mangle]
+pub unsafe extern "C" fn tofu_value_to_string(value: *const TofuValue) -> *mut c_char {
+    c_string((*value).0.to_string())
+}
+
+/// The name of the type of `value`, like `"Int"`, which isn't to be freed.
+///
+/// # Safety
+///
+/// `value` must be a valid value.
+#[no_mangle]
+pub unsafe extern "C" fn tofu_value_type(value: *const TofuValue) -> *const c_char {
+    type_name(&(*value).0).as_ptr()
+}
+
+/// Sets `*out` to `value` and returns `true` if it's an `Int` that fits in
+/// 64 bits, or returns `false`.
+///
+/// # Safety
+///
+/// `value` must be a valid value and `out` valid to write to.
+#[no_mangle]
+pub unsafe extern "C" fn tofu_value_as_int(value: *const TofuValue, out: *mut i64) -> bool {
+    match i64::try_from(&(*value).0) {
+        Ok(int) => {
+            *out = int;
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Sets `*out` to `value` and returns `true` if it's a number, or returns
+/// `false`.
+///
+/// # Safety
+///
+/// `value` must be a valid value and `out` valid to write to.
+#[no_mangle]
+pub unsafe extern "C" fn tofu_value_as_float(value: *const TofuValue, out: *mut f64) -> bool {
+    match f64::try_from(&(*value).0) {
+        Ok(float) => {
+            *out = float;
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Sets `*out` to `value` and returns `true` if it's a `Bool`, or returns
+/// `false`.
+///
+/// # Safety
+///
+/// `value` must be a valid value and `out` valid to write to.
+#[no_mangle]
+pub unsafe extern "C" fn tofu_value_as_bool(value: *const TofuValue, out: *mut bool) -> bool {
+    match bool::try_from(&(*value).0) {
+        Ok(boolean) => {
+            *out = boolean;
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// A new `Int`, to free with [`tofu_value_free`] unless it's given to Tofu.
+#[no_mangle]
+pub extern "C" fn tofu_value_int(value: i64) -> *mut TofuValue {
+    boxed(Value::Int(value))
+}
+
+/// A new `Float`, to free with [`tofu_value_free`] unless it's given to
+/// Tofu.
+#[no_mangle]
+pub extern "C" fn tofu_value_float(value: f64) -> *mut TofuValue {
+    boxed(Value::Float(value))
+}
+
+/// A new `Bool`, to free with [`tofu_value_free`] unless it's given to Tofu.
+#[no_mangle]
+pub extern "C" fn tofu_value_bool(value: bool) -> *mut TofuValue {
+    boxed(Value::Bool(value))
+}
+
+/// A new `null`, to free with [`tofu_value_free`] unless it's given to Tofu.
+#[no_mangle]
+pub extern "C" fn tofu_value_null() -> *mut TofuValue {
+    boxed(Value::Null)
+}
+
+/// A new `Str` with a copy of `text`, to free with [`tofu_value_free`]
+/// unless it's given to Tofu. Invalid UTF-8 in `text` is replaced.
+///
+/// # Safety
+///
+/// `text` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tofu_value_str(text: *const c_char) -> *mut TofuValue {
+    boxed(Value::Str(
+        CStr::from_ptr(text).to_string_lossy().into_owned(),
+    ))
+}
+
+/// Frees a value.
+///
+/// # Safety
+///
+/// `value` must be a value this module made that hasn't been freed or
+/// given to Tofu, or `NULL`, which is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn tofu_value_free(value: *mut TofuValue) {
+    if !value.is_null() {
+        drop(Box::from_raw(value));
+    }
+}
+
+/// Frees a string this module returned.
+///
+/// # Safety
+///
+/// `text` must be a string from this module that hasn't been freed, or
+/// `NULL`, which is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn tofu_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::{c_char, c_void, CStr};
+    use std::ptr;
+
+    use super::*;
+
+    unsafe fn take_string(text: *mut c_char) -> String {
+        let string = CStr::from_ptr(text).to_str().unwrap().to_string();
+        tofu_string_free(text);
+        string
+    }
+
+    /// Adds its arguments to the total `data` points to and returns it.
+    extern "C" fn add(
+        data: *mut c_void,
+        arguments: *const *const TofuValue,
+        count: usize,
+        error: *mut *const c_char,
+    ) -> *mut TofuValue {
+        let total = data.cast::<i64>();
+        unsafe {
+            for index in 0..count {
+                let mut value = 0;
+                if !tofu_value_as_int(*arguments.add(index), &mut value) {
+                    *error = c"`add` takes Ints".as_ptr();
+                    return ptr::null_mut();
+                }
+                *total += value;
+            }
+            tofu_value_int(*total)
+        }
+    }
+
+    #[test]
+    fn test_capi() {
+        unsafe {
+            let tofu = tofu_new();
+            let mut total: i64 = 0;
+            assert!(tofu_register_fn(
+                tofu,
+                c"add".as_ptr(),
+                -1,
+                add,
+                (&mut total as *mut i64).cast(),
+            ));
+
+            let mut error = ptr::null_mut();
+            let value = tofu_eval(tofu, c"let x = 20\nadd(x, 1) * 2".as_ptr(), &mut error);
+            assert!(error.is_null());
+            let mut int = 0;
+            assert!(tofu_value_as_int(value, &mut int));
+            assert_eq!(42, int);
+            assert_eq!(
+                "Int",
+                CStr::from_ptr(tofu_value_type(value)).to_str().unwrap()
+            );
+            assert_eq!("42", take_string(tofu_value_to_string(value)));
+            tofu_value_free(value);
+            assert_eq!(21, total);
+
+            let value = tofu_eval(tofu, c"add(\"x\")".as_ptr(), &mut error);
+            assert!(value.is_null());
+            assert_eq!("1:4: `add` takes Ints", take_string(error));
+            let value = tofu_eval(tofu, c"let = 1".as_ptr(), ptr::null_mut());
+            assert!(value.is_null());
+
+            let text = tofu_value_str(c"tofu".as_ptr());
+            let mut boolean = false;
+            assert!(!tofu_value_as_bool(text, &mut boolean));
+            assert_eq!("tofu", take_string(tofu_value_to_string(text)));
+            tofu_value_free(text);
+            tofu_free(tofu);
+        }
+    }
+
+    #[test]
+    fn test_value_type() {
+        let sources = [
+            "1",
+            "1.5",
+            "true",
+            "\"s\"",
+            "'c'",
+            "null",
+            "fn() {}",
+            "len",
+            "struct P { x }; P",
+            "struct P { x }; P(1)",
+            "enum O { Some(v), None }; Some",
+            "enum O { Some(v), None }; Some(1)",
+            "[1]",
+            "{}",
+            "set([1])",
+            "buf_new(\"b\")",
+            "(1, 2)",
+            "0..1",
+        ];
+        let mut interpreter = Interpreter::new();
+        let mut values: Vec<Value> = sources
+            .iter()
+            .map(|source| interpreter.eval(source).unwrap())
+            .collect();
+        let evaluator = interpreter.evaluator();
+        let program =
+            crate::parser::Parser::new(crate::lexer::Lexer::new("fn() {}")).parse_program();
+        let bytecode = crate::compiler::compile(&program, evaluator.builtins()).unwrap();
+        values.push(crate::vm::run(evaluator, &bytecode).unwrap());
+        #[cfg(feature = "bigint")]
+        values.push(Value::BigInt(std::rc::Rc::new(1.into())));
+
+        for value in values {
+            let expected = value.type_name();
+            let value = boxed(value);
+            unsafe {
+                assert_eq!(
+                    expected,
+                    CStr::from_ptr(tofu_value_type(value)).to_str().unwrap()
+                );
+                tofu_value_free(value);
+            }
+        }
+    }
+}
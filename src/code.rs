@@ -0,0 +1,1203 @@
+//! Bytecode, what [`crate::compiler`] lowers the AST to. A function's code
+//! is a flat run of bytes: each instruction is an [`Opcode`] byte followed
+//! by its operands, big-endian, with the widths [`Opcode::operand_widths`]
+//! gives. Operands index the constants pool or a scope's slots, or give
+//! jump targets as byte offsets into the same function's code.
+
+use std::fmt::{Display, Write};
+use std::rc::Rc;
+
+use crate::symbol::Symbol;
+use crate::token::Span;
+
+/// The operation an instruction performs. Unless it says otherwise, an
+/// instruction pops its operands off the stack and pushes its result.
+#[repr(u8)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Opcode {
+    /// Pushes the constant at the operand's index.
+    Constant,
+    Null,
+    True,
+    False,
+    /// Discards the top of the stack.
+    Pop,
+    /// Pushes a copy of the top two values, in the same order.
+    Dup2,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    Equal,
+    NotEqual,
+    LessThan,
+    GreaterThan,
+    LessThanEqual,
+    GreaterThanEqual,
+    /// Negates a number.
+    Minus,
+    /// Whether the value is falsy, as a Bool.
+    Not,
+    /// Whether the value is truthy, as a Bool.
+    Truthy,
+    /// Continues at the operand's offset.
+    Jump,
+    /// Pops a value and jumps if it's falsy.
+    JumpIfFalse,
+    /// Jumps, leaving the value in place, if the top of the stack is
+    /// `null`.
+    JumpIfNull,
+    /// Jumps, leaving the value in place, if the top of the stack isn't
+    /// `null`, and pops it otherwise.
+    JumpIfNotNull,
+    /// Pushes the global in the operand's slot, which is an error if it
+    /// hasn't been declared.
+    GetGlobal,
+    /// Stores the top of the stack in the global in the operand's slot,
+    /// leaving the value in place. It's an error if it hasn't been
+    /// declared.
+    SetGlobal,
+    /// Pops a value and declares the global in the operand's slot with it.
+    DefineGlobal,
+    /// Pushes the local in the operand's slot of the current call.
+    GetLocal,
+    /// Stores the top of the stack in the local in the operand's slot,
+    /// leaving the value in place.
+    SetLocal,
+    /// Pops a value and declares the local in the operand's slot with it,
+    /// as a new variable, so functions that captured the slot's previous
+    /// variable keep it.
+    DefineLocal,
+    /// Pushes the current function's free variable at the operand's index.
+    GetFree,
+    /// Stores the top of the stack in the current function's free variable
+    /// at the operand's index, leaving the value in place.
+    SetFree,
+    /// Pushes the builtin at the operand's index in [`Bytecode::builtins`].
+    GetBuiltin,
+    /// Collects the operand's number of values into an array.
+    Array,
+    /// Collects the operand's number of key-value pairs into a hash.
+    Hash,
+    /// Makes `start..end` from the top two values. The operand's
+    /// [`RANGE_OPEN_START`] and [`RANGE_OPEN_END`] bits say which bounds
+    /// were left out, so the range shows as it was written.
+    Range,
+    /// Makes `start..=end` from the top two values, with the same operand
+    /// as [`Opcode::Range`].
+    RangeInclusive,
+    /// Pops an index and then the value to index.
+    Index,
+    /// Pops a value, an index and the value to store it in, and pushes the
+    /// value back.
+    SetIndex,
+    /// Calls the function below the operand's number of arguments.
+    Call,
+    /// Like [`Opcode::Call`], for a call whose value the current function
+    /// returns: a [`Return`](Opcode::Return) follows it, directly or
+    /// through jumps. A compiled function called this way takes the
+    /// current call's place instead of starting one inside it, so tail
+    /// recursion runs in constant space.
+    TailCall,
+    /// Calls the method named by the first operand's constant on the
+    /// receiver below the second operand's number of arguments.
+    CallMethod,
+    /// Returns the top of the stack from the current function.
+    Return,
+    /// Makes a function from the compiled function at the operand's
+    /// constant index, capturing the variables its
+    /// [`CompiledFunction::captures`] lists.
+    Closure,
+    /// Pops a value and starts iterating over it, for a `for` loop. Each
+    /// call keeps its iterators apart from its other values, innermost
+    /// last.
+    GetIter,
+    /// Pushes the next item from the innermost iterator, or, once it's
+    /// done, drops it and jumps to the operand's offset.
+    IterNext,
+    /// Drops the innermost iterator, for a `break` out of a `for` loop.
+    PopIter,
+}
+
+impl Opcode {
+    /// Every opcode, in the order of their byte values.
+    const ALL: [Opcode; 52] = [
+        Opcode::Constant,
+        Opcode::Null,
+        Opcode::True,
+        Opcode::False,
+        Opcode::Pop,
+        Opcode::Dup2,
+        Opcode::Add,
+        Opcode::Subtract,
+        Opcode::Multiply,
+        Opcode::Divide,
+        Opcode::Power,
+        Opcode::BitAnd,
+        Opcode::BitOr,
+        Opcode::BitXor,
+        Opcode::ShiftLeft,
+        Opcode::ShiftRight,
+        Opcode::Equal,
+        Opcode::NotEqual,
+        Opcode::LessThan,
+        Opcode::GreaterThan,
+        Opcode::LessThanEqual,
+        Opcode::GreaterThanEqual,
+        Opcode::Minus,
+        Opcode::Not,
+        Opcode::Truthy,
+        Opcode::Jump,
+        Opcode::JumpIfFalse,
+        Opcode::JumpIfNull,
+        Opcode::JumpIfNotNull,
+        Opcode::GetGlobal,
+        Opcode::SetGlobal,
+        Opcode::DefineGlobal,
+        Opcode::GetLocal,
+        Opcode::SetLocal,
+        Opcode::DefineLocal,
+        Opcode::GetFree,
+        Opcode::SetFree,
+        Opcode::GetBuiltin,
+        Opcode::Array,
+        Opcode::Hash,
+        Opcode::Range,
+        Opcode::RangeInclusive,
+        Opcode::Index,
+        Opcode::SetIndex,
+        Opcode::Call,
+        Opcode::TailCall,
+        Opcode::CallMethod,
+        Opcode::Return,
+        Opcode::Closure,
+        Opcode::GetIter,
+        Opcode::IterNext,
+        Opcode::PopIter,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<Opcode> {
+        Opcode::ALL.get(usize::from(byte)).copied()
+    }
+
+    /// How many bytes each of the instruction's operands takes.
+    pub fn operand_widths(self) -> &'static [usize] {
+        match self {
+            Opcode::Constant
+            | Opcode::Jump
+            | Opcode::JumpIfFalse
+            | Opcode::JumpIfNull
+            | Opcode::JumpIfNotNull
+            | Opcode::GetGlobal
+            | Opcode::SetGlobal
+            | Opcode::DefineGlobal
+            | Opcode::GetLocal
+            | Opcode::SetLocal
+            | Opcode::DefineLocal
+            | Opcode::GetFree
+            | Opcode::SetFree
+            | Opcode::GetBuiltin
+            | Opcode::Array
+            | Opcode::Hash
+            | Opcode::Closure
+            | Opcode::IterNext => &[2],
+            Opcode::Call | Opcode::TailCall | Opcode::Range | Opcode::RangeInclusive => &[1],
+            Opcode::CallMethod => &[2, 1],
+            _ => &[],
+        }
+    }
+}
+
+/// Set in the operand of [`Opcode::Range`] if the range's start was left out.
+pub const RANGE_OPEN_START: usize = 1;
+/// Set in the operand of [`Opcode::Range`] if the range's end was left out.
+pub const RANGE_OPEN_END: usize = 2;
+
+impl Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// Encodes an instruction. Each operand must fit in its width.
+pub fn make(opcode: Opcode, operands: &[usize]) -> Vec<u8> {
+    let widths = opcode.operand_widths();
+    debug_assert_eq!(widths.len(), operands.len(), "operands for {opcode}");
+
+    let mut instruction = vec![opcode as u8];
+    for (operand, width) in operands.iter().zip(widths) {
+        let bytes = operand.to_be_bytes();
+        debug_assert!(
+            bytes[..bytes.len() - width].iter().all(|byte| *byte == 0),
+            "operand {operand} of {opcode} doesn't fit in {width} bytes"
+        );
+        instruction.extend_from_slice(&bytes[bytes.len() - width..]);
+    }
+
+    instruction
+}
+
+/// Decodes the operands of the instruction at `offset`, which starts with
+/// `opcode`, returning them and the offset of the next instruction.
+pub fn read_operands(opcode: Opcode, code: &[u8], offset: usize) -> (Vec<usize>, usize) {
+    let mut operands = Vec::with_capacity(opcode.operand_widths().len());
+    let mut next = offset + 1;
+
+    for width in opcode.operand_widths() {
+        let operand = code[next..next + width]
+            .iter()
+            .fold(0, |operand, byte| operand << 8 | usize::from(*byte));
+        operands.push(operand);
+        next += width;
+    }
+
+    (operands, next)
+}
+
+/// Reads a two-byte operand at `offset`.
+pub fn read_u16(code: &[u8], offset: usize) -> usize {
+    usize::from(u16::from_be_bytes([code[offset], code[offset + 1]]))
+}
+
+/// A function as the compiler leaves it, ready to be closed over a scope
+/// when its definition runs. A whole program compiles to one of these too,
+/// taking no parameters.
+#[derive(PartialEq, Debug, Clone)]
+pub struct CompiledFunction {
+    /// The name the function was bound to where it was defined, if any.
+    pub name: Option<String>,
+    /// The parameters' names. Calls bind the arguments to the first local
+    /// slots.
+    pub parameters: Vec<Symbol>,
+    /// How many local slots a call needs.
+    pub locals: usize,
+    /// The variables of the enclosing function it uses, which are captured
+    /// when its definition runs.
+    pub captures: Vec<Capture>,
+    pub code: Vec<u8>,
+    /// Where in the source the instructions that can fail came from, as
+    /// offsets into `code` in increasing order, for runtime errors.
+    pub spans: Vec<(usize, Span)>,
+}
+
+impl CompiledFunction {
+    /// The span recorded for the instruction at `offset`, if any.
+    pub fn span_at(&self, offset: usize) -> Option<Span> {
+        let index = self
+            .spans
+            .binary_search_by_key(&offset, |(offset, _)| *offset)
+            .ok()?;
+        Some(self.spans[index].1)
+    }
+}
+
+/// Where a function's free variable comes from in the function enclosing
+/// it.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Capture {
+    /// The local in this slot.
+    Local(usize),
+    /// The enclosing function's own free variable at this index.
+    Free(usize),
+}
+
+/// A value known when compiling, which instructions refer to by its index
+/// in the constants pool.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Constant {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Char(char),
+    /// The name of a method to call.
+    Name(Symbol),
+    Function(Rc<CompiledFunction>),
+}
+
+/// How long a string constant's preview in a disassembly can get.
+const PREVIEW_CHARS: usize = 32;
+
+impl Display for Constant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Constant::Int(value) => write!(f, "{value}"),
+            Constant::Float(value) => write!(f, "{value:?}"),
+            Constant::Str(value) if value.chars().count() > PREVIEW_CHARS => {
+                let preview: String = value.chars().take(PREVIEW_CHARS).collect();
+                write!(f, "{preview:?}...")
+            }
+            Constant::Str(value) => write!(f, "{value:?}"),
+            Constant::Char(value) => write!(f, "{value:?}"),
+            Constant::Name(name) => write!(f, ".{name}"),
+            Constant::Function(function) => match &function.name {
+                Some(name) => write!(f, "<fn {name}>"),
+                None => write!(f, "<fn>"),
+            },
+        }
+    }
+}
+
+/// A compiled program: its top-level code and the constants pool shared by
+/// it and every function in it.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Bytecode {
+    pub main: Rc<CompiledFunction>,
+    pub constants: Vec<Constant>,
+    /// The name of each global slot.
+    pub globals: Vec<Symbol>,
+    /// The names of the builtins the program uses, by index.
+    pub builtins: Vec<Symbol>,
+}
+
+/// The first bytes of a bytecode file.
+const MAGIC: &[u8; 4] = b"TOFU";
+
+/// The version of the format [`Bytecode::encode`] writes. It goes up
+/// whenever the format or the meaning of an opcode changes, since code
+/// compiled for one version can't run on another.
+pub const FORMAT_VERSION: u16 = 3;
+
+impl Bytecode {
+    /// Encodes the program for a bytecode file, after a header of magic
+    /// bytes and the format version.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer(MAGIC.to_vec());
+        writer.u16(FORMAT_VERSION);
+        writer.symbols(&self.globals);
+        writer.symbols(&self.builtins);
+        writer.len(self.constants.len());
+        for constant in &self.constants {
+            writer.constant(constant);
+        }
+        writer.function(&self.main);
+        writer.0
+    }
+
+    /// Decodes a program [`Bytecode::encode`] wrote. The code is checked
+    /// to only use opcodes, constants, slots and jump targets that exist,
+    /// so a damaged file is reported here rather than when it runs.
+    pub fn decode(bytes: &[u8]) -> Result<Bytecode, String> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err("not a Tofu bytecode file".to_string());
+        }
+        let version = reader.u16()?;
+        if version != FORMAT_VERSION {
+            return Err(format!(
+                "the file is bytecode version {version}, but this interpreter runs version \
+                 {FORMAT_VERSION}; build it again from its source"
+            ));
+        }
+
+        let globals = reader.symbols()?;
+        let builtins = reader.symbols()?;
+        let count = reader.len()?;
+        let mut constants = Vec::new();
+        for _ in 0..count {
+            constants.push(reader.constant()?);
+        }
+        let main = Rc::new(reader.function()?);
+        if reader.offset != bytes.len() {
+            return Err("the bytecode file has extra bytes at the end".to_string());
+        }
+
+        let bytecode = Bytecode {
+            main,
+            constants,
+            globals,
+            builtins,
+        };
+        bytecode.verify()?;
+        Ok(bytecode)
+    }
+
+    fn verify(&self) -> Result<(), String> {
+        if !self.main.parameters.is_empty() || !self.main.captures.is_empty() {
+            return Err("the program's code can't take parameters or capture variables".into());
+        }
+        self.verify_function(&self.main)?;
+        for constant in &self.constants {
+            if let Constant::Function(function) = constant {
+                self.verify_function(function)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn verify_function(&self, function: &CompiledFunction) -> Result<(), String> {
+        let code = &function.code;
+        let mut starts = Vec::new();
+        let mut jumps = Vec::new();
+        let mut last = None;
+        let mut offset = 0;
+
+        while offset < code.len() {
+            let invalid =
+                |problem: String| format!("invalid bytecode at offset {offset}: {problem}");
+            let opcode = Opcode::from_byte(code[offset])
+                .ok_or_else(|| invalid(format!("unknown opcode {}", code[offset])))?;
+            let width: usize = opcode.operand_widths().iter().sum();
+            if offset + 1 + width > code.len() {
+                return Err(invalid(format!("{opcode} is cut short")));
+            }
+            let (operands, next) = read_operands(opcode, code, offset);
+
+            let limit = match opcode {
+                Opcode::Constant | Opcode::Closure | Opcode::CallMethod => self.constants.len(),
+                Opcode::GetGlobal | Opcode::SetGlobal | Opcode::DefineGlobal => self.globals.len(),
+                Opcode::GetLocal | Opcode::SetLocal | Opcode::DefineLocal => function.locals,
+                Opcode::GetFree | Opcode::SetFree => function.captures.len(),
+                Opcode::GetBuiltin => self.builtins.len(),
+                _ => usize::MAX,
+            };
+            if operands.first().is_some_and(|operand| *operand >= limit) {
+                return Err(invalid(format!("{opcode} {} is out of range", operands[0])));
+            }
+
+            let constant = || &self.constants[operands[0]];
+            let right_constant = match opcode {
+                Opcode::Constant => {
+                    !matches!(constant(), Constant::Name(_) | Constant::Function(_))
+                }
+                Opcode::CallMethod => matches!(constant(), Constant::Name(_)),
+                Opcode::Closure => match constant() {
+                    Constant::Function(closure) => {
+                        let captures_exist = closure.captures.iter().all(|capture| match capture {
+                            Capture::Local(slot) => *slot < function.locals,
+                            Capture::Free(index) => *index < function.captures.len(),
+                        });
+                        if !captures_exist {
+                            return Err(invalid(
+                                "a function captures a variable that doesn't exist".into(),
+                            ));
+                        }
+                        true
+                    }
+                    _ => false,
+                },
+                _ => true,
+            };
+            if !right_constant {
+                return Err(invalid(format!(
+                    "{opcode} refers to the wrong sort of constant"
+                )));
+            }
+
+            if matches!(
+                opcode,
+                Opcode::Jump
+                    | Opcode::JumpIfFalse
+                    | Opcode::JumpIfNull
+                    | Opcode::JumpIfNotNull
+                    | Opcode::IterNext
+            ) {
+                jumps.push((offset, operands[0]));
+            }
+            starts.push(offset);
+            last = Some(opcode);
+            offset = next;
+        }
+
+        if !matches!(last, Some(Opcode::Return | Opcode::Jump)) {
+            return Err("invalid bytecode: a function doesn't end with a return or a jump".into());
+        }
+        for (offset, target) in jumps {
+            if starts.binary_search(&target).is_err() {
+                return Err(format!(
+                    "invalid bytecode at offset {offset}: it jumps to {target}, which isn't an instruction"
+                ));
+            }
+        }
+        if function.parameters.len() > function.locals {
+            return Err("invalid bytecode: a function has more parameters than locals".into());
+        }
+        verify_stack(function)
+    }
+}
+
+/// Checks that each instruction of `function` the code can reach has the
+/// values and `for` loop iterators it takes, and has the same number of
+/// each whichever way the code gets to it, so running the function can't
+/// take more off the stack than it put there.
+fn verify_stack(function: &CompiledFunction) -> Result<(), String> {
+    let code = &function.code;
+    // How many values and iterators each instruction starts with.
+    let mut depths: Vec<Option<(usize, usize)>> = vec![None; code.len()];
+    let mut pending = vec![(0, (0, 0))];
+
+    while let Some((offset, depth)) = pending.pop() {
+        let invalid = |problem: String| format!("invalid bytecode at offset {offset}: {problem}");
+        match depths[offset] {
+            Some(seen) if seen == depth => continue,
+            Some(_) => {
+                return Err(invalid(
+                    "the stack isn't the same size every way the code gets here".into(),
+                ))
+            }
+            None => depths[offset] = Some(depth),
+        }
+
+        let opcode = Opcode::from_byte(code[offset]).expect("verified instructions have opcodes");
+        let (operands, next) = read_operands(opcode, code, offset);
+        let (values, iterators) = depth;
+        let (pops, pushes) = match opcode {
+            Opcode::Constant
+            | Opcode::Null
+            | Opcode::True
+            | Opcode::False
+            | Opcode::GetGlobal
+            | Opcode::GetLocal
+            | Opcode::GetFree
+            | Opcode::GetBuiltin
+            | Opcode::Closure => (0, 1),
+            Opcode::Pop
+            | Opcode::DefineGlobal
+            | Opcode::DefineLocal
+            | Opcode::GetIter
+            | Opcode::JumpIfFalse
+            | Opcode::Return => (1, 0),
+            Opcode::Dup2 => (2, 4),
+            Opcode::Add
+            | Opcode::Subtract
+            | Opcode::Multiply
+            | Opcode::Divide
+            | Opcode::Power
+            | Opcode::BitAnd
+            | Opcode::BitOr
+            | Opcode::BitXor
+            | Opcode::ShiftLeft
+            | Opcode::ShiftRight
+            | Opcode::Equal
+            | Opcode::NotEqual
+            | Opcode::LessThan
+            | Opcode::GreaterThan
+            | Opcode::LessThanEqual
+            | Opcode::GreaterThanEqual
+            | Opcode::Range
+            | Opcode::RangeInclusive
+            | Opcode::Index => (2, 1),
+            Opcode::Minus
+            | Opcode::Not
+            | Opcode::Truthy
+            | Opcode::JumpIfNull
+            | Opcode::JumpIfNotNull
+            | Opcode::SetGlobal
+            | Opcode::SetLocal
+            | Opcode::SetFree => (1, 1),
+            Opcode::Jump | Opcode::IterNext | Opcode::PopIter => (0, 0),
+            Opcode::Array => (operands[0], 1),
+            Opcode::Hash => (2 * operands[0], 1),
+            Opcode::SetIndex => (3, 1),
+            Opcode::Call | Opcode::TailCall => (operands[0] + 1, 1),
+            Opcode::CallMethod => (operands[1] + 1, 1),
+        };
+        if values < pops {
+            return Err(invalid(format!(
+                "{opcode} takes more values than the stack has"
+            )));
+        }
+        if matches!(opcode, Opcode::IterNext | Opcode::PopIter) && iterators == 0 {
+            return Err(invalid(format!("{opcode} isn't in a loop")));
+        }
+
+        let after = values - pops + pushes;
+        match opcode {
+            Opcode::Return => {}
+            Opcode::Jump => pending.push((operands[0], (after, iterators))),
+            Opcode::JumpIfFalse | Opcode::JumpIfNull => {
+                pending.push((operands[0], (after, iterators)));
+                pending.push((next, (after, iterators)));
+            }
+            Opcode::JumpIfNotNull => {
+                pending.push((operands[0], (after, iterators)));
+                pending.push((next, (after - 1, iterators)));
+            }
+            Opcode::GetIter => pending.push((next, (after, iterators + 1))),
+            Opcode::IterNext => {
+                pending.push((operands[0], (after, iterators - 1)));
+                pending.push((next, (after + 1, iterators)));
+            }
+            Opcode::PopIter => pending.push((next, (after, iterators - 1))),
+            _ => pending.push((next, (after, iterators))),
+        }
+    }
+    Ok(())
+}
+
+impl Bytecode {
+    /// A listing of the program's code, then each function's in the order
+    /// of the constants pool. Each instruction gets a line with its offset,
+    /// opcode and operands, and what the operands refer to: the constant
+    /// loaded, the global, builtin or parameter named, or the jump target.
+    pub fn disassemble(&self) -> String {
+        let mut listing = String::new();
+        self.disassemble_function(&mut listing, "main", &self.main);
+
+        for (index, constant) in self.constants.iter().enumerate() {
+            if let Constant::Function(function) = constant {
+                let name = function.name.as_deref().unwrap_or("anonymous function");
+                listing.push('\n');
+                self.disassemble_function(
+                    &mut listing,
+                    &format!("{name} (constant {index})"),
+                    function,
+                );
+            }
+        }
+
+        listing
+    }
+
+    fn disassemble_function(&self, listing: &mut String, title: &str, function: &CompiledFunction) {
+        writeln!(listing, "== {title} ==").unwrap();
+        if !function.parameters.is_empty() {
+            let parameters: Vec<&str> = function.parameters.iter().map(Symbol::as_str).collect();
+            writeln!(listing, "parameters: {}", parameters.join(", ")).unwrap();
+        }
+        writeln!(listing, "locals: {}", function.locals).unwrap();
+        if !function.captures.is_empty() {
+            let captures: Vec<String> = function
+                .captures
+                .iter()
+                .map(|capture| match capture {
+                    Capture::Local(slot) => format!("local {slot}"),
+                    Capture::Free(index) => format!("free {index}"),
+                })
+                .collect();
+            writeln!(listing, "captures: {}", captures.join(", ")).unwrap();
+        }
+
+        let code = &function.code;
+        let mut offset = 0;
+        while offset < code.len() {
+            let Some(opcode) = Opcode::from_byte(code[offset]) else {
+                writeln!(listing, "{offset:04}  <unknown opcode {}>", code[offset]).unwrap();
+                offset += 1;
+                continue;
+            };
+            let width: usize = opcode.operand_widths().iter().sum();
+            if offset + 1 + width > code.len() {
+                writeln!(listing, "{offset:04}  {opcode} <cut short>").unwrap();
+                break;
+            }
+
+            let (operands, next) = read_operands(opcode, code, offset);
+            let mut instruction = opcode.to_string();
+            for operand in &operands {
+                write!(instruction, " {operand}").unwrap();
+            }
+            match self.describe_operands(function, opcode, &operands) {
+                Some(comment) => writeln!(listing, "{offset:04}  {instruction:<20} ; {comment}"),
+                None => writeln!(listing, "{offset:04}  {instruction}"),
+            }
+            .unwrap();
+
+            offset = next;
+        }
+    }
+
+    /// What an instruction's operands refer to, for a disassembly.
+    fn describe_operands(
+        &self,
+        function: &CompiledFunction,
+        opcode: Opcode,
+        operands: &[usize],
+    ) -> Option<String> {
+        let constant = |index: usize| match self.constants.get(index) {
+            Some(constant) => constant.to_string(),
+            None => "<out of range>".to_string(),
+        };
+        let name = |names: &[Symbol], index: usize| match names.get(index) {
+            Some(name) => name.to_string(),
+            None => "<out of range>".to_string(),
+        };
+
+        match opcode {
+            Opcode::Constant | Opcode::Closure => Some(constant(operands[0])),
+            Opcode::CallMethod => Some(format!(
+                "{}, {} arguments",
+                constant(operands[0]),
+                operands[1]
+            )),
+            Opcode::GetGlobal | Opcode::SetGlobal | Opcode::DefineGlobal => {
+                Some(name(&self.globals, operands[0]))
+            }
+            Opcode::GetBuiltin => Some(name(&self.builtins, operands[0])),
+            // Only parameters keep their names.
+            Opcode::GetLocal | Opcode::SetLocal | Opcode::DefineLocal => {
+                function.parameters.get(operands[0]).map(Symbol::to_string)
+            }
+            Opcode::Jump
+            | Opcode::JumpIfFalse
+            | Opcode::JumpIfNull
+            | Opcode::JumpIfNotNull
+            | Opcode::IterNext => Some(format!("-> {:04}", operands[0])),
+            _ => None,
+        }
+    }
+}
+
+/// Encodes the parts of [`Bytecode`]. Counts and indexes are four bytes
+/// and everything is big-endian.
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend(value.to_be_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend(value.to_be_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u32(u32::try_from(len).expect("fits in the format"));
+    }
+
+    fn str(&mut self, value: &str) {
+        self.len(value.len());
+        self.0.extend(value.as_bytes());
+    }
+
+    fn symbols(&mut self, symbols: &[Symbol]) {
+        self.len(symbols.len());
+        for symbol in symbols {
+            self.str(symbol.as_str());
+        }
+    }
+
+    fn constant(&mut self, constant: &Constant) {
+        match constant {
+            Constant::Int(value) => {
+                self.u8(0);
+                self.0.extend(value.to_be_bytes());
+            }
+            Constant::Float(value) => {
+                self.u8(1);
+                self.0.extend(value.to_bits().to_be_bytes());
+            }
+            Constant::Str(value) => {
+                self.u8(2);
+                self.str(value);
+            }
+            Constant::Char(value) => {
+                self.u8(3);
+                self.u32(u32::from(*value));
+            }
+            Constant::Name(name) => {
+                self.u8(4);
+                self.str(name.as_str());
+            }
+            Constant::Function(function) => {
+                self.u8(5);
+                self.function(function);
+            }
+        }
+    }
+
+    fn function(&mut self, function: &CompiledFunction) {
+        match &function.name {
+            Some(name) => {
+                self.u8(1);
+                self.str(name);
+            }
+            None => self.u8(0),
+        }
+        self.symbols(&function.parameters);
+        self.len(function.locals);
+        self.len(function.captures.len());
+        for capture in &function.captures {
+            let (tag, index) = match capture {
+                Capture::Local(slot) => (0, slot),
+                Capture::Free(index) => (1, index),
+            };
+            self.u8(tag);
+            self.len(*index);
+        }
+        self.len(function.code.len());
+        self.0.extend(&function.code);
+        self.len(function.spans.len());
+        for (offset, span) in &function.spans {
+            self.len(*offset);
+            self.len(span.line);
+            self.len(span.column);
+        }
+    }
+}
+
+/// Decodes what [`Writer`] encodes.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + count)
+            .ok_or("the bytecode file is cut short")?;
+        self.offset += count;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    fn len(&mut self) -> Result<usize, String> {
+        Ok(u32::from_be_bytes(self.array()?) as usize)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.len()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| "the bytecode file has a malformed string".into())
+    }
+
+    fn symbols(&mut self) -> Result<Vec<Symbol>, String> {
+        let count = self.len()?;
+        (0..count)
+            .map(|_| Ok(Symbol::from(self.string()?.as_str())))
+            .collect()
+    }
+
+    fn constant(&mut self) -> Result<Constant, String> {
+        Ok(match self.u8()? {
+            0 => Constant::Int(i64::from_be_bytes(self.array()?)),
+            1 => Constant::Float(f64::from_bits(u64::from_be_bytes(self.array()?))),
+            2 => Constant::Str(self.string()?),
+            3 => Constant::Char(
+                char::from_u32(self.len()? as u32)
+                    .ok_or("the bytecode file has a malformed char")?,
+            ),
+            4 => Constant::Name(Symbol::from(self.string()?.as_str())),
+            5 => Constant::Function(Rc::new(self.function()?)),
+            tag => {
+                return Err(format!(
+                    "the bytecode file has an unknown constant tag {tag}"
+                ))
+            }
+        })
+    }
+
+    fn function(&mut self) -> Result<CompiledFunction, String> {
+        let name = match self.u8()? {
+            0 => None,
+            _ => Some(self.string()?),
+        };
+        let parameters = self.symbols()?;
+        let locals = self.len()?;
+        let count = self.len()?;
+        let mut captures = Vec::new();
+        for _ in 0..count {
+            captures.push(match self.u8()? {
+                0 => Capture::Local(self.len()?),
+                _ => Capture::Free(self.len()?),
+            });
+        }
+        let len = self.len()?;
+        let code = self.take(len)?.to_vec();
+        let count = self.len()?;
+        let mut spans = Vec::new();
+        for _ in 0..count {
+            let offset = self.len()?;
+            let line = self.len()?;
+            let column = self.len()?;
+            spans.push((offset, Span { line, column }));
+        }
+
+        Ok(CompiledFunction {
+            name,
+            parameters,
+            locals,
+            captures,
+            code,
+            spans,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use super::{
+        make, read_operands, Bytecode, Capture, CompiledFunction, Constant, Opcode, FORMAT_VERSION,
+    };
+    use crate::token::Span;
+
+    fn function(code: Vec<Vec<u8>>) -> CompiledFunction {
+        CompiledFunction {
+            name: None,
+            parameters: Vec::new(),
+            locals: 0,
+            captures: Vec::new(),
+            code: code.concat(),
+            spans: Vec::new(),
+        }
+    }
+
+    /// A program that makes a closure over a local and returns it.
+    fn bytecode() -> Bytecode {
+        let inner = CompiledFunction {
+            name: Some("inner".to_string()),
+            parameters: vec!["x".into()],
+            locals: 1,
+            captures: vec![Capture::Free(0)],
+            ..function(vec![make(Opcode::GetFree, &[0]), make(Opcode::Return, &[])])
+        };
+        let outer = CompiledFunction {
+            name: Some("outer".to_string()),
+            locals: 1,
+            captures: vec![Capture::Local(0)],
+            spans: vec![(0, Span { line: 2, column: 5 })],
+            ..function(vec![
+                make(Opcode::Constant, &[1]),
+                make(Opcode::Closure, &[0]),
+                make(Opcode::Return, &[]),
+            ])
+        };
+        let main = CompiledFunction {
+            locals: 1,
+            ..function(vec![
+                make(Opcode::Constant, &[2]),
+                make(Opcode::DefineLocal, &[0]),
+                make(Opcode::Closure, &[3]),
+                make(Opcode::DefineGlobal, &[0]),
+                make(Opcode::GetBuiltin, &[0]),
+                make(Opcode::Pop, &[]),
+                make(Opcode::Jump, &[0]),
+            ])
+        };
+
+        Bytecode {
+            main: Rc::new(main),
+            constants: vec![
+                Constant::Function(Rc::new(inner)),
+                Constant::Float(1.5),
+                Constant::Str("tofu".to_string()),
+                Constant::Function(Rc::new(outer)),
+                Constant::Int(-7),
+                Constant::Char('é'),
+                Constant::Name("len".into()),
+            ],
+            globals: vec!["f".into()],
+            builtins: vec!["print".into()],
+        }
+    }
+
+    #[test]
+    fn test_opcodes_round_trip() {
+        for (byte, opcode) in Opcode::ALL.iter().enumerate() {
+            assert_eq!(byte, *opcode as usize);
+            assert_eq!(Some(*opcode), Opcode::from_byte(byte as u8));
+        }
+        assert_eq!(None, Opcode::from_byte(Opcode::ALL.len() as u8));
+    }
+
+    #[test]
+    fn test_make_and_read() {
+        let tests = vec![
+            (Opcode::Constant, vec![65534], vec![0, 255, 254]),
+            (Opcode::Add, vec![], vec![6]),
+            (Opcode::Call, vec![255], vec![44, 255]),
+            (Opcode::TailCall, vec![3], vec![45, 3]),
+            (Opcode::CallMethod, vec![258, 3], vec![46, 1, 2, 3]),
+        ];
+
+        for (opcode, operands, expected) in tests {
+            let instruction = make(opcode, &operands);
+            assert_eq!(expected, instruction, "{opcode}");
+
+            let (read, next) = read_operands(opcode, &instruction, 0);
+            assert_eq!(operands, read, "{opcode}");
+            assert_eq!(instruction.len(), next, "{opcode}");
+        }
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let mut bytecode = bytecode();
+        bytecode.constants[2] = Constant::Str("tofu ".repeat(10));
+
+        let expected = r#"== main ==
+locals: 1
+0000  Constant 2           ; "tofu tofu tofu tofu tofu tofu to"...
+0003  DefineLocal 0
+0006  Closure 3            ; <fn outer>
+0009  DefineGlobal 0       ; f
+0012  GetBuiltin 0         ; print
+0015  Pop
+0016  Jump 0               ; -> 0000
+
+== inner (constant 0) ==
+parameters: x
+locals: 1
+captures: free 0
+0000  GetFree 0
+0003  Return
+
+== outer (constant 3) ==
+locals: 1
+captures: local 0
+0000  Constant 1           ; 1.5
+0003  Closure 0            ; <fn inner>
+0006  Return
+"#;
+        assert_eq!(expected, bytecode.disassemble());
+
+        let constants = [
+            (Constant::Int(-7), "-7"),
+            (Constant::Float(2.0), "2.0"),
+            (Constant::Char('\n'), r"'\n'"),
+            (Constant::Name("len".into()), ".len"),
+            (Constant::Str("\"hi\"".to_string()), r#""\"hi\"""#),
+        ];
+        for (constant, expected) in constants {
+            assert_eq!(expected, constant.to_string());
+        }
+    }
+
+    #[test]
+    fn test_encode_and_decode() {
+        let bytecode = bytecode();
+        let bytes = bytecode.encode();
+
+        assert_eq!(b"TOFU", &bytes[..4]);
+        assert_eq!(FORMAT_VERSION.to_be_bytes(), bytes[4..6]);
+        assert_eq!(Ok(bytecode), Bytecode::decode(&bytes));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let bytes = bytecode().encode();
+        let with_main = |code: Vec<Vec<u8>>| {
+            Bytecode {
+                main: Rc::new(CompiledFunction {
+                    locals: 1,
+                    ..function(code)
+                }),
+                ..bytecode()
+            }
+            .encode()
+        };
+        let mut future = bytes.clone();
+        future[5] += 1;
+        let mut extra = bytes.clone();
+        extra.push(0);
+
+        let tests =
+            vec![
+            (b"#!/bin/sh".to_vec(), "not a Tofu bytecode file".to_string()),
+            (
+                future,
+                format!(
+                    "the file is bytecode version {}, but this interpreter runs version \
+                     {FORMAT_VERSION}; build it again from its source",
+                    FORMAT_VERSION + 1
+                ),
+            ),
+            (
+                bytes[..bytes.len() - 1].to_vec(),
+                "the bytecode file is cut short".to_string(),
+            ),
+            (
+                extra,
+                "the bytecode file has extra bytes at the end".to_string(),
+            ),
+            (
+                with_main(vec![vec![Opcode::ALL.len() as u8]]),
+                format!(
+                    "invalid bytecode at offset 0: unknown opcode {}",
+                    Opcode::ALL.len()
+                ),
+            ),
+            (
+                with_main(vec![make(Opcode::Null, &[]), vec![Opcode::Jump as u8, 0]]),
+                "invalid bytecode at offset 1: Jump is cut short".to_string(),
+            ),
+            (
+                with_main(vec![make(Opcode::Constant, &[7]), make(Opcode::Return, &[])]),
+                "invalid bytecode at offset 0: Constant 7 is out of range".to_string(),
+            ),
+            (
+                with_main(vec![make(Opcode::GetLocal, &[1]), make(Opcode::Return, &[])]),
+                "invalid bytecode at offset 0: GetLocal 1 is out of range".to_string(),
+            ),
+            (
+                with_main(vec![make(Opcode::Constant, &[0]), make(Opcode::Return, &[])]),
+                "invalid bytecode at offset 0: Constant refers to the wrong sort of constant"
+                    .to_string(),
+            ),
+            (
+                with_main(vec![make(Opcode::CallMethod, &[4, 0]), make(Opcode::Return, &[])]),
+                "invalid bytecode at offset 0: CallMethod refers to the wrong sort of constant"
+                    .to_string(),
+            ),
+            (
+                with_main(vec![make(Opcode::Closure, &[0]), make(Opcode::Return, &[])]),
+                "invalid bytecode at offset 0: a function captures a variable that doesn't exist"
+                    .to_string(),
+            ),
+            (
+                with_main(vec![make(Opcode::Jump, &[1])]),
+                "invalid bytecode at offset 0: it jumps to 1, which isn't an instruction"
+                    .to_string(),
+            ),
+            (
+                with_main(vec![make(Opcode::Null, &[])]),
+                "invalid bytecode: a function doesn't end with a return or a jump".to_string(),
+            ),
+            (
+                with_main(vec![
+                    make(Opcode::Null, &[]),
+                    make(Opcode::Pop, &[]),
+                    make(Opcode::Pop, &[]),
+                    make(Opcode::Pop, &[]),
+                    make(Opcode::Return, &[]),
+                ]),
+                "invalid bytecode at offset 2: Pop takes more values than the stack has"
+                    .to_string(),
+            ),
+            (
+                with_main(vec![
+                    make(Opcode::True, &[]),
+                    make(Opcode::JumpIfFalse, &[5]),
+                    make(Opcode::Null, &[]),
+                    make(Opcode::Return, &[]),
+                ]),
+                "invalid bytecode at offset 5: the stack isn't the same size every way the \
+                 code gets here"
+                    .to_string(),
+            ),
+            (
+                with_main(vec![make(Opcode::IterNext, &[0]), make(Opcode::Return, &[])]),
+                "invalid bytecode at offset 0: IterNext isn't in a loop".to_string(),
+            ),
+        ];
+
+        for (bytes, expected) in tests {
+            assert_eq!(Err(expected), Bytecode::decode(&bytes));
+        }
+    }
+}
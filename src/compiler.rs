@@ -0,0 +1,1209 @@
+//! Lowers the AST to [`Bytecode`], the first half of running programs on a
+//! stack machine instead of walking the tree. Every expression compiles to
+//! code that leaves its value on the stack, and every statement to code
+//! that leaves the stack as it found it. Names are resolved to slots as
+//! they're compiled, with a [`SymbolTable`]. Parts of the language the compiler
+//! doesn't handle yet are reported as a [`CompileError`] rather than
+//! compiled wrongly.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::rc::Rc;
+
+use crate::ast::{
+    with_stack, BlockStatement, DeclarationKind, Expression, InfixOperator, LetStatement,
+    Parameters, Pattern, PrefixOperator, Program, Statement,
+};
+use crate::builtins::Builtins;
+use crate::code::{
+    make, read_operands, Bytecode, Capture, CompiledFunction, Constant, Opcode, RANGE_OPEN_END,
+    RANGE_OPEN_START,
+};
+use crate::symbol::Symbol;
+use crate::symbol_table::{Binding, Scope, SymbolTable};
+use crate::token::Span;
+
+/// Why a program couldn't be compiled.
+#[derive(PartialEq, Debug, Clone)]
+pub struct CompileError {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl CompileError {
+    fn new(message: impl Into<String>, span: Option<Span>) -> CompileError {
+        CompileError {
+            message: message.into(),
+            span,
+        }
+    }
+
+    fn unsupported(what: &str, span: Option<Span>) -> CompileError {
+        CompileError::new(format!("the compiler doesn't support {what} yet"), span)
+    }
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{span}: {}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+type Compile<T = ()> = Result<T, CompileError>;
+
+/// Compiles a whole program that can use `builtins`. Its code returns the
+/// value of its last statement, or the value passed to a top-level
+/// `return`.
+pub fn compile(program: &Program, builtins: &Builtins) -> Result<Bytecode, CompileError> {
+    let mut compiler = Compiler {
+        constants: Vec::new(),
+        names: HashMap::new(),
+        units: vec![Unit::default()],
+        symbols: SymbolTable::new(builtins.names()),
+    };
+
+    compiler.compile_statements(&program.statements)?;
+    compiler.emit(Opcode::Return, &[])?;
+
+    let unit = compiler.units.pop().expect("there is always a unit");
+    let main = CompiledFunction {
+        name: None,
+        parameters: Vec::new(),
+        locals: compiler.symbols.top_level_slots(),
+        captures: Vec::new(),
+        code: unit.code,
+        spans: unit.spans,
+    };
+    Ok(Bytecode {
+        main: Rc::new(main),
+        constants: compiler.constants,
+        globals: compiler.symbols.globals().to_vec(),
+        builtins: compiler.symbols.builtins().to_vec(),
+    })
+}
+
+/// A loop being compiled, for the `break`s and `continue`s in it.
+struct Loop {
+    /// Where `continue` jumps to.
+    start: usize,
+    /// The `break` jumps, to be pointed past the loop once it's done.
+    breaks: Vec<usize>,
+    /// Whether the loop has an iterator, which `break` has to drop.
+    iterates: bool,
+}
+
+/// The code of the function being compiled, which is the program itself
+/// at the top level.
+#[derive(Default)]
+struct Unit {
+    code: Vec<u8>,
+    spans: Vec<(usize, Span)>,
+    loops: Vec<Loop>,
+}
+
+struct Compiler {
+    constants: Vec<Constant>,
+    /// The constant index of each method name used so far, so a name is
+    /// only in the pool once.
+    names: HashMap<Symbol, usize>,
+    /// The function being compiled last, with the ones it's nested in
+    /// before it.
+    units: Vec<Unit>,
+    symbols: SymbolTable,
+}
+
+impl Compiler {
+    fn unit(&mut self) -> &mut Unit {
+        self.units.last_mut().expect("there is always a unit")
+    }
+
+    /// Appends an instruction and returns its offset.
+    fn emit(&mut self, opcode: Opcode, operands: &[usize]) -> Compile<usize> {
+        for (operand, width) in operands.iter().zip(opcode.operand_widths()) {
+            if *operand >> (8 * width) != 0 {
+                return Err(CompileError::new(
+                    format!("too much to compile: {opcode} can't take {operand}"),
+                    None,
+                ));
+            }
+        }
+
+        let unit = self.unit();
+        let offset = unit.code.len();
+        unit.code.extend(make(opcode, operands));
+        Ok(offset)
+    }
+
+    /// Like [`Compiler::emit`], recording `span` for errors the instruction
+    /// raises.
+    fn emit_at(&mut self, opcode: Opcode, operands: &[usize], span: Span) -> Compile<usize> {
+        let offset = self.emit(opcode, operands)?;
+        self.unit().spans.push((offset, span));
+        Ok(offset)
+    }
+
+    /// Emits a jump whose target isn't known yet, to be set with
+    /// [`Compiler::patch_jump`].
+    fn emit_jump(&mut self, opcode: Opcode) -> Compile<usize> {
+        self.emit(opcode, &[0])
+    }
+
+    /// Points the jump at `offset` to the end of the code so far.
+    fn patch_jump(&mut self, offset: usize) -> Compile {
+        let target = self.unit().code.len();
+        if target > usize::from(u16::MAX) {
+            return Err(CompileError::new(
+                "too much to compile: a jump is too far",
+                None,
+            ));
+        }
+
+        let code = &mut self.unit().code;
+        code[offset + 1..offset + 3].copy_from_slice(&(target as u16).to_be_bytes());
+        Ok(())
+    }
+
+    fn add_constant(&mut self, constant: Constant) -> usize {
+        self.constants.push(constant);
+        self.constants.len() - 1
+    }
+
+    fn name(&mut self, name: &Symbol) -> usize {
+        if let Some(index) = self.names.get(name) {
+            return *index;
+        }
+        let index = self.add_constant(Constant::Name(name.clone()));
+        self.names.insert(name.clone(), index);
+        index
+    }
+
+    /// Compiles statements so the value of the last one is left on the
+    /// stack, which is `null` unless it's an expression.
+    fn compile_statements(&mut self, statements: &[Statement]) -> Compile {
+        match statements.split_last() {
+            Some((Statement::Expression(last), rest)) => {
+                for statement in rest {
+                    self.compile_statement(statement)?;
+                }
+                self.compile_expression(last)
+            }
+            _ => {
+                for statement in statements {
+                    self.compile_statement(statement)?;
+                }
+                self.emit(Opcode::Null, &[]).map(drop)
+            }
+        }
+    }
+
+    /// Compiles a block in a scope of its own, leaving its value.
+    fn compile_block(&mut self, block: &BlockStatement) -> Compile {
+        self.symbols.enter_block();
+        let result = self
+            .declare_local_functions(&block.statements)
+            .and_then(|()| self.compile_statements(&block.statements));
+        self.symbols.leave_block();
+        result
+    }
+
+    /// Declares the functions a nested block's `let`s make before any of
+    /// them is made, so each can capture the others, and itself, to call
+    /// them, as in the evaluator, where they're all in the block's
+    /// environment by the time any is called. A global doesn't need to be,
+    /// as functions use globals directly.
+    fn declare_local_functions(&mut self, statements: &[Statement]) -> Compile {
+        if self.symbols.at_top_level() {
+            return Ok(());
+        }
+        for statement in statements {
+            if let Statement::Let(LetStatement {
+                pattern: Pattern::Identifier(name),
+                value: Expression::Function { .. },
+                ..
+            }) = statement
+            {
+                if !self.symbols.declared_in_block(name) {
+                    self.emit(Opcode::Null, &[])?;
+                    self.compile_declaration(name, DeclarationKind::Let, None)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles a loop's body in a scope of its own, which the loop's
+    /// variable, if any, is declared in first with the value on the stack.
+    fn compile_loop_body(&mut self, variable: Option<&Pattern>, body: &BlockStatement) -> Compile {
+        self.symbols.enter_block();
+        let result = (|| {
+            match variable {
+                Some(Pattern::Identifier(name)) => {
+                    self.compile_declaration(name, DeclarationKind::Let, None)?
+                }
+                // `_` binds nothing, but the item is still on the stack.
+                Some(_) => drop(self.emit(Opcode::Pop, &[])?),
+                None => {}
+            }
+            self.declare_local_functions(&body.statements)?;
+            for statement in &body.statements {
+                self.compile_statement(statement)?;
+            }
+            Ok(())
+        })();
+        self.symbols.leave_block();
+        result
+    }
+
+    /// Declares `name` in the innermost block with the value on the stack.
+    fn compile_declaration(
+        &mut self,
+        name: &Symbol,
+        kind: DeclarationKind,
+        span: Option<Span>,
+    ) -> Compile {
+        let redeclared = self.symbols.declared_in_block(name);
+        let binding = self
+            .symbols
+            .define(name, kind)
+            .map_err(|message| CompileError::new(message, span))?;
+
+        match binding.scope {
+            Scope::Global => drop(self.emit(Opcode::DefineGlobal, &[binding.index])?),
+            // A local declared again in the same block is the same
+            // variable, as far as the functions that captured it can tell.
+            Scope::Local if redeclared => {
+                self.emit(Opcode::SetLocal, &[binding.index])?;
+                self.emit(Opcode::Pop, &[])?;
+            }
+            Scope::Local => drop(self.emit(Opcode::DefineLocal, &[binding.index])?),
+            Scope::Free | Scope::Builtin => unreachable!("declarations are global or local"),
+        }
+        Ok(())
+    }
+
+    /// Pushes the value of the variable `binding` refers to.
+    fn compile_load(&mut self, binding: &Binding, span: Span) -> Compile {
+        let opcode = match binding.scope {
+            Scope::Global => Opcode::GetGlobal,
+            Scope::Local => Opcode::GetLocal,
+            Scope::Free => Opcode::GetFree,
+            Scope::Builtin => Opcode::GetBuiltin,
+        };
+        self.emit_at(opcode, &[binding.index], span).map(drop)
+    }
+
+    /// Stores the value on the stack in the variable `binding` refers to,
+    /// leaving it in place.
+    fn compile_store(&mut self, binding: &Binding, span: Span) -> Compile {
+        let opcode = match (binding.scope, binding.kind) {
+            (_, Some(DeclarationKind::Const)) => {
+                return Err(CompileError::new(
+                    format!("cannot assign to constant `{}`", binding.name),
+                    Some(span),
+                ))
+            }
+            (Scope::Builtin, _) => {
+                return Err(CompileError::new(
+                    format!("cannot assign to undeclared variable `{}`", binding.name),
+                    Some(span),
+                ))
+            }
+            (Scope::Global, _) => Opcode::SetGlobal,
+            (Scope::Local, _) => Opcode::SetLocal,
+            (Scope::Free, _) => Opcode::SetFree,
+        };
+        self.emit_at(opcode, &[binding.index], span).map(drop)
+    }
+
+    fn compile_statement(&mut self, statement: &Statement) -> Compile {
+        match statement {
+            Statement::Let(statement) => {
+                let name = match &statement.pattern {
+                    Pattern::Identifier(name) => name,
+                    Pattern::Wildcard => {
+                        self.compile_expression(&statement.value)?;
+                        return self.emit(Opcode::Pop, &[]).map(drop);
+                    }
+                    _ => return Err(CompileError::unsupported("destructuring", None)),
+                };
+                match &statement.value {
+                    Expression::Function {
+                        parameters, body, ..
+                    } => {
+                        self.compile_function(Some(name), parameters, body)?;
+                        self.compile_declaration(name, statement.kind, Some(statement.span))?;
+                    }
+                    value => {
+                        self.compile_expression(value)?;
+                        self.compile_declaration(name, statement.kind, Some(statement.span))?;
+                    }
+                }
+            }
+            Statement::Return(value) => {
+                match value {
+                    Some(value) => self.compile_expression(value)?,
+                    None => drop(self.emit(Opcode::Null, &[])?),
+                }
+                self.emit(Opcode::Return, &[])?;
+            }
+            Statement::Expression(expression) => {
+                self.compile_expression(expression)?;
+                self.emit(Opcode::Pop, &[])?;
+            }
+            Statement::While { condition, body } => {
+                let start = self.unit().code.len();
+                self.compile_expression(condition)?;
+                let exit = self.emit_jump(Opcode::JumpIfFalse)?;
+
+                self.compile_loop(start, false, |this| this.compile_loop_body(None, body))?;
+                self.patch_jump(exit)?;
+            }
+            Statement::For {
+                pattern,
+                iterable,
+                body,
+            } => {
+                if !matches!(pattern, Pattern::Identifier(_) | Pattern::Wildcard) {
+                    return Err(CompileError::unsupported("destructuring", None));
+                }
+                self.compile_expression(iterable)?;
+                self.emit(Opcode::GetIter, &[])?;
+                let start = self.unit().code.len();
+                let exit = self.emit_jump(Opcode::IterNext)?;
+
+                self.compile_loop(start, true, |this| {
+                    this.compile_loop_body(Some(pattern), body)
+                })?;
+                self.patch_jump(exit)?;
+            }
+            Statement::Break | Statement::Continue => {
+                let unit = self.unit();
+                let Some(innermost) = unit.loops.last() else {
+                    let keyword = if *statement == Statement::Break {
+                        "break"
+                    } else {
+                        "continue"
+                    };
+                    return Err(CompileError::new(
+                        format!("`{keyword}` outside of a loop"),
+                        None,
+                    ));
+                };
+                let (start, iterates) = (innermost.start, innermost.iterates);
+
+                if *statement == Statement::Continue {
+                    self.emit(Opcode::Jump, &[start])?;
+                } else {
+                    if iterates {
+                        self.emit(Opcode::PopIter, &[])?;
+                    }
+                    let jump = self.emit_jump(Opcode::Jump)?;
+                    let innermost = self.unit().loops.last_mut().expect("checked above");
+                    innermost.breaks.push(jump);
+                }
+            }
+            Statement::Import(import) => {
+                return Err(CompileError::unsupported("`import`", Some(import.span)))
+            }
+            Statement::Throw { span, .. } => {
+                return Err(CompileError::unsupported("`throw`", Some(*span)))
+            }
+            Statement::Struct { span, .. } => {
+                return Err(CompileError::unsupported("`struct`", Some(*span)))
+            }
+            Statement::Enum { span, .. } => {
+                return Err(CompileError::unsupported("`enum`", Some(*span)))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compiles a loop's body with `body`, followed by the jump back to
+    /// `start`, and points its `break`s past it.
+    fn compile_loop(
+        &mut self,
+        start: usize,
+        iterates: bool,
+        body: impl FnOnce(&mut Compiler) -> Compile,
+    ) -> Compile {
+        self.unit().loops.push(Loop {
+            start,
+            breaks: Vec::new(),
+            iterates,
+        });
+
+        let result = body(self).and_then(|()| self.emit(Opcode::Jump, &[start]));
+        let innermost = self.unit().loops.pop().expect("pushed above");
+        result?;
+
+        // A `break` leaves the loop where its condition or iterator would.
+        let end = self.unit().code.len();
+        for jump in innermost.breaks {
+            self.unit().code[jump + 1..jump + 3].copy_from_slice(&(end as u16).to_be_bytes());
+        }
+        Ok(())
+    }
+
+    fn compile_expression(&mut self, expression: &Expression) -> Compile {
+        with_stack(|| self.compile_expression_node(expression))
+    }
+
+    fn compile_expression_node(&mut self, expression: &Expression) -> Compile {
+        match expression {
+            Expression::Identifier { name, span, .. } => {
+                let binding = self.symbols.resolve(name);
+                self.compile_load(&binding, *span)?;
+            }
+            Expression::Int(value) => self.compile_constant(Constant::Int(*value))?,
+            Expression::Float(value) => self.compile_constant(Constant::Float(*value))?,
+            Expression::Str(value) => self.compile_constant(Constant::Str(value.clone()))?,
+            Expression::Char(value) => self.compile_constant(Constant::Char(*value))?,
+            Expression::Bool(true) => drop(self.emit(Opcode::True, &[])?),
+            Expression::Bool(false) => drop(self.emit(Opcode::False, &[])?),
+            Expression::Null => drop(self.emit(Opcode::Null, &[])?),
+            Expression::Prefix {
+                operator,
+                right,
+                span,
+            } => {
+                self.compile_expression(right)?;
+                let opcode = match operator {
+                    PrefixOperator::Minus => Opcode::Minus,
+                    PrefixOperator::Bang => Opcode::Not,
+                };
+                self.emit_at(opcode, &[], *span)?;
+            }
+            Expression::Infix {
+                left,
+                operator: InfixOperator::And,
+                right,
+                ..
+            } => {
+                self.compile_expression(left)?;
+                let short_circuit = self.emit_jump(Opcode::JumpIfFalse)?;
+                self.compile_expression(right)?;
+                self.emit(Opcode::Truthy, &[])?;
+                let end = self.emit_jump(Opcode::Jump)?;
+                self.patch_jump(short_circuit)?;
+                self.emit(Opcode::False, &[])?;
+                self.patch_jump(end)?;
+            }
+            Expression::Infix {
+                left,
+                operator: InfixOperator::Or,
+                right,
+                ..
+            } => {
+                self.compile_expression(left)?;
+                let otherwise = self.emit_jump(Opcode::JumpIfFalse)?;
+                self.emit(Opcode::True, &[])?;
+                let end = self.emit_jump(Opcode::Jump)?;
+                self.patch_jump(otherwise)?;
+                self.compile_expression(right)?;
+                self.emit(Opcode::Truthy, &[])?;
+                self.patch_jump(end)?;
+            }
+            Expression::Infix {
+                left,
+                operator: InfixOperator::Coalesce,
+                right,
+                ..
+            } => {
+                self.compile_expression(left)?;
+                let end = self.emit_jump(Opcode::JumpIfNotNull)?;
+                self.compile_expression(right)?;
+                self.patch_jump(end)?;
+            }
+            Expression::Infix {
+                left,
+                operator,
+                right,
+                span,
+            } => {
+                self.compile_expression(left)?;
+                self.compile_expression(right)?;
+                self.emit_at(infix_opcode(*operator), &[], *span)?;
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.compile_expression(condition)?;
+                let otherwise = self.emit_jump(Opcode::JumpIfFalse)?;
+                self.compile_block(consequence)?;
+                let end = self.emit_jump(Opcode::Jump)?;
+                self.patch_jump(otherwise)?;
+                match alternative {
+                    Some(alternative) => self.compile_block(alternative)?,
+                    None => drop(self.emit(Opcode::Null, &[])?),
+                }
+                self.patch_jump(end)?;
+            }
+            Expression::Conditional {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.compile_expression(condition)?;
+                let otherwise = self.emit_jump(Opcode::JumpIfFalse)?;
+                self.compile_expression(consequence)?;
+                let end = self.emit_jump(Opcode::Jump)?;
+                self.patch_jump(otherwise)?;
+                self.compile_expression(alternative)?;
+                self.patch_jump(end)?;
+            }
+            Expression::Function {
+                parameters, body, ..
+            } => self.compile_function(None, parameters, body)?,
+            Expression::Call {
+                function,
+                arguments,
+                span,
+            } => {
+                self.compile_expression(function)?;
+                let count = self.compile_arguments(arguments)?;
+                self.emit_at(Opcode::Call, &[count], *span)?;
+            }
+            Expression::MethodCall {
+                receiver,
+                method,
+                arguments,
+                span,
+            } => {
+                self.compile_expression(receiver)?;
+                let count = self.compile_arguments(arguments)?;
+                let method = self.name(method);
+                self.emit_at(Opcode::CallMethod, &[method, count], *span)?;
+            }
+            Expression::Array(elements) => {
+                let count = self.compile_arguments(elements)?;
+                self.emit(Opcode::Array, &[count])?;
+            }
+            Expression::Hash(pairs) => {
+                for (key, value) in pairs {
+                    self.compile_expression(key)?;
+                    self.compile_expression(value)?;
+                }
+                self.emit(Opcode::Hash, &[pairs.len()])?;
+            }
+            Expression::Index {
+                left,
+                index,
+                optional,
+                span,
+            } => {
+                self.compile_expression(left)?;
+                let null = if *optional {
+                    Some(self.emit_jump(Opcode::JumpIfNull)?)
+                } else {
+                    None
+                };
+                self.compile_expression(index)?;
+                self.emit_at(Opcode::Index, &[], *span)?;
+                if let Some(null) = null {
+                    self.patch_jump(null)?;
+                }
+            }
+            Expression::Assign {
+                target,
+                operator,
+                value,
+                span,
+            } => match &**target {
+                Expression::Identifier { name, .. } => {
+                    let binding = self.symbols.resolve(name);
+                    if let Some(operator) = operator {
+                        self.compile_load(&binding, *span)?;
+                        self.compile_expression(value)?;
+                        self.emit_at(infix_opcode(*operator), &[], *span)?;
+                    } else {
+                        self.compile_expression(value)?;
+                    }
+                    self.compile_store(&binding, *span)?;
+                }
+                Expression::Index { left, index, .. } => {
+                    self.compile_expression(left)?;
+                    self.compile_expression(index)?;
+                    if let Some(operator) = operator {
+                        self.emit(Opcode::Dup2, &[])?;
+                        self.emit_at(Opcode::Index, &[], *span)?;
+                        self.compile_expression(value)?;
+                        self.emit_at(infix_opcode(*operator), &[], *span)?;
+                    } else {
+                        self.compile_expression(value)?;
+                    }
+                    self.emit_at(Opcode::SetIndex, &[], *span)?;
+                }
+                _ => unreachable!("the parser only allows names and indexes as targets"),
+            },
+            Expression::Range {
+                start,
+                end,
+                inclusive,
+                span,
+            } => {
+                // As the evaluator fills them in.
+                match start {
+                    Some(start) => self.compile_expression(start)?,
+                    None => self.compile_constant(Constant::Int(0))?,
+                }
+                match end {
+                    Some(end) => self.compile_expression(end)?,
+                    None => self.compile_constant(Constant::Int(-1))?,
+                }
+                let opcode = if *inclusive || end.is_none() {
+                    Opcode::RangeInclusive
+                } else {
+                    Opcode::Range
+                };
+                let mut open = 0;
+                if start.is_none() {
+                    open |= RANGE_OPEN_START;
+                }
+                if end.is_none() {
+                    open |= RANGE_OPEN_END;
+                }
+                self.emit_at(opcode, &[open], *span)?;
+            }
+            Expression::Try { .. } => return Err(CompileError::unsupported("`try`", None)),
+            Expression::Match { span, .. } => {
+                return Err(CompileError::unsupported("`match`", Some(*span)))
+            }
+            Expression::Tuple(_) => return Err(CompileError::unsupported("tuples", None)),
+            Expression::Spread(_) => return Err(CompileError::unsupported("`...`", None)),
+            Expression::Named { .. } => {
+                return Err(CompileError::unsupported("named arguments", None))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn compile_constant(&mut self, constant: Constant) -> Compile {
+        let index = self.add_constant(constant);
+        self.emit(Opcode::Constant, &[index]).map(drop)
+    }
+
+    /// Compiles a call's arguments or an array's elements, returning how
+    /// many there are.
+    fn compile_arguments(&mut self, arguments: &[Expression]) -> Compile<usize> {
+        for argument in arguments {
+            self.compile_expression(argument)?;
+        }
+        Ok(arguments.len())
+    }
+
+    fn compile_function(
+        &mut self,
+        name: Option<&Symbol>,
+        parameters: &Parameters,
+        body: &BlockStatement,
+    ) -> Compile {
+        if parameters.rest.is_some() {
+            return Err(CompileError::unsupported("rest parameters", None));
+        }
+        if parameters.required() < parameters.positional.len() {
+            return Err(CompileError::unsupported("default parameter values", None));
+        }
+
+        // The body gets a block of its own inside the one the parameters
+        // are declared in, like it does in the evaluator.
+        self.units.push(Unit::default());
+        self.symbols.enter_function();
+        let result = (|| {
+            for parameter in &parameters.positional {
+                self.symbols
+                    .define(&parameter.name, DeclarationKind::Let)
+                    .map_err(|message| CompileError::new(message, None))?;
+            }
+            self.compile_block(body)?;
+            self.emit(Opcode::Return, &[]).map(drop)
+        })();
+        let (locals, captures) = self.symbols.leave_function();
+        let mut unit = self.units.pop().expect("pushed above");
+        result?;
+        mark_tail_calls(&mut unit.code);
+
+        let function = CompiledFunction {
+            name: name.map(Symbol::to_string),
+            parameters: parameters
+                .positional
+                .iter()
+                .map(|parameter| parameter.name.clone())
+                .collect(),
+            locals,
+            captures: captures
+                .iter()
+                .map(|binding| match binding.scope {
+                    Scope::Local => Capture::Local(binding.index),
+                    Scope::Free => Capture::Free(binding.index),
+                    Scope::Global | Scope::Builtin => {
+                        unreachable!("only locals and free variables are captured")
+                    }
+                })
+                .collect(),
+            code: unit.code,
+            spans: unit.spans,
+        };
+
+        let index = self.add_constant(Constant::Function(Rc::new(function)));
+        self.emit(Opcode::Closure, &[index]).map(drop)
+    }
+}
+
+/// Turns each call in `code` whose value is returned straight away, as a
+/// function's last expression or a `return`'s value is, into a
+/// [`Opcode::TailCall`], following the jumps out of an `if` or
+/// conditional to the `Return` after it.
+fn mark_tail_calls(code: &mut [u8]) {
+    let opcode_at = |code: &[u8], offset: usize| {
+        Opcode::from_byte(code[offset]).expect("compiled code only has opcodes")
+    };
+
+    let mut offset = 0;
+    while offset < code.len() {
+        let opcode = opcode_at(code, offset);
+        let (_, next) = read_operands(opcode, code, offset);
+        if opcode == Opcode::Call {
+            let mut after = next;
+            while opcode_at(code, after) == Opcode::Jump {
+                after = read_operands(Opcode::Jump, code, after).0[0];
+            }
+            if opcode_at(code, after) == Opcode::Return {
+                code[offset] = Opcode::TailCall as u8;
+            }
+        }
+        offset = next;
+    }
+}
+
+fn infix_opcode(operator: InfixOperator) -> Opcode {
+    match operator {
+        InfixOperator::Plus => Opcode::Add,
+        InfixOperator::Minus => Opcode::Subtract,
+        InfixOperator::Multiply => Opcode::Multiply,
+        InfixOperator::Divide => Opcode::Divide,
+        InfixOperator::Power => Opcode::Power,
+        InfixOperator::Eq => Opcode::Equal,
+        InfixOperator::NotEq => Opcode::NotEqual,
+        InfixOperator::LessThan => Opcode::LessThan,
+        InfixOperator::GreaterThan => Opcode::GreaterThan,
+        InfixOperator::LessThanEq => Opcode::LessThanEqual,
+        InfixOperator::GreaterThanEq => Opcode::GreaterThanEqual,
+        InfixOperator::BitAnd => Opcode::BitAnd,
+        InfixOperator::BitOr => Opcode::BitOr,
+        InfixOperator::BitXor => Opcode::BitXor,
+        InfixOperator::ShiftLeft => Opcode::ShiftLeft,
+        InfixOperator::ShiftRight => Opcode::ShiftRight,
+        InfixOperator::And | InfixOperator::Or | InfixOperator::Coalesce => {
+            unreachable!("logical operators compile to jumps")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::compile;
+    use crate::builtins::Builtins;
+    use crate::code::{make, Bytecode, Capture, Opcode};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::symbol::Symbol;
+
+    fn compile_input(input: &str) -> Result<Bytecode, String> {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+
+        assert!(
+            parser.errors().is_empty(),
+            "parser had errors: {:?}",
+            parser.errors()
+        );
+
+        compile(&program, &Builtins::standard()).map_err(|error| error.to_string())
+    }
+
+    type Instruction = (Opcode, &'static [usize]);
+
+    fn instructions(instructions: &[Instruction]) -> Vec<u8> {
+        instructions
+            .iter()
+            .flat_map(|(opcode, operands)| make(*opcode, operands))
+            .collect()
+    }
+
+    fn function(bytecode: &Bytecode, index: usize) -> &crate::code::CompiledFunction {
+        match &bytecode.constants[index] {
+            crate::code::Constant::Function(function) => function,
+            constant => panic!("expected a function, got {constant:?}"),
+        }
+    }
+
+    #[test]
+    fn test_expressions() {
+        use Opcode::*;
+
+        let tests: Vec<(&str, Vec<Instruction>)> = vec![
+            ("", vec![(Null, &[]), (Return, &[])]),
+            (
+                "1 + 2",
+                vec![
+                    (Constant, &[0]),
+                    (Constant, &[1]),
+                    (Add, &[]),
+                    (Return, &[]),
+                ],
+            ),
+            (
+                "1; 2",
+                vec![
+                    (Constant, &[0]),
+                    (Pop, &[]),
+                    (Constant, &[1]),
+                    (Return, &[]),
+                ],
+            ),
+            ("-true", vec![(True, &[]), (Minus, &[]), (Return, &[])]),
+            (
+                "a && b",
+                vec![
+                    (GetGlobal, &[0]),
+                    (JumpIfFalse, &[13]),
+                    (GetGlobal, &[1]),
+                    (Truthy, &[]),
+                    (Jump, &[14]),
+                    (False, &[]),
+                    (Return, &[]),
+                ],
+            ),
+            (
+                "a || b",
+                vec![
+                    (GetGlobal, &[0]),
+                    (JumpIfFalse, &[10]),
+                    (True, &[]),
+                    (Jump, &[14]),
+                    (GetGlobal, &[1]),
+                    (Truthy, &[]),
+                    (Return, &[]),
+                ],
+            ),
+            (
+                "a ?? b",
+                vec![
+                    (GetGlobal, &[0]),
+                    (JumpIfNotNull, &[9]),
+                    (GetGlobal, &[1]),
+                    (Return, &[]),
+                ],
+            ),
+            (
+                "if (a) { 1 }",
+                vec![
+                    (GetGlobal, &[0]),
+                    (JumpIfFalse, &[12]),
+                    (Constant, &[0]),
+                    (Jump, &[13]),
+                    (Null, &[]),
+                    (Return, &[]),
+                ],
+            ),
+            (
+                "a?[0]",
+                vec![
+                    (GetGlobal, &[0]),
+                    (JumpIfNull, &[10]),
+                    (Constant, &[0]),
+                    (Index, &[]),
+                    (Return, &[]),
+                ],
+            ),
+            (
+                "[1, 2][0] += 3",
+                vec![
+                    (Constant, &[0]),
+                    (Constant, &[1]),
+                    (Array, &[2]),
+                    (Constant, &[2]),
+                    (Dup2, &[]),
+                    (Index, &[]),
+                    (Constant, &[3]),
+                    (Add, &[]),
+                    (SetIndex, &[]),
+                    (Return, &[]),
+                ],
+            ),
+            (
+                "x.push({1: 2}, 0..=1)",
+                vec![
+                    (GetGlobal, &[0]),
+                    (Constant, &[0]),
+                    (Constant, &[1]),
+                    (Hash, &[1]),
+                    (Constant, &[2]),
+                    (Constant, &[3]),
+                    (RangeInclusive, &[0]),
+                    (CallMethod, &[4, 2]),
+                    (Return, &[]),
+                ],
+            ),
+            (
+                "len([])",
+                vec![
+                    (GetBuiltin, &[0]),
+                    (Array, &[0]),
+                    (Call, &[1]),
+                    (Return, &[]),
+                ],
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let bytecode = compile_input(input).unwrap();
+            assert_eq!(instructions(&expected), bytecode.main.code, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_statements() {
+        use Opcode::*;
+
+        let tests: Vec<(&str, Vec<Instruction>)> = vec![
+            (
+                "let x = 1; x = x * 2;",
+                vec![
+                    (Constant, &[0]),
+                    (DefineGlobal, &[0]),
+                    (GetGlobal, &[0]),
+                    (Constant, &[1]),
+                    (Multiply, &[]),
+                    (SetGlobal, &[0]),
+                    (Return, &[]),
+                ],
+            ),
+            (
+                "while (a) { break; }",
+                vec![
+                    (GetGlobal, &[0]),
+                    (JumpIfFalse, &[12]),
+                    (Jump, &[12]),
+                    (Jump, &[0]),
+                    (Null, &[]),
+                    (Return, &[]),
+                ],
+            ),
+            (
+                "for (i in xs) { continue; }",
+                vec![
+                    (GetGlobal, &[0]),
+                    (GetIter, &[]),
+                    (IterNext, &[16]),
+                    (DefineLocal, &[0]),
+                    (Jump, &[4]),
+                    (Jump, &[4]),
+                    (Null, &[]),
+                    (Return, &[]),
+                ],
+            ),
+            (
+                "for (i in xs) { if (i) { break; } }",
+                vec![
+                    (GetGlobal, &[0]),
+                    (GetIter, &[]),
+                    (IterNext, &[29]),
+                    (DefineLocal, &[0]),
+                    (GetLocal, &[0]),
+                    (JumpIfFalse, &[24]),
+                    (PopIter, &[]),
+                    (Jump, &[29]),
+                    (Null, &[]),
+                    (Jump, &[25]),
+                    (Null, &[]),
+                    (Pop, &[]),
+                    (Jump, &[4]),
+                    (Null, &[]),
+                    (Return, &[]),
+                ],
+            ),
+            (
+                "if (true) { let x = 1; let x = x; x }",
+                vec![
+                    (True, &[]),
+                    (JumpIfFalse, &[23]),
+                    (Constant, &[0]),
+                    (DefineLocal, &[0]),
+                    (GetLocal, &[0]),
+                    (SetLocal, &[0]),
+                    (Pop, &[]),
+                    (GetLocal, &[0]),
+                    (Jump, &[24]),
+                    (Null, &[]),
+                    (Return, &[]),
+                ],
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let bytecode = compile_input(input).unwrap();
+            assert_eq!(instructions(&expected), bytecode.main.code, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_functions() {
+        use Opcode::*;
+
+        let bytecode = compile_input("let add = fn(a, b) { return a + b; }; add(1, 2)").unwrap();
+
+        let add = function(&bytecode, 0);
+        assert_eq!(Some("add".to_string()), add.name);
+        assert_eq!(vec![Symbol::from("a"), "b".into()], add.parameters);
+        assert_eq!(2, add.locals);
+        assert_eq!(
+            instructions(&[
+                (GetLocal, &[0]),
+                (GetLocal, &[1]),
+                (Add, &[]),
+                (Return, &[]),
+                (Null, &[]),
+                (Return, &[]),
+            ]),
+            add.code
+        );
+
+        assert_eq!(
+            instructions(&[
+                (Closure, &[0]),
+                (DefineGlobal, &[0]),
+                (GetGlobal, &[0]),
+                (Constant, &[1]),
+                (Constant, &[2]),
+                (Call, &[2]),
+                (Return, &[]),
+            ]),
+            bytecode.main.code
+        );
+        assert_eq!(vec![Symbol::from("add")], bytecode.globals);
+    }
+
+    #[test]
+    fn test_closures() {
+        use Opcode::*;
+
+        let bytecode = compile_input("fn(a) { let b = a; fn() { fn() { a + b } } }").unwrap();
+
+        let innermost = function(&bytecode, 0);
+        assert_eq!(vec![Capture::Free(0), Capture::Free(1)], innermost.captures);
+        assert_eq!(
+            instructions(&[(GetFree, &[0]), (GetFree, &[1]), (Add, &[]), (Return, &[])]),
+            innermost.code
+        );
+
+        let middle = function(&bytecode, 1);
+        assert_eq!(vec![Capture::Local(0), Capture::Local(1)], middle.captures);
+        assert_eq!(instructions(&[(Closure, &[0]), (Return, &[])]), middle.code);
+
+        let outer = function(&bytecode, 2);
+        assert_eq!(Vec::<Capture>::new(), outer.captures);
+        assert_eq!(
+            instructions(&[
+                (GetLocal, &[0]),
+                (DefineLocal, &[1]),
+                (Closure, &[1]),
+                (Return, &[]),
+            ]),
+            outer.code
+        );
+    }
+
+    #[test]
+    fn test_recursive_local_functions() {
+        use Opcode::*;
+
+        let bytecode = compile_input("fn() { let f = fn() { f() }; }").unwrap();
+
+        let f = function(&bytecode, 0);
+        assert_eq!(vec![Capture::Local(0)], f.captures);
+        assert_eq!(
+            instructions(&[(GetFree, &[0]), (TailCall, &[0]), (Return, &[])]),
+            f.code
+        );
+
+        assert_eq!(
+            instructions(&[
+                (Null, &[]),
+                (DefineLocal, &[0]),
+                (Closure, &[0]),
+                (SetLocal, &[0]),
+                (Pop, &[]),
+                (Null, &[]),
+                (Return, &[]),
+            ]),
+            function(&bytecode, 1).code
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let bytecode = compile_input("let x = 1;\nx + y").unwrap();
+
+        let spans: Vec<_> = bytecode
+            .main
+            .spans
+            .iter()
+            .map(|(offset, span)| (*offset, span.to_string()))
+            .collect();
+        assert_eq!(
+            vec![
+                (6, "2:1".to_string()),
+                (9, "2:5".to_string()),
+                (12, "2:3".to_string())
+            ],
+            spans
+        );
+        assert_eq!(Some(bytecode.main.spans[2].1), bytecode.main.span_at(12));
+        assert_eq!(None, bytecode.main.span_at(11));
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let tests = vec![
+            ("break;", "`break` outside of a loop"),
+            (
+                "let [a] = b;",
+                "the compiler doesn't support destructuring yet",
+            ),
+            ("f(...xs)", "the compiler doesn't support `...` yet"),
+            (
+                "f(a: 1)",
+                "the compiler doesn't support named arguments yet",
+            ),
+            ("throw 1;", "1:1: the compiler doesn't support `throw` yet"),
+            (
+                "fn(a = 1) { a }",
+                "the compiler doesn't support default parameter values yet",
+            ),
+            ("const x = 1; x = 2", "1:16: cannot assign to constant `x`"),
+            (
+                "const x = 1; fn() { x += 2 }",
+                "1:23: cannot assign to constant `x`",
+            ),
+            ("len = 1", "1:5: cannot assign to undeclared variable `len`"),
+            (
+                "const x = 1; const x = 2;",
+                "1:20: cannot redeclare constant `x`",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Err(expected.to_string()), compile_input(input), "{input}");
+        }
+    }
+}
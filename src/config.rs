@@ -0,0 +1,145 @@
+//! The settings in `~/.tofurc`, read at startup before the command line,
+//! so flags given there win. Each line sets one, as `name = value`, and
+//! `#` starts a comment:
+//!
+//! ```text
+//! # A quieter REPL.
+//! prompt = "tofu> "
+//! color = false
+//! history_size = 1000
+//! engine = vm
+//! prelude = true
+//! ```
+
+use std::path::PathBuf;
+
+use crate::repl::Options;
+
+/// Where the settings are read from: `.tofurc` in the home directory.
+pub fn path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".tofurc"))
+}
+
+/// Applies the settings in `~/.tofurc` to `options`, if there is one.
+/// Lines it can't make sense of are reported on stderr and skipped,
+/// since they're no reason not to run.
+pub fn load(options: &mut Options) {
+    let Some(path) = path() else {
+        return;
+    };
+    // Not having one is the usual case.
+    let Ok(source) = std::fs::read_to_string(&path) else {
+        return;
+    };
+    for error in apply(&source, options) {
+        eprintln!("{}:{error}", path.display());
+    }
+}
+
+/// Applies each setting in `source` to `options`, returning what was wrong
+/// with the lines that couldn't be, each starting with the line's number.
+pub fn apply(source: &str, options: &mut Options) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let line = &line[..comment_start(line)];
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = match line.split_once('=') {
+            Some((name, value)) => set(name.trim(), value.trim(), options),
+            None => Err(format!("expected `name = value`, got `{}`", line.trim())),
+        };
+        if let Err(error) = result {
+            errors.push(format!("{}: {error}", index + 1));
+        }
+    }
+    errors
+}
+
+/// Where the comment on `line` starts, or its end if there isn't one. A
+/// `#` inside quotes is part of the value.
+fn comment_start(line: &str) -> usize {
+    let mut quoted = false;
+    for (index, ch) in line.char_indices() {
+        match ch {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return index,
+            _ => {}
+        }
+    }
+    line.len()
+}
+
+fn set(name: &str, value: &str, options: &mut Options) -> Result<(), String> {
+    match name {
+        "prompt" => {
+            let prompt = match value.strip_prefix('"') {
+                Some(quoted) => quoted
+                    .strip_suffix('"')
+                    .ok_or_else(|| format!("unterminated string in `prompt`: {value}"))?,
+                None => value,
+            };
+            // Read once, at startup, and used until the process ends.
+            options.prompt = Box::leak(prompt.to_string().into_boxed_str());
+        }
+        "color" => options.color = boolean(name, value)?,
+        "prelude" => options.prelude = boolean(name, value)?,
+        "history_size" => {
+            options.history_size = value
+                .parse()
+                .map_err(|_| format!("`history_size` expects a number of lines, got `{value}`"))?
+        }
+        "engine" => {
+            options.engine = value
+                .parse()
+                .map_err(|_| format!("`engine` expects `tree` or `vm`, got `{value}`"))?
+        }
+        _ => return Err(format!("unknown setting `{name}`")),
+    }
+    Ok(())
+}
+
+fn boolean(name: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("`{name}` expects `true` or `false`, got `{value}`")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::apply;
+    use crate::repl::{Engine, Options};
+
+    #[test]
+    fn test_apply() {
+        let mut options = Options::default();
+        let errors = apply(
+            "# Settings.\n\
+             prompt = \"tofu# \" # with a space\n\
+             \n\
+             color = false\n\
+             history_size=1000\n\
+             engine = vm\n\
+             prelude = maybe\n\
+             verbose = true\n\
+             nonsense\n",
+            &mut options,
+        );
+
+        assert_eq!("tofu# ", options.prompt);
+        assert!(!options.color);
+        assert_eq!(1000, options.history_size);
+        assert_eq!(Engine::Vm, options.engine);
+        assert!(options.prelude);
+        assert_eq!(
+            vec![
+                "7: `prelude` expects `true` or `false`, got `maybe`",
+                "8: unknown setting `verbose`",
+                "9: expected `name = value`, got `nonsense`",
+            ],
+            errors
+        );
+    }
+}
@@ -0,0 +1,326 @@
+//! Conversions between [`Value`] and Rust types, for passing arguments and
+//! results between Tofu and the program embedding it.
+//!
+//! Rust values turn into Tofu values with [`From`], and back with
+//! [`TryFrom`], which fails with a [`RuntimeError`] naming the type it
+//! wanted when the value is some other type. That error converts into a
+//! [`TofuError`](crate::TofuError), so host functions can use `?` on it:
+//!
+//! ```
+//! use tofu_interpreter::{Arity, Interpreter, Value};
+//!
+//! let mut interpreter = Interpreter::new();
+//! interpreter.register_fn("total", Arity::Exact(1), |arguments| {
+//!     let prices = Vec::<f64>::try_from(&arguments[0])?;
+//!     Ok(Value::from(prices.iter().sum::<f64>()))
+//! });
+//! let total = interpreter.eval("total([1.5, 2.5, 3])").unwrap();
+//! assert_eq!(7.0, f64::try_from(total).unwrap());
+//! ```
+//!
+//! Arrays convert to and from `Vec`s and hashes with string keys to and
+//! from `HashMap<String, _>`, converting their elements the same way. An
+//! `Option` is `null` when it's `None`.
+
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use indexmap::IndexMap;
+
+use crate::evaluator::RuntimeError;
+use crate::value::{HashKey, Value};
+
+fn mismatch(expected: &str, value: &Value) -> RuntimeError {
+    RuntimeError::new(format!("expected {expected}, got {}", value.type_name()))
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Value {
+        Value::Int(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Value {
+        Value::Float(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Value {
+        Value::Bool(value)
+    }
+}
+
+impl From<char> for Value {
+    fn from(value: char) -> Value {
+        Value::Char(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Value {
+        Value::Str(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Value {
+        Value::Str(value.to_string())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Value {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(elements: Vec<T>) -> Value {
+        Value::array(elements.into_iter().map(Into::into).collect())
+    }
+}
+
+/// A hash with the map's entries, in order of their keys, since a
+/// `HashMap` has no order of its own and a hash keeps the order it's given.
+impl<T: Into<Value>, S: BuildHasher> From<HashMap<String, T, S>> for Value {
+    fn from(map: HashMap<String, T, S>) -> Value {
+        let mut entries: Vec<_> = map.into_iter().collect();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Value::hash(
+            entries
+                .into_iter()
+                .map(|(key, value)| (HashKey::Str(key), value.into()))
+                .collect::<IndexMap<_, _>>(),
+        )
+    }
+}
+
+impl TryFrom<&Value> for i64 {
+    type Error = RuntimeError;
+
+    fn try_from(value: &Value) -> Result<i64, RuntimeError> {
+        match value {
+            Value::Int(value) => Ok(*value),
+            value => Err(mismatch("Int", value)),
+        }
+    }
+}
+
+/// An `Int` converts too, as it would in arithmetic with a `Float`.
+impl TryFrom<&Value> for f64 {
+    type Error = RuntimeError;
+
+    fn try_from(value: &Value) -> Result<f64, RuntimeError> {
+        match value {
+            Value::Float(value) => Ok(*value),
+            Value::Int(value) => Ok(*value as f64),
+            value => Err(mismatch("Float", value)),
+        }
+    }
+}
+
+impl TryFrom<&Value> for bool {
+    type Error = RuntimeError;
+
+    fn try_from(value: &Value) -> Result<bool, RuntimeError> {
+        match value {
+            Value::Bool(value) => Ok(*value),
+            value => Err(mismatch("Bool", value)),
+        }
+    }
+}
+
+impl TryFrom<&Value> for char {
+    type Error = RuntimeError;
+
+    fn try_from(value: &Value) -> Result<char, RuntimeError> {
+        match value {
+            Value::Char(value) => Ok(*value),
+            value => Err(mismatch("Char", value)),
+        }
+    }
+}
+
+impl TryFrom<&Value> for String {
+    type Error = RuntimeError;
+
+    fn try_from(value: &Value) -> Result<String, RuntimeError> {
+        match value {
+            Value::Str(value) => Ok(value.clone()),
+            value => Err(mismatch("Str", value)),
+        }
+    }
+}
+
+impl<T> TryFrom<&Value> for Option<T>
+where
+    T: for<'a> TryFrom<&'a Value, Error = RuntimeError>,
+{
+    type Error = RuntimeError;
+
+    fn try_from(value: &Value) -> Result<Option<T>, RuntimeError> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::try_from(value).map(Some),
+        }
+    }
+}
+
+impl<T> TryFrom<&Value> for Vec<T>
+where
+    T: for<'a> TryFrom<&'a Value, Error = RuntimeError>,
+{
+    type Error = RuntimeError;
+
+    fn try_from(value: &Value) -> Result<Vec<T>, RuntimeError> {
+        match value {
+            Value::Array(elements) => elements.borrow().iter().map(T::try_from).collect(),
+            value => Err(mismatch("Array", value)),
+        }
+    }
+}
+
+impl<T, S> TryFrom<&Value> for HashMap<String, T, S>
+where
+    T: for<'a> TryFrom<&'a Value, Error = RuntimeError>,
+    S: BuildHasher + Default,
+{
+    type Error = RuntimeError;
+
+    fn try_from(value: &Value) -> Result<HashMap<String, T, S>, RuntimeError> {
+        let Value::Hash(pairs) = value else {
+            return Err(mismatch("Hash", value));
+        };
+        pairs
+            .borrow()
+            .iter()
+            .map(|(key, value)| match key {
+                HashKey::Str(key) => Ok((key.clone(), T::try_from(value)?)),
+                key => Err(mismatch("Str keys", &key.clone().into())),
+            })
+            .collect()
+    }
+}
+
+/// Implements `TryFrom<Value>` by converting a reference to the value, so
+/// results can be converted without borrowing them first.
+macro_rules! try_from_value {
+    ($($target:ty),* $(,)?) => {
+        $(
+            impl TryFrom<Value> for $target {
+                type Error = RuntimeError;
+
+                fn try_from(value: Value) -> Result<$target, RuntimeError> {
+                    <$target>::try_from(&value)
+                }
+            }
+        )*
+    };
+}
+
+try_from_value!(i64, f64, bool, char, String);
+
+impl<T> TryFrom<Value> for Option<T>
+where
+    T: for<'a> TryFrom<&'a Value, Error = RuntimeError>,
+{
+    type Error = RuntimeError;
+
+    fn try_from(value: Value) -> Result<Option<T>, RuntimeError> {
+        Option::try_from(&value)
+    }
+}
+
+impl<T> TryFrom<Value> for Vec<T>
+where
+    T: for<'a> TryFrom<&'a Value, Error = RuntimeError>,
+{
+    type Error = RuntimeError;
+
+    fn try_from(value: Value) -> Result<Vec<T>, RuntimeError> {
+        Vec::try_from(&value)
+    }
+}
+
+impl<T, S> TryFrom<Value> for HashMap<String, T, S>
+where
+    T: for<'a> TryFrom<&'a Value, Error = RuntimeError>,
+    S: BuildHasher + Default,
+{
+    type Error = RuntimeError;
+
+    fn try_from(value: Value) -> Result<HashMap<String, T, S>, RuntimeError> {
+        HashMap::try_from(&value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::evaluator::RuntimeError;
+    use crate::interpreter::Interpreter;
+    use crate::value::Value;
+
+    #[test]
+    fn test_conversions() {
+        let mut interpreter = Interpreter::new();
+        let eval = |interpreter: &mut Interpreter, source: &str| interpreter.eval(source).unwrap();
+
+        assert_eq!(Ok(3), i64::try_from(eval(&mut interpreter, "1 + 2")));
+        assert_eq!(Ok(2.0), f64::try_from(eval(&mut interpreter, "2")));
+        assert_eq!(Ok(true), bool::try_from(eval(&mut interpreter, "1 < 2")));
+        assert_eq!(
+            Ok("ab".to_string()),
+            String::try_from(eval(&mut interpreter, "\"a\" + \"b\""))
+        );
+        assert_eq!(
+            Ok(vec![Some(1), None]),
+            Vec::<Option<i64>>::try_from(eval(&mut interpreter, "[1, null]"))
+        );
+        assert_eq!(
+            Ok(HashMap::from([
+                ("a".to_string(), vec![1]),
+                ("b".to_string(), vec![])
+            ])),
+            HashMap::<String, Vec<i64>>::try_from(eval(
+                &mut interpreter,
+                "{\"a\": [1], \"b\": []}"
+            ))
+        );
+
+        let map = HashMap::from([("y", 2), ("x", 1)].map(|(key, value)| (key.to_string(), value)));
+        interpreter.set("point", Value::from(map)).unwrap();
+        interpreter
+            .set("names", Value::from(vec!["a", "b"]))
+            .unwrap();
+        assert_eq!(
+            "{\"x\": 1, \"y\": 2} [\"a\", \"b\"]",
+            eval(&mut interpreter, "[point, names]")
+                .iter()
+                .unwrap()
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+        assert_eq!(Value::Null, Value::from(None::<bool>));
+
+        let errors = [
+            (i64::try_from(Value::from("1")), "expected Int, got Str"),
+            (
+                Vec::<i64>::try_from(Value::from(vec![Value::Int(1), Value::Bool(true)]))
+                    .map(|_| 0),
+                "expected Int, got Bool",
+            ),
+            (
+                HashMap::<String, i64>::try_from(eval(&mut interpreter, "{1: 2}")).map(|_| 0),
+                "expected Str keys, got Int",
+            ),
+        ];
+        for (result, message) in errors {
+            assert_eq!(Err(RuntimeError::new(message)), result);
+        }
+    }
+}
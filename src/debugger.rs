@@ -0,0 +1,405 @@
+//! An interactive debugger for `tofu debug`. The evaluator hands it each
+//! statement before running it, and it pauses there when asked to, reading
+//! commands that step through the program, set breakpoints, and look at the
+//! variables and the calls in progress.
+//!
+//! It pauses at the first statement, at each line with a breakpoint, and
+//! wherever the program calls `breakpoint()`. Only the statements of the
+//! program being debugged are paused at, not those of the modules it
+//! imports or the prelude.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufRead, Write};
+
+use crate::ast::{Program, Statement};
+use crate::builtins::output_error;
+use crate::evaluator::{ErrorKind, Evaluator, RuntimeError};
+use crate::format::positions;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::token::Span;
+
+const PROMPT: &str = "(debug) ";
+
+/// How many lines `list` shows on each side of the current one.
+const CONTEXT: usize = 3;
+
+const COMMANDS: [(&str, &str); 11] = [
+    ("step, s", "Run to the next statement, going into calls"),
+    ("next, n", "Run to the next statement outside any calls"),
+    ("continue, c", "Run to the next breakpoint"),
+    ("break, b [<line>]", "Pause at <line>, or list breakpoints"),
+    ("clear <line>", "Remove the breakpoint at <line>"),
+    ("locals", "Show the variables in scope here"),
+    ("print, p <code>", "Evaluate an expression here"),
+    ("backtrace, bt", "Show the calls in progress"),
+    ("list", "Show the source around this line"),
+    ("quit, q", "Stop the program"),
+    ("help, h", "Show this help"),
+];
+
+/// When to pause next.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Mode {
+    /// At the next statement.
+    Step,
+    /// At the next statement no more than this many calls deep.
+    Next(usize),
+    /// At the next line with a breakpoint.
+    Continue,
+}
+
+pub struct Debugger {
+    /// The name of the program being debugged, for locations.
+    name: String,
+    lines: Vec<String>,
+    /// Where each statement of the program starts, by its address.
+    statement_starts: HashMap<*const Statement, Span>,
+    breakpoints: BTreeSet<usize>,
+    mode: Mode,
+    /// The Tofu function calls in progress, outermost first, each with the
+    /// name it was called by and where.
+    calls: Vec<(Option<String>, Span)>,
+    /// The line of the last statement reached, so a breakpoint pauses once
+    /// for the line rather than at each statement on it.
+    line: Option<usize>,
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+}
+
+impl Debugger {
+    /// A debugger for `program`, which `parser` parsed from `source`,
+    /// called `name`. It reads commands from stdin and writes to stdout.
+    pub fn new(name: &str, source: &str, program: &Program, parser: &Parser) -> Debugger {
+        Debugger {
+            name: name.to_string(),
+            lines: source.lines().map(str::to_string).collect(),
+            statement_starts: positions(program, parser).0,
+            breakpoints: BTreeSet::new(),
+            mode: Mode::Step,
+            calls: Vec::new(),
+            line: None,
+            input: Box::new(std::io::stdin().lock()),
+            output: Box::new(std::io::stdout()),
+        }
+    }
+
+    /// Makes the debugger read commands from `input` instead of stdin.
+    pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+        self.input = input;
+    }
+
+    /// Makes the debugger write to `output` instead of stdout.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    /// Records the start of a call of the function named `function`, made
+    /// at `call_site`.
+    pub(crate) fn enter(&mut self, function: Option<&str>, call_site: Span) {
+        self.calls.push((function.map(str::to_string), call_site));
+    }
+
+    /// Records the end of the call [`Debugger::enter`] last recorded.
+    pub(crate) fn leave(&mut self) {
+        self.calls.pop();
+    }
+
+    /// Pauses before `statement` if it's time to.
+    pub(crate) fn before_statement(
+        &mut self,
+        evaluator: &mut Evaluator,
+        statement: &Statement,
+    ) -> Result<(), RuntimeError> {
+        let Some(&span) = self.statement_starts.get(&(statement as *const _)) else {
+            return Ok(());
+        };
+        let new_line = self.line != Some(span.line);
+        self.line = Some(span.line);
+        let pause = match self.mode {
+            Mode::Step => true,
+            Mode::Next(depth) => self.calls.len() <= depth,
+            Mode::Continue => new_line && self.breakpoints.contains(&span.line),
+        };
+        if pause {
+            self.pause(evaluator, span)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Pauses for a call of `breakpoint()` at `span`.
+    pub(crate) fn breakpoint(
+        &mut self,
+        evaluator: &mut Evaluator,
+        span: Span,
+    ) -> Result<(), RuntimeError> {
+        self.pause(evaluator, span)
+    }
+
+    /// Shows where the program is and runs commands until one resumes it.
+    /// Failing to write ends the program as the program's own output
+    /// failing would.
+    fn pause(&mut self, evaluator: &mut Evaluator, span: Span) -> Result<(), RuntimeError> {
+        self.run_commands(evaluator, span)
+            .unwrap_or_else(|error| Err(output_error(error)))
+    }
+
+    /// Runs commands until one resumes the program, which it then does
+    /// unless it couldn't read them or they quit it.
+    fn run_commands(
+        &mut self,
+        evaluator: &mut Evaluator,
+        span: Span,
+    ) -> io::Result<Result<(), RuntimeError>> {
+        let function = match self.calls.last() {
+            Some((Some(name), _)) => format!(" in {name}"),
+            Some((None, _)) => " in <anonymous>".to_string(),
+            None => String::new(),
+        };
+        self.say(&format!("stopped at {}:{span}{function}", self.name))?;
+        self.show_line(span.line, true)?;
+
+        loop {
+            write!(self.output, "{PROMPT}")?;
+            self.output.flush()?;
+            let mut line = String::new();
+            let read = match self.input.read_line(&mut line) {
+                Ok(read) => read,
+                Err(error) => {
+                    return Ok(Err(RuntimeError::new(format!(
+                        "could not read input: {error}"
+                    ))))
+                }
+            };
+            if read == 0 {
+                // With nothing more to read, the program runs to the end.
+                self.say("")?;
+                self.breakpoints.clear();
+                self.mode = Mode::Continue;
+                return Ok(Ok(()));
+            }
+
+            let line = line.trim();
+            let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+            let argument = argument.trim();
+            match command {
+                "step" | "s" => {
+                    self.mode = Mode::Step;
+                    return Ok(Ok(()));
+                }
+                "next" | "n" => {
+                    self.mode = Mode::Next(self.calls.len());
+                    return Ok(Ok(()));
+                }
+                "continue" | "c" => {
+                    self.mode = Mode::Continue;
+                    return Ok(Ok(()));
+                }
+                "break" | "b" if argument.is_empty() => {
+                    if self.breakpoints.is_empty() {
+                        self.say("no breakpoints")?;
+                    }
+                    for line in self.breakpoints.clone() {
+                        self.say(&format!("breakpoint at {}:{line}", self.name))?;
+                    }
+                }
+                "break" | "b" => match self.line_number(argument) {
+                    Some(line) => {
+                        self.breakpoints.insert(line);
+                        self.say(&format!("breakpoint at {}:{line}", self.name))?;
+                    }
+                    None => self.say(&format!("Error no line {argument} to break at"))?,
+                },
+                "clear" => match argument.parse() {
+                    Ok(line) if self.breakpoints.remove(&line) => {
+                        self.say(&format!("cleared breakpoint at {}:{line}", self.name))?
+                    }
+                    _ => self.say(&format!("Error no breakpoint at line {argument}"))?,
+                },
+                "locals" => {
+                    let locals = evaluator.locals();
+                    if locals.is_empty() {
+                        self.say("no local variables")?;
+                    }
+                    for (name, value) in locals {
+                        self.say(&format!("{name} = {value}"))?;
+                    }
+                }
+                "print" | "p" => self.print(evaluator, argument)?,
+                "backtrace" | "bt" => self.backtrace(span)?,
+                "list" => {
+                    let first = span.line.saturating_sub(CONTEXT).max(1);
+                    let last = (span.line + CONTEXT).min(self.lines.len());
+                    for line in first..=last {
+                        self.show_line(line, line == span.line)?;
+                    }
+                }
+                "quit" | "q" => {
+                    return Ok(Err(RuntimeError {
+                        kind: ErrorKind::Exit(1),
+                        ..RuntimeError::new("quit in the debugger")
+                    }))
+                }
+                "help" | "h" => {
+                    for (command, help) in COMMANDS {
+                        self.say(&format!("{command:<18} {help}"))?;
+                    }
+                }
+                "" => {}
+                _ => self.say(&format!("Error unknown command `{command}`, try `help`"))?,
+            }
+        }
+    }
+
+    /// Evaluates `source` in the scope the program is paused in.
+    fn print(&mut self, evaluator: &mut Evaluator, source: &str) -> io::Result<()> {
+        if source.is_empty() {
+            return self.say("Error `print` needs an expression");
+        }
+        let mut parser = Parser::new(Lexer::new(source));
+        let program = parser.parse_program();
+        if let Some(error) = parser.errors().first() {
+            return self.say(&format!("Error {error}"));
+        }
+        let [Statement::Expression(expression)] = program.statements.as_slice() else {
+            return self.say("Error `print` needs an expression");
+        };
+        match evaluator.eval_here(expression) {
+            Ok(value) => self.say(&value.to_string()),
+            Err(error) => self.say(&format!("Error {}", error.message)),
+        }
+    }
+
+    /// Lists the calls in progress, innermost first, with where each one is
+    /// up to.
+    fn backtrace(&mut self, span: Span) -> io::Result<()> {
+        let mut at = span;
+        let mut frames = Vec::new();
+        for (function, call_site) in self.calls.iter().rev() {
+            let function = function.as_deref().unwrap_or("<anonymous>");
+            frames.push(format!("{function} at {}:{at}", self.name));
+            at = *call_site;
+        }
+        frames.push(format!("<top level> at {}:{at}", self.name));
+        for (index, frame) in frames.iter().enumerate() {
+            self.say(&format!("#{index} {frame}"))?;
+        }
+        Ok(())
+    }
+
+    /// `argument` as the number of one of the program's lines.
+    fn line_number(&self, argument: &str) -> Option<usize> {
+        argument
+            .parse()
+            .ok()
+            .filter(|&line| line >= 1 && line <= self.lines.len())
+    }
+
+    fn show_line(&mut self, line: usize, current: bool) -> io::Result<()> {
+        let Some(text) = self.lines.get(line - 1) else {
+            return Ok(());
+        };
+        let marker = if current { ">" } else { " " };
+        let text = format!("{marker} {line:>4} | {text}");
+        self.say(&text)
+    }
+
+    fn say(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.output, "{text}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::rc::Rc;
+
+    use super::Debugger;
+    use crate::builtins::test::Closed;
+    use crate::evaluator::{ErrorKind, Evaluator};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_debugger() {
+        let source = "let add = fn(a, b) {
+    let sum = a + b
+    sum
+}
+let x = add(1, 2)
+breakpoint()
+println(x)
+";
+        let mut lexer = Lexer::new(source);
+        lexer.set_newline_terminators(true);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+
+        let commands = "b 3\nc\nlocals\np a * 10\nbt\nn\nc\np x\nbogus\nc\n";
+        let output = Output::default();
+        let mut debugger = Debugger::new("test.tofu", source, &program, &parser);
+        debugger.set_input(Box::new(commands.as_bytes()));
+        debugger.set_output(Box::new(output.clone()));
+
+        let mut evaluator = Evaluator::new();
+        evaluator.set_output(Box::new(output.clone()));
+        evaluator.set_debugger(Some(debugger));
+        evaluator.eval_program(&program).unwrap();
+
+        let output = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(
+            "stopped at test.tofu:1:1
+>    1 | let add = fn(a, b) {
+(debug) breakpoint at test.tofu:3
+(debug) stopped at test.tofu:3:5 in add
+>    3 |     sum
+(debug) sum = 3
+a = 1
+b = 2
+(debug) 10
+(debug) #0 add at test.tofu:3:5
+#1 <top level> at test.tofu:5:12
+(debug) stopped at test.tofu:6:1
+>    6 | breakpoint()
+(debug) stopped at test.tofu:6:11
+>    6 | breakpoint()
+(debug) 3
+(debug) Error unknown command `bogus`, try `help`
+(debug) 3
+",
+            output
+        );
+    }
+
+    #[test]
+    fn test_closed_output() {
+        let source = "let x = 1";
+        let mut parser = Parser::new(Lexer::new(source));
+        let program = parser.parse_program();
+
+        let mut debugger = Debugger::new("test.tofu", source, &program, &parser);
+        debugger.set_input(Box::new("c\n".as_bytes()));
+        debugger.set_output(Box::new(Closed));
+
+        let mut evaluator = Evaluator::new();
+        evaluator.set_debugger(Some(debugger));
+        let error = evaluator.eval_program(&program).unwrap_err();
+        assert_eq!(ErrorKind::Exit(0), error.kind);
+    }
+}
@@ -0,0 +1,346 @@
+//! Errors as the user sees them: the message, where it happened, and the
+//! line of source it happened on with the token there underlined.
+//!
+//! ```text
+//! error: type mismatch: Int + Bool
+//!  --> script.tofu:2:7
+//!   |
+//! 2 | x = 1 + true
+//!   |       ^
+//! ```
+
+use std::fmt::Write;
+
+use crate::compiler::CompileError;
+use crate::evaluator::{RuntimeError, PRELUDE, PRELUDE_LINE};
+use crate::highlight::highlight;
+use crate::lexer::Lexer;
+use crate::parser::ParseError;
+use crate::resolve::ResolveError;
+use crate::token::{Span, TokenKind};
+use crate::typecheck::TypeError;
+
+const RESET: &str = "\x1b[0m";
+const ERROR: &str = "\x1b[1;31m";
+const WARNING: &str = "\x1b[1;33m";
+const BOLD: &str = "\x1b[1m";
+const GUTTER: &str = "\x1b[1;34m";
+
+/// The source code spans can point into, each piece numbered from the
+/// line passed to [`Lexer::starting_at_line`] when it was lexed. The
+/// prelude is always one of them.
+pub struct Sources {
+    /// What to call the program in errors that don't point into any of
+    /// the sources.
+    name: String,
+    files: Vec<File>,
+}
+
+struct File {
+    name: String,
+    first_line: usize,
+    /// The number its first line goes by in errors.
+    shown_line: usize,
+    lines: Vec<String>,
+}
+
+impl Sources {
+    pub fn new(name: impl Into<String>) -> Sources {
+        let mut sources = Sources {
+            name: name.into(),
+            files: Vec::new(),
+        };
+        sources.add_at("<prelude>", PRELUDE, PRELUDE_LINE);
+        sources
+    }
+
+    /// The sources of a program that's all in `source`, named `name`.
+    pub fn single(name: &str, source: &str) -> Sources {
+        let mut sources = Sources::new(name);
+        sources.add_at(name, source, 1);
+        sources
+    }
+
+    /// Adds `source`, named `name`, which was lexed with its lines
+    /// numbered from `first_line`.
+    pub fn add_at(&mut self, name: impl Into<String>, source: &str, first_line: usize) {
+        self.add_part(name, source, first_line, 1);
+    }
+
+    /// Like [`Sources::add_at`], for a part of `name` that starts on its
+    /// `shown_line`th line, like one of the lines typed into the REPL.
+    pub fn add_part(
+        &mut self,
+        name: impl Into<String>,
+        source: &str,
+        first_line: usize,
+        shown_line: usize,
+    ) {
+        self.files.push(File {
+            name: name.into(),
+            first_line,
+            shown_line,
+            lines: source.split('\n').map(str::to_string).collect(),
+        });
+    }
+
+    /// The name of the source `span` is in, the line and column it's at
+    /// there, and the text of that line.
+    fn locate(&self, span: Span) -> Option<(&str, Span, &str)> {
+        self.files.iter().find_map(|file| {
+            let index = span.line.checked_sub(file.first_line)?;
+            let line = file.lines.get(index)?;
+            let local = Span {
+                line: file.shown_line + index,
+                column: span.column,
+            };
+            Some((file.name.as_str(), local, line.as_str()))
+        })
+    }
+
+    /// Where `span` is, as `name:line:column`.
+    fn location(&self, span: Span) -> String {
+        match self.locate(span) {
+            Some((name, span, _)) => format!("{name}:{span}"),
+            None => format!("{}:{span}", self.name),
+        }
+    }
+}
+
+/// How bad what a [`Diagnostic`] reports is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// Something that stopped the program, or would.
+    Error,
+    /// Something that's probably a mistake, like from [`crate::lint`].
+    Warning,
+}
+
+/// An error from any stage, or a warning, ready to be shown with
+/// [`Diagnostic::render`].
+pub struct Diagnostic<'e> {
+    pub severity: Severity,
+    /// The name of the check a warning is from, shown next to it so it
+    /// can be looked up or allowed.
+    pub code: Option<&'e str>,
+    pub message: &'e str,
+    pub span: Option<Span>,
+    /// The runtime error the diagnostic is for, whose stack trace goes
+    /// after the source.
+    pub runtime: Option<&'e RuntimeError>,
+}
+
+impl<'e> From<&'e ParseError> for Diagnostic<'e> {
+    fn from(error: &'e ParseError) -> Diagnostic<'e> {
+        Diagnostic {
+            severity: Severity::Error,
+            code: None,
+            message: &error.message,
+            span: Some(error.span),
+            runtime: None,
+        }
+    }
+}
+
+impl<'e> From<&'e ResolveError> for Diagnostic<'e> {
+    fn from(error: &'e ResolveError) -> Diagnostic<'e> {
+        Diagnostic {
+            severity: Severity::Error,
+            code: None,
+            message: &error.message,
+            span: error.span,
+            runtime: None,
+        }
+    }
+}
+
+impl<'e> From<&'e TypeError> for Diagnostic<'e> {
+    fn from(error: &'e TypeError) -> Diagnostic<'e> {
+        Diagnostic {
+            severity: Severity::Error,
+            code: None,
+            message: &error.message,
+            span: error.span,
+            runtime: None,
+        }
+    }
+}
+
+impl<'e> From<&'e CompileError> for Diagnostic<'e> {
+    fn from(error: &'e CompileError) -> Diagnostic<'e> {
+        Diagnostic {
+            severity: Severity::Error,
+            code: None,
+            message: &error.message,
+            span: error.span,
+            runtime: None,
+        }
+    }
+}
+
+impl<'e> From<&'e RuntimeError> for Diagnostic<'e> {
+    fn from(error: &'e RuntimeError) -> Diagnostic<'e> {
+        Diagnostic {
+            severity: Severity::Error,
+            code: None,
+            message: &error.message,
+            span: error.span,
+            runtime: Some(error),
+        }
+    }
+}
+
+impl Diagnostic<'_> {
+    /// The diagnostic as lines of text, finding the source it points into
+    /// among `sources`, and colored with ANSI escapes if `color` is set.
+    pub fn render(&self, sources: &Sources, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("{style}{text}{RESET}")
+            } else {
+                text.to_string()
+            }
+        };
+
+        let (style, label) = match self.severity {
+            Severity::Error => (ERROR, "error"),
+            Severity::Warning => (WARNING, "warning"),
+        };
+        let label = match self.code {
+            Some(code) => format!("{label}[{code}]"),
+            None => label.to_string(),
+        };
+        let mut rendered = format!(
+            "{}{}\n",
+            paint(style, &label),
+            paint(BOLD, &format!(": {}", self.message))
+        );
+
+        let arrow = paint(GUTTER, "-->");
+        match self.span.map(|span| (span, sources.locate(span))) {
+            Some((_, Some((name, span, line)))) => {
+                let number = span.line.to_string();
+                let gutter = " ".repeat(number.len());
+                let bar = paint(GUTTER, "|");
+                let source = if color {
+                    highlight(line)
+                } else {
+                    line.to_string()
+                };
+                // Tabs are kept so the carets line up however wide the
+                // terminal draws them.
+                let indent: String = line
+                    .chars()
+                    .take(span.column - 1)
+                    .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+                    .collect();
+                let carets = paint(style, &"^".repeat(token_width(line, span.column)));
+
+                writeln!(rendered, "{gutter}{arrow} {name}:{span}").unwrap();
+                writeln!(rendered, "{gutter} {bar}").unwrap();
+                writeln!(rendered, "{} {bar} {source}", paint(GUTTER, &number)).unwrap();
+                writeln!(rendered, "{gutter} {bar} {indent}{carets}").unwrap();
+            }
+            Some((span, None)) => writeln!(rendered, " {arrow} {}:{span}", sources.name).unwrap(),
+            None => {}
+        }
+
+        if let Some(error) = self.runtime {
+            let mut trace = String::new();
+            error
+                .write_trace(&mut trace, |span| sources.location(span))
+                .unwrap();
+            // Each frame starts with a newline rather than ending with one.
+            for frame in trace.lines().skip(1) {
+                writeln!(rendered, "{frame}").unwrap();
+            }
+        }
+
+        rendered
+    }
+}
+
+/// How many characters the token at `column` of `line` takes up, so
+/// there's a caret under each. Past the end of the line, there's one.
+fn token_width(line: &str, column: usize) -> usize {
+    let Some((start, _)) = line.char_indices().nth(column - 1) else {
+        return 1;
+    };
+    let rest = &line[start..];
+    let mut lexer = Lexer::new(rest);
+    if lexer.next_token().kind == TokenKind::Eof {
+        return 1;
+    }
+    rest[..lexer.offset().min(rest.len())]
+        .trim_end()
+        .chars()
+        .count()
+        .max(1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Diagnostic, Sources};
+    use crate::evaluator::Evaluator;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_parse_error() {
+        let source = "let x = 1;\nlet y = (x + 2;\n";
+        let mut parser = Parser::new(Lexer::new(source));
+        parser.parse_program();
+        let mut sources = Sources::new("script.tofu");
+        sources.add_at("script.tofu", source, 1);
+
+        let rendered = Diagnostic::from(&parser.errors()[0]).render(&sources, false);
+        assert_eq!(
+            "error: expected `)` to close `(`, found `;`\n \
+             --> script.tofu:2:15\n  \
+               |\n\
+             2 | let y = (x + 2;\n  \
+               |               ^\n",
+            rendered
+        );
+    }
+
+    #[test]
+    fn test_runtime_error() {
+        let first = "let add = fn(a, b) {\n\ta + b\n}";
+        let second = "add(1, \"two\")";
+        let mut sources = Sources::new("<repl>");
+        sources.add_at("<repl>", first, 1);
+        sources.add_at("<repl>", second, 4);
+
+        let mut evaluator = Evaluator::new();
+        for (source, first_line) in [(first, 1), (second, 4)] {
+            let mut parser = Parser::new(Lexer::starting_at_line(source, first_line));
+            let program = parser.parse_program();
+            assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+            let result = evaluator.eval_program(&program);
+
+            if first_line == 4 {
+                let error = result.unwrap_err();
+                let rendered = Diagnostic::from(&error).render(&sources, false);
+                assert_eq!(
+                    "error: type mismatch: Int + Str\n \
+                     --> <repl>:2:4\n  \
+                       |\n\
+                     2 | \ta + b\n  \
+                       | \t  ^\n    \
+                     in add, called from <repl>:1:4\n",
+                    rendered
+                );
+            }
+        }
+
+        // Errors in the prelude point into it rather than the program.
+        let mut evaluator = Evaluator::new();
+        evaluator.load_prelude();
+        let mut parser = Parser::new(Lexer::new("sum(5)"));
+        let error = evaluator.eval_program(&parser.parse_program()).unwrap_err();
+        let rendered = Diagnostic::from(&error).render(&sources, false);
+        assert!(rendered.contains(" --> <prelude>:"), "{rendered}");
+        assert!(rendered.contains("let sum = "), "{rendered}");
+    }
+}
@@ -0,0 +1,195 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::ast::{DeclarationKind, Slot};
+use crate::evaluator::RuntimeError;
+use crate::symbol::{Symbol, SymbolMap};
+use crate::value::Value;
+
+struct Binding {
+    kind: DeclarationKind,
+    value: Value,
+}
+
+/// One scope's bindings, plus the scope it is nested in. Environments are
+/// shared, so a function can keep the one it was defined in alive and see
+/// later changes to it, which is what makes closures work.
+///
+/// The bindings are kept in the order their names were first declared,
+/// which is the order [`crate::resolve`] numbers a scope's names in, so a
+/// name it's resolved can be found by its [`Slot`] without hashing it.
+#[derive(Default)]
+pub struct Environment {
+    slots: Vec<(Symbol, Binding)>,
+    /// Where each name is in `slots`.
+    indexes: SymbolMap<usize>,
+    outer: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    /// An empty global environment.
+    pub fn new() -> Environment {
+        Environment::default()
+    }
+
+    /// An empty environment nested in `outer`.
+    pub fn enclosed(outer: Rc<RefCell<Environment>>) -> Environment {
+        Environment {
+            outer: Some(outer),
+            ..Environment::default()
+        }
+    }
+
+    fn binding(&self, name: &Symbol) -> Option<&Binding> {
+        let index = *self.indexes.get(name)?;
+        Some(&self.slots[index].1)
+    }
+
+    /// The value bound to `name` here or in an enclosing environment.
+    pub fn get(&self, name: &Symbol) -> Option<Value> {
+        match self.binding(name) {
+            Some(binding) => Some(binding.value.clone()),
+            None => self.outer.as_ref()?.borrow().get(name),
+        }
+    }
+
+    /// The value bound to `name` in `slot`, counting out from here, if
+    /// it's bound there. It isn't if the declaration the slot is for
+    /// hasn't run yet.
+    pub fn get_slot(&self, slot: Slot, name: &Symbol) -> Option<Value> {
+        if slot.depth > 0 {
+            return self.outer.as_ref()?.borrow().get_slot(slot.out(), name);
+        }
+        match self.slots.get(slot.index as usize) {
+            Some((bound, binding)) if bound == name => Some(binding.value.clone()),
+            _ => None,
+        }
+    }
+
+    /// Binds `name` in this environment, shadowing any outer binding. Only
+    /// a constant declared in this same environment can't be redeclared.
+    pub fn declare(
+        &mut self,
+        name: &Symbol,
+        kind: DeclarationKind,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        let binding = Binding { kind, value };
+        match self.indexes.get(name) {
+            Some(&index) if self.slots[index].1.kind == DeclarationKind::Const => {
+                return Err(RuntimeError::new(format!(
+                    "cannot redeclare constant `{name}`"
+                )));
+            }
+            Some(&index) => self.slots[index].1 = binding,
+            None => {
+                self.indexes.insert(name.clone(), self.slots.len());
+                self.slots.push((name.clone(), binding));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The names bound directly in this environment, not in enclosing
+    /// ones, with their values, sorted by name.
+    pub fn bindings(&self) -> Vec<(Symbol, Value)> {
+        let mut bindings: Vec<_> = self
+            .slots
+            .iter()
+            .map(|(name, binding)| (name.clone(), binding.value.clone()))
+            .collect();
+        bindings.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+        bindings
+    }
+
+    /// The global environment `env` is nested in, or `env` itself if it's
+    /// not nested in any.
+    pub fn root(env: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        match env.borrow().outer() {
+            Some(outer) => Environment::root(outer),
+            None => Rc::clone(env),
+        }
+    }
+
+    /// The environment this one is nested in, if any.
+    pub(crate) fn outer(&self) -> Option<&Rc<RefCell<Environment>>> {
+        self.outer.as_ref()
+    }
+
+    /// How many names are bound directly in this environment.
+    pub(crate) fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// The bindings made directly in this environment, in the order they
+    /// were first made.
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&Symbol, DeclarationKind, &Value)> {
+        self.slots
+            .iter()
+            .map(|(name, binding)| (name, binding.kind, &binding.value))
+    }
+
+    /// The values bound directly in this environment.
+    pub(crate) fn values(&self) -> impl Iterator<Item = &Value> {
+        self.slots.iter().map(|(_, binding)| &binding.value)
+    }
+
+    /// Removes every binding and the link to the enclosing environment,
+    /// and hands them back so they can be dropped once this environment
+    /// is no longer borrowed.
+    pub(crate) fn clear(&mut self) -> (Vec<Value>, Option<Rc<RefCell<Environment>>>) {
+        self.indexes.clear();
+        let values = self
+            .slots
+            .drain(..)
+            .map(|(_, binding)| binding.value)
+            .collect();
+        (values, self.outer.take())
+    }
+
+    /// Updates the nearest existing binding of `name`.
+    pub fn assign(&mut self, name: &Symbol, value: Value) -> Result<(), RuntimeError> {
+        match self.indexes.get(name) {
+            Some(&index) => assign_binding(&mut self.slots[index].1, name, value),
+            None => match &self.outer {
+                Some(outer) => outer.borrow_mut().assign(name, value),
+                None => Err(RuntimeError::new(format!(
+                    "cannot assign to undeclared variable `{name}`"
+                ))),
+            },
+        }
+    }
+
+    /// Updates the binding of `name` in `slot`, as [`Environment::get_slot`]
+    /// finds it, or hands `value` back if it isn't there.
+    pub fn assign_slot(
+        &mut self,
+        slot: Slot,
+        name: &Symbol,
+        value: Value,
+    ) -> Result<Result<(), RuntimeError>, Value> {
+        if slot.depth > 0 {
+            return match &self.outer {
+                Some(outer) => outer.borrow_mut().assign_slot(slot.out(), name, value),
+                None => Err(value),
+            };
+        }
+        match self.slots.get_mut(slot.index as usize) {
+            Some((bound, binding)) if bound == name => Ok(assign_binding(binding, name, value)),
+            _ => Err(value),
+        }
+    }
+}
+
+fn assign_binding(binding: &mut Binding, name: &Symbol, value: Value) -> Result<(), RuntimeError> {
+    match binding.kind {
+        DeclarationKind::Const => Err(RuntimeError::new(format!(
+            "cannot assign to constant `{name}`"
+        ))),
+        DeclarationKind::Let => {
+            binding.value = value;
+            Ok(())
+        }
+    }
+}
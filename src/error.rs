@@ -0,0 +1,137 @@
+//! [`TofuError`], what the library's entry points, like
+//! [`Interpreter::eval`](crate::interpreter::Interpreter::eval), fail with,
+//! whichever stage the problem was found in.
+
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::sync::Arc;
+
+use crate::evaluator::RuntimeError;
+use crate::lexer::LexError;
+use crate::parser::ParseError;
+use crate::typecheck::TypeError;
+
+#[derive(Debug, Clone)]
+pub enum TofuError {
+    /// Reading source or writing output failed.
+    Io(Arc<io::Error>),
+    /// The source has characters the lexer couldn't make tokens of, as
+    /// [`tokenize`](crate::tokenize) finds.
+    Lex(Vec<LexError>),
+    /// The source couldn't be parsed, for these reasons, which include
+    /// any the lexer found.
+    Parse(Vec<ParseError>),
+    /// The program's types don't fit together, for these reasons. Only
+    /// [`Interpreter::check_types`](crate::interpreter::Interpreter::check_types)
+    /// looks for these.
+    Type(Vec<TypeError>),
+    /// The program raised an error while it ran.
+    Runtime(RuntimeError),
+}
+
+impl TofuError {
+    /// A runtime error saying `message`, for a host function to fail with.
+    /// See [`Interpreter::register_fn`](crate::interpreter::Interpreter::register_fn).
+    pub fn runtime(message: impl Into<String>) -> TofuError {
+        TofuError::Runtime(RuntimeError::new(message))
+    }
+
+    /// Whether this is a failure to write output because whatever was
+    /// reading it has stopped, as `head` does once it has its lines, which
+    /// is no reason to complain.
+    pub fn is_broken_pipe(&self) -> bool {
+        matches!(self, TofuError::Io(error) if error.kind() == io::ErrorKind::BrokenPipe)
+    }
+}
+
+impl PartialEq for TofuError {
+    fn eq(&self, other: &TofuError) -> bool {
+        match (self, other) {
+            // There's no comparing I/O errors beyond their kinds.
+            (TofuError::Io(error), TofuError::Io(other)) => error.kind() == other.kind(),
+            (TofuError::Lex(errors), TofuError::Lex(others)) => errors == others,
+            (TofuError::Parse(errors), TofuError::Parse(others)) => errors == others,
+            (TofuError::Type(errors), TofuError::Type(others)) => errors == others,
+            (TofuError::Runtime(error), TofuError::Runtime(other)) => error == other,
+            _ => false,
+        }
+    }
+}
+
+impl Display for TofuError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TofuError::Io(error) => write!(f, "{error}"),
+            TofuError::Lex(errors) => {
+                for (index, error) in errors.iter().enumerate() {
+                    if index > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{error}")?;
+                }
+                Ok(())
+            }
+            TofuError::Parse(errors) => {
+                for (index, error) in errors.iter().enumerate() {
+                    if index > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{error}")?;
+                }
+                Ok(())
+            }
+            TofuError::Type(errors) => {
+                for (index, error) in errors.iter().enumerate() {
+                    if index > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{error}")?;
+                }
+                Ok(())
+            }
+            TofuError::Runtime(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for TofuError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TofuError::Io(error) => Some(error.as_ref()),
+            TofuError::Lex(errors) => errors
+                .first()
+                .map(|error| error as &(dyn std::error::Error + 'static)),
+            TofuError::Parse(errors) => errors
+                .first()
+                .map(|error| error as &(dyn std::error::Error + 'static)),
+            TofuError::Type(errors) => errors
+                .first()
+                .map(|error| error as &(dyn std::error::Error + 'static)),
+            TofuError::Runtime(error) => Some(error),
+        }
+    }
+}
+
+impl From<io::Error> for TofuError {
+    fn from(error: io::Error) -> TofuError {
+        TofuError::Io(Arc::new(error))
+    }
+}
+
+impl From<Vec<LexError>> for TofuError {
+    fn from(errors: Vec<LexError>) -> TofuError {
+        TofuError::Lex(errors)
+    }
+}
+
+impl From<Vec<ParseError>> for TofuError {
+    fn from(errors: Vec<ParseError>) -> TofuError {
+        TofuError::Parse(errors)
+    }
+}
+
+impl From<RuntimeError> for TofuError {
+    fn from(error: RuntimeError) -> TofuError {
+        TofuError::Runtime(error)
+    }
+}
@@ -0,0 +1,4278 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+
+use indexmap::IndexMap;
+
+use crate::ast::{
+    with_stack, BlockStatement, DeclarationKind, Expression, Import, ImportSource, InfixOperator,
+    LetStatement, Literal, MatchArm, Parameters, Pattern, PrefixOperator, Program, Slot, Statement,
+};
+#[cfg(feature = "bigint")]
+use crate::bigint;
+use crate::builtins::Builtins;
+use crate::debugger::Debugger;
+use crate::environment::Environment;
+use crate::gc::Collector;
+use crate::heap;
+use crate::interrupt;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::profile::Profiler;
+use crate::resolve::resolve;
+use crate::symbol::Symbol;
+use crate::token::Span;
+use crate::value::{address, Function, HashKey, Range, Struct, StructType, Value, VariantType};
+use crate::vm;
+
+/// An error raised while evaluating a program. `span` is where it happened,
+/// when that's known, and `trace` lists the calls it unwound through,
+/// innermost first.
+#[derive(PartialEq, Debug, Clone)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Option<Span>,
+    pub trace: Vec<Frame>,
+    /// The value passed to `throw`, for an error raised by one.
+    pub thrown: Option<Value>,
+}
+
+/// What sort of runtime error happened, for code that handles some
+/// differently from others.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum ErrorKind {
+    /// An error in the program, like a type mismatch or a failed assertion.
+    #[default]
+    Error,
+    /// A value raised with `throw`.
+    Thrown,
+    /// The program ran for longer than its fuel allowed. See
+    /// [`Evaluator::with_fuel`].
+    FuelExhausted,
+    /// The program used more memory than it was allowed. See
+    /// [`Evaluator::set_max_memory`].
+    OutOfMemory,
+    /// The program was stopped with Ctrl-C. See
+    /// [`Evaluator::set_interrupt`].
+    Interrupted,
+    /// The program called `exit`, asking to end with this status.
+    Exit(u8),
+}
+
+/// A function call that a runtime error unwound through.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Frame {
+    /// The name the function was defined with, or `None` if it was never
+    /// bound directly to a name.
+    pub function: Option<String>,
+    pub call_site: Span,
+}
+
+impl RuntimeError {
+    pub fn new(message: impl Into<String>) -> RuntimeError {
+        RuntimeError {
+            kind: ErrorKind::Error,
+            message: message.into(),
+            span: None,
+            trace: Vec::new(),
+            thrown: None,
+        }
+    }
+
+    /// Whether a `try` can catch the error. Running out of fuel or memory,
+    /// being interrupted or calling `exit` ends the program, however it
+    /// tries to carry on.
+    fn is_catchable(&self) -> bool {
+        matches!(self.kind, ErrorKind::Error | ErrorKind::Thrown)
+    }
+
+    /// What a `catch` binds for the error: the value thrown, or for any
+    /// other error a hash of its `"message"` and `"kind"`.
+    fn into_value(self) -> Value {
+        if let Some(thrown) = self.thrown {
+            return thrown;
+        }
+
+        let pairs = [
+            ("message", Value::Str(self.message)),
+            ("kind", Value::Str(format!("{:?}", self.kind))),
+        ];
+        Value::hash(
+            pairs
+                .into_iter()
+                .map(|(key, value)| (HashKey::Str(key.to_string()), value))
+                .collect(),
+        )
+    }
+
+    /// Writes the calls the error unwound through, each on a line of its
+    /// own, with where it was called from as `location` puts it.
+    pub fn write_trace(
+        &self,
+        f: &mut impl std::fmt::Write,
+        location: impl Fn(Span) -> String,
+    ) -> std::fmt::Result {
+        // Runaway recursion repeats the same frame hundreds of times, so
+        // only the first few of a run are written out.
+        for run in self.trace.chunk_by(|a, b| a == b) {
+            for frame in run.iter().take(REPEATED_FRAMES_SHOWN) {
+                let function = frame.function.as_deref().unwrap_or("anonymous function");
+                write!(
+                    f,
+                    "\n    in {function}, called from {}",
+                    location(frame.call_site)
+                )?;
+            }
+            let hidden = run.len().saturating_sub(REPEATED_FRAMES_SHOWN);
+            if hidden > 0 {
+                write!(f, "\n    ... the same call {hidden} more times")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records `span` as where the error happened, unless a more precise
+    /// span was already recorded further in.
+    pub(crate) fn at(mut self, span: Span) -> RuntimeError {
+        self.span.get_or_insert(span);
+        self
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(span) = self.span {
+            write!(f, "{span}: ")?;
+        }
+        write!(f, "{}", self.message)?;
+        self.write_trace(f, |span| span.to_string())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// How many identical frames in a row an error's trace shows.
+const REPEATED_FRAMES_SHOWN: usize = 3;
+
+/// Why evaluation stopped short of the end of a block.
+enum Unwind {
+    Return(Value),
+    /// A call in tail position, which returns from the current function
+    /// with whatever the call returns. The function that was running makes
+    /// the call itself once its own body has been left, so tail recursion
+    /// doesn't grow the stack.
+    TailCall {
+        function: Value,
+        arguments: Arguments,
+        call_site: Span,
+    },
+    Break,
+    Continue,
+    Error(RuntimeError),
+}
+
+impl Unwind {
+    /// Turns a `break` or `continue` that escaped every loop into an error,
+    /// at a function or program boundary.
+    fn stray_loop_control(self) -> Unwind {
+        match self {
+            Unwind::Break => RuntimeError::new("`break` outside of a loop").into(),
+            Unwind::Continue => RuntimeError::new("`continue` outside of a loop").into(),
+            unwind => unwind,
+        }
+    }
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(error: RuntimeError) -> Unwind {
+        Unwind::Error(error)
+    }
+}
+
+type Eval<T> = Result<T, Unwind>;
+
+/// The arguments a call was made with: the positional ones, with any
+/// spreads already expanded, and then the named ones.
+struct Arguments {
+    positional: Vec<Value>,
+    named: Vec<(Symbol, Value)>,
+}
+
+impl From<Vec<Value>> for Arguments {
+    fn from(positional: Vec<Value>) -> Arguments {
+        Arguments {
+            positional,
+            named: Vec::new(),
+        }
+    }
+}
+
+impl Arguments {
+    /// Lines the arguments up with `parameters`, giving a slot for each
+    /// positional parameter, which is `None` where its default is to be
+    /// used, and the values left over for the rest parameter.
+    fn resolve(
+        self,
+        parameters: &Parameters,
+    ) -> Result<(Vec<Option<Value>>, Vec<Value>), RuntimeError> {
+        let Arguments {
+            mut positional,
+            named,
+        } = self;
+        let given = positional.len() + named.len();
+        let rest = positional.split_off(positional.len().min(parameters.positional.len()));
+        let mut slots: Vec<Option<Value>> = positional.into_iter().map(Some).collect();
+        slots.resize(parameters.positional.len(), None);
+
+        for (name, value) in named {
+            let index = parameters
+                .positional
+                .iter()
+                .position(|parameter| parameter.name == name)
+                .ok_or_else(|| RuntimeError::new(format!("unknown argument `{name}`")))?;
+            if slots[index].is_some() {
+                return Err(RuntimeError::new(format!("argument `{name}` given twice")));
+            }
+            slots[index] = Some(value);
+        }
+
+        let (required, allowed) = (parameters.required(), parameters.positional.len());
+        let expected = match parameters.rest {
+            Some(_) if given < required => Some(format!("at least {required}")),
+            Some(_) => None,
+            None if (required..=allowed).contains(&given) => None,
+            None if required == allowed => Some(required.to_string()),
+            None => Some(format!("{required} to {allowed}")),
+        };
+        if let Some(expected) = expected {
+            return Err(RuntimeError::new(format!(
+                "wrong number of arguments: expected {expected}, got {given}"
+            )));
+        }
+
+        let missing = parameters
+            .positional
+            .iter()
+            .zip(&slots)
+            .find(|(parameter, slot)| slot.is_none() && parameter.default.is_none());
+        if let Some((parameter, _)) = missing {
+            return Err(RuntimeError::new(format!(
+                "missing argument `{}`",
+                parameter.name
+            )));
+        }
+
+        Ok((slots, rest))
+    }
+}
+
+/// What integer arithmetic does when a result doesn't fit in an `Int`.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum Overflow {
+    /// Raise a runtime error.
+    #[cfg_attr(not(feature = "bigint"), default)]
+    Error,
+    /// Carry on with an arbitrary-precision integer, which is the default
+    /// when the `bigint` feature is enabled.
+    #[cfg(feature = "bigint")]
+    #[default]
+    Promote,
+    /// Wrap around, two's complement style, for scripts that rely on it or
+    /// want to skip the checks.
+    Wrap,
+}
+
+/// The assertions a program has made with `assert` and `assert_eq`.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Assertions {
+    /// How many assertions were checked, whether they held or not.
+    pub checked: usize,
+    /// The assertions that failed, when failures are being collected with
+    /// [`Evaluator::set_collect_assertions`].
+    pub failed: Vec<RuntimeError>,
+}
+
+/// Helpers written in Tofu, like `sum` and `range`, for
+/// [`Evaluator::load_prelude`].
+pub const PRELUDE: &str = include_str!("prelude.tofu");
+
+/// The line the prelude is numbered from, far past where any program's
+/// lines go, so errors in it can be told apart from errors in the program.
+pub const PRELUDE_LINE: usize = 1_000_000_000;
+
+/// How deeply Tofu functions may call each other before evaluation stops
+/// with an error, unless set otherwise with [`Evaluator::set_max_depth`].
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+/// A generous allowance of Rust stack for each level of Tofu calls, enough
+/// for an unoptimized build. A thread with a stack of `n * STACK_PER_CALL`
+/// bytes runs `n` levels of calls without the evaluator having to find
+/// more stack, as it does when it's running out.
+pub const STACK_PER_CALL: usize = 64 * 1024;
+
+/// How much stack, for the calls a Tofu call makes, the evaluator moves
+/// onto when what's left of the one it's on gets short. Calls recurse on
+/// the Rust stack, so without this a depth limit higher than the thread's
+/// stack allows would crash rather than stop with an error.
+const STACK_SEGMENT: usize = 256 * STACK_PER_CALL;
+
+/// A tree-walking evaluator. Each block runs in an environment nested in
+/// the one around it, and a function's body in one nested in the
+/// environment the function was defined in. Names that aren't bound
+/// anywhere are looked up among the builtins.
+pub struct Evaluator {
+    env: Rc<RefCell<Environment>>,
+    /// The global environment `env` is nested in, where the names
+    /// [`resolve`] found no other declaration of are looked up.
+    globals: Rc<RefCell<Environment>>,
+    builtins: Builtins,
+    output: Box<dyn Write>,
+    /// Where `input` reads lines from, or `None` for stdin.
+    input: Option<Box<dyn BufRead>>,
+    overflow: Overflow,
+    /// How many Tofu function calls are in progress.
+    depth: usize,
+    max_depth: usize,
+    /// How many more steps evaluation may take, or `None` for no limit.
+    fuel: Option<u64>,
+    /// How many bytes the thread may allocate beyond what it had when the
+    /// limit was set, or `None` for no limit.
+    max_memory: Option<usize>,
+    memory_base: usize,
+    /// Stops the program when set. See [`Evaluator::set_interrupt`].
+    interrupt: Option<&'static AtomicBool>,
+    allow_files: bool,
+    allow_env: bool,
+    /// The environment variables `env_set` set, which the environment
+    /// builtins and the programs `exec` runs see over the process's own.
+    env_vars: HashMap<String, String>,
+    allow_exec: bool,
+    allow_net: bool,
+    collect_assertions: bool,
+    assertions: Assertions,
+    /// Where `import` looks for modules that aren't next to the file
+    /// importing them.
+    module_path: Vec<PathBuf>,
+    /// The file the program was read from, if any.
+    source_path: Option<PathBuf>,
+    /// The namespaces of the modules imported so far, by canonical path, so
+    /// each module runs once however often it's imported.
+    modules: HashMap<PathBuf, Value>,
+    /// The modules being imported, outermost first, for finding the
+    /// importing file's directory and catching import cycles.
+    importing: Vec<PathBuf>,
+    /// The canonical path of every module file read, in the order they
+    /// were, including any that failed to run.
+    module_files: Vec<PathBuf>,
+    /// Frees the reference cycles closures make with their environments.
+    collector: Collector,
+    /// Where the builtin that is running was called, for tracing calls it
+    /// makes back into Tofu code.
+    builtin_call_site: Span,
+    /// Records the calls made, when profiling.
+    profiler: Option<Profiler>,
+    /// Pauses the program between statements, when debugging.
+    debugger: Option<Debugger>,
+    /// Where each node evaluated is logged, when tracing.
+    trace: Option<Box<dyn Write>>,
+}
+
+impl Drop for Evaluator {
+    // Functions defined at the top level are bound in the global
+    // environment they capture, so it's only freed by collecting it.
+    fn drop(&mut self) {
+        self.env = Rc::new(RefCell::new(Environment::new()));
+        self.globals = Rc::clone(&self.env);
+        self.modules.clear();
+        self.collector.collect();
+    }
+}
+
+impl Default for Evaluator {
+    fn default() -> Evaluator {
+        Evaluator::new()
+    }
+}
+
+impl Evaluator {
+    pub fn new() -> Evaluator {
+        let env = Rc::new(RefCell::new(Environment::new()));
+        Evaluator {
+            globals: Rc::clone(&env),
+            env,
+            builtins: Builtins::standard(),
+            output: Box::new(std::io::stdout()),
+            input: None,
+            overflow: Overflow::default(),
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            fuel: None,
+            max_memory: None,
+            memory_base: 0,
+            interrupt: None,
+            allow_files: true,
+            allow_env: true,
+            env_vars: HashMap::new(),
+            allow_exec: true,
+            allow_net: true,
+            collect_assertions: false,
+            assertions: Assertions::default(),
+            module_path: Vec::new(),
+            source_path: None,
+            modules: HashMap::new(),
+            importing: Vec::new(),
+            module_files: Vec::new(),
+            collector: Collector::new(),
+            builtin_call_site: Span::default(),
+            profiler: None,
+            debugger: None,
+            trace: None,
+        }
+    }
+
+    /// An evaluator that stops with an [`ErrorKind::FuelExhausted`] error
+    /// once it has taken `fuel` steps, so a program that never ends, like
+    /// `while (true) {}`, can be run without hanging the caller. See
+    /// [`Evaluator::set_fuel`].
+    pub fn with_fuel(fuel: u64) -> Evaluator {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_fuel(Some(fuel));
+        evaluator
+    }
+
+    /// How many more steps evaluation may take, or `None` if there's no
+    /// limit.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Limits how many more steps evaluation may take, across every
+    /// program this evaluator runs from now on, or lifts the limit with
+    /// `None`. Each statement, each expression and each turn of a loop is
+    /// a step. Time spent inside a builtin isn't counted, though Tofu
+    /// functions it calls are.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// Limits how much more memory programs may use, counting from what is
+    /// in use now, or lifts the limit with `None`. Using more raises an
+    /// [`ErrorKind::OutOfMemory`] error. Usage is checked at every step
+    /// and before a builtin builds something big in one go, so a step can
+    /// overshoot the limit by about what it builds.
+    ///
+    /// Memory is counted by [`heap::CountingAllocator`], which has to be
+    /// the global allocator, as it is in the binary. Without it, nothing
+    /// could be counted, so setting a limit fails and leaves none set.
+    pub fn set_max_memory(&mut self, max_memory: Option<usize>) -> Result<(), RuntimeError> {
+        if max_memory.is_some() && !heap::is_installed() {
+            return Err(RuntimeError::new(
+                "a memory limit needs `heap::CountingAllocator` as the global allocator",
+            ));
+        }
+        self.max_memory = max_memory;
+        self.memory_base = heap::allocated();
+        Ok(())
+    }
+
+    /// Makes programs stop with an [`ErrorKind::Interrupted`] error at the
+    /// next step after `interrupt` is set, clearing it, or stops watching
+    /// for that with `None`. The REPL passes [`interrupt::CTRL_C`].
+    pub fn set_interrupt(&mut self, interrupt: Option<&'static AtomicBool>) {
+        self.interrupt = interrupt;
+    }
+
+    /// Checks that allocating `additional` more bytes would stay within
+    /// the memory limit, if there is one.
+    pub fn check_memory(&self, additional: usize) -> Result<(), RuntimeError> {
+        let Some(max_memory) = self.max_memory else {
+            return Ok(());
+        };
+
+        let used = heap::allocated().saturating_sub(self.memory_base);
+        if used.saturating_add(additional) > max_memory {
+            return Err(RuntimeError {
+                kind: ErrorKind::OutOfMemory,
+                ..RuntimeError::new(format!("out of memory: the limit is {max_memory} bytes"))
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    /// Limits how deeply Tofu functions may call each other, turning
+    /// runaway recursion into a runtime error. The evaluator recurses on
+    /// the Rust stack, and moves onto more when it runs short, so it needs
+    /// no particular stack size for the limit, though one big enough (see
+    /// [`STACK_PER_CALL`]) saves it the trouble. Calls between compiled
+    /// functions don't use the Rust stack, since the VM keeps its frames on
+    /// the heap, so they're only limited by this.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn allow_files(&self) -> bool {
+        self.allow_files
+    }
+
+    /// Turns the file builtins, like `read_file`, on or off. They're on by
+    /// default; turn them off to run a script you don't trust.
+    pub fn set_allow_files(&mut self, allow_files: bool) {
+        self.allow_files = allow_files;
+    }
+
+    pub fn allow_env(&self) -> bool {
+        self.allow_env
+    }
+
+    /// Turns the environment variable builtins, like `env_get`, on or off.
+    /// Like the file builtins, they're on by default.
+    pub fn set_allow_env(&mut self, allow_env: bool) {
+        self.allow_env = allow_env;
+    }
+
+    /// The environment variables `env_set` set, which only this evaluator
+    /// and the programs it runs see; the process's environment is left as
+    /// it is.
+    pub fn env_vars(&self) -> &HashMap<String, String> {
+        &self.env_vars
+    }
+
+    /// The environment variable `name` as the program sees it.
+    pub(crate) fn env_var(&self, name: &str) -> Option<String> {
+        match self.env_vars.get(name) {
+            Some(value) => Some(value.clone()),
+            None => std::env::var(name).ok(),
+        }
+    }
+
+    pub(crate) fn set_env_var(&mut self, name: &str, value: &str) {
+        self.env_vars.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn allow_exec(&self) -> bool {
+        self.allow_exec
+    }
+
+    /// Turns the `exec` builtin, which runs other programs, on or off.
+    /// It's on by default.
+    pub fn set_allow_exec(&mut self, allow_exec: bool) {
+        self.allow_exec = allow_exec;
+    }
+
+    pub fn allow_net(&self) -> bool {
+        self.allow_net
+    }
+
+    /// Turns the network builtins, like `http_get` with the `http`
+    /// feature, on or off. They're on by default.
+    pub fn set_allow_net(&mut self, allow_net: bool) {
+        self.allow_net = allow_net;
+    }
+
+    /// The names bound in the global environment, with their values, sorted
+    /// by name.
+    pub fn globals(&self) -> Vec<(Symbol, Value)> {
+        self.globals.borrow().bindings()
+    }
+
+    /// Starts or stops recording how often each Tofu function is called
+    /// and how long the calls take, for [`Evaluator::profiler`]. Compiled
+    /// functions always run in the VM while profiling, since native code
+    /// can't record its calls.
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profiler = profiling.then(Profiler::new);
+    }
+
+    /// The calls recorded since profiling started, if it has.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Starts or stops logging each expression evaluated to `trace`, after
+    /// its value, indented by how many calls deep it is. The VM logs each
+    /// instruction it runs instead, with the value it left on the stack.
+    pub fn set_trace(&mut self, trace: Option<Box<dyn Write>>) {
+        self.trace = trace;
+    }
+
+    pub(crate) fn is_tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    /// How many Tofu function calls are in progress.
+    pub(crate) fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Logs `line` to the trace, if tracing, indented for `depth` calls.
+    pub(crate) fn trace(&mut self, depth: usize, line: &str) {
+        if let Some(trace) = &mut self.trace {
+            // The trace is a diagnostic aid, so losing it is no reason to
+            // stop the program.
+            let _ = writeln!(trace, "{:indent$}{line}", "", indent = 2 * depth);
+        }
+    }
+
+    /// Starts or stops debugging with `debugger`, which is handed each
+    /// statement before it runs and each call of `breakpoint()`.
+    pub fn set_debugger(&mut self, debugger: Option<Debugger>) {
+        self.debugger = debugger;
+    }
+
+    /// Pauses in the debugger for a call of `breakpoint()`, if debugging.
+    pub(crate) fn breakpoint(&mut self) -> Result<(), RuntimeError> {
+        let Some(mut debugger) = self.debugger.take() else {
+            return Ok(());
+        };
+        let result = debugger.breakpoint(self, self.builtin_call_site);
+        self.debugger = Some(debugger);
+        result
+    }
+
+    /// The variables in scope where the program is, other than the global
+    /// ones, innermost first and without those they shadow.
+    pub(crate) fn locals(&self) -> Vec<(Symbol, Value)> {
+        let mut locals: Vec<(Symbol, Value)> = Vec::new();
+        let mut env = Rc::clone(&self.env);
+        while !Rc::ptr_eq(&env, &self.globals) {
+            for (name, value) in env.borrow().bindings() {
+                if !locals.iter().any(|(local, _)| *local == name) {
+                    locals.push((name, value));
+                }
+            }
+            let Some(outer) = env.borrow().outer().cloned() else {
+                break;
+            };
+            env = outer;
+        }
+        locals
+    }
+
+    /// Evaluates `expression` in the scope the program is in, for the
+    /// debugger, whose expressions no pass has resolved.
+    pub(crate) fn eval_here(&mut self, expression: &Expression) -> Result<Value, RuntimeError> {
+        self.eval_expression(expression)
+            .map_err(Unwind::stray_loop_control)
+            .or_else(|unwind| match unwind {
+                Unwind::Error(error) => Err(error),
+                Unwind::Return(value) => Ok(value),
+                Unwind::TailCall {
+                    function,
+                    arguments,
+                    call_site,
+                } => self.apply_function(function, arguments, call_site),
+                Unwind::Break | Unwind::Continue => unreachable!(),
+            })
+    }
+
+    /// Makes a failed assertion get recorded in [`Evaluator::assertions`]
+    /// and evaluation carry on, instead of raising a runtime error, so a
+    /// script can report every assertion that fails in one run.
+    pub fn set_collect_assertions(&mut self, collect_assertions: bool) {
+        self.collect_assertions = collect_assertions;
+    }
+
+    pub fn assertions(&self) -> &Assertions {
+        &self.assertions
+    }
+
+    /// Records an assertion made by the running builtin, which failed with
+    /// `failure` if that's `Some`. A failure is located where the builtin
+    /// was called and raised as an error, unless failures are being
+    /// collected.
+    pub fn check_assertion(&mut self, failure: Option<String>) -> Result<(), RuntimeError> {
+        self.assertions.checked += 1;
+
+        let Some(failure) = failure else {
+            return Ok(());
+        };
+        let error = RuntimeError::new(failure).at(self.builtin_call_site);
+
+        if self.collect_assertions {
+            self.assertions.failed.push(error);
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    /// Frees the functions, environments and collections that are only
+    /// kept alive by referring to each other, like a closure stored in the
+    /// environment it captured, and returns how many environments that
+    /// freed. This happens now and then anyway as closures are made, and
+    /// when the evaluator is dropped.
+    pub fn collect_garbage(&mut self) -> usize {
+        self.collector.collect()
+    }
+
+    /// Notes that `value` was stored in `container`, which is how a
+    /// collection comes to contain itself, collecting now and then like
+    /// making a closure does.
+    pub(crate) fn track_store(&mut self, container: &Value, value: &Value) {
+        if self.collector.track_store(container, value) {
+            self.collector.collect();
+        }
+    }
+
+    /// Notes that a compiled closure captured variables, which can make a
+    /// cycle through them the way capturing an environment can.
+    pub(crate) fn track_closure(&mut self, closure: &Rc<vm::Closure>) {
+        if self.collector.track_closure(closure) {
+            self.collector.collect();
+        }
+    }
+
+    /// Runs the prelude, a handful of helpers written in Tofu like `sum`
+    /// and `range`, so programs run afterwards can use them. Like builtins,
+    /// they can be shadowed.
+    pub fn load_prelude(&mut self) {
+        let mut lexer = Lexer::starting_at_line(PRELUDE, PRELUDE_LINE);
+        lexer.set_newline_terminators(true);
+
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        assert!(
+            parser.errors().is_empty(),
+            "the prelude has errors: {:?}",
+            parser.errors()
+        );
+
+        self.eval_program(&program).expect("the prelude runs");
+    }
+
+    /// Sets the directories `import` searches, in order, for a module that
+    /// isn't next to the file importing it.
+    pub fn set_module_path(&mut self, module_path: Vec<PathBuf>) {
+        self.module_path = module_path;
+    }
+
+    /// Tells the evaluator which file the program it runs was read from,
+    /// so the program's imports are looked for next to it rather than in
+    /// the working directory.
+    pub fn set_source_path(&mut self, source_path: impl Into<PathBuf>) {
+        let source_path = source_path.into();
+        self.source_path = Some(source_path.canonicalize().unwrap_or(source_path));
+    }
+
+    /// The files of the modules imported so far, including any that
+    /// couldn't be run, e.g. for noticing when one changes.
+    pub fn module_files(&self) -> &[PathBuf] {
+        &self.module_files
+    }
+
+    /// The builtins available to programs.
+    pub fn builtins(&self) -> &Builtins {
+        &self.builtins
+    }
+
+    /// The builtins available to programs, for registering more.
+    pub fn builtins_mut(&mut self) -> &mut Builtins {
+        &mut self.builtins
+    }
+
+    /// The value bound to `name` in the global environment, like the
+    /// prelude's functions, if any. Only meaningful between programs.
+    pub fn global(&self, name: &Symbol) -> Option<Value> {
+        self.env.borrow().get(name)
+    }
+
+    pub(crate) fn global_environment(&self) -> &Rc<RefCell<Environment>> {
+        &self.globals
+    }
+
+    /// Replaces the global environment, between programs.
+    pub(crate) fn set_global_environment(&mut self, globals: Rc<RefCell<Environment>>) {
+        self.env = Rc::clone(&globals);
+        self.globals = globals;
+    }
+
+    pub(crate) fn modules(&self) -> &HashMap<PathBuf, Value> {
+        &self.modules
+    }
+
+    pub(crate) fn set_modules(&mut self, modules: HashMap<PathBuf, Value>) {
+        self.modules = modules;
+    }
+
+    /// Binds `name` to `value` in the global environment, as `let` would,
+    /// which fails if it's a constant there.
+    pub fn set_global(&mut self, name: &Symbol, value: Value) -> Result<(), RuntimeError> {
+        self.globals
+            .borrow_mut()
+            .declare(name, DeclarationKind::Let, value)
+    }
+
+    /// Where `print` and friends write, which is stdout by default.
+    pub fn output(&mut self) -> &mut dyn Write {
+        &mut *self.output
+    }
+
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    /// Reads a line for `input`, including its line ending, and returns how
+    /// many bytes were read, which is 0 at the end of the input. Stdin is
+    /// read through [`std::io::stdin`], whose buffer is shared with anything
+    /// else reading stdin in the process, like the REPL, so no line is
+    /// swallowed by one reader when it was meant for the other.
+    pub fn read_line(&mut self, line: &mut String) -> std::io::Result<usize> {
+        match &mut self.input {
+            Some(input) => input.read_line(line),
+            None => std::io::stdin().read_line(line),
+        }
+    }
+
+    /// Makes `input` read from `input` instead of stdin.
+    pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+        self.input = Some(input);
+    }
+
+    /// Calls a function value, for builtins that take Tofu functions as
+    /// arguments. An error raised inside it is traced as if the function
+    /// had been called where the builtin was.
+    pub fn call(&mut self, function: Value, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        self.apply_function(function, arguments.into(), self.builtin_call_site)
+    }
+
+    /// Like [`Evaluator::call`], for a call made at `call_site`.
+    pub(crate) fn call_at(
+        &mut self,
+        function: Value,
+        arguments: Vec<Value>,
+        call_site: Span,
+    ) -> Result<Value, RuntimeError> {
+        self.apply_function(function, arguments.into(), call_site)
+    }
+
+    /// Runs `program` and returns the value of its last statement, or the
+    /// value passed to a top-level `return`.
+    pub fn eval_program(&mut self, program: &Program) -> Result<Value, RuntimeError> {
+        resolve(program);
+        let mut result = Value::Null;
+
+        for statement in &program.statements {
+            match self
+                .eval_statement(statement)
+                .map_err(Unwind::stray_loop_control)
+            {
+                Ok(value) => result = value,
+                Err(Unwind::Return(value)) => return Ok(value),
+                Err(Unwind::TailCall {
+                    function,
+                    arguments,
+                    call_site,
+                }) => return self.apply_function(function, arguments, call_site),
+                Err(Unwind::Error(error)) => return Err(error),
+                Err(Unwind::Break | Unwind::Continue) => unreachable!(),
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn eval_statement(&mut self, statement: &Statement) -> Eval<Value> {
+        self.step()?;
+        self.debug(statement)?;
+        match statement {
+            Statement::Let(statement) => {
+                self.eval_let_statement(statement)?;
+                Ok(Value::Null)
+            }
+            Statement::Return(value) => {
+                let value = match value {
+                    Some(value) => self.eval_tail_expression(value)?,
+                    None => Value::Null,
+                };
+                Err(Unwind::Return(value))
+            }
+            Statement::Expression(expression) => self.eval_expression(expression),
+            Statement::While { condition, body } => {
+                while self.eval_expression(condition)?.is_truthy() {
+                    if !self.eval_loop_body(body, None)? {
+                        break;
+                    }
+                }
+                Ok(Value::Null)
+            }
+            Statement::For {
+                pattern,
+                iterable,
+                body,
+            } => {
+                let iterable = self.eval_expression(iterable)?;
+                let iter = iterable.iter().ok_or_else(|| {
+                    RuntimeError::new(format!("cannot iterate over {}", iterable.type_name()))
+                })?;
+
+                for item in iter {
+                    if !self.eval_loop_body(body, Some((pattern, item)))? {
+                        break;
+                    }
+                }
+                Ok(Value::Null)
+            }
+            Statement::Break => Err(Unwind::Break),
+            Statement::Continue => Err(Unwind::Continue),
+            Statement::Import(import) => {
+                self.eval_import(import)
+                    .map_err(|error| error.at(import.span))?;
+                Ok(Value::Null)
+            }
+            Statement::Throw { value, span } => {
+                let value = self.eval_expression(value)?;
+                let message = match &value {
+                    Value::Str(message) => message.clone(),
+                    value => value.to_string(),
+                };
+                Err(RuntimeError {
+                    kind: ErrorKind::Thrown,
+                    thrown: Some(value),
+                    ..RuntimeError::new(message).at(*span)
+                }
+                .into())
+            }
+            Statement::Struct { name, fields, span } => {
+                let ty = StructType {
+                    name: name.to_string(),
+                    fields: fields.iter().map(Symbol::to_string).collect(),
+                };
+                self.env
+                    .borrow_mut()
+                    .declare(name, DeclarationKind::Let, Value::StructType(Rc::new(ty)))
+                    .map_err(|error| error.at(*span))?;
+                Ok(Value::Null)
+            }
+            // A variant without fields is declared as its one value, and
+            // one with fields as what makes them.
+            Statement::Enum {
+                name,
+                variants,
+                span,
+            } => {
+                for variant in variants {
+                    let ty = Rc::new(VariantType {
+                        enum_name: name.to_string(),
+                        name: variant.name.to_string(),
+                        fields: variant.fields.iter().map(Symbol::to_string).collect(),
+                    });
+                    let value = if variant.fields.is_empty() {
+                        Value::variant(ty, Vec::new())
+                    } else {
+                        Value::VariantType(ty)
+                    };
+                    self.env
+                        .borrow_mut()
+                        .declare(&variant.name, DeclarationKind::Let, value)
+                        .map_err(|error| error.at(*span))?;
+                }
+                Ok(Value::Null)
+            }
+        }
+    }
+
+    /// Hands `statement` to the debugger before it runs, if debugging.
+    fn debug(&mut self, statement: &Statement) -> Result<(), RuntimeError> {
+        let Some(mut debugger) = self.debugger.take() else {
+            return Ok(());
+        };
+        let result = debugger.before_statement(self, statement);
+        self.debugger = Some(debugger);
+        result
+    }
+
+    /// Binds the namespace of the imported module, running the module first
+    /// unless it already ran.
+    fn eval_import(&mut self, import: &Import) -> Result<(), RuntimeError> {
+        if !self.allow_files {
+            return Err(RuntimeError::new(
+                "`import` is unavailable: filesystem access is disabled",
+            ));
+        }
+
+        let path = self.find_module(&import.source)?;
+        let namespace = match self.modules.get(&path) {
+            Some(namespace) => namespace.clone(),
+            None => {
+                let namespace = self.load_module(&path)?;
+                self.modules.insert(path, namespace.clone());
+                namespace
+            }
+        };
+
+        self.env
+            .borrow_mut()
+            .declare(&import.name, DeclarationKind::Let, namespace)
+    }
+
+    /// The canonical path of a module's file, looked for first next to the
+    /// importing file, or in the working directory if that isn't known,
+    /// and then along the module path.
+    fn find_module(&self, source: &ImportSource) -> Result<PathBuf, RuntimeError> {
+        let file = source.file();
+        let importer = self.importing.last().or(self.source_path.as_ref());
+        let beside_importer = importer
+            .and_then(|importer| importer.parent())
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        std::iter::once(&beside_importer)
+            .chain(&self.module_path)
+            .map(|directory| directory.join(&file))
+            .find(|candidate| candidate.is_file())
+            .and_then(|found| found.canonicalize().ok())
+            .ok_or_else(|| RuntimeError::new(format!("cannot find module {file:?}")))
+    }
+
+    /// Runs the module at `path` in an environment of its own, and returns
+    /// a hash of its top-level bindings.
+    fn load_module(&mut self, path: &Path) -> Result<Value, RuntimeError> {
+        // The program itself is where every chain of imports starts.
+        let chain: Vec<&Path> = self
+            .source_path
+            .iter()
+            .chain(&self.importing)
+            .map(PathBuf::as_path)
+            .collect();
+
+        if let Some(start) = chain.iter().position(|importing| *importing == path) {
+            let cycle: Vec<String> = chain[start..]
+                .iter()
+                .copied()
+                .chain([path])
+                .map(|importing| importing.display().to_string())
+                .collect();
+            return Err(RuntimeError::new(format!(
+                "import cycle: {}",
+                cycle.join(" -> ")
+            )));
+        }
+
+        self.module_files.push(path.to_path_buf());
+        let shown = path.display();
+        let source = std::fs::read_to_string(path)
+            .map_err(|error| RuntimeError::new(format!("could not read {shown}: {error}")))?;
+
+        let mut lexer = Lexer::new(&source);
+        lexer.set_newline_terminators(true);
+
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+
+        if !parser.errors().is_empty() {
+            let errors: Vec<String> = parser
+                .errors()
+                .iter()
+                .map(|error| format!("{shown}:{error}"))
+                .collect();
+            return Err(RuntimeError::new(errors.join("\n")));
+        }
+
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        let outer = std::mem::replace(&mut self.env, Rc::clone(&globals));
+        let outer_globals = std::mem::replace(&mut self.globals, globals);
+        self.importing.push(path.to_path_buf());
+        let result = self.eval_program(&program);
+        self.importing.pop();
+        self.globals = outer_globals;
+        let env = std::mem::replace(&mut self.env, outer);
+
+        // Kept, so running out of fuel or calling `exit` in a module still
+        // ends the program.
+        result.map_err(|error| RuntimeError {
+            kind: error.kind,
+            ..RuntimeError::new(format!("{shown}:{error}"))
+        })?;
+
+        let pairs = env
+            .borrow()
+            .bindings()
+            .into_iter()
+            .map(|(name, value)| (HashKey::Str(name.to_string()), value))
+            .collect();
+        Ok(Value::hash(pairs))
+    }
+
+    fn eval_let_statement(&mut self, statement: &LetStatement) -> Eval<()> {
+        let value = match (&statement.pattern, &statement.value) {
+            // A function bound straight to a name takes that name, so stack
+            // traces can mention it.
+            (
+                Pattern::Identifier(name),
+                Expression::Function {
+                    parameters, body, ..
+                },
+            ) => self.make_function(Some(name), parameters, body),
+            (_, value) => self.eval_expression(value)?,
+        };
+        self.bind_pattern(&statement.pattern, value, statement.kind)
+            .map_err(|error| error.at(statement.span))?;
+
+        Ok(())
+    }
+
+    /// Declares the names in `pattern` in the current environment, taking their
+    /// values from the matching parts of `value`.
+    fn bind_pattern(
+        &mut self,
+        pattern: &Pattern,
+        value: Value,
+        kind: DeclarationKind,
+    ) -> Result<(), RuntimeError> {
+        match (pattern, value) {
+            (Pattern::Identifier(name), value) => self.env.borrow_mut().declare(name, kind, value),
+            (Pattern::Wildcard, _) => Ok(()),
+            (Pattern::Literal(literal), value) if value == literal_value(literal) => Ok(()),
+            (Pattern::Literal(literal), value) => Err(RuntimeError::new(format!(
+                "cannot destructure {} as {literal}",
+                value.type_name()
+            ))),
+            (Pattern::Array(patterns), Value::Array(elements)) => {
+                let elements = elements.borrow().clone();
+                if elements.len() != patterns.len() {
+                    return Err(RuntimeError::new(format!(
+                        "cannot destructure an array of length {} into {} names",
+                        elements.len(),
+                        patterns.len()
+                    )));
+                }
+
+                for (pattern, element) in patterns.iter().zip(elements) {
+                    self.bind_pattern(pattern, element, kind)?;
+                }
+                Ok(())
+            }
+            (Pattern::Tuple(patterns), Value::Tuple(elements)) => {
+                if elements.len() != patterns.len() {
+                    return Err(RuntimeError::new(format!(
+                        "cannot destructure a tuple of length {} into {} names",
+                        elements.len(),
+                        patterns.len()
+                    )));
+                }
+
+                for (pattern, element) in patterns.iter().zip(elements.iter()) {
+                    self.bind_pattern(pattern, element.clone(), kind)?;
+                }
+                Ok(())
+            }
+            (Pattern::Hash(entries), Value::Hash(pairs)) => {
+                for (key, pattern) in entries {
+                    let value = pairs
+                        .borrow()
+                        .get(&HashKey::Str(key.clone()))
+                        .cloned()
+                        .unwrap_or(Value::Null);
+                    self.bind_pattern(pattern, value, kind)?;
+                }
+                Ok(())
+            }
+            (Pattern::Struct { name, fields }, Value::Struct(instance))
+                if instance.ty.name == name.as_str() =>
+            {
+                for (field, pattern) in fields {
+                    let position = instance.ty.position(field).ok_or_else(|| {
+                        RuntimeError::new(format!("{name} has no field `{field}`"))
+                    })?;
+                    let value = instance.fields.borrow()[position].clone();
+                    self.bind_pattern(pattern, value, kind)?;
+                }
+                Ok(())
+            }
+            (Pattern::Variant { name, fields }, Value::Variant(variant))
+                if variant.ty.name == name.as_str() =>
+            {
+                if variant.values.len() != fields.len() {
+                    return Err(RuntimeError::new(format!(
+                        "wrong number of fields in a {name} pattern: expected {}, got {}",
+                        variant.values.len(),
+                        fields.len()
+                    )));
+                }
+
+                for (pattern, value) in fields.iter().zip(variant.values.iter()) {
+                    self.bind_pattern(pattern, value.clone(), kind)?;
+                }
+                Ok(())
+            }
+            (Pattern::Array(_), value) => Err(RuntimeError::new(format!(
+                "cannot destructure {} as an array",
+                value.type_name()
+            ))),
+            (Pattern::Tuple(_), value) => Err(RuntimeError::new(format!(
+                "cannot destructure {} as a tuple",
+                value.type_name()
+            ))),
+            (Pattern::Hash(_), value) => Err(RuntimeError::new(format!(
+                "cannot destructure {} as a hash",
+                value.type_name()
+            ))),
+            (Pattern::Struct { name, .. }, Value::Struct(instance)) => Err(RuntimeError::new(
+                format!("cannot destructure a {} as a {name}", instance.ty.name),
+            )),
+            (Pattern::Struct { name, .. }, value) => Err(RuntimeError::new(format!(
+                "cannot destructure {} as a {name}",
+                value.type_name()
+            ))),
+            (Pattern::Variant { name, .. }, Value::Variant(variant)) => Err(RuntimeError::new(
+                format!("cannot destructure a {} as a {name}", variant.ty.name),
+            )),
+            (Pattern::Variant { name, .. }, value) => Err(RuntimeError::new(format!(
+                "cannot destructure {} as a {name}",
+                value.type_name()
+            ))),
+        }
+    }
+
+    /// Whether `value` has the shape `pattern` has, so binding it can't
+    /// fail. A name matches anything, except that the name of an enum's
+    /// variant without fields matches only that variant, and a literal
+    /// matches only an equal value.
+    fn matches(&self, pattern: &Pattern, value: &Value) -> bool {
+        match (pattern, value) {
+            (Pattern::Identifier(name), value) => {
+                let declared = self
+                    .env
+                    .borrow()
+                    .get(name)
+                    .or_else(|| self.globals.borrow().get(name));
+                match declared {
+                    Some(Value::Variant(variant))
+                        if variant.values.is_empty() && variant.ty.name == name.as_str() =>
+                    {
+                        *value == Value::Variant(variant)
+                    }
+                    _ => true,
+                }
+            }
+            (Pattern::Wildcard, _) => true,
+            (Pattern::Literal(literal), value) => *value == literal_value(literal),
+            (Pattern::Array(patterns), Value::Array(elements)) => {
+                let elements = elements.borrow();
+                elements.len() == patterns.len()
+                    && patterns
+                        .iter()
+                        .zip(elements.iter())
+                        .all(|(pattern, element)| self.matches(pattern, element))
+            }
+            (Pattern::Tuple(patterns), Value::Tuple(elements)) => {
+                elements.len() == patterns.len()
+                    && patterns
+                        .iter()
+                        .zip(elements.iter())
+                        .all(|(pattern, element)| self.matches(pattern, element))
+            }
+            (Pattern::Hash(entries), Value::Hash(pairs)) => entries.iter().all(|(key, pattern)| {
+                let value = pairs.borrow().get(&HashKey::Str(key.clone())).cloned();
+                self.matches(pattern, &value.unwrap_or(Value::Null))
+            }),
+            (Pattern::Struct { name, fields }, Value::Struct(instance))
+                if instance.ty.name == name.as_str() =>
+            {
+                fields.iter().all(|(field, pattern)| {
+                    instance.ty.position(field).is_some_and(|position| {
+                        let value = instance.fields.borrow()[position].clone();
+                        self.matches(pattern, &value)
+                    })
+                })
+            }
+            (Pattern::Variant { name, fields }, Value::Variant(variant))
+                if variant.ty.name == name.as_str() =>
+            {
+                variant.values.len() == fields.len()
+                    && fields
+                        .iter()
+                        .zip(variant.values.iter())
+                        .all(|(pattern, value)| self.matches(pattern, value))
+            }
+            _ => false,
+        }
+    }
+
+    /// Runs the body of the first of `arms` whose pattern `subject` has the
+    /// shape of, with what the pattern binds in scope.
+    fn eval_match_expression(
+        &mut self,
+        subject: &Expression,
+        arms: &[MatchArm],
+        span: Span,
+    ) -> Eval<Value> {
+        let subject = self.eval_expression(subject)?;
+        let Some(arm) = arms.iter().find(|arm| self.matches(&arm.pattern, &subject)) else {
+            return Err(
+                RuntimeError::new(format!("no `match` arm matches {subject}"))
+                    .at(span)
+                    .into(),
+            );
+        };
+
+        let scope = Environment::enclosed(Rc::clone(&self.env));
+        self.in_environment(scope, |this| {
+            this.bind_pattern(&arm.pattern, subject, DeclarationKind::Let)?;
+            this.eval_block(&arm.body)
+        })
+    }
+
+    /// Runs `body`, and if it raises an error a `try` can catch, runs
+    /// `handler` with the error bound to `pattern` instead.
+    fn eval_try_expression(
+        &mut self,
+        body: &BlockStatement,
+        pattern: &Pattern,
+        handler: &BlockStatement,
+    ) -> Eval<Value> {
+        let error = match self.eval_block(body) {
+            // A call in tail position would otherwise be made after the
+            // `try` was left, where its errors would escape it.
+            Err(Unwind::TailCall {
+                function,
+                arguments,
+                call_site,
+            }) => match self.apply_function(function, arguments, call_site) {
+                Ok(value) => return Err(Unwind::Return(value)),
+                Err(error) => error,
+            },
+            Err(Unwind::Error(error)) => error,
+            result => return result,
+        };
+
+        if !error.is_catchable() {
+            return Err(error.into());
+        }
+
+        let scope = Environment::enclosed(Rc::clone(&self.env));
+        self.in_environment(scope, |this| {
+            this.bind_pattern(pattern, error.into_value(), DeclarationKind::Let)?;
+            this.eval_block(handler)
+        })
+    }
+
+    /// Runs one iteration of a loop, with `variable` bound for the duration
+    /// if given. Returns `false` if the body asked to `break`.
+    fn eval_loop_body(
+        &mut self,
+        body: &BlockStatement,
+        variable: Option<(&Pattern, Value)>,
+    ) -> Eval<bool> {
+        // An empty body takes no steps of its own.
+        self.step()?;
+        let scope = Environment::enclosed(Rc::clone(&self.env));
+        let result = self.in_environment(scope, |this| match variable {
+            Some((pattern, value)) => this
+                .bind_pattern(pattern, value, DeclarationKind::Let)
+                .map_err(Unwind::from)
+                .and_then(|()| this.eval_block(body)),
+            None => this.eval_block(body),
+        });
+
+        match result {
+            Ok(_) | Err(Unwind::Continue) => Ok(true),
+            Err(Unwind::Break) => Ok(false),
+            Err(unwind) => Err(unwind),
+        }
+    }
+
+    /// Evaluates `block` in an environment of its own.
+    fn eval_block(&mut self, block: &BlockStatement) -> Eval<Value> {
+        let scope = Environment::enclosed(Rc::clone(&self.env));
+        self.in_environment(scope, |this| {
+            let mut result = Value::Null;
+            for statement in &block.statements {
+                result = this.eval_statement(statement)?;
+            }
+            Ok(result)
+        })
+    }
+
+    /// Like [`Evaluator::eval_block`], for a block whose value is returned
+    /// from the function it's in, so its last expression is in tail
+    /// position.
+    fn eval_tail_block(&mut self, block: &BlockStatement) -> Eval<Value> {
+        let scope = Environment::enclosed(Rc::clone(&self.env));
+        self.in_environment(scope, |this| {
+            let Some((last, rest)) = block.statements.split_last() else {
+                return Ok(Value::Null);
+            };
+            for statement in rest {
+                this.eval_statement(statement)?;
+            }
+            match last {
+                Statement::Expression(expression) => {
+                    this.debug(last)?;
+                    this.eval_tail_expression(expression)
+                }
+                statement => this.eval_statement(statement),
+            }
+        })
+    }
+
+    /// Evaluates an expression whose value is returned from the function
+    /// it's in. A call there isn't made here but unwinds as an
+    /// [`Unwind::TailCall`], and so does one in the taken branch of an `if`
+    /// or conditional there.
+    fn eval_tail_expression(&mut self, expression: &Expression) -> Eval<Value> {
+        match expression {
+            Expression::Call {
+                function,
+                arguments,
+                span,
+            } => {
+                let function = pinned(self.eval_expression(function), Some(*span))?;
+                let arguments = pinned(self.eval_arguments(arguments), Some(*span))?;
+                Err(Unwind::TailCall {
+                    function,
+                    arguments,
+                    call_site: *span,
+                })
+            }
+            Expression::MethodCall {
+                receiver,
+                method,
+                arguments,
+                span,
+            } => {
+                let (function, arguments) = pinned(
+                    self.eval_method_call(receiver, method, arguments),
+                    Some(*span),
+                )?;
+                Err(Unwind::TailCall {
+                    function,
+                    arguments,
+                    call_site: *span,
+                })
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                if self.eval_expression(condition)?.is_truthy() {
+                    self.eval_tail_block(consequence)
+                } else if let Some(alternative) = alternative {
+                    self.eval_tail_block(alternative)
+                } else {
+                    Ok(Value::Null)
+                }
+            }
+            Expression::Conditional {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                if self.eval_expression(condition)?.is_truthy() {
+                    self.eval_tail_expression(consequence)
+                } else {
+                    self.eval_tail_expression(alternative)
+                }
+            }
+            expression => self.eval_expression(expression),
+        }
+    }
+
+    /// Runs `f` with `env` as the current environment, restoring the
+    /// previous one afterwards whether or not `f` succeeds.
+    fn in_environment<T>(&mut self, env: Environment, f: impl FnOnce(&mut Self) -> T) -> T {
+        let previous = std::mem::replace(&mut self.env, Rc::new(RefCell::new(env)));
+        let result = f(self);
+        self.env = previous;
+        result
+    }
+
+    /// Evaluates `expression`, pinning any error raised inside it that
+    /// doesn't yet know where it happened on the expression's span.
+    fn eval_expression(&mut self, expression: &Expression) -> Eval<Value> {
+        let result = pinned(
+            with_stack(|| self.eval_expression_node(expression)),
+            expression.span(),
+        );
+        if self.trace.is_some() {
+            let node = one_line(&expression.to_string());
+            match &result {
+                Ok(value) => {
+                    let line = format!("{node} => {}", one_line(&value.to_string()));
+                    self.trace(self.depth, &line)
+                }
+                Err(Unwind::Error(error)) => {
+                    let line = format!("{node} => error: {}", error.message);
+                    self.trace(self.depth, &line)
+                }
+                Err(_) => {}
+            }
+        }
+        result
+    }
+
+    fn eval_expression_node(&mut self, expression: &Expression) -> Eval<Value> {
+        self.step()?;
+        match expression {
+            Expression::Identifier {
+                name, global, slot, ..
+            } => self.lookup(name, global.get(), slot.get()),
+            Expression::Int(value) => Ok(Value::Int(*value)),
+            Expression::Float(value) => Ok(Value::Float(*value)),
+            Expression::Str(value) => Ok(Value::Str(value.clone())),
+            Expression::Char(value) => Ok(Value::Char(*value)),
+            Expression::Bool(value) => Ok(Value::Bool(*value)),
+            Expression::Null => Ok(Value::Null),
+            Expression::Prefix {
+                operator, right, ..
+            } => {
+                let right = self.eval_expression(right)?;
+                Ok(eval_prefix_expression(*operator, right, self.overflow)?)
+            }
+            Expression::Infix {
+                left,
+                operator: InfixOperator::And,
+                right,
+                ..
+            } => {
+                let left = self.eval_expression(left)?;
+                if !left.is_truthy() {
+                    return Ok(Value::Bool(false));
+                }
+                Ok(Value::Bool(self.eval_expression(right)?.is_truthy()))
+            }
+            Expression::Infix {
+                left,
+                operator: InfixOperator::Or,
+                right,
+                ..
+            } => {
+                let left = self.eval_expression(left)?;
+                if left.is_truthy() {
+                    return Ok(Value::Bool(true));
+                }
+                Ok(Value::Bool(self.eval_expression(right)?.is_truthy()))
+            }
+            Expression::Infix {
+                left,
+                operator: InfixOperator::Coalesce,
+                right,
+                ..
+            } => match self.eval_expression(left)? {
+                Value::Null => self.eval_expression(right),
+                left => Ok(left),
+            },
+            Expression::Infix {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                let left = self.eval_expression(left)?;
+                let right = self.eval_expression(right)?;
+                Ok(eval_infix_expression(
+                    *operator,
+                    left,
+                    right,
+                    self.overflow,
+                )?)
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                if self.eval_expression(condition)?.is_truthy() {
+                    self.eval_block(consequence)
+                } else if let Some(alternative) = alternative {
+                    self.eval_block(alternative)
+                } else {
+                    Ok(Value::Null)
+                }
+            }
+            Expression::Conditional {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                if self.eval_expression(condition)?.is_truthy() {
+                    self.eval_expression(consequence)
+                } else {
+                    self.eval_expression(alternative)
+                }
+            }
+            Expression::Try {
+                body,
+                pattern,
+                handler,
+            } => self.eval_try_expression(body, pattern, handler),
+            Expression::Match {
+                subject,
+                arms,
+                span,
+            } => self.eval_match_expression(subject, arms, *span),
+            Expression::Function {
+                parameters, body, ..
+            } => Ok(self.make_function(None, parameters, body)),
+            Expression::Call {
+                function,
+                arguments,
+                span,
+            } => {
+                let function = self.eval_expression(function)?;
+                let arguments = self.eval_arguments(arguments)?;
+                Ok(self.apply_function(function, arguments, *span)?)
+            }
+            Expression::MethodCall {
+                receiver,
+                method,
+                arguments,
+                span,
+            } => {
+                let (function, arguments) = self.eval_method_call(receiver, method, arguments)?;
+                Ok(self.apply_function(function, arguments, *span)?)
+            }
+            Expression::Array(elements) => Ok(Value::array(self.eval_expressions(elements)?)),
+            Expression::Tuple(elements) => Ok(Value::Tuple(
+                elements
+                    .iter()
+                    .map(|element| self.eval_expression(element))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Expression::Spread(_) => Err(RuntimeError::new(
+                "`...` can only be used in call arguments and array literals",
+            )
+            .into()),
+            Expression::Named { .. } => {
+                Err(RuntimeError::new("named arguments can only be used in calls").into())
+            }
+            Expression::Hash(pairs) => {
+                let mut hash = IndexMap::new();
+                for (key, value) in pairs {
+                    let key = self.eval_expression(key)?;
+                    let key = key.hash_key().ok_or_else(|| {
+                        RuntimeError::new(format!("unusable as hash key: {}", key.type_name()))
+                    })?;
+                    let value = self.eval_expression(value)?;
+                    hash.insert(key, value);
+                }
+                Ok(Value::hash(hash))
+            }
+            Expression::Index {
+                left,
+                index,
+                optional,
+                ..
+            } => {
+                let left = self.eval_expression(left)?;
+                if *optional && left == Value::Null {
+                    return Ok(Value::Null);
+                }
+                let index = self.eval_expression(index)?;
+                Ok(eval_index_expression(left, index)?)
+            }
+            Expression::Assign {
+                target,
+                operator,
+                value,
+                ..
+            } => self.eval_assign_expression(target, *operator, value),
+            Expression::Range {
+                start,
+                end,
+                inclusive,
+                ..
+            } => {
+                let start = match start {
+                    Some(start) => Some(self.eval_expression(start)?),
+                    None => None,
+                };
+                let end = match end {
+                    Some(end) => Some(self.eval_expression(end)?),
+                    None => None,
+                };
+                let int = |bound: &Option<Value>| match bound {
+                    Some(Value::Int(value)) => Ok(Some(*value)),
+                    Some(_) => Err(()),
+                    None => Ok(None),
+                };
+                match (int(&start), int(&end)) {
+                    (Ok(start), Ok(end)) => Ok(Value::Range(Range::new(start, end, *inclusive))),
+                    _ => Err(RuntimeError::new(format!(
+                        "range bounds must be Int, got {} and {}",
+                        start.as_ref().map_or("Int", Value::type_name),
+                        end.as_ref().map_or("Int", Value::type_name)
+                    ))
+                    .into()),
+                }
+            }
+        }
+    }
+
+    /// Assigns to an existing binding or to an element of an array or hash,
+    /// returning the value assigned.
+    fn eval_assign_expression(
+        &mut self,
+        target: &Expression,
+        operator: Option<InfixOperator>,
+        value: &Expression,
+    ) -> Eval<Value> {
+        match target {
+            Expression::Identifier {
+                name, global, slot, ..
+            } => {
+                let mut value = self.eval_expression(value)?;
+                if let Some(operator) = operator {
+                    let current = self.lookup(name, global.get(), slot.get())?;
+                    value = eval_infix_expression(operator, current, value, self.overflow)?;
+                }
+
+                if let Some(slot) = slot.get() {
+                    match self.env.borrow_mut().assign_slot(slot, name, value.clone()) {
+                        Ok(result) => return Ok(result.map(|()| value)?),
+                        Err(unassigned) => value = unassigned,
+                    }
+                }
+                let env = if global.get() {
+                    &self.globals
+                } else {
+                    &self.env
+                };
+                env.borrow_mut().assign(name, value.clone())?;
+                Ok(value)
+            }
+            Expression::Index { left, index, .. } => {
+                let left = self.eval_expression(left)?;
+                let index = self.eval_expression(index)?;
+                let mut value = self.eval_expression(value)?;
+                if let Some(operator) = operator {
+                    let current = eval_index_expression(left.clone(), index.clone())?;
+                    value = eval_infix_expression(operator, current, value, self.overflow)?;
+                }
+
+                set_index(&left, index, value.clone())?;
+                self.track_store(&left, &value);
+                Ok(value)
+            }
+            _ => unreachable!("the parser only allows names and indexes as targets"),
+        }
+    }
+
+    /// A function value closed over the current environment. Capturing
+    /// environments is what makes reference cycles, so now and then this
+    /// collects the ones that became garbage.
+    fn make_function(
+        &mut self,
+        name: Option<&Symbol>,
+        parameters: &Parameters,
+        body: &Rc<BlockStatement>,
+    ) -> Value {
+        if self.collector.track(&self.env) {
+            self.collector.collect();
+        }
+
+        Value::Function(Rc::new(Function {
+            name: name.map(Symbol::to_string),
+            parameters: parameters.clone(),
+            body: Rc::clone(body),
+            env: Rc::clone(&self.env),
+        }))
+    }
+
+    /// Evaluates a call's arguments or an array's elements, putting the
+    /// elements of each `...value` in its place.
+    fn eval_expressions(&mut self, expressions: &[Expression]) -> Eval<Vec<Value>> {
+        let mut values = Vec::with_capacity(expressions.len());
+
+        for expression in expressions {
+            match expression {
+                Expression::Spread(value) => {
+                    let value = self.eval_expression(value)?;
+                    let iter = value.iter().ok_or_else(|| {
+                        RuntimeError::new(format!("cannot spread {}", value.type_name()))
+                    })?;
+                    values.extend(iter);
+                }
+                expression => values.push(self.eval_expression(expression)?),
+            }
+        }
+
+        Ok(values)
+    }
+
+    /// Evaluates a method call's receiver and arguments, and finds the
+    /// function to call them with among the methods of the receiver's type.
+    fn eval_method_call(
+        &mut self,
+        receiver: &Expression,
+        method: &Symbol,
+        arguments: &[Expression],
+    ) -> Eval<(Value, Arguments)> {
+        let receiver = self.eval_expression(receiver)?;
+        let (function, receiver) = self.builtins.method_call(receiver, method.as_str())?;
+
+        let mut arguments = self.eval_arguments(arguments)?;
+        if let Some(receiver) = receiver {
+            arguments.positional.insert(0, receiver);
+        }
+        Ok((function, arguments))
+    }
+
+    /// Evaluates a call's arguments. The parser puts the named ones last.
+    fn eval_arguments(&mut self, expressions: &[Expression]) -> Eval<Arguments> {
+        let split = expressions
+            .iter()
+            .position(|expression| matches!(expression, Expression::Named { .. }))
+            .unwrap_or(expressions.len());
+        let positional = self.eval_expressions(&expressions[..split])?;
+        let mut named = Vec::with_capacity(expressions.len() - split);
+
+        for expression in &expressions[split..] {
+            let Expression::Named { name, value } = expression else {
+                unreachable!("positional arguments come before named ones")
+            };
+            named.push((name.clone(), self.eval_expression(value)?));
+        }
+
+        Ok(Arguments { positional, named })
+    }
+
+    /// Binds a function's parameters in the current environment to the
+    /// arguments it was called with, as lined up by [`Arguments::resolve`].
+    /// Parameters left without an argument take their defaults.
+    fn bind_arguments(
+        &mut self,
+        parameters: &Parameters,
+        slots: Vec<Option<Value>>,
+        rest: Vec<Value>,
+    ) -> Eval<()> {
+        for (parameter, slot) in parameters.positional.iter().zip(slots) {
+            let value = match (slot, &parameter.default) {
+                (Some(value), _) => value,
+                (None, Some(default)) => self.eval_expression(default)?,
+                (None, None) => unreachable!("the arguments were resolved"),
+            };
+            self.env
+                .borrow_mut()
+                .declare(&parameter.name, DeclarationKind::Let, value)?;
+        }
+
+        if let Some(name) = &parameters.rest {
+            self.env
+                .borrow_mut()
+                .declare(name, DeclarationKind::Let, Value::array(rest))?;
+        }
+
+        Ok(())
+    }
+
+    /// Calls `function` with its parameters bound in a new environment
+    /// nested in the one it was defined in, so the body sees its own
+    /// parameters and whatever was in scope where it was written, but
+    /// nothing of the caller's. Errors from the body record the call in
+    /// their trace.
+    ///
+    /// A call the body makes in tail position comes back here to be made
+    /// in its place, so a chain of tail calls takes one level of depth and
+    /// leaves one frame in an error's trace, for the last call in it.
+    fn apply_function(
+        &mut self,
+        mut callee: Value,
+        mut arguments: Arguments,
+        mut call_site: Span,
+    ) -> Result<Value, RuntimeError> {
+        loop {
+            // Errors from making the call belong at the call site. The
+            // caller would pin them there anyway, except for tail calls.
+            let function = match callee {
+                Value::Function(function) => function,
+                Value::Closure(closure) => {
+                    if let Some((name, _)) = arguments.named.first() {
+                        return Err(RuntimeError::new(format!(
+                            "compiled functions don't take named arguments, like `{name}`"
+                        ))
+                        .at(call_site));
+                    }
+                    return vm::call(self, &closure, arguments.positional, call_site);
+                }
+                Value::Builtin(builtin) => {
+                    if let Some((name, _)) = arguments.named.first() {
+                        return Err(RuntimeError::new(format!(
+                            "`{}` doesn't take named arguments, like `{name}`",
+                            builtin.name
+                        ))
+                        .at(call_site));
+                    }
+                    let outer = std::mem::replace(&mut self.builtin_call_site, call_site);
+                    let result = builtin.call(self, arguments.positional);
+                    self.builtin_call_site = outer;
+                    return result.map_err(|error| error.at(call_site));
+                }
+                Value::StructType(ty) => {
+                    return field_values(&ty.name, &ty.fields, arguments)
+                        .map(|fields| Value::instance(ty, fields))
+                        .map_err(|error| error.at(call_site))
+                }
+                Value::VariantType(ty) => {
+                    return field_values(&ty.name, &ty.fields, arguments)
+                        .map(|values| Value::variant(ty, values))
+                        .map_err(|error| error.at(call_site))
+                }
+                other => {
+                    return Err(
+                        RuntimeError::new(format!("not a function: {}", other.type_name()))
+                            .at(call_site),
+                    )
+                }
+            };
+
+            let parameters = &function.parameters;
+            let (slots, rest) = arguments
+                .resolve(parameters)
+                .map_err(|error| error.at(call_site))?;
+
+            self.enter_call(function.name.as_deref())
+                .map_err(|error| error.at(call_site))?;
+            if let Some(debugger) = &mut self.debugger {
+                debugger.enter(function.name.as_deref(), call_site);
+            }
+            let locals = Environment::enclosed(Rc::clone(&function.env));
+            let globals = Environment::root(&function.env);
+            let outer_globals = std::mem::replace(&mut self.globals, globals);
+            let result = stacker::maybe_grow(2 * STACK_PER_CALL, STACK_SEGMENT, || {
+                self.in_environment(locals, |this| {
+                    this.bind_arguments(parameters, slots, rest)?;
+                    this.eval_tail_block(&function.body)
+                })
+            });
+            self.globals = outer_globals;
+            self.leave_call();
+            if let Some(debugger) = &mut self.debugger {
+                debugger.leave();
+            }
+
+            match result.map_err(Unwind::stray_loop_control) {
+                Ok(value) | Err(Unwind::Return(value)) => return Ok(value),
+                Err(Unwind::TailCall {
+                    function: next,
+                    arguments: next_arguments,
+                    call_site: next_call_site,
+                }) => (callee, arguments, call_site) = (next, next_arguments, next_call_site),
+                Err(Unwind::Error(mut error)) => {
+                    error.trace.push(Frame {
+                        function: function.name.clone(),
+                        call_site,
+                    });
+                    return Err(error);
+                }
+                Err(Unwind::Break | Unwind::Continue) => unreachable!(),
+            }
+        }
+    }
+
+    /// Counts a call of the Tofu function named `function` as in progress,
+    /// unless that would take more than the maximum depth.
+    pub(crate) fn enter_call(&mut self, function: Option<&str>) -> Result<(), RuntimeError> {
+        if self.depth >= self.max_depth {
+            return Err(RuntimeError::new(format!(
+                "maximum recursion depth {} exceeded",
+                self.max_depth
+            )));
+        }
+        self.depth += 1;
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter(function);
+        }
+        Ok(())
+    }
+
+    /// Counts a call [`Evaluator::enter_call`] counted as finished.
+    pub(crate) fn leave_call(&mut self) {
+        self.depth -= 1;
+        if let Some(profiler) = &mut self.profiler {
+            profiler.leave();
+        }
+    }
+
+    /// How many more calls can be in progress at once.
+    #[cfg(feature = "jit")]
+    pub(crate) fn calls_left(&self) -> usize {
+        self.max_depth.saturating_sub(self.depth)
+    }
+
+    /// Uses up a step's worth of fuel, if fuel is limited, and checks
+    /// memory use is within its limit and there's been no interrupt.
+    pub(crate) fn step(&mut self) -> Result<(), RuntimeError> {
+        self.check_memory(0)?;
+        if self.interrupt.is_some_and(interrupt::take) {
+            return Err(RuntimeError {
+                kind: ErrorKind::Interrupted,
+                ..RuntimeError::new("interrupted")
+            });
+        }
+        match &mut self.fuel {
+            Some(0) => Err(RuntimeError {
+                kind: ErrorKind::FuelExhausted,
+                ..RuntimeError::new("out of fuel")
+            }),
+            Some(fuel) => {
+                *fuel -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// The value of `name`, found by its `slot` if the resolver gave it one
+    /// and the declaration it's for has run, or else by searching for it.
+    fn lookup(&self, name: &Symbol, global: bool, slot: Option<Slot>) -> Eval<Value> {
+        if let Some(value) = slot.and_then(|slot| self.env.borrow().get_slot(slot, name)) {
+            return Ok(value);
+        }
+        let env = if global { &self.globals } else { &self.env };
+        env.borrow()
+            .get(name)
+            .or_else(|| self.builtins.lookup(name))
+            .ok_or_else(|| RuntimeError::new(format!("identifier not found: {name}")).into())
+    }
+}
+
+/// `text` on one line, and cut short if it's long, for the trace.
+pub(crate) fn one_line(text: &str) -> String {
+    const MAX: usize = 60;
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(MAX) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text,
+    }
+}
+
+/// `result`, with an error that doesn't yet know where it happened pinned
+/// on `span`.
+fn pinned<T>(result: Eval<T>, span: Option<Span>) -> Eval<T> {
+    match (result, span) {
+        (Err(Unwind::Error(error)), Some(span)) => Err(error.at(span).into()),
+        (result, _) => result,
+    }
+}
+
+pub(crate) fn eval_prefix_expression(
+    operator: PrefixOperator,
+    right: Value,
+    overflow: Overflow,
+) -> Result<Value, RuntimeError> {
+    match (operator, right) {
+        (PrefixOperator::Bang, right) => Ok(Value::Bool(!right.is_truthy())),
+        (PrefixOperator::Minus, Value::Int(value)) => match overflow {
+            Overflow::Wrap => Ok(Value::Int(value.wrapping_neg())),
+            Overflow::Error => value
+                .checked_neg()
+                .map(Value::Int)
+                .ok_or_else(|| RuntimeError::new(format!("integer overflow: -({value})"))),
+            #[cfg(feature = "bigint")]
+            Overflow::Promote => Ok(bigint::normalize(-num_bigint::BigInt::from(value))),
+        },
+        #[cfg(feature = "bigint")]
+        (PrefixOperator::Minus, Value::BigInt(value)) => Ok(bigint::normalize(-&*value)),
+        (PrefixOperator::Minus, Value::Float(value)) => Ok(Value::Float(-value)),
+        (operator, right) => Err(RuntimeError::new(format!(
+            "unknown operator: {operator}{}",
+            right.type_name()
+        ))),
+    }
+}
+
+/// The values of the fields `names` of `owner`, a struct or an enum's
+/// variant, given in order or by name, like a function's parameters. None
+/// can be left out.
+fn field_values(
+    owner: &str,
+    names: &[String],
+    arguments: Arguments,
+) -> Result<Vec<Value>, RuntimeError> {
+    let Arguments { positional, named } = arguments;
+    let given = positional.len() + named.len();
+    if given != names.len() {
+        return Err(RuntimeError::new(format!(
+            "wrong number of arguments to `{owner}`: expected {}, got {given}",
+            names.len()
+        )));
+    }
+
+    let mut fields: Vec<Option<Value>> = positional.into_iter().map(Some).collect();
+    fields.resize(names.len(), None);
+    for (name, value) in named {
+        let position = names
+            .iter()
+            .position(|field| field == name.as_str())
+            .ok_or_else(|| RuntimeError::new(format!("{owner} has no field `{name}`")))?;
+        if fields[position].is_some() {
+            return Err(RuntimeError::new(format!("argument `{name}` given twice")));
+        }
+        fields[position] = Some(value);
+    }
+
+    // As many were given as there are fields, none twice, so all of them were.
+    Ok(fields
+        .into_iter()
+        .map(|field| field.expect("every field is given"))
+        .collect())
+}
+
+/// Indexing past either end of an array is an error rather than `null`, so
+/// mistakes surface where they happen. Hashes are the exception below.
+pub(crate) fn eval_index_expression(left: Value, index: Value) -> Result<Value, RuntimeError> {
+    match (&left, &index) {
+        (Value::Array(elements), Value::Int(position)) => {
+            let elements = elements.borrow();
+            element_at(&elements, *position).cloned()
+        }
+        (Value::Array(elements), Value::Range(range)) => {
+            let elements = elements.borrow();
+            let (start, end) = slice_bounds(*range, elements.len(), "an array")?;
+            Ok(Value::array(elements[start..end].to_vec()))
+        }
+        (Value::Array(_), index) => Err(RuntimeError::new(format!(
+            "array index must be an Int, got {}",
+            index.type_name()
+        ))),
+        // Strings are indexed by character, not by byte.
+        (Value::Str(value), Value::Int(position)) => {
+            let chars: Vec<char> = value.chars().collect();
+            element_at(&chars, *position).map(|char| Value::Char(*char))
+        }
+        (Value::Str(value), Value::Range(range)) => {
+            let chars: Vec<char> = value.chars().collect();
+            let (start, end) = slice_bounds(*range, chars.len(), "a string")?;
+            Ok(Value::Str(chars[start..end].iter().collect()))
+        }
+        (Value::Str(_), index) => Err(RuntimeError::new(format!(
+            "string index must be an Int, got {}",
+            index.type_name()
+        ))),
+        (Value::Tuple(elements), Value::Int(position)) => element_at(elements, *position).cloned(),
+        (Value::Tuple(_), index) => Err(RuntimeError::new(format!(
+            "tuple index must be an Int, got {}",
+            index.type_name()
+        ))),
+        (Value::Struct(instance), Value::Str(field)) => {
+            let position = field_position(instance, field)?;
+            Ok(instance.fields.borrow()[position].clone())
+        }
+        (Value::Struct(_), index) => Err(RuntimeError::new(format!(
+            "struct field must be a Str, got {}",
+            index.type_name()
+        ))),
+        (Value::Variant(variant), Value::Str(field)) => {
+            let position = variant.ty.fields.iter().position(|name| name == field);
+            let position = position.ok_or_else(|| {
+                RuntimeError::new(format!("{} has no field `{field}`", variant.ty.name))
+            })?;
+            Ok(variant.values[position].clone())
+        }
+        (Value::Variant(_), index) => Err(RuntimeError::new(format!(
+            "variant field must be a Str, got {}",
+            index.type_name()
+        ))),
+        // A missing key is `null`, so hashes can be probed without checking
+        // first.
+        (Value::Hash(pairs), index) => match index.hash_key() {
+            Some(key) => Ok(pairs.borrow().get(&key).cloned().unwrap_or(Value::Null)),
+            None => Err(RuntimeError::new(format!(
+                "unusable as hash key: {}",
+                index.type_name()
+            ))),
+        },
+        (left, _) => Err(RuntimeError::new(format!(
+            "cannot index into {}",
+            left.type_name()
+        ))),
+    }
+}
+
+/// The value a literal pattern matches.
+fn literal_value(literal: &Literal) -> Value {
+    match literal {
+        Literal::Int(value) => Value::Int(*value),
+        Literal::Str(value) => Value::Str(value.clone()),
+        Literal::Char(value) => Value::Char(*value),
+        Literal::Bool(value) => Value::Bool(*value),
+        Literal::Null => Value::Null,
+    }
+}
+
+/// Where `field` is among `instance`'s fields, or an error if it has no
+/// such field.
+fn field_position(instance: &Struct, field: &str) -> Result<usize, RuntimeError> {
+    instance
+        .ty
+        .position(field)
+        .ok_or_else(|| RuntimeError::new(format!("{} has no field `{field}`", instance.ty.name)))
+}
+
+/// The element at `position`, counting back from the end if it's negative,
+/// or an error if it's past either end.
+fn element_at<T>(elements: &[T], position: i64) -> Result<&T, RuntimeError> {
+    usize::try_from(from_end(position, elements.len()))
+        .ok()
+        .and_then(|position| elements.get(position))
+        .ok_or_else(|| {
+            RuntimeError::new(format!(
+                "index out of bounds: the length is {} but the index is {position}",
+                elements.len()
+            ))
+        })
+}
+
+/// `position` counted from the start of something of length `len`, where
+/// `-1` is the last element, `-2` the one before, and so on. It's still
+/// negative if it's before the start.
+fn from_end(position: i64, len: usize) -> i64 {
+    if position < 0 {
+        position.saturating_add(len as i64)
+    } else {
+        position
+    }
+}
+
+/// `range` as start and end positions for slicing something of length
+/// `len`, or an error naming `what` if it doesn't fit. Negative bounds
+/// count back from the end, as indexes do, so `-2..=-1` is the last two.
+fn slice_bounds(range: Range, len: usize, what: &str) -> Result<(usize, usize), RuntimeError> {
+    let start = usize::try_from(from_end(range.start, len)).ok();
+    let end = from_end(range.end, len);
+    let end = if range.inclusive {
+        end.checked_add(1)
+    } else {
+        Some(end)
+    };
+    let end = end.and_then(|end| usize::try_from(end).ok());
+
+    match (start, end) {
+        (Some(start), Some(end)) if start <= end && end <= len => Ok((start, end)),
+        _ => Err(RuntimeError::new(format!(
+            "slice {range} out of bounds for {what} of length {len}"
+        ))),
+    }
+}
+
+/// Stores `value` at `index` in an array, hash or struct. Arrays and
+/// structs can only have existing elements replaced; hashes gain the key if
+/// it's new.
+pub(crate) fn set_index(left: &Value, index: Value, value: Value) -> Result<(), RuntimeError> {
+    match (left, &index) {
+        (Value::Array(elements), Value::Int(position)) => {
+            let mut elements = elements.borrow_mut();
+            let length = elements.len();
+            let element = usize::try_from(from_end(*position, length))
+                .ok()
+                .and_then(|position| elements.get_mut(position))
+                .ok_or_else(|| {
+                    RuntimeError::new(format!(
+                        "index out of bounds: the length is {length} but the index is {position}"
+                    ))
+                })?;
+            *element = value;
+            Ok(())
+        }
+        (Value::Array(_), index) => Err(RuntimeError::new(format!(
+            "array index must be an Int, got {}",
+            index.type_name()
+        ))),
+        (Value::Hash(pairs), index) => {
+            let key = index.hash_key().ok_or_else(|| {
+                RuntimeError::new(format!("unusable as hash key: {}", index.type_name()))
+            })?;
+            pairs.borrow_mut().insert(key, value);
+            Ok(())
+        }
+        (Value::Struct(instance), Value::Str(field)) => {
+            let position = field_position(instance, field)?;
+            instance.fields.borrow_mut()[position] = value;
+            Ok(())
+        }
+        (Value::Struct(_), index) => Err(RuntimeError::new(format!(
+            "struct field must be a Str, got {}",
+            index.type_name()
+        ))),
+        (left, _) => Err(RuntimeError::new(format!(
+            "cannot assign into {}",
+            left.type_name()
+        ))),
+    }
+}
+
+pub(crate) fn eval_infix_expression(
+    operator: InfixOperator,
+    left: Value,
+    right: Value,
+    overflow: Overflow,
+) -> Result<Value, RuntimeError> {
+    match (&left, &right) {
+        (Value::Int(left), Value::Int(right)) => {
+            eval_int_infix_expression(operator, *left, *right, overflow)
+        }
+        #[cfg(feature = "bigint")]
+        (Value::Int(_) | Value::BigInt(_), Value::Int(_) | Value::BigInt(_)) => {
+            bigint::eval_infix_expression(operator, &bigint::to_big(&left), &bigint::to_big(&right))
+        }
+        (Value::Float(left), Value::Float(right)) => {
+            eval_float_infix_expression(operator, *left, *right)
+        }
+        // An Int meeting a Float is converted to a Float, so `1 + 2.5` is
+        // `3.5` and `1 == 1.0`. Two Ints always give an Int, so `7 / 2` is
+        // `3`: integer division truncates towards zero.
+        (Value::Int(left), Value::Float(right)) => {
+            eval_float_infix_expression(operator, *left as f64, *right)
+        }
+        (Value::Float(left), Value::Int(right)) => {
+            eval_float_infix_expression(operator, *left, *right as f64)
+        }
+        #[cfg(feature = "bigint")]
+        (Value::BigInt(left), Value::Float(right)) => {
+            eval_float_infix_expression(operator, bigint::to_float(left), *right)
+        }
+        #[cfg(feature = "bigint")]
+        (Value::Float(left), Value::BigInt(right)) => {
+            eval_float_infix_expression(operator, *left, bigint::to_float(right))
+        }
+        (Value::Str(left), Value::Str(right)) if operator == InfixOperator::Plus => {
+            Ok(Value::Str(format!("{left}{right}")))
+        }
+        // Strings compare lexicographically by character.
+        (Value::Str(left), Value::Str(right)) if operator.is_comparison() => {
+            Ok(compare(operator, left.cmp(right)))
+        }
+        (Value::Char(left), Value::Char(right)) if operator.is_comparison() => {
+            Ok(compare(operator, left.cmp(right)))
+        }
+        // Equal arrays and tuples are equal element for element, so only
+        // the orderings need comparing here.
+        (Value::Array(_), Value::Array(_)) | (Value::Tuple(_), Value::Tuple(_))
+            if operator.is_comparison()
+                && !matches!(operator, InfixOperator::Eq | InfixOperator::NotEq) =>
+        {
+            Ok(compare(operator, ordering(&left, &right)?))
+        }
+        _ => match operator {
+            InfixOperator::Eq => Ok(Value::Bool(left == right)),
+            InfixOperator::NotEq => Ok(Value::Bool(left != right)),
+            _ if left.type_name() != right.type_name() => Err(RuntimeError::new(format!(
+                "type mismatch: {} {operator} {}",
+                left.type_name(),
+                right.type_name()
+            ))),
+            _ => Err(RuntimeError::new(format!(
+                "unknown operator: {} {operator} {}",
+                left.type_name(),
+                right.type_name()
+            ))),
+        },
+    }
+}
+
+/// The natural order of `left` and `right`, which `<` and `sort` use:
+/// numbers by value, strings and chars by character, and arrays and tuples
+/// lexicographically, by their first elements that differ, or, if one
+/// starts with the other, by length. It's a total order over the values it
+/// orders. Anything else, like values of different types, NaN, or arrays
+/// with elements that can't be ordered, is an error.
+pub(crate) fn ordering(left: &Value, right: &Value) -> Result<Ordering, RuntimeError> {
+    ordering_in(left, right, &mut HashSet::new())
+}
+
+/// Like [`ordering`], given that each pair of arrays in `comparing` is
+/// equal, so ordering arrays that contain themselves ends.
+fn ordering_in(
+    left: &Value,
+    right: &Value,
+    comparing: &mut HashSet<(usize, usize)>,
+) -> Result<Ordering, RuntimeError> {
+    let sequences = |left: &[Value], right: &[Value], comparing: &mut HashSet<_>| {
+        for (a, b) in left.iter().zip(right) {
+            match ordering_in(a, b, comparing)? {
+                Ordering::Equal => {}
+                unequal => return Ok(unequal),
+            }
+        }
+        Ok(left.len().cmp(&right.len()))
+    };
+    let number = |value: &Value| match value {
+        Value::Int(value) => *value as f64,
+        Value::Float(value) => *value,
+        _ => unreachable!("only called with numbers"),
+    };
+
+    match (left, right) {
+        (Value::Int(a), Value::Int(b)) => Ok(a.cmp(b)),
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => number(left)
+            .partial_cmp(&number(right))
+            .ok_or_else(|| RuntimeError::new(format!("cannot order NaN: {left} and {right}"))),
+        (Value::Array(a), Value::Array(b)) => {
+            if Rc::ptr_eq(a, b) || !comparing.insert((address(a), address(b))) {
+                return Ok(Ordering::Equal);
+            }
+            sequences(&a.borrow(), &b.borrow(), comparing)
+        }
+        (Value::Tuple(a), Value::Tuple(b)) => sequences(a, b, comparing),
+        _ => {
+            let less = |a: &Value, b: &Value| {
+                eval_infix_expression(
+                    InfixOperator::LessThan,
+                    a.clone(),
+                    b.clone(),
+                    Overflow::Error,
+                )
+                .map(|less| less.is_truthy())
+            };
+            if less(left, right)? {
+                Ok(Ordering::Less)
+            } else if less(right, left)? {
+                Ok(Ordering::Greater)
+            } else {
+                Ok(Ordering::Equal)
+            }
+        }
+    }
+}
+
+/// Applies a comparison operator to the result of comparing its operands.
+pub(crate) fn compare(operator: InfixOperator, ordering: Ordering) -> Value {
+    let result = match operator {
+        InfixOperator::Eq => ordering.is_eq(),
+        InfixOperator::NotEq => ordering.is_ne(),
+        InfixOperator::LessThan => ordering.is_lt(),
+        InfixOperator::GreaterThan => ordering.is_gt(),
+        InfixOperator::LessThanEq => ordering.is_le(),
+        InfixOperator::GreaterThanEq => ordering.is_ge(),
+        _ => unreachable!("only called with comparison operators"),
+    };
+
+    Value::Bool(result)
+}
+
+fn eval_int_infix_expression(
+    operator: InfixOperator,
+    left: i64,
+    right: i64,
+    overflow: Overflow,
+) -> Result<Value, RuntimeError> {
+    let arithmetic = |checked: fn(i64, i64) -> Option<i64>, wrapping: fn(i64, i64) -> i64| {
+        let result = match overflow {
+            Overflow::Error => checked(left, right).ok_or_else(|| {
+                RuntimeError::new(format!("integer overflow: {left} {operator} {right}"))
+            })?,
+            Overflow::Wrap => wrapping(left, right),
+            #[cfg(feature = "bigint")]
+            Overflow::Promote => match checked(left, right) {
+                Some(result) => result,
+                None => {
+                    return bigint::eval_infix_expression(operator, &left.into(), &right.into())
+                }
+            },
+        };
+        Ok(Value::Int(result))
+    };
+
+    let value = match operator {
+        InfixOperator::Plus => arithmetic(i64::checked_add, i64::wrapping_add)?,
+        InfixOperator::Minus => arithmetic(i64::checked_sub, i64::wrapping_sub)?,
+        InfixOperator::Multiply => arithmetic(i64::checked_mul, i64::wrapping_mul)?,
+        // Like dividing by zero.
+        InfixOperator::Power if right < 0 && left == 0 => {
+            return Err(RuntimeError::new(format!(
+                "`**` is undefined for {left} and {right}"
+            )))
+        }
+        // A negative power of an Int is a fraction, so a Float.
+        InfixOperator::Power if right < 0 => {
+            return eval_float_infix_expression(operator, left as f64, right as f64)
+        }
+        InfixOperator::Power => {
+            let exponent = right.unsigned_abs();
+            let result = match overflow {
+                Overflow::Error => square_and_multiply(left, exponent, i64::checked_mul)
+                    .ok_or_else(|| {
+                        RuntimeError::new(format!("integer overflow: {left} ** {right}"))
+                    })?,
+                Overflow::Wrap => {
+                    square_and_multiply(left, exponent, |a, b| Some(a.wrapping_mul(b)))
+                        .expect("wrapping never fails")
+                }
+                #[cfg(feature = "bigint")]
+                Overflow::Promote => match square_and_multiply(left, exponent, i64::checked_mul) {
+                    Some(result) => result,
+                    None => {
+                        return bigint::eval_infix_expression(operator, &left.into(), &right.into())
+                    }
+                },
+            };
+            Value::Int(result)
+        }
+        InfixOperator::Divide => {
+            if right == 0 {
+                return Err(RuntimeError::new("division by zero"));
+            }
+            // Only `i64::MIN / -1` can overflow.
+            arithmetic(i64::checked_div, i64::wrapping_div)?
+        }
+        InfixOperator::Eq => Value::Bool(left == right),
+        InfixOperator::NotEq => Value::Bool(left != right),
+        InfixOperator::LessThan => Value::Bool(left < right),
+        InfixOperator::GreaterThan => Value::Bool(left > right),
+        InfixOperator::LessThanEq => Value::Bool(left <= right),
+        InfixOperator::GreaterThanEq => Value::Bool(left >= right),
+        InfixOperator::BitAnd => Value::Int(left & right),
+        InfixOperator::BitOr => Value::Int(left | right),
+        InfixOperator::BitXor => Value::Int(left ^ right),
+        InfixOperator::ShiftLeft | InfixOperator::ShiftRight => {
+            let Some(amount) = u32::try_from(right).ok().filter(|amount| *amount < 64) else {
+                return Err(RuntimeError::new(format!(
+                    "shift amount {right} is out of range"
+                )));
+            };
+            if operator == InfixOperator::ShiftLeft {
+                Value::Int(left << amount)
+            } else {
+                Value::Int(left >> amount)
+            }
+        }
+        InfixOperator::And | InfixOperator::Or | InfixOperator::Coalesce => {
+            unreachable!("logical operators short-circuit before reaching here")
+        }
+    };
+
+    Ok(value)
+}
+
+/// `base` to the power `exponent` by repeated squaring, with `multiply`
+/// deciding what happens when a product doesn't fit.
+pub(crate) fn square_and_multiply(
+    mut base: i64,
+    mut exponent: u64,
+    multiply: impl Fn(i64, i64) -> Option<i64>,
+) -> Option<i64> {
+    let mut result = 1;
+
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = multiply(result, base)?;
+        }
+        exponent >>= 1;
+        // The last square would go unused, and could overflow needlessly.
+        if exponent > 0 {
+            base = multiply(base, base)?;
+        }
+    }
+
+    Some(result)
+}
+
+fn eval_float_infix_expression(
+    operator: InfixOperator,
+    left: f64,
+    right: f64,
+) -> Result<Value, RuntimeError> {
+    let value = match operator {
+        InfixOperator::Plus => Value::Float(left + right),
+        InfixOperator::Minus => Value::Float(left - right),
+        InfixOperator::Multiply => Value::Float(left * right),
+        InfixOperator::Divide => Value::Float(left / right),
+        InfixOperator::Power => {
+            let result = left.powf(right);
+            if result.is_nan() && !left.is_nan() && !right.is_nan() {
+                return Err(RuntimeError::new(format!(
+                    "`**` is undefined for {} and {}",
+                    Value::Float(left),
+                    Value::Float(right)
+                )));
+            }
+            Value::Float(result)
+        }
+        InfixOperator::Eq => Value::Bool(left == right),
+        InfixOperator::NotEq => Value::Bool(left != right),
+        InfixOperator::LessThan => Value::Bool(left < right),
+        InfixOperator::GreaterThan => Value::Bool(left > right),
+        InfixOperator::LessThanEq => Value::Bool(left <= right),
+        InfixOperator::GreaterThanEq => Value::Bool(left >= right),
+        _ => {
+            return Err(RuntimeError::new(format!(
+                "unknown operator: Float {operator} Float"
+            )))
+        }
+    };
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::rc::Rc;
+
+    use super::{ErrorKind, Evaluator, Frame, Overflow, DEFAULT_MAX_DEPTH, STACK_PER_CALL};
+    use crate::builtins::test::eval;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::token::Span;
+    use crate::value::{HashKey, Range, Value};
+    use indexmap::IndexMap;
+
+    fn assert_values(tests: Vec<(&str, Value)>) {
+        for (input, expected) in tests {
+            assert_eq!(Ok(expected), eval(input), "input={input}");
+        }
+    }
+
+    fn assert_errors(tests: Vec<(&str, &str)>) {
+        for (input, expected) in tests {
+            match eval(input) {
+                Err(error) => assert_eq!(expected, error.message, "input={input}"),
+                Ok(value) => panic!("expected an error for {input}, got {value:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_values(vec![
+            ("5", Value::Int(5)),
+            ("-5 + 10 * 2", Value::Int(15)),
+            ("(5 + 10) / 3", Value::Int(5)),
+            ("7 / -2", Value::Int(-3)),
+            ("1 << 4 | 1", Value::Int(17)),
+            ("6 & 3 ^ 1", Value::Int(3)),
+            ("1.5 * 2.0", Value::Float(3.0)),
+            ("-0.5 + 1.0", Value::Float(0.5)),
+        ]);
+    }
+
+    #[test]
+    fn test_power() {
+        assert_values(vec![
+            ("2 ** 10", Value::Int(1024)),
+            ("2 ** 3 ** 2", Value::Int(512)),
+            ("(2 ** 3) ** 2", Value::Int(64)),
+            ("-2 ** 2", Value::Int(-4)),
+            ("(-2) ** 3", Value::Int(-8)),
+            ("2 * 3 ** 2", Value::Int(18)),
+            ("7 ** 0", Value::Int(1)),
+            ("2 ** -1", Value::Float(0.5)),
+            ("4 ** 0.5", Value::Float(2.0)),
+            ("1.5 ** 2", Value::Float(2.25)),
+            ("2 ** 62", Value::Int(1 << 62)),
+            ("(-2) ** 63", Value::Int(i64::MIN)),
+        ]);
+
+        assert_errors(vec![
+            (
+                "(-8) ** (1.0 / 3.0)",
+                "`**` is undefined for -8.0 and 0.3333333333333333",
+            ),
+            ("0 ** -1", "`**` is undefined for 0 and -1"),
+            (r#""a" ** 2"#, "type mismatch: Str ** Int"),
+        ]);
+    }
+
+    #[test]
+    fn test_numeric_coercion() {
+        assert_values(vec![
+            ("1 + 2.5", Value::Float(3.5)),
+            ("2.5 - 1", Value::Float(1.5)),
+            ("3 * 0.5", Value::Float(1.5)),
+            ("7 / 2", Value::Int(3)),
+            ("7 / 2.0", Value::Float(3.5)),
+            ("1 / 0.0", Value::Float(f64::INFINITY)),
+            ("1 == 1.0", Value::Bool(true)),
+            ("1.0 != 1", Value::Bool(false)),
+            ("1 < 1.5", Value::Bool(true)),
+            ("2.5 >= 3", Value::Bool(false)),
+        ]);
+
+        assert_errors(vec![
+            ("1 & 1.0", "unknown operator: Float & Float"),
+            ("1.5 << 1", "unknown operator: Float << Float"),
+        ]);
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        let max = "9223372036854775807";
+        let min = format!("(-{max} - 1)");
+
+        let eval_with = |input: &str, overflow| {
+            let mut parser = Parser::new(Lexer::new(input));
+            let program = parser.parse_program();
+
+            let mut evaluator = Evaluator::new();
+            evaluator.set_overflow(overflow);
+            evaluator.eval_program(&program)
+        };
+
+        assert_values(vec![
+            (&format!("{max} - 1 + 1"), Value::Int(i64::MAX)),
+            (&format!("{min} + 1 - 1"), Value::Int(i64::MIN)),
+        ]);
+
+        let errors = vec![
+            (
+                "255 * 255 * 255 * 255 * 255 * 255 * 255 * 255".to_string(),
+                "integer overflow: 70110209207109375 * 255",
+            ),
+            (
+                format!("{max} + 1"),
+                "integer overflow: 9223372036854775807 + 1",
+            ),
+            (
+                format!("{min} - 1"),
+                "integer overflow: -9223372036854775808 - 1",
+            ),
+            (
+                format!("{min} / -1"),
+                "integer overflow: -9223372036854775808 / -1",
+            ),
+            (
+                format!("-{min}"),
+                "integer overflow: -(-9223372036854775808)",
+            ),
+            (
+                format!("let x = {max}; x += 1"),
+                "integer overflow: 9223372036854775807 + 1",
+            ),
+            ("2 ** 63".to_string(), "integer overflow: 2 ** 63"),
+        ];
+
+        for (input, expected) in errors {
+            let error = eval_with(&input, Overflow::Error).unwrap_err();
+            assert_eq!(expected, error.message, "input={input}");
+        }
+
+        let wrapping = vec![
+            (format!("{max} + 1"), Value::Int(i64::MIN)),
+            (format!("{min} - 1"), Value::Int(i64::MAX)),
+            (format!("{min} / -1"), Value::Int(i64::MIN)),
+            (format!("-{min}"), Value::Int(i64::MIN)),
+            (
+                "255 * 255 * 255 * 255 * 255 * 255 * 255 * 255".to_string(),
+                Value::Int(-568640725896660991),
+            ),
+            ("255 ** 8".to_string(), Value::Int(-568640725896660991)),
+        ];
+
+        for (input, expected) in wrapping {
+            assert_eq!(
+                Ok(expected),
+                eval_with(&input, Overflow::Wrap),
+                "input={input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_comparison_and_logic() {
+        assert_values(vec![
+            ("1 < 2", Value::Bool(true)),
+            ("1 >= 2", Value::Bool(false)),
+            ("1.5 <= 1.5", Value::Bool(true)),
+            ("\"a\" == \"a\"", Value::Bool(true)),
+            ("1 == \"1\"", Value::Bool(false)),
+            ("null == null", Value::Bool(true)),
+            ("!null", Value::Bool(true)),
+            ("!0", Value::Bool(false)),
+            ("true && 1", Value::Bool(true)),
+            ("false || null", Value::Bool(false)),
+            // The right-hand side must not be evaluated at all.
+            ("false && missing", Value::Bool(false)),
+            ("true || missing", Value::Bool(true)),
+        ]);
+    }
+
+    #[test]
+    fn test_nullish_operators() {
+        let user = r#"let user = {"name": "Ada", "pets": ["cat"], "boss": null};"#;
+
+        assert_values(vec![
+            ("null ?? 1", Value::Int(1)),
+            ("false ?? 1", Value::Bool(false)),
+            ("0 ?? missing", Value::Int(0)),
+            ("null ?? null ?? 2", Value::Int(2)),
+            (&format!("{user} user?.name"), Value::Str("Ada".to_string())),
+            (&format!("{user} user?.age ?? 36"), Value::Int(36)),
+            (&format!("{user} user?.boss?.name"), Value::Null),
+            (
+                &format!("{user} user?.boss?[\"name\"] ?? \"nobody\""),
+                Value::Str("nobody".to_string()),
+            ),
+            (
+                &format!("{user} user?[\"pets\"]?[0]"),
+                Value::Str("cat".to_string()),
+            ),
+            // The index isn't evaluated when there is nothing to index.
+            ("null?[missing]", Value::Null),
+            ("[1]?[0]", Value::Int(1)),
+        ]);
+
+        assert_errors(vec![
+            // Each `?.` only guards its own step.
+            ("let user = {}; user?.boss[0]", "cannot index into Null"),
+            ("1?.name", "cannot index into Int"),
+        ]);
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_values(vec![
+            (
+                r#""tofu" + " " + "rocks""#,
+                Value::Str("tofu rocks".to_string()),
+            ),
+            (r#"let s = "a"; s += "b"; s"#, Value::Str("ab".to_string())),
+            (r#""abc" == "abc""#, Value::Bool(true)),
+            (r#""abc" != "abd""#, Value::Bool(true)),
+            (r#""apple" < "banana""#, Value::Bool(true)),
+            (r#""b" > "abc""#, Value::Bool(true)),
+            (r#""ab" < "abc""#, Value::Bool(true)),
+            (r#""abc" <= "abc""#, Value::Bool(true)),
+            (r#""Z" >= "a""#, Value::Bool(false)),
+            (r#""" < "a""#, Value::Bool(true)),
+            ("'a' < 'b'", Value::Bool(true)),
+            (r#""tofu"[0]"#, Value::Char('t')),
+            (r#""tofu"[3]"#, Value::Char('u')),
+            (r#""héllo"[1]"#, Value::Char('é')),
+            (r#""tofu"[1..3]"#, Value::Str("of".to_string())),
+            (r#""héllo"[1..=4]"#, Value::Str("éllo".to_string())),
+            (r#""tofu"[4..4]"#, Value::Str(String::new())),
+            (r#""tofu"[-1]"#, Value::Char('u')),
+            (
+                r#""tofu"[..2] + "tofu"[2..]"#,
+                Value::Str("tofu".to_string()),
+            ),
+            (r#""tofu"[-3..-1]"#, Value::Str("of".to_string())),
+            (r#"""[..]"#, Value::Str(String::new())),
+        ]);
+        assert_errors(vec![
+            (
+                r#""tofu"[4]"#,
+                "index out of bounds: the length is 4 but the index is 4",
+            ),
+            (
+                r#""tofu"[-5]"#,
+                "index out of bounds: the length is 4 but the index is -5",
+            ),
+            (
+                r#""tofu"[2..5]"#,
+                "slice 2..5 out of bounds for a string of length 4",
+            ),
+            (r#""tofu"["a"]"#, "string index must be an Int, got Str"),
+            (r#""a" * "b""#, "unknown operator: Str * Str"),
+            (r#""a" < 1"#, "type mismatch: Str < Int"),
+            (r#"let s = "a"; s[0] = 'b'"#, "cannot assign into Str"),
+        ]);
+    }
+
+    #[test]
+    fn test_if_expressions() {
+        assert_values(vec![
+            ("if (true) { 10 }", Value::Int(10)),
+            ("if (false) { 10 }", Value::Null),
+            ("if (1 > 2) { 10 } else { 20 }", Value::Int(20)),
+            (
+                "let score = 95; let grade = if (score > 90) { \"A\" } else { \"B\" }; grade",
+                Value::Str("A".to_string()),
+            ),
+            (
+                "let n = 5; if (n < 0) { -1 } else if (n == 0) { 0 } else { 1 }",
+                Value::Int(1),
+            ),
+            ("if (true) { let x = 1; }", Value::Null),
+            ("if (true) {}", Value::Null),
+        ]);
+    }
+
+    #[test]
+    fn test_conditional_expressions() {
+        assert_values(vec![
+            ("true ? 1 : 2", Value::Int(1)),
+            ("null ? 1 : 2", Value::Int(2)),
+            ("let n = 0; n < 0 ? -1 : n == 0 ? 0 : 1", Value::Int(0)),
+            ("let x = 1; x = x > 0 ? x * 10 : 0; x", Value::Int(10)),
+            // Only the branch taken is evaluated.
+            ("1 ? 2 : missing", Value::Int(2)),
+            ("false ? missing : 3", Value::Int(3)),
+        ]);
+    }
+
+    #[test]
+    fn test_bindings_and_scopes() {
+        assert_values(vec![
+            ("let a = 5; let b = a * 2; b", Value::Int(10)),
+            ("let a = 1; if (true) { let a = 2; a }", Value::Int(2)),
+            ("let a = 1; if (true) { let a = 2; } a", Value::Int(1)),
+            ("const a = 1; if (true) { let a = 2; a }", Value::Int(2)),
+        ]);
+    }
+
+    #[test]
+    fn test_return_statements() {
+        assert_values(vec![
+            ("return 10; 9", Value::Int(10)),
+            ("9; return 2 * 5; 9", Value::Int(10)),
+            (
+                "if (true) { if (true) { return 10; } return 1; }",
+                Value::Int(10),
+            ),
+            ("return;", Value::Null),
+        ]);
+    }
+
+    #[test]
+    fn test_while_loops() {
+        assert_values(vec![
+            ("while (false) { 1 }", Value::Null),
+            ("while (true) { break; 1 }", Value::Null),
+            (
+                "while (true) { if (false) { continue } break }",
+                Value::Null,
+            ),
+            (
+                "let f = fn() { while (true) { while (true) { break } return 5; } }; f()",
+                Value::Int(5),
+            ),
+            (
+                "let f = fn() { while (true) { if (true) { return 1; } } }; f()",
+                Value::Int(1),
+            ),
+            ("let i = 0; while (i < 10) { i += 1; } i", Value::Int(10)),
+            (
+                "let i = 0; let odd = 0; while (i < 10) { i += 1; if (i / 2 * 2 == i) { continue } odd += 1; } odd",
+                Value::Int(5),
+            ),
+            (
+                "let i = 0; while (true) { i += 1; if (i == 3) { break } } i",
+                Value::Int(3),
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_assignment() {
+        assert_values(vec![
+            ("let x = 1; x = x + 1; x", Value::Int(2)),
+            ("let x = 1; x = 5", Value::Int(5)),
+            ("let a = 1; let b = 2; a = b = 3; a + b", Value::Int(6)),
+            ("let x = 10; x -= 3; x *= 2; x /= 7; x", Value::Int(2)),
+            // Assignment writes to the binding in the nearest enclosing scope.
+            ("let x = 1; if (true) { x = 2; } x", Value::Int(2)),
+            (
+                "let x = 1; if (true) { let x = 5; x = 2; } x",
+                Value::Int(1),
+            ),
+            (
+                "let count = 0; let bump = fn() { count += 1 }; bump(); bump(); count",
+                Value::Int(2),
+            ),
+            (
+                "let xs = [1, 2, 3]; xs[1] = 20; xs[2] += 10; xs",
+                Value::array(vec![Value::Int(1), Value::Int(20), Value::Int(13)]),
+            ),
+            (
+                r#"let h = {"a": 1}; h["b"] = 2; h["a"] += 1; h["a"] + h["b"]"#,
+                Value::Int(4),
+            ),
+            // Arrays are shared, so writes through one name are seen through another.
+            ("let xs = [0]; let ys = xs; ys[0] = 1; xs[0]", Value::Int(1)),
+            (
+                "let total = 0; for (i in 1..=4) { total += i; } total",
+                Value::Int(10),
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_for_loops() {
+        assert_values(vec![
+            ("for (x in []) { 1 }", Value::Null),
+            (
+                "let f = fn(xs) { for (x in xs) { if (x > 1) { return x; } } }; f([1, 2, 3])",
+                Value::Int(2),
+            ),
+            (
+                "let f = fn(xs) { for (x in xs) { if (x < 2) { continue } return x; } }; f([1, 2, 3])",
+                Value::Int(2),
+            ),
+            (
+                "let f = fn(xs) { for (x in xs) { if (x == 1) { break } return x; } 99 }; f([1, 2])",
+                Value::Int(99),
+            ),
+            (
+                r#"let f = fn(h) { for (entry in h) { return entry; } }; f({"a": 1, "b": 2})"#,
+                Value::array(vec![Value::Str("a".to_string()), Value::Int(1)]),
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let ints = |values: &[i64]| Value::array(values.iter().copied().map(Value::Int).collect());
+
+        assert_values(vec![
+            ("1..10", Value::Range(Range::new(Some(1), Some(10), false))),
+            ("let n = 2; (0..=n * 2) == (0..=4)", Value::Bool(true)),
+            (
+                "let f = fn() { for (i in 3..10) { if (i * i > 20) { return i; } } }; f()",
+                Value::Int(5),
+            ),
+            (
+                "let f = fn() { for (i in 0..=3) { if (i == 3) { return i; } } -1 }; f()",
+                Value::Int(3),
+            ),
+            (
+                "let f = fn() { for (i in 0..3) { if (i == 3) { return i; } } -1 }; f()",
+                Value::Int(-1),
+            ),
+            (
+                "let f = fn() { for (i in 5..0) { return i; } -1 }; f()",
+                Value::Int(-1),
+            ),
+            ("[10, 20, 30, 40][1..3]", ints(&[20, 30])),
+            ("[10, 20, 30, 40][1..=3]", ints(&[20, 30, 40])),
+            ("[10, 20][2..2]", ints(&[])),
+            // Negative indexes count back from the end.
+            ("[10, 20, 30][-1]", Value::Int(30)),
+            ("[10, 20, 30][-3]", Value::Int(10)),
+            ("[10, 20, 30][-2..]", ints(&[20, 30])),
+            ("[10, 20, 30][..-1]", ints(&[10, 20])),
+            ("[10, 20, 30][-3..=-2]", ints(&[10, 20])),
+            ("[10, 20, 30][1..]", ints(&[20, 30])),
+            ("[10, 20, 30][..2]", ints(&[10, 20])),
+            ("[10, 20, 30][3..]", ints(&[])),
+            ("[][..]", ints(&[])),
+            ("let n = 1; [10, 20, 30][n + 1..]", ints(&[30])),
+            // A slice is a copy.
+            (
+                "let xs = [1, 2]; let ys = xs[..]; push(ys, 3); xs",
+                ints(&[1, 2]),
+            ),
+            ("let xs = [1, 2]; xs[-1] = 5; xs", ints(&[1, 5])),
+            ("(1, 2)[-1]", Value::Int(2)),
+        ]);
+    }
+
+    #[test]
+    fn test_destructuring() {
+        assert_values(vec![
+            ("let [a, b, c] = [1, 2, 3]; a + b * c", Value::Int(7)),
+            (
+                "let [a, [b, c]] = [1, [2, 3]]; [a, b, c] == [1, 2, 3]",
+                Value::Bool(true),
+            ),
+            ("let [] = []; 1", Value::Int(1)),
+            (
+                r#"let person = {"name": "John", "age": 3}; let {name, age: years} = person; years"#,
+                Value::Int(3),
+            ),
+            (r#"let {missing} = {"a": 1}; missing"#, Value::Null),
+            (
+                r#"let {point: [x, y]} = {"point": [3, 4]}; x * y"#,
+                Value::Int(12),
+            ),
+            (
+                r#"let total = 0; for ([key, value] in {"a": 1, "b": 2}) { total += value; } total"#,
+                Value::Int(3),
+            ),
+            (
+                "let sum = 0; for ([a, b] in [[1, 2], [3, 4]]) { sum += a * b; } sum",
+                Value::Int(14),
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_tuples() {
+        assert_values(vec![
+            (r#"let t = (1, "a", true); t.2"#, Value::Bool(true)),
+            ("let t = ((1, 2), 3); t.0.1 + t[1]", Value::Int(5)),
+            ("(1, 2) == (1, 2)", Value::Bool(true)),
+            ("(1, 2) == (2, 1)", Value::Bool(false)),
+            ("(1, 2) < (1, 3)", Value::Bool(true)),
+            ("(1, 2) < (1,)", Value::Bool(false)),
+            ("(2,) >= (1, 9)", Value::Bool(true)),
+            ("() < (0,)", Value::Bool(true)),
+            ("[1, 2] < [1, 3]", Value::Bool(true)),
+            ("[1, [2, 3]] >= [1, [2]]", Value::Bool(true)),
+            ("[1.5] > [1]", Value::Bool(true)),
+            (r#"["b"] <= ["a", "z"]"#, Value::Bool(false)),
+            (r#"let h = {(1, "a"): 2}; h[(1, "a")]"#, Value::Int(2)),
+            ("let (a, (b, c)) = (1, (2, 3)); a + b * c", Value::Int(7)),
+            (
+                "let sum = 0; for ((a, b) in [(1, 2), (3, 4)]) { sum += a * b; } sum",
+                Value::Int(14),
+            ),
+        ]);
+
+        let shown = eval(r#"[(1, "a"), (2,), ()]"#).unwrap().to_string();
+        assert_eq!(r#"[(1, "a"), (2,), ()]"#, shown);
+
+        assert_errors(vec![
+            (
+                "(1, 2)[2]",
+                "index out of bounds: the length is 2 but the index is 2",
+            ),
+            ("(1, 2)[\"a\"]", "tuple index must be an Int, got Str"),
+            ("let t = (1, 2); t[0] = 3", "cannot assign into Tuple"),
+            ("(1, 2) < (1, \"a\")", "type mismatch: Int < Str"),
+            ("{([1], 2): 3}", "unusable as hash key: Tuple"),
+            (
+                "let (a, b) = (1, 2, 3)",
+                "cannot destructure a tuple of length 3 into 2 names",
+            ),
+            ("let (a, b) = [1, 2]", "cannot destructure Array as a tuple"),
+        ]);
+    }
+
+    #[test]
+    fn test_structs() {
+        assert_values(vec![
+            ("struct P { x, y }; let p = P(1, 2); p.x + p.y", Value::Int(3)),
+            ("struct P { x, y }; let p = P(y: 2, x: 1); p.x - p.y", Value::Int(-1)),
+            (
+                "struct P { x, y }; let p = P(1, y: 2); p.y = 5; p.y += 1; p.y",
+                Value::Int(6),
+            ),
+            // Copies share the instance, like arrays.
+            (
+                "struct P { x, y }; let p = P(1, 2); let q = p; q.x = 3; p.x",
+                Value::Int(3),
+            ),
+            ("struct P { x, y }; P(1, 2) == P(1, 2)", Value::Bool(true)),
+            ("struct P { x, y }; P(1, 2) == P(2, 1)", Value::Bool(false)),
+            (
+                r#"struct P { x }; type(P(1)) + " " + type(P)"#,
+                Value::Str("P Function".to_string()),
+            ),
+            (
+                "struct P { x, y }; let P { x, y: [a, b] } = P(1, [2, 3]); x + a * b",
+                Value::Int(7),
+            ),
+            (
+                "struct P { x, y }; let sum = 0; for (P { y } in [P(0, 1), P(0, 2)]) { sum += y; } sum",
+                Value::Int(3),
+            ),
+            ("let f = fn() { struct L { a }; L(1) }; f().a", Value::Int(1)),
+        ]);
+
+        let shown = eval(r#"struct E {}; struct P { a, b }; [P("x", E()), P]"#)
+            .unwrap()
+            .to_string();
+        assert_eq!(r#"[P { a: "x", b: E {} }, struct P { a, b }]"#, shown);
+
+        assert_errors(vec![
+            (
+                "struct P { x, y }; P(1)",
+                "wrong number of arguments to `P`: expected 2, got 1",
+            ),
+            ("struct P { x, y }; P(1, z: 2)", "P has no field `z`"),
+            ("struct P { x, y }; P(1, x: 2)", "argument `x` given twice"),
+            ("struct P { x, y }; P(1, 2).z", "P has no field `z`"),
+            (
+                "struct P { x, y }; let p = P(1, 2); p.z = 3",
+                "P has no field `z`",
+            ),
+            (
+                "struct P { x, y }; P(1, 2)[0]",
+                "struct field must be a Str, got Int",
+            ),
+            (
+                "struct P { x, y }; let P { z } = P(1, 2)",
+                "P has no field `z`",
+            ),
+            (
+                r#"struct P { x, y }; let P { x } = {"x": 1}"#,
+                "cannot destructure Hash as a P",
+            ),
+            (
+                "struct P { x, y }; struct Q { x, y }; let P { x } = Q(1, 2)",
+                "cannot destructure a Q as a P",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_enums_and_match() {
+        assert_values(vec![
+            (
+                "enum S { C(r), R(w, h), D }
+                let area = fn(s) { match s { C(r) => 3 * r * r, R(w, h) => w * h, D => 0 } }
+                area(C(2)) + area(R(2, 3)) + area(D)",
+                Value::Int(18),
+            ),
+            // Any other name matches whatever's left.
+            (
+                "enum S { C(r), D }; match D { C(_) => 1, other => 2 }",
+                Value::Int(2),
+            ),
+            (
+                "enum O { Some(v), None }; match Some([1, 2]) { Some([a, b]) => a + b, None => 0 }",
+                Value::Int(3),
+            ),
+            (
+                "enum O { Some(v), None }
+                match Some(None) { Some(Some(x)) => x, Some(None) => -1, None => 0 }",
+                Value::Int(-1),
+            ),
+            ("match [1, 2] { [a] => a, [a, b] => b }", Value::Int(2)),
+            (
+                "let x = 1; let y = match 5 { n => { let x = n; x * 2 } }; x + y",
+                Value::Int(11),
+            ),
+            ("enum S { R(w, h) }; R(2, 3).h", Value::Int(3)),
+            (
+                "enum S { R(w, h) }; R(h: 1, w: 2) == R(2, 1)",
+                Value::Bool(true),
+            ),
+            (
+                r#"enum S { C(r), D }; type(C(1)) + " " + type(D) + " " + type(C)"#,
+                Value::Str("S S Function".to_string()),
+            ),
+            ("enum S { C(r) }; let C(r) = C(4); r", Value::Int(4)),
+            (
+                r#"let x = 1; match x { 1 => "one", _ => "other" }"#,
+                Value::Str("one".to_string()),
+            ),
+            (
+                r#"let x = 2; match x { 1 => "one", _ => "other" }"#,
+                Value::Str("other".to_string()),
+            ),
+            (
+                r#"let f = fn(x) { match x { -1 => 1, "a" => 2, 'a' => 3, true => 4, null => 5, _ => 6 } };
+                [f(-1), f("a"), f('a'), f(true), f(null), f(false)]"#,
+                Value::array([1, 2, 3, 4, 5, 6].into_iter().map(Value::Int).collect()),
+            ),
+            (
+                "enum O { Some(v), None }; match Some(0) { Some(0) => 1, Some(_) => 2, None => 3 }",
+                Value::Int(1),
+            ),
+            ("let _ = 1; let [_, b] = [2, 3]; b", Value::Int(3)),
+        ]);
+
+        let shown = eval(r#"enum S { C(r), D }; [C("x"), D, C]"#)
+            .unwrap()
+            .to_string();
+        assert_eq!(r#"[C("x"), D, variant C(r)]"#, shown);
+
+        assert_errors(vec![
+            (
+                "enum S { C(r), D }; match C(1) { D => 0 }",
+                "no `match` arm matches C(1)",
+            ),
+            (
+                "enum S { C(r) }; C()",
+                "wrong number of arguments to `C`: expected 1, got 0",
+            ),
+            (
+                "enum S { C(r), D }; let C(r) = D",
+                "cannot destructure a D as a C",
+            ),
+            (
+                "enum S { C(r) }; let C(a, b) = C(1)",
+                "wrong number of fields in a C pattern: expected 1, got 2",
+            ),
+            ("enum S { C(r) }; C(1).x", "C has no field `x`"),
+            (
+                "enum S { C(r) }; let c = C(1); c.r = 2",
+                "cannot assign into Enum",
+            ),
+            ("let C(r) = 1", "cannot destructure Int as a C"),
+            ("let _ = 1; _", "identifier not found: _"),
+            ("let [1, b] = [2, 3]", "cannot destructure Int as 1"),
+        ]);
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_values(vec![
+            ("let identity = fn(x) { x }; identity(5)", Value::Int(5)),
+            ("let identity = fn(x) { return x; 1 }; identity(5)", Value::Int(5)),
+            ("let add = fn(a, b) { a + b }; add(add(1, 2), 3)", Value::Int(6)),
+            ("fn(x) { x * 2 }(4)", Value::Int(8)),
+            ("let f = fn() {}; f()", Value::Null),
+            // Functions are values: passed in, returned, and compared by identity.
+            (
+                "let twice = fn(f, x) { f(f(x)) }; twice(fn(x) { x + 3 }, 1)",
+                Value::Int(7),
+            ),
+            (
+                "let pick = fn(n) { if (n > 0) { fn(x) { x } } else { fn(x) { -x } } }; pick(-1)(5)",
+                Value::Int(-5),
+            ),
+            ("let f = fn() {}; f == f", Value::Bool(true)),
+            ("fn() {} == fn() {}", Value::Bool(false)),
+            // Recursion works through the global binding.
+            (
+                "let fact = fn(n) { if (n < 2) { 1 } else { n * fact(n - 1) } }; fact(10)",
+                Value::Int(3628800),
+            ),
+            // A call's locals don't leak into the caller or the globals.
+            ("let x = 1; let f = fn(x) { let y = x; y }; f(2); x", Value::Int(1)),
+        ]);
+    }
+
+    #[test]
+    fn test_error_locations() {
+        let error =
+            eval("let add = fn(a, b) { a + b }; let outer = fn() { add(1, true) * 2 }; outer()")
+                .unwrap_err();
+
+        assert_eq!(
+            Some(Span {
+                line: 1,
+                column: 24
+            }),
+            error.span
+        );
+        assert_eq!(
+            vec![
+                Frame {
+                    function: Some("add".to_string()),
+                    call_site: Span {
+                        line: 1,
+                        column: 53
+                    },
+                },
+                Frame {
+                    function: Some("outer".to_string()),
+                    call_site: Span {
+                        line: 1,
+                        column: 75
+                    },
+                },
+            ],
+            error.trace
+        );
+        assert_eq!(
+            "1:24: type mismatch: Int + Bool\n    in add, called from 1:53\n    in outer, called from 1:75",
+            error.to_string()
+        );
+
+        let tests = vec![
+            (
+                "fn() { missing }()",
+                "1:8: identifier not found: missing\n    in anonymous function, called from 1:17",
+            ),
+            // Calling a non-function fails at the call, not inside it.
+            ("let x = 1;\nx(2)", "2:2: not a function: Int"),
+            ("let [a] = 1", "1:5: cannot destructure Int as an array"),
+            (
+                "const x = 1;\nconst x = 2",
+                "2:7: cannot redeclare constant `x`",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                expected,
+                eval(input).unwrap_err().to_string(),
+                "input={input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_long_operator_chains() {
+        // Each operator holds the chain before it a level deeper, far
+        // deeper than a test thread's stack goes without growing.
+        let sum = format!("1{}", " + 1".repeat(20_000));
+        let calls = format!("let f = fn() {{ f }}; f{}; 1", "()".repeat(20_000));
+        assert_values(vec![(&sum, Value::Int(20_001)), (&calls, Value::Int(1))]);
+    }
+
+    #[test]
+    fn test_recursion_depth_limit() {
+        // Not tail recursive, so every call takes a level of depth.
+        let count = "let f = fn(n) { if (n > 0) { f(n - 1) + 1 } else { 0 } };";
+
+        let eval_with_limit = |input: &str, max_depth| {
+            let mut parser = Parser::new(Lexer::new(input));
+            let program = parser.parse_program();
+
+            let mut evaluator = Evaluator::new();
+            evaluator.set_max_depth(max_depth);
+            evaluator.eval_program(&program)
+        };
+
+        // `f(n)` makes `n + 1` calls.
+        assert_eq!(
+            Ok(Value::Int(9)),
+            eval_with_limit(&format!("{count} f(9)"), 10)
+        );
+        // Calls that have returned don't count.
+        assert_eq!(
+            Ok(Value::Int(9)),
+            eval_with_limit(&format!("{count} f(9); f(9)"), 10)
+        );
+
+        let error = eval_with_limit(&format!("{count} f(10)"), 10).unwrap_err();
+        assert_eq!(
+            "1:31: maximum recursion depth 10 exceeded\n\
+             \x20   in f, called from 1:31\n\
+             \x20   in f, called from 1:31\n\
+             \x20   in f, called from 1:31\n\
+             \x20   ... the same call 6 more times\n\
+             \x20   in f, called from 1:60",
+            error.to_string()
+        );
+        assert_eq!(10, error.trace.len());
+
+        // The default limit fits in the stack it asks for.
+        let deep = std::thread::Builder::new()
+            .stack_size(DEFAULT_MAX_DEPTH * STACK_PER_CALL)
+            .spawn(move || {
+                // Values can't leave the thread, so compare them as text.
+                let eval = |n| {
+                    eval(&format!("{count} f({n})"))
+                        .map(|value| value.to_string())
+                        .map_err(|error| error.message)
+                };
+                (eval(DEFAULT_MAX_DEPTH - 1), eval(DEFAULT_MAX_DEPTH))
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        assert_eq!(
+            (
+                Ok("999".to_string()),
+                Err("maximum recursion depth 1000 exceeded".to_string())
+            ),
+            deep
+        );
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        let eval_with_limit = |input: &str, max_depth| {
+            let mut parser = Parser::new(Lexer::new(input));
+            let program = parser.parse_program();
+
+            let mut evaluator = Evaluator::new();
+            evaluator.set_max_depth(max_depth);
+            evaluator.eval_program(&program)
+        };
+
+        // With a limit of one call at a time, only tail calls can go on.
+        let tests = vec![
+            (
+                "let sum = fn(n, acc) { if (n == 0) { acc } else { sum(n - 1, acc + n) } };
+                 sum(1000000, 0)",
+                Value::Int(500000500000),
+            ),
+            (
+                "let even = fn(n) { if (n == 0) { return true; } return odd(n - 1); };
+                 let odd = fn(n) { if (n == 0) { false } else { even(n - 1) } };
+                 even(100001)",
+                Value::Bool(false),
+            ),
+            (
+                "let f = fn(n) { if (n < 10) { f(n + 1) } else if (n < 20) { f(n + 2) } else { n } };
+                 f(0)",
+                Value::Int(20),
+            ),
+            (
+                "let f = fn(n) { n > 0 ? f(n - 1) : \"done\" }; f(100000)",
+                Value::Str("done".to_string()),
+            ),
+            (
+                "let f = fn(n) { while (true) { return if (n > 0) { f(n - 1) } else { len(\"done\") }; } };
+                 f(1000)",
+                Value::Int(4),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Ok(expected), eval_with_limit(input, 1), "input={input}");
+        }
+
+        // Calls whose result is still needed aren't tail calls.
+        assert_eq!(
+            "maximum recursion depth 1 exceeded",
+            eval_with_limit("let f = fn(n) { if (n > 0) { 1 + f(n - 1) } }; f(1)", 1)
+                .unwrap_err()
+                .message
+        );
+
+        // A failed tail call is reported where it was made.
+        assert_eq!(
+            "1:20: not a function: Int",
+            eval("let f = fn() { 1; 2() }; f()")
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_default_parameters() {
+        let tests = vec![
+            (
+                r#"let greet = fn(greeting, name = "world") { greeting + ", " + name };
+                   [greet("hello"), greet("hi", "tofu")]"#,
+                r#"["hello, world", "hi, tofu"]"#,
+            ),
+            // Evaluated at each call, seeing the parameters before them and
+            // the function's own scope rather than the caller's.
+            (
+                "let x = 1;
+                 let f = fn(a, b = a + x, c = []) { push(c, b); c };
+                 let g = fn() { let x = 100; [f(1), f(1), f(1, 5)] };
+                 g()",
+                "[[2], [2], [5]]",
+            ),
+            (
+                "let f = fn(a = 1, ...xs) { [a, xs] }; [f(), f(2, 3)]",
+                "[[1, []], [2, [3]]]",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                Ok(expected.to_string()),
+                eval(input).map(|value| value.to_string()),
+                "input={input}"
+            );
+        }
+
+        let errors = vec![
+            (
+                "let f = fn(a, b = 1) { a }; f()",
+                "1:30: wrong number of arguments: expected 1 to 2, got 0",
+            ),
+            (
+                "let f = fn(a = 1) { a }; f(1, 2)",
+                "1:27: wrong number of arguments: expected 0 to 1, got 2",
+            ),
+            (
+                "let f = fn(a = nope) { a }; f()",
+                "1:16: identifier not found: nope\n    in f, called from 1:30",
+            ),
+        ];
+
+        for (input, expected) in errors {
+            assert_eq!(
+                expected,
+                eval(input).unwrap_err().to_string(),
+                "input={input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_pipelines() {
+        let tests = vec![
+            (
+                r#"let double = fn(xs) { map(xs, fn(x) { x * 2 }) };
+                   1..4 |> double |> filter(fn(x) { x > 2 }) |> join(", ")"#,
+                "4, 6",
+            ),
+            (r#""tofu" |> upper |> contains("OF")"#, "true"),
+            (
+                "let add = fn(a, b) { a + b }; 1 |> add(2) |> add(b: 3)",
+                "6",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                Ok(expected.to_string()),
+                eval(input).map(|value| value.to_string()),
+                "input={input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_named_arguments() {
+        let tests =
+            vec![
+            (
+                r#"let make_user = fn(name, admin = false, age = null) { [name, admin, age] };
+                   [make_user(name: "John", admin: true), make_user("Jane", age: 30)]"#,
+                r#"[["John", true, null], ["Jane", false, 30]]"#,
+            ),
+            ("let f = fn(a, b) { a - b }; f(b: 1, a: 5)", "4"),
+            ("let f = fn(a, b, ...xs) { [a, b, xs] }; f(1, b: 2)", "[1, 2, []]"),
+            // In tail position too.
+            (
+                "let f = fn(n, acc = 0) { if (n == 0) { acc } else { f(acc: acc + n, n: n - 1) } };
+                 f(100)",
+                "5050",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                Ok(expected.to_string()),
+                eval(input).map(|value| value.to_string()),
+                "input={input}"
+            );
+        }
+
+        let errors = vec![
+            ("let f = fn(a) { a }; f(b: 1)", "1:23: unknown argument `b`"),
+            (
+                "let f = fn(a, b) { a }; f(1, a: 2)",
+                "1:26: argument `a` given twice",
+            ),
+            (
+                "let f = fn(a, b = 1) { a }; f(b: 2)",
+                "1:30: missing argument `a`",
+            ),
+            (
+                "let f = fn(a) { a }; f(1, b: 3)",
+                "1:23: unknown argument `b`",
+            ),
+            (
+                "let f = fn(...xs) { xs }; f(xs: 1)",
+                "1:28: unknown argument `xs`",
+            ),
+            (
+                "len(value: [])",
+                "1:4: `len` doesn't take named arguments, like `value`",
+            ),
+            (
+                "let f = fn(a) { a }; f(1, a: nope)",
+                "1:30: identifier not found: nope",
+            ),
+        ];
+
+        for (input, expected) in errors {
+            assert_eq!(
+                expected,
+                eval(input).unwrap_err().to_string(),
+                "input={input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_variadic_functions_and_spread() {
+        let tests = vec![
+            ("let f = fn(...xs) { xs }; f()", "[]"),
+            ("let f = fn(a, ...xs) { [a, xs] }; f(1, 2, 3)", "[1, [2, 3]]"),
+            ("let f = fn(a, ...xs) { [a, xs] }; f(1)", "[1, []]"),
+            ("let f = fn(a, b) { a - b }; f(...[5, 3])", "2"),
+            ("let f = fn(...xs) { len(xs) }; f(1, ...0..3, ...[], 2)", "5"),
+            ("max(...[3, 9, 4])", "9"),
+            (r#"[0, ...{"a": 1}, ..."bc" == "bc" ? [2] : []]"#, r#"[0, ["a", 1], 2]"#),
+            // Tail calls spread too.
+            (
+                "let count = fn(n, ...xs) { n == 0 ? len(xs) : count(n - 1, ...xs, n) }; count(100)",
+                "100",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                Ok(expected.to_string()),
+                eval(input).map(|value| value.to_string()),
+                "input={input}"
+            );
+        }
+
+        let errors = vec![
+            (
+                "let f = fn(a, b, ...xs) { a }; f(1)",
+                "1:33: wrong number of arguments: expected at least 2, got 1",
+            ),
+            (
+                "let f = fn(a) { a }; f(...[1, 2])",
+                "1:23: wrong number of arguments: expected 1, got 2",
+            ),
+            ("len(...1)", "1:4: cannot spread Int"),
+        ];
+
+        for (input, expected) in errors {
+            assert_eq!(
+                expected,
+                eval(input).unwrap_err().to_string(),
+                "input={input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_try_catch() {
+        let tests = vec![
+            ("try { 1 } catch (e) { 2 }", Value::Int(1)),
+            ("try { throw 1; 2 } catch (e) { e + 10 }", Value::Int(11)),
+            (
+                r#"try { 1 + true } catch ({message, kind}) { kind + ": " + message }"#,
+                Value::Str("Error: type mismatch: Int + Bool".to_string()),
+            ),
+            // Thrown through functions and builtins, and caught as thrown.
+            (
+                r#"let check = fn(x) { if (x < 0) { throw {"kind": "Negative"} } x };
+                   try { map([1, -1], check) } catch (e) { e["kind"] }"#,
+                Value::Str("Negative".to_string()),
+            ),
+            // A call in tail position is still inside the `try`.
+            (
+                r#"let fail = fn() { throw "no" };
+                   let f = fn() { try { return fail() } catch (e) { "caught " + e } };
+                   f()"#,
+                Value::Str("caught no".to_string()),
+            ),
+            // Rethrown from the handler, to an outer `try`.
+            (
+                "try { try { throw 1 } catch (e) { throw e + 1 } } catch (e) { e }",
+                Value::Int(2),
+            ),
+            // The handler's bindings don't outlive it.
+            (
+                "let e = 0; try { throw 1 } catch (e) { e }; e",
+                Value::Int(0),
+            ),
+            (
+                "let n = 0; for (i in 0..5) { try { if (i == 3) { break } n += i } catch (e) {} }; n",
+                Value::Int(3),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Ok(expected), eval(input), "input={input}");
+        }
+
+        let error = eval(r#"let f = fn() { throw [1, "a"]; }; f(); 1"#).unwrap_err();
+        assert_eq!(
+            "1:16: [1, \"a\"]\n    in f, called from 1:36",
+            error.to_string()
+        );
+        assert_eq!(ErrorKind::Thrown, error.kind);
+        assert_eq!(
+            Some(Value::array(vec![
+                Value::Int(1),
+                Value::Str("a".to_string())
+            ])),
+            error.thrown
+        );
+
+        // Running out of fuel can't be caught.
+        let mut parser = Parser::new(Lexer::new("try { while (true) {} } catch (e) { 1 }"));
+        let program = parser.parse_program();
+        let error = Evaluator::with_fuel(100)
+            .eval_program(&program)
+            .unwrap_err();
+        assert_eq!(ErrorKind::FuelExhausted, error.kind);
+    }
+
+    #[test]
+    fn test_fuel() {
+        let eval_with_fuel = |input: &str, fuel| {
+            let mut parser = Parser::new(Lexer::new(input));
+            let program = parser.parse_program();
+
+            let mut evaluator = Evaluator::with_fuel(fuel);
+            let result = evaluator.eval_program(&program);
+            (result, evaluator.fuel())
+        };
+
+        // A statement, then the infix expression and its two operands.
+        assert_eq!((Ok(Value::Int(3)), Some(6)), eval_with_fuel("1 + 2", 10));
+        assert_eq!((Ok(Value::Int(3)), Some(0)), eval_with_fuel("1 + 2", 4));
+
+        let runaways = vec![
+            "while (true) {}",
+            "for (i in 0..9223372036854775807) {}",
+            "let f = fn() { f() }; f()",
+            "let f = fn() { f() + 1 }; f()",
+            "map(0..1000000, fn(x) { x })",
+        ];
+
+        for input in runaways {
+            // Little enough that the recursion fits on the test's stack.
+            let (result, fuel) = eval_with_fuel(input, 50);
+            let error = result.unwrap_err();
+            assert_eq!(ErrorKind::FuelExhausted, error.kind, "input={input}");
+            assert_eq!("out of fuel", error.message, "input={input}");
+            assert_eq!(Some(0), fuel, "input={input}");
+        }
+
+        // Other errors are just errors.
+        let (result, _) = eval_with_fuel("1 + true", 10);
+        assert_eq!(ErrorKind::Error, result.unwrap_err().kind);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        static INTERRUPT: AtomicBool = AtomicBool::new(false);
+
+        let mut parser = Parser::new(Lexer::new(
+            "try { while (true) {} } catch (e) { \"caught\" }",
+        ));
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new();
+        evaluator.set_interrupt(Some(&INTERRUPT));
+
+        let interrupter = std::thread::spawn(|| {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            INTERRUPT.store(true, Ordering::Relaxed);
+        });
+        let error = evaluator.eval_program(&program).unwrap_err();
+        interrupter.join().unwrap();
+
+        assert_eq!(ErrorKind::Interrupted, error.kind);
+        assert_eq!("interrupted", error.message);
+        // Taken, so the next program runs.
+        assert!(!INTERRUPT.load(Ordering::Relaxed));
+        assert_eq!(
+            Ok(Value::Int(2)),
+            evaluator.eval_program(&Parser::new(Lexer::new("1 + 1")).parse_program())
+        );
+    }
+
+    #[test]
+    fn test_max_memory() {
+        let eval_with_max_memory = |input: &str| {
+            let mut parser = Parser::new(Lexer::new(input));
+            let program = parser.parse_program();
+
+            let mut evaluator = Evaluator::new();
+            evaluator.set_max_memory(Some(1 << 20)).unwrap();
+            evaluator.eval_program(&program)
+        };
+
+        assert_eq!(
+            Ok(Value::Int(1000)),
+            eval_with_max_memory("let a = []; for (i in 0..1000) { push(a, i) }; len(a)")
+        );
+
+        let hogs = vec![
+            r#"let a = []; while (true) { push(a, "tofu") }"#,
+            r#"let s = "tofu"; while (true) { s = s + s }"#,
+            // Too big to build at all, so it fails before it starts.
+            "array(0..100000000)",
+        ];
+
+        for input in hogs {
+            let error = eval_with_max_memory(input).unwrap_err();
+            assert_eq!(ErrorKind::OutOfMemory, error.kind, "input={input}");
+            assert_eq!(
+                "out of memory: the limit is 1048576 bytes", error.message,
+                "input={input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_closures() {
+        assert_values(vec![
+            (
+                "let add = fn(x) { fn(y) { x + y } }; let add_two = add(2); add_two(3)",
+                Value::Int(5),
+            ),
+            (
+                "let make_counter = fn() { let n = 0; fn() { n = n + 1; n } };
+                 let counter = make_counter(); counter(); counter(); counter()",
+                Value::Int(3),
+            ),
+            // Each call to the outer function makes a separate `n`.
+            (
+                "let make_counter = fn() { let n = 0; fn() { n += 1; n } };
+                 let a = make_counter(); let b = make_counter(); a(); a(); b()",
+                Value::Int(1),
+            ),
+            // Each loop iteration has its own `i` to capture.
+            (
+                "let fns = {}; for (i in 0..3) { fns[i] = fn() { i * 10 }; } fns[0]() + fns[2]()",
+                Value::Int(20),
+            ),
+            // Functions see later changes to the variables they capture.
+            ("let x = 1; let f = fn() { x }; x = 2; f()", Value::Int(2)),
+            (
+                "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(10)",
+                Value::Int(55),
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_arrays() {
+        assert_values(vec![
+            ("[]", Value::array(vec![])),
+            (
+                "[1, 2 * 2, \"three\"]",
+                Value::array(vec![
+                    Value::Int(1),
+                    Value::Int(4),
+                    Value::Str("three".to_string()),
+                ]),
+            ),
+            ("[1, 2, 3][0]", Value::Int(1)),
+            ("let i = 0; [1][i]", Value::Int(1)),
+            ("let xs = [1, 2, 3]; xs[1 + 1]", Value::Int(3)),
+            ("let xs = [[1], [2, 3]]; xs[1][0]", Value::Int(2)),
+            (
+                "let first = fn(xs) { xs[0] }; first([fn() { 7 }])()",
+                Value::Int(7),
+            ),
+            ("[1, [2]] == [1, [2]]", Value::Bool(true)),
+            ("[1] == [2]", Value::Bool(false)),
+        ]);
+    }
+
+    #[test]
+    fn test_hashes() {
+        let expected = IndexMap::from([
+            (HashKey::Str("one".to_string()), Value::Int(1)),
+            (HashKey::Int(2), Value::Int(2)),
+            (HashKey::Bool(true), Value::Int(3)),
+        ]);
+
+        assert_values(vec![
+            (
+                r#"let two = 2; {"one": 10 - 9, two: 1 + 1, 2 > 1: 3}"#,
+                Value::hash(expected),
+            ),
+            (
+                r#"{"name": "John", "age": 3}["name"]"#,
+                Value::Str("John".to_string()),
+            ),
+            (
+                r#"let key = "age"; {"name": "John", "age": 3}[key]"#,
+                Value::Int(3),
+            ),
+            (r#"{"a": 1}["b"]"#, Value::Null),
+            ("{}[0]", Value::Null),
+            ("{1: 1, 1: 2}[1]", Value::Int(2)),
+            ("{true: fn(x) { x }}[true](5)", Value::Int(5)),
+            // Equality doesn't depend on insertion order.
+            ("{1: 1, 2: 2} == {2: 2, 1: 1}", Value::Bool(true)),
+        ]);
+    }
+
+    #[test]
+    fn test_hash_display() {
+        let value = eval(r#"{"b": [1], 1: "a", false: {}}"#).unwrap();
+        assert_eq!(r#"{"b": [1], 1: "a", false: {}}"#, value.to_string());
+    }
+
+    #[test]
+    fn test_hashes_that_contain_themselves() {
+        let input = r#"let h = {}; h["me"] = h; let g = {}; g["me"] = g;"#;
+        assert_values(vec![
+            (&format!("{input} h == h"), Value::Bool(true)),
+            (&format!("{input} h == g"), Value::Bool(true)),
+            (
+                &format!(r#"{input} h == {{"me": {{}}}}"#),
+                Value::Bool(false),
+            ),
+        ]);
+
+        let shown = eval(&format!("{input} h")).unwrap().to_string();
+        assert_eq!(r#"{"me": {...}}"#, shown);
+    }
+
+    #[test]
+    fn test_array_display() {
+        let value = eval("[1, \"a, b\", 'c', [null, 2.5]]").unwrap();
+        assert_eq!("[1, \"a, b\", 'c', [null, 2.5]]", value.to_string());
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        assert_errors(vec![
+            ("5 + true", "type mismatch: Int + Bool"),
+            ("true + false", "unknown operator: Bool + Bool"),
+            ("\"a\" - \"b\"", "unknown operator: Str - Str"),
+            ("-\"a\"", "unknown operator: -Str"),
+            ("1 / 0", "division by zero"),
+            ("1 << 64", "shift amount 64 is out of range"),
+            ("foobar", "identifier not found: foobar"),
+            ("if (true) { let x = 1; } x", "identifier not found: x"),
+            ("const a = 1; let a = 2;", "cannot redeclare constant `a`"),
+            ("if (true) { 5 + true; 10 }", "type mismatch: Int + Bool"),
+            ("let x = 1; x(1)", "not a function: Int"),
+            (
+                "let f = fn(a, b) { a }; f(1)",
+                "wrong number of arguments: expected 2, got 1",
+            ),
+            ("let f = fn(a) { a }; f(1); a", "identifier not found: a"),
+            (
+                "[1, 2, 3][3]",
+                "index out of bounds: the length is 3 but the index is 3",
+            ),
+            (
+                "[1, 2, 3][-4]",
+                "index out of bounds: the length is 3 but the index is -4",
+            ),
+            ("[1][true]", "array index must be an Int, got Bool"),
+            ("1[0]", "cannot index into Int"),
+            ("{[1]: 1}", "unusable as hash key: Array"),
+            ("{}[fn() {}]", "unusable as hash key: Function"),
+            ("break", "`break` outside of a loop"),
+            ("for (x in 5) {}", "cannot iterate over Int"),
+            ("1..true", "range bounds must be Int, got Int and Bool"),
+            ("x = 1", "cannot assign to undeclared variable `x`"),
+            ("const x = 1; x = 2", "cannot assign to constant `x`"),
+            (
+                "let [a, b] = [1, 2, 3]",
+                "cannot destructure an array of length 3 into 2 names",
+            ),
+            ("let [a] = 1", "cannot destructure Int as an array"),
+            ("let {a} = [1]", "cannot destructure Array as a hash"),
+            ("const [a, a] = [1, 2]", "cannot redeclare constant `a`"),
+            (
+                "for ([a, b] in [[1]]) {}",
+                "cannot destructure an array of length 1 into 2 names",
+            ),
+            ("const x = 1; x += 2", "cannot assign to constant `x`"),
+            (
+                "let xs = [1]; xs[1] = 2",
+                "index out of bounds: the length is 1 but the index is 1",
+            ),
+            ("let s = \"a\"; s[0] = 1", "cannot assign into Str"),
+            ("let x = true; x += 1", "type mismatch: Bool + Int"),
+            (
+                "[1, 2][1..3]",
+                "slice 1..3 out of bounds for an array of length 2",
+            ),
+            (
+                "[1, 2][2..1]",
+                "slice 2..1 out of bounds for an array of length 2",
+            ),
+            (
+                "[1, 2][-3..]",
+                "slice -3.. out of bounds for an array of length 2",
+            ),
+            (
+                "[1, 2][3..]",
+                "slice 3.. out of bounds for an array of length 2",
+            ),
+            (
+                "[1, 2, 3][-10..]",
+                "slice -10.. out of bounds for an array of length 3",
+            ),
+            (
+                "\"abc\"[..5]",
+                "slice ..5 out of bounds for a string of length 3",
+            ),
+            (
+                "\"abc\"[..=-5]",
+                "slice ..=-5 out of bounds for a string of length 3",
+            ),
+            (
+                "let xs = [1]; xs[-2] = 2",
+                "index out of bounds: the length is 1 but the index is -2",
+            ),
+            ("for (x in [1]) {} x", "identifier not found: x"),
+            ("if (true) { continue }", "`continue` outside of a loop"),
+            (
+                "let f = fn() { break }; while (true) { f() }",
+                "`break` outside of a loop",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_prelude() {
+        let tests = vec![
+            ("range(2, 5)", "[2, 3, 4]"),
+            ("sum(range(1, 5)) + sum([])", "10"),
+            ("product([2, 3.5])", "7.0"),
+            (
+                "any([1, 2], fn(x) { x > 1 }) && !all([1, 2], fn(x) { x > 1 })",
+                "true",
+            ),
+            (r#"keys({"a": 1, "b": 2})"#, r#"["a", "b"]"#),
+            (r#"values({"a": 1, "b": 2})"#, "[1, 2]"),
+            ("reverse(1..4)", "[3, 2, 1]"),
+            (
+                r#"[contains([1, "a"], "a"), contains(1..3, 3)]"#,
+                "[true, false]",
+            ),
+            (
+                r#"[contains({"a": 1}, "a"), contains({"a": 1}, 1)]"#,
+                "[true, false]",
+            ),
+            (
+                r#"[contains("tofu", "of"), contains("tofu", 'x')]"#,
+                "[true, false]",
+            ),
+            // Programs can shadow the prelude.
+            ("let sum = fn(xs) { 0 }; sum([1])", "0"),
+        ];
+
+        for (input, expected) in tests {
+            let mut parser = Parser::new(Lexer::new(input));
+            let program = parser.parse_program();
+            assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+
+            let mut evaluator = Evaluator::new();
+            evaluator.load_prelude();
+            let value = evaluator.eval_program(&program).unwrap();
+            assert_eq!(expected, value.to_string(), "input={input}");
+        }
+
+        assert_errors(vec![("sum([1])", "identifier not found: sum")]);
+    }
+
+    #[test]
+    fn test_imports() {
+        let dir = std::env::temp_dir().join(format!("tofu-imports-{}", std::process::id()));
+        let lib = dir.join("lib");
+        std::fs::create_dir_all(&lib).unwrap();
+        let files = [
+            ("counter.tofu", "let n = 0\nlet double = fn(x) { x * 2 }\n"),
+            (
+                "lib/shapes.tofu",
+                "let area = fn(w, h) { w * h }\nlet unit = 1\n",
+            ),
+            (
+                "utils.tofu",
+                "let len = fn(n) { n * 2 }\nlet keys = \"not a function\"\n",
+            ),
+            ("a.tofu", "import b\n"),
+            ("b.tofu", "import a\n"),
+            ("broken.tofu", "let x = 1\nlet y = x / 0\n"),
+        ];
+        for (name, source) in files {
+            std::fs::write(dir.join(name), source).unwrap();
+        }
+
+        let run = |input: &str, source_path: &str, allow_files: bool| {
+            let mut parser = Parser::new(Lexer::new(input));
+            let program = parser.parse_program();
+            assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+
+            let mut evaluator = Evaluator::new();
+            evaluator.set_module_path(vec![lib.clone()]);
+            evaluator.set_source_path(dir.join(source_path));
+            evaluator.set_allow_files(allow_files);
+            evaluator.eval_program(&program)
+        };
+
+        // The second import of `counter` sees the change made through the
+        // first, since the module only runs once.
+        let result = run(
+            r#"import counter
+            counter["n"] = 5
+            import "counter.tofu"
+            import shapes
+            [counter["n"], counter["double"](4), shapes.area(2, 3), shapes.unit]"#,
+            "main.tofu",
+            true,
+        );
+        assert_eq!("[5, 8, 6, 1]", result.unwrap().to_string());
+
+        // A module's own functions come before the methods of hashes.
+        let result = run(
+            "import utils\n[utils.len(21), utils.keys(), math.len()]",
+            "main.tofu",
+            true,
+        );
+        assert_eq!(r#"[42, ["keys", "len"], 8]"#, result.unwrap().to_string());
+
+        let canonical = |name: &str| dir.join(name).canonicalize().unwrap().display().to_string();
+        let errors = vec![
+            (
+                "import b",
+                "a.tofu",
+                format!(
+                    "1:1: {}:1:1: import cycle: {} -> {} -> {}",
+                    canonical("b.tofu"),
+                    canonical("a.tofu"),
+                    canonical("b.tofu"),
+                    canonical("a.tofu"),
+                ),
+            ),
+            (
+                "import missing",
+                "main.tofu",
+                r#"1:1: cannot find module "missing.tofu""#.to_string(),
+            ),
+            (
+                "let x = 1; import broken",
+                "main.tofu",
+                format!("1:12: {}:2:11: division by zero", canonical("broken.tofu")),
+            ),
+        ];
+
+        for (input, source_path, expected) in errors {
+            let error = run(input, source_path, true).unwrap_err();
+            assert_eq!(expected, error.to_string(), "input={input}");
+        }
+
+        // Each module's file is listed once, even if it failed to run.
+        let mut parser = Parser::new(Lexer::new("import counter\nimport counter\nimport broken"));
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new();
+        evaluator.set_source_path(dir.join("main.tofu"));
+        assert!(evaluator.eval_program(&program).is_err());
+        let module_files: Vec<String> = evaluator
+            .module_files()
+            .iter()
+            .map(|file| file.display().to_string())
+            .collect();
+        assert_eq!(
+            vec![canonical("counter.tofu"), canonical("broken.tofu")],
+            module_files
+        );
+
+        let error = run("import counter", "main.tofu", false).unwrap_err();
+        assert_eq!(
+            "`import` is unavailable: filesystem access is disabled",
+            error.message
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_trace() {
+        #[derive(Clone, Default)]
+        struct Output(Rc<RefCell<Vec<u8>>>);
+
+        impl Write for Output {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let trace = Output::default();
+        let mut evaluator = Evaluator::new();
+        evaluator.set_trace(Some(Box::new(trace.clone())));
+        let mut parser = Parser::new(Lexer::new(
+            "let double = fn(x) { x * 2 }; double(4) + missing",
+        ));
+        let program = parser.parse_program();
+        assert!(evaluator.eval_program(&program).is_err());
+
+        assert_eq!(
+            "double => fn(x) { x * 2; }\n\
+             4 => 4\n  \
+               x => 4\n  \
+               2 => 2\n  \
+               x * 2 => 8\n\
+             double(4) => 8\n\
+             missing => error: identifier not found: missing\n\
+             double(4) + missing => error: identifier not found: missing\n",
+            String::from_utf8(trace.0.borrow().clone()).unwrap()
+        );
+    }
+}
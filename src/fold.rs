@@ -0,0 +1,190 @@
+//! Constant folding: rewrites operations on literals into their results
+//! before a program runs, so `2 * 3 + 1` becomes `7` and `"a" + "b"`
+//! becomes `"ab"`. Anything that would fail at runtime, like `1 / 0`, is
+//! left alone so the error still surfaces where it happens.
+
+use crate::ast::{Expression, InfixOperator, Program};
+use crate::evaluator::{eval_infix_expression, eval_prefix_expression, Overflow};
+use crate::value::Value;
+use crate::visit::{walk_expression_mut, VisitMut};
+
+impl Program {
+    /// Folds constant expressions throughout the program in place.
+    pub fn fold_constants(&mut self) {
+        ConstantFolder.visit_program_mut(self);
+    }
+}
+
+struct ConstantFolder;
+
+impl VisitMut for ConstantFolder {
+    /// Folds the expression's operands first, so whole chains like
+    /// `1 + 2 + 3` collapse from the inside out.
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression);
+
+        let folded = match expression {
+            Expression::Prefix {
+                operator, right, ..
+            } => literal_value(right)
+                .and_then(|right| eval_prefix_expression(*operator, right, Overflow::Error).ok())
+                .and_then(literal_expression),
+            Expression::Infix {
+                left,
+                operator,
+                right,
+                ..
+            } => fold_infix(left, *operator, right),
+            // Only the branch that would run is kept.
+            Expression::Conditional {
+                condition,
+                consequence,
+                alternative,
+            } => literal_value(condition).map(|condition| {
+                if condition.is_truthy() {
+                    take(consequence)
+                } else {
+                    take(alternative)
+                }
+            }),
+            _ => None,
+        };
+
+        if let Some(folded) = folded {
+            *expression = folded;
+        }
+    }
+}
+
+/// The folded form of `left operator right`, if there is one. `&&` and `||`
+/// only need a constant left side when it decides the result on its own,
+/// since the right side is never evaluated then.
+fn fold_infix(
+    left: &Expression,
+    operator: InfixOperator,
+    right: &mut Expression,
+) -> Option<Expression> {
+    let left = literal_value(left)?;
+
+    match operator {
+        InfixOperator::And | InfixOperator::Or => {
+            let decided = left.is_truthy() == (operator == InfixOperator::Or);
+            if decided {
+                Some(Expression::Bool(left.is_truthy()))
+            } else {
+                literal_value(right).map(|right| Expression::Bool(right.is_truthy()))
+            }
+        }
+        InfixOperator::Coalesce => match left {
+            Value::Null => Some(take(right)),
+            left => literal_expression(left),
+        },
+        _ => {
+            // Overflow is left for the evaluator to handle however it's
+            // configured to.
+            let right = literal_value(right)?;
+            let value = eval_infix_expression(operator, left, right, Overflow::Error).ok()?;
+            literal_expression(value)
+        }
+    }
+}
+
+/// Moves `expression` out to replace what it's part of with, rather than
+/// copying it, as it may be a long chain of operators.
+fn take(expression: &mut Expression) -> Expression {
+    std::mem::replace(expression, Expression::Null)
+}
+
+fn literal_value(expression: &Expression) -> Option<Value> {
+    match expression {
+        Expression::Int(value) => Some(Value::Int(*value)),
+        Expression::Float(value) => Some(Value::Float(*value)),
+        Expression::Str(value) => Some(Value::Str(value.clone())),
+        Expression::Char(value) => Some(Value::Char(*value)),
+        Expression::Bool(value) => Some(Value::Bool(*value)),
+        Expression::Null => Some(Value::Null),
+        _ => None,
+    }
+}
+
+fn literal_expression(value: Value) -> Option<Expression> {
+    match value {
+        Value::Int(value) => Some(Expression::Int(value)),
+        Value::Float(value) => Some(Expression::Float(value)),
+        Value::Str(value) => Some(Expression::Str(value)),
+        Value::Char(value) => Some(Expression::Char(value)),
+        Value::Bool(value) => Some(Expression::Bool(value)),
+        Value::Null => Some(Expression::Null),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn fold(input: &str) -> String {
+        let mut parser = Parser::new(Lexer::new(input));
+        let mut program = parser.parse_program();
+
+        assert!(
+            parser.errors().is_empty(),
+            "parser had errors: {:?}",
+            parser.errors()
+        );
+
+        program.fold_constants();
+        program.to_string()
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        let tests = vec![
+            ("2 * 3 + 1", "7;"),
+            ("-(2 - 5)", "3;"),
+            ("1.5 * 2.0", "3.0;"),
+            ("1 << 4 | 1", "17;"),
+            ("\"tofu\" + \" \" + \"rocks\"", "\"tofu rocks\";"),
+            ("1 < 2 == true", "true;"),
+            ("!null", "true;"),
+            ("false && x", "false;"),
+            ("true || x()", "true;"),
+            ("true && 1", "true;"),
+            ("null || false", "false;"),
+            ("null ?? x * (1 + 1)", "x * 2;"),
+            ("0 ?? x", "0;"),
+            ("1 < 2 ? x : y()", "x;"),
+            ("null ? x : 1 + 1", "2;"),
+            ("x + 2 * 3", "x + 6;"),
+            (
+                "let f = fn(a) { return a * (4 - 2); }",
+                "let f = fn(a) { return a * 2; };",
+            ),
+            (
+                "for (i in 0..2 + 1) { [i * 1, 1 + 1]; }",
+                "for (i in 0..3) { [i * 1, 2]; }",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(expected, fold(input), "folding {input}");
+        }
+    }
+
+    #[test]
+    fn test_leaves_runtime_errors() {
+        let tests = vec![
+            ("1 / 0", "1 / 0;"),
+            ("1 << 64", "1 << 64;"),
+            ("9223372036854775807 + 1", "9223372036854775807 + 1;"),
+            ("1 + true", "1 + true;"),
+            ("true && x", "true && x;"),
+            ("x || false", "x || false;"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(expected, fold(input), "folding {input}");
+        }
+    }
+}
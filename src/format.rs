@@ -0,0 +1,821 @@
+//! Reprints Tofu source in one canonical layout: four spaces of indent per
+//! block, one statement per line, no semicolons, and lists and calls that
+//! don't fit in [`WIDTH`] columns broken up with an item per line. Comments
+//! and single blank lines are kept where they were, between statements.
+//!
+//! The AST doesn't keep comments, so they're picked out of the gaps between
+//! tokens, as [`crate::highlight`] does, and put back next to the statements
+//! they came before or after, using where the parser saw each start and end.
+//! Comments inside an expression, like between the elements of an array,
+//! end up after the statement it's in.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Display, Formatter};
+
+use crate::ast::{
+    next_precedence, with_stack, BlockStatement, Expression, ImportSource, InfixOperator, MatchArm,
+    Precedence, Program, Statement,
+};
+use crate::highlight::{line_starts, offset};
+use crate::lexer::Lexer;
+use crate::parser::{ParseError, Parser};
+use crate::token::{Span, TokenKind};
+use crate::visit::{walk_block, walk_statement, Visit};
+
+/// How many columns a line may take before what's on it is broken up.
+pub const WIDTH: usize = 100;
+
+const INDENT: &str = "    ";
+
+/// Why source couldn't be formatted.
+#[derive(Debug)]
+pub enum FormatError {
+    Parse(Vec<ParseError>),
+    /// The formatted source wouldn't parse to the same program, which is a
+    /// bug in the formatter, so the source is best left as it is.
+    Changed,
+}
+
+impl Display for FormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Parse(errors) => {
+                for (index, error) in errors.iter().enumerate() {
+                    if index > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{error}")?;
+                }
+                Ok(())
+            }
+            FormatError::Changed => write!(f, "formatting would change what the program does"),
+        }
+    }
+}
+
+/// `source` in the canonical layout, ending in a newline.
+pub fn format(source: &str) -> Result<String, FormatError> {
+    let (program, parser) = parse(source)?;
+
+    let (statement_starts, block_ends) = positions(&program, &parser);
+
+    let mut printer = Printer {
+        out: String::with_capacity(source.len()),
+        indent: 0,
+        comments: comments(source),
+        blank: source
+            .split('\n')
+            .map(|line| line.trim().is_empty())
+            .collect(),
+        statement_starts,
+        block_ends,
+    };
+    printer.statements(&program.statements, None);
+
+    let mut formatted: String = printer
+        .out
+        .lines()
+        .map(|line| format!("{}\n", line.trim_end()))
+        .collect();
+    formatted = formatted.trim_start_matches('\n').to_string();
+
+    // Comments and layout aside, the program has to stay the same.
+    match parse(&formatted) {
+        Ok((reformatted, _)) if reformatted.to_string() == program.to_string() => Ok(formatted),
+        _ => Err(FormatError::Changed),
+    }
+}
+
+fn parse(source: &str) -> Result<(Program, Parser<'_>), FormatError> {
+    let mut lexer = Lexer::new(source);
+    lexer.set_newline_terminators(true);
+    let mut parser = Parser::new(lexer);
+    let program = parser.parse_program();
+    if !parser.errors().is_empty() {
+        return Err(FormatError::Parse(parser.errors().to_vec()));
+    }
+    Ok((program, parser))
+}
+
+/// Where each statement of `program`, which `parser` parsed, starts, and
+/// where each of its blocks ends, by the address of the node.
+#[allow(clippy::type_complexity)]
+pub(crate) fn positions(
+    program: &Program,
+    parser: &Parser,
+) -> (
+    HashMap<*const Statement, Span>,
+    HashMap<*const BlockStatement, Span>,
+) {
+    let mut positions = Positions::default();
+    positions.visit_program(program);
+    let statement_starts = positions
+        .statements
+        .into_iter()
+        .zip(parser.statement_starts().iter().copied())
+        .collect();
+    let block_ends = positions
+        .blocks
+        .into_iter()
+        .zip(parser.block_ends().iter().copied())
+        .collect();
+    (statement_starts, block_ends)
+}
+
+/// The statements and blocks of a program, in the orders
+/// [`Parser::statement_starts`] and [`Parser::block_ends`] list them.
+#[derive(Default)]
+struct Positions {
+    statements: Vec<*const Statement>,
+    blocks: Vec<*const BlockStatement>,
+}
+
+impl Visit for Positions {
+    fn visit_statement(&mut self, statement: &Statement) {
+        self.statements.push(statement);
+        walk_statement(self, statement);
+    }
+
+    fn visit_block(&mut self, block: &BlockStatement) {
+        walk_block(self, block);
+        self.blocks.push(block);
+    }
+}
+
+pub(crate) struct Comment {
+    /// Where the comment starts.
+    pub span: Span,
+    pub text: String,
+    /// Whether the comment starts its line, rather than following code.
+    pub own_line: bool,
+}
+
+/// The comments in `source`, in order, including doc comments and a `#!`
+/// line.
+pub(crate) fn comments(source: &str) -> VecDeque<Comment> {
+    let lines = line_starts(source);
+    let mut lexer = Lexer::new(source);
+    let mut comments = VecDeque::new();
+    let mut add = |start: usize, end: usize| {
+        let line = lines.partition_point(|&line_start| line_start <= start);
+        let before = &source[lines[line - 1]..start];
+        comments.push_back(Comment {
+            span: Span {
+                line,
+                column: before.chars().count() + 1,
+            },
+            text: source[start..end].trim_end().to_string(),
+            own_line: before.trim().is_empty(),
+        });
+    };
+
+    let mut end = 0;
+    loop {
+        let token = lexer.next_token();
+        let start = offset(source, &lines, token.span).max(end);
+
+        // The lexer skips whitespace and comments, so that's what the gaps
+        // between tokens hold.
+        let mut position = end;
+        while position < start {
+            let rest = &source[position..start];
+            let length = if rest.starts_with("//") || rest.starts_with("#!") {
+                rest.find('\n').unwrap_or(rest.len())
+            } else if rest.starts_with("/*") {
+                block_comment_length(rest)
+            } else {
+                position += rest.chars().next().map_or(1, char::len_utf8);
+                continue;
+            };
+            add(position, position + length);
+            position += length;
+        }
+
+        if token.kind == TokenKind::Eof {
+            return comments;
+        }
+        end = lexer.offset();
+        if token.kind == TokenKind::DocComment {
+            add(start, end);
+        }
+    }
+}
+
+/// The length of the `/* */` comment `text` starts with, which can nest.
+fn block_comment_length(text: &str) -> usize {
+    let mut depth = 0;
+    let mut position = 0;
+    while position < text.len() {
+        let rest = &text[position..];
+        if rest.starts_with("/*") {
+            depth += 1;
+            position += 2;
+        } else if rest.starts_with("*/") {
+            depth -= 1;
+            position += 2;
+            if depth == 0 {
+                return position;
+            }
+        } else {
+            position += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    text.len()
+}
+
+fn before(span: Span, other: Span) -> bool {
+    (span.line, span.column) < (other.line, other.column)
+}
+
+struct Printer {
+    out: String,
+    /// How many blocks deep the line being written is.
+    indent: usize,
+    /// The comments not yet written.
+    comments: VecDeque<Comment>,
+    /// Whether each line of the source, from the first, is blank.
+    blank: Vec<bool>,
+    statement_starts: HashMap<*const Statement, Span>,
+    block_ends: HashMap<*const BlockStatement, Span>,
+}
+
+impl Printer {
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    /// Whether the source had a blank line just before `line`.
+    fn blank_before(&self, line: usize) -> bool {
+        line >= 2 && self.blank.get(line - 2).copied().unwrap_or(false)
+    }
+
+    /// How many columns the line being written takes so far.
+    fn column(&self) -> usize {
+        let start = self.out.rfind('\n').map_or(0, |index| index + 1);
+        self.out[start..].chars().count()
+    }
+
+    /// Writes `statements`, one per line, with the comments that came
+    /// before each, then any left before `end`, where the `}` that closes
+    /// them is, or all that are left at the end of the program.
+    fn statements(&mut self, statements: &[Statement], end: Option<Span>) {
+        let mut first = true;
+        for statement in statements {
+            let start = self.statement_starts.get(&(statement as *const _)).copied();
+            if let Some(start) = start {
+                self.comments_before(start, &mut first);
+                if !first && self.blank_before(start.line) {
+                    self.out.push('\n');
+                }
+            }
+            self.newline();
+            self.statement(statement);
+            first = false;
+        }
+        let end = end.unwrap_or(Span {
+            line: usize::MAX,
+            column: usize::MAX,
+        });
+        self.comments_before(end, &mut first);
+    }
+
+    /// Writes the comments that start before `position`. Ones that followed
+    /// code go after what's been written last, and the rest on lines of
+    /// their own.
+    fn comments_before(&mut self, position: Span, first: &mut bool) {
+        while let Some(comment) = self.comments.front() {
+            if !before(comment.span, position) {
+                return;
+            }
+            let comment = self.comments.pop_front().unwrap();
+            if comment.own_line {
+                if !*first && self.blank_before(comment.span.line) {
+                    self.out.push('\n');
+                }
+                self.newline();
+                *first = false;
+            } else {
+                self.out.push(' ');
+            }
+            self.out.push_str(&comment.text);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let(statement) => {
+                // Its doc comments are written with the other comments.
+                self.out
+                    .push_str(&format!("{} {}", statement.kind, statement.pattern));
+                if let Some(annotation) = &statement.annotation {
+                    self.out.push_str(&format!(": {annotation}"));
+                }
+                self.out.push_str(" = ");
+                self.expression(&statement.value, Precedence::Lowest);
+            }
+            Statement::Return(Some(value)) => {
+                self.out.push_str("return ");
+                self.expression(value, Precedence::Lowest);
+            }
+            Statement::Return(None) => self.out.push_str("return"),
+            Statement::Expression(expression) => self.expression(expression, Precedence::Lowest),
+            Statement::While { condition, body } => {
+                self.out.push_str("while (");
+                self.expression(condition, Precedence::Lowest);
+                self.out.push_str(") ");
+                self.block_lines(body);
+            }
+            Statement::For {
+                pattern,
+                iterable,
+                body,
+            } => {
+                self.out.push_str(&format!("for ({pattern} in "));
+                self.expression(iterable, Precedence::Lowest);
+                self.out.push_str(") ");
+                self.block_lines(body);
+            }
+            Statement::Break => self.out.push_str("break"),
+            Statement::Continue => self.out.push_str("continue"),
+            Statement::Import(import) => match &import.source {
+                ImportSource::Name(name) => self.out.push_str(&format!("import {name}")),
+                ImportSource::Path(path) => self.out.push_str(&format!("import {path:?}")),
+            },
+            Statement::Throw { value, .. } => {
+                self.out.push_str("throw ");
+                self.expression(value, Precedence::Lowest);
+            }
+            Statement::Struct { .. } | Statement::Enum { .. } => {
+                self.out.push_str(&statement.to_string())
+            }
+        }
+    }
+
+    /// Writes `expression`, in parentheses if it binds looser than
+    /// `precedence`: all on the line if it fits, and broken up otherwise.
+    fn expression(&mut self, expression: &Expression, precedence: Precedence) {
+        let parenthesized = expression.precedence() < precedence;
+        if parenthesized {
+            self.out.push('(');
+        }
+        let start = self.out.len();
+        let fits = self.write(expression, true)
+            && !self.out[start..].contains('\n')
+            && self.column() <= WIDTH;
+        if !fits {
+            self.out.truncate(start);
+            self.write(expression, false);
+        }
+        if parenthesized {
+            self.out.push(')');
+        }
+    }
+
+    /// Writes an operand of an expression being written `flat`, or not,
+    /// returning whether it could be.
+    fn operand(&mut self, expression: &Expression, precedence: Precedence, flat: bool) -> bool {
+        if !flat {
+            self.expression(expression, precedence);
+            return true;
+        }
+        let parenthesized = expression.precedence() < precedence;
+        if parenthesized {
+            self.out.push('(');
+        }
+        let written = self.write(expression, true);
+        if parenthesized {
+            self.out.push(')');
+        }
+        written
+    }
+
+    /// Writes `expression` as the AST prints it, but with blocks laid out
+    /// as [`Printer::block`] lays them out. If `flat`, it's all on one
+    /// line, and this returns `false` if that can't be.
+    fn write(&mut self, expression: &Expression, flat: bool) -> bool {
+        with_stack(|| match expression {
+            Expression::Identifier { .. }
+            | Expression::Int(_)
+            | Expression::Float(_)
+            | Expression::Str(_)
+            | Expression::Char(_)
+            | Expression::Bool(_)
+            | Expression::Null => {
+                self.out.push_str(&expression.to_string());
+                true
+            }
+            Expression::Prefix {
+                operator, right, ..
+            } => {
+                self.out.push_str(&operator.to_string());
+                self.operand(right, Precedence::Prefix, flat)
+            }
+            Expression::Infix {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                let (left_precedence, right_precedence) = if *operator == InfixOperator::Power {
+                    (next_precedence(Precedence::Power), Precedence::Prefix)
+                } else {
+                    let precedence = operator.precedence();
+                    (precedence, next_precedence(precedence))
+                };
+                self.operand(left, left_precedence, flat)
+                    && self.text(&format!(" {operator} "))
+                    && self.operand(right, right_precedence, flat)
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.out.push_str("if (");
+                if !self.operand(condition, Precedence::Lowest, flat) {
+                    return false;
+                }
+                self.out.push_str(") ");
+                if !self.block(consequence, flat) {
+                    return false;
+                }
+                let Some(alternative) = alternative else {
+                    return true;
+                };
+                self.out.push_str(" else ");
+                match alternative.statements.as_slice() {
+                    [Statement::Expression(nested @ Expression::If { .. })] => {
+                        self.write(nested, flat)
+                    }
+                    _ => self.block(alternative, flat),
+                }
+            }
+            Expression::Function {
+                parameters,
+                return_type,
+                body,
+            } => {
+                self.out.push_str(&format!("fn({parameters}) "));
+                if let Some(return_type) = return_type {
+                    self.out.push_str(&format!("-> {return_type} "));
+                }
+                self.block(body, flat)
+            }
+            Expression::Call {
+                function,
+                arguments,
+                ..
+            } => {
+                self.operand(function, Precedence::Call, flat)
+                    && self.list("(", arguments, ")", flat)
+            }
+            Expression::MethodCall {
+                receiver,
+                method,
+                arguments,
+                ..
+            } => {
+                self.operand(receiver, Precedence::Call, flat)
+                    && self.text(&format!(".{method}"))
+                    && self.list("(", arguments, ")", flat)
+            }
+            Expression::Array(elements) => self.list("[", elements, "]", flat),
+            // One element keeps its trailing comma, or it'd be a grouping.
+            Expression::Tuple(elements) if elements.len() == 1 => {
+                self.text("(")
+                    && self.operand(&elements[0], Precedence::Lowest, flat)
+                    && self.text(",)")
+            }
+            Expression::Tuple(elements) => self.list("(", elements, ")", flat),
+            Expression::Hash(pairs) => self.hash(pairs, flat),
+            Expression::Try {
+                body,
+                pattern,
+                handler,
+            } => {
+                self.out.push_str("try ");
+                self.block(body, flat)
+                    && self.text(&format!(" catch ({pattern}) "))
+                    && self.block(handler, flat)
+            }
+            // Always one arm per line.
+            Expression::Match { .. } if flat => false,
+            Expression::Match { subject, arms, .. } => {
+                self.out.push_str("match ");
+                self.expression(subject, Precedence::Lowest);
+                self.out.push_str(" {");
+                self.indent += 1;
+                for arm in arms {
+                    self.arm(arm);
+                }
+                self.indent -= 1;
+                self.newline();
+                self.out.push('}');
+                true
+            }
+            Expression::Spread(value) => {
+                self.out.push_str("...");
+                self.operand(value, Precedence::Lowest, flat)
+            }
+            Expression::Named { name, value } => {
+                self.out.push_str(&format!("{name}: "));
+                self.operand(value, Precedence::Lowest, flat)
+            }
+            Expression::Conditional {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.operand(condition, next_precedence(Precedence::Conditional), flat)
+                    && self.text(" ? ")
+                    && self.operand(consequence, Precedence::Lowest, flat)
+                    && self.text(" : ")
+                    && self.operand(alternative, Precedence::Conditional, flat)
+            }
+            Expression::Index {
+                left,
+                index,
+                optional,
+                ..
+            } => {
+                self.operand(left, Precedence::Call, flat)
+                    && self.text(if *optional { "?[" } else { "[" })
+                    && self.operand(index, Precedence::Lowest, flat)
+                    && self.text("]")
+            }
+            Expression::Assign {
+                target,
+                operator,
+                value,
+                ..
+            } => {
+                let operator = match operator {
+                    Some(operator) => format!(" {operator}= "),
+                    None => " = ".to_string(),
+                };
+                self.operand(target, Precedence::Call, flat)
+                    && self.text(&operator)
+                    && self.operand(value, Precedence::Assign, flat)
+            }
+            Expression::Range {
+                start,
+                end,
+                inclusive,
+                ..
+            } => {
+                let tighter = next_precedence(Precedence::Range);
+                let bound = |this: &mut Self, bound: &Option<Box<Expression>>| match bound {
+                    Some(bound) => this.operand(bound, tighter, flat),
+                    None => true,
+                };
+                bound(self, start)
+                    && self.text(if *inclusive { "..=" } else { ".." })
+                    && bound(self, end)
+            }
+        })
+    }
+
+    /// Writes `text`, so it can be chained with the writes around it.
+    fn text(&mut self, text: &str) -> bool {
+        self.out.push_str(text);
+        true
+    }
+
+    /// Writes `items` between `open` and `close`: separated by commas if
+    /// `flat`, and otherwise one per line, unless they fit on the line as
+    /// they are, or only the last one needs breaking up, like a function
+    /// passed last.
+    fn list(&mut self, open: &str, items: &[Expression], close: &str, flat: bool) -> bool {
+        if !flat {
+            // What comes before the list can be what didn't fit.
+            let start = self.out.len();
+            if self.list(open, items, close, true)
+                && !self.out[start..].contains('\n')
+                && self.column() <= WIDTH
+            {
+                return true;
+            }
+            self.out.truncate(start);
+        }
+
+        self.out.push_str(open);
+        if flat || items.is_empty() {
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    self.out.push_str(", ");
+                }
+                if !self.operand(item, Precedence::Lowest, true) {
+                    return false;
+                }
+            }
+            self.out.push_str(close);
+            return true;
+        }
+
+        if let Some((last, rest)) = items.split_last() {
+            if matches!(
+                last,
+                Expression::Function { .. } | Expression::If { .. } | Expression::Try { .. }
+            ) {
+                let start = self.out.len();
+                let hugged = rest
+                    .iter()
+                    .all(|item| self.operand(item, Precedence::Lowest, true) && self.text(", "));
+                if hugged && !self.out[start..].contains('\n') && self.column() < WIDTH {
+                    self.expression(last, Precedence::Lowest);
+                    self.out.push_str(close);
+                    return true;
+                }
+                self.out.truncate(start);
+            }
+        }
+
+        self.indent += 1;
+        for (index, item) in items.iter().enumerate() {
+            self.newline();
+            self.expression(item, Precedence::Lowest);
+            if index + 1 < items.len() {
+                self.out.push(',');
+            }
+        }
+        self.indent -= 1;
+        self.newline();
+        self.out.push_str(close);
+        true
+    }
+
+    fn hash(&mut self, pairs: &[(Expression, Expression)], flat: bool) -> bool {
+        if pairs.is_empty() {
+            self.out.push_str("{}");
+            return true;
+        }
+
+        if flat {
+            self.out.push('{');
+            for (index, (key, value)) in pairs.iter().enumerate() {
+                self.out.push_str(if index > 0 { ", " } else { " " });
+                if !(self.operand(key, Precedence::Lowest, true)
+                    && self.text(": ")
+                    && self.operand(value, Precedence::Lowest, true))
+                {
+                    return false;
+                }
+            }
+            self.out.push_str(" }");
+            return true;
+        }
+
+        self.out.push('{');
+        self.indent += 1;
+        for (key, value) in pairs {
+            self.newline();
+            self.expression(key, Precedence::Lowest);
+            self.out.push_str(": ");
+            self.expression(value, Precedence::Lowest);
+            self.out.push(',');
+        }
+        self.indent -= 1;
+        self.newline();
+        self.out.push('}');
+        true
+    }
+
+    /// Writes `block` on one line, `{ x }`, if `flat`, which it can be if
+    /// it's no more than an expression with no comments in it, and over
+    /// several lines otherwise.
+    fn block(&mut self, block: &BlockStatement, flat: bool) -> bool {
+        if !flat {
+            self.block_lines(block);
+            return true;
+        }
+
+        let end = self.block_ends.get(&(block as *const _)).copied();
+        let has_comments = end.is_some_and(|end| {
+            self.comments
+                .front()
+                .is_some_and(|comment| before(comment.span, end))
+        });
+        match block.statements.as_slice() {
+            _ if has_comments => false,
+            [] => self.text("{}"),
+            [Statement::Expression(expression)] => {
+                self.out.push_str("{ ");
+                self.operand(expression, Precedence::Lowest, true) && self.text(" }")
+            }
+            _ => false,
+        }
+    }
+
+    /// Writes a `match` arm on a line of its own, with a body of one
+    /// expression after the `=>` and any other body as a block.
+    fn arm(&mut self, arm: &MatchArm) {
+        let pattern = &arm.pattern;
+        match arm.body.statements.as_slice() {
+            [statement @ Statement::Expression(expression)]
+                if !matches!(expression, Expression::Hash(_)) =>
+            {
+                let start = self.statement_starts.get(&(statement as *const _)).copied();
+                if let Some(start) = start {
+                    self.comments_before(start, &mut true);
+                }
+                self.newline();
+                self.out.push_str(&format!("{pattern} => "));
+                self.expression(expression, Precedence::Lowest);
+                self.out.push(',');
+                // With the comment that follows it on its line.
+                let end = self.block_ends.get(&(&arm.body as *const _)).copied();
+                if let Some(end) = end {
+                    let line_end = Span {
+                        line: end.line,
+                        column: usize::MAX,
+                    };
+                    self.comments_before(line_end, &mut false);
+                }
+            }
+            _ => {
+                self.newline();
+                self.out.push_str(&format!("{pattern} => "));
+                self.block_lines(&arm.body);
+            }
+        }
+    }
+
+    fn block_lines(&mut self, block: &BlockStatement) {
+        let end = self.block_ends.get(&(block as *const _)).copied();
+        self.out.push('{');
+        self.indent += 1;
+        self.statements(&block.statements, end);
+        self.indent -= 1;
+        if self.out.ends_with('{') {
+            self.out.push('}');
+            return;
+        }
+        self.newline();
+        self.out.push('}');
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format, FormatError};
+
+    #[test]
+    fn test_format() {
+        let tests = [
+            ("let x=1;let y = x+2", "let x = 1\nlet y = x + 2\n"),
+            (
+                "// The answer.\n\n\n/// Doubles.\nlet double = fn(x){x*2}; // twice\ndouble(21)",
+                "// The answer.\n\n/// Doubles.\nlet double = fn(x) { x * 2 } // twice\ndouble(21)\n",
+            ),
+            (
+                "let f = fn(xs) { let total = 0; for (x in xs) { total += x }; total }",
+                "let f = fn(xs) {\n    let total = 0\n    for (x in xs) {\n        total += x\n    }\n    total\n}\n",
+            ),
+            (
+                "if (a) { 1 } else if (b) { 2 } else { /* none */ 3 }",
+                "if (a) {\n    1\n} else if (b) {\n    2\n} else { /* none */\n    3\n}\n",
+            ),
+            (
+                "let f = fn() { // starts here\n  1;\n\n  // ends here\n}",
+                "let f = fn() { // starts here\n    1\n\n    // ends here\n}\n",
+            ),
+            ("while (true) {}", "while (true) {}\n"),
+            (
+                "let xs = map([1, 2, 3], fn(x) { let y = x; y })",
+                "let xs = map([1, 2, 3], fn(x) {\n    let y = x\n    y\n})\n",
+            ),
+            ("#!/usr/bin/env tofu\nprint((1 + 2) * 3)", "#!/usr/bin/env tofu\nprint((1 + 2) * 3)\n"),
+            (
+                "enum S {\n  C(r),\n  D\n}\nlet n = match s { C(r) => r // the radius\n  D => { let z = 0; z } }",
+                "enum S { C(r), D }\nlet n = match s {\n    C(r) => r, // the radius\n    D => {\n        let z = 0\n        z\n    }\n}\n",
+            ),
+        ];
+        for (input, expected) in tests {
+            let formatted = format(input).unwrap();
+            assert_eq!(expected, formatted, "input={input}");
+            assert_eq!(formatted, format(&formatted).unwrap(), "input={input}");
+        }
+
+        // Too long for a line.
+        let long = format!(
+            "let h = {{\"{}\": [{}], \"b\": 2}}",
+            "a".repeat(50),
+            "1, ".repeat(20) + "1"
+        );
+        let formatted = format(&long).unwrap();
+        assert!(
+            formatted.starts_with("let h = {\n    \"aaaa"),
+            "{formatted}"
+        );
+        assert!(
+            formatted.lines().all(|line| line.len() <= 100),
+            "{formatted}"
+        );
+        assert_eq!(formatted, format(&formatted).unwrap());
+
+        assert!(matches!(format("let = 1"), Err(FormatError::Parse(_))));
+    }
+}
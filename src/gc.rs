@@ -0,0 +1,610 @@
+//! Reclaims values kept alive only by reference cycles. A closure holds on
+//! to the environment it was defined in, so a function bound in that same
+//! environment, or in one nested in it, makes a cycle that reference
+//! counting alone never frees. A compiled closure does the same with the
+//! variables it captured, and arrays, hashes and structs that contain
+//! themselves do too, directly or through tuples and enum values.
+//!
+//! The [`Collector`] finds such cycles by trial deletion. It starts from
+//! what every cycle passes through: the environments closures have
+//! captured, the compiled closures that captured variables, and the
+//! arrays, hashes and structs something was stored in after they were
+//! made, which is the only way one comes to contain itself. It walks
+//! everything reachable from those and counts the references each object
+//! gets from the others it found. An object with more references than
+//! that is held from outside, by the evaluator or by Rust code, and is
+//! alive, along with everything it reaches. The rest can only be reached
+//! from each other, and is freed by clearing it.
+//!
+//! Miscounting can only ever keep garbage alive: a reference the walk
+//! doesn't see counts as one from outside.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+use indexmap::IndexMap;
+
+use crate::environment::Environment;
+use crate::value::{Function, HashKey, Struct, Value, Variant};
+use crate::vm::Closure;
+
+/// How many roots to track before collecting for the first time.
+const MIN_THRESHOLD: usize = 1024;
+
+/// Tracks what cycles can pass through, and collects the cycles through
+/// them.
+pub struct Collector {
+    roots: Vec<Root>,
+    /// How many roots to track before the next automatic collection. It
+    /// grows with what survives, so collections take time in proportion to
+    /// the garbage they can find.
+    threshold: usize,
+}
+
+/// Something a cycle can pass through, held without keeping it alive.
+enum Root {
+    Environment(Weak<RefCell<Environment>>),
+    Closure(Weak<Closure>),
+    Array(Weak<RefCell<Vec<Value>>>),
+    Hash(Weak<RefCell<IndexMap<HashKey, Value>>>),
+    Struct(Weak<Struct>),
+}
+
+impl Root {
+    fn upgrade(&self) -> Option<Object> {
+        match self {
+            Root::Environment(env) => env.upgrade().map(Object::Environment),
+            Root::Closure(closure) => closure.upgrade().map(Object::Closure),
+            Root::Array(elements) => elements.upgrade().map(Object::Array),
+            Root::Hash(pairs) => pairs.upgrade().map(Object::Hash),
+            Root::Struct(instance) => instance.upgrade().map(Object::Struct),
+        }
+    }
+
+    fn address(&self) -> usize {
+        match self {
+            Root::Environment(env) => env.as_ptr() as *const () as usize,
+            Root::Closure(closure) => closure.as_ptr() as *const () as usize,
+            Root::Array(elements) => elements.as_ptr() as *const () as usize,
+            Root::Hash(pairs) => pairs.as_ptr() as *const () as usize,
+            Root::Struct(instance) => instance.as_ptr() as *const () as usize,
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            Root::Environment(env) => env.strong_count() > 0,
+            Root::Closure(closure) => closure.strong_count() > 0,
+            Root::Array(elements) => elements.strong_count() > 0,
+            Root::Hash(pairs) => pairs.strong_count() > 0,
+            Root::Struct(instance) => instance.strong_count() > 0,
+        }
+    }
+}
+
+impl Default for Collector {
+    fn default() -> Collector {
+        Collector::new()
+    }
+}
+
+impl Collector {
+    pub fn new() -> Collector {
+        Collector {
+            roots: Vec::new(),
+            threshold: MIN_THRESHOLD,
+        }
+    }
+
+    /// Notes that a closure captured `env`, and returns whether enough
+    /// has been tracked since the last collection to collect again.
+    pub fn track(&mut self, env: &Rc<RefCell<Environment>>) -> bool {
+        self.root(Root::Environment(Rc::downgrade(env)))
+    }
+
+    /// Notes that a compiled closure captured variables, like
+    /// [`Collector::track`].
+    pub fn track_closure(&mut self, closure: &Rc<Closure>) -> bool {
+        self.root(Root::Closure(Rc::downgrade(closure)))
+    }
+
+    /// Notes that `value` was stored in `container`, like
+    /// [`Collector::track`]. Only an array, hash or struct that was given
+    /// something that can refer back to it needs tracking.
+    pub fn track_store(&mut self, container: &Value, value: &Value) -> bool {
+        if Object::of(value).is_none() {
+            return false;
+        }
+        let root = match container {
+            Value::Array(elements) => Root::Array(Rc::downgrade(elements)),
+            Value::Hash(pairs) => Root::Hash(Rc::downgrade(pairs)),
+            Value::Struct(instance) => Root::Struct(Rc::downgrade(instance)),
+            _ => return false,
+        };
+        self.root(root)
+    }
+
+    fn root(&mut self, root: Root) -> bool {
+        // Functions defined one after another usually share an
+        // environment, and a loop usually fills one container.
+        if self
+            .roots
+            .last()
+            .is_none_or(|last| last.address() != root.address())
+        {
+            self.roots.push(root);
+        }
+        self.roots.len() >= self.threshold
+    }
+
+    /// Frees every cycle that nothing outside it refers to, and returns how
+    /// many environments, variables and containers that freed.
+    pub fn collect(&mut self) -> usize {
+        let mut graph = Graph::default();
+        for root in self.roots.iter().filter_map(Root::upgrade) {
+            graph.add(root);
+        }
+        graph.explore();
+
+        let garbage = graph.garbage();
+        let scanned = graph.scanned;
+        let freed = garbage
+            .iter()
+            .filter(|object| {
+                !matches!(
+                    object,
+                    Object::Function(_)
+                        | Object::Closure(_)
+                        | Object::Tuple(_)
+                        | Object::Variant(_)
+                )
+            })
+            .count();
+
+        // Taken out first and dropped last, so no object is borrowed while
+        // the values it held are dropped.
+        let mut contents = Vec::new();
+        for object in &garbage {
+            object.clear(&mut contents);
+        }
+        drop(garbage);
+        drop(graph);
+        drop(contents);
+
+        self.roots.retain(Root::is_alive);
+        // Waiting for as many roots as values were scanned keeps the cost
+        // of collecting a big heap to about one value per root.
+        self.threshold = (self.roots.len() * 2).max(scanned).max(MIN_THRESHOLD);
+
+        freed
+    }
+}
+
+/// Something shared through an `Rc` that can refer to other such things.
+#[derive(Clone)]
+enum Object {
+    Environment(Rc<RefCell<Environment>>),
+    Function(Rc<Function>),
+    Closure(Rc<Closure>),
+    /// A compiled closure's captured variable.
+    Cell(Rc<RefCell<Value>>),
+    Array(Rc<RefCell<Vec<Value>>>),
+    Hash(Rc<RefCell<IndexMap<HashKey, Value>>>),
+    Struct(Rc<Struct>),
+    Tuple(Rc<[Value]>),
+    Variant(Rc<Variant>),
+}
+
+impl Object {
+    fn of(value: &Value) -> Option<Object> {
+        match value {
+            Value::Function(function) => Some(Object::Function(Rc::clone(function))),
+            Value::Closure(closure) => Some(Object::Closure(Rc::clone(closure))),
+            Value::Array(elements) => Some(Object::Array(Rc::clone(elements))),
+            Value::Hash(pairs) => Some(Object::Hash(Rc::clone(pairs))),
+            Value::Struct(instance) => Some(Object::Struct(Rc::clone(instance))),
+            Value::Tuple(elements) => Some(Object::Tuple(Rc::clone(elements))),
+            Value::Variant(variant) => Some(Object::Variant(Rc::clone(variant))),
+            _ => None,
+        }
+    }
+
+    /// Identifies the object while it's alive.
+    fn address(&self) -> usize {
+        match self {
+            Object::Environment(env) => Rc::as_ptr(env) as *const () as usize,
+            Object::Function(function) => Rc::as_ptr(function) as *const () as usize,
+            Object::Closure(closure) => Rc::as_ptr(closure) as *const () as usize,
+            Object::Cell(cell) => Rc::as_ptr(cell) as *const () as usize,
+            Object::Array(elements) => Rc::as_ptr(elements) as *const () as usize,
+            Object::Hash(pairs) => Rc::as_ptr(pairs) as *const () as usize,
+            Object::Struct(instance) => Rc::as_ptr(instance) as *const () as usize,
+            Object::Tuple(elements) => Rc::as_ptr(elements) as *const () as usize,
+            Object::Variant(variant) => Rc::as_ptr(variant) as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Environment(env) => Rc::strong_count(env),
+            Object::Function(function) => Rc::strong_count(function),
+            Object::Closure(closure) => Rc::strong_count(closure),
+            Object::Cell(cell) => Rc::strong_count(cell),
+            Object::Array(elements) => Rc::strong_count(elements),
+            Object::Hash(pairs) => Rc::strong_count(pairs),
+            Object::Struct(instance) => Rc::strong_count(instance),
+            Object::Tuple(elements) => Rc::strong_count(elements),
+            Object::Variant(variant) => Rc::strong_count(variant),
+        }
+    }
+
+    /// The objects this one refers to, or `None` if it's being changed
+    /// right now and can't be looked into. Adds the number of values it
+    /// looked at to `scanned`.
+    fn references(&self, scanned: &mut usize) -> Option<Vec<Object>> {
+        let references: Vec<Object> = match self {
+            Object::Environment(env) => {
+                let env = env.try_borrow().ok()?;
+                *scanned += env.len();
+                env.values()
+                    .filter_map(Object::of)
+                    .chain(
+                        env.outer()
+                            .map(|outer| Object::Environment(Rc::clone(outer))),
+                    )
+                    .collect()
+            }
+            Object::Function(function) => vec![Object::Environment(Rc::clone(&function.env))],
+            Object::Closure(closure) => {
+                *scanned += closure.free().len();
+                closure
+                    .free()
+                    .iter()
+                    .map(|cell| Object::Cell(Rc::clone(cell)))
+                    .collect()
+            }
+            Object::Cell(cell) => {
+                *scanned += 1;
+                Object::of(&*cell.try_borrow().ok()?).into_iter().collect()
+            }
+            Object::Array(elements) => {
+                let elements = elements.try_borrow().ok()?;
+                *scanned += elements.len();
+                elements.iter().filter_map(Object::of).collect()
+            }
+            Object::Hash(pairs) => {
+                let pairs = pairs.try_borrow().ok()?;
+                *scanned += pairs.len();
+                pairs.values().filter_map(Object::of).collect()
+            }
+            Object::Struct(instance) => {
+                let fields = instance.fields.try_borrow().ok()?;
+                *scanned += fields.len();
+                fields.iter().filter_map(Object::of).collect()
+            }
+            Object::Tuple(elements) => {
+                *scanned += elements.len();
+                elements.iter().filter_map(Object::of).collect()
+            }
+            Object::Variant(variant) => {
+                *scanned += variant.values.len();
+                variant.values.iter().filter_map(Object::of).collect()
+            }
+        };
+
+        Some(references)
+    }
+
+    /// Empties the object into `contents`, which breaks any cycle through
+    /// it. Functions can't be emptied, but every cycle through one also
+    /// passes through the environment it captured, or for a compiled one,
+    /// the variables it captured. Tuples and enum values
+    /// can't either, and can only be in a cycle through something that can,
+    /// since nothing is added to them once they're made.
+    fn clear(&self, contents: &mut Vec<Box<dyn std::any::Any>>) {
+        match self {
+            Object::Environment(env) => {
+                if let Ok(mut env) = env.try_borrow_mut() {
+                    contents.push(Box::new(env.clear()));
+                }
+            }
+            Object::Function(_) | Object::Closure(_) | Object::Tuple(_) | Object::Variant(_) => {}
+            Object::Cell(cell) => {
+                if let Ok(mut value) = cell.try_borrow_mut() {
+                    contents.push(Box::new(std::mem::replace(&mut *value, Value::Null)));
+                }
+            }
+            Object::Array(elements) => {
+                if let Ok(mut elements) = elements.try_borrow_mut() {
+                    contents.push(Box::new(std::mem::take(&mut *elements)));
+                }
+            }
+            Object::Hash(pairs) => {
+                if let Ok(mut pairs) = pairs.try_borrow_mut() {
+                    contents.push(Box::new(std::mem::take(&mut *pairs)));
+                }
+            }
+            // Its fields are set to `null`, since it has to keep one each.
+            Object::Struct(instance) => {
+                if let Ok(mut fields) = instance.fields.try_borrow_mut() {
+                    let nulls = vec![Value::Null; fields.len()];
+                    contents.push(Box::new(std::mem::replace(&mut *fields, nulls)));
+                }
+            }
+        }
+    }
+}
+
+struct Node {
+    object: Object,
+    /// How many references to the object come from other nodes.
+    internal: usize,
+    /// The addresses of the nodes this one refers to, or `None` if it
+    /// couldn't be looked into, which keeps it alive.
+    references: Option<Vec<usize>>,
+}
+
+/// The objects reachable from the roots.
+#[derive(Default)]
+struct Graph {
+    nodes: HashMap<usize, Node>,
+    unexplored: Vec<usize>,
+    /// How many values were looked at exploring the graph, which is most
+    /// of the work of a collection.
+    scanned: usize,
+}
+
+impl Graph {
+    /// Adds an object, unless it's already there, and returns its address.
+    /// The graph holds exactly one reference to each object it has.
+    fn add(&mut self, object: Object) -> usize {
+        let address = object.address();
+        self.nodes.entry(address).or_insert_with(|| {
+            self.unexplored.push(address);
+            Node {
+                object,
+                internal: 0,
+                references: None,
+            }
+        });
+        address
+    }
+
+    /// Adds everything reachable from the objects added so far.
+    fn explore(&mut self) {
+        while let Some(address) = self.unexplored.pop() {
+            let Some(references) = self.nodes[&address].object.references(&mut self.scanned) else {
+                continue;
+            };
+
+            let addresses = references
+                .into_iter()
+                .map(|object| {
+                    let address = self.add(object);
+                    self.nodes.get_mut(&address).expect("just added").internal += 1;
+                    address
+                })
+                .collect();
+            self.nodes.get_mut(&address).expect("explored").references = Some(addresses);
+        }
+    }
+
+    /// The objects that are only referred to from each other.
+    fn garbage(&self) -> Vec<Object> {
+        let mut alive: Vec<usize> = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| {
+                // Less the graph's own reference.
+                let external = node.object.strong_count().checked_sub(1 + node.internal);
+                external != Some(0) || node.references.is_none()
+            })
+            .map(|(address, _)| *address)
+            .collect();
+
+        let mut marked: HashSet<usize> = alive.iter().copied().collect();
+        while let Some(address) = alive.pop() {
+            for reference in self.nodes[&address].references.iter().flatten() {
+                if marked.insert(*reference) {
+                    alive.push(*reference);
+                }
+            }
+        }
+
+        self.nodes
+            .iter()
+            .filter(|(address, _)| !marked.contains(address))
+            .map(|(_, node)| node.object.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::{Rc, Weak};
+
+    use crate::builtins::test::parse;
+    use crate::compiler::compile;
+    use crate::evaluator::Evaluator;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::value::{Function, Value};
+
+    fn eval(evaluator: &mut Evaluator, input: &str) -> Value {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+
+        assert!(
+            parser.errors().is_empty(),
+            "parser had errors: {:?}",
+            parser.errors()
+        );
+
+        evaluator.eval_program(&program).unwrap()
+    }
+
+    fn downgrade(value: Value) -> Weak<Function> {
+        match value {
+            Value::Function(function) => Rc::downgrade(&function),
+            other => panic!("expected a function, got {other}"),
+        }
+    }
+
+    #[test]
+    fn test_collects_closure_cycles() {
+        let mut evaluator = Evaluator::new();
+        eval(
+            &mut evaluator,
+            // `helper` is bound in the environment it captures, and `kept`
+            // is in a cycle through an array too.
+            "let leak = fn() {
+                let helper = fn() { helper };
+                let kept = [];
+                push(kept, fn() { kept });
+                helper
+            }",
+        );
+
+        let leaked: Vec<Weak<Function>> = (0..10)
+            .map(|_| downgrade(eval(&mut evaluator, "leak()")))
+            .collect();
+        assert!(leaked.iter().all(|function| function.upgrade().is_some()));
+
+        assert_ne!(0, evaluator.collect_garbage());
+        assert!(leaked.iter().all(|function| function.upgrade().is_none()));
+        assert_eq!(0, evaluator.collect_garbage());
+    }
+
+    #[test]
+    fn test_collects_cycles_through_tuples() {
+        let mut evaluator = Evaluator::new();
+        // The function is only reachable from its environment through the
+        // tuple holding it.
+        let leaked = downgrade(eval(
+            &mut evaluator,
+            "let leak = fn() { let pair = (fn() { pair }, 1); pair[0] }; leak()",
+        ));
+
+        assert!(leaked.upgrade().is_some());
+        assert_ne!(0, evaluator.collect_garbage());
+        assert!(leaked.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_collects_cycles_through_enum_values() {
+        let mut evaluator = Evaluator::new();
+        let leaked = downgrade(eval(
+            &mut evaluator,
+            "enum Holder { Full(f) };
+            let leak = fn() { let full = Full(fn() { full }); full.f };
+            leak()",
+        ));
+
+        assert!(leaked.upgrade().is_some());
+        assert_ne!(0, evaluator.collect_garbage());
+        assert!(leaked.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_collects_containers_holding_themselves() {
+        let mut evaluator = Evaluator::new();
+        let array = eval(&mut evaluator, "fn() { let a = []; push(a, a); a }()");
+        let nested = eval(
+            &mut evaluator,
+            "fn() { let a = [0]; insert(a, 0, [a]); a }()",
+        );
+        let hash = eval(&mut evaluator, "fn() { let h = {}; h[\"self\"] = h; h }()");
+        let (Value::Array(array), Value::Array(nested), Value::Hash(hash)) = (array, nested, hash)
+        else {
+            panic!("expected two arrays and a hash");
+        };
+
+        // Held from Rust, so still alive.
+        assert_eq!(0, evaluator.collect_garbage());
+        let weak = (
+            Rc::downgrade(&array),
+            Rc::downgrade(&nested),
+            Rc::downgrade(&hash),
+        );
+        drop((array, nested, hash));
+        let (array, nested, hash) = weak;
+        assert!(array.upgrade().is_some() && nested.upgrade().is_some());
+        assert!(hash.upgrade().is_some());
+
+        assert_ne!(0, evaluator.collect_garbage());
+        assert!(array.upgrade().is_none() && nested.upgrade().is_none());
+        assert!(hash.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_collects_compiled_closure_cycles() {
+        let mut evaluator = Evaluator::new();
+        let bytecode = compile(
+            &parse("let leak = fn() { let helper = fn() { helper }; helper }; leak()"),
+            evaluator.builtins(),
+        )
+        .expect("the program compiles");
+        let leaked = match crate::vm::run(&mut evaluator, &bytecode).unwrap() {
+            Value::Closure(closure) => Rc::downgrade(&closure),
+            other => panic!("expected a closure, got {other}"),
+        };
+
+        assert!(leaked.upgrade().is_some());
+        assert_ne!(0, evaluator.collect_garbage());
+        assert!(leaked.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_keeps_what_is_reachable() {
+        let mut evaluator = Evaluator::new();
+        let counter = eval(
+            &mut evaluator,
+            "let make_counter = fn() {
+                let count = 0;
+                let increment = fn() { count += 1 };
+                increment
+            };
+            let global_counter = make_counter();
+            make_counter()",
+        );
+
+        assert_eq!(0, evaluator.collect_garbage());
+        assert_eq!(
+            Value::Int(3),
+            eval(
+                &mut evaluator,
+                "global_counter(); global_counter(); global_counter()"
+            )
+        );
+
+        // Held only from Rust, which still keeps it alive.
+        let weak = match &counter {
+            Value::Function(function) => Rc::downgrade(function),
+            _ => unreachable!(),
+        };
+        evaluator.collect_garbage();
+        assert!(weak.upgrade().is_some());
+        drop(counter);
+        assert_ne!(0, evaluator.collect_garbage());
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_collects_automatically_and_on_drop() {
+        let mut evaluator = Evaluator::new();
+        let first = downgrade(eval(
+            &mut evaluator,
+            "let leak = fn() { let helper = fn() { helper }; helper }; leak()",
+        ));
+
+        // Enough closures to pass the collection threshold.
+        eval(&mut evaluator, "for (i in 0..5000) { leak() }");
+        assert!(first.upgrade().is_none());
+
+        let global = downgrade(eval(&mut evaluator, "leak"));
+        assert!(global.upgrade().is_some());
+        drop(evaluator);
+        assert!(global.upgrade().is_none());
+    }
+}
@@ -0,0 +1,95 @@
+//! Accounting for the memory a program uses, so the evaluator can stop one
+//! that builds something huge before it takes the host process down with
+//! it. [`CountingAllocator`] wraps the system allocator and keeps a running
+//! total of the bytes allocated on each thread, which is what
+//! [`Evaluator::set_max_memory`](crate::evaluator::Evaluator::set_max_memory)
+//! holds programs to. It only works when installed as the global
+//! allocator, as the binary does:
+//!
+//! ```
+//! #[global_allocator]
+//! static ALLOCATOR: tofu_interpreter::heap::CountingAllocator =
+//!     tofu_interpreter::heap::CountingAllocator;
+//!
+//! fn main() {
+//!     let mut evaluator = tofu_interpreter::Evaluator::new();
+//!     assert!(evaluator.set_max_memory(Some(1 << 20)).is_ok());
+//! }
+//! ```
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+thread_local! {
+    /// Bytes allocated on this thread less those freed on it. Memory freed
+    /// on a different thread than it was allocated on makes this drift, but
+    /// an evaluator and its values never leave the thread they're made on.
+    static ALLOCATED: Cell<isize> = const { Cell::new(0) };
+}
+
+/// Set by [`CountingAllocator`] the first time it counts anything, which
+/// it only does if it's the global allocator.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// The system allocator, counting what each thread has allocated.
+pub struct CountingAllocator;
+
+impl CountingAllocator {
+    fn count(bytes: isize) {
+        // Read first, so allocating doesn't keep writing to memory every
+        // thread shares.
+        if !INSTALLED.load(Ordering::Relaxed) {
+            INSTALLED.store(true, Ordering::Relaxed);
+        }
+        // Fails only while the thread is being torn down, when nothing is
+        // counting any more.
+        let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get().wrapping_add(bytes)));
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let pointer = unsafe { System.alloc(layout) };
+        if !pointer.is_null() {
+            CountingAllocator::count(layout.size() as isize);
+        }
+        pointer
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let pointer = unsafe { System.alloc_zeroed(layout) };
+        if !pointer.is_null() {
+            CountingAllocator::count(layout.size() as isize);
+        }
+        pointer
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(pointer, layout) };
+        CountingAllocator::count(-(layout.size() as isize));
+    }
+
+    unsafe fn realloc(&self, pointer: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_pointer = unsafe { System.realloc(pointer, layout, new_size) };
+        if !new_pointer.is_null() {
+            CountingAllocator::count(new_size as isize - layout.size() as isize);
+        }
+        new_pointer
+    }
+}
+
+/// How many bytes are allocated on this thread right now, as far as
+/// [`CountingAllocator`] knows, which is nothing if it isn't installed.
+pub fn allocated() -> usize {
+    ALLOCATED.with(|allocated| allocated.get().max(0) as usize)
+}
+
+/// Whether [`CountingAllocator`] is the global allocator, so memory is
+/// being counted.
+pub fn is_installed() -> bool {
+    // Anything allocated before now was counted if it's installed, but
+    // this makes sure something has been.
+    drop(std::hint::black_box(Box::new(0u8)));
+    INSTALLED.load(Ordering::Relaxed)
+}
@@ -0,0 +1,149 @@
+//! Colors Tofu source for the terminal with ANSI escapes, telling keywords,
+//! literals and comments apart by the tokens the lexer makes of it, so the
+//! colors always agree with how the source will be read.
+
+use crate::lexer::Lexer;
+use crate::token::{Span, TokenKind};
+
+const RESET: &str = "\x1b[0m";
+const KEYWORD: &str = "\x1b[35m";
+const STRING: &str = "\x1b[32m";
+const NUMBER: &str = "\x1b[33m";
+const COMMENT: &str = "\x1b[90m";
+const ILLEGAL: &str = "\x1b[31m";
+
+/// `source` with escapes around each token worth coloring. Taking the
+/// escapes out gives `source` back.
+pub fn highlight(source: &str) -> String {
+    let lines = line_starts(source);
+    let mut lexer = Lexer::new(source);
+    let mut highlighted = String::with_capacity(source.len() * 2);
+    let mut end = 0;
+
+    loop {
+        let token = lexer.next_token();
+        let start = offset(source, &lines, token.span).max(end);
+
+        // The lexer skips whitespace and comments, so anything else left
+        // between tokens is a comment.
+        let gap = &source[end..start];
+        let comment = gap.trim();
+        if comment.is_empty() {
+            highlighted.push_str(gap);
+        } else {
+            let before = gap.len() - gap.trim_start().len();
+            highlighted.push_str(&gap[..before]);
+            paint(&mut highlighted, COMMENT, comment);
+            highlighted.push_str(&gap[before + comment.len()..]);
+        }
+
+        if token.kind == TokenKind::Eof {
+            highlighted.push_str(&source[start..]);
+            return highlighted;
+        }
+        end = lexer.offset();
+        let text = &source[start..end];
+        match color(token.kind) {
+            Some(color) => paint(&mut highlighted, color, text),
+            None => highlighted.push_str(text),
+        }
+    }
+}
+
+fn color(kind: TokenKind) -> Option<&'static str> {
+    let color = match kind {
+        TokenKind::Str | TokenKind::Char => STRING,
+        TokenKind::Int
+        | TokenKind::Float
+        | TokenKind::True
+        | TokenKind::False
+        | TokenKind::Null => NUMBER,
+        TokenKind::DocComment => COMMENT,
+        TokenKind::Illegal => ILLEGAL,
+        TokenKind::If
+        | TokenKind::Else
+        | TokenKind::Return
+        | TokenKind::Match
+        | TokenKind::While
+        | TokenKind::For
+        | TokenKind::In
+        | TokenKind::Break
+        | TokenKind::Continue
+        | TokenKind::Try
+        | TokenKind::Catch
+        | TokenKind::Throw
+        | TokenKind::Fn
+        | TokenKind::Let
+        | TokenKind::Const
+        | TokenKind::Import
+        | TokenKind::Struct
+        | TokenKind::Enum => KEYWORD,
+        _ => return None,
+    };
+    Some(color)
+}
+
+fn paint(highlighted: &mut String, color: &str, text: &str) {
+    highlighted.push_str(color);
+    highlighted.push_str(text);
+    highlighted.push_str(RESET);
+}
+
+/// The byte offset each line of `source` starts at.
+pub(crate) fn line_starts(source: &str) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+        .collect()
+}
+
+/// The byte offset of `span`, whose column counts characters.
+pub(crate) fn offset(source: &str, lines: &[usize], span: Span) -> usize {
+    let Some(&start) = lines.get(span.line - 1) else {
+        return source.len();
+    };
+    source[start..]
+        .char_indices()
+        .nth(span.column - 1)
+        .map_or(source.len(), |(index, _)| start + index)
+}
+
+#[cfg(test)]
+mod test {
+    use super::highlight;
+
+    fn plain(highlighted: &str) -> String {
+        let mut plain = String::new();
+        let mut chars = highlighted.chars();
+        while let Some(ch) = chars.next() {
+            if ch == '\x1b' {
+                chars.by_ref().find(|&ch| ch == 'm');
+            } else {
+                plain.push(ch);
+            }
+        }
+        plain
+    }
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(
+            "\x1b[35mlet\x1b[0m x = \x1b[33m5\x1b[0m + y; \x1b[90m// five\x1b[0m\n",
+            highlight("let x = 5 + y; // five\n")
+        );
+        assert_eq!(
+            "\x1b[35mif\x1b[0m (\x1b[33mtrue\x1b[0m) { \x1b[32m\"a\\\"b\"\x1b[0m }",
+            highlight("if (true) { \"a\\\"b\" }")
+        );
+
+        let inputs = [
+            "#!/usr/bin/env tofu\nlet s = \"héllo\"; /* a /* nested */ comment */ s",
+            "/// Docs.\nlet f = fn(x) {\n\tx ** 2.5 // squared-ish\n};",
+            "let c = 'c'; \"unterminated",
+            "let x = 1 /* unterminated",
+            "",
+        ];
+        for input in inputs {
+            assert_eq!(input, plain(&highlight(input)), "{input}");
+        }
+    }
+}
@@ -0,0 +1,662 @@
+//! [`Interpreter`], for using Tofu as a scripting or configuration language
+//! from a Rust program, without the REPL or the command line.
+//!
+//! ```
+//! use tofu_interpreter::{Interpreter, Value};
+//!
+//! let mut interpreter = Interpreter::new();
+//! interpreter.set("width", Value::Int(3)).unwrap();
+//! interpreter.eval("let area = fn(height) { width * height }").unwrap();
+//! assert_eq!(Value::Int(12), interpreter.eval("area(4)").unwrap());
+//! ```
+//!
+//! Scripts can call functions the application provides, registered with
+//! [`Interpreter::register_fn`], and the application can hold on to
+//! functions the script defines, as [`TofuFunction`]s, to call back later:
+//!
+//! ```
+//! use tofu_interpreter::{Interpreter, Value};
+//!
+//! let mut interpreter = Interpreter::new();
+//! interpreter.eval("let on_event = fn(code) { code * 2 }").unwrap();
+//! let on_event = interpreter.get_fn("on_event").unwrap();
+//! assert_eq!(
+//!     Value::Int(84),
+//!     on_event.call(&mut interpreter, &[Value::from(42)]).unwrap()
+//! );
+//! ```
+//!
+//! Scripts the application doesn't trust can be limited through
+//! [`Interpreter::evaluator`]. A memory limit, set with
+//! [`Evaluator::set_max_memory`], needs the application to install
+//! [`heap::CountingAllocator`](crate::heap::CountingAllocator) as its
+//! global allocator; without it, setting one fails.
+
+#[cfg(feature = "async")]
+use std::cell::{Cell, RefCell};
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+#[cfg(feature = "async")]
+use std::rc::Rc;
+
+#[cfg(feature = "async")]
+use corosensei::stack::{DefaultStack, MIN_STACK_SIZE};
+#[cfg(feature = "async")]
+use corosensei::{Coroutine, CoroutineResult, Yielder};
+
+use crate::builtins::Arity;
+use crate::error::TofuError;
+#[cfg(feature = "async")]
+use crate::evaluator::STACK_PER_CALL;
+use crate::evaluator::{Evaluator, RuntimeError};
+use crate::symbol::Symbol;
+use crate::value::Value;
+
+/// What an async host function returns, for [`Interpreter::eval_async`] to
+/// wait for.
+#[cfg(feature = "async")]
+type HostFuture = Pin<Box<dyn Future<Output = Result<Value, TofuError>>>>;
+
+/// What the program run by [`Interpreter::eval_async`] suspends itself
+/// with, handing over a future and getting back what it resolved to.
+#[cfg(feature = "async")]
+type AsyncYielder = Yielder<Result<Value, TofuError>, HostFuture>;
+
+/// Runs Tofu source, keeping what each piece binds at the top level for
+/// the pieces run after it, as the REPL does with each line.
+///
+/// It stays on the thread that made it. Threads can each run their own,
+/// passing values between them as [`SharedValue`](crate::shared::SharedValue)s.
+pub struct Interpreter {
+    evaluator: Evaluator,
+    /// How the program [`Interpreter::eval_async`] is running suspends
+    /// itself, while there is one.
+    #[cfg(feature = "async")]
+    yielder: Rc<Cell<Option<*const AsyncYielder>>>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    /// An interpreter with the standard builtins and the prelude loaded.
+    /// It runs on the stack of whichever thread calls it, and finds more
+    /// when deep recursion needs it, so the recursion limit holds however
+    /// small that stack is.
+    pub fn new() -> Interpreter {
+        let mut evaluator = Evaluator::new();
+        evaluator.load_prelude();
+        Interpreter {
+            evaluator,
+            #[cfg(feature = "async")]
+            yielder: Rc::default(),
+        }
+    }
+
+    /// An interpreter like [`Interpreter::new`]'s whose programs may take
+    /// `fuel` steps between them, for running code that might never stop,
+    /// like `while (true) {}`. Running out is an
+    /// [`ErrorKind::FuelExhausted`](crate::evaluator::ErrorKind::FuelExhausted)
+    /// error. See [`Evaluator::set_fuel`] for what a step is.
+    pub fn with_fuel(fuel: u64) -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        interpreter.evaluator.set_fuel(Some(fuel));
+        interpreter
+    }
+
+    /// Runs `source` and returns the value of its last statement, or the
+    /// value passed to a top-level `return`. It's parsed as a script is,
+    /// so newlines can end statements.
+    pub fn eval(&mut self, source: &str) -> Result<Value, TofuError> {
+        let program = crate::parse(source)?;
+        Ok(self.evaluator.eval_program(&program)?)
+    }
+
+    /// Checks the types in `source` without running it, as
+    /// `tofu check --types` does, against the builtins and host functions
+    /// registered so far.
+    pub fn check_types(&self, source: &str) -> Result<(), TofuError> {
+        let program = crate::parse(source)?;
+        let errors = crate::typecheck::check(&program, self.evaluator.builtins());
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(TofuError::Type(errors))
+        }
+    }
+
+    /// The value bound to `name` at the top level, if any.
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.evaluator.global(&Symbol::intern(name))
+    }
+
+    /// The function `name` refers to at the top level, to call with
+    /// [`TofuFunction::call`]: the one bound to it there, or else the
+    /// builtin, as in a script. Fails if there's no such function.
+    pub fn get_fn(&self, name: &str) -> Result<TofuFunction, TofuError> {
+        let name = Symbol::intern(name);
+        let value = self
+            .evaluator
+            .global(&name)
+            .or_else(|| self.evaluator.builtins().lookup(&name))
+            .ok_or_else(|| TofuError::runtime(format!("identifier not found: {name}")))?;
+        Ok(TofuFunction::try_from(value)?)
+    }
+
+    /// Binds `name` to `value` at the top level, as `let` would, for the
+    /// source run afterwards to use. Fails if `name` is a constant there.
+    pub fn set(&mut self, name: &str, value: Value) -> Result<(), TofuError> {
+        Ok(self.evaluator.set_global(&Symbol::intern(name), value)?)
+    }
+
+    /// Makes `function` callable from Tofu as `name`, like a builtin, and
+    /// with the same precedence: a binding the script makes with the name
+    /// hides it. Calls with a number of arguments `arity` doesn't accept
+    /// fail before it runs, and an error it returns is raised in the
+    /// script, where `try` can catch it.
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        function: impl Fn(&[Value]) -> Result<Value, TofuError> + 'static,
+    ) {
+        self.evaluator
+            .builtins_mut()
+            .register(name, arity, move |_, arguments| {
+                function(&arguments).map_err(|error| match error {
+                    TofuError::Runtime(error) => error,
+                    error => RuntimeError::new(error.to_string()),
+                })
+            });
+    }
+
+    /// Like [`Interpreter::register_fn`], for a function that returns a
+    /// future, like one doing the host's async I/O. Scripts call it like
+    /// any other, and the script is suspended until the future resolves,
+    /// without blocking the thread. It can only be called in source run by
+    /// [`Interpreter::eval_async`].
+    #[cfg(feature = "async")]
+    pub fn register_async_fn<F, Fut>(&mut self, name: &str, arity: Arity, function: F)
+    where
+        F: Fn(Vec<Value>) -> Fut + 'static,
+        Fut: Future<Output = Result<Value, TofuError>> + 'static,
+    {
+        let yielder = Rc::clone(&self.yielder);
+        let function_name = name.to_string();
+        self.register_fn(name, arity, move |arguments| {
+            let Some(yielder) = yielder.get() else {
+                return Err(TofuError::runtime(format!(
+                    "`{function_name}` is async, so it can only be called by `eval_async`"
+                )));
+            };
+            let future: HostFuture = Box::pin(function(arguments.to_vec()));
+            // SAFETY: the yielder is only set while the coroutine it
+            // belongs to is running, and builtins run on its stack.
+            unsafe { &*yielder }.suspend(future)
+        });
+    }
+
+    /// Like [`Interpreter::eval`], but calls of functions registered with
+    /// [`Interpreter::register_async_fn`] wait for their futures by
+    /// suspending the script and awaiting them here.
+    ///
+    /// The program runs on a stack of its own, big enough for
+    /// [`Evaluator::max_depth`] calls, that it's suspended on. If the future
+    /// this returns is dropped before it's done, the program stops where it
+    /// was, and what it bound so far stays bound.
+    #[cfg(feature = "async")]
+    pub async fn eval_async(&mut self, source: &str) -> Result<Value, TofuError> {
+        let program = crate::parse(source)?;
+        let stack_size = self
+            .evaluator
+            .max_depth()
+            .saturating_mul(STACK_PER_CALL)
+            .max(MIN_STACK_SIZE);
+        let stack = DefaultStack::new(stack_size)
+            .map_err(|error| TofuError::runtime(format!("could not make a stack: {error}")))?;
+
+        // The evaluator moves onto the coroutine's stack while the program
+        // runs, and comes back through `returned` however that ends.
+        let evaluator = std::mem::take(&mut self.evaluator);
+        let returned = Rc::new(RefCell::new(None));
+        let running = Running {
+            evaluator: Some(evaluator),
+            returned: Rc::clone(&returned),
+            yielder: Rc::clone(&self.yielder),
+        };
+        let _restore = Restore {
+            interpreter: self,
+            returned,
+        };
+        let mut coroutine = Coroutine::with_stack(stack, move |yielder: &AsyncYielder, _| {
+            let mut running = running;
+            running.yielder.set(Some(yielder));
+            let evaluator = running
+                .evaluator
+                .as_mut()
+                .expect("Should have an evaluator");
+            Ok(evaluator.eval_program(&program)?)
+        });
+
+        let mut input = Ok(Value::Null);
+        loop {
+            match coroutine.resume(input) {
+                CoroutineResult::Yield(future) => input = future.await,
+                CoroutineResult::Return(result) => return result,
+            }
+        }
+    }
+
+    /// A snapshot of what's bound at the top level and the modules imported
+    /// so far, for [`Interpreter::restore`] to pick up from, even in
+    /// another process. Fails if it would have to save a function compiled
+    /// for the VM. See [`crate::snapshot`] for what's in it.
+    pub fn snapshot(&self) -> Result<Vec<u8>, TofuError> {
+        Ok(crate::snapshot::save(&self.evaluator)?)
+    }
+
+    /// Replaces what's bound at the top level and the modules imported with
+    /// those in `snapshot`, from [`Interpreter::snapshot`]. The functions
+    /// registered with [`Interpreter::register_fn`] are kept, and should be
+    /// registered before restoring a snapshot that uses them. If it fails,
+    /// nothing is replaced.
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<(), TofuError> {
+        Ok(crate::snapshot::restore(&mut self.evaluator, snapshot)?)
+    }
+
+    /// The evaluator that runs the source, for settings like
+    /// [`Evaluator::set_fuel`] and [`Evaluator::set_output`]. See
+    /// [`Evaluator::set_max_memory`] for what a memory limit needs.
+    pub fn evaluator(&mut self) -> &mut Evaluator {
+        &mut self.evaluator
+    }
+}
+
+/// A function value kept by the application, like a callback the script
+/// registered, to call whenever the application wants.
+///
+/// It keeps what the function closed over alive, and sees the bindings as
+/// they are when it's called, not when it was got. Values returned by
+/// [`Interpreter::eval`] convert to one with [`TryFrom`], if they're
+/// functions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TofuFunction {
+    value: Value,
+}
+
+impl TofuFunction {
+    /// Calls the function with `arguments` in `interpreter`, which should
+    /// be the one it came from, returning what it returns. An error raised
+    /// inside it, including a wrong number of arguments, is returned.
+    pub fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &[Value],
+    ) -> Result<Value, TofuError> {
+        Ok(interpreter
+            .evaluator
+            .call(self.value.clone(), arguments.to_vec())?)
+    }
+
+    /// How many arguments the function takes.
+    pub fn arity(&self) -> Arity {
+        self.value
+            .arity()
+            .expect("Should only hold values that can be called")
+    }
+
+    /// The function as a value, to pass back to the script.
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+}
+
+impl TryFrom<Value> for TofuFunction {
+    type Error = RuntimeError;
+
+    fn try_from(value: Value) -> Result<TofuFunction, RuntimeError> {
+        match value.arity() {
+            Some(_) => Ok(TofuFunction { value }),
+            None => Err(RuntimeError::new(format!(
+                "not a function: {}",
+                value.type_name()
+            ))),
+        }
+    }
+}
+
+impl From<TofuFunction> for Value {
+    fn from(function: TofuFunction) -> Value {
+        function.value
+    }
+}
+
+/// The evaluator of a program [`Interpreter::eval_async`] is running, which
+/// it hands back when the program's done or stopped.
+#[cfg(feature = "async")]
+struct Running {
+    evaluator: Option<Evaluator>,
+    returned: Rc<RefCell<Option<Evaluator>>>,
+    yielder: Rc<Cell<Option<*const AsyncYielder>>>,
+}
+
+#[cfg(feature = "async")]
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.yielder.set(None);
+        *self.returned.borrow_mut() = self.evaluator.take();
+    }
+}
+
+/// Puts the evaluator [`Running`] handed back into the interpreter.
+#[cfg(feature = "async")]
+struct Restore<'a> {
+    interpreter: &'a mut Interpreter,
+    returned: Rc<RefCell<Option<Evaluator>>>,
+}
+
+#[cfg(feature = "async")]
+impl Drop for Restore<'_> {
+    fn drop(&mut self) {
+        if let Some(evaluator) = self.returned.borrow_mut().take() {
+            self.interpreter.evaluator = evaluator;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "async")]
+    use std::future::Future;
+    #[cfg(feature = "async")]
+    use std::pin::{pin, Pin};
+    #[cfg(feature = "async")]
+    use std::task::{Context, Poll, Waker};
+
+    use super::{Interpreter, TofuFunction};
+    use crate::builtins::Arity;
+    use crate::error::TofuError;
+    use crate::evaluator::ErrorKind;
+    use crate::value::Value;
+
+    #[test]
+    fn test_interpreter() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(
+            Value::Null,
+            interpreter
+                .eval("let total = 0\nlet add = fn(n) { total += n }")
+                .unwrap()
+        );
+        interpreter.eval("add(2)").unwrap();
+        interpreter.eval("add(3)").unwrap();
+        assert_eq!(Some(Value::Int(5)), interpreter.get("total"));
+        assert_eq!(None, interpreter.get("missing"));
+
+        // The prelude is loaded.
+        assert_eq!(Value::Int(6), interpreter.eval("sum([1, 2, 3])").unwrap());
+
+        interpreter.set("limit", Value::Int(10)).unwrap();
+        interpreter.eval("const pi = 3").unwrap();
+        assert!(interpreter.set("pi", Value::Int(4)).is_err());
+        assert_eq!(
+            Value::Bool(true),
+            interpreter.eval("total < limit").unwrap()
+        );
+
+        match interpreter.eval("let = 1") {
+            Err(TofuError::Parse(errors)) => assert_eq!(1, errors.len(), "{errors:?}"),
+            result => panic!("expected a parse error, got {result:?}"),
+        }
+        match interpreter.eval("total + \"x\"") {
+            Err(TofuError::Runtime(error)) => {
+                assert_eq!("type mismatch: Int + Str", error.message)
+            }
+            result => panic!("expected a runtime error, got {result:?}"),
+        }
+
+        // A failed piece leaves what ran before it bound.
+        assert_eq!(Value::Int(5), interpreter.eval("total").unwrap());
+    }
+
+    #[test]
+    fn test_with_fuel() {
+        let mut interpreter = Interpreter::with_fuel(10_000);
+        // The prelude doesn't use any up.
+        assert_eq!(Value::Int(6), interpreter.eval("sum([1, 2, 3])").unwrap());
+        match interpreter.eval("while (true) {}") {
+            Err(TofuError::Runtime(error)) => assert_eq!(ErrorKind::FuelExhausted, error.kind),
+            result => panic!("expected running out of fuel, got {result:?}"),
+        }
+        // The fuel is shared by every program, so it's still gone.
+        assert!(interpreter.eval("1 + 1").is_err());
+    }
+
+    #[test]
+    fn test_deep_recursion_on_a_default_thread() {
+        // Values can't leave the thread, so compare them as text.
+        let results = std::thread::spawn(|| {
+            let mut interpreter = Interpreter::new();
+            let runaway = interpreter
+                .eval("let f = fn(n) { 1 + f(n + 1) }; f(0)")
+                .map_err(|error| error.to_string());
+            interpreter.evaluator().set_max_depth(20_000);
+            let deep = interpreter
+                .eval("let g = fn(n) { if (n == 0) { 0 } else { 1 + g(n - 1) } }; g(15000)")
+                .map(|value| value.to_string());
+            (runaway.unwrap_err(), deep.unwrap())
+        })
+        .join()
+        .unwrap();
+
+        assert!(
+            results.0.contains("maximum recursion depth 1000 exceeded"),
+            "{}",
+            results.0
+        );
+        assert_eq!("15000", results.1);
+    }
+
+    #[test]
+    fn test_register_fn() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_fn("shout", Arity::Exact(1), |arguments| match &arguments[0] {
+            Value::Str(text) => Ok(Value::Str(format!("{}!", text.to_uppercase()))),
+            other => Err(TofuError::runtime(format!(
+                "`shout` expects a Str, got {}",
+                other.type_name()
+            ))),
+        });
+
+        assert_eq!(
+            Value::Str("HI!".to_string()),
+            interpreter.eval("shout(\"hi\")").unwrap()
+        );
+        assert_eq!(
+            Value::Str("TOFU!".to_string()),
+            interpreter.eval("\"tofu\" |> shout()").unwrap()
+        );
+
+        let mut error = |source| match interpreter.eval(source) {
+            Err(TofuError::Runtime(error)) => error.to_string(),
+            result => panic!("expected a runtime error, got {result:?}"),
+        };
+        assert_eq!(
+            "1:6: wrong number of arguments to `shout`: expected 1, got 2",
+            error("shout(1, 2)")
+        );
+        assert_eq!("1:6: `shout` expects a Str, got Int", error("shout(1)"));
+
+        // Its errors can be caught.
+        assert_eq!(
+            Value::Str("`shout` expects a Str, got Bool".to_string()),
+            interpreter
+                .eval("try { shout(true) } catch (error) { error[\"message\"] }")
+                .unwrap()
+        );
+
+        // And the script can shadow it.
+        assert_eq!(
+            Value::Int(1),
+            interpreter
+                .eval("let shout = fn(x) { x }; shout(1)")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_fn() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .eval("let seen = []\nlet on_event = fn(event, extra = 1) { push(seen, event); len(seen) + extra }")
+            .unwrap();
+
+        let on_event = interpreter.get_fn("on_event").unwrap();
+        assert_eq!(Arity::Between(1, 2), on_event.arity());
+        assert_eq!(
+            Ok(Value::Int(2)),
+            on_event.call(&mut interpreter, &[Value::from("start")])
+        );
+        assert_eq!(
+            Ok(Value::Int(12)),
+            on_event.call(&mut interpreter, &[Value::from("stop"), Value::from(10)])
+        );
+        assert_eq!(
+            "[\"start\", \"stop\"]",
+            interpreter.get("seen").unwrap().to_string()
+        );
+
+        // Closures keep what they closed over, and builtins can be got too.
+        let counter = interpreter
+            .eval("let count = 0\nfn() { count += 1 }")
+            .unwrap();
+        let counter = TofuFunction::try_from(counter).unwrap();
+        counter.call(&mut interpreter, &[]).unwrap();
+        assert_eq!(Ok(Value::Int(2)), counter.call(&mut interpreter, &[]));
+        let len = interpreter.get_fn("len").unwrap();
+        assert_eq!(
+            Ok(Value::Int(3)),
+            len.call(&mut interpreter, &[Value::from("abc")])
+        );
+
+        let error = |error: Option<TofuError>| match error {
+            Some(TofuError::Runtime(error)) => error.message,
+            error => panic!("expected a runtime error, got {error:?}"),
+        };
+        assert_eq!(
+            "identifier not found: missing",
+            error(interpreter.get_fn("missing").err())
+        );
+        assert_eq!(
+            "not a function: Array",
+            error(interpreter.get_fn("seen").err())
+        );
+        assert_eq!(
+            "wrong number of arguments: expected 1 to 2, got 0",
+            error(on_event.call(&mut interpreter, &[]).err())
+        );
+    }
+
+    /// Resolves after being polled `polls` times, like I/O that isn't ready
+    /// at first.
+    #[cfg(feature = "async")]
+    struct Later {
+        polls: usize,
+        value: Value,
+    }
+
+    #[cfg(feature = "async")]
+    impl Future for Later {
+        type Output = Result<Value, TofuError>;
+
+        fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+            if self.polls == 0 {
+                return Poll::Ready(Ok(self.value.clone()));
+            }
+            self.polls -= 1;
+            context.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    /// Polls `future` until it's ready, counting the times it wasn't.
+    #[cfg(feature = "async")]
+    fn block_on<F: Future>(future: F) -> (F::Output, usize) {
+        let mut future = pin!(future);
+        let mut context = Context::from_waker(Waker::noop());
+        let mut pending = 0;
+        loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(output) => return (output, pending),
+                Poll::Pending => pending += 1,
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_eval_async() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_async_fn("fetch", Arity::Exact(1), |arguments| {
+            let key = arguments[0].clone();
+            async move {
+                match key {
+                    Value::Str(key) => {
+                        Later {
+                            polls: 2,
+                            value: Value::Str(format!("<{key}>")),
+                        }
+                        .await
+                    }
+                    key => Err(TofuError::runtime(format!(
+                        "`fetch` takes a Str, got {}",
+                        key.type_name()
+                    ))),
+                }
+            }
+        });
+
+        let source = "let pages = map([\"a\", \"b\"], fn(key) { fetch(key) })
+let failed = try { fetch(1) } catch (error) { error[\"message\"] }
+pages";
+        let (result, pending) = block_on(interpreter.eval_async(source));
+        assert_eq!("[\"<a>\", \"<b>\"]", result.unwrap().to_string());
+        assert_eq!(4, pending);
+        assert_eq!(
+            Some(Value::Str("`fetch` takes a Str, got Int".to_string())),
+            interpreter.get("failed")
+        );
+
+        // Errors come back as from `eval`, and async functions need it.
+        let error = |result: Result<Value, TofuError>| match result {
+            Err(TofuError::Runtime(error)) => error.to_string(),
+            result => panic!("expected a runtime error, got {result:?}"),
+        };
+        let (result, _) = block_on(interpreter.eval_async("fetch(\"x\") + 1"));
+        assert_eq!("1:12: type mismatch: Str + Int", error(result));
+        assert_eq!(
+            "1:6: `fetch` is async, so it can only be called by `eval_async`",
+            error(interpreter.eval("fetch(\"a\")"))
+        );
+
+        // Dropping it while the script waits stops the script but keeps
+        // what it bound.
+        {
+            let future = interpreter.eval_async("let early = 1\nlet late = fetch(\"x\")");
+            let mut future = pin!(future);
+            let mut context = Context::from_waker(Waker::noop());
+            assert!(future.as_mut().poll(&mut context).is_pending());
+        }
+        assert_eq!(Some(Value::Int(1)), interpreter.get("early"));
+        assert_eq!(None, interpreter.get("late"));
+        assert_eq!(
+            Value::Int(3),
+            interpreter.eval("sum([early, len(pages)])").unwrap()
+        );
+    }
+}
@@ -0,0 +1,42 @@
+//! Ctrl-C for the REPL. Once [`install`]ed, pressing it sets [`CTRL_C`],
+//! which an evaluator watching it with
+//! [`Evaluator::set_interrupt`](crate::evaluator::Evaluator::set_interrupt)
+//! takes as the signal to stop the program it's running, rather than the
+//! whole process ending.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set when Ctrl-C is pressed, once [`install`] has run.
+pub static CTRL_C: AtomicBool = AtomicBool::new(false);
+
+/// Makes Ctrl-C set [`CTRL_C`] instead of ending the process. Returns
+/// whether it could, which it can't on platforms without signals.
+pub fn install() -> bool {
+    #[cfg(unix)]
+    {
+        extern "C" fn on_interrupt(_signal: libc::c_int) {
+            CTRL_C.store(true, Ordering::Relaxed);
+        }
+
+        // SAFETY: the handler only stores to an atomic, which is safe to do
+        // in a signal handler.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            // So reads the signal lands in, like `input()`'s, carry on.
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut()) == 0
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+/// Whether `flag` was set, clearing it.
+pub fn take(flag: &AtomicBool) -> bool {
+    // Loading first keeps the usual case, with nothing to take, to a read.
+    flag.load(Ordering::Relaxed) && flag.swap(false, Ordering::Relaxed)
+}
@@ -0,0 +1,978 @@
+//! Compiles the functions [`crate::vm`] calls most to native code with
+//! Cranelift, when the `jit` feature is enabled. A function is compiled
+//! once it's been called [`HOT_CALLS`] times, if it only does what the
+//! native code can: arithmetic and comparisons on Ints and Bools held in
+//! locals, jumps, and calls of functions in globals. Anything else leaves
+//! it to the VM.
+//!
+//! Native code takes Ints as arguments and returns an Int or a Bool. It
+//! can't raise errors: when an operation would fail or overflow, a callee
+//! isn't native code too, or the fuel or recursion depth left runs out, it
+//! gives up, and the VM runs the call again from the start. Nothing native
+//! code does has an effect, so running it again gives the same result,
+//! error or effect the VM would have given the first time.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{
+    types, AbiParam, Block, InstBuilder, MemFlags, Signature, StackSlotData, StackSlotKind,
+};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module as _};
+
+use crate::code::{read_operands, CompiledFunction, Constant, Opcode};
+use crate::value::Value;
+use crate::vm::Module;
+
+/// How many calls make a function worth compiling.
+pub const HOT_CALLS: u32 = 100;
+
+/// How deeply calls in native code may nest. They recurse on the Rust
+/// stack, unlike the VM's, so deeper recursion gives up and leaves it to
+/// the VM, whose frames are on the heap.
+const MAX_NATIVE_DEPTH: usize = 200;
+
+/// What native code returns: the kind of value it wrote, or that it gave
+/// up.
+const RETURNED_INT: i64 = 0;
+const RETURNED_BOOL: i64 = 1;
+const GAVE_UP: i64 = 2;
+
+/// A compiled function, called with the context, a pointer to its Int
+/// arguments and where to write its result.
+type NativeFunction = unsafe extern "C" fn(*mut Context, *const i64, *mut i64) -> i64;
+
+/// What a run of native code shares, which it reads and writes through a
+/// pointer.
+#[repr(C)]
+struct Context {
+    /// The steps left before the program runs out of fuel, or
+    /// `u64::MAX` when fuel isn't limited.
+    fuel: u64,
+    /// How many more calls can be in progress at once.
+    calls_left: u64,
+    /// The program whose globals calls look functions up in.
+    module: *const Module,
+}
+
+/// Where each function the VM calls is on its way to native code.
+enum Entry {
+    /// Called this many times so far.
+    Counting(u32),
+    Native(NativeFunction),
+    /// It does something native code can't, or its native code gave up.
+    Interpreted,
+}
+
+/// The native code of a program's functions.
+pub(crate) struct Jit {
+    /// `None` if Cranelift doesn't support the machine it's running on.
+    module: Option<JITModule>,
+    /// The function native code calls to call a global.
+    call_global: Option<FuncId>,
+    /// By the address of each function's [`CompiledFunction`].
+    functions: HashMap<*const CompiledFunction, Entry>,
+}
+
+impl Jit {
+    pub(crate) fn new() -> Jit {
+        let mut module = new_module();
+        let call_global = module.as_mut().and_then(|module| {
+            // The context, the global's index, the arguments, how many
+            // there are and where the result goes.
+            let signature = native_signature(module, 5);
+            module
+                .declare_function("tofu_call_global", Linkage::Import, &signature)
+                .ok()
+        });
+
+        Jit {
+            module,
+            call_global,
+            functions: HashMap::new(),
+        }
+    }
+
+    /// Counts a call of `function`, compiling it if that makes it hot, or
+    /// straight away if `eager` is set, and returns its native code if it
+    /// has some.
+    fn native(
+        &mut self,
+        function: &Rc<CompiledFunction>,
+        constants: &[Constant],
+        eager: bool,
+    ) -> Option<NativeFunction> {
+        let entry = self
+            .functions
+            .entry(Rc::as_ptr(function))
+            .or_insert(Entry::Counting(0));
+        match entry {
+            Entry::Native(native) => return Some(*native),
+            Entry::Interpreted => return None,
+            Entry::Counting(calls) if !eager && *calls + 1 < HOT_CALLS => {
+                *calls += 1;
+                return None;
+            }
+            Entry::Counting(_) => {}
+        }
+
+        let native = self.compile(function, constants);
+        self.functions.insert(
+            Rc::as_ptr(function),
+            match native {
+                Some(native) => Entry::Native(native),
+                None => Entry::Interpreted,
+            },
+        );
+        native
+    }
+
+    /// Stops using the native code of `function`, since it gave up.
+    fn give_up(&mut self, function: &Rc<CompiledFunction>) {
+        self.functions
+            .insert(Rc::as_ptr(function), Entry::Interpreted);
+    }
+
+    fn compile(
+        &mut self,
+        function: &CompiledFunction,
+        constants: &[Constant],
+    ) -> Option<NativeFunction> {
+        let module = self.module.as_mut()?;
+        let call_global = self.call_global?;
+        let analysis = analyze(function, constants)?;
+
+        let pointer = module.target_config().pointer_type();
+        let signature = native_signature(module, 3);
+        let id = module.declare_anonymous_function(&signature).ok()?;
+        let mut context = module.make_context();
+        context.func.signature = signature;
+        let mut builder_context = FunctionBuilderContext::new();
+        let builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+        let call_global = module.declare_func_in_func(call_global, builder.func);
+        Translator {
+            builder,
+            function,
+            constants,
+            analysis: &analysis,
+            pointer,
+            call_global,
+        }
+        .translate();
+
+        module.define_function(id, &mut context).ok()?;
+        module.clear_context(&mut context);
+        module.finalize_definitions().ok()?;
+        let code = module.get_finalized_function(id);
+        // SAFETY: the function was defined with `NativeFunction`'s
+        // signature.
+        Some(unsafe { std::mem::transmute::<*const u8, NativeFunction>(code) })
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // SAFETY: the functions of a program's module only run while
+            // it's alive, and this is dropped with it.
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+/// A signature that takes `pointers` pointers and returns an `i64`.
+fn native_signature(module: &JITModule, pointers: usize) -> Signature {
+    let mut signature = module.make_signature();
+    let pointer = module.target_config().pointer_type();
+    for _ in 0..pointers {
+        signature.params.push(AbiParam::new(pointer));
+    }
+    signature.returns.push(AbiParam::new(types::I64));
+    signature
+}
+
+fn new_module() -> Option<JITModule> {
+    let mut flags = settings::builder();
+    flags.set("use_colocated_libcalls", "false").ok()?;
+    flags.set("is_pic", "false").ok()?;
+    let isa = cranelift_native::builder()
+        .ok()?
+        .finish(settings::Flags::new(flags))
+        .ok()?;
+
+    let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+    builder.symbol("tofu_call_global", call_global as *const u8);
+    Some(JITModule::new(builder))
+}
+
+/// Runs `function` of `module` as native code, if it has been compiled to
+/// some, with `fuel` and `calls_left`, or [`MAX_NATIVE_DEPTH`] if that's
+/// lower, as the limits. Returns the result
+/// and the fuel left, or `None` if it has to run in the VM instead.
+pub(crate) fn call(
+    module: &Module,
+    function: &Rc<CompiledFunction>,
+    arguments: &[Value],
+    fuel: Option<u64>,
+    calls_left: usize,
+) -> Option<(Value, Option<u64>)> {
+    if arguments.len() != function.parameters.len() {
+        return None;
+    }
+    let arguments = arguments
+        .iter()
+        .map(|argument| match argument {
+            Value::Int(value) => Some(*value),
+            _ => None,
+        })
+        .collect::<Option<Vec<i64>>>()?;
+    let native = module
+        .jit
+        .borrow_mut()
+        .native(function, &module.constants, false)?;
+
+    let mut context = Context {
+        fuel: fuel.unwrap_or(u64::MAX),
+        calls_left: calls_left.min(MAX_NATIVE_DEPTH) as u64,
+        module,
+    };
+    let mut result = 0;
+    // SAFETY: the arguments are the Ints it expects, and the context and
+    // the result outlive the call.
+    let returned = unsafe { native(&mut context, arguments.as_ptr(), &mut result) };
+
+    let value = match returned {
+        RETURNED_INT => Value::Int(result),
+        RETURNED_BOOL => Value::Bool(result != 0),
+        _ => {
+            module.jit.borrow_mut().give_up(function);
+            return None;
+        }
+    };
+    Some((value, fuel.map(|_| context.fuel)))
+}
+
+/// Calls the function in a global for native code, through its own native
+/// code, giving up unless it has some.
+extern "C" fn call_global(
+    context: *mut Context,
+    global: usize,
+    arguments: *const i64,
+    count: usize,
+    result: *mut i64,
+) -> i64 {
+    // SAFETY: native code only runs while the module it was compiled for
+    // is alive, from `call`.
+    let module = unsafe { &*(*context).module };
+    let callee = module.globals.borrow().get(global).cloned().flatten();
+    let Some(Value::Closure(closure)) = callee else {
+        return GAVE_UP;
+    };
+    // A function from another program has its own constants.
+    if !std::ptr::eq(Rc::as_ptr(&closure.module), module)
+        || closure.function.parameters.len() != count
+    {
+        return GAVE_UP;
+    }
+
+    // The caller is hot, so the callee is too.
+    let native = module
+        .jit
+        .borrow_mut()
+        .native(&closure.function, &module.constants, true);
+    match native {
+        // SAFETY: the arguments are as many Ints as it takes.
+        Some(native) => unsafe { native(context, arguments, result) },
+        None => GAVE_UP,
+    }
+}
+
+/// What a value on the stack or in a local is while native code runs.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Type {
+    Int,
+    Bool,
+    /// Only ever popped.
+    Null,
+    /// The function in this global, about to be called.
+    Global(usize),
+}
+
+/// The types of the values on the stack and in the locals before an
+/// instruction runs. A local is `None` until it's declared, or when paths
+/// that give it different types meet.
+#[derive(PartialEq, Debug, Clone)]
+struct State {
+    stack: Vec<Type>,
+    locals: Vec<Option<Type>>,
+}
+
+struct Instruction {
+    opcode: Opcode,
+    operands: Vec<usize>,
+    offset: usize,
+}
+
+/// What [`analyze`] finds out about a function native code can run.
+struct Analysis {
+    instructions: Vec<Instruction>,
+    /// The state before each instruction, or `None` if nothing reaches it.
+    states: Vec<Option<State>>,
+    /// The index of the instruction each jump goes to, by the jump's index.
+    targets: HashMap<usize, usize>,
+    returns: Type,
+    max_stack: usize,
+}
+
+/// Works out the types everything has everywhere in `function`, or
+/// `None` if it does something native code can't.
+fn analyze(function: &CompiledFunction, constants: &[Constant]) -> Option<Analysis> {
+    let code = &function.code;
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        let opcode = Opcode::from_byte(code[offset])?;
+        let (operands, next) = read_operands(opcode, code, offset);
+        instructions.push(Instruction {
+            opcode,
+            operands,
+            offset,
+        });
+        offset = next;
+    }
+    let index_of = |offset: usize| {
+        instructions
+            .binary_search_by_key(&offset, |instruction| instruction.offset)
+            .ok()
+    };
+    let mut targets = HashMap::new();
+    for (index, instruction) in instructions.iter().enumerate() {
+        if matches!(instruction.opcode, Opcode::Jump | Opcode::JumpIfFalse) {
+            targets.insert(index, index_of(instruction.operands[0])?);
+        }
+    }
+
+    let mut locals = vec![None; function.locals];
+    for local in &mut locals[..function.parameters.len()] {
+        *local = Some(Type::Int);
+    }
+    let mut states: Vec<Option<State>> = vec![None; instructions.len()];
+    states[0] = Some(State {
+        stack: Vec::new(),
+        locals,
+    });
+    let mut returns = None;
+    let mut max_stack = 0;
+    let mut pending = vec![0];
+
+    while let Some(index) = pending.pop() {
+        let mut state = states[index].clone()?;
+        let instruction = &instructions[index];
+        let operand = instruction.operands.first().copied().unwrap_or(0);
+        let pop = |state: &mut State| state.stack.pop();
+        let mut next = vec![index + 1];
+
+        match instruction.opcode {
+            Opcode::Constant => match constants.get(operand)? {
+                Constant::Int(_) => state.stack.push(Type::Int),
+                _ => return None,
+            },
+            Opcode::True | Opcode::False => state.stack.push(Type::Bool),
+            Opcode::Null => state.stack.push(Type::Null),
+            Opcode::Pop => {
+                pop(&mut state)?;
+            }
+            Opcode::GetLocal => match state.locals[operand] {
+                Some(Type::Int) => state.stack.push(Type::Int),
+                Some(Type::Bool) => state.stack.push(Type::Bool),
+                _ => return None,
+            },
+            Opcode::SetLocal => state.locals[operand] = Some(*state.stack.last()?),
+            Opcode::DefineLocal => state.locals[operand] = Some(pop(&mut state)?),
+            Opcode::GetGlobal => state.stack.push(Type::Global(operand)),
+            Opcode::Add | Opcode::Subtract | Opcode::Multiply | Opcode::Divide => {
+                if (pop(&mut state)?, pop(&mut state)?) != (Type::Int, Type::Int) {
+                    return None;
+                }
+                state.stack.push(Type::Int);
+            }
+            Opcode::LessThan
+            | Opcode::GreaterThan
+            | Opcode::LessThanEqual
+            | Opcode::GreaterThanEqual => {
+                if (pop(&mut state)?, pop(&mut state)?) != (Type::Int, Type::Int) {
+                    return None;
+                }
+                state.stack.push(Type::Bool);
+            }
+            Opcode::Equal | Opcode::NotEqual => {
+                let (right, left) = (pop(&mut state)?, pop(&mut state)?);
+                if left != right || !matches!(left, Type::Int | Type::Bool) {
+                    return None;
+                }
+                state.stack.push(Type::Bool);
+            }
+            Opcode::Minus => {
+                if pop(&mut state)? != Type::Int {
+                    return None;
+                }
+                state.stack.push(Type::Int);
+            }
+            Opcode::Not | Opcode::Truthy => {
+                if !matches!(pop(&mut state)?, Type::Int | Type::Bool) {
+                    return None;
+                }
+                state.stack.push(Type::Bool);
+            }
+            Opcode::Jump => next = vec![targets[&index]],
+            Opcode::JumpIfFalse => {
+                if !matches!(pop(&mut state)?, Type::Int | Type::Bool) {
+                    return None;
+                }
+                next.push(targets[&index]);
+            }
+            // Native code makes tail calls like any other.
+            Opcode::Call | Opcode::TailCall => {
+                for _ in 0..operand {
+                    if pop(&mut state)? != Type::Int {
+                        return None;
+                    }
+                }
+                let Type::Global(_) = pop(&mut state)? else {
+                    return None;
+                };
+                // Only calls that return an Int carry on.
+                state.stack.push(Type::Int);
+            }
+            Opcode::Return => {
+                let value = pop(&mut state)?;
+                if !matches!(value, Type::Int | Type::Bool) || returns.is_some_and(|t| t != value) {
+                    return None;
+                }
+                returns = Some(value);
+                next.clear();
+            }
+            _ => return None,
+        }
+        max_stack = max_stack.max(state.stack.len());
+
+        for next in next {
+            let merged = match &states.get(next)? {
+                None => state.clone(),
+                Some(existing) => {
+                    if existing.stack != state.stack {
+                        return None;
+                    }
+                    let locals = existing
+                        .locals
+                        .iter()
+                        .zip(&state.locals)
+                        .map(|(a, b)| if a == b { *a } else { None })
+                        .collect();
+                    State {
+                        stack: state.stack.clone(),
+                        locals,
+                    }
+                }
+            };
+            if states[next].as_ref() != Some(&merged) {
+                states[next] = Some(merged);
+                pending.push(next);
+            }
+        }
+    }
+
+    Some(Analysis {
+        instructions,
+        states,
+        targets,
+        returns: returns?,
+        max_stack,
+    })
+}
+
+/// Translates a function [`analyze`] approved to Cranelift's IR. Values
+/// live in variables, one for each local and one for each place on the
+/// stack, with Bools as 0 or 1.
+struct Translator<'a> {
+    builder: FunctionBuilder<'a>,
+    function: &'a CompiledFunction,
+    constants: &'a [Constant],
+    analysis: &'a Analysis,
+    pointer: types::Type,
+    call_global: cranelift_codegen::ir::FuncRef,
+}
+
+impl Translator<'_> {
+    fn local(&self, slot: usize) -> Variable {
+        Variable::from_u32(slot as u32)
+    }
+
+    fn stack(&self, depth: usize) -> Variable {
+        Variable::from_u32((self.function.locals + depth) as u32)
+    }
+
+    fn translate(mut self) {
+        let analysis = self.analysis;
+        let instructions = &analysis.instructions;
+        let variables = self.function.locals + analysis.max_stack;
+        for variable in 0..variables {
+            self.builder
+                .declare_var(Variable::from_u32(variable as u32), types::I64);
+        }
+
+        // A block for each instruction a jump goes to or that follows one.
+        let mut blocks: Vec<Option<Block>> = vec![None; instructions.len()];
+        blocks[0] = Some(self.builder.create_block());
+        for (index, instruction) in instructions.iter().enumerate() {
+            if let Some(target) = analysis.targets.get(&index) {
+                blocks[*target].get_or_insert_with(|| self.builder.create_block());
+            }
+            let ends_block = matches!(
+                instruction.opcode,
+                Opcode::Jump | Opcode::JumpIfFalse | Opcode::Return
+            );
+            if ends_block && index + 1 < instructions.len() && analysis.states[index + 1].is_some()
+            {
+                blocks[index + 1].get_or_insert_with(|| self.builder.create_block());
+            }
+        }
+
+        let entry = self.builder.create_block();
+        let gave_up = self.builder.create_block();
+        self.builder.append_block_params_for_function_params(entry);
+        self.builder.switch_to_block(entry);
+        let params = self.builder.block_params(entry).to_vec();
+        let (context, arguments, result) = (params[0], params[1], params[2]);
+
+        self.use_fuel(context, gave_up);
+        let calls_left = self.load(context, 8);
+        let none_left = self.builder.ins().icmp_imm(IntCC::Equal, calls_left, 0);
+        let carry_on = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(none_left, gave_up, &[], carry_on, &[]);
+        self.builder.switch_to_block(carry_on);
+        let calls_left = self.builder.ins().iadd_imm(calls_left, -1);
+        self.store(calls_left, context, 8);
+
+        for slot in 0..self.function.locals {
+            let value = if slot < self.function.parameters.len() {
+                self.load(arguments, 8 * slot as i32)
+            } else {
+                self.builder.ins().iconst(types::I64, 0)
+            };
+            let local = self.local(slot);
+            self.builder.def_var(local, value);
+        }
+        self.builder.ins().jump(blocks[0].expect("made above"), &[]);
+
+        let mut ended = true;
+        for (index, instruction) in instructions.iter().enumerate() {
+            if let Some(block) = blocks[index] {
+                if !ended {
+                    self.builder.ins().jump(block, &[]);
+                }
+                self.builder.switch_to_block(block);
+                ended = false;
+            }
+            let Some(state) = &analysis.states[index] else {
+                continue;
+            };
+            ended = self.instruction(index, instruction, state, &blocks, context, result, gave_up);
+        }
+
+        self.builder.switch_to_block(gave_up);
+        let status = self.builder.ins().iconst(types::I64, GAVE_UP);
+        self.builder.ins().return_(&[status]);
+
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+    }
+
+    fn load(
+        &mut self,
+        pointer: cranelift_codegen::ir::Value,
+        offset: i32,
+    ) -> cranelift_codegen::ir::Value {
+        self.builder
+            .ins()
+            .load(types::I64, MemFlags::trusted(), pointer, offset)
+    }
+
+    fn store(
+        &mut self,
+        value: cranelift_codegen::ir::Value,
+        pointer: cranelift_codegen::ir::Value,
+        offset: i32,
+    ) {
+        self.builder
+            .ins()
+            .store(MemFlags::trusted(), value, pointer, offset);
+    }
+
+    /// Uses up a step of fuel, giving up if there's none left, as the VM
+    /// does on a call or a jump back.
+    fn use_fuel(&mut self, context: cranelift_codegen::ir::Value, gave_up: Block) {
+        let fuel = self.load(context, 0);
+        let empty = self.builder.ins().icmp_imm(IntCC::Equal, fuel, 0);
+        let carry_on = self.builder.create_block();
+        self.builder.ins().brif(empty, gave_up, &[], carry_on, &[]);
+        self.builder.switch_to_block(carry_on);
+        // Unlimited fuel never gets anywhere near running out.
+        let fuel = self.builder.ins().iadd_imm(fuel, -1);
+        self.store(fuel, context, 0);
+    }
+
+    /// Gives up if `condition` is true.
+    fn give_up_if(&mut self, condition: cranelift_codegen::ir::Value, gave_up: Block) {
+        let carry_on = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(condition, gave_up, &[], carry_on, &[]);
+        self.builder.switch_to_block(carry_on);
+    }
+
+    /// Translates an instruction, returning whether it ends its block.
+    #[allow(clippy::too_many_arguments)]
+    fn instruction(
+        &mut self,
+        index: usize,
+        instruction: &Instruction,
+        state: &State,
+        blocks: &[Option<Block>],
+        context: cranelift_codegen::ir::Value,
+        result: cranelift_codegen::ir::Value,
+        gave_up: Block,
+    ) -> bool {
+        let depth = state.stack.len();
+        let operand = instruction.operands.first().copied().unwrap_or(0);
+        let top = |translator: &mut Self, from_top: usize| {
+            let variable = translator.stack(depth - from_top);
+            translator.builder.use_var(variable)
+        };
+        let push = |translator: &mut Self, at: usize, value| {
+            let variable = translator.stack(at);
+            translator.builder.def_var(variable, value);
+        };
+
+        match instruction.opcode {
+            Opcode::Constant => {
+                let Constant::Int(value) = self.constants[operand] else {
+                    unreachable!("only Int constants are approved")
+                };
+                let value = self.builder.ins().iconst(types::I64, value);
+                push(self, depth, value);
+            }
+            Opcode::True | Opcode::False | Opcode::Null | Opcode::GetGlobal => {
+                let value = i64::from(instruction.opcode == Opcode::True);
+                let value = self.builder.ins().iconst(types::I64, value);
+                push(self, depth, value);
+            }
+            Opcode::Pop => {}
+            Opcode::GetLocal => {
+                let local = self.local(operand);
+                let value = self.builder.use_var(local);
+                push(self, depth, value);
+            }
+            Opcode::SetLocal | Opcode::DefineLocal => {
+                let value = top(self, 1);
+                let local = self.local(operand);
+                self.builder.def_var(local, value);
+            }
+            Opcode::Add | Opcode::Subtract | Opcode::Multiply => {
+                let (left, right) = (top(self, 2), top(self, 1));
+                let (value, overflowed) = match instruction.opcode {
+                    Opcode::Add => self.builder.ins().sadd_overflow(left, right),
+                    Opcode::Subtract => self.builder.ins().ssub_overflow(left, right),
+                    _ => self.builder.ins().smul_overflow(left, right),
+                };
+                self.give_up_if(overflowed, gave_up);
+                push(self, depth - 2, value);
+            }
+            Opcode::Divide => {
+                let (left, right) = (top(self, 2), top(self, 1));
+                let by_zero = self.builder.ins().icmp_imm(IntCC::Equal, right, 0);
+                self.give_up_if(by_zero, gave_up);
+                // `i64::MIN / -1` overflows.
+                let minimum = self.builder.ins().icmp_imm(IntCC::Equal, left, i64::MIN);
+                let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, right, -1);
+                let overflows = self.builder.ins().band(minimum, minus_one);
+                self.give_up_if(overflows, gave_up);
+                let value = self.builder.ins().sdiv(left, right);
+                push(self, depth - 2, value);
+            }
+            Opcode::Equal
+            | Opcode::NotEqual
+            | Opcode::LessThan
+            | Opcode::GreaterThan
+            | Opcode::LessThanEqual
+            | Opcode::GreaterThanEqual => {
+                let condition = match instruction.opcode {
+                    Opcode::Equal => IntCC::Equal,
+                    Opcode::NotEqual => IntCC::NotEqual,
+                    Opcode::LessThan => IntCC::SignedLessThan,
+                    Opcode::GreaterThan => IntCC::SignedGreaterThan,
+                    Opcode::LessThanEqual => IntCC::SignedLessThanOrEqual,
+                    _ => IntCC::SignedGreaterThanOrEqual,
+                };
+                let (left, right) = (top(self, 2), top(self, 1));
+                let compared = self.builder.ins().icmp(condition, left, right);
+                let value = self.builder.ins().uextend(types::I64, compared);
+                push(self, depth - 2, value);
+            }
+            Opcode::Minus => {
+                let value = top(self, 1);
+                let minimum = self.builder.ins().icmp_imm(IntCC::Equal, value, i64::MIN);
+                self.give_up_if(minimum, gave_up);
+                let value = self.builder.ins().ineg(value);
+                push(self, depth - 1, value);
+            }
+            Opcode::Not | Opcode::Truthy => {
+                let value = match state.stack[depth - 1] {
+                    // Every Int is truthy.
+                    Type::Int => self.builder.ins().iconst(types::I64, 1),
+                    _ => top(self, 1),
+                };
+                let value = match instruction.opcode {
+                    Opcode::Not => self.builder.ins().bxor_imm(value, 1),
+                    _ => value,
+                };
+                push(self, depth - 1, value);
+            }
+            Opcode::Jump => {
+                let target = self.analysis.targets[&index];
+                if self.analysis.instructions[target].offset <= instruction.offset {
+                    self.use_fuel(context, gave_up);
+                }
+                let block = blocks[target].expect("jump targets have blocks");
+                self.builder.ins().jump(block, &[]);
+                return true;
+            }
+            Opcode::JumpIfFalse => {
+                let target =
+                    blocks[self.analysis.targets[&index]].expect("jump targets have blocks");
+                let next = blocks[index + 1].expect("what follows a jump has a block");
+                match state.stack[depth - 1] {
+                    Type::Int => {
+                        self.builder.ins().jump(next, &[]);
+                    }
+                    _ => {
+                        let condition = top(self, 1);
+                        self.builder.ins().brif(condition, next, &[], target, &[]);
+                    }
+                }
+                return true;
+            }
+            Opcode::Call | Opcode::TailCall => {
+                let count = operand;
+                let Type::Global(global) = state.stack[depth - count - 1] else {
+                    unreachable!("only globals are called")
+                };
+                let slot = self.builder.create_sized_stack_slot(StackSlotData::new(
+                    StackSlotKind::ExplicitSlot,
+                    8 * count.max(1) as u32,
+                    3,
+                ));
+                for argument in 0..count {
+                    let value = top(self, count - argument);
+                    self.builder
+                        .ins()
+                        .stack_store(value, slot, 8 * argument as i32);
+                }
+                let returned_slot = self.builder.create_sized_stack_slot(StackSlotData::new(
+                    StackSlotKind::ExplicitSlot,
+                    8,
+                    3,
+                ));
+
+                let arguments = self.builder.ins().stack_addr(self.pointer, slot, 0);
+                let returned = self
+                    .builder
+                    .ins()
+                    .stack_addr(self.pointer, returned_slot, 0);
+                let global = self.builder.ins().iconst(self.pointer, global as i64);
+                let count = self.builder.ins().iconst(self.pointer, count as i64);
+                let call = self.builder.ins().call(
+                    self.call_global,
+                    &[context, global, arguments, count, returned],
+                );
+                let status = self.builder.inst_results(call)[0];
+                let not_int = self
+                    .builder
+                    .ins()
+                    .icmp_imm(IntCC::NotEqual, status, RETURNED_INT);
+                self.give_up_if(not_int, gave_up);
+                let value = self.builder.ins().stack_load(types::I64, returned_slot, 0);
+                push(self, depth - operand - 1, value);
+            }
+            Opcode::Return => {
+                let value = top(self, 1);
+                self.store(value, result, 0);
+                let calls_left = self.load(context, 8);
+                let calls_left = self.builder.ins().iadd_imm(calls_left, 1);
+                self.store(calls_left, context, 8);
+                let status = match self.analysis.returns {
+                    Type::Bool => RETURNED_BOOL,
+                    _ => RETURNED_INT,
+                };
+                let status = self.builder.ins().iconst(types::I64, status);
+                self.builder.ins().return_(&[status]);
+                return true;
+            }
+            opcode => unreachable!("{opcode} isn't approved"),
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use super::{Jit, HOT_CALLS};
+    use crate::code::{Bytecode, CompiledFunction, Constant};
+    use crate::compiler::compile;
+    use crate::evaluator::Evaluator;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::value::Value;
+    use crate::vm;
+
+    fn compile_input(input: &str) -> Bytecode {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+
+        assert!(
+            parser.errors().is_empty(),
+            "parser had errors: {:?}",
+            parser.errors()
+        );
+
+        compile(&program, Evaluator::new().builtins()).expect("the program compiles")
+    }
+
+    fn functions(bytecode: &Bytecode) -> Vec<Rc<CompiledFunction>> {
+        bytecode
+            .constants
+            .iter()
+            .filter_map(|constant| match constant {
+                Constant::Function(function) => Some(Rc::clone(function)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_compiles_what_it_supports() {
+        let tests = [
+            ("fn(n) { if (n < 2) { n } else { n * 2 - 1 } }", true),
+            (
+                "fn(a, b) { let s = 0; while (a < b) { s += a / 2; a += 1; } s }",
+                true,
+            ),
+            ("fn(n) { !(n == 0) && n > -5 }", true),
+            ("fn(n) { f(n - 1) }", true),
+            ("fn(n) { \"tofu\" }", false),
+            ("fn(n) { [n] }", false),
+            ("fn(n) { n.abs() }", false),
+            ("fn(n) { print(n) }", false),
+            ("fn(n) { fn() { n } }", false),
+            // An Int on one path and a Bool on another.
+            ("fn(n) { if (n > 0) { return 1; } true }", false),
+        ];
+
+        for (input, supported) in tests {
+            let bytecode = compile_input(&format!("let f = {input}"));
+            let function = functions(&bytecode).pop().expect("a function");
+            let native = Jit::new().native(&function, &bytecode.constants, true);
+            assert_eq!(supported, native.is_some(), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_only_hot_functions_are_compiled() {
+        let bytecode = compile_input("let f = fn(n) { n + 1 }");
+        let function = functions(&bytecode).pop().expect("a function");
+
+        let mut jit = Jit::new();
+        for _ in 1..HOT_CALLS {
+            assert!(jit.native(&function, &bytecode.constants, false).is_none());
+        }
+        assert!(jit.native(&function, &bytecode.constants, false).is_some());
+    }
+
+    /// Checks each program gives the same result with native code as it
+    /// does evaluated, once it calls its functions far more than
+    /// [`HOT_CALLS`] times.
+    #[test]
+    fn test_same_results_as_the_vm() {
+        let fib = "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };";
+        let tests = [
+            format!("{fib} fib(20)"),
+            "let even = fn(n) { n == 0 ? true : odd(n - 1) }; \
+             let odd = fn(n) { n == 0 ? false : even(n - 1) }; \
+             let c = 0; for (i in 0..300) { if (even(i)) { c += 1; } } c"
+                .to_string(),
+            "let f = fn(a, b) { let s = 0; while (a < b) { s += a / 3 - -a; a += 1; } s }; \
+             let t = 0; for (i in 0..300) { t += f(i, 2 * i); } t"
+                .to_string(),
+            // Overflowing and dividing by zero are left to the VM.
+            "let big = fn(n) { if (n == 0) { 1 } else { 1000000 * big(n - 1) } }; \
+             for (i in 0..200) { big(3); } big(4)"
+                .to_string(),
+            "let f = fn(n) { 10 / n }; for (i in 0..200) { f(i + 1); } f(0)".to_string(),
+            // A callee that returns something other than an Int.
+            "let s = fn(n) { \"x\" }; let f = fn(n) { s(n) + 1 }; \
+             for (i in 0..200) { f(i); }"
+                .to_string(),
+        ];
+
+        for input in &tests {
+            let mut parser = Parser::new(Lexer::new(input));
+            let evaluated = Evaluator::new().eval_program(&parser.parse_program());
+            let compiled = vm::run(&mut Evaluator::new(), &compile_input(input));
+            assert_eq!(
+                evaluated.map_err(|error| error.to_string()),
+                compiled.map_err(|error| error.to_string()),
+                "{input}"
+            );
+        }
+
+        // The evaluator needs a bigger stack than a test thread has to
+        // recurse this deep, so check against what the VM reports alone.
+        let input = "let f = fn(n) { 1 + f(n + 1) }; f(0)";
+        let message = vm::run(&mut Evaluator::new(), &compile_input(input))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            message.starts_with("1:22: maximum recursion depth 1000 exceeded\n"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn test_fuel() {
+        let input =
+            "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15)";
+        let bytecode = compile_input(input);
+
+        // Native code uses a step of fuel a call, as the VM does.
+        let mut evaluator = Evaluator::with_fuel(100_000);
+        assert_eq!(Ok(Value::Int(610)), vm::run(&mut evaluator, &bytecode));
+        assert_eq!(Some(100_000 - 1973), evaluator.fuel());
+
+        let error = vm::run(&mut Evaluator::with_fuel(1000), &bytecode).unwrap_err();
+        assert_eq!("out of fuel", error.message);
+
+        let looping =
+            compile_input("let f = fn(n) { while (true) {} }; for (i in 0..200) { f(1); }");
+        let error = vm::run(&mut Evaluator::with_fuel(10_000), &looping).unwrap_err();
+        assert_eq!("out of fuel", error.message);
+    }
+}
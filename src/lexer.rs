@@ -1,19 +1,100 @@
-use crate::token::{lookup_identifier, Token, TokenKind};
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::io::{self, BufRead};
+use std::str::CharIndices;
 
-pub struct Lexer {
-    input: Vec<char>,
+use crate::token::{lookup_identifier, Span, Token, TokenKind};
+
+/// Turns source text into tokens. Literals borrow from the input wherever
+/// the token is spelled exactly as written, so lexing an identifier or a
+/// plain number doesn't allocate.
+pub struct Lexer<'a> {
+    input: &'a str,
+    chars: CharIndices<'a>,
     pos: usize,
     read_pos: usize,
     ch: char,
+    line: usize,
+    column: usize,
+    newline_terminators: bool,
+    last_kind: Option<TokenKind>,
+    group_depth: usize,
+    errors: Vec<LexError>,
+}
+
+/// A problem found while lexing. The lexer still produces an `Illegal`
+/// token at the same spot and carries on, so every error in a source can be
+/// collected in one pass.
+#[derive(PartialEq, Debug, Clone)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum LexErrorKind {
+    UnexpectedChar(char),
+    UnterminatedString,
+    UnterminatedBlockComment,
+    InvalidEscape,
+    InvalidChar,
+    InvalidNumber,
+}
+
+impl Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.span, self.kind)
+    }
 }
 
-impl Lexer {
-    pub fn new(input: &str) -> Lexer {
+impl Display for LexErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LexErrorKind::UnexpectedChar(ch) => {
+                write!(f, "unexpected character {:?} (U+{:04X})", ch, *ch as u32)
+            }
+            LexErrorKind::UnterminatedString => write!(f, "unterminated string literal"),
+            LexErrorKind::UnterminatedBlockComment => write!(f, "unterminated block comment"),
+            LexErrorKind::InvalidEscape => write!(f, "invalid escape sequence"),
+            LexErrorKind::InvalidChar => {
+                write!(f, "character literals must hold exactly one character")
+            }
+            LexErrorKind::InvalidNumber => write!(f, "invalid number literal"),
+        }
+    }
+}
+
+impl std::error::Error for LexError {}
+
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Lexer<'a> {
+        Lexer::starting_at_line(input, 1)
+    }
+
+    /// Lexes `input` with its lines numbered from `line`, so spans in it
+    /// can be told apart from spans in other sources.
+    pub fn starting_at_line(input: &'a str, line: usize) -> Lexer<'a> {
+        let mut lexer = Lexer::starting_at(input, Span { line, column: 1 });
+        lexer.skip_shebang();
+
+        lexer
+    }
+
+    /// Lexes `input` as though its first character sits at `span` in some
+    /// larger source.
+    fn starting_at(input: &'a str, span: Span) -> Lexer<'a> {
         let mut lexer = Lexer {
-            input: input.chars().collect(),
+            input,
+            chars: input.char_indices(),
             pos: 0,
             read_pos: 0,
             ch: Default::default(),
+            line: span.line,
+            column: span.column - 1,
+            newline_terminators: false,
+            last_kind: None,
+            group_depth: 0,
+            errors: Vec::new(),
         };
 
         lexer.read_char();
@@ -21,65 +102,210 @@ impl Lexer {
         lexer
     }
 
-    pub fn next(&mut self) -> Token {
+    /// Makes a newline end the statement before it, as if a `;` had been
+    /// written, whenever the line finishes on something that can close an
+    /// expression. Newlines inside parentheses, or before an `else`, never
+    /// terminate. Explicit semicolons still work everywhere.
+    pub fn set_newline_terminators(&mut self, enabled: bool) {
+        self.newline_terminators = enabled;
+    }
+
+    /// Every error found so far, in source order.
+    pub fn errors(&self) -> &[LexError] {
+        &self.errors
+    }
+
+    pub fn take_errors(&mut self) -> Vec<LexError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// The byte offset in the input just past the last token lexed.
+    pub fn offset(&self) -> usize {
+        self.pos
+    }
+
+    pub fn next_token(&mut self) -> Token<'a> {
+        let token = self.lex_token();
+
+        match token.kind {
+            TokenKind::LeftParen | TokenKind::LeftBracket | TokenKind::QuestionBracket => {
+                self.group_depth += 1
+            }
+            TokenKind::RightParen | TokenKind::RightBracket => {
+                self.group_depth = self.group_depth.saturating_sub(1)
+            }
+            _ => {}
+        }
+        // Doc comments sit between statements, so they shouldn't decide
+        // whether the newline after them ends one.
+        if token.kind != TokenKind::DocComment {
+            self.last_kind = Some(token.kind);
+        }
+
+        token
+    }
+
+    fn lex_token(&mut self) -> Token<'a> {
+        if self.newline_terminators {
+            if let Some(token) = self.newline_terminator() {
+                return token;
+            }
+        }
+
         self.skip_whitespace();
 
+        let span = self.span();
+
         let token = match self.ch {
-            ';' => Lexer::new_token(TokenKind::Semicolon, self.ch),
-            ',' => Lexer::new_token(TokenKind::Comma, self.ch),
-            '(' => Lexer::new_token(TokenKind::LeftParen, self.ch),
-            ')' => Lexer::new_token(TokenKind::RightParen, self.ch),
-            '{' => Lexer::new_token(TokenKind::LeftBrace, self.ch),
-            '}' => Lexer::new_token(TokenKind::RightBrace, self.ch),
-            '+' => Lexer::new_token(TokenKind::Plus, self.ch),
-            '=' => {
-                if self.peek_char() == '=' {
-                    self.read_char();
-                    Token {
-                        kind: TokenKind::Eq,
-                        literal: String::from("=="),
+            ';' => self.new_token(TokenKind::Semicolon, span),
+            ',' => self.new_token(TokenKind::Comma, span),
+            ':' => self.new_token(TokenKind::Colon, span),
+            '(' => self.new_token(TokenKind::LeftParen, span),
+            ')' => self.new_token(TokenKind::RightParen, span),
+            '[' => self.new_token(TokenKind::LeftBracket, span),
+            ']' => self.new_token(TokenKind::RightBracket, span),
+            '{' => self.new_token(TokenKind::LeftBrace, span),
+            '}' => self.new_token(TokenKind::RightBrace, span),
+            '+' => match self.peek_char() {
+                '=' => self.new_double_token(TokenKind::PlusAssign, span),
+                _ => self.new_token(TokenKind::Plus, span),
+            },
+            '=' => match self.peek_char() {
+                '=' => self.new_double_token(TokenKind::Eq, span),
+                '>' => self.new_double_token(TokenKind::FatArrow, span),
+                _ => self.new_token(TokenKind::Assign, span),
+            },
+            '!' => match self.peek_char() {
+                '=' => self.new_double_token(TokenKind::NotEq, span),
+                _ => self.new_token(TokenKind::Bang, span),
+            },
+            '-' => match self.peek_char() {
+                '=' => self.new_double_token(TokenKind::MinusAssign, span),
+                '>' => self.new_double_token(TokenKind::Arrow, span),
+                _ => self.new_token(TokenKind::Minus, span),
+            },
+            '/' => match self.peek_char() {
+                '/' => {
+                    if let Some(doc) = self.read_doc_comment() {
+                        return Token {
+                            kind: TokenKind::DocComment,
+                            literal: Some(Cow::Borrowed(doc)),
+                            span,
+                        };
                     }
-                } else {
-                    Lexer::new_token(TokenKind::Assign, self.ch)
+
+                    return self.lex_token();
                 }
-            }
-            '!' => {
-                if self.peek_char() == '=' {
-                    self.read_char();
-                    Token {
-                        kind: TokenKind::NotEq,
-                        literal: String::from("!="),
+                '*' => {
+                    let start = self.pos;
+
+                    if self.skip_block_comment() {
+                        return self.lex_token();
                     }
-                } else {
-                    Lexer::new_token(TokenKind::Bang, self.ch)
+
+                    // Unterminated, so report where the comment started.
+                    self.error(LexErrorKind::UnterminatedBlockComment, span);
+                    return Token {
+                        kind: TokenKind::Illegal,
+                        literal: Some(Cow::Borrowed(&self.input[start..start + 2])),
+                        span,
+                    };
+                }
+                '=' => self.new_double_token(TokenKind::SlashAssign, span),
+                _ => self.new_token(TokenKind::Slash, span),
+            },
+            '*' => match self.peek_char() {
+                '=' => self.new_double_token(TokenKind::AsteriskAssign, span),
+                '*' => self.new_double_token(TokenKind::AsteriskAsterisk, span),
+                _ => self.new_token(TokenKind::Asterisk, span),
+            },
+            '"' => {
+                let (kind, literal) = self.read_str();
+
+                Token {
+                    kind,
+                    literal: Some(literal),
+                    span,
                 }
             }
-            '-' => Lexer::new_token(TokenKind::Minus, self.ch),
-            '/' => {
-                if self.peek_char() == '/' {
-                    self.skip_comment();
-                    return self.next();
+            '\'' => {
+                let (kind, literal) = self.read_char_literal();
+
+                Token {
+                    kind,
+                    literal: Some(literal),
+                    span,
+                }
+            }
+            '<' => match self.peek_char() {
+                '=' => self.new_double_token(TokenKind::LessThanEq, span),
+                '<' => self.new_double_token(TokenKind::ShiftLeft, span),
+                _ => self.new_token(TokenKind::LessThan, span),
+            },
+            '>' => match self.peek_char() {
+                '=' => self.new_double_token(TokenKind::GreaterThanEq, span),
+                '>' => self.new_double_token(TokenKind::ShiftRight, span),
+                _ => self.new_token(TokenKind::GreaterThan, span),
+            },
+            '&' => match self.peek_char() {
+                '&' => self.new_double_token(TokenKind::And, span),
+                _ => self.new_token(TokenKind::Ampersand, span),
+            },
+            '|' => match self.peek_char() {
+                '|' => self.new_double_token(TokenKind::Or, span),
+                '>' => self.new_double_token(TokenKind::PipeGreater, span),
+                _ => self.new_token(TokenKind::Pipe, span),
+            },
+            '^' => self.new_token(TokenKind::Caret, span),
+            '?' if self.peek_char() == '?' => {
+                self.new_double_token(TokenKind::QuestionQuestion, span)
+            }
+            '?' if self.peek_char() == '.' => self.new_double_token(TokenKind::QuestionDot, span),
+            // So `c ?[1] : [2]` needs a space after the `?`.
+            '?' if self.peek_char() == '[' => {
+                self.new_double_token(TokenKind::QuestionBracket, span)
+            }
+            '?' => self.new_token(TokenKind::Question, span),
+            '.' if self.peek_char() == '.' => {
+                if self.peek_nth(1) == '=' {
+                    self.read_char();
+                    self.new_double_token(TokenKind::DotDotEq, span)
+                } else if self.peek_nth(1) == '.' {
+                    self.read_char();
+                    self.new_double_token(TokenKind::DotDotDot, span)
                 } else {
-                    Lexer::new_token(TokenKind::Slash, self.ch)
+                    self.new_double_token(TokenKind::DotDot, span)
                 }
             }
-            '*' => Lexer::new_token(TokenKind::Asterisk, self.ch),
-            '<' => Lexer::new_token(TokenKind::LessThan, self.ch),
-            '>' => Lexer::new_token(TokenKind::GreaterThan, self.ch),
-            '\0' => Lexer::new_token(TokenKind::Eof, '\0'),
+            '.' => self.new_token(TokenKind::Dot, span),
+            '\0' if self.is_eof() => self.new_token(TokenKind::Eof, span),
             _ => {
                 return if Lexer::is_letter(self.ch) {
                     let literal = self.read_identifier();
-                    let kind = lookup_identifier(&literal);
+                    let kind = lookup_identifier(literal);
 
-                    Token { kind, literal }
+                    Token {
+                        kind,
+                        literal: kind.spelling().is_none().then_some(Cow::Borrowed(literal)),
+                        span,
+                    }
                 } else if Lexer::is_num(self.ch) {
-                    let literal = self.read_num();
-                    let kind = TokenKind::Int;
+                    let (kind, literal) = self.read_num();
 
-                    Token { kind, literal }
+                    Token {
+                        kind,
+                        literal: Some(literal),
+                        span,
+                    }
                 } else {
-                    Lexer::new_token(TokenKind::Illegal, self.ch)
+                    self.error(LexErrorKind::UnexpectedChar(self.ch), span);
+                    let token = Token {
+                        kind: TokenKind::Illegal,
+                        literal: Some(Cow::Borrowed(&self.input[self.pos..self.read_pos])),
+                        span,
+                    };
+                    self.read_char();
+                    token
                 }
             }
         };
@@ -89,16 +315,135 @@ impl Lexer {
         token
     }
 
+    /// The position of the character currently under the cursor.
+    fn span(&self) -> Span {
+        Span {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Produces a `;` token for the newline under the cursor, after any
+    /// spaces, when the newline terminates a statement.
+    fn newline_terminator(&mut self) -> Option<Token<'a>> {
+        while self.ch != '\n' && self.ch.is_ascii_whitespace() {
+            self.read_char();
+        }
+
+        let ends_statement = matches!(
+            self.last_kind,
+            Some(
+                TokenKind::Identifier
+                    | TokenKind::Int
+                    | TokenKind::Float
+                    | TokenKind::Str
+                    | TokenKind::Char
+                    | TokenKind::True
+                    | TokenKind::False
+                    | TokenKind::Null
+                    | TokenKind::Return
+                    | TokenKind::Break
+                    | TokenKind::Continue
+                    | TokenKind::RightParen
+                    | TokenKind::RightBracket
+                    | TokenKind::RightBrace
+            )
+        );
+
+        if self.ch != '\n'
+            || self.group_depth > 0
+            || !ends_statement
+            || self.continues_with_clause()
+        {
+            return None;
+        }
+
+        // Keep the newline as the literal so it's clear where the `;` came from.
+        let token = Token {
+            kind: TokenKind::Semicolon,
+            literal: Some(Cow::Borrowed(&self.input[self.pos..self.read_pos])),
+            span: self.span(),
+        };
+        self.read_char();
+
+        Some(token)
+    }
+
+    /// Whether the next line starts with `else` or `catch`, which carry on
+    /// the `if` or `try` that the line before ended.
+    fn continues_with_clause(&self) -> bool {
+        let rest = self.input[self.pos..].trim_start();
+
+        ["else", "catch"]
+            .iter()
+            .any(|keyword| match rest.strip_prefix(keyword) {
+                Some(after) => !after.starts_with(|ch: char| ch.is_alphanumeric() || ch == '_'),
+                None => false,
+            })
+    }
+
     fn skip_whitespace(&mut self) {
         while self.ch.is_ascii_whitespace() {
             self.read_char();
         }
     }
 
+    /// Skips a `#!/usr/bin/env tofu` line at the very start of a script so
+    /// the file can be made executable.
+    fn skip_shebang(&mut self) {
+        let at_start = self.pos == 0 && self.span() == Span { line: 1, column: 1 };
+
+        if at_start && self.input.starts_with("#!") {
+            self.skip_comment();
+        }
+    }
+
+    /// Skips a `//` line comment, returning its text when it is a `///` doc
+    /// comment. As in Rust, four or more slashes make a plain comment.
+    fn read_doc_comment(&mut self) -> Option<&'a str> {
+        let start = self.pos;
+        self.skip_comment();
+
+        let comment = &self.input[start..self.pos];
+        let doc = comment.strip_prefix("///")?;
+
+        if doc.starts_with('/') {
+            return None;
+        }
+
+        let doc = doc.trim_end_matches('\r');
+        Some(doc.strip_prefix(' ').unwrap_or(doc))
+    }
+
     fn skip_comment(&mut self) {
-        while self.ch != '\n' {
+        while self.ch != '\n' && !self.is_eof() {
+            self.read_char();
+        }
+    }
+
+    /// Skips a `/* ... */` comment, which may nest. Returns `false` if the
+    /// input ran out before every opened comment was closed.
+    fn skip_block_comment(&mut self) -> bool {
+        let mut depth = 0;
+
+        while !self.is_eof() {
+            if self.ch == '/' && self.peek_char() == '*' {
+                depth += 1;
+                self.read_char();
+            } else if self.ch == '*' && self.peek_char() == '/' {
+                depth -= 1;
+                self.read_char();
+
+                if depth == 0 {
+                    self.read_char();
+                    return true;
+                }
+            }
+
             self.read_char();
         }
+
+        false
     }
 
     fn is_letter(ch: char) -> bool {
@@ -109,406 +454,554 @@ impl Lexer {
         ch.is_numeric()
     }
 
-    fn read_num(&mut self) -> String {
-        let mut num = String::new();
+    /// Reads an integer or a float such as `3.14` or `1.5e3`. A `.` only
+    /// starts a fraction when a digit follows it, and not right after a `.`,
+    /// so `pair.0.1` is two positional accesses. Underscores between digits
+    /// are dropped, so `1_000` lexes as `1000`.
+    fn read_num(&mut self) -> (TokenKind, Cow<'a, str>) {
+        if self.ch == '0' {
+            match self.peek_char() {
+                'x' | 'X' => return self.read_radix_int(16),
+                'o' | 'O' => return self.read_radix_int(8),
+                'b' | 'B' => return self.read_radix_int(2),
+                _ => {}
+            }
+        }
+
+        let start = self.pos;
+        let mut kind = TokenKind::Int;
+
+        self.read_digits();
+
+        let after_dot = matches!(
+            self.last_kind,
+            Some(TokenKind::Dot | TokenKind::QuestionDot)
+        );
+        if self.ch == '.' && Lexer::is_num(self.peek_char()) && !after_dot {
+            kind = TokenKind::Float;
+            self.read_char();
+            self.read_digits();
+        }
+
+        if self.ch == 'e' || self.ch == 'E' {
+            let sign = self.peek_char();
+            let has_exponent = Lexer::is_num(sign)
+                || ((sign == '+' || sign == '-') && Lexer::is_num(self.peek_nth(1)));
+
+            if has_exponent {
+                kind = TokenKind::Float;
+                self.read_char();
+
+                if self.ch == '+' || self.ch == '-' {
+                    self.read_char();
+                }
+
+                self.read_digits();
+            }
+        }
+
+        let num = &self.input[start..self.pos];
+
+        if num.contains('_') {
+            (kind, Cow::Owned(num.replace('_', "")))
+        } else {
+            (kind, Cow::Borrowed(num))
+        }
+    }
+
+    /// Reads a `0x`, `0o` or `0b` prefixed integer and normalizes it into its
+    /// decimal spelling. Malformed or oversized literals come back `Illegal`
+    /// with the source text as written.
+    fn read_radix_int(&mut self, radix: u32) -> (TokenKind, Cow<'a, str>) {
+        let start = self.pos;
+        let span = self.span();
+
+        // The leading `0` and the radix letter.
+        self.read_char();
+        self.read_char();
+
+        let digits_start = self.pos;
+        while self.ch.is_ascii_alphanumeric() || self.ch == '_' {
+            self.read_char();
+        }
+
+        let digits = self.input[digits_start..self.pos].replace('_', "");
+
+        match u128::from_str_radix(&digits, radix) {
+            Ok(value) => (TokenKind::Int, Cow::Owned(value.to_string())),
+            Err(_) => {
+                self.error(LexErrorKind::InvalidNumber, span);
+                (
+                    TokenKind::Illegal,
+                    Cow::Borrowed(&self.input[start..self.pos]),
+                )
+            }
+        }
+    }
+
+    fn read_digits(&mut self) {
+        loop {
+            let is_separator = self.ch == '_' && matches!(self.peek_char(), '_' | '0'..='9');
+
+            if !Lexer::is_num(self.ch) && !is_separator {
+                break;
+            }
+
+            self.read_char();
+        }
+    }
+
+    /// Reads a double quoted string, decoding escape sequences as it goes and
+    /// leaving the cursor on the closing quote. Strings without escapes
+    /// borrow straight from the input. Unterminated strings and bad escapes
+    /// come back `Illegal` with the source text as written.
+    fn read_str(&mut self) -> (TokenKind, Cow<'a, str>) {
+        let start = self.pos;
+        let span = self.span();
+        let mut value: Option<String> = None;
+        let mut valid = true;
+
+        loop {
+            self.read_char();
+
+            if self.is_eof() {
+                self.error(LexErrorKind::UnterminatedString, span);
+                return (TokenKind::Illegal, Cow::Borrowed(&self.input[start..]));
+            }
+
+            match self.ch {
+                '"' => break,
+                '\\' => {
+                    let escape_span = self.span();
+                    let decoded =
+                        value.get_or_insert_with(|| self.input[start + 1..self.pos].to_string());
+
+                    self.read_char();
+
+                    match self.read_escape() {
+                        Some(ch) => decoded.push(ch),
+                        None => {
+                            self.errors.push(LexError {
+                                kind: LexErrorKind::InvalidEscape,
+                                span: escape_span,
+                            });
+                            valid = false;
+                        }
+                    }
+                }
+                ch => {
+                    if let Some(decoded) = value.as_mut() {
+                        decoded.push(ch);
+                    }
+                }
+            }
+        }
+
+        if !valid {
+            return (
+                TokenKind::Illegal,
+                Cow::Borrowed(&self.input[start..self.read_pos]),
+            );
+        }
+
+        match value {
+            Some(decoded) => (TokenKind::Str, Cow::Owned(decoded)),
+            None => (
+                TokenKind::Str,
+                Cow::Borrowed(&self.input[start + 1..self.pos]),
+            ),
+        }
+    }
+
+    /// Reads a single quoted character such as `'a'` or `'\n'`, leaving the
+    /// cursor on the closing quote. Anything other than exactly one
+    /// character or escape between the quotes comes back `Illegal`.
+    fn read_char_literal(&mut self) -> (TokenKind, Cow<'a, str>) {
+        let start = self.pos;
+        let span = self.span();
+        let mut error = LexErrorKind::InvalidChar;
+
+        self.read_char();
+
+        let value = match self.ch {
+            '\'' | '\n' => None,
+            '\0' if self.is_eof() => None,
+            '\\' => {
+                self.read_char();
+                error = LexErrorKind::InvalidEscape;
+                self.read_escape().map(|ch| Cow::Owned(ch.to_string()))
+            }
+            _ => Some(Cow::Borrowed(&self.input[self.pos..self.read_pos])),
+        };
+
+        if let Some(literal) = value {
+            if self.peek_char() == '\'' {
+                self.read_char();
+                return (TokenKind::Char, literal);
+            }
+        }
+
+        self.error(error, span);
 
-        while Lexer::is_num(self.ch) {
-            num.push(self.ch);
+        // Skip to the end of the bad literal so lexing picks up after it.
+        while !matches!(self.ch, '\'' | '\n') && !self.is_eof() {
             self.read_char();
         }
 
-        num
+        let end = if self.ch == '\'' {
+            self.read_pos
+        } else {
+            self.pos
+        };
+
+        (TokenKind::Illegal, Cow::Borrowed(&self.input[start..end]))
+    }
+
+    /// Decodes the escape whose first character (after the `\`) is under
+    /// the cursor, leaving the cursor on its last character.
+    fn read_escape(&mut self) -> Option<char> {
+        let ch = self.ch;
+
+        match ch {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            '0' => Some('\0'),
+            '"' => Some('"'),
+            '\'' => Some('\''),
+            '\\' => Some('\\'),
+            'u' if self.peek_char() == '{' => {
+                self.read_char();
+
+                let digits_start = self.read_pos;
+                while self.peek_char() != '}' && !self.peek_char().is_whitespace() {
+                    if matches!(self.peek_char(), '"' | '\0') {
+                        return None;
+                    }
+
+                    self.read_char();
+                }
+
+                let digits = &self.input[digits_start..self.read_pos];
+                if self.peek_char() != '}' || digits.is_empty() || digits.len() > 6 {
+                    return None;
+                }
+
+                self.read_char();
+
+                u32::from_str_radix(digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+            }
+            _ => None,
+        }
     }
 
-    fn read_identifier(&mut self) -> String {
-        let mut identifier = String::new();
+    /// Reads an identifier, which starts with a letter or underscore and may
+    /// carry on with digits, as in `point_3d`.
+    fn read_identifier(&mut self) -> &'a str {
+        let start = self.pos;
 
-        while Lexer::is_letter(self.ch) {
-            identifier.push(self.ch);
+        while Lexer::is_letter(self.ch) || Lexer::is_num(self.ch) {
             self.read_char();
         }
 
-        identifier
+        &self.input[start..self.pos]
+    }
+
+    /// Builds a token with a fixed single character spelling.
+    fn new_token(&self, kind: TokenKind, span: Span) -> Token<'a> {
+        Token {
+            kind,
+            literal: None,
+            span,
+        }
     }
 
-    fn new_token(kind: TokenKind, ch: char) -> Token {
+    /// Builds a token with a fixed two character spelling, consuming the
+    /// first of the pair.
+    fn new_double_token(&mut self, kind: TokenKind, span: Span) -> Token<'a> {
+        self.read_char();
+
         Token {
             kind,
-            literal: ch.to_string(),
+            literal: None,
+            span,
         }
     }
 
+    fn error(&mut self, kind: LexErrorKind, span: Span) {
+        self.errors.push(LexError { kind, span });
+    }
+
+    fn is_eof(&self) -> bool {
+        self.pos >= self.input.len()
+    }
+
     fn peek_char(&self) -> char {
-        return if self.read_pos >= self.input.len() {
-            '\0'
-        } else {
-            self.input[self.read_pos]
-        };
+        self.peek_nth(0)
+    }
+
+    /// Looks `n` characters past the next one without consuming anything.
+    fn peek_nth(&self, n: usize) -> char {
+        match self.chars.clone().nth(n) {
+            Some((_, ch)) => ch,
+            None => '\0',
+        }
     }
 
     fn read_char(&mut self) {
-        if self.read_pos >= self.input.len() {
-            self.ch = '\0'; // ascii eof
+        if self.ch == '\n' {
+            self.line += 1;
+            self.column = 1;
         } else {
-            self.ch = self.input[self.read_pos];
+            self.column += 1;
+        }
+
+        match self.chars.next() {
+            Some((index, ch)) => {
+                self.pos = index;
+                self.ch = ch;
+                self.read_pos = index + ch.len_utf8();
+            }
+            None => {
+                self.pos = self.input.len();
+                self.read_pos = self.input.len();
+                self.ch = '\0'; // ascii eof
+            }
+        }
+    }
+}
+
+/// Lexes from a reader, pulling in a line at a time as tokens are requested
+/// rather than buffering the whole source up front. Tokens come back owning
+/// their literals since the buffer they were read from doesn't outlive them.
+pub struct ReaderLexer<R> {
+    reader: Option<R>,
+    buffer: String,
+    offset: usize,
+    span: Span,
+    /// The kind of the last token, which a fresh [`Lexer`] for the next one
+    /// needs to know.
+    last_kind: Option<TokenKind>,
+    read_error: Option<io::Error>,
+    errors: Vec<LexError>,
+}
+
+impl<R: BufRead> ReaderLexer<R> {
+    pub fn new(reader: R) -> ReaderLexer<R> {
+        ReaderLexer {
+            reader: Some(reader),
+            buffer: String::new(),
+            offset: 0,
+            span: Span { line: 1, column: 1 },
+            last_kind: None,
+            read_error: None,
+            errors: Vec::new(),
+        }
+    }
+
+    /// The error that stopped the lexer early, if any. A failed read is
+    /// treated as the end of the input.
+    pub fn read_error(&self) -> Option<&io::Error> {
+        self.read_error.as_ref()
+    }
+
+    /// Every error found so far, in source order.
+    pub fn errors(&self) -> &[LexError] {
+        &self.errors
+    }
+
+    pub fn next_token(&mut self) -> Token<'static> {
+        loop {
+            let mut lexer = Lexer::starting_at(&self.buffer[self.offset..], self.span);
+            lexer.last_kind = self.last_kind;
+            if self.offset == 0 {
+                lexer.skip_shebang();
+            }
+            let token = lexer.next_token();
+
+            // A token that ran into the end of the buffer may continue on the
+            // next line, like a string or block comment, so lex it again with
+            // more input.
+            if lexer.is_eof() && self.reader.is_some() {
+                self.read_line();
+                continue;
+            }
+
+            self.offset += lexer.pos;
+            self.span = lexer.span();
+            self.last_kind = lexer.last_kind;
+            self.errors.append(&mut lexer.errors);
+
+            return token.into_owned();
         }
+    }
 
-        self.pos = self.read_pos;
-        self.read_pos += 1;
+    fn read_line(&mut self) {
+        let Some(reader) = self.reader.as_mut() else {
+            return;
+        };
+
+        // Everything before the cursor has already been turned into tokens.
+        self.buffer.drain(..self.offset);
+        self.offset = 0;
+
+        match reader.read_line(&mut self.buffer) {
+            Ok(0) => self.reader = None,
+            Ok(_) => {}
+            Err(e) => {
+                self.read_error = Some(e);
+                self.reader = None;
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Lexer;
-    use crate::token::{Token, TokenKind};
-
-    #[test]
-    fn test_comments_ignored() {
-        let input = r#"
-// Comments should be ignored!
-let is_logged_in = true;
-"#;
+    use std::borrow::Cow;
+    use std::io;
 
-        let expected: Vec<Token> = vec![
-            Token {
-                kind: TokenKind::Let,
-                literal: "let".to_string(),
-            },
-            Token {
-                kind: TokenKind::Identifier,
-                literal: "is_logged_in".to_string(),
-            },
-            Token {
-                kind: TokenKind::Assign,
-                literal: "=".to_string(),
-            },
-            Token {
-                kind: TokenKind::True,
-                literal: "true".to_string(),
-            },
-        ];
+    use super::{LexError, LexErrorKind, Lexer, ReaderLexer};
+    use crate::token::{Span, TokenKind};
 
+    fn assert_tokens(input: &str, expected: Vec<(TokenKind, &str)>) {
         let mut lexer = Lexer::new(input);
 
-        for (index, expected_token) in expected.into_iter().enumerate() {
-            let next_token = lexer.next();
+        for (index, (expected_kind, expected_literal)) in expected.into_iter().enumerate() {
+            let next_token = lexer.next_token();
             assert_eq!(
-                expected_token.kind, next_token.kind,
+                expected_kind, next_token.kind,
                 "Index={index} incorrect token, Expected={}, Got={}",
-                expected_token.kind, next_token.kind
+                expected_kind, next_token.kind
             );
 
             assert_eq!(
-                expected_token.literal, next_token.literal,
+                expected_literal,
+                next_token.literal_str(),
                 "Index={index} incorrect literal, Expected={}, Got={}",
-                expected_token.literal, next_token.literal
+                expected_literal,
+                next_token.literal_str()
             );
         }
     }
 
     #[test]
-    fn test_parse_basic_script() {
-        let input = r#"let one = 1;
-let three = 3;
-
-let add = fn(x, y) {
-    x + y
-}
+    fn test_comments_ignored() {
+        let input = r#"
+// Comments should be ignored!
+let is_logged_in = true;
+"#;
+
+        let expected = vec![
+            (TokenKind::Let, "let"),
+            (TokenKind::Identifier, "is_logged_in"),
+            (TokenKind::Assign, "="),
+            (TokenKind::True, "true"),
+        ];
+
+        assert_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_parse_basic_script() {
+        let input = r#"let one = 1;
+let three = 3;
+
+let add = fn(x, y) {
+    x + y
+}
 
 let result = add(one, three);
 "#;
 
-        let expected: Vec<Token> = vec![
+        let expected = vec![
             // let one = 1
-            Token {
-                kind: TokenKind::Let,
-                literal: "let".to_string(),
-            },
-            Token {
-                kind: TokenKind::Identifier,
-                literal: "one".to_string(),
-            },
-            Token {
-                kind: TokenKind::Assign,
-                literal: "=".to_string(),
-            },
-            Token {
-                kind: TokenKind::Int,
-                literal: "1".to_string(),
-            },
-            Token {
-                kind: TokenKind::Semicolon,
-                literal: ";".to_string(),
-            },
+            (TokenKind::Let, "let"),
+            (TokenKind::Identifier, "one"),
+            (TokenKind::Assign, "="),
+            (TokenKind::Int, "1"),
+            (TokenKind::Semicolon, ";"),
             // let three = 3
-            Token {
-                kind: TokenKind::Let,
-                literal: "let".to_string(),
-            },
-            Token {
-                kind: TokenKind::Identifier,
-                literal: "three".to_string(),
-            },
-            Token {
-                kind: TokenKind::Assign,
-                literal: "=".to_string(),
-            },
-            Token {
-                kind: TokenKind::Int,
-                literal: "3".to_string(),
-            },
-            Token {
-                kind: TokenKind::Semicolon,
-                literal: ";".to_string(),
-            },
+            (TokenKind::Let, "let"),
+            (TokenKind::Identifier, "three"),
+            (TokenKind::Assign, "="),
+            (TokenKind::Int, "3"),
+            (TokenKind::Semicolon, ";"),
             // let add = fn(x, y) { x + y }
-            Token {
-                kind: TokenKind::Let,
-                literal: "let".to_string(),
-            },
-            Token {
-                kind: TokenKind::Identifier,
-                literal: "add".to_string(),
-            },
-            Token {
-                kind: TokenKind::Assign,
-                literal: "=".to_string(),
-            },
-            Token {
-                kind: TokenKind::Fn,
-                literal: "fn".to_string(),
-            },
-            Token {
-                kind: TokenKind::LeftParen,
-                literal: "(".to_string(),
-            },
-            Token {
-                kind: TokenKind::Identifier,
-                literal: "x".to_string(),
-            },
-            Token {
-                kind: TokenKind::Comma,
-                literal: ",".to_string(),
-            },
-            Token {
-                kind: TokenKind::Identifier,
-                literal: "y".to_string(),
-            },
-            Token {
-                kind: TokenKind::RightParen,
-                literal: ")".to_string(),
-            },
-            Token {
-                kind: TokenKind::LeftBrace,
-                literal: "{".to_string(),
-            },
-            Token {
-                kind: TokenKind::Identifier,
-                literal: "x".to_string(),
-            },
-            Token {
-                kind: TokenKind::Plus,
-                literal: "+".to_string(),
-            },
-            Token {
-                kind: TokenKind::Identifier,
-                literal: "y".to_string(),
-            },
-            Token {
-                kind: TokenKind::RightBrace,
-                literal: "}".to_string(),
-            },
+            (TokenKind::Let, "let"),
+            (TokenKind::Identifier, "add"),
+            (TokenKind::Assign, "="),
+            (TokenKind::Fn, "fn"),
+            (TokenKind::LeftParen, "("),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::Comma, ","),
+            (TokenKind::Identifier, "y"),
+            (TokenKind::RightParen, ")"),
+            (TokenKind::LeftBrace, "{"),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::Plus, "+"),
+            (TokenKind::Identifier, "y"),
+            (TokenKind::RightBrace, "}"),
             // let result = add(one, three)
-            Token {
-                kind: TokenKind::Let,
-                literal: "let".to_string(),
-            },
-            Token {
-                kind: TokenKind::Identifier,
-                literal: "result".to_string(),
-            },
-            Token {
-                kind: TokenKind::Assign,
-                literal: "=".to_string(),
-            },
-            Token {
-                kind: TokenKind::Identifier,
-                literal: "add".to_string(),
-            },
-            Token {
-                kind: TokenKind::LeftParen,
-                literal: "(".to_string(),
-            },
-            Token {
-                kind: TokenKind::Identifier,
-                literal: "one".to_string(),
-            },
-            Token {
-                kind: TokenKind::Comma,
-                literal: ",".to_string(),
-            },
-            Token {
-                kind: TokenKind::Identifier,
-                literal: "three".to_string(),
-            },
-            Token {
-                kind: TokenKind::RightParen,
-                literal: ")".to_string(),
-            },
+            (TokenKind::Let, "let"),
+            (TokenKind::Identifier, "result"),
+            (TokenKind::Assign, "="),
+            (TokenKind::Identifier, "add"),
+            (TokenKind::LeftParen, "("),
+            (TokenKind::Identifier, "one"),
+            (TokenKind::Comma, ","),
+            (TokenKind::Identifier, "three"),
+            (TokenKind::RightParen, ")"),
         ];
 
-        let mut lexer = Lexer::new(input);
-
-        for (index, expected_token) in expected.into_iter().enumerate() {
-            let next_token = lexer.next();
-            assert_eq!(
-                expected_token.kind, next_token.kind,
-                "Index={index} incorrect token, Expected={}, Got={}",
-                expected_token.kind, next_token.kind
-            );
-
-            assert_eq!(
-                expected_token.literal, next_token.literal,
-                "Index={index} incorrect literal, Expected={}, Got={}",
-                expected_token.literal, next_token.literal
-            );
-        }
+        assert_tokens(input, expected);
     }
 
     #[test]
     fn test_next_token() {
         let input = "=+(){},;";
 
-        let expected: Vec<Token> = vec![
-            Token {
-                kind: TokenKind::Assign,
-                literal: "=".to_string(),
-            },
-            Token {
-                kind: TokenKind::Plus,
-                literal: "+".to_string(),
-            },
-            Token {
-                kind: TokenKind::LeftParen,
-                literal: "(".to_string(),
-            },
-            Token {
-                kind: TokenKind::RightParen,
-                literal: ")".to_string(),
-            },
-            Token {
-                kind: TokenKind::LeftBrace,
-                literal: "{".to_string(),
-            },
-            Token {
-                kind: TokenKind::RightBrace,
-                literal: "}".to_string(),
-            },
-            Token {
-                kind: TokenKind::Comma,
-                literal: ",".to_string(),
-            },
-            Token {
-                kind: TokenKind::Semicolon,
-                literal: ";".to_string(),
-            },
+        let expected = vec![
+            (TokenKind::Assign, "="),
+            (TokenKind::Plus, "+"),
+            (TokenKind::LeftParen, "("),
+            (TokenKind::RightParen, ")"),
+            (TokenKind::LeftBrace, "{"),
+            (TokenKind::RightBrace, "}"),
+            (TokenKind::Comma, ","),
+            (TokenKind::Semicolon, ";"),
         ];
 
-        let mut lexer = Lexer::new(input);
-
-        for (index, expected_token) in expected.into_iter().enumerate() {
-            let next_token = lexer.next();
-            assert_eq!(
-                expected_token.kind, next_token.kind,
-                "Index={index} incorrect token, Expected={}, Got={}",
-                expected_token.kind, next_token.kind
-            );
-
-            assert_eq!(
-                expected_token.literal, next_token.literal,
-                "Index={index} incorrect literal, Expected={}, Got={}",
-                expected_token.literal, next_token.literal
-            );
-        }
+        assert_tokens(input, expected);
     }
 
     #[test]
     fn test_additional_tokens() {
         let input = r#"
-        !-/*5;
+        !-/ *5;
         2 < 3 > 8;
         "#;
 
-        let expected: Vec<Token> = vec![
-            // !-/*5;
-            Token {
-                kind: TokenKind::Bang,
-                literal: "!".to_string(),
-            },
-            Token {
-                kind: TokenKind::Minus,
-                literal: "-".to_string(),
-            },
-            Token {
-                kind: TokenKind::Slash,
-                literal: "/".to_string(),
-            },
-            Token {
-                kind: TokenKind::Asterisk,
-                literal: "*".to_string(),
-            },
-            Token {
-                kind: TokenKind::Int,
-                literal: "5".to_string(),
-            },
-            Token {
-                kind: TokenKind::Semicolon,
-                literal: ";".to_string(),
-            },
+        let expected = vec![
+            // !-/ *5;
+            (TokenKind::Bang, "!"),
+            (TokenKind::Minus, "-"),
+            (TokenKind::Slash, "/"),
+            (TokenKind::Asterisk, "*"),
+            (TokenKind::Int, "5"),
+            (TokenKind::Semicolon, ";"),
             // 2 < 3 > 8;
-            Token {
-                kind: TokenKind::Int,
-                literal: "2".to_string(),
-            },
-            Token {
-                kind: TokenKind::LessThan,
-                literal: "<".to_string(),
-            },
-            Token {
-                kind: TokenKind::Int,
-                literal: "3".to_string(),
-            },
-            Token {
-                kind: TokenKind::GreaterThan,
-                literal: ">".to_string(),
-            },
-            Token {
-                kind: TokenKind::Int,
-                literal: "8".to_string(),
-            },
-            Token {
-                kind: TokenKind::Semicolon,
-                literal: ";".to_string(),
-            },
+            (TokenKind::Int, "2"),
+            (TokenKind::LessThan, "<"),
+            (TokenKind::Int, "3"),
+            (TokenKind::GreaterThan, ">"),
+            (TokenKind::Int, "8"),
+            (TokenKind::Semicolon, ";"),
         ];
 
-        let mut lexer = Lexer::new(input);
-
-        for (index, expected_token) in expected.into_iter().enumerate() {
-            let next_token = lexer.next();
-            assert_eq!(
-                expected_token.kind, next_token.kind,
-                "Index={index} incorrect token, Expected={}, Got={}",
-                expected_token.kind, next_token.kind
-            );
-
-            assert_eq!(
-                expected_token.literal, next_token.literal,
-                "Index={index} incorrect literal, Expected={}, Got={}",
-                expected_token.literal, next_token.literal
-            );
-        }
+        assert_tokens(input, expected);
     }
 
     #[test]
@@ -521,92 +1014,63 @@ let result = add(one, three);
         }
         "#;
 
-        let expected: Vec<Token> = vec![
+        let expected = vec![
             // if (4 > 2) { return true; } else { return false; }
-            Token {
-                kind: TokenKind::If,
-                literal: "if".to_string(),
-            },
-            Token {
-                kind: TokenKind::LeftParen,
-                literal: "(".to_string(),
-            },
-            Token {
-                kind: TokenKind::Int,
-                literal: "4".to_string(),
-            },
-            Token {
-                kind: TokenKind::GreaterThan,
-                literal: ">".to_string(),
-            },
-            Token {
-                kind: TokenKind::Int,
-                literal: "2".to_string(),
-            },
-            Token {
-                kind: TokenKind::RightParen,
-                literal: ")".to_string(),
-            },
-            Token {
-                kind: TokenKind::LeftBrace,
-                literal: "{".to_string(),
-            },
-            Token {
-                kind: TokenKind::Return,
-                literal: "return".to_string(),
-            },
-            Token {
-                kind: TokenKind::True,
-                literal: "true".to_string(),
-            },
-            Token {
-                kind: TokenKind::Semicolon,
-                literal: ";".to_string(),
-            },
-            Token {
-                kind: TokenKind::RightBrace,
-                literal: "}".to_string(),
-            },
-            Token {
-                kind: TokenKind::Else,
-                literal: "else".to_string(),
-            },
-            Token {
-                kind: TokenKind::LeftBrace,
-                literal: "{".to_string(),
-            },
-            Token {
-                kind: TokenKind::Return,
-                literal: "return".to_string(),
-            },
-            Token {
-                kind: TokenKind::False,
-                literal: "false".to_string(),
-            },
-            Token {
-                kind: TokenKind::Semicolon,
-                literal: ";".to_string(),
-            },
-            Token {
-                kind: TokenKind::RightBrace,
-                literal: "}".to_string(),
-            },
+            (TokenKind::If, "if"),
+            (TokenKind::LeftParen, "("),
+            (TokenKind::Int, "4"),
+            (TokenKind::GreaterThan, ">"),
+            (TokenKind::Int, "2"),
+            (TokenKind::RightParen, ")"),
+            (TokenKind::LeftBrace, "{"),
+            (TokenKind::Return, "return"),
+            (TokenKind::True, "true"),
+            (TokenKind::Semicolon, ";"),
+            (TokenKind::RightBrace, "}"),
+            (TokenKind::Else, "else"),
+            (TokenKind::LeftBrace, "{"),
+            (TokenKind::Return, "return"),
+            (TokenKind::False, "false"),
+            (TokenKind::Semicolon, ";"),
+            (TokenKind::RightBrace, "}"),
         ];
 
-        let mut lexer = Lexer::new(input);
+        assert_tokens(input, expected);
+    }
 
-        for (index, expected_token) in expected.into_iter().enumerate() {
-            let next_token = lexer.next();
-            assert_eq!(
-                expected_token.kind, next_token.kind,
-                "Index={index} incorrect token, Expected={}, Got={}",
-                expected_token.kind, next_token.kind
-            );
+    #[test]
+    fn test_loop_keywords() {
+        let mut lexer = Lexer::new("while (x) { break\n continue\n}\nfor (x in xs) {}");
+        lexer.set_newline_terminators(true);
 
+        let expected = vec![
+            TokenKind::While,
+            TokenKind::LeftParen,
+            TokenKind::Identifier,
+            TokenKind::RightParen,
+            TokenKind::LeftBrace,
+            TokenKind::Break,
+            TokenKind::Semicolon,
+            TokenKind::Continue,
+            TokenKind::Semicolon,
+            TokenKind::RightBrace,
+            TokenKind::Semicolon,
+            TokenKind::For,
+            TokenKind::LeftParen,
+            TokenKind::Identifier,
+            TokenKind::In,
+            TokenKind::Identifier,
+            TokenKind::RightParen,
+            TokenKind::LeftBrace,
+            TokenKind::RightBrace,
+            TokenKind::Eof,
+        ];
+
+        for (index, expected_kind) in expected.into_iter().enumerate() {
             assert_eq!(
-                expected_token.literal, next_token.literal,
-                "Index={index} incorrect literal, Expected={}, Got={}",
-                expected_token.literal, next_token.literal
+                expected_kind,
+                lexer.next_token().kind,
+                "Index={index} incorrect token"
             );
         }
     }
@@ -618,58 +1082,772 @@ let result = add(one, three);
         2 != 1;
         "#;
 
-        let expected: Vec<Token> = vec![
+        let expected = vec![
             // 1 == 1;
-            Token {
-                kind: TokenKind::Int,
-                literal: "1".to_string(),
-            },
-            Token {
-                kind: TokenKind::Eq,
-                literal: "==".to_string(),
-            },
-            Token {
-                kind: TokenKind::Int,
-                literal: "1".to_string(),
-            },
-            Token {
-                kind: TokenKind::Semicolon,
-                literal: ";".to_string(),
-            },
+            (TokenKind::Int, "1"),
+            (TokenKind::Eq, "=="),
+            (TokenKind::Int, "1"),
+            (TokenKind::Semicolon, ";"),
             // 2 != 1;
-            Token {
-                kind: TokenKind::Int,
-                literal: "2".to_string(),
-            },
-            Token {
-                kind: TokenKind::NotEq,
-                literal: "!=".to_string(),
-            },
-            Token {
-                kind: TokenKind::Int,
-                literal: "1".to_string(),
-            },
-            Token {
-                kind: TokenKind::Semicolon,
-                literal: ";".to_string(),
-            },
+            (TokenKind::Int, "2"),
+            (TokenKind::NotEq, "!="),
+            (TokenKind::Int, "1"),
+            (TokenKind::Semicolon, ";"),
+        ];
+
+        assert_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let input = "let x = 10;\n  x + @";
+
+        let expected = vec![
+            (TokenKind::Let, 1, 1),
+            (TokenKind::Identifier, 1, 5),
+            (TokenKind::Assign, 1, 7),
+            (TokenKind::Int, 1, 9),
+            (TokenKind::Semicolon, 1, 11),
+            (TokenKind::Identifier, 2, 3),
+            (TokenKind::Plus, 2, 5),
+            (TokenKind::Illegal, 2, 7),
+            (TokenKind::Eof, 2, 8),
         ];
 
         let mut lexer = Lexer::new(input);
 
-        for (index, expected_token) in expected.into_iter().enumerate() {
-            let next_token = lexer.next();
+        for (index, (expected_kind, line, column)) in expected.into_iter().enumerate() {
+            let expected_span = Span { line, column };
+            let next_token = lexer.next_token();
             assert_eq!(
-                expected_token.kind, next_token.kind,
-                "Index={index} incorrect token, Expected={}, Got={}",
-                expected_token.kind, next_token.kind
+                expected_kind, next_token.kind,
+                "Index={index} incorrect token"
             );
+            assert_eq!(
+                expected_span, next_token.span,
+                "Index={index} incorrect span, Expected={}, Got={}",
+                expected_span, next_token.span
+            );
+        }
+    }
+
+    #[test]
+    fn test_float_literals() {
+        let input = r#"
+        let pi = 3.14;
+        1.5e3 2E-4 7e+2;
+        1.foo 3e x;
+        pair.0.1;
+        "#;
+
+        let expected = vec![
+            // let pi = 3.14;
+            (TokenKind::Let, "let"),
+            (TokenKind::Identifier, "pi"),
+            (TokenKind::Assign, "="),
+            (TokenKind::Float, "3.14"),
+            (TokenKind::Semicolon, ";"),
+            // 1.5e3 2E-4 7e+2;
+            (TokenKind::Float, "1.5e3"),
+            (TokenKind::Float, "2E-4"),
+            (TokenKind::Float, "7e+2"),
+            (TokenKind::Semicolon, ";"),
+            // 1.foo 3e x;
+            (TokenKind::Int, "1"),
+            (TokenKind::Dot, "."),
+            (TokenKind::Identifier, "foo"),
+            (TokenKind::Int, "3"),
+            (TokenKind::Identifier, "e"),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::Semicolon, ";"),
+            // pair.0.1;
+            (TokenKind::Identifier, "pair"),
+            (TokenKind::Dot, "."),
+            (TokenKind::Int, "0"),
+            (TokenKind::Dot, "."),
+            (TokenKind::Int, "1"),
+            (TokenKind::Semicolon, ";"),
+        ];
+
+        assert_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_radix_and_separated_integers() {
+        let input = r#"
+        0xFF 0b1010 0o755 1_000_000 0x_dead_BEEF;
+        1_000.000_1 0b102 0x;
+        "#;
+
+        let expected = vec![
+            // 0xFF 0b1010 0o755 1_000_000 0x_dead_BEEF;
+            (TokenKind::Int, "255"),
+            (TokenKind::Int, "10"),
+            (TokenKind::Int, "493"),
+            (TokenKind::Int, "1000000"),
+            (TokenKind::Int, "3735928559"),
+            (TokenKind::Semicolon, ";"),
+            // 1_000.000_1 0b102 0x;
+            (TokenKind::Float, "1000.0001"),
+            (TokenKind::Illegal, "0b102"),
+            (TokenKind::Illegal, "0x"),
+            (TokenKind::Semicolon, ";"),
+        ];
 
+        assert_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_string_literals() {
+        let input = r#"
+        let greeting = "hello world";
+        "he said \"hi\"\n" "tab\there" "back\\slash\0" "\u{1F600}\u{e9}";
+        "bad \q escape" "unterminated
+        "#;
+
+        let expected = vec![
+            // let greeting = "hello world";
+            (TokenKind::Let, "let"),
+            (TokenKind::Identifier, "greeting"),
+            (TokenKind::Assign, "="),
+            (TokenKind::Str, "hello world"),
+            (TokenKind::Semicolon, ";"),
+            // "he said \"hi\"\n" "tab\there" "back\\slash\0" "\u{1F600}\u{e9}";
+            (TokenKind::Str, "he said \"hi\"\n"),
+            (TokenKind::Str, "tab\there"),
+            (TokenKind::Str, "back\\slash\0"),
+            (TokenKind::Str, "\u{1F600}\u{e9}"),
+            (TokenKind::Semicolon, ";"),
+            // "bad \q escape" "unterminated
+            (TokenKind::Illegal, r#""bad \q escape""#),
+            (TokenKind::Illegal, "\"unterminated\n        "),
+            (TokenKind::Eof, ""),
+        ];
+
+        assert_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_block_comments() {
+        let input = r#"
+        /* a block
+           comment */ let x = /* inline */ 5;
+        /* outer /* nested */ still a comment */ x
+        // trailing line comment without a newline"#;
+
+        let expected = vec![
+            (TokenKind::Let, "let"),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::Assign, "="),
+            (TokenKind::Int, "5"),
+            (TokenKind::Semicolon, ";"),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::Eof, ""),
+        ];
+
+        assert_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let input = "let x; /* never /* closed */";
+
+        let expected = vec![
+            (TokenKind::Let, "let"),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::Semicolon, ";"),
+            (TokenKind::Illegal, "/*"),
+            (TokenKind::Eof, ""),
+        ];
+
+        assert_tokens(input, expected);
+
+        let mut lexer = Lexer::new(input);
+        let illegal = std::iter::from_fn(|| Some(lexer.next_token()))
+            .find(|token| token.kind == TokenKind::Illegal)
+            .unwrap();
+        assert_eq!(Span { line: 1, column: 8 }, illegal.span);
+    }
+
+    #[test]
+    fn test_less_and_greater_than_or_equal() {
+        let input = r#"
+        if (x <= 10) { x >= 2 }
+        1 < = 2;
+        "#;
+
+        let expected = vec![
+            // if (x <= 10) { x >= 2 }
+            (TokenKind::If, "if"),
+            (TokenKind::LeftParen, "("),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::LessThanEq, "<="),
+            (TokenKind::Int, "10"),
+            (TokenKind::RightParen, ")"),
+            (TokenKind::LeftBrace, "{"),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::GreaterThanEq, ">="),
+            (TokenKind::Int, "2"),
+            (TokenKind::RightBrace, "}"),
+            // 1 < = 2;
+            (TokenKind::Int, "1"),
+            (TokenKind::LessThan, "<"),
+            (TokenKind::Assign, "="),
+            (TokenKind::Int, "2"),
+            (TokenKind::Semicolon, ";"),
+        ];
+
+        assert_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_bitwise_and_logical_operators() {
+        let input = r#"
+        a & b | c ^ d;
+        1 << 4 >> 2 ** 3;
+        x && y || z |> f;
+        "#;
+
+        let expected = vec![
+            // a & b | c ^ d;
+            (TokenKind::Identifier, "a"),
+            (TokenKind::Ampersand, "&"),
+            (TokenKind::Identifier, "b"),
+            (TokenKind::Pipe, "|"),
+            (TokenKind::Identifier, "c"),
+            (TokenKind::Caret, "^"),
+            (TokenKind::Identifier, "d"),
+            (TokenKind::Semicolon, ";"),
+            // 1 << 4 >> 2 ** 3;
+            (TokenKind::Int, "1"),
+            (TokenKind::ShiftLeft, "<<"),
+            (TokenKind::Int, "4"),
+            (TokenKind::ShiftRight, ">>"),
+            (TokenKind::Int, "2"),
+            (TokenKind::AsteriskAsterisk, "**"),
+            (TokenKind::Int, "3"),
+            (TokenKind::Semicolon, ";"),
+            // x && y || z;
+            (TokenKind::Identifier, "x"),
+            (TokenKind::And, "&&"),
+            (TokenKind::Identifier, "y"),
+            (TokenKind::Or, "||"),
+            (TokenKind::Identifier, "z"),
+            (TokenKind::PipeGreater, "|>"),
+            (TokenKind::Identifier, "f"),
+            (TokenKind::Semicolon, ";"),
+        ];
+
+        assert_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_compound_assignment() {
+        let input = r#"
+        count += 1;
+        count -= 2;
+        count *= 3;
+        count /= 4;
+        "#;
+
+        let mut expected = vec![];
+        for (kind, literal, value) in [
+            (TokenKind::PlusAssign, "+=", "1"),
+            (TokenKind::MinusAssign, "-=", "2"),
+            (TokenKind::AsteriskAssign, "*=", "3"),
+            (TokenKind::SlashAssign, "/=", "4"),
+        ] {
+            expected.push((TokenKind::Identifier, "count"));
+            expected.push((kind, literal));
+            expected.push((TokenKind::Int, value));
+            expected.push((TokenKind::Semicolon, ";"));
+        }
+
+        assert_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_reader_matches_str() {
+        let input = r#"let add = fn(x, y) {
+    /* spans
+       lines */ x + y
+}
+let s = "a string
+across lines";
+add(1.5, 0xFF);
+"#;
+
+        let mut expected = Lexer::new(input);
+        let mut streamed = ReaderLexer::new(io::Cursor::new(input));
+
+        loop {
+            let expected_token = expected.next_token();
+            let streamed_token = streamed.next_token();
+            assert_eq!(expected_token, streamed_token);
+
+            if expected_token.kind == TokenKind::Eof {
+                break;
+            }
+        }
+
+        assert!(streamed.buffer.len() < input.len());
+        assert!(streamed.read_error().is_none());
+    }
+
+    #[test]
+    fn test_literals_borrow_from_input() {
+        let input = r#"total 1_000 "plain" "esc\n""#;
+        let mut lexer = Lexer::new(input);
+
+        let is_borrowed = |literal: Option<Cow<str>>| matches!(literal, Some(Cow::Borrowed(_)));
+
+        assert!(is_borrowed(lexer.next_token().literal));
+        assert!(!is_borrowed(lexer.next_token().literal));
+        assert!(is_borrowed(lexer.next_token().literal));
+        assert!(!is_borrowed(lexer.next_token().literal));
+    }
+
+    #[test]
+    fn test_identifiers_with_digits() {
+        let input = r#"
+        let user1 = x2 + point_3d;
+        _0 9lives
+        "#;
+
+        let expected = vec![
+            // let user1 = x2 + point_3d;
+            (TokenKind::Let, "let"),
+            (TokenKind::Identifier, "user1"),
+            (TokenKind::Assign, "="),
+            (TokenKind::Identifier, "x2"),
+            (TokenKind::Plus, "+"),
+            (TokenKind::Identifier, "point_3d"),
+            (TokenKind::Semicolon, ";"),
+            // _0 9lives
+            (TokenKind::Identifier, "_0"),
+            (TokenKind::Int, "9"),
+            (TokenKind::Identifier, "lives"),
+        ];
+
+        assert_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_char_literals() {
+        let input = r#"
+        'a' '\n' '\'' '\\' 'é' '\u{1F600}';
+        'ab' '' '\q' x;
+        "#;
+
+        let expected = vec![
+            (TokenKind::Char, "a"),
+            (TokenKind::Char, "\n"),
+            (TokenKind::Char, "'"),
+            (TokenKind::Char, "\\"),
+            (TokenKind::Char, "é"),
+            (TokenKind::Char, "\u{1F600}"),
+            (TokenKind::Semicolon, ";"),
+            (TokenKind::Illegal, "'ab'"),
+            (TokenKind::Illegal, "''"),
+            (TokenKind::Illegal, r"'\q'"),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::Semicolon, ";"),
+        ];
+
+        assert_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_newline_terminators() {
+        let input = r#"let x = 1
+let y = add(x,
+    2) // comment
+if (y) {
+    y
+}
+else { x }
+try { x }
+catch (e) { y }
+x +
+    y
+"#;
+
+        let expected = vec![
+            (TokenKind::Let, "let"),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::Assign, "="),
+            (TokenKind::Int, "1"),
+            (TokenKind::Semicolon, "\n"),
+            (TokenKind::Let, "let"),
+            (TokenKind::Identifier, "y"),
+            (TokenKind::Assign, "="),
+            (TokenKind::Identifier, "add"),
+            (TokenKind::LeftParen, "("),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::Comma, ","),
+            (TokenKind::Int, "2"),
+            (TokenKind::RightParen, ")"),
+            (TokenKind::Semicolon, "\n"),
+            (TokenKind::If, "if"),
+            (TokenKind::LeftParen, "("),
+            (TokenKind::Identifier, "y"),
+            (TokenKind::RightParen, ")"),
+            (TokenKind::LeftBrace, "{"),
+            (TokenKind::Identifier, "y"),
+            (TokenKind::Semicolon, "\n"),
+            (TokenKind::RightBrace, "}"),
+            (TokenKind::Else, "else"),
+            (TokenKind::LeftBrace, "{"),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::RightBrace, "}"),
+            (TokenKind::Semicolon, "\n"),
+            (TokenKind::Try, "try"),
+            (TokenKind::LeftBrace, "{"),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::RightBrace, "}"),
+            (TokenKind::Catch, "catch"),
+            (TokenKind::LeftParen, "("),
+            (TokenKind::Identifier, "e"),
+            (TokenKind::RightParen, ")"),
+            (TokenKind::LeftBrace, "{"),
+            (TokenKind::Identifier, "y"),
+            (TokenKind::RightBrace, "}"),
+            (TokenKind::Semicolon, "\n"),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::Plus, "+"),
+            (TokenKind::Identifier, "y"),
+            (TokenKind::Semicolon, "\n"),
+            (TokenKind::Eof, ""),
+        ];
+
+        let mut lexer = Lexer::new(input);
+        lexer.set_newline_terminators(true);
+
+        for (index, (expected_kind, expected_literal)) in expected.into_iter().enumerate() {
+            let next_token = lexer.next_token();
             assert_eq!(
-                expected_token.literal, next_token.literal,
-                "Index={index} incorrect literal, Expected={}, Got={}",
-                expected_token.literal, next_token.literal
+                expected_kind, next_token.kind,
+                "Index={index} incorrect token"
+            );
+            assert_eq!(
+                expected_literal,
+                next_token.literal_str(),
+                "Index={index} incorrect literal"
             );
         }
     }
+
+    #[test]
+    fn test_brackets() {
+        let mut lexer = Lexer::new("let xs = [1,\n  2]\nxs[0]\n");
+        lexer.set_newline_terminators(true);
+
+        let expected = vec![
+            (TokenKind::Let, "let"),
+            (TokenKind::Identifier, "xs"),
+            (TokenKind::Assign, "="),
+            (TokenKind::LeftBracket, "["),
+            (TokenKind::Int, "1"),
+            (TokenKind::Comma, ","),
+            (TokenKind::Int, "2"),
+            (TokenKind::RightBracket, "]"),
+            (TokenKind::Semicolon, "\n"),
+            (TokenKind::Identifier, "xs"),
+            (TokenKind::LeftBracket, "["),
+            (TokenKind::Int, "0"),
+            (TokenKind::RightBracket, "]"),
+            (TokenKind::Semicolon, "\n"),
+            (TokenKind::Eof, ""),
+        ];
+
+        for (index, (expected_kind, expected_literal)) in expected.into_iter().enumerate() {
+            let token = lexer.next_token();
+            assert_eq!(expected_kind, token.kind, "Index={index} incorrect token");
+            assert_eq!(
+                expected_literal,
+                token.literal_str(),
+                "Index={index} incorrect literal"
+            );
+        }
+    }
+
+    #[test]
+    fn test_hash_literal() {
+        assert_tokens(
+            r#"{"a": 1, 2: b}"#,
+            vec![
+                (TokenKind::LeftBrace, "{"),
+                (TokenKind::Str, "a"),
+                (TokenKind::Colon, ":"),
+                (TokenKind::Int, "1"),
+                (TokenKind::Comma, ","),
+                (TokenKind::Int, "2"),
+                (TokenKind::Colon, ":"),
+                (TokenKind::Identifier, "b"),
+                (TokenKind::RightBrace, "}"),
+                (TokenKind::Eof, ""),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_ranges() {
+        assert_tokens(
+            "1..10 0..=n 1.5..2 ...xs .",
+            vec![
+                (TokenKind::Int, "1"),
+                (TokenKind::DotDot, ".."),
+                (TokenKind::Int, "10"),
+                (TokenKind::Int, "0"),
+                (TokenKind::DotDotEq, "..="),
+                (TokenKind::Identifier, "n"),
+                (TokenKind::Float, "1.5"),
+                (TokenKind::DotDot, ".."),
+                (TokenKind::Int, "2"),
+                (TokenKind::DotDotDot, "..."),
+                (TokenKind::Identifier, "xs"),
+                (TokenKind::Dot, "."),
+                (TokenKind::Eof, ""),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_nullish_operators() {
+        assert_tokens(
+            r#"a ?? b?.c?["d"] ? 1 : 2"#,
+            vec![
+                (TokenKind::Identifier, "a"),
+                (TokenKind::QuestionQuestion, "??"),
+                (TokenKind::Identifier, "b"),
+                (TokenKind::QuestionDot, "?."),
+                (TokenKind::Identifier, "c"),
+                (TokenKind::QuestionBracket, "?["),
+                (TokenKind::Str, "d"),
+                (TokenKind::RightBracket, "]"),
+                (TokenKind::Question, "?"),
+                (TokenKind::Int, "1"),
+                (TokenKind::Colon, ":"),
+                (TokenKind::Int, "2"),
+                (TokenKind::Eof, ""),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_null_keyword() {
+        let input = "let nothing = null; nullable";
+
+        let expected = vec![
+            (TokenKind::Let, "let"),
+            (TokenKind::Identifier, "nothing"),
+            (TokenKind::Assign, "="),
+            (TokenKind::Null, "null"),
+            (TokenKind::Semicolon, ";"),
+            (TokenKind::Identifier, "nullable"),
+        ];
+
+        assert_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_const_keyword() {
+        let input = "const limit = 10; let constant = limit;";
+
+        let expected = vec![
+            (TokenKind::Const, "const"),
+            (TokenKind::Identifier, "limit"),
+            (TokenKind::Assign, "="),
+            (TokenKind::Int, "10"),
+            (TokenKind::Semicolon, ";"),
+            (TokenKind::Let, "let"),
+            (TokenKind::Identifier, "constant"),
+            (TokenKind::Assign, "="),
+            (TokenKind::Identifier, "limit"),
+            (TokenKind::Semicolon, ";"),
+        ];
+
+        assert_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_match_and_fat_arrow() {
+        let input = r#"match x { 1 => "one", _ => "other" }"#;
+
+        let expected = vec![
+            (TokenKind::Match, "match"),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::LeftBrace, "{"),
+            (TokenKind::Int, "1"),
+            (TokenKind::FatArrow, "=>"),
+            (TokenKind::Str, "one"),
+            (TokenKind::Comma, ","),
+            (TokenKind::Identifier, "_"),
+            (TokenKind::FatArrow, "=>"),
+            (TokenKind::Str, "other"),
+            (TokenKind::RightBrace, "}"),
+        ];
+
+        assert_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_fixed_spellings_carry_no_literal() {
+        let mut lexer = ReaderLexer::new(io::Cursor::new("let total += (count);"));
+
+        let mut literals = vec![];
+        loop {
+            let token = lexer.next_token();
+            if token.kind == TokenKind::Eof {
+                break;
+            }
+            literals.push((token.literal.is_some(), token.literal_str().to_string()));
+        }
+
+        let expected = vec![
+            (false, "let"),
+            (true, "total"),
+            (false, "+="),
+            (false, "("),
+            (true, "count"),
+            (false, ")"),
+            (false, ";"),
+        ];
+        let expected: Vec<(bool, String)> = expected
+            .into_iter()
+            .map(|(owned, literal)| (owned, literal.to_string()))
+            .collect();
+
+        assert_eq!(expected, literals);
+    }
+
+    #[test]
+    fn test_errors_are_collected() {
+        let input = "let @ = \"a\\qb\";\n'xy' 0b12 # \"open";
+
+        let mut lexer = Lexer::new(input);
+        while lexer.next_token().kind != TokenKind::Eof {}
+
+        let expected = vec![
+            LexError {
+                kind: LexErrorKind::UnexpectedChar('@'),
+                span: Span { line: 1, column: 5 },
+            },
+            LexError {
+                kind: LexErrorKind::InvalidEscape,
+                span: Span {
+                    line: 1,
+                    column: 11,
+                },
+            },
+            LexError {
+                kind: LexErrorKind::InvalidChar,
+                span: Span { line: 2, column: 1 },
+            },
+            LexError {
+                kind: LexErrorKind::InvalidNumber,
+                span: Span { line: 2, column: 6 },
+            },
+            LexError {
+                kind: LexErrorKind::UnexpectedChar('#'),
+                span: Span {
+                    line: 2,
+                    column: 11,
+                },
+            },
+            LexError {
+                kind: LexErrorKind::UnterminatedString,
+                span: Span {
+                    line: 2,
+                    column: 13,
+                },
+            },
+        ];
+
+        assert_eq!(expected, lexer.errors());
+        assert_eq!(
+            "1:5: unexpected character '@' (U+0040)",
+            lexer.errors()[0].to_string()
+        );
+
+        let mut streamed = ReaderLexer::new(io::Cursor::new(input));
+        while streamed.next_token().kind != TokenKind::Eof {}
+        assert_eq!(expected, streamed.errors());
+    }
+
+    #[test]
+    fn test_shebang_skipped() {
+        let input = "#!/usr/bin/env tofu\nlet x = 1;\n";
+
+        let expected = vec![
+            (TokenKind::Let, 2, 1),
+            (TokenKind::Identifier, 2, 5),
+            (TokenKind::Assign, 2, 7),
+            (TokenKind::Int, 2, 9),
+            (TokenKind::Semicolon, 2, 10),
+            (TokenKind::Eof, 3, 1),
+        ];
+
+        let mut lexer = Lexer::new(input);
+        let mut streamed = ReaderLexer::new(io::Cursor::new(input));
+
+        for (index, (expected_kind, line, column)) in expected.into_iter().enumerate() {
+            let expected_span = Span { line, column };
+
+            for token in [lexer.next_token(), streamed.next_token()] {
+                assert_eq!(expected_kind, token.kind, "Index={index} incorrect token");
+                assert_eq!(expected_span, token.span, "Index={index} incorrect span");
+            }
+        }
+
+        assert!(lexer.errors().is_empty());
+
+        // Only the first line can be a shebang.
+        let mut lexer = Lexer::new("let x;\n#!/usr/bin/env tofu");
+        while lexer.next_token().kind != TokenKind::Eof {}
+        assert_eq!(
+            vec![LexErrorKind::UnexpectedChar('#')],
+            lexer
+                .errors()
+                .iter()
+                .map(|error| error.kind.clone())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let input = r#"
+        /// Adds two numbers.
+        ///
+        ///Returns their sum.
+        let add = fn(x, y) { x + y };
+        //// Not documentation.
+        // Nor this.
+        "#;
+
+        let expected = vec![
+            (TokenKind::DocComment, "Adds two numbers."),
+            (TokenKind::DocComment, ""),
+            (TokenKind::DocComment, "Returns their sum."),
+            (TokenKind::Let, "let"),
+            (TokenKind::Identifier, "add"),
+            (TokenKind::Assign, "="),
+            (TokenKind::Fn, "fn"),
+            (TokenKind::LeftParen, "("),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::Comma, ","),
+            (TokenKind::Identifier, "y"),
+            (TokenKind::RightParen, ")"),
+            (TokenKind::LeftBrace, "{"),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::Plus, "+"),
+            (TokenKind::Identifier, "y"),
+            (TokenKind::RightBrace, "}"),
+            (TokenKind::Semicolon, ";"),
+            (TokenKind::Eof, ""),
+        ];
+
+        assert_tokens(input, expected);
+    }
 }
@@ -0,0 +1,165 @@
+//! The Tofu language: its lexer, parser, tree-walking evaluator, and
+//! bytecode compiler and VM, for running Tofu from other programs. The
+//! `tofu_interpreter` binary is a command line over this library.
+//!
+//! The usual way in is to [`parse`] some source, then evaluate the
+//! program:
+//!
+//! ```
+//! use tofu_interpreter::{Evaluator, Value};
+//!
+//! let program = tofu_interpreter::parse("let x = 2\nx * 21").unwrap();
+//! let mut evaluator = Evaluator::new();
+//! assert_eq!(Ok(Value::Int(42)), evaluator.eval_program(&program));
+//! ```
+//!
+//! [`Interpreter`] does that with each piece of source it's given, keeping
+//! what they bind, for embedding Tofu in an application.
+//!
+//! [`Lexer`] and [`Parser`] can be driven by hand for more control, like
+//! where line numbers start or keeping what parsed despite errors. The
+//! types all this takes are re-exported here. The modules have the rest,
+//! like [`compiler`] and [`vm`] for running compiled code, and
+//! [`diagnostic`] for showing errors the way the binary does.
+
+pub mod ast;
+#[cfg(feature = "bigint")]
+pub mod bigint;
+pub mod builtins;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod code;
+pub mod compiler;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod config;
+pub mod convert;
+pub mod debugger;
+pub mod diagnostic;
+pub mod environment;
+pub mod error;
+pub mod evaluator;
+pub mod fold;
+pub mod format;
+pub mod gc;
+pub mod heap;
+pub mod highlight;
+pub mod interpreter;
+pub mod interrupt;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod lexer;
+pub mod lint;
+pub mod lsp;
+pub mod parser;
+pub mod peephole;
+pub mod plugin;
+pub mod profile;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod repl;
+pub mod resolve;
+pub mod sexp;
+pub mod shared;
+pub mod snapshot;
+pub mod symbol;
+pub mod symbol_table;
+pub mod token;
+pub mod typecheck;
+pub mod value;
+pub mod visit;
+pub mod vm;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use ast::Program;
+pub use builtins::Arity;
+pub use error::TofuError;
+pub use evaluator::{ErrorKind, Evaluator, RuntimeError};
+pub use interpreter::{Interpreter, TofuFunction};
+pub use lexer::{LexError, Lexer};
+pub use parser::{ParseError, Parser};
+pub use token::{Span, Token, TokenKind};
+pub use value::Value;
+
+/// The tokens in `source`, not counting the [`TokenKind::Eof`] at the end,
+/// or everything wrong with them.
+pub fn tokenize(source: &str) -> Result<Vec<Token<'_>>, Vec<LexError>> {
+    let mut lexer = Lexer::new(source);
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.next_token();
+        if token.kind == TokenKind::Eof {
+            break;
+        }
+        tokens.push(token);
+    }
+    match lexer.take_errors() {
+        errors if errors.is_empty() => Ok(tokens),
+        errors => Err(errors),
+    }
+}
+
+/// The program in `source`, parsed as the binary parses a script, where a
+/// newline can end a statement, or everything wrong with it.
+pub fn parse(source: &str) -> Result<Program, Vec<ParseError>> {
+    let mut lexer = Lexer::new(source);
+    lexer.set_newline_terminators(true);
+    let mut parser = Parser::new(lexer);
+    let program = parser.parse_program();
+    match parser.errors() {
+        [] => Ok(program),
+        errors => Err(errors.to_vec()),
+    }
+}
+
+/// So [`Evaluator::set_max_memory`] is tested with memory being counted,
+/// as it is in the binary.
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: heap::CountingAllocator = heap::CountingAllocator;
+
+#[cfg(test)]
+mod test {
+    use super::{parse, tokenize, TokenKind};
+    use crate::lexer::LexErrorKind;
+
+    #[test]
+    fn test_tokenize() {
+        let kinds: Vec<TokenKind> = tokenize("let x = 1;")
+            .unwrap()
+            .iter()
+            .map(|token| token.kind)
+            .collect();
+        assert_eq!(
+            vec![
+                TokenKind::Let,
+                TokenKind::Identifier,
+                TokenKind::Assign,
+                TokenKind::Int,
+                TokenKind::Semicolon
+            ],
+            kinds
+        );
+
+        let errors = tokenize("let @ = 1 $").unwrap_err();
+        assert_eq!(
+            vec![
+                LexErrorKind::UnexpectedChar('@'),
+                LexErrorKind::UnexpectedChar('$')
+            ],
+            errors
+                .into_iter()
+                .map(|error| error.kind)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let program = parse("let x = 1\nx + 2").unwrap();
+        assert_eq!(2, program.statements.len());
+        assert_eq!("let x = 1;\nx + 2;", program.to_string());
+
+        let errors = parse("let = 1\nlet y = (2").unwrap_err();
+        assert_eq!(2, errors.len(), "{errors:?}");
+    }
+}
@@ -0,0 +1,513 @@
+//! Warnings about code that runs but is probably a mistake: bindings and
+//! parameters that are never used, bindings that shadow others, code after
+//! a `return` that can never run, comparisons like `x == true`, and
+//! `match`es over an enum's variants that leave some out.
+//!
+//! Each warning has a code, and a comment like `// lint: allow(code)`
+//! silences the warnings with that code on its line, or on the next line if
+//! the comment is on a line of its own. Names starting with `_` are never
+//! reported as unused.
+
+use std::collections::HashMap;
+
+use crate::ast::{
+    BlockStatement, Expression, InfixOperator, MatchArm, Pattern, Program, Statement,
+};
+use crate::format::{comments, positions};
+use crate::lexer::Lexer;
+use crate::parser::{ParseError, Parser};
+use crate::symbol::{Symbol, SymbolMap};
+use crate::token::Span;
+use crate::visit::{walk_expression, walk_statement, Visit};
+
+pub const UNUSED_VARIABLE: &str = "unused_variable";
+pub const UNUSED_PARAMETER: &str = "unused_parameter";
+pub const SHADOWED_VARIABLE: &str = "shadowed_variable";
+pub const UNREACHABLE_CODE: &str = "unreachable_code";
+pub const BOOL_COMPARISON: &str = "bool_comparison";
+pub const NON_EXHAUSTIVE_MATCH: &str = "non_exhaustive_match";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    /// Which check the warning is from, like [`UNUSED_VARIABLE`].
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+}
+
+/// The warnings about `source`, in the order of where they point, or why it
+/// couldn't be parsed.
+pub fn lint(source: &str) -> Result<Vec<Warning>, Vec<ParseError>> {
+    let mut lexer = Lexer::new(source);
+    lexer.set_newline_terminators(true);
+    let mut parser = Parser::new(lexer);
+    let program = parser.parse_program();
+    if !parser.errors().is_empty() {
+        return Err(parser.errors().to_vec());
+    }
+
+    let mut linter = Linter {
+        warnings: Vec::new(),
+        scopes: Vec::new(),
+        statement_starts: positions(&program, &parser).0,
+        enums: SymbolMap::default(),
+        variants: SymbolMap::default(),
+    };
+    linter.visit_program(&program);
+
+    let allowed = allowed(source);
+    let mut warnings: Vec<Warning> = linter
+        .warnings
+        .into_iter()
+        .filter(|warning| {
+            !allowed
+                .get(&warning.span.line)
+                .is_some_and(|codes| codes.iter().any(|code| code == warning.code))
+        })
+        .collect();
+    warnings.sort_by_key(|warning| (warning.span.line, warning.span.column));
+    Ok(warnings)
+}
+
+/// The codes allowed on each line of `source` by `// lint: allow(...)`
+/// comments.
+fn allowed(source: &str) -> HashMap<usize, Vec<String>> {
+    let mut allowed: HashMap<usize, Vec<String>> = HashMap::new();
+    for comment in comments(source) {
+        let Some(codes) = comment
+            .text
+            .strip_prefix("//")
+            .map(str::trim)
+            .and_then(|text| text.strip_prefix("lint: allow("))
+            .and_then(|text| text.strip_suffix(')'))
+        else {
+            continue;
+        };
+        let line = if comment.own_line {
+            comment.span.line + 1
+        } else {
+            comment.span.line
+        };
+        allowed
+            .entry(line)
+            .or_default()
+            .extend(codes.split(',').map(|code| code.trim().to_string()));
+    }
+    allowed
+}
+
+/// What a name was bound by, which decides what's said if it's never used.
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Variable,
+    Parameter,
+    /// A `for`, `catch` or `match` pattern, an import, a struct, an enum's
+    /// variant, or a name starting with `_`, which needn't be used.
+    Quiet,
+}
+
+struct Binding {
+    name: Symbol,
+    span: Span,
+    kind: Kind,
+    used: bool,
+}
+
+#[derive(Default)]
+struct Scope {
+    /// The bindings in the order they were made, so the last one with a
+    /// name is the one a use of it sees.
+    bindings: Vec<Binding>,
+    /// The names used inside the scope before anything bound them, which
+    /// count as used if the scope binds them later, as a function can call
+    /// one defined after it.
+    early: SymbolMap<()>,
+    /// Whether it's the top level, whose bindings an importer can use.
+    top_level: bool,
+}
+
+struct Linter {
+    warnings: Vec<Warning>,
+    /// The scopes around the current node, outermost first.
+    scopes: Vec<Scope>,
+    statement_starts: HashMap<*const Statement, Span>,
+    /// The variants of each enum declared so far, by the enum's name.
+    enums: SymbolMap<Vec<Symbol>>,
+    /// The enum each variant declared so far is of, and whether it has no
+    /// fields, by the variant's name.
+    variants: SymbolMap<(Symbol, bool)>,
+}
+
+impl Linter {
+    fn warn(&mut self, code: &'static str, message: String, span: Span) {
+        self.warnings.push(Warning {
+            code,
+            message,
+            span,
+        });
+    }
+
+    fn in_scope(&mut self, top_level: bool, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(Scope {
+            top_level,
+            ..Scope::default()
+        });
+        f(self);
+        let scope = self.scopes.pop().expect("Should have pushed a scope");
+        if scope.top_level {
+            return;
+        }
+        for binding in scope.bindings.into_iter().filter(|binding| !binding.used) {
+            let name = &binding.name;
+            match binding.kind {
+                Kind::Variable => self.warn(
+                    UNUSED_VARIABLE,
+                    format!("`{name}` is never used"),
+                    binding.span,
+                ),
+                Kind::Parameter => self.warn(
+                    UNUSED_PARAMETER,
+                    format!("parameter `{name}` is never used"),
+                    binding.span,
+                ),
+                Kind::Quiet => {}
+            }
+        }
+    }
+
+    fn is_bound(&self, name: &Symbol) -> bool {
+        self.scopes
+            .iter()
+            .any(|scope| scope.bindings.iter().any(|binding| binding.name == *name))
+    }
+
+    /// Binds `name` in the innermost scope, warning if that hides another
+    /// binding when `kind` is one that's reported.
+    fn bind(&mut self, name: Symbol, span: Span, mut kind: Kind) {
+        if name.as_str().starts_with('_') {
+            kind = Kind::Quiet;
+        }
+        if kind != Kind::Quiet && self.is_bound(&name) {
+            self.warn(
+                SHADOWED_VARIABLE,
+                format!("`{name}` shadows an earlier binding"),
+                span,
+            );
+        }
+        let scope = self.scopes.last_mut().expect("Should be in a scope");
+        let used = scope.early.contains_key(&name);
+        scope.bindings.push(Binding {
+            name,
+            span,
+            kind,
+            used,
+        });
+    }
+
+    fn use_name(&mut self, name: &Symbol) {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(binding) = scope
+                .bindings
+                .iter_mut()
+                .rev()
+                .find(|binding| binding.name == *name)
+            {
+                binding.used = true;
+                return;
+            }
+        }
+        for scope in &mut self.scopes {
+            scope.early.insert(name.clone(), ());
+        }
+    }
+
+    /// Warns if `arms` all match variants of one enum, with none matching
+    /// whatever's left, and some of its variants aren't matched.
+    fn check_exhaustive(&mut self, arms: &[MatchArm], span: Span) {
+        let mut enum_name = None;
+        let mut covered = Vec::new();
+        for arm in arms {
+            let (name, fields) = match &arm.pattern {
+                Pattern::Variant { name, fields } => (name, fields.as_slice()),
+                Pattern::Identifier(name)
+                    if self.variants.get(name).is_some_and(|&(_, nullary)| nullary) =>
+                {
+                    (name, [].as_slice())
+                }
+                _ => return,
+            };
+            let Some((owner, _)) = self.variants.get(name) else {
+                return;
+            };
+            if enum_name.get_or_insert_with(|| owner.clone()) != owner {
+                return;
+            }
+            // A field pattern that can fail to match leaves the variant
+            // partly unmatched.
+            let irrefutable = fields.iter().all(|field| match field {
+                Pattern::Identifier(field) => !self.variants.contains_key(field),
+                field => *field == Pattern::Wildcard,
+            });
+            if irrefutable {
+                covered.push(name.clone());
+            }
+        }
+
+        let Some(enum_name) = enum_name else {
+            return;
+        };
+        let missing: Vec<String> = self.enums[&enum_name]
+            .iter()
+            .filter(|variant| !covered.contains(variant))
+            .map(|variant| format!("`{variant}`"))
+            .collect();
+        if !missing.is_empty() {
+            self.warn(
+                NON_EXHAUSTIVE_MATCH,
+                format!("`match` on `{enum_name}` is missing {}", missing.join(", ")),
+                span,
+            );
+        }
+    }
+
+    /// Visits `statements`, warning about the first one after a statement
+    /// that always leaves them.
+    fn statements(&mut self, statements: &[Statement]) {
+        let mut leaving = None;
+        let mut reported = false;
+        for statement in statements {
+            if let (Some(keyword), false) = (leaving, reported) {
+                reported = true;
+                if let Some(&span) = self.statement_starts.get(&(statement as *const _)) {
+                    self.warn(
+                        UNREACHABLE_CODE,
+                        format!("unreachable code after `{keyword}`"),
+                        span,
+                    );
+                }
+            }
+            self.visit_statement(statement);
+            if leaving.is_none() {
+                leaving = match statement {
+                    Statement::Return(_) => Some("return"),
+                    Statement::Break => Some("break"),
+                    Statement::Continue => Some("continue"),
+                    Statement::Throw { .. } => Some("throw"),
+                    _ => None,
+                };
+            }
+        }
+    }
+}
+
+impl Visit for Linter {
+    fn visit_program(&mut self, program: &Program) {
+        self.in_scope(true, |this| this.statements(&program.statements));
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let(statement) => {
+                self.visit_expression(&statement.value);
+                for name in pattern_names(&statement.pattern) {
+                    self.bind(name, statement.span, Kind::Variable);
+                }
+            }
+            Statement::Import(import) => self.bind(import.name.clone(), import.span, Kind::Quiet),
+            Statement::Struct { name, span, .. } => self.bind(name.clone(), *span, Kind::Quiet),
+            Statement::Enum {
+                name,
+                variants,
+                span,
+            } => {
+                for variant in variants {
+                    self.bind(variant.name.clone(), *span, Kind::Quiet);
+                    self.variants.insert(
+                        variant.name.clone(),
+                        (name.clone(), variant.fields.is_empty()),
+                    );
+                }
+                let names = variants.iter().map(|variant| variant.name.clone());
+                self.enums.insert(name.clone(), names.collect());
+            }
+            Statement::For {
+                pattern,
+                iterable,
+                body,
+            } => {
+                self.visit_expression(iterable);
+                self.in_scope(false, |this| {
+                    for name in pattern_names(pattern) {
+                        this.bind(name, Span::default(), Kind::Quiet);
+                    }
+                    this.visit_block(body);
+                });
+            }
+            statement => walk_statement(self, statement),
+        }
+    }
+
+    fn visit_block(&mut self, block: &BlockStatement) {
+        self.in_scope(false, |this| this.statements(&block.statements));
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Identifier { name, .. } => self.use_name(name),
+            Expression::Function {
+                parameters, body, ..
+            } => self.in_scope(false, |this| {
+                for parameter in &parameters.positional {
+                    if let Some(default) = &parameter.default {
+                        this.visit_expression(default);
+                    }
+                    this.bind(parameter.name.clone(), parameter.span, Kind::Parameter);
+                }
+                if let Some(rest) = &parameters.rest {
+                    this.bind(rest.clone(), Span::default(), Kind::Quiet);
+                }
+                this.visit_block(body);
+            }),
+            Expression::Try {
+                body,
+                pattern,
+                handler,
+            } => {
+                self.visit_block(body);
+                self.in_scope(false, |this| {
+                    for name in pattern_names(pattern) {
+                        this.bind(name, Span::default(), Kind::Quiet);
+                    }
+                    this.visit_block(handler);
+                });
+            }
+            Expression::Match {
+                subject,
+                arms,
+                span,
+            } => {
+                self.visit_expression(subject);
+                for arm in arms {
+                    self.in_scope(false, |this| {
+                        for name in pattern_names(&arm.pattern) {
+                            this.bind(name, Span::default(), Kind::Quiet);
+                        }
+                        this.visit_block(&arm.body);
+                    });
+                }
+                self.check_exhaustive(arms, *span);
+            }
+            // Assigning to a binding isn't using it.
+            Expression::Assign {
+                target,
+                operator: None,
+                value,
+                ..
+            } if matches!(**target, Expression::Identifier { .. }) => self.visit_expression(value),
+            Expression::Infix {
+                left,
+                operator: InfixOperator::Eq | InfixOperator::NotEq,
+                right,
+                span,
+            } => {
+                if let (Expression::Bool(value), _) | (_, Expression::Bool(value)) =
+                    (&**left, &**right)
+                {
+                    self.warn(
+                        BOOL_COMPARISON,
+                        format!("comparing with `{value}` is redundant"),
+                        *span,
+                    );
+                }
+                walk_expression(self, expression);
+            }
+            expression => walk_expression(self, expression),
+        }
+    }
+}
+
+/// The names `pattern` binds.
+fn pattern_names(pattern: &Pattern) -> Vec<Symbol> {
+    match pattern {
+        Pattern::Identifier(name) => vec![name.clone()],
+        Pattern::Wildcard | Pattern::Literal(_) => Vec::new(),
+        Pattern::Array(patterns)
+        | Pattern::Tuple(patterns)
+        | Pattern::Variant {
+            fields: patterns, ..
+        } => patterns.iter().flat_map(pattern_names).collect(),
+        Pattern::Hash(entries)
+        | Pattern::Struct {
+            fields: entries, ..
+        } => entries
+            .iter()
+            .flat_map(|(_, pattern)| pattern_names(pattern))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::lint;
+
+    #[test]
+    fn test_lint() {
+        let source = "let top = 1
+let f = fn(a, b, _c) {
+    let unused = 1
+    let a = 2 // lint: allow(shadowed_variable)
+    if (a == true) { return a }
+    return b
+    println(\"never\")
+}
+let g = fn(n) {
+    // lint: allow(unused_variable)
+    let skipped = n
+    let h = fn() { later() }
+    let later = fn() { top }
+    let count = 0
+    count = 1
+    h()
+}
+for (x in [1, 2]) { break; continue }
+enum Shape { Circle(r), Rect(w, h), Dot }
+let area = fn(s) { match s { Circle(r) => r * r, Rect(w, Dot) => w } }
+let name = fn(s) { match s { Dot => \"dot\", other => \"shape\" } }
+";
+        let warnings: Vec<_> = lint(source)
+            .unwrap()
+            .into_iter()
+            .map(|warning| (warning.code, warning.message, warning.span.to_string()))
+            .collect();
+        let expected = [
+            ("unused_parameter", "parameter `a` is never used", "2:12"),
+            ("unused_variable", "`unused` is never used", "3:9"),
+            (
+                "bool_comparison",
+                "comparing with `true` is redundant",
+                "5:11",
+            ),
+            ("unreachable_code", "unreachable code after `return`", "7:5"),
+            ("unused_variable", "`count` is never used", "14:9"),
+            (
+                "unreachable_code",
+                "unreachable code after `break`",
+                "18:28",
+            ),
+            (
+                "non_exhaustive_match",
+                "`match` on `Shape` is missing `Rect`, `Dot`",
+                "20:20",
+            ),
+        ];
+        assert_eq!(
+            expected
+                .iter()
+                .map(|(code, message, span)| (*code, message.to_string(), span.to_string()))
+                .collect::<Vec<_>>(),
+            warnings
+        );
+
+        assert!(lint("let x = ").is_err());
+    }
+}
@@ -0,0 +1,1006 @@
+//! A Language Server Protocol server on stdin and stdout, for `tofu lsp`,
+//! which gives editors diagnostics, hover, go-to-definition and completion.
+//!
+//! A document is analyzed whenever something about it is asked: parsed,
+//! then walked with the scopes the evaluator would have, matching each name
+//! used to the binding it refers to, wherever in an enclosing scope that
+//! is. Names that nothing binds and that aren't builtins or in the prelude
+//! are reported as running the program would report them, along with parse
+//! errors and what [`crate::lint`] finds. A name bound by `import` leads to
+//! the module's file, found as the evaluator would find it, which is
+//! analyzed the same way to follow `module.name` into it.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value as Json};
+
+use crate::ast::{DeclarationKind, Expression, ImportSource, LetStatement, Pattern, Statement};
+use crate::builtins::Builtins;
+use crate::diagnostic::Severity;
+use crate::evaluator::PRELUDE;
+use crate::lexer::Lexer;
+use crate::lint::lint;
+use crate::parser::Parser;
+use crate::resolve::pattern_names;
+use crate::symbol::{Symbol, SymbolMap};
+use crate::token::{Span, Token, TokenKind};
+use crate::typecheck::{infer, Type};
+use crate::visit::{walk_expression, walk_statement, Visit};
+
+const KEYWORDS: &[&str] = &[
+    "fn", "let", "const", "if", "else", "true", "false", "null", "return", "match", "while", "for",
+    "in", "break", "continue", "try", "catch", "throw", "import", "struct", "enum",
+];
+
+/// Serves the client talking on `input` and `output` until it says to
+/// exit or hangs up. `builtins` are the names programs can use without
+/// binding them.
+pub fn run(mut input: impl BufRead, output: impl Write, builtins: &Builtins) -> io::Result<()> {
+    let mut server = Server::new(output, builtins);
+    while let Some(message) = read_message(&mut input)? {
+        if !server.handle(&message)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Reads a message's headers and the JSON after them, or `None` at the end
+/// of `input`. A body that isn't JSON reads as `null`.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "a message has no Content-Length",
+        ));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body).unwrap_or(Json::Null)))
+}
+
+struct Server<W> {
+    output: W,
+    /// The text of each open document, by URI.
+    documents: HashMap<String, String>,
+    builtins: Vec<String>,
+    prelude: Analysis,
+    /// The builtins' names and the prelude's, which programs can use
+    /// without binding them.
+    known: HashSet<String>,
+}
+
+impl<W: Write> Server<W> {
+    fn new(output: W, builtins: &Builtins) -> Server<W> {
+        let builtins: Vec<String> = builtins.names().map(str::to_string).collect();
+        let mut known: HashSet<String> = builtins.iter().cloned().collect();
+        let prelude = analyze(PRELUDE, &known);
+        known.extend(
+            prelude
+                .top_level()
+                .map(|definition| definition.name.to_string()),
+        );
+        Server {
+            output,
+            documents: HashMap::new(),
+            builtins,
+            prelude,
+            known,
+        }
+    }
+
+    /// Handles a request or notification, returning whether to carry on.
+    fn handle(&mut self, message: &Json) -> io::Result<bool> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": {"openClose": true, "change": 1},
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "completionProvider": {"triggerCharacters": ["."]},
+                },
+                "serverInfo": {"name": "tofu", "version": env!("CARGO_PKG_VERSION")},
+            }),
+            "shutdown" => Json::Null,
+            "exit" => return Ok(false),
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                // Changes are whole documents, as `initialize` asked for.
+                let text = match method {
+                    "textDocument/didOpen" => &params["textDocument"]["text"],
+                    _ => &params["contentChanges"][0]["text"],
+                };
+                self.documents.insert(
+                    uri.to_string(),
+                    text.as_str().unwrap_or_default().to_string(),
+                );
+                self.publish_diagnostics(uri)?;
+                return Ok(true);
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                self.publish_diagnostics(uri)?;
+                return Ok(true);
+            }
+            "textDocument/hover" => self.hover(uri, &params["position"]),
+            "textDocument/definition" => self.definition(uri, &params["position"]),
+            "textDocument/completion" => self.completion(uri, &params["position"]),
+            _ => {
+                // Notifications like `initialized` need no answer.
+                if let Some(id) = message.get("id") {
+                    self.send(json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": {"code": -32601, "message": format!("unknown method `{method}`")},
+                    }))?;
+                }
+                return Ok(true);
+            }
+        };
+        self.send(json!({"jsonrpc": "2.0", "id": message["id"], "result": result}))?;
+        Ok(true)
+    }
+
+    fn send(&mut self, message: Json) -> io::Result<()> {
+        let body = message.to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+        self.output.flush()
+    }
+
+    /// Sends the problems in the document at `uri`, or none once it's
+    /// closed.
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let diagnostics: Vec<Json> = match self.documents.get(uri) {
+            Some(text) => analyze(text, &self.known)
+                .problems
+                .iter()
+                .map(|problem| {
+                    json!({
+                        "range": range(text, problem.span),
+                        "severity": match problem.severity {
+                            Severity::Error => 1,
+                            Severity::Warning => 2,
+                        },
+                        "code": problem.code,
+                        "source": "tofu",
+                        "message": problem.message,
+                    })
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {"uri": uri, "diagnostics": diagnostics},
+        }))
+    }
+
+    fn hover(&self, uri: &str, position: &Json) -> Json {
+        let contents = match self.locate(uri, position) {
+            Some(Target::Binding { definition, .. }) => {
+                let mut contents = format!("```tofu\n{}\n", definition.signature);
+                if let Some(inferred) = &definition.inferred {
+                    writeln!(contents, "{}: {inferred}", definition.name)
+                        .expect("Should have written to a String.");
+                }
+                contents.push_str("```");
+                if !definition.docs.is_empty() {
+                    write!(contents, "\n\n{}", definition.docs.join("\n"))
+                        .expect("Should have written to a String.");
+                }
+                contents
+            }
+            Some(Target::Module(path)) => format!("module `{}`", path.display()),
+            Some(Target::Builtin(name)) => format!("```tofu\n{name}\n```\n\nA builtin."),
+            None => return Json::Null,
+        };
+        json!({"contents": {"kind": "markdown", "value": contents}})
+    }
+
+    fn definition(&self, uri: &str, position: &Json) -> Json {
+        match self.locate(uri, position) {
+            Some(Target::Binding {
+                location: Some(location),
+                ..
+            }) => location,
+            Some(Target::Module(path)) => json!({
+                "uri": path_to_uri(&path),
+                "range": range("", Span { line: 1, column: 1 }),
+            }),
+            _ => Json::Null,
+        }
+    }
+
+    fn completion(&self, uri: &str, position: &Json) -> Json {
+        let Some(text) = self.documents.get(uri) else {
+            return Json::Null;
+        };
+        let analysis = analyze(text, &self.known);
+        let at = span_at(text, position);
+        let line = text.lines().nth(at.line - 1).unwrap_or_default();
+        let before: String = line.chars().take(at.column - 1).collect();
+        let before = before.trim_end_matches(is_identifier_char);
+
+        // After `module.`, only what the module binds.
+        if let Some(receiver) = before.strip_suffix('.') {
+            let receiver = &receiver[receiver.trim_end_matches(is_identifier_char).len()..];
+            let module = analysis
+                .definitions
+                .iter()
+                .rev()
+                .find(|definition| definition.name == receiver)
+                .and_then(|definition| definition.import.as_ref())
+                .and_then(|source| find_module(uri_to_path(uri).as_deref(), source))
+                .and_then(|path| self.read(&path))
+                .map(|text| analyze(&text, &self.known));
+            let items: Vec<Json> = module
+                .iter()
+                .flat_map(Analysis::top_level)
+                .map(Definition::completion)
+                .collect();
+            return json!(items);
+        }
+
+        let mut seen = HashSet::new();
+        let mut items: Vec<Json> = KEYWORDS
+            .iter()
+            .map(|keyword| json!({"label": keyword, "kind": 14}))
+            .collect();
+        for definition in analysis.definitions.iter().chain(self.prelude.top_level()) {
+            if seen.insert(definition.name.as_str()) {
+                items.push(definition.completion());
+            }
+        }
+        for name in &self.builtins {
+            if seen.insert(name) {
+                items.push(json!({"label": name, "kind": 3, "detail": "builtin"}));
+            }
+        }
+        json!(items)
+    }
+
+    /// What the name at `position` in the document at `uri` refers to.
+    fn locate(&self, uri: &str, position: &Json) -> Option<Target> {
+        let text = self.documents.get(uri)?;
+        let path = uri_to_path(uri);
+        let analysis = analyze(text, &self.known);
+        let tokens = tokens(text);
+        let at = span_at(text, position);
+        let index = tokens.iter().position(|token| {
+            let width = token.literal_str().chars().count()
+                + if token.kind == TokenKind::Str { 2 } else { 0 };
+            token.span.line == at.line
+                && (token.span.column..=token.span.column + width).contains(&at.column)
+        })?;
+        let token = &tokens[index];
+        let previous = |n: usize| index.checked_sub(n).map(|index| &tokens[index]);
+
+        // The module an `import` names.
+        if let Some(import) = previous(1).filter(|token| token.kind == TokenKind::Import) {
+            let source = analysis
+                .definitions
+                .iter()
+                .find(|definition| definition.span == Some(import.span))?
+                .import
+                .as_ref()?;
+            return find_module(path.as_deref(), source).map(Target::Module);
+        }
+        if token.kind != TokenKind::Identifier {
+            return None;
+        }
+        let name = token.literal_str();
+
+        // A name a module binds, after the name it's imported as.
+        if previous(1)
+            .is_some_and(|token| matches!(token.kind, TokenKind::Dot | TokenKind::QuestionDot))
+        {
+            let receiver = previous(2)?;
+            let source = analysis.reference(receiver.span)?.import.as_ref()?;
+            let module = find_module(path.as_deref(), source)?;
+            let module_text = self.read(&module)?;
+            let definition = analyze(&module_text, &self.known)
+                .top_level()
+                .find(|definition| definition.name == name)?
+                .clone();
+            return Some(Target::Binding {
+                location: definition.span.map(
+                    |span| json!({"uri": path_to_uri(&module), "range": range(&module_text, span)}),
+                ),
+                definition,
+            });
+        }
+
+        if let Some(definition) = analysis.reference(token.span) {
+            return Some(Target::Binding {
+                location: definition
+                    .span
+                    .map(|span| json!({"uri": uri, "range": range(text, span)})),
+                definition: definition.clone(),
+            });
+        }
+        if let Some(definition) = self
+            .prelude
+            .top_level()
+            .find(|definition| definition.name == name)
+        {
+            return Some(Target::Binding {
+                location: None,
+                definition: definition.clone(),
+            });
+        }
+        self.builtins
+            .iter()
+            .any(|builtin| builtin == name)
+            .then(|| Target::Builtin(name.to_string()))
+    }
+
+    /// The text of the file at `path`, as the client has it if it's open.
+    fn read(&self, path: &Path) -> Option<String> {
+        match self.documents.get(&path_to_uri(path)) {
+            Some(text) => Some(text.clone()),
+            None => std::fs::read_to_string(path).ok(),
+        }
+    }
+}
+
+/// What a name refers to.
+enum Target {
+    /// A binding in Tofu code, with where it is, unless that's in the
+    /// prelude or isn't known.
+    Binding {
+        location: Option<Json>,
+        definition: Definition,
+    },
+    /// A module's file.
+    Module(PathBuf),
+    Builtin(String),
+}
+
+/// What's known about a document without running it.
+#[derive(Default)]
+struct Analysis {
+    /// Every binding, in the order they were reached.
+    definitions: Vec<Definition>,
+    /// Where each name used or bound is, with the index of the binding in
+    /// `definitions` it refers to.
+    references: Vec<(Span, usize)>,
+    problems: Vec<Problem>,
+}
+
+impl Analysis {
+    /// The binding the name at `span` refers to.
+    fn reference(&self, span: Span) -> Option<&Definition> {
+        self.references
+            .iter()
+            .find(|(reference, _)| *reference == span)
+            .map(|&(_, index)| &self.definitions[index])
+    }
+
+    /// The bindings at the top level, which an importer can use.
+    fn top_level(&self) -> impl Iterator<Item = &Definition> {
+        self.definitions
+            .iter()
+            .filter(|definition| definition.top_level)
+    }
+}
+
+#[derive(Clone)]
+struct Definition {
+    name: Symbol,
+    kind: DefinitionKind,
+    /// Where the name is bound, if the syntax tree records it, which it
+    /// doesn't for the names in a `for` or `catch` pattern, or nested in a
+    /// destructuring `let`.
+    span: Option<Span>,
+    /// How it's bound, like `let add = fn(a, b)`, for hover.
+    signature: String,
+    /// The type [`infer`] found for it, if it found one.
+    inferred: Option<Type>,
+    docs: Vec<String>,
+    top_level: bool,
+    /// The module it's bound to, for an import.
+    import: Option<ImportSource>,
+}
+
+impl Definition {
+    fn completion(&self) -> Json {
+        let kind = match self.kind {
+            DefinitionKind::Function => 3,
+            DefinitionKind::Variable => 6,
+            DefinitionKind::Module => 9,
+            DefinitionKind::Struct => 22,
+            DefinitionKind::Variant => 20,
+        };
+        json!({"label": self.name.as_str(), "kind": kind, "detail": self.signature})
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum DefinitionKind {
+    Function,
+    Variable,
+    Module,
+    Struct,
+    Variant,
+}
+
+struct Problem {
+    span: Span,
+    severity: Severity,
+    code: Option<&'static str>,
+    message: String,
+}
+
+/// Parses and analyzes `source`, where the names in `known` are bound
+/// without it binding them.
+fn analyze(source: &str, known: &HashSet<String>) -> Analysis {
+    let mut lexer = Lexer::new(source);
+    lexer.set_newline_terminators(true);
+    let mut parser = Parser::new(lexer);
+    let program = parser.parse_program();
+
+    let mut analyzer = Analyzer {
+        known,
+        scopes: Vec::new(),
+        analysis: Analysis::default(),
+    };
+    analyzer.statements(&program.statements, Vec::new());
+    let mut analysis = analyzer.analysis;
+
+    // The builtins' arities only matter to errors, which aren't reported
+    // here.
+    for (name, span, inferred) in infer(&program, &Builtins::new()).bindings {
+        let definition = analysis
+            .definitions
+            .iter_mut()
+            .find(|definition| definition.span == Some(span) && definition.name == name);
+        if let (Some(definition), false) = (definition, inferred == Type::Any) {
+            definition.inferred = Some(inferred);
+        }
+    }
+
+    if !parser.errors().is_empty() {
+        // The names a program that didn't parse seems to leave unbound
+        // are more likely to be the parser's mistake than the program's.
+        analysis.problems = parser
+            .errors()
+            .iter()
+            .map(|error| Problem {
+                span: error.span,
+                severity: Severity::Error,
+                code: None,
+                message: error.message.clone(),
+            })
+            .collect();
+    } else if let Ok(warnings) = lint(source) {
+        analysis
+            .problems
+            .extend(warnings.into_iter().map(|warning| Problem {
+                span: warning.span,
+                severity: Severity::Warning,
+                code: Some(warning.code),
+                message: warning.message,
+            }));
+    }
+    analysis
+        .problems
+        .sort_by_key(|problem| (problem.span.line, problem.span.column));
+    analysis
+}
+
+struct Analyzer<'k> {
+    known: &'k HashSet<String>,
+    scopes: Vec<Scope>,
+    analysis: Analysis,
+}
+
+#[derive(Default)]
+struct Scope {
+    /// The bindings made so far, in order.
+    bound: Vec<usize>,
+    /// The first binding of each name the scope's statements make, for
+    /// names used before they're bound, like a function calling one
+    /// defined after it.
+    later: SymbolMap<usize>,
+}
+
+impl Analyzer<'_> {
+    fn define(
+        &mut self,
+        name: &Symbol,
+        kind: DefinitionKind,
+        span: Option<Span>,
+        signature: String,
+    ) -> usize {
+        let index = self.analysis.definitions.len();
+        if let (Some(span), false) = (span, kind == DefinitionKind::Module) {
+            self.analysis.references.push((span, index));
+        }
+        self.analysis.definitions.push(Definition {
+            name: name.clone(),
+            kind,
+            span,
+            signature,
+            inferred: None,
+            docs: Vec::new(),
+            top_level: false,
+            import: None,
+        });
+        index
+    }
+
+    /// Defines the names `pattern` binds, as `what` binds them.
+    fn define_pattern(&mut self, pattern: &Pattern, what: &str) -> Vec<usize> {
+        pattern_names(pattern)
+            .iter()
+            .map(|name| {
+                self.define(
+                    name,
+                    DefinitionKind::Variable,
+                    None,
+                    format!("{what} {name}"),
+                )
+            })
+            .collect()
+    }
+
+    fn define_let(&mut self, statement: &LetStatement) -> Vec<usize> {
+        let keyword = match statement.kind {
+            DeclarationKind::Let => "let",
+            DeclarationKind::Const => "const",
+        };
+        let indexes = match (&statement.pattern, &statement.value) {
+            (Pattern::Identifier(name), Expression::Function { parameters, .. }) => vec![self
+                .define(
+                    name,
+                    DefinitionKind::Function,
+                    Some(statement.span),
+                    format!("{keyword} {name} = fn({parameters})"),
+                )],
+            (Pattern::Identifier(name), _) => vec![self.define(
+                name,
+                DefinitionKind::Variable,
+                Some(statement.span),
+                format!("{keyword} {name}"),
+            )],
+            (pattern, _) => self.define_pattern(pattern, keyword),
+        };
+        for &index in &indexes {
+            self.analysis.definitions[index].docs = statement.docs.clone();
+        }
+        indexes
+    }
+
+    fn bind(&mut self, index: usize) {
+        self.scopes
+            .last_mut()
+            .expect("Should be in a scope.")
+            .bound
+            .push(index);
+    }
+
+    /// The binding `name` refers to: the latest one made in the nearest
+    /// scope around it that's made one, or failing that, one made later.
+    fn lookup(&self, name: &Symbol) -> Option<usize> {
+        let definitions = &self.analysis.definitions;
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| {
+                scope
+                    .bound
+                    .iter()
+                    .rev()
+                    .copied()
+                    .find(|&index| definitions[index].name == *name)
+            })
+            .or_else(|| {
+                self.scopes
+                    .iter()
+                    .rev()
+                    .find_map(|scope| scope.later.get(name).copied())
+            })
+    }
+
+    /// Analyzes `statements` in a scope of their own, which starts with
+    /// `bindings`.
+    fn statements(&mut self, statements: &[Statement], bindings: Vec<usize>) {
+        self.scopes.push(Scope {
+            bound: bindings,
+            later: SymbolMap::default(),
+        });
+        let top_level = self.scopes.len() == 1;
+        let mut declared = Vec::new();
+        for statement in statements {
+            let indexes = match statement {
+                Statement::Let(statement) => self.define_let(statement),
+                Statement::Import(import) => {
+                    let signature = import.to_string().trim_end_matches(';').to_string();
+                    let index = self.define(
+                        &import.name,
+                        DefinitionKind::Module,
+                        Some(import.span),
+                        signature,
+                    );
+                    self.analysis.definitions[index].import = Some(import.source.clone());
+                    vec![index]
+                }
+                Statement::Struct { name, span, .. } => vec![self.define(
+                    name,
+                    DefinitionKind::Struct,
+                    Some(*span),
+                    statement.to_string(),
+                )],
+                Statement::Enum {
+                    name,
+                    variants,
+                    span,
+                } => variants
+                    .iter()
+                    .map(|variant| {
+                        self.define(
+                            &variant.name,
+                            DefinitionKind::Variant,
+                            Some(*span),
+                            format!("variant {variant} of {name}"),
+                        )
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            };
+            for &index in &indexes {
+                let definition = &mut self.analysis.definitions[index];
+                definition.top_level = top_level;
+                let name = definition.name.clone();
+                let scope = self.scopes.last_mut().expect("Should be in a scope.");
+                scope.later.entry(name).or_insert(index);
+            }
+            declared.push(indexes);
+        }
+
+        for (statement, indexes) in statements.iter().zip(declared) {
+            self.visit_statement(statement);
+            for index in indexes {
+                self.bind(index);
+            }
+        }
+        self.scopes.pop();
+    }
+}
+
+impl Visit for Analyzer<'_> {
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            // Already defined by `statements`, to be bound after this.
+            Statement::Let(statement) => self.visit_expression(&statement.value),
+            Statement::Import(_) => {}
+            Statement::For {
+                pattern,
+                iterable,
+                body,
+            } => {
+                self.visit_expression(iterable);
+                let bindings = self.define_pattern(pattern, "for");
+                self.statements(&body.statements, bindings);
+            }
+            statement => walk_statement(self, statement),
+        }
+    }
+
+    fn visit_block(&mut self, block: &crate::ast::BlockStatement) {
+        self.statements(&block.statements, Vec::new());
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Identifier { name, span, .. } => match self.lookup(name) {
+                Some(index) => self.analysis.references.push((*span, index)),
+                None if !self.known.contains(name.as_str()) => {
+                    self.analysis.problems.push(Problem {
+                        span: *span,
+                        severity: Severity::Error,
+                        code: None,
+                        message: format!("identifier not found: {name}"),
+                    })
+                }
+                None => {}
+            },
+            Expression::Function {
+                parameters, body, ..
+            } => {
+                self.scopes.push(Scope::default());
+                for parameter in &parameters.positional {
+                    if let Some(default) = &parameter.default {
+                        self.visit_expression(default);
+                    }
+                    let index = self.define(
+                        &parameter.name,
+                        DefinitionKind::Variable,
+                        Some(parameter.span),
+                        format!("parameter {}", parameter.name),
+                    );
+                    self.bind(index);
+                }
+                if let Some(rest) = &parameters.rest {
+                    let index = self.define(
+                        rest,
+                        DefinitionKind::Variable,
+                        None,
+                        format!("parameter ...{rest}"),
+                    );
+                    self.bind(index);
+                }
+                self.visit_block(body);
+                self.scopes.pop();
+            }
+            Expression::Try {
+                body,
+                pattern,
+                handler,
+            } => {
+                self.visit_block(body);
+                let bindings = self.define_pattern(pattern, "catch");
+                self.statements(&handler.statements, bindings);
+            }
+            Expression::Match { subject, arms, .. } => {
+                self.visit_expression(subject);
+                for arm in arms {
+                    let bindings = self.define_pattern(&arm.pattern, "match");
+                    self.statements(&arm.body.statements, bindings);
+                }
+            }
+            expression => walk_expression(self, expression),
+        }
+    }
+}
+
+/// The tokens in `source`, without doc comments.
+fn tokens(source: &str) -> Vec<Token<'_>> {
+    let mut lexer = Lexer::new(source);
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.next_token();
+        match token.kind {
+            TokenKind::Eof => return tokens,
+            TokenKind::DocComment => {}
+            _ => tokens.push(token),
+        }
+    }
+}
+
+/// The file an import of `source` from the document at `document` runs,
+/// looked for where the evaluator looks: next to the document, then along
+/// `TOFU_PATH`.
+fn find_module(document: Option<&Path>, source: &ImportSource) -> Option<PathBuf> {
+    let file = source.file();
+    let beside_document = document
+        .and_then(Path::parent)
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let module_path: Vec<PathBuf> = std::env::var_os("TOFU_PATH")
+        .map(|module_path| std::env::split_paths(&module_path).collect())
+        .unwrap_or_default();
+    std::iter::once(beside_document)
+        .chain(module_path)
+        .map(|directory| directory.join(&file))
+        .find(|candidate| candidate.is_file())
+        .and_then(|found| found.canonicalize().ok())
+}
+
+fn is_identifier_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+/// The LSP position of `span` in `source`, which counts from 0, and counts
+/// UTF-16 code units along the line rather than characters.
+fn position(source: &str, span: Span) -> Json {
+    let line = source.lines().nth(span.line - 1).unwrap_or_default();
+    let character: usize = line
+        .chars()
+        .take(span.column - 1)
+        .map(char::len_utf16)
+        .sum();
+    json!({"line": span.line - 1, "character": character})
+}
+
+/// The LSP range of the word at `span` in `source`, or of the character
+/// there if it isn't in a word.
+fn range(source: &str, span: Span) -> Json {
+    let line = source.lines().nth(span.line - 1).unwrap_or_default();
+    let width = line
+        .chars()
+        .skip(span.column - 1)
+        .take_while(|&ch| is_identifier_char(ch))
+        .count()
+        .max(1);
+    let end = Span {
+        line: span.line,
+        column: span.column + width,
+    };
+    json!({"start": position(source, span), "end": position(source, end)})
+}
+
+/// The span an LSP position points to in `source`.
+fn span_at(source: &str, position: &Json) -> Span {
+    let line = position["line"].as_u64().unwrap_or_default() as usize;
+    let character = position["character"].as_u64().unwrap_or_default() as usize;
+    let mut units = 0;
+    let column = source
+        .lines()
+        .nth(line)
+        .unwrap_or_default()
+        .chars()
+        .take_while(|ch| {
+            units += ch.len_utf16();
+            units <= character
+        })
+        .count();
+    Span {
+        line: line + 1,
+        column: column + 1,
+    }
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?.as_bytes();
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut index = 0;
+    while index < path.len() {
+        let escaped = path
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (path[index], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok().map(PathBuf::from)
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            write!(uri, "%{byte:02X}").expect("Should have written to a String.");
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{json, Value as Json};
+
+    use super::{path_to_uri, run};
+    use crate::builtins::Builtins;
+
+    fn message(body: Json) -> String {
+        let body = body.to_string();
+        format!("Content-Length: {}\r\n\r\n{body}", body.len())
+    }
+
+    #[test]
+    fn test_lsp() {
+        let dir = std::env::temp_dir().join(format!("tofu-lsp-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let utils = dir.join("utils.tofu");
+        std::fs::write(&utils, "/// Adds one.\nlet inc = fn(x) { x + 1 }\n").unwrap();
+        let utils = path_to_uri(&utils.canonicalize().unwrap());
+        let main = path_to_uri(&dir.canonicalize().unwrap().join("main.tofu"));
+
+        let source =
+            "import utils\n/// The answer.\nlet answer = utils.inc(41)\nprintln(answer, missing)\nlet shout = fn(s) { s + \"!\" }\n";
+        let at = |id: u64, method: &str, line: u64, character: u64| {
+            message(json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": method,
+                "params": {
+                    "textDocument": {"uri": main},
+                    "position": {"line": line, "character": character},
+                },
+            }))
+        };
+        let input = [
+            message(json!({"jsonrpc": "2.0", "id": 0, "method": "initialize", "params": {}})),
+            message(json!({"jsonrpc": "2.0", "method": "initialized", "params": {}})),
+            message(json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": {"textDocument": {"uri": main, "text": source}},
+            })),
+            at(1, "textDocument/hover", 3, 10),
+            at(2, "textDocument/definition", 3, 10),
+            at(3, "textDocument/definition", 2, 20),
+            at(4, "textDocument/completion", 2, 19),
+            at(5, "textDocument/hover", 4, 16),
+            message(json!({"jsonrpc": "2.0", "id": 6, "method": "shutdown"})),
+            message(json!({"jsonrpc": "2.0", "method": "exit"})),
+        ]
+        .concat();
+
+        let mut output = Vec::new();
+        run(input.as_bytes(), &mut output, &Builtins::standard()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let messages: Vec<Json> = output
+            .split("Content-Length: ")
+            .skip(1)
+            .map(|message| serde_json::from_str(message.split_once("\r\n\r\n").unwrap().1).unwrap())
+            .collect();
+        assert_eq!(
+            json!(true),
+            messages[0]["result"]["capabilities"]["hoverProvider"]
+        );
+
+        let diagnostics = &messages[1]["params"]["diagnostics"];
+        assert_eq!(1, diagnostics.as_array().unwrap().len());
+        assert_eq!("identifier not found: missing", diagnostics[0]["message"]);
+        assert_eq!(
+            json!({"start": {"line": 3, "character": 16}, "end": {"line": 3, "character": 23}}),
+            diagnostics[0]["range"]
+        );
+
+        // `answer`, bound two lines up.
+        assert_eq!(
+            "```tofu\nlet answer\n```\n\nThe answer.",
+            messages[2]["result"]["contents"]["value"]
+        );
+        assert_eq!(
+            json!({
+                "uri": main,
+                "range": {"start": {"line": 2, "character": 4}, "end": {"line": 2, "character": 10}},
+            }),
+            messages[3]["result"]
+        );
+
+        // `inc`, in the imported module.
+        assert_eq!(
+            json!({
+                "uri": utils,
+                "range": {"start": {"line": 1, "character": 4}, "end": {"line": 1, "character": 7}},
+            }),
+            messages[4]["result"]
+        );
+        assert_eq!(
+            json!([{"label": "inc", "kind": 3, "detail": "let inc = fn(x)"}]),
+            messages[5]["result"]
+        );
+        // `s`, whose type is inferred from what's added to it.
+        assert_eq!(
+            "```tofu\nparameter s\ns: str\n```",
+            messages[6]["result"]["contents"]["value"]
+        );
+        assert_eq!(Json::Null, messages[7]["result"]);
+    }
+}
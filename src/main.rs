@@ -1,12 +1,904 @@
-use std::io;
+//! The `tofu_interpreter` command: the REPL, and running, checking and
+//! formatting scripts, over the [`tofu_interpreter`] library.
 
-use repl::start;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant, SystemTime};
 
-pub mod token;
-pub mod lexer;
-pub mod repl;
+use tofu_interpreter::ast::{Pattern, Program, Statement};
+use tofu_interpreter::code::Bytecode;
+use tofu_interpreter::debugger::Debugger;
+use tofu_interpreter::diagnostic::{Diagnostic, Severity, Sources};
+use tofu_interpreter::evaluator::{ErrorKind, Evaluator, Overflow, RuntimeError, STACK_PER_CALL};
+use tofu_interpreter::format::FormatError;
+use tofu_interpreter::lexer::Lexer;
+use tofu_interpreter::parser::{ParseError, Parser};
+use tofu_interpreter::repl::{start_terminal, Engine, Options};
+use tofu_interpreter::token::TokenKind;
+use tofu_interpreter::value::Value;
+use tofu_interpreter::{
+    compiler, config, format, heap, lint, lsp, peephole, resolve, sexp, typecheck, vm, TofuError,
+};
 
-fn main() {
-    println!("Welcome to the Tofu interpreter.");
-    start(io::stdin(), io::stdout());
+const USAGE: &str = "usage: tofu_interpreter [--fold-constants] [-O] [--wrap-overflow] \
+                     [--max-depth=<calls>] [--fuel=<steps>] \
+                     [--max-memory=<bytes>] [--sandbox] [--no-prelude] [--profile] [--trace] [--no-color] \
+                     [--engine=tree|vm] [--plugin=<path>]... \
+                     [-e <source> | --emit=tokens|source|ast|sexp|bytecode <file> | \
+                     --lex-only <file> | --parse-only <file> | --check <file> | \
+                     build <file> [-o <output>] | debug <file> | check [--types] <file>... | lint <file>... | fmt [--check] <file>... | lsp | test [<path>...] | run --watch <file> | [run] <file>]";
+
+/// Counts what each thread allocates, for `--max-memory`.
+#[global_allocator]
+static ALLOCATOR: heap::CountingAllocator = heap::CountingAllocator;
+
+/// The least stack the REPL thread gets, whatever the recursion limit.
+const MIN_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// The most stack a thread running the VM gets, however high the
+/// recursion limit.
+const MAX_VM_STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Loads the plugins in `~/.tofu/plugins`, then those in `paths`, for as
+/// long as the process runs.
+#[cfg(feature = "plugins")]
+fn load_plugins(
+    paths: &[PathBuf],
+) -> Result<&'static [&'static dyn tofu_interpreter::plugin::Plugin], String> {
+    use tofu_interpreter::plugin;
+
+    let mut plugins = match std::env::var_os("HOME") {
+        Some(home) => plugin::load_dir(&PathBuf::from(home).join(".tofu/plugins"))
+            .map_err(|error| error.to_string())?,
+        None => Vec::new(),
+    };
+    for path in paths {
+        plugins.push(plugin::load(path).map_err(|error| error.to_string())?);
+    }
+    Ok(plugins.leak())
+}
+
+#[cfg(not(feature = "plugins"))]
+fn load_plugins(
+    paths: &[PathBuf],
+) -> Result<&'static [&'static dyn tofu_interpreter::plugin::Plugin], String> {
+    if paths.is_empty() {
+        Ok(&[])
+    } else {
+        Err(format!("--plugin needs the `plugins` feature\n{USAGE}"))
+    }
+}
+
+/// What to print instead of starting the REPL.
+enum Emit {
+    /// The tokens the lexer makes of the source, one per line.
+    Tokens,
+    /// The parsed program printed back out as source.
+    Source,
+    /// The parsed AST as JSON.
+    Ast,
+    /// The parsed AST as s-expressions.
+    Sexp,
+    /// A disassembly of the compiled program.
+    Bytecode,
+}
+
+fn main() -> ExitCode {
+    let mut options = Options::default();
+    config::load(&mut options);
+    let mut args = Vec::new();
+    let mut plugins = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--fold-constants" => options.fold_constants = true,
+            "-O" => options.optimize = true,
+            "--wrap-overflow" => options.overflow = Overflow::Wrap,
+            "--sandbox" => {
+                options.allow_files = false;
+                options.allow_env = false;
+                options.allow_exec = false;
+                options.allow_net = false;
+            }
+            "--no-prelude" => options.prelude = false,
+            "--profile" => options.profile = true,
+            "--trace" => options.trace = true,
+            "--no-color" => options.color = false,
+            _ if arg.starts_with("--max-depth=") => match arg["--max-depth=".len()..].parse() {
+                Ok(max_depth) => options.max_depth = max_depth,
+                Err(_) => {
+                    eprintln!("--max-depth expects a number of calls\n{USAGE}");
+                    return ExitCode::from(2);
+                }
+            },
+            _ if arg.starts_with("--max-memory=") => match arg["--max-memory=".len()..].parse() {
+                Ok(max_memory) => options.max_memory = Some(max_memory),
+                Err(_) => {
+                    eprintln!("--max-memory expects a number of bytes\n{USAGE}");
+                    return ExitCode::from(2);
+                }
+            },
+            _ if arg.starts_with("--engine=") => match arg["--engine=".len()..].parse() {
+                Ok(engine) => options.engine = engine,
+                Err(()) => {
+                    eprintln!("--engine expects `tree` or `vm`\n{USAGE}");
+                    return ExitCode::from(2);
+                }
+            },
+            _ if arg.starts_with("--plugin=") => {
+                plugins.push(PathBuf::from(&arg["--plugin=".len()..]))
+            }
+            _ if arg.starts_with("--fuel=") => match arg["--fuel=".len()..].parse() {
+                Ok(fuel) => options.fuel = Some(fuel),
+                Err(_) => {
+                    eprintln!("--fuel expects a number of steps\n{USAGE}");
+                    return ExitCode::from(2);
+                }
+            },
+            _ => args.push(arg),
+        }
+    }
+    match load_plugins(&plugins) {
+        Ok(loaded) => options.plugins = loaded,
+        Err(message) => {
+            eprintln!("{message}");
+            return ExitCode::from(2);
+        }
+    }
+
+    match args.as_slice() {
+        // Piped input is a whole program rather than lines typed one by one.
+        [] if !io::stdin().is_terminal() => {
+            let mut source = String::new();
+            if let Err(error) = io::stdin().read_to_string(&mut source) {
+                eprintln!("could not read stdin: {error}");
+                return ExitCode::FAILURE;
+            }
+            on_big_stack(options, move || eval_source("<stdin>", &source, options))
+        }
+        [] => on_big_stack(options, move || {
+            finish(
+                writeln!(io::stdout(), "Welcome to the Tofu interpreter.")
+                    .map_err(TofuError::from)
+                    .and_then(|()| start_terminal(options))
+                    .map(|()| ExitCode::SUCCESS),
+            )
+        }),
+        [flag, path] if flag.starts_with("--emit=") => {
+            let emit = match &flag["--emit=".len()..] {
+                "tokens" => Emit::Tokens,
+                "source" => Emit::Source,
+                "ast" => Emit::Ast,
+                "sexp" => Emit::Sexp,
+                "bytecode" => Emit::Bytecode,
+                other => {
+                    eprintln!("unknown --emit format `{other}`\n{USAGE}");
+                    return ExitCode::from(2);
+                }
+            };
+            finish(emit_file(emit, path, options))
+        }
+        [flag, path] if flag == "--lex-only" => finish(emit_file(Emit::Tokens, path, options)),
+        [flag, path] if flag == "--parse-only" => finish(emit_file(Emit::Source, path, options)),
+        [flag, path] if flag == "--check" => {
+            let path = path.clone();
+            on_big_stack(options, move || check_file(&path, options))
+        }
+        [command, path, flag, output] if command == "build" && flag == "-o" => {
+            build_file(path, Path::new(output), options)
+        }
+        [command, paths @ ..] if command == "test" => {
+            let paths = paths.to_vec();
+            on_big_stack(options, move || finish(test_files(&paths, options)))
+        }
+        [command, flag, paths @ ..]
+            if command == "check" && flag == "--types" && !paths.is_empty() =>
+        {
+            validate_files(paths, true, options)
+        }
+        [command, paths @ ..] if command == "check" && !paths.is_empty() => {
+            validate_files(paths, false, options)
+        }
+        [command, paths @ ..] if command == "lint" && !paths.is_empty() => {
+            lint_files(paths, options)
+        }
+        [command, paths @ ..] if command == "fmt" => match paths {
+            [flag, paths @ ..] if flag == "--check" && !paths.is_empty() => {
+                finish(format_files(paths, true, options))
+            }
+            [_, ..] => finish(format_files(paths, false, options)),
+            [] => {
+                eprintln!("{USAGE}");
+                ExitCode::from(2)
+            }
+        },
+        [command] if command == "lsp" => {
+            match lsp::run(io::stdin().lock(), io::stdout().lock(), &options.builtins()) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(error) => {
+                    eprintln!("lsp: {error}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        [command, path] if command == "build" => {
+            build_file(path, &Path::new(path).with_extension("tofuc"), options)
+        }
+        [flag, source] if flag == "-e" || flag == "--eval" => {
+            let source = source.clone();
+            on_big_stack(options, move || eval_source("-e", &source, options))
+        }
+        [command, path] if command == "debug" => {
+            let path = path.clone();
+            on_big_stack(
+                Options {
+                    engine: Engine::Tree,
+                    ..options
+                },
+                move || debug_file(&path, options),
+            )
+        }
+        [command, flag, path] if command == "run" && flag == "--watch" => {
+            let path = path.clone();
+            on_big_stack(options, move || watch_file(&path, options))
+        }
+        [command, path] if command == "run" => {
+            let path = path.clone();
+            on_big_stack(options, move || run_file(&path, options))
+        }
+        [path] if !path.starts_with('-') => {
+            let path = path.clone();
+            on_big_stack(options, move || run_file(&path, options))
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// The status to exit with once a command has finished with `result`,
+/// having said why it failed, if it did. Output stopping being read, as it
+/// does in `tofu ... | head`, ends the command early, but isn't a failure.
+fn finish(result: Result<ExitCode, TofuError>) -> ExitCode {
+    match result {
+        Ok(code) => code,
+        Err(error) if error.is_broken_pipe() => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs `run` on a thread with enough stack for the recursion limit,
+/// since the evaluator recurses on the Rust stack. The VM keeps calls
+/// between compiled functions off it, so only builtins calling back into
+/// them use it, and its stack is capped rather than sized for any limit.
+fn on_big_stack(options: Options, run: impl FnOnce() -> ExitCode + Send + 'static) -> ExitCode {
+    let stack_size = options.max_depth.saturating_mul(STACK_PER_CALL);
+    let stack_size = match options.engine {
+        Engine::Tree => stack_size,
+        Engine::Vm => stack_size.min(MAX_VM_STACK_SIZE),
+    }
+    .max(MIN_STACK_SIZE);
+
+    let thread = std::thread::Builder::new()
+        .stack_size(stack_size)
+        .spawn(run);
+
+    match thread.map(|thread| thread.join()) {
+        Ok(Ok(code)) => code,
+        // The panic has already been reported.
+        Ok(Err(_)) => ExitCode::FAILURE,
+        Err(error) => {
+            eprintln!("could not start the interpreter: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Parses the file at `path`, folding constants if `--fold-constants` was
+/// given, or reports why it couldn't. Returns the source with the program,
+/// for reporting errors in it later.
+fn parse_file(path: &str, options: Options) -> Option<(Program, Sources)> {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("could not read {path}: {error}");
+            return None;
+        }
+    };
+
+    let sources = Sources::single(path, &source);
+    let program = parse_source(&source, &sources, options)?;
+    Some((program, sources))
+}
+
+/// Parses `source`, folding constants if `--fold-constants` was given, or
+/// reports why it couldn't, pointing into `sources`.
+fn parse_source(source: &str, sources: &Sources, options: Options) -> Option<Program> {
+    let mut lexer = Lexer::new(source);
+    lexer.set_newline_terminators(true);
+
+    let mut parser = Parser::new(lexer);
+    let mut program = parser.parse_program();
+
+    if !parser.errors().is_empty() {
+        for error in parser.errors() {
+            report_error(error.into(), sources, options);
+        }
+        return None;
+    }
+
+    if options.fold_constants {
+        program.fold_constants();
+    }
+
+    Some(program)
+}
+
+/// Prints `diagnostic` to stderr, in color unless `--no-color` was given
+/// or stderr isn't a terminal.
+fn report_error(diagnostic: Diagnostic, sources: &Sources, options: Options) {
+    let color = options.color && io::stderr().is_terminal();
+    eprint!("{}", diagnostic.render(sources, color));
+}
+
+/// Runs the script at `path`, carrying on past failed assertions, then
+/// reports every failure and how many assertions held. Fails if any
+/// assertion did or the script stopped with an error.
+fn check_file(path: &str, options: Options) -> ExitCode {
+    let Some((program, sources)) = parse_file(path, options) else {
+        return ExitCode::FAILURE;
+    };
+
+    let mut evaluator = options.evaluator();
+    evaluator.set_source_path(path);
+    evaluator.set_collect_assertions(true);
+    let result = evaluator.eval_program(&program);
+    let assertions = evaluator.assertions();
+
+    for failure in &assertions.failed {
+        report_error(failure.into(), &sources, options);
+    }
+    let code = report_result(result, &sources, options);
+
+    let failed = assertions.failed.len();
+    let passed = assertions.checked - failed;
+    eprintln!("assertions: {passed} passed, {failed} failed");
+    report_profile(&evaluator);
+
+    if failed > 0 && code == ExitCode::SUCCESS {
+        ExitCode::FAILURE
+    } else {
+        code
+    }
+}
+
+/// Runs the script at `path`, which is compiled bytecode if it ends in
+/// `.tofuc` and source code otherwise, and reports the error it stopped
+/// with, if any.
+fn run_file(path: &str, options: Options) -> ExitCode {
+    run_file_in(&mut options.evaluator(), path, options)
+}
+
+/// Runs the script at `path` as [`run_file`] does, in `evaluator`.
+fn run_file_in(evaluator: &mut Evaluator, path: &str, options: Options) -> ExitCode {
+    evaluator.set_source_path(path);
+
+    let (result, sources) = if path.ends_with(".tofuc") {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(error) => {
+                eprintln!("could not read {path}: {error}");
+                return ExitCode::FAILURE;
+            }
+        };
+        let bytecode = match Bytecode::decode(&bytes) {
+            Ok(bytecode) => bytecode,
+            Err(error) => {
+                eprintln!("{path}: {error}");
+                return ExitCode::FAILURE;
+            }
+        };
+        // The source isn't at hand, so errors only say where they were.
+        (vm::run(evaluator, &bytecode), Sources::new(path))
+    } else {
+        let Some((program, sources)) = parse_file(path, options) else {
+            return ExitCode::FAILURE;
+        };
+        let Some(result) = execute(evaluator, &program, &sources, options) else {
+            return ExitCode::FAILURE;
+        };
+        (result, sources)
+    };
+    report_profile(evaluator);
+    report_result(result, &sources, options)
+}
+
+/// Runs the script at `path` in the tree-walking evaluator under the
+/// [`Debugger`], which pauses at its first statement.
+fn debug_file(path: &str, options: Options) -> ExitCode {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("could not read {path}: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let sources = Sources::single(path, &source);
+
+    let mut lexer = Lexer::new(&source);
+    lexer.set_newline_terminators(true);
+    let mut parser = Parser::new(lexer);
+    let program = parser.parse_program();
+    if !parser.errors().is_empty() {
+        for error in parser.errors() {
+            report_error(error.into(), &sources, options);
+        }
+        return ExitCode::FAILURE;
+    }
+
+    let mut evaluator = options.evaluator();
+    evaluator.set_source_path(path);
+    evaluator.set_debugger(Some(Debugger::new(path, &source, &program, &parser)));
+    let result = evaluator.eval_program(&program);
+    report_result(result, &sources, options)
+}
+
+/// Runs the script at `path` with [`run_file`], then again each time it or
+/// a module it imported changes, clearing the screen first, until the
+/// process is stopped.
+fn watch_file(path: &str, options: Options) -> ExitCode {
+    /// How often the files are checked for changes.
+    const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+    let clear = io::stdout().is_terminal();
+    loop {
+        if clear {
+            // A screen that can't be cleared is no reason to stop watching.
+            let _ = write!(io::stdout(), "\x1b[2J\x1b[H");
+        }
+        let mut evaluator = options.evaluator();
+        run_file_in(&mut evaluator, path, options);
+
+        let files: Vec<PathBuf> = std::iter::once(PathBuf::from(path))
+            .chain(evaluator.module_files().iter().cloned())
+            .collect();
+        // Dropped before waiting, so what the script held isn't held
+        // until the next run.
+        drop(evaluator);
+        let modified = |files: &[PathBuf]| -> Vec<Option<SystemTime>> {
+            files
+                .iter()
+                .map(|file| {
+                    std::fs::metadata(file)
+                        .and_then(|metadata| metadata.modified())
+                        .ok()
+                })
+                .collect()
+        };
+        let before = modified(&files);
+        eprintln!("\n[watching {path} for changes; Ctrl-C to stop]");
+        while modified(&files) == before {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Runs `source`, given on the command line or piped in, as `run_file`
+/// runs a script, naming it `name` in errors.
+fn eval_source(name: &str, source: &str, options: Options) -> ExitCode {
+    let sources = Sources::single(name, source);
+    let Some(program) = parse_source(source, &sources, options) else {
+        return ExitCode::FAILURE;
+    };
+
+    let mut evaluator = options.evaluator();
+    let Some(result) = execute(&mut evaluator, &program, &sources, options) else {
+        return ExitCode::FAILURE;
+    };
+    report_profile(&evaluator);
+    report_result(result, &sources, options)
+}
+
+/// Runs `program` on the engine `options` pick, or reports why it couldn't
+/// be compiled for the VM, pointing into `sources`.
+fn execute(
+    evaluator: &mut Evaluator,
+    program: &Program,
+    sources: &Sources,
+    options: Options,
+) -> Option<Result<Value, RuntimeError>> {
+    match options.engine {
+        Engine::Tree => Some(evaluator.eval_program(program)),
+        Engine::Vm => {
+            let bytecode = compile_program(program, sources, options)?;
+            Some(vm::run(evaluator, &bytecode))
+        }
+    }
+}
+
+/// Reports the error a program stopped with, if any, pointing into
+/// `sources`, and returns the status the process should exit with: 0 if
+/// it ran to the end, what it passed to `exit` if it called that, and 1
+/// otherwise.
+fn report_result(
+    result: Result<Value, RuntimeError>,
+    sources: &Sources,
+    options: Options,
+) -> ExitCode {
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(RuntimeError {
+            kind: ErrorKind::Exit(status),
+            ..
+        }) => ExitCode::from(status),
+        Err(error) => {
+            report_error((&error).into(), sources, options);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Prints the calls `evaluator` recorded, if it was profiling, sorted by
+/// the time spent in each function's own code.
+fn report_profile(evaluator: &Evaluator) {
+    if let Some(profiler) = evaluator.profiler() {
+        eprint!("{profiler}");
+    }
+}
+
+/// Compiles the script at `path`, optimizing the code if `-O` was given,
+/// or reports why it couldn't.
+fn compile_file(path: &str, options: Options) -> Option<Bytecode> {
+    let (program, sources) = parse_file(path, options)?;
+    compile_program(&program, &sources, options)
+}
+
+/// Compiles `program`, optimizing the code if `-O` was given, or reports
+/// why it couldn't, pointing into `sources`.
+fn compile_program(program: &Program, sources: &Sources, options: Options) -> Option<Bytecode> {
+    let mut bytecode = match compiler::compile(program, &options.builtins()) {
+        Ok(bytecode) => bytecode,
+        Err(error) => {
+            report_error((&error).into(), sources, options);
+            return None;
+        }
+    };
+    if options.optimize {
+        peephole::optimize(&mut bytecode);
+    }
+
+    Some(bytecode)
+}
+
+/// Compiles the script at `path` and writes the bytecode to `output`, so
+/// running it later skips lexing, parsing and compiling.
+fn build_file(path: &str, output: &Path, options: Options) -> ExitCode {
+    let Some(bytecode) = compile_file(path, options) else {
+        return ExitCode::FAILURE;
+    };
+
+    if let Err(error) = std::fs::write(output, bytecode.encode()) {
+        eprintln!("could not write {}: {error}", output.display());
+        return ExitCode::FAILURE;
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// Runs the tests in the files at `paths`, and in the files ending in
+/// `_test.tofu` in and under the directories among them, or the working
+/// directory if there are none. A test is a function bound at the top level
+/// of its file to a name starting with `test_`, which passes unless calling
+/// it fails, as it does when an `assert` does.
+fn test_files(paths: &[String], options: Options) -> Result<ExitCode, TofuError> {
+    let mut files = Vec::new();
+    let paths = if paths.is_empty() {
+        &[".".to_string()]
+    } else {
+        paths
+    };
+    for path in paths {
+        let path = Path::new(path);
+        if path.is_dir() {
+            find_tests(path, &mut files);
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+
+    let (mut passed, mut failed) = (0, 0);
+    for file in &files {
+        let path = file.display().to_string();
+        let Some((program, sources)) = parse_file(&path, options) else {
+            failed += 1;
+            continue;
+        };
+        let mut evaluator = options.evaluator();
+        evaluator.set_source_path(&path);
+        if let Err(error) = evaluator.eval_program(&program) {
+            report_error((&error).into(), &sources, options);
+            failed += 1;
+            continue;
+        }
+
+        // In the order they're defined in.
+        let tests: Vec<_> = program
+            .statements
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::Let(statement) => match &statement.pattern {
+                    Pattern::Identifier(name) if name.as_str().starts_with("test_") => {
+                        Some((name, evaluator.global(name)?))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .filter(|(_, test)| test.arity().is_some())
+            .collect();
+        let plural = if tests.len() == 1 { "" } else { "s" };
+        writeln!(
+            io::stdout(),
+            "running {} test{plural} in {path}",
+            tests.len()
+        )?;
+
+        for (name, test) in tests {
+            let start = Instant::now();
+            let result = evaluator.call(test, Vec::new());
+            let elapsed = start.elapsed();
+            match result {
+                Ok(_) => {
+                    writeln!(io::stdout(), "test {name} ... ok ({elapsed:.1?})")?;
+                    passed += 1;
+                }
+                Err(mut error) => {
+                    writeln!(io::stdout(), "test {name} ... FAILED ({elapsed:.1?})")?;
+                    // The test's own frame, called from nowhere in the file.
+                    if error
+                        .trace
+                        .last()
+                        .is_some_and(|frame| frame.function.as_deref() == Some(name.as_str()))
+                    {
+                        error.trace.pop();
+                    }
+                    report_error((&error).into(), &sources, options);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    let result = if failed == 0 { "ok" } else { "FAILED" };
+    writeln!(
+        io::stdout(),
+        "\ntest result: {result}. {passed} passed, {failed} failed"
+    )?;
+    if failed == 0 {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+/// Adds the files ending in `_test.tofu` in and under `directory` to
+/// `files`, in order of their paths, skipping hidden directories.
+fn find_tests(directory: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return;
+    };
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+    paths.sort();
+    for path in paths {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.is_dir() {
+            if !name.starts_with('.') {
+                find_tests(&path, files);
+            }
+        } else if name.ends_with("_test.tofu") {
+            files.push(path);
+        }
+    }
+}
+
+/// Reports every problem that can be found in the files at `paths` without
+/// running them: whatever keeps them from lexing or parsing, the names
+/// [`resolve::check`] finds undefined or declared twice, and, if `types` is
+/// set, what [`typecheck::check`] finds. Fails if there were any.
+fn validate_files(paths: &[String], types: bool, options: Options) -> ExitCode {
+    let mut status = ExitCode::SUCCESS;
+    for path in paths {
+        let Some((program, sources)) = parse_file(path, options) else {
+            status = ExitCode::FAILURE;
+            continue;
+        };
+        let errors = resolve::check(&program, &options.builtins());
+        for error in &errors {
+            report_error(error.into(), &sources, options);
+        }
+        if !errors.is_empty() {
+            status = ExitCode::FAILURE;
+            continue;
+        }
+        if types {
+            let errors = typecheck::check(&program, &options.builtins());
+            for error in &errors {
+                report_error(error.into(), &sources, options);
+            }
+            if !errors.is_empty() {
+                status = ExitCode::FAILURE;
+            }
+        }
+    }
+    status
+}
+
+/// Reports what [`lint::lint`] finds in each of the files at `paths`, failing
+/// if it finds anything or one can't be parsed.
+fn lint_files(paths: &[String], options: Options) -> ExitCode {
+    let mut status = ExitCode::SUCCESS;
+    let mut count = 0;
+    for path in paths {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("could not read {path}: {error}");
+                status = ExitCode::FAILURE;
+                continue;
+            }
+        };
+
+        let sources = Sources::single(path, &source);
+        match lint::lint(&source) {
+            Ok(warnings) => {
+                for warning in &warnings {
+                    let diagnostic = Diagnostic {
+                        severity: Severity::Warning,
+                        code: Some(warning.code),
+                        message: &warning.message,
+                        span: Some(warning.span),
+                        runtime: None,
+                    };
+                    report_error(diagnostic, &sources, options);
+                }
+                count += warnings.len();
+            }
+            Err(errors) => {
+                for error in &errors {
+                    report_error(error.into(), &sources, options);
+                }
+                status = ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if count > 0 {
+        let plural = if count == 1 { "" } else { "s" };
+        eprintln!("{count} warning{plural}");
+        status = ExitCode::FAILURE;
+    }
+    status
+}
+
+/// Rewrites each of the files at `paths` in the canonical layout, or, if
+/// `check` is set, only lists the ones that aren't in it, failing if there
+/// are any.
+fn format_files(paths: &[String], check: bool, options: Options) -> Result<ExitCode, TofuError> {
+    let mut status = ExitCode::SUCCESS;
+    for path in paths {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("could not read {path}: {error}");
+                status = ExitCode::FAILURE;
+                continue;
+            }
+        };
+
+        let formatted = match format::format(&source) {
+            Ok(formatted) => formatted,
+            Err(FormatError::Parse(errors)) => {
+                let sources = Sources::single(path, &source);
+                for error in &errors {
+                    report_error(error.into(), &sources, options);
+                }
+                status = ExitCode::FAILURE;
+                continue;
+            }
+            Err(error) => {
+                eprintln!("{path}: {error}");
+                status = ExitCode::FAILURE;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+
+        if check {
+            writeln!(io::stdout(), "{path} isn't formatted")?;
+            status = ExitCode::FAILURE;
+        } else if let Err(error) = std::fs::write(path, formatted) {
+            eprintln!("could not write {path}: {error}");
+            status = ExitCode::FAILURE;
+        }
+    }
+    Ok(status)
+}
+
+/// Parses the file at `path` and prints it in the `emit` format, after
+/// folding constants if `--fold-constants` was given.
+fn emit_file(emit: Emit, path: &str, options: Options) -> Result<ExitCode, TofuError> {
+    let mut stdout = io::stdout().lock();
+    if let Emit::Bytecode = emit {
+        let Some(bytecode) = compile_file(path, options) else {
+            return Ok(ExitCode::FAILURE);
+        };
+        write!(stdout, "{}", bytecode.disassemble())?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Emit::Tokens = emit {
+        return lex_file(path, &mut stdout, options);
+    }
+
+    let Some((program, _)) = parse_file(path, options) else {
+        return Ok(ExitCode::FAILURE);
+    };
+
+    match emit {
+        Emit::Source => writeln!(stdout, "{program}")?,
+        Emit::Ast => match serde_json::to_string_pretty(&program) {
+            Ok(json) => writeln!(stdout, "{json}")?,
+            Err(error) => {
+                eprintln!("could not serialize the AST: {error}");
+                return Ok(ExitCode::FAILURE);
+            }
+        },
+        Emit::Sexp => write!(stdout, "{}", sexp::program(&program))?,
+        Emit::Tokens | Emit::Bytecode => unreachable!("handled above"),
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Prints the tokens of the file at `path`, one per line with where it
+/// starts, its kind and, for kinds whose text varies, its text quoted. The
+/// `;`s the parser would find at the ends of lines are included. Fails if
+/// the file has lexing errors, after printing every token.
+fn lex_file(path: &str, output: &mut impl Write, options: Options) -> Result<ExitCode, TofuError> {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("could not read {path}: {error}");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    let mut lexer = Lexer::new(&source);
+    lexer.set_newline_terminators(true);
+    loop {
+        let token = lexer.next_token();
+        match &token.literal {
+            Some(literal) => writeln!(output, "{} {:?} {literal:?}", token.span, token.kind)?,
+            None => writeln!(output, "{} {:?}", token.span, token.kind)?,
+        }
+        if token.kind == TokenKind::Eof {
+            break;
+        }
+    }
+
+    let sources = Sources::single(path, &source);
+    for error in lexer.errors() {
+        report_error((&ParseError::from(error.clone())).into(), &sources, options);
+    }
+    if lexer.errors().is_empty() {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
 }
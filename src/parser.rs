@@ -0,0 +1,3415 @@
+use crate::ast::{
+    BlockStatement, DeclarationKind, Expression, Import, ImportSource, InfixOperator, LetStatement,
+    Literal, MatchArm, Parameter, Parameters, Pattern, Precedence, PrefixOperator, Program,
+    Statement, TypeAnnotation, VariantDeclaration,
+};
+use crate::lexer::{LexError, Lexer};
+use crate::symbol::Symbol;
+use crate::token::{Span, Token, TokenKind};
+use std::cell::Cell;
+use std::fmt::Display;
+use std::rc::Rc;
+
+impl Precedence {
+    fn of(kind: TokenKind) -> Precedence {
+        match kind {
+            TokenKind::LeftParen
+            | TokenKind::LeftBracket
+            | TokenKind::QuestionBracket
+            | TokenKind::QuestionDot
+            | TokenKind::Dot => return Precedence::Call,
+            TokenKind::DotDot | TokenKind::DotDotEq => return Precedence::Range,
+            TokenKind::Question => return Precedence::Conditional,
+            TokenKind::PipeGreater => return Precedence::Pipe,
+            kind if Parser::assign_operator(kind).is_some() => return Precedence::Assign,
+            _ => {}
+        }
+
+        match Parser::infix_operator(kind) {
+            Some(operator) => operator.precedence(),
+            None => Precedence::Lowest,
+        }
+    }
+}
+
+/// A problem found while parsing. The parser skips ahead to the next
+/// statement after reporting one, so a single run can report several.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<LexError> for ParseError {
+    fn from(error: LexError) -> ParseError {
+        ParseError {
+            message: error.kind.to_string(),
+            span: error.span,
+        }
+    }
+}
+
+/// How deeply expressions may nest before the parser gives up, unless set
+/// otherwise with [`Parser::set_max_depth`].
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// A Pratt parser that turns the lexer's tokens into a [`Program`].
+pub struct Parser<'a> {
+    lexer: Lexer<'a>,
+    cur_token: Token<'a>,
+    peek_token: Token<'a>,
+    docs: Vec<String>,
+    /// How many `{` up to and including `cur_token` are still open.
+    brace_depth: usize,
+    /// How many expressions are being parsed inside one another right now.
+    depth: usize,
+    max_depth: usize,
+    errors: Vec<ParseError>,
+    /// Where each statement starts, in the order they were started.
+    statement_starts: Vec<Span>,
+    /// Where each block's closing `}` is, in the order they were closed.
+    block_ends: Vec<Span>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(lexer: Lexer<'a>) -> Parser<'a> {
+        let placeholder = Token {
+            kind: TokenKind::Eof,
+            literal: None,
+            span: Span::default(),
+        };
+
+        let mut parser = Parser {
+            lexer,
+            cur_token: placeholder.clone(),
+            peek_token: placeholder,
+            docs: Vec::new(),
+            brace_depth: 0,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            errors: Vec::new(),
+            statement_starts: Vec::new(),
+            block_ends: Vec::new(),
+        };
+
+        parser.next_token();
+        parser.next_token();
+
+        parser
+    }
+
+    /// Limits how deeply expressions, including blocks and chains of
+    /// operators, may nest. The parser and evaluator are recursive, so this
+    /// keeps hostile input like a hundred thousand `(` or `+ 1` from
+    /// overflowing the stack; going over it is a parse error.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// Where each statement parsed so far starts, in the order a
+    /// [`Visit`](crate::visit::Visit) reaches them, for placing what the
+    /// AST leaves out, like comments. See [`crate::format`].
+    pub fn statement_starts(&self) -> &[Span] {
+        &self.statement_starts
+    }
+
+    /// Where each block parsed so far ends, in the order a
+    /// [`Visit`](crate::visit::Visit) finishes them.
+    pub fn block_ends(&self) -> &[Span] {
+        &self.block_ends
+    }
+
+    pub fn parse_program(&mut self) -> Program {
+        let mut program = Program::default();
+
+        while self.cur_token.kind != TokenKind::Eof {
+            let errors_before = self.errors.len();
+
+            match self.parse_statement() {
+                Some(statement) => program.statements.push(statement),
+                None if self.errors.len() > errors_before => {
+                    self.synchronize(0);
+                }
+                None => {}
+            }
+
+            self.next_token();
+        }
+
+        self.report_lexer_errors();
+        self.errors
+            .sort_by_key(|error| (error.span.line, error.span.column));
+
+        program
+    }
+
+    /// Moves any errors the lexer has collected so far into `errors`.
+    fn report_lexer_errors(&mut self) {
+        let errors = self.lexer.take_errors();
+        self.errors.extend(errors.into_iter().map(ParseError::from));
+    }
+
+    /// Skips the rest of a broken statement that started `depth` braces
+    /// deep, stopping on its `;` or just before whatever starts the next
+    /// one. Returns `true` if it stopped on the `}` that closes the enclosing
+    /// block instead.
+    fn synchronize(&mut self, depth: usize) -> bool {
+        self.docs.clear();
+
+        loop {
+            if self.brace_depth < depth {
+                return true;
+            }
+
+            if self.cur_token.kind == TokenKind::Eof {
+                return false;
+            }
+
+            if self.brace_depth == depth
+                && (self.cur_token.kind == TokenKind::Semicolon
+                    || matches!(
+                        self.peek_token.kind,
+                        TokenKind::Let
+                            | TokenKind::Const
+                            | TokenKind::Return
+                            | TokenKind::While
+                            | TokenKind::For
+                            | TokenKind::Import
+                            | TokenKind::RightBrace
+                    ))
+            {
+                return false;
+            }
+
+            self.next_token();
+        }
+    }
+
+    /// Moves along a token, setting aside any doc comments on the way so the
+    /// next declaration can claim them.
+    fn next_token(&mut self) {
+        let next = self.lexer.next_token();
+        self.cur_token = std::mem::replace(&mut self.peek_token, next);
+
+        match self.cur_token.kind {
+            TokenKind::LeftBrace => self.brace_depth += 1,
+            TokenKind::RightBrace => self.brace_depth = self.brace_depth.saturating_sub(1),
+            _ => {}
+        }
+
+        while self.peek_token.kind == TokenKind::DocComment {
+            self.docs.push(self.peek_token.literal_str().to_string());
+            self.peek_token = self.lexer.next_token();
+        }
+    }
+
+    fn parse_statement(&mut self) -> Option<Statement> {
+        if self.cur_token.kind != TokenKind::Semicolon {
+            self.statement_starts.push(self.cur_token.span);
+        }
+
+        match self.cur_token.kind {
+            TokenKind::Let => self.parse_let_statement(DeclarationKind::Let),
+            TokenKind::Const => self.parse_let_statement(DeclarationKind::Const),
+            TokenKind::Return => self.parse_return_statement(),
+            TokenKind::While => self.parse_while_statement(),
+            TokenKind::For => self.parse_for_statement(),
+            TokenKind::Break => self.parse_loop_control(Statement::Break),
+            TokenKind::Continue => self.parse_loop_control(Statement::Continue),
+            TokenKind::Import => self.parse_import_statement(),
+            TokenKind::Throw => self.parse_throw_statement(),
+            TokenKind::Struct => self.parse_struct_statement(),
+            TokenKind::Enum => self.parse_enum_statement(),
+            TokenKind::Semicolon => None,
+            _ => self.parse_expression_statement(),
+        }
+    }
+
+    fn parse_import_statement(&mut self) -> Option<Statement> {
+        let span = self.cur_token.span;
+
+        let source = match self.peek_token.kind {
+            TokenKind::Identifier => ImportSource::Name(self.peek_token.literal_str().to_string()),
+            TokenKind::Str => ImportSource::Path(self.peek_token.literal_str().to_string()),
+            kind => {
+                self.error(
+                    format!(
+                        "expected a module name or string after `import`, found {}",
+                        describe(kind)
+                    ),
+                    self.peek_token.span,
+                );
+                return None;
+            }
+        };
+        self.next_token();
+
+        let name = match &source {
+            ImportSource::Name(name) => Symbol::intern(name),
+            ImportSource::Path(path) => {
+                let stem = std::path::Path::new(path)
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .filter(|stem| is_identifier(stem));
+                match stem {
+                    Some(stem) => Symbol::intern(stem),
+                    None => {
+                        self.error(
+                            format!("cannot import {path:?}: its file name isn't a valid name"),
+                            self.cur_token.span,
+                        );
+                        return None;
+                    }
+                }
+            }
+        };
+
+        if self.peek_token_is(TokenKind::Semicolon) {
+            self.next_token();
+        }
+
+        Some(Statement::Import(Import { source, name, span }))
+    }
+
+    fn parse_let_statement(&mut self, kind: DeclarationKind) -> Option<Statement> {
+        let docs = std::mem::take(&mut self.docs);
+
+        if !self.expect_peek_pattern(&format!("after `{kind}`")) {
+            return None;
+        }
+
+        let span = self.cur_token.span;
+        let pattern = self.parse_pattern()?;
+
+        let annotation = if self.peek_token_is(TokenKind::Colon) {
+            self.next_token();
+            self.next_token();
+            Some(self.parse_type_annotation()?)
+        } else {
+            None
+        };
+
+        let declared = match &annotation {
+            Some(annotation) => format!("{kind} {pattern}: {annotation}"),
+            None => format!("{kind} {pattern}"),
+        };
+        if !self.expect_peek(TokenKind::Assign, &format!("after `{declared}`")) {
+            return None;
+        }
+
+        self.next_token();
+
+        let value = self.parse_expression(Precedence::Lowest)?;
+
+        if self.peek_token_is(TokenKind::Semicolon) {
+            self.next_token();
+        }
+
+        Some(Statement::Let(LetStatement {
+            kind,
+            pattern,
+            value,
+            docs,
+            annotation,
+            span,
+        }))
+    }
+
+    fn parse_return_statement(&mut self) -> Option<Statement> {
+        self.docs.clear();
+
+        if matches!(
+            self.peek_token.kind,
+            TokenKind::Semicolon | TokenKind::RightBrace | TokenKind::Eof
+        ) {
+            if self.peek_token_is(TokenKind::Semicolon) {
+                self.next_token();
+            }
+
+            return Some(Statement::Return(None));
+        }
+
+        self.next_token();
+
+        let value = self.parse_expression(Precedence::Lowest)?;
+
+        if self.peek_token_is(TokenKind::Semicolon) {
+            self.next_token();
+        }
+
+        Some(Statement::Return(Some(value)))
+    }
+
+    fn parse_throw_statement(&mut self) -> Option<Statement> {
+        let span = self.cur_token.span;
+        self.next_token();
+
+        let value = self.parse_expression(Precedence::Lowest)?;
+
+        if self.peek_token_is(TokenKind::Semicolon) {
+            self.next_token();
+        }
+
+        Some(Statement::Throw { value, span })
+    }
+
+    /// `struct Name { field, ... }`, with the current token being `struct`.
+    fn parse_struct_statement(&mut self) -> Option<Statement> {
+        self.docs.clear();
+
+        if !self.expect_peek(TokenKind::Identifier, "after `struct`") {
+            return None;
+        }
+        let name = Symbol::intern(self.cur_token.literal_str());
+        let span = self.cur_token.span;
+
+        if !self.expect_peek(TokenKind::LeftBrace, &format!("after `struct {name}`")) {
+            return None;
+        }
+        let fields =
+            self.parse_name_list(TokenKind::RightBrace, &format!("struct `{name}`"), "field")?;
+
+        if self.peek_token_is(TokenKind::Semicolon) {
+            self.next_token();
+        }
+
+        Some(Statement::Struct { name, fields, span })
+    }
+
+    /// `enum Name { Variant, Variant(field, ...), ... }`, with the current
+    /// token being `enum`.
+    fn parse_enum_statement(&mut self) -> Option<Statement> {
+        self.docs.clear();
+
+        if !self.expect_peek(TokenKind::Identifier, "after `enum`") {
+            return None;
+        }
+        let name = Symbol::intern(self.cur_token.literal_str());
+        let span = self.cur_token.span;
+
+        if !self.expect_peek(TokenKind::LeftBrace, &format!("after `enum {name}`")) {
+            return None;
+        }
+
+        let mut variants: Vec<VariantDeclaration> = Vec::new();
+        self.skip_newlines();
+        while !self.peek_token_is(TokenKind::RightBrace) {
+            if !self.expect_peek(
+                TokenKind::Identifier,
+                &format!("in enum `{name}`'s variants"),
+            ) {
+                return None;
+            }
+            let variant = Symbol::intern(self.cur_token.literal_str());
+            if variants.iter().any(|declared| declared.name == variant) {
+                self.error(
+                    format!("enum `{name}` has two variants called `{variant}`"),
+                    self.cur_token.span,
+                );
+                return None;
+            }
+
+            let fields = if self.peek_token_is(TokenKind::LeftParen) {
+                self.next_token();
+                let owner = format!("variant `{variant}`");
+                self.parse_name_list(TokenKind::RightParen, &owner, "field")?
+            } else {
+                Vec::new()
+            };
+            variants.push(VariantDeclaration {
+                name: variant,
+                fields,
+            });
+
+            self.skip_newlines();
+            if !self.peek_token_is(TokenKind::RightBrace)
+                && !self.expect_peek(TokenKind::Comma, "between variants")
+            {
+                return None;
+            }
+            self.skip_newlines();
+        }
+        self.next_token();
+
+        if self.peek_token_is(TokenKind::Semicolon) {
+            self.next_token();
+        }
+
+        Some(Statement::Enum {
+            name,
+            variants,
+            span,
+        })
+    }
+
+    /// Comma-separated names up to `end`, with the current token being the
+    /// opening delimiter. `owner` and `what` say what they are in errors,
+    /// like "struct `Point`" and "field". A name can't be given twice.
+    fn parse_name_list(&mut self, end: TokenKind, owner: &str, what: &str) -> Option<Vec<Symbol>> {
+        let mut names: Vec<Symbol> = Vec::new();
+        self.skip_newlines();
+        while !self.peek_token_is(end) {
+            if !self.expect_peek(TokenKind::Identifier, &format!("in {owner}'s {what}s")) {
+                return None;
+            }
+            let name = Symbol::intern(self.cur_token.literal_str());
+            if names.contains(&name) {
+                self.error(
+                    format!("{owner} has two {what}s called `{name}`"),
+                    self.cur_token.span,
+                );
+                return None;
+            }
+            names.push(name);
+
+            self.skip_newlines();
+            if !self.peek_token_is(end)
+                && !self.expect_peek(TokenKind::Comma, &format!("between {what}s"))
+            {
+                return None;
+            }
+            self.skip_newlines();
+        }
+        self.next_token();
+
+        Some(names)
+    }
+
+    /// Skips the `;`s that newlines inside braces become when newlines end
+    /// statements, in braces that don't hold statements.
+    fn skip_newlines(&mut self) {
+        while self.peek_token_is(TokenKind::Semicolon) {
+            self.next_token();
+        }
+    }
+
+    fn parse_while_statement(&mut self) -> Option<Statement> {
+        self.docs.clear();
+
+        if !self.expect_peek(TokenKind::LeftParen, "after `while`") {
+            return None;
+        }
+
+        self.next_token();
+        let condition = self.parse_expression(Precedence::Lowest)?;
+
+        if !self.expect_peek(TokenKind::RightParen, "after the `while` condition") {
+            return None;
+        }
+
+        if !self.expect_peek(TokenKind::LeftBrace, "to start the `while` body") {
+            return None;
+        }
+
+        let body = self.parse_block_statement()?;
+
+        if self.peek_token_is(TokenKind::Semicolon) {
+            self.next_token();
+        }
+
+        Some(Statement::While { condition, body })
+    }
+
+    fn parse_for_statement(&mut self) -> Option<Statement> {
+        self.docs.clear();
+
+        if !self.expect_peek(TokenKind::LeftParen, "after `for`") {
+            return None;
+        }
+
+        if !self.expect_peek_pattern("to name the loop variable") {
+            return None;
+        }
+
+        let pattern = self.parse_pattern()?;
+
+        if !self.expect_peek(TokenKind::In, "after the loop variable") {
+            return None;
+        }
+
+        self.next_token();
+        let iterable = self.parse_expression(Precedence::Lowest)?;
+
+        if !self.expect_peek(TokenKind::RightParen, "after the `for` iterable") {
+            return None;
+        }
+
+        if !self.expect_peek(TokenKind::LeftBrace, "to start the `for` body") {
+            return None;
+        }
+
+        let body = self.parse_block_statement()?;
+
+        if self.peek_token_is(TokenKind::Semicolon) {
+            self.next_token();
+        }
+
+        Some(Statement::For {
+            pattern,
+            iterable,
+            body,
+        })
+    }
+
+    /// Like [`Parser::expect_peek`], for the first token of a pattern.
+    fn expect_peek_pattern(&mut self, context: &str) -> bool {
+        if matches!(
+            self.peek_token.kind,
+            TokenKind::Identifier
+                | TokenKind::LeftBracket
+                | TokenKind::LeftParen
+                | TokenKind::LeftBrace
+                | TokenKind::Int
+                | TokenKind::Minus
+                | TokenKind::Str
+                | TokenKind::Char
+                | TokenKind::True
+                | TokenKind::False
+                | TokenKind::Null
+        ) {
+            self.next_token();
+            true
+        } else {
+            self.error(
+                format!(
+                    "expected a name or pattern {context}, found {}",
+                    describe(self.peek_token.kind)
+                ),
+                self.peek_token.span,
+            );
+            false
+        }
+    }
+
+    /// Parses the pattern starting at the current token, which is an
+    /// identifier, `[`, `(` or `{`.
+    fn parse_pattern(&mut self) -> Option<Pattern> {
+        match self.cur_token.kind {
+            // Like a tuple literal, `(a)` is only `a` in parentheses, and
+            // `(a,)` is the tuple of one.
+            TokenKind::LeftParen => {
+                let mut patterns = Vec::new();
+                let mut tuple = self.peek_token_is(TokenKind::RightParen);
+
+                while !self.peek_token_is(TokenKind::RightParen) {
+                    if !self.expect_peek_pattern("in a tuple pattern") {
+                        return None;
+                    }
+                    patterns.push(self.parse_pattern()?);
+
+                    if self.peek_token_is(TokenKind::Comma) {
+                        self.next_token();
+                        tuple = true;
+                    } else if !self.peek_token_is(TokenKind::RightParen) {
+                        self.expect_peek(TokenKind::Comma, "between pattern elements");
+                        return None;
+                    }
+                }
+                self.next_token();
+
+                match patterns.pop() {
+                    Some(pattern) if !tuple => Some(pattern),
+                    last => {
+                        patterns.extend(last);
+                        Some(Pattern::Tuple(patterns))
+                    }
+                }
+            }
+            TokenKind::LeftBracket => {
+                let mut patterns = Vec::new();
+
+                while !self.peek_token_is(TokenKind::RightBracket) {
+                    if !self.expect_peek_pattern("in an array pattern") {
+                        return None;
+                    }
+                    patterns.push(self.parse_pattern()?);
+
+                    if !self.peek_token_is(TokenKind::RightBracket)
+                        && !self.expect_peek(TokenKind::Comma, "between pattern elements")
+                    {
+                        return None;
+                    }
+                }
+                self.next_token();
+
+                Some(Pattern::Array(patterns))
+            }
+            TokenKind::LeftBrace => Some(Pattern::Hash(
+                self.parse_pattern_entries("in a hash pattern")?,
+            )),
+            TokenKind::Minus => {
+                if !self.expect_peek(TokenKind::Int, "after `-` in a pattern") {
+                    return None;
+                }
+                let Expression::Int(value) = self.parse_int_literal()? else {
+                    unreachable!("Int tokens parse as Ints")
+                };
+                Some(Pattern::Literal(Literal::Int(-value)))
+            }
+            TokenKind::Int => {
+                let Expression::Int(value) = self.parse_int_literal()? else {
+                    unreachable!("Int tokens parse as Ints")
+                };
+                Some(Pattern::Literal(Literal::Int(value)))
+            }
+            TokenKind::Str => Some(Pattern::Literal(Literal::Str(
+                self.cur_token.literal_str().to_string(),
+            ))),
+            TokenKind::Char => self
+                .cur_token
+                .literal_str()
+                .chars()
+                .next()
+                .map(|value| Pattern::Literal(Literal::Char(value))),
+            TokenKind::True => Some(Pattern::Literal(Literal::Bool(true))),
+            TokenKind::False => Some(Pattern::Literal(Literal::Bool(false))),
+            TokenKind::Null => Some(Pattern::Literal(Literal::Null)),
+            TokenKind::Identifier if self.cur_token.literal_str() == "_" => Some(Pattern::Wildcard),
+            _ => {
+                let name = Symbol::intern(self.cur_token.literal_str());
+                if self.peek_token_is(TokenKind::LeftParen) {
+                    self.next_token();
+                    let fields = self.parse_variant_fields()?;
+                    return Some(Pattern::Variant { name, fields });
+                }
+                if !self.peek_token_is(TokenKind::LeftBrace) {
+                    return Some(Pattern::Identifier(name));
+                }
+                self.next_token();
+                let fields = self.parse_pattern_entries("in a struct pattern")?;
+                Some(Pattern::Struct { name, fields })
+            }
+        }
+    }
+
+    /// The `(pattern, ...)` of a variant pattern, with the current token
+    /// being the `(`.
+    fn parse_variant_fields(&mut self) -> Option<Vec<Pattern>> {
+        let mut patterns = Vec::new();
+
+        while !self.peek_token_is(TokenKind::RightParen) {
+            if !self.expect_peek_pattern("in a variant pattern") {
+                return None;
+            }
+            patterns.push(self.parse_pattern()?);
+
+            if !self.peek_token_is(TokenKind::RightParen)
+                && !self.expect_peek(TokenKind::Comma, "between pattern elements")
+            {
+                return None;
+            }
+        }
+        self.next_token();
+
+        Some(patterns)
+    }
+
+    /// The `{key: pattern, ...}` of a hash or struct pattern, with the
+    /// current token being the `{`.
+    fn parse_pattern_entries(&mut self, context: &str) -> Option<Vec<(String, Pattern)>> {
+        let mut entries = Vec::new();
+
+        while !self.peek_token_is(TokenKind::RightBrace) {
+            if !self.expect_peek(TokenKind::Identifier, context) {
+                return None;
+            }
+            let key = self.cur_token.literal_str().to_string();
+
+            // `{name}` is short for `{name: name}`.
+            let pattern = if self.peek_token_is(TokenKind::Colon) {
+                self.next_token();
+                if !self.expect_peek_pattern(&format!("after `{key}:`")) {
+                    return None;
+                }
+                self.parse_pattern()?
+            } else {
+                Pattern::Identifier(Symbol::intern(&key))
+            };
+            entries.push((key, pattern));
+
+            if !self.peek_token_is(TokenKind::RightBrace)
+                && !self.expect_peek(TokenKind::Comma, "between pattern entries")
+            {
+                return None;
+            }
+        }
+        self.next_token();
+
+        Some(entries)
+    }
+
+    /// Parses `break` or `continue`, which is the current token.
+    fn parse_loop_control(&mut self, statement: Statement) -> Option<Statement> {
+        self.docs.clear();
+
+        if self.peek_token_is(TokenKind::Semicolon) {
+            self.next_token();
+        }
+
+        Some(statement)
+    }
+
+    fn parse_expression_statement(&mut self) -> Option<Statement> {
+        self.docs.clear();
+
+        let expression = self.parse_expression(Precedence::Lowest)?;
+
+        if self.peek_token_is(TokenKind::Semicolon) {
+            self.next_token();
+        }
+
+        Some(Statement::Expression(expression))
+    }
+
+    /// Every level of nesting, whether a group, a block, or an operand,
+    /// passes through here, so this is where the depth limit is enforced.
+    /// The operators chained after the first operand don't nest any deeper
+    /// here, so a chain like `a + b + c` can be as long as it likes.
+    fn parse_expression(&mut self, precedence: Precedence) -> Option<Expression> {
+        if !self.nest() {
+            return None;
+        }
+        let expression = self.parse_expression_within_limit(precedence);
+        self.depth -= 1;
+
+        expression
+    }
+
+    /// Goes a level deeper, or reports that it's too deep.
+    fn nest(&mut self) -> bool {
+        if self.depth >= self.max_depth {
+            self.error(
+                format!(
+                    "expression nested too deeply (the limit is {})",
+                    self.max_depth
+                ),
+                self.cur_token.span,
+            );
+            return false;
+        }
+
+        self.depth += 1;
+        true
+    }
+
+    fn parse_expression_within_limit(&mut self, precedence: Precedence) -> Option<Expression> {
+        let left = self.parse_prefix()?;
+        self.parse_infix_expressions(left, precedence)
+    }
+
+    /// Parses the operators after `left` that bind tighter than
+    /// `precedence`, with `left` as the leftmost operand.
+    fn parse_infix_expressions(
+        &mut self,
+        mut left: Expression,
+        precedence: Precedence,
+    ) -> Option<Expression> {
+        while !self.peek_token_is(TokenKind::Semicolon) && precedence < self.peek_precedence() {
+            self.next_token();
+
+            left = match self.cur_token.kind {
+                TokenKind::LeftParen => self.parse_call_expression(left)?,
+                TokenKind::LeftBracket => self.parse_index_expression(left, false)?,
+                TokenKind::QuestionBracket => self.parse_index_expression(left, true)?,
+                TokenKind::QuestionDot => self.parse_optional_field(left)?,
+                TokenKind::Dot => self.parse_method_call(left)?,
+                TokenKind::DotDot | TokenKind::DotDotEq => {
+                    self.parse_range_expression(Some(left), false)?
+                }
+                TokenKind::Question => self.parse_conditional_expression(left)?,
+                TokenKind::PipeGreater => self.parse_pipeline(left)?,
+                kind if Parser::assign_operator(kind).is_some() => {
+                    self.parse_assign_expression(left)?
+                }
+                kind => {
+                    let operator = Parser::infix_operator(kind)
+                        .expect("only infix operators are left that bind tighter than Lowest");
+                    self.parse_infix_expression(left, operator)?
+                }
+            };
+        }
+
+        Some(left)
+    }
+
+    fn parse_prefix(&mut self) -> Option<Expression> {
+        match self.cur_token.kind {
+            TokenKind::Identifier => Some(Expression::Identifier {
+                name: Symbol::intern(self.cur_token.literal_str()),
+                span: self.cur_token.span,
+                global: Cell::default(),
+                slot: Cell::default(),
+            }),
+            TokenKind::Int => self.parse_int_literal(),
+            TokenKind::Float => self.parse_float_literal(),
+            TokenKind::Str => Some(Expression::Str(self.cur_token.literal_str().to_string())),
+            TokenKind::Char => self
+                .cur_token
+                .literal_str()
+                .chars()
+                .next()
+                .map(Expression::Char),
+            TokenKind::True => Some(Expression::Bool(true)),
+            TokenKind::False => Some(Expression::Bool(false)),
+            TokenKind::Null => Some(Expression::Null),
+            TokenKind::Bang => self.parse_prefix_expression(PrefixOperator::Bang),
+            TokenKind::Minus => self.parse_prefix_expression(PrefixOperator::Minus),
+            TokenKind::LeftParen => self.parse_grouped_expression(),
+            TokenKind::If => self.parse_if_expression(),
+            TokenKind::Try => self.parse_try_expression(),
+            TokenKind::Match => self.parse_match_expression(),
+            TokenKind::Fn => self.parse_function_literal(),
+            TokenKind::LeftBrace => self.parse_hash_literal(),
+            TokenKind::LeftBracket => {
+                let elements = self.parse_expression_list(
+                    TokenKind::RightBracket,
+                    "after array elements",
+                    false,
+                )?;
+                Some(Expression::Array(elements))
+            }
+            // The lexer has already said what's wrong with the token.
+            TokenKind::Illegal => {
+                self.report_lexer_errors();
+                None
+            }
+            kind => {
+                self.error(
+                    format!("expected an expression, found {}", describe(kind)),
+                    self.cur_token.span,
+                );
+                None
+            }
+        }
+    }
+
+    fn parse_int_literal(&mut self) -> Option<Expression> {
+        match self.cur_token.literal_str().parse() {
+            Ok(value) => Some(Expression::Int(value)),
+            Err(_) => {
+                self.error(
+                    format!(
+                        "integer literal `{}` is out of range",
+                        self.cur_token.literal_str()
+                    ),
+                    self.cur_token.span,
+                );
+                None
+            }
+        }
+    }
+
+    fn parse_float_literal(&mut self) -> Option<Expression> {
+        match self.cur_token.literal_str().parse() {
+            Ok(value) => Some(Expression::Float(value)),
+            Err(_) => {
+                self.error(
+                    format!(
+                        "`{}` is not a valid float literal",
+                        self.cur_token.literal_str()
+                    ),
+                    self.cur_token.span,
+                );
+                None
+            }
+        }
+    }
+
+    fn parse_prefix_expression(&mut self, operator: PrefixOperator) -> Option<Expression> {
+        let span = self.cur_token.span;
+        self.next_token();
+
+        let right = self.parse_expression(Precedence::Prefix)?;
+
+        Some(Expression::Prefix {
+            operator,
+            right: Box::new(right),
+            span,
+        })
+    }
+
+    /// `(a)`, or a tuple: `()`, `(a,)` or `(a, b)`.
+    fn parse_grouped_expression(&mut self) -> Option<Expression> {
+        if self.peek_token_is(TokenKind::RightParen) {
+            self.next_token();
+            return Some(Expression::Tuple(Vec::new()));
+        }
+        self.next_token();
+
+        let expression = self.parse_expression(Precedence::Lowest)?;
+
+        if self.peek_token_is(TokenKind::Comma) {
+            return self.parse_tuple_literal(expression);
+        }
+
+        if !self.expect_peek(TokenKind::RightParen, "to close `(`") {
+            return None;
+        }
+
+        Some(expression)
+    }
+
+    /// The rest of a tuple literal after its first element. Kept apart
+    /// from [`Parser::parse_grouped_expression`] so deeply nested
+    /// parentheses don't pay for its stack space.
+    fn parse_tuple_literal(&mut self, first: Expression) -> Option<Expression> {
+        let mut elements = vec![first];
+        while self.peek_token_is(TokenKind::Comma) {
+            self.next_token();
+            if self.peek_token_is(TokenKind::RightParen) {
+                break;
+            }
+            self.next_token();
+            elements.push(self.parse_expression(Precedence::Lowest)?);
+        }
+
+        if !self.expect_peek(TokenKind::RightParen, "to close the tuple") {
+            return None;
+        }
+
+        Some(Expression::Tuple(elements))
+    }
+
+    fn parse_if_expression(&mut self) -> Option<Expression> {
+        if !self.expect_peek(TokenKind::LeftParen, "after `if`") {
+            return None;
+        }
+
+        self.next_token();
+        let condition = self.parse_expression(Precedence::Lowest)?;
+
+        if !self.expect_peek(TokenKind::RightParen, "after the `if` condition") {
+            return None;
+        }
+
+        if !self.expect_peek(TokenKind::LeftBrace, "to start the `if` body") {
+            return None;
+        }
+
+        let consequence = self.parse_block_statement()?;
+
+        let alternative = if self.peek_token_is(TokenKind::Else) {
+            self.next_token();
+
+            if self.peek_token_is(TokenKind::If) {
+                // `else if` is sugar for an `else` block holding another `if`.
+                self.next_token();
+                self.statement_starts.push(self.cur_token.span);
+                let nested = self.parse_if_expression()?;
+                self.block_ends.push(self.cur_token.span);
+                Some(BlockStatement {
+                    statements: vec![Statement::Expression(nested)],
+                })
+            } else {
+                if !self.expect_peek(TokenKind::LeftBrace, "after `else`") {
+                    return None;
+                }
+                Some(self.parse_block_statement()?)
+            }
+        } else {
+            None
+        };
+
+        Some(Expression::If {
+            condition: Box::new(condition),
+            consequence,
+            alternative,
+        })
+    }
+
+    fn parse_try_expression(&mut self) -> Option<Expression> {
+        if !self.expect_peek(TokenKind::LeftBrace, "after `try`") {
+            return None;
+        }
+
+        let body = self.parse_block_statement()?;
+
+        if !self.expect_peek(TokenKind::Catch, "after the `try` body") {
+            return None;
+        }
+
+        if !self.expect_peek(TokenKind::LeftParen, "after `catch`") {
+            return None;
+        }
+
+        if !self.expect_peek_pattern("to name the caught error") {
+            return None;
+        }
+
+        let pattern = self.parse_pattern()?;
+
+        if !self.expect_peek(TokenKind::RightParen, "after the caught error") {
+            return None;
+        }
+
+        if !self.expect_peek(TokenKind::LeftBrace, "to start the `catch` body") {
+            return None;
+        }
+
+        let handler = self.parse_block_statement()?;
+
+        Some(Expression::Try {
+            body,
+            pattern,
+            handler,
+        })
+    }
+
+    /// `match subject { pattern => body, ... }`, with the current token
+    /// being `match`. A body is a block or an expression, and the comma
+    /// after a block, or at the end of a line, can be left out.
+    fn parse_match_expression(&mut self) -> Option<Expression> {
+        let span = self.cur_token.span;
+        self.next_token();
+        let subject = self.parse_expression(Precedence::Lowest)?;
+
+        if !self.expect_peek(TokenKind::LeftBrace, "after the `match` subject") {
+            return None;
+        }
+
+        let mut arms = Vec::new();
+        self.skip_newlines();
+        while !self.peek_token_is(TokenKind::RightBrace) {
+            if !self.expect_peek_pattern("to start a `match` arm") {
+                return None;
+            }
+            let pattern = self.parse_pattern()?;
+
+            if !self.expect_peek(TokenKind::FatArrow, &format!("after `{pattern}`")) {
+                return None;
+            }
+            let (body, block) = self.parse_match_body()?;
+            arms.push(MatchArm { pattern, body });
+
+            let newline = self.peek_token_is(TokenKind::Semicolon);
+            self.skip_newlines();
+            if self.peek_token_is(TokenKind::Comma) {
+                self.next_token();
+                self.skip_newlines();
+            } else if !newline && !block && !self.peek_token_is(TokenKind::RightBrace) {
+                self.expect_peek(TokenKind::Comma, "between `match` arms");
+                return None;
+            }
+        }
+        self.next_token();
+
+        Some(Expression::Match {
+            subject: Box::new(subject),
+            arms,
+            span,
+        })
+    }
+
+    /// The body of a `match` arm, with the current token being the `=>`,
+    /// and whether it was written as a block. An expression is made into a
+    /// block holding it, recorded as the parser's own statements and blocks
+    /// are.
+    fn parse_match_body(&mut self) -> Option<(BlockStatement, bool)> {
+        if self.peek_token_is(TokenKind::LeftBrace) {
+            self.next_token();
+            return Some((self.parse_block_statement()?, true));
+        }
+
+        self.next_token();
+        self.statement_starts.push(self.cur_token.span);
+        let expression = self.parse_expression(Precedence::Lowest)?;
+        self.block_ends.push(self.peek_token.span);
+        let body = BlockStatement {
+            statements: vec![Statement::Expression(expression)],
+        };
+        Some((body, false))
+    }
+
+    fn parse_function_literal(&mut self) -> Option<Expression> {
+        if !self.expect_peek(TokenKind::LeftParen, "after `fn`") {
+            return None;
+        }
+
+        let parameters = self.parse_function_parameters()?;
+
+        let return_type = if self.peek_token_is(TokenKind::Arrow) {
+            self.next_token();
+            self.next_token();
+            Some(self.parse_type_annotation()?)
+        } else {
+            None
+        };
+
+        if !self.expect_peek(TokenKind::LeftBrace, "to start the function body") {
+            return None;
+        }
+
+        let body = self.parse_block_statement()?;
+
+        Some(Expression::Function {
+            parameters,
+            return_type,
+            body: Rc::new(body),
+        })
+    }
+
+    /// Parses the type annotation starting at the current token: a name,
+    /// with type arguments in `<...>` if it has any, or `fn(...) -> type`.
+    fn parse_type_annotation(&mut self) -> Option<TypeAnnotation> {
+        match self.cur_token.kind {
+            TokenKind::Identifier | TokenKind::Null => {
+                let name = self.cur_token.literal_str().to_string();
+                let span = self.cur_token.span;
+                let arguments = if self.peek_token_is(TokenKind::LessThan) {
+                    self.next_token();
+                    self.parse_type_list(TokenKind::GreaterThan)?
+                } else {
+                    Vec::new()
+                };
+                Some(TypeAnnotation::Named {
+                    name,
+                    arguments,
+                    span,
+                })
+            }
+            TokenKind::Fn => {
+                if !self.expect_peek(TokenKind::LeftParen, "after `fn` in a type") {
+                    return None;
+                }
+                let parameters = self.parse_type_list(TokenKind::RightParen)?;
+                if !self.expect_peek(TokenKind::Arrow, "after a function type's parameters") {
+                    return None;
+                }
+                self.next_token();
+                let result = self.parse_type_annotation()?;
+                Some(TypeAnnotation::Function {
+                    parameters,
+                    result: Box::new(result),
+                })
+            }
+            kind => {
+                self.error(
+                    format!("expected a type, found {}", describe(kind)),
+                    self.cur_token.span,
+                );
+                None
+            }
+        }
+    }
+
+    /// Parses the types separated by commas after the current `<` or `(`,
+    /// up to the `end` that closes them.
+    fn parse_type_list(&mut self, end: TokenKind) -> Option<Vec<TypeAnnotation>> {
+        let mut types = Vec::new();
+
+        if !self.peek_token_is(end) {
+            loop {
+                self.next_token();
+                types.push(self.parse_type_annotation()?);
+
+                if !self.peek_token_is(TokenKind::Comma) {
+                    break;
+                }
+                self.next_token();
+            }
+        }
+
+        // In `array<array<int>>`, the lexer reads the two `>` as a shift,
+        // so this list takes the first and leaves the second.
+        if end == TokenKind::GreaterThan && self.peek_token_is(TokenKind::ShiftRight) {
+            let span = self.peek_token.span;
+            self.cur_token = Token {
+                kind: TokenKind::GreaterThan,
+                literal: None,
+                span,
+            };
+            self.peek_token = Token {
+                kind: TokenKind::GreaterThan,
+                literal: None,
+                span: Span {
+                    line: span.line,
+                    column: span.column + 1,
+                },
+            };
+            return Some(types);
+        }
+
+        let context = match end {
+            TokenKind::GreaterThan => "after the type arguments",
+            _ => "after the parameter types",
+        };
+        if !self.expect_peek(end, context) {
+            return None;
+        }
+
+        Some(types)
+    }
+
+    fn parse_function_parameters(&mut self) -> Option<Parameters> {
+        let mut parameters = Parameters::default();
+
+        if self.peek_token_is(TokenKind::RightParen) {
+            self.next_token();
+            return Some(parameters);
+        }
+
+        loop {
+            if self.peek_token_is(TokenKind::DotDotDot) {
+                self.next_token();
+                if !self.expect_peek(TokenKind::Identifier, "after `...`") {
+                    return None;
+                }
+                parameters.rest = Some(Symbol::intern(self.cur_token.literal_str()));
+
+                // Nothing is left over for parameters after it.
+                if !self.expect_peek(TokenKind::RightParen, "after the rest parameter") {
+                    return None;
+                }
+                return Some(parameters);
+            }
+
+            if !self.expect_peek(TokenKind::Identifier, "in the parameter list") {
+                return None;
+            }
+            let name = Symbol::intern(self.cur_token.literal_str());
+            let span = self.cur_token.span;
+
+            let annotation = if self.peek_token_is(TokenKind::Colon) {
+                self.next_token();
+                self.next_token();
+                Some(self.parse_type_annotation()?)
+            } else {
+                None
+            };
+
+            let default = if self.peek_token_is(TokenKind::Assign) {
+                self.next_token();
+                self.next_token();
+                Some(self.parse_expression(Precedence::Lowest)?)
+            } else {
+                let after_default = parameters
+                    .positional
+                    .last()
+                    .is_some_and(|parameter| parameter.default.is_some());
+                if after_default {
+                    self.error(
+                        format!("parameter `{name}` needs a default, like the ones before it"),
+                        self.cur_token.span,
+                    );
+                    return None;
+                }
+                None
+            };
+
+            parameters.positional.push(Parameter {
+                name,
+                annotation,
+                default,
+                span,
+            });
+
+            if !self.peek_token_is(TokenKind::Comma) {
+                break;
+            }
+            self.next_token();
+        }
+
+        if !self.expect_peek(TokenKind::RightParen, "after parameters") {
+            return None;
+        }
+
+        Some(parameters)
+    }
+
+    fn parse_call_expression(&mut self, function: Expression) -> Option<Expression> {
+        let span = self.cur_token.span;
+        let arguments =
+            self.parse_expression_list(TokenKind::RightParen, "after arguments", true)?;
+
+        Some(Expression::Call {
+            function: Box::new(function),
+            arguments,
+            span,
+        })
+    }
+
+    /// `left |> right`, which becomes a call with `left` as its first
+    /// argument: `right(left)`, or, if `right` is a call already, that call
+    /// with `left` put before its other arguments, so `x |> f(y)` is
+    /// `f(x, y)`.
+    fn parse_pipeline(&mut self, left: Expression) -> Option<Expression> {
+        let span = self.cur_token.span;
+        self.next_token();
+
+        let mut right = self.parse_expression(Precedence::Pipe)?;
+        match &mut right {
+            Expression::Call { arguments, .. } | Expression::MethodCall { arguments, .. } => {
+                arguments.insert(0, left);
+                Some(right)
+            }
+            _ => Some(Expression::Call {
+                function: Box::new(right),
+                arguments: vec![left],
+                span,
+            }),
+        }
+    }
+
+    /// `receiver.method(arguments)`, with the current token being the `.`,
+    /// or `receiver.0` or `receiver.name`, which mean `receiver[0]` and
+    /// `receiver["name"]`, for a tuple's elements and a struct's fields.
+    fn parse_method_call(&mut self, receiver: Expression) -> Option<Expression> {
+        let span = self.cur_token.span;
+
+        if self.peek_token_is(TokenKind::Int) {
+            self.next_token();
+            return Some(Expression::Index {
+                left: Box::new(receiver),
+                index: Box::new(self.parse_int_literal()?),
+                optional: false,
+                span,
+            });
+        }
+
+        if !self.expect_peek(TokenKind::Identifier, "after `.`") {
+            return None;
+        }
+        if !self.peek_token_is(TokenKind::LeftParen) {
+            return Some(Expression::Index {
+                left: Box::new(receiver),
+                index: Box::new(Expression::Str(self.cur_token.literal_str().to_string())),
+                optional: false,
+                span,
+            });
+        }
+        let method = Symbol::intern(self.cur_token.literal_str());
+        self.next_token();
+        let arguments =
+            self.parse_expression_list(TokenKind::RightParen, "after arguments", true)?;
+
+        Some(Expression::MethodCall {
+            receiver: Box::new(receiver),
+            method,
+            arguments,
+            span,
+        })
+    }
+
+    fn parse_hash_literal(&mut self) -> Option<Expression> {
+        let mut pairs = Vec::new();
+
+        while !self.peek_token_is(TokenKind::RightBrace) {
+            self.next_token();
+            let key = self.parse_expression(Precedence::Lowest)?;
+
+            if !self.expect_peek(TokenKind::Colon, "after a hash key") {
+                return None;
+            }
+
+            self.next_token();
+            let value = self.parse_expression(Precedence::Lowest)?;
+            pairs.push((key, value));
+
+            if !self.peek_token_is(TokenKind::RightBrace)
+                && !self.expect_peek(TokenKind::Comma, "between hash entries")
+            {
+                return None;
+            }
+        }
+
+        self.next_token();
+
+        Some(Expression::Hash(pairs))
+    }
+
+    fn parse_assign_expression(&mut self, target: Expression) -> Option<Expression> {
+        let span = self.cur_token.span;
+        let operator =
+            Parser::assign_operator(self.cur_token.kind).expect("called on an assignment operator");
+
+        if !matches!(
+            target,
+            Expression::Identifier { .. }
+                | Expression::Index {
+                    optional: false,
+                    ..
+                }
+        ) {
+            self.error(
+                format!("cannot assign to `{target}`, only to a name or an index"),
+                span,
+            );
+            return None;
+        }
+
+        self.next_token();
+
+        // Parsing the value at the lowest precedence makes `a = b = c` group
+        // as `a = (b = c)`.
+        let value = self.parse_expression(Precedence::Lowest)?;
+
+        Some(Expression::Assign {
+            target: Box::new(target),
+            operator,
+            value: Box::new(value),
+            span,
+        })
+    }
+
+    fn parse_conditional_expression(&mut self, condition: Expression) -> Option<Expression> {
+        self.next_token();
+        let consequence = self.parse_expression(Precedence::Lowest)?;
+
+        if !self.expect_peek(TokenKind::Colon, "in a conditional expression") {
+            return None;
+        }
+        self.next_token();
+
+        // Parsing the alternative just below conditionals makes `a ? b : c ?
+        // d : e` group as `a ? b : (c ? d : e)`.
+        let alternative = self.parse_expression(Precedence::Assign)?;
+
+        Some(Expression::Conditional {
+            condition: Box::new(condition),
+            consequence: Box::new(consequence),
+            alternative: Box::new(alternative),
+        })
+    }
+
+    /// The rest of a range, with the current token being its `..` or `..=`.
+    /// One that's `slicing` can leave out its end, as in `xs[1..]`.
+    fn parse_range_expression(
+        &mut self,
+        start: Option<Expression>,
+        slicing: bool,
+    ) -> Option<Expression> {
+        let span = self.cur_token.span;
+        let inclusive = self.cur_token.kind == TokenKind::DotDotEq;
+
+        let end = if slicing && !inclusive && self.peek_token_is(TokenKind::RightBracket) {
+            None
+        } else {
+            self.next_token();
+            Some(Box::new(self.parse_expression(Precedence::Range)?))
+        };
+
+        Some(Expression::Range {
+            start: start.map(Box::new),
+            end,
+            inclusive,
+            span,
+        })
+    }
+
+    /// `left[index]`, with the current token being the `[`. A range there
+    /// can leave out its start or end, as in `xs[..n]` and `xs[1..]`.
+    fn parse_index_expression(&mut self, left: Expression, optional: bool) -> Option<Expression> {
+        let span = self.cur_token.span;
+        self.next_token();
+
+        let is_range = |kind| matches!(kind, TokenKind::DotDot | TokenKind::DotDotEq);
+        let index = if is_range(self.cur_token.kind) {
+            self.parse_range_expression(None, true)?
+        } else {
+            // Whatever binds tighter than `..` could be a range's start.
+            let start = self.parse_expression(Precedence::Range)?;
+            if is_range(self.peek_token.kind) {
+                self.next_token();
+                self.parse_range_expression(Some(start), true)?
+            } else {
+                start
+            }
+        };
+        let index = self.parse_infix_expressions(index, Precedence::Lowest)?;
+
+        if !self.expect_peek(TokenKind::RightBracket, "after the index") {
+            return None;
+        }
+
+        Some(Expression::Index {
+            left: Box::new(left),
+            index: Box::new(index),
+            optional,
+            span,
+        })
+    }
+
+    /// `left?.name`, which means `left?["name"]`.
+    fn parse_optional_field(&mut self, left: Expression) -> Option<Expression> {
+        let span = self.cur_token.span;
+
+        if !self.expect_peek(TokenKind::Identifier, "after `?.`") {
+            return None;
+        }
+
+        Some(Expression::Index {
+            left: Box::new(left),
+            index: Box::new(Expression::Str(self.cur_token.literal_str().to_string())),
+            optional: true,
+            span,
+        })
+    }
+
+    /// Parses comma-separated expressions up to `end`, with the current token
+    /// being the opening delimiter. Elements can be spread with `...`, and,
+    /// if `named` is set, as in a call's arguments, given as `name: value`
+    /// once the positional ones are done.
+    fn parse_expression_list(
+        &mut self,
+        end: TokenKind,
+        context: &str,
+        named: bool,
+    ) -> Option<Vec<Expression>> {
+        let mut list = Vec::new();
+
+        if self.peek_token_is(end) {
+            self.next_token();
+            return Some(list);
+        }
+
+        loop {
+            self.next_token();
+            let is_named = named
+                && self.cur_token.kind == TokenKind::Identifier
+                && self.peek_token_is(TokenKind::Colon);
+            let after_named = list
+                .last()
+                .is_some_and(|element| matches!(element, Expression::Named { .. }));
+            if after_named && !is_named {
+                let span = self.cur_token.span;
+                self.error(
+                    "positional argument after named arguments".to_string(),
+                    span,
+                );
+                return None;
+            }
+
+            let element = if self.cur_token.kind == TokenKind::DotDotDot {
+                self.next_token();
+                Expression::Spread(Box::new(self.parse_expression(Precedence::Lowest)?))
+            } else if is_named {
+                let name = Symbol::intern(self.cur_token.literal_str());
+                let span = self.cur_token.span;
+                let repeated = list.iter().any(
+                    |element| matches!(element, Expression::Named { name: other, .. } if *other == name),
+                );
+                if repeated {
+                    self.error(format!("argument `{name}` is named twice"), span);
+                    return None;
+                }
+
+                self.next_token();
+                self.next_token();
+                let value = self.parse_expression(Precedence::Lowest)?;
+                Expression::Named {
+                    name,
+                    value: Box::new(value),
+                }
+            } else {
+                self.parse_expression(Precedence::Lowest)?
+            };
+            list.push(element);
+
+            if !self.peek_token_is(TokenKind::Comma) {
+                break;
+            }
+            self.next_token();
+        }
+
+        if !self.expect_peek(end, context) {
+            return None;
+        }
+
+        Some(list)
+    }
+
+    /// Parses statements up to the `}` matching the current `{`, leaving that
+    /// `}` as the current token.
+    fn parse_block_statement(&mut self) -> Option<BlockStatement> {
+        let mut block = BlockStatement::default();
+        let depth = self.brace_depth;
+        self.next_token();
+
+        while !matches!(self.cur_token.kind, TokenKind::RightBrace | TokenKind::Eof) {
+            let errors_before = self.errors.len();
+
+            if let Some(statement) = self.parse_statement() {
+                block.statements.push(statement);
+            } else if self.errors.len() > errors_before && self.synchronize(depth) {
+                break;
+            }
+
+            self.next_token();
+        }
+
+        if self.cur_token.kind == TokenKind::Eof {
+            self.error(
+                "expected `}` to close the block, found end of input".to_string(),
+                self.cur_token.span,
+            );
+            return None;
+        }
+
+        self.block_ends.push(self.cur_token.span);
+        Some(block)
+    }
+
+    fn parse_infix_expression(
+        &mut self,
+        left: Expression,
+        operator: InfixOperator,
+    ) -> Option<Expression> {
+        let precedence = self.cur_precedence();
+        let span = self.cur_token.span;
+        self.next_token();
+
+        // `**` groups to the right, taking in anything as tight as a prefix
+        // operator, like `-1` in `2 ** -1`. The rest group to the left.
+        let right = if operator == InfixOperator::Power {
+            self.parse_expression(Precedence::Prefix)?
+        } else {
+            self.parse_expression(precedence)?
+        };
+
+        Some(Expression::Infix {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+            span,
+        })
+    }
+
+    fn infix_operator(kind: TokenKind) -> Option<InfixOperator> {
+        let operator = match kind {
+            TokenKind::Plus => InfixOperator::Plus,
+            TokenKind::Minus => InfixOperator::Minus,
+            TokenKind::Asterisk => InfixOperator::Multiply,
+            TokenKind::AsteriskAsterisk => InfixOperator::Power,
+            TokenKind::Slash => InfixOperator::Divide,
+            TokenKind::Eq => InfixOperator::Eq,
+            TokenKind::NotEq => InfixOperator::NotEq,
+            TokenKind::LessThan => InfixOperator::LessThan,
+            TokenKind::GreaterThan => InfixOperator::GreaterThan,
+            TokenKind::LessThanEq => InfixOperator::LessThanEq,
+            TokenKind::GreaterThanEq => InfixOperator::GreaterThanEq,
+            TokenKind::Ampersand => InfixOperator::BitAnd,
+            TokenKind::Pipe => InfixOperator::BitOr,
+            TokenKind::Caret => InfixOperator::BitXor,
+            TokenKind::ShiftLeft => InfixOperator::ShiftLeft,
+            TokenKind::ShiftRight => InfixOperator::ShiftRight,
+            TokenKind::And => InfixOperator::And,
+            TokenKind::Or => InfixOperator::Or,
+            TokenKind::QuestionQuestion => InfixOperator::Coalesce,
+            _ => return None,
+        };
+
+        Some(operator)
+    }
+
+    /// For an assignment token, the operator it applies before assigning:
+    /// `None` for plain `=`, `Some(Plus)` for `+=`, and so on.
+    fn assign_operator(kind: TokenKind) -> Option<Option<InfixOperator>> {
+        let operator = match kind {
+            TokenKind::Assign => None,
+            TokenKind::PlusAssign => Some(InfixOperator::Plus),
+            TokenKind::MinusAssign => Some(InfixOperator::Minus),
+            TokenKind::AsteriskAssign => Some(InfixOperator::Multiply),
+            TokenKind::SlashAssign => Some(InfixOperator::Divide),
+            _ => return None,
+        };
+
+        Some(operator)
+    }
+
+    fn peek_token_is(&self, kind: TokenKind) -> bool {
+        self.peek_token.kind == kind
+    }
+
+    /// Advances if the next token is `kind`, otherwise records an error and
+    /// stays put. `context` finishes the sentence "expected `kind` ...", e.g.
+    /// "after arguments".
+    fn expect_peek(&mut self, kind: TokenKind, context: &str) -> bool {
+        if self.peek_token_is(kind) {
+            self.next_token();
+            true
+        } else {
+            self.error(
+                format!(
+                    "expected {} {context}, found {}",
+                    describe(kind),
+                    describe(self.peek_token.kind)
+                ),
+                self.peek_token.span,
+            );
+            false
+        }
+    }
+
+    fn error(&mut self, message: String, span: Span) {
+        self.errors.push(ParseError { message, span });
+    }
+
+    fn peek_precedence(&self) -> Precedence {
+        Precedence::of(self.peek_token.kind)
+    }
+
+    fn cur_precedence(&self) -> Precedence {
+        Precedence::of(self.cur_token.kind)
+    }
+}
+
+/// Whether `name` lexes as a single identifier, so it can be bound.
+fn is_identifier(name: &str) -> bool {
+    let mut lexer = Lexer::new(name);
+    let token = lexer.next_token();
+    token.kind == TokenKind::Identifier
+        && token.literal_str() == name
+        && lexer.next_token().kind == TokenKind::Eof
+}
+
+/// Names a token kind for an error message: fixed spellings in backticks,
+/// everything else by name.
+fn describe(kind: TokenKind) -> String {
+    match (kind, kind.spelling()) {
+        (TokenKind::Eof, _) => "end of input".to_string(),
+        (_, Some(spelling)) => format!("`{spelling}`"),
+        (kind, None) => kind.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::{ParseError, Parser, DEFAULT_MAX_DEPTH};
+    use crate::ast::{
+        BlockStatement, DeclarationKind, Expression, Import, ImportSource, InfixOperator,
+        LetStatement, Literal, MatchArm, Parameter, Parameters, Pattern, PrefixOperator, Program,
+        Statement, TypeAnnotation, VariantDeclaration,
+    };
+    use crate::lexer::Lexer;
+    use crate::token::Span;
+    use crate::visit::{walk_expression_mut, walk_statement_mut, VisitMut};
+
+    /// Resets every span to the default, so tests can compare trees without
+    /// spelling out where each node was.
+    struct EraseSpans;
+
+    impl VisitMut for EraseSpans {
+        fn visit_expression_mut(&mut self, expression: &mut Expression) {
+            walk_expression_mut(self, expression);
+            match expression {
+                Expression::Identifier { span, .. }
+                | Expression::Prefix { span, .. }
+                | Expression::Infix { span, .. }
+                | Expression::Call { span, .. }
+                | Expression::MethodCall { span, .. }
+                | Expression::Index { span, .. }
+                | Expression::Assign { span, .. }
+                | Expression::Match { span, .. }
+                | Expression::Range { span, .. } => *span = Span::default(),
+                Expression::Function { parameters, .. } => {
+                    for parameter in &mut parameters.positional {
+                        parameter.span = Span::default();
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        fn visit_statement_mut(&mut self, statement: &mut Statement) {
+            walk_statement_mut(self, statement);
+            match statement {
+                Statement::Import(Import { span, .. })
+                | Statement::Throw { span, .. }
+                | Statement::Struct { span, .. }
+                | Statement::Enum { span, .. }
+                | Statement::Let(LetStatement { span, .. }) => *span = Span::default(),
+                _ => {}
+            }
+        }
+    }
+
+    fn parse_with_spans(input: &str) -> Program {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+
+        assert!(
+            parser.errors().is_empty(),
+            "parser had errors: {:?}",
+            parser.errors()
+        );
+
+        program
+    }
+
+    fn parse(input: &str) -> Program {
+        let mut program = parse_with_spans(input);
+        EraseSpans.visit_program_mut(&mut program);
+        program
+    }
+
+    fn parse_expression(input: &str) -> Expression {
+        let program = parse(input);
+        assert_eq!(1, program.statements.len(), "{program:?}");
+
+        match program.statements.into_iter().next() {
+            Some(Statement::Expression(expression)) => expression,
+            other => panic!("expected an expression statement, got {other:?}"),
+        }
+    }
+
+    fn parameter(name: &str, default: Option<Expression>) -> Parameter {
+        Parameter {
+            name: name.into(),
+            annotation: None,
+            default,
+            span: Span::default(),
+        }
+    }
+
+    fn ident(name: &str) -> Expression {
+        Expression::Identifier {
+            name: name.into(),
+            span: Span::default(),
+            global: Cell::default(),
+            slot: Cell::default(),
+        }
+    }
+
+    fn infix(left: Expression, operator: InfixOperator, right: Expression) -> Expression {
+        Expression::Infix {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+            span: Span::default(),
+        }
+    }
+
+    fn prefix(operator: PrefixOperator, right: Expression) -> Expression {
+        Expression::Prefix {
+            operator,
+            right: Box::new(right),
+            span: Span::default(),
+        }
+    }
+
+    #[test]
+    fn test_let_statements() {
+        let input = r#"
+        let x = 5;
+        let y = true;
+        const foobar = y;
+        "#;
+
+        let expected = vec![
+            (DeclarationKind::Let, "x", Expression::Int(5)),
+            (DeclarationKind::Let, "y", Expression::Bool(true)),
+            (DeclarationKind::Const, "foobar", ident("y")),
+        ];
+
+        let program = parse(input);
+        assert_eq!(expected.len(), program.statements.len());
+
+        for ((kind, name, value), statement) in expected.into_iter().zip(program.statements) {
+            let expected_statement = Statement::Let(LetStatement {
+                kind,
+                pattern: Pattern::Identifier(name.into()),
+                value,
+                docs: vec![],
+                annotation: None,
+                span: Span::default(),
+            });
+            assert_eq!(expected_statement, statement);
+        }
+    }
+
+    #[test]
+    fn test_let_statement_errors() {
+        let mut parser = Parser::new(Lexer::new("let = 5; let x 5; let *;"));
+        parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "1:5: expected a name or pattern after `let`, found `=`",
+                "1:16: expected `=` after `let x`, found int",
+                "1:23: expected a name or pattern after `let`, found `*`",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_import_statements() {
+        let program = parse("import utils\nimport \"lib/my_shapes.tofu\";");
+        assert_eq!(
+            vec![
+                Statement::Import(Import {
+                    source: ImportSource::Name("utils".to_string()),
+                    name: "utils".into(),
+                    span: Span::default(),
+                }),
+                Statement::Import(Import {
+                    source: ImportSource::Path("lib/my_shapes.tofu".to_string()),
+                    name: "my_shapes".into(),
+                    span: Span::default(),
+                }),
+            ],
+            program.statements
+        );
+
+        let mut parser = Parser::new(Lexer::new(
+            r#"import 1; import "my-utils.tofu"; import "if.tofu"; import;"#,
+        ));
+        parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "1:8: expected a module name or string after `import`, found int",
+                r#"1:18: cannot import "my-utils.tofu": its file name isn't a valid name"#,
+                r#"1:42: cannot import "if.tofu": its file name isn't a valid name"#,
+                "1:59: expected a module name or string after `import`, found `;`",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_parser_recovers_at_statement_boundaries() {
+        let input = "let a = (1 + 2;\nlet b = 2;\n* 3;\nlet c = 3\nreturn c";
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+
+        assert_eq!(
+            vec![
+                ParseError {
+                    message: "expected `)` to close `(`, found `;`".to_string(),
+                    span: Span {
+                        line: 1,
+                        column: 15
+                    },
+                },
+                ParseError {
+                    message: "expected an expression, found `*`".to_string(),
+                    span: Span { line: 3, column: 1 },
+                },
+            ],
+            parser.errors()
+        );
+        assert_eq!("let b = 2;\nlet c = 3;\nreturn c;", program.to_string());
+    }
+
+    #[test]
+    fn test_lexer_errors_are_reported() {
+        let mut parser = Parser::new(Lexer::new("let a = @;\nlet b = \"oops"));
+        parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "1:9: unexpected character '@' (U+0040)",
+                "2:9: unterminated string literal",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_docs_attach_to_let() {
+        let input = r#"
+        /// The answer.
+        /// Obviously.
+        let answer = 42;
+        let question = null;
+        "#;
+
+        let program = parse(input);
+
+        let docs: Vec<Vec<String>> = program
+            .statements
+            .iter()
+            .map(|statement| match statement {
+                Statement::Let(statement) => statement.docs.clone(),
+                other => panic!("expected let, got {other:?}"),
+            })
+            .collect();
+
+        assert_eq!(
+            vec![
+                vec!["The answer.".to_string(), "Obviously.".to_string()],
+                vec![],
+            ],
+            docs
+        );
+    }
+
+    #[test]
+    fn test_type_annotations() {
+        let input = r#"
+        let age: int = 3;
+        let add = fn(x: int, y: int = 1) -> int { x + y };
+        let grid: hash<str, array<array<float>>> = {};
+        let apply = fn(f: fn(int) -> null, x) { f(x) };
+        "#;
+
+        let program = parse(input);
+        assert_eq!(
+            "let age: int = 3;\n\
+             let add = fn(x: int, y: int = 1) -> int { x + y; };\n\
+             let grid: hash<str, array<array<float>>> = {};\n\
+             let apply = fn(f: fn(int) -> null, x) { f(x); };",
+            program.to_string()
+        );
+        let Statement::Let(statement) = &program.statements[0] else {
+            panic!("expected let, got {:?}", program.statements[0]);
+        };
+        assert_eq!(
+            Some(TypeAnnotation::Named {
+                name: "int".to_string(),
+                arguments: vec![],
+                span: Span {
+                    line: 2,
+                    column: 18
+                },
+            }),
+            statement.annotation
+        );
+
+        let mut parser = Parser::new(Lexer::new("let x: = 1; let f = fn() -> { 1 };"));
+        parser.parse_program();
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "1:8: expected a type, found `=`",
+                "1:29: expected a type, found `{`",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_return_statements() {
+        let input = r#"
+        return 5;
+        return x;
+        return;
+        "#;
+
+        let program = parse(input);
+
+        assert_eq!(
+            vec![
+                Statement::Return(Some(Expression::Int(5))),
+                Statement::Return(Some(ident("x"))),
+                Statement::Return(None),
+            ],
+            program.statements
+        );
+    }
+
+    #[test]
+    fn test_literal_expressions() {
+        let tests = vec![
+            ("foobar;", ident("foobar")),
+            ("5;", Expression::Int(5)),
+            ("0xFF;", Expression::Int(255)),
+            ("2.5;", Expression::Float(2.5)),
+            (r#""hello";"#, Expression::Str("hello".to_string())),
+            ("'c';", Expression::Char('c')),
+            ("true;", Expression::Bool(true)),
+            ("false;", Expression::Bool(false)),
+            ("null;", Expression::Null),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(expected, parse_expression(input), "input={input}");
+        }
+    }
+
+    #[test]
+    fn test_prefix_expressions() {
+        let tests = vec![
+            ("!5;", prefix(PrefixOperator::Bang, Expression::Int(5))),
+            ("-15;", prefix(PrefixOperator::Minus, Expression::Int(15))),
+            (
+                "!true;",
+                prefix(PrefixOperator::Bang, Expression::Bool(true)),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(expected, parse_expression(input), "input={input}");
+        }
+    }
+
+    #[test]
+    fn test_infix_expressions() {
+        let tests = vec![
+            ("5 + 5;", InfixOperator::Plus),
+            ("5 - 5;", InfixOperator::Minus),
+            ("5 * 5;", InfixOperator::Multiply),
+            ("5 ** 5;", InfixOperator::Power),
+            ("5 / 5;", InfixOperator::Divide),
+            ("5 > 5;", InfixOperator::GreaterThan),
+            ("5 < 5;", InfixOperator::LessThan),
+            ("5 >= 5;", InfixOperator::GreaterThanEq),
+            ("5 <= 5;", InfixOperator::LessThanEq),
+            ("5 == 5;", InfixOperator::Eq),
+            ("5 != 5;", InfixOperator::NotEq),
+            ("5 & 5;", InfixOperator::BitAnd),
+            ("5 | 5;", InfixOperator::BitOr),
+            ("5 ^ 5;", InfixOperator::BitXor),
+            ("5 << 5;", InfixOperator::ShiftLeft),
+            ("5 >> 5;", InfixOperator::ShiftRight),
+            ("5 && 5;", InfixOperator::And),
+            ("5 || 5;", InfixOperator::Or),
+        ];
+
+        for (input, operator) in tests {
+            let expected = infix(Expression::Int(5), operator, Expression::Int(5));
+            assert_eq!(expected, parse_expression(input), "input={input}");
+        }
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        use InfixOperator::*;
+
+        let tests = vec![
+            (
+                "-a * b",
+                infix(
+                    prefix(PrefixOperator::Minus, ident("a")),
+                    Multiply,
+                    ident("b"),
+                ),
+            ),
+            (
+                "-a ** b ** -c * d",
+                infix(
+                    prefix(
+                        PrefixOperator::Minus,
+                        infix(
+                            ident("a"),
+                            Power,
+                            infix(ident("b"), Power, prefix(PrefixOperator::Minus, ident("c"))),
+                        ),
+                    ),
+                    Multiply,
+                    ident("d"),
+                ),
+            ),
+            (
+                "a + b * c",
+                infix(ident("a"), Plus, infix(ident("b"), Multiply, ident("c"))),
+            ),
+            (
+                "a + b - c",
+                infix(infix(ident("a"), Plus, ident("b")), Minus, ident("c")),
+            ),
+            (
+                "(a + b) * c",
+                infix(infix(ident("a"), Plus, ident("b")), Multiply, ident("c")),
+            ),
+            (
+                "a < b == c > d",
+                infix(
+                    infix(ident("a"), LessThan, ident("b")),
+                    Eq,
+                    infix(ident("c"), GreaterThan, ident("d")),
+                ),
+            ),
+            (
+                "a || b && c",
+                infix(ident("a"), Or, infix(ident("b"), And, ident("c"))),
+            ),
+            (
+                "a | b ^ c & d",
+                infix(
+                    ident("a"),
+                    BitOr,
+                    infix(ident("b"), BitXor, infix(ident("c"), BitAnd, ident("d"))),
+                ),
+            ),
+            (
+                "1 << 2 + 3 < 4",
+                infix(
+                    infix(
+                        Expression::Int(1),
+                        ShiftLeft,
+                        infix(Expression::Int(2), Plus, Expression::Int(3)),
+                    ),
+                    LessThan,
+                    Expression::Int(4),
+                ),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(expected, parse_expression(input), "input={input}");
+        }
+    }
+
+    #[test]
+    fn test_if_expressions() {
+        let expected = Expression::If {
+            condition: Box::new(infix(ident("x"), InfixOperator::LessThan, ident("y"))),
+            consequence: BlockStatement {
+                statements: vec![Statement::Expression(ident("x"))],
+            },
+            alternative: Some(BlockStatement {
+                statements: vec![
+                    Statement::Let(LetStatement {
+                        kind: DeclarationKind::Let,
+                        pattern: Pattern::Identifier("z".into()),
+                        value: ident("y"),
+                        docs: vec![],
+                        annotation: None,
+                        span: Span::default(),
+                    }),
+                    Statement::Expression(ident("z")),
+                ],
+            }),
+        };
+
+        assert_eq!(
+            expected,
+            parse_expression("if (x < y) { x } else { let z = y; z }")
+        );
+    }
+
+    #[test]
+    fn test_if_expression_errors() {
+        let mut parser = Parser::new(Lexer::new("if x { 1 }; if (x) { 1 + } let y = 2;"));
+        let program = parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "1:4: expected `(` after `if`, found identifier",
+                "1:26: expected an expression, found `}`",
+            ],
+            errors
+        );
+        assert_eq!("if (x) {};\nlet y = 2;", program.to_string());
+    }
+
+    #[test]
+    fn test_try_expressions() {
+        let expected = Expression::Try {
+            body: BlockStatement {
+                statements: vec![Statement::Throw {
+                    value: ident("x"),
+                    span: Span::default(),
+                }],
+            },
+            pattern: Pattern::Hash(vec![(
+                "message".to_string(),
+                Pattern::Identifier("message".into()),
+            )]),
+            handler: BlockStatement {
+                statements: vec![Statement::Expression(ident("message"))],
+            },
+        };
+
+        assert_eq!(
+            expected,
+            parse_expression("try { throw x } catch ({message}) { message }")
+        );
+
+        let mut parser = Parser::new(Lexer::new(
+            "try { 1 }; try { 1 } catch e { e }; try { 1 } catch (*) {}; throw;",
+        ));
+        parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "1:10: expected `catch` after the `try` body, found `;`",
+                "1:28: expected `(` after `catch`, found identifier",
+                "1:54: expected a name or pattern to name the caught error, found `*`",
+                "1:66: expected an expression, found `;`",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_function_literals_and_calls() {
+        let function = Expression::Function {
+            parameters: Parameters {
+                positional: vec![parameter("x", None), parameter("y", None)],
+                rest: None,
+            },
+            return_type: None,
+            body: Rc::new(BlockStatement {
+                statements: vec![Statement::Expression(infix(
+                    ident("x"),
+                    InfixOperator::Plus,
+                    ident("y"),
+                ))],
+            }),
+        };
+        assert_eq!(function, parse_expression("fn(x, y) { x + y }"));
+
+        assert_eq!(
+            Expression::Call {
+                function: Box::new(ident("add")),
+                arguments: vec![
+                    Expression::Int(1),
+                    infix(
+                        Expression::Int(2),
+                        InfixOperator::Multiply,
+                        Expression::Int(3)
+                    ),
+                ],
+                span: Span::default(),
+            },
+            parse_expression("add(1, 2 * 3)")
+        );
+
+        assert_eq!(
+            infix(
+                ident("a"),
+                InfixOperator::Plus,
+                Expression::Call {
+                    function: Box::new(Expression::Call {
+                        function: Box::new(ident("f")),
+                        arguments: vec![],
+                        span: Span::default(),
+                    }),
+                    arguments: vec![ident("b")],
+                    span: Span::default(),
+                },
+            ),
+            parse_expression("a + f()(b)")
+        );
+    }
+
+    #[test]
+    fn test_default_parameters() {
+        assert_eq!(
+            Expression::Function {
+                parameters: Parameters {
+                    positional: vec![
+                        parameter("greeting", None),
+                        parameter("name", Some(Expression::Str("world".to_string()))),
+                        parameter(
+                            "times",
+                            Some(infix(ident("x"), InfixOperator::Plus, Expression::Int(1)))
+                        ),
+                    ],
+                    rest: Some("rest".into()),
+                },
+                return_type: None,
+                body: Rc::default(),
+            },
+            parse_expression(r#"fn(greeting, name = "world", times = x + 1, ...rest) {}"#)
+        );
+
+        let mut parser = Parser::new(Lexer::new("fn(a = 1, b) {}; fn(a = ) {}"));
+        parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "1:11: parameter `b` needs a default, like the ones before it",
+                "1:25: expected an expression, found `)`",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_variadic_functions_and_spread() {
+        assert_eq!(
+            Expression::Function {
+                parameters: Parameters {
+                    positional: vec![parameter("first", None)],
+                    rest: Some("rest".into()),
+                },
+                return_type: None,
+                body: Rc::default(),
+            },
+            parse_expression("fn(first, ...rest) {}")
+        );
+
+        assert_eq!(
+            Expression::Call {
+                function: Box::new(ident("f")),
+                arguments: vec![
+                    Expression::Int(1),
+                    Expression::Spread(Box::new(infix(
+                        ident("a"),
+                        InfixOperator::Plus,
+                        ident("b")
+                    ))),
+                ],
+                span: Span::default(),
+            },
+            parse_expression("f(1, ...a + b)")
+        );
+
+        let mut parser = Parser::new(Lexer::new("fn(...rest, x) {}; fn(... 1) {}; ...xs; {...h}"));
+        parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "1:11: expected `)` after the rest parameter, found `,`",
+                "1:27: expected identifier after `...`, found int",
+                "1:34: expected an expression, found `...`",
+                "1:42: expected an expression, found `...`",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_pipelines() {
+        let call = |function: Expression, arguments: Vec<Expression>| Expression::Call {
+            function: Box::new(function),
+            arguments,
+            span: Span::default(),
+        };
+
+        assert_eq!(
+            call(
+                ident("save"),
+                vec![call(
+                    ident("validate"),
+                    vec![call(ident("parse"), vec![ident("data")]), ident("strict")]
+                )]
+            ),
+            parse_expression("data |> parse |> validate(strict) |> save")
+        );
+
+        // Looser than everything but `?:` and assignment.
+        assert_eq!(
+            call(
+                ident("sum"),
+                vec![Expression::Range {
+                    start: Some(Box::new(Expression::Int(0))),
+                    end: Some(Box::new(infix(
+                        ident("n"),
+                        InfixOperator::Plus,
+                        Expression::Int(1)
+                    ))),
+                    inclusive: false,
+                    span: Span::default(),
+                }]
+            ),
+            parse_expression("0..n + 1 |> sum")
+        );
+
+        assert_eq!(
+            Expression::MethodCall {
+                receiver: Box::new(ident("s")),
+                method: "join".into(),
+                arguments: vec![ident("xs")],
+                span: Span::default(),
+            },
+            parse_expression("xs |> s.join()")
+        );
+
+        let mut parser = Parser::new(Lexer::new("x |> ;"));
+        parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(vec!["1:6: expected an expression, found `;`"], errors);
+    }
+
+    #[test]
+    fn test_method_calls() {
+        assert_eq!(
+            Expression::MethodCall {
+                receiver: Box::new(Expression::MethodCall {
+                    receiver: Box::new(ident("xs")),
+                    method: "map".into(),
+                    arguments: vec![ident("f")],
+                    span: Span::default(),
+                }),
+                method: "len".into(),
+                arguments: vec![],
+                span: Span::default(),
+            },
+            parse_expression("xs.map(f).len()")
+        );
+
+        assert_eq!(
+            infix(
+                Expression::Prefix {
+                    operator: PrefixOperator::Minus,
+                    right: Box::new(Expression::MethodCall {
+                        receiver: Box::new(Expression::Int(1)),
+                        method: "abs".into(),
+                        arguments: vec![],
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
+                },
+                InfixOperator::Plus,
+                ident("a"),
+            ),
+            parse_expression("-1.abs() + a")
+        );
+
+        let mut parser = Parser::new(Lexer::new("xs.; xs.\"a\"()"));
+        parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "1:4: expected identifier after `.`, found `;`",
+                "1:9: expected identifier after `.`, found string",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_named_arguments() {
+        assert_eq!(
+            Expression::Call {
+                function: Box::new(ident("make_user")),
+                arguments: vec![
+                    ident("id"),
+                    Expression::Named {
+                        name: "name".into(),
+                        value: Box::new(Expression::Str("John".to_string())),
+                    },
+                    Expression::Named {
+                        name: "admin".into(),
+                        value: Box::new(Expression::Bool(true)),
+                    },
+                ],
+                span: Span::default(),
+            },
+            parse_expression(r#"make_user(id, name: "John", admin: true)"#)
+        );
+
+        let mut parser = Parser::new(Lexer::new(
+            "f(a: 1, 2); f(a: 1, ...b); f(a: 1, a: 2); [a: 1]",
+        ));
+        parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "1:9: positional argument after named arguments",
+                "1:21: positional argument after named arguments",
+                "1:36: argument `a` is named twice",
+                "1:45: expected `]` after array elements, found `:`",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_array_literals_and_index_expressions() {
+        assert_eq!(
+            Expression::Array(vec![
+                Expression::Int(1),
+                infix(
+                    Expression::Int(2),
+                    InfixOperator::Multiply,
+                    Expression::Int(2)
+                ),
+                Expression::Array(vec![]),
+            ]),
+            parse_expression("[1, 2 * 2, []]")
+        );
+
+        assert_eq!(
+            infix(
+                ident("a"),
+                InfixOperator::Multiply,
+                Expression::Index {
+                    left: Box::new(Expression::Index {
+                        left: Box::new(ident("xs")),
+                        index: Box::new(infix(
+                            Expression::Int(1),
+                            InfixOperator::Plus,
+                            Expression::Int(1),
+                        )),
+                        optional: false,
+                        span: Span::default(),
+                    }),
+                    index: Box::new(Expression::Int(0)),
+                    optional: false,
+                    span: Span::default(),
+                },
+            ),
+            parse_expression("a * xs[1 + 1][0]")
+        );
+    }
+
+    #[test]
+    fn test_tuple_literals_and_positional_access() {
+        assert_eq!(
+            Expression::Tuple(vec![
+                Expression::Int(1),
+                Expression::Str("a".to_string()),
+                Expression::Tuple(vec![ident("b")]),
+                Expression::Tuple(vec![]),
+            ]),
+            parse_expression(r#"(1, "a", (b,), ())"#)
+        );
+        assert_eq!(ident("a"), parse_expression("(a)"));
+
+        let index = |left, position| Expression::Index {
+            left: Box::new(left),
+            index: Box::new(Expression::Int(position)),
+            optional: false,
+            span: Span::default(),
+        };
+        assert_eq!(index(index(ident("t"), 0), 1), parse_expression("t.0.1"));
+
+        let program = parse("let (a, [b], (c,)) = x; let (d) = y;");
+        let patterns: Vec<_> = program
+            .statements
+            .iter()
+            .map(|statement| match statement {
+                Statement::Let(statement) => statement.pattern.clone(),
+                other => panic!("expected a let statement, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                Pattern::Tuple(vec![
+                    Pattern::Identifier("a".into()),
+                    Pattern::Array(vec![Pattern::Identifier("b".into())]),
+                    Pattern::Tuple(vec![Pattern::Identifier("c".into())]),
+                ]),
+                Pattern::Identifier("d".into()),
+            ],
+            patterns
+        );
+    }
+
+    #[test]
+    fn test_structs() {
+        let program = parse("struct Point { x, y } let Point { x, y: [z] } = p;");
+        assert_eq!(
+            vec![
+                Statement::Struct {
+                    name: "Point".into(),
+                    fields: vec!["x".into(), "y".into()],
+                    span: Span::default(),
+                },
+                Statement::Let(LetStatement {
+                    kind: DeclarationKind::Let,
+                    pattern: Pattern::Struct {
+                        name: "Point".into(),
+                        fields: vec![
+                            ("x".to_string(), Pattern::Identifier("x".into())),
+                            (
+                                "y".to_string(),
+                                Pattern::Array(vec![Pattern::Identifier("z".into())]),
+                            ),
+                        ],
+                    },
+                    value: ident("p"),
+                    docs: vec![],
+                    annotation: None,
+                    span: Span::default(),
+                }),
+            ],
+            program.statements
+        );
+
+        assert_eq!(
+            Expression::Index {
+                left: Box::new(ident("p")),
+                index: Box::new(Expression::Str("x".to_string())),
+                optional: false,
+                span: Span::default(),
+            },
+            parse_expression("p.x")
+        );
+
+        let tests = vec![
+            (
+                "struct { x }",
+                "1:8: expected identifier after `struct`, found `{`",
+            ),
+            (
+                "struct P { x, 1 }",
+                "1:15: expected identifier in struct `P`'s fields, found int",
+            ),
+            (
+                "struct Q { x, x }",
+                "1:15: struct `Q` has two fields called `x`",
+            ),
+        ];
+        for (input, expected) in tests {
+            let mut parser = Parser::new(Lexer::new(input));
+            parser.parse_program();
+            let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+            assert_eq!(vec![expected], errors, "input={input}");
+        }
+    }
+
+    #[test]
+    fn test_enums_and_match() {
+        let program = parse(
+            "enum Shape { Circle(r), Rect(w, h), Dot } match s { Circle(r) => r, Dot => { 0 } }",
+        );
+        let arm = |pattern, body| MatchArm {
+            pattern,
+            body: BlockStatement {
+                statements: vec![Statement::Expression(body)],
+            },
+        };
+        assert_eq!(
+            vec![
+                Statement::Enum {
+                    name: "Shape".into(),
+                    variants: vec![
+                        VariantDeclaration {
+                            name: "Circle".into(),
+                            fields: vec!["r".into()],
+                        },
+                        VariantDeclaration {
+                            name: "Rect".into(),
+                            fields: vec!["w".into(), "h".into()],
+                        },
+                        VariantDeclaration {
+                            name: "Dot".into(),
+                            fields: vec![],
+                        },
+                    ],
+                    span: Span::default(),
+                },
+                Statement::Expression(Expression::Match {
+                    subject: Box::new(ident("s")),
+                    arms: vec![
+                        arm(
+                            Pattern::Variant {
+                                name: "Circle".into(),
+                                fields: vec![Pattern::Identifier("r".into())],
+                            },
+                            ident("r"),
+                        ),
+                        arm(Pattern::Identifier("Dot".into()), Expression::Int(0)),
+                    ],
+                    span: Span::default(),
+                }),
+            ],
+            program.statements
+        );
+
+        let program = parse(
+            r#"match x { 1 => 0, -2 => 0, "s" => 0, 'c' => 0, false => 0, null => 0, _ => 0 }"#,
+        );
+        let Statement::Expression(Expression::Match { arms, .. }) = &program.statements[0] else {
+            panic!("expected a `match`, got {:?}", program.statements[0]);
+        };
+        let patterns: Vec<&Pattern> = arms.iter().map(|arm| &arm.pattern).collect();
+        assert_eq!(
+            vec![
+                &Pattern::Literal(Literal::Int(1)),
+                &Pattern::Literal(Literal::Int(-2)),
+                &Pattern::Literal(Literal::Str("s".to_string())),
+                &Pattern::Literal(Literal::Char('c')),
+                &Pattern::Literal(Literal::Bool(false)),
+                &Pattern::Literal(Literal::Null),
+                &Pattern::Wildcard,
+            ],
+            patterns
+        );
+
+        let tests = vec![
+            (
+                "enum { A }",
+                "1:6: expected identifier after `enum`, found `{`",
+            ),
+            (
+                "enum E { A, A }",
+                "1:13: enum `E` has two variants called `A`",
+            ),
+            (
+                "enum E { A(x, x) }",
+                "1:15: variant `A` has two fields called `x`",
+            ),
+            (
+                "enum E { A B }",
+                "1:12: expected `,` between variants, found identifier",
+            ),
+            (
+                "match x { a 1 }",
+                "1:13: expected `=>` after `a`, found int",
+            ),
+            (
+                "match x { a => 1 b => 2 }",
+                "1:18: expected `,` between `match` arms, found identifier",
+            ),
+            (
+                "match x { * => 2 }",
+                "1:11: expected a name or pattern to start a `match` arm, found `*`",
+            ),
+            (
+                "match x { -a => 2 }",
+                "1:12: expected int after `-` in a pattern, found identifier",
+            ),
+        ];
+        for (input, expected) in tests {
+            let mut parser = Parser::new(Lexer::new(input));
+            parser.parse_program();
+            let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+            assert_eq!(vec![expected], errors, "input={input}");
+        }
+    }
+
+    #[test]
+    fn test_conditional_expressions() {
+        let conditional = |condition, consequence, alternative| Expression::Conditional {
+            condition: Box::new(condition),
+            consequence: Box::new(consequence),
+            alternative: Box::new(alternative),
+        };
+
+        assert_eq!(
+            conditional(
+                infix(ident("a"), InfixOperator::Or, ident("b")),
+                ident("c"),
+                conditional(ident("d"), ident("e"), ident("f"))
+            ),
+            parse_expression("a || b ? c : d ? e : f")
+        );
+        assert_eq!(
+            Expression::Assign {
+                target: Box::new(ident("x")),
+                operator: None,
+                value: Box::new(conditional(
+                    ident("a"),
+                    Expression::Int(1),
+                    infix(Expression::Int(2), InfixOperator::Plus, Expression::Int(3))
+                )),
+                span: Span::default(),
+            },
+            parse_expression("x = a ? 1 : 2 + 3")
+        );
+        assert_eq!(
+            Expression::Hash(vec![(
+                conditional(ident("a"), ident("b"), ident("c")),
+                ident("d")
+            )]),
+            parse_expression("{a ? b : c: d}")
+        );
+
+        let mut parser = Parser::new(Lexer::new("a ? b; a ? b : c = 1;"));
+        parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "1:6: expected `:` in a conditional expression, found `;`",
+                "1:18: cannot assign to `a ? b : c`, only to a name or an index",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_nullish_expressions() {
+        let optional_index = |left, index| Expression::Index {
+            left: Box::new(left),
+            index: Box::new(index),
+            optional: true,
+            span: Span::default(),
+        };
+
+        assert_eq!(
+            infix(
+                optional_index(
+                    optional_index(ident("a"), Expression::Str("b".to_string())),
+                    Expression::Int(0)
+                ),
+                InfixOperator::Coalesce,
+                ident("c")
+            ),
+            parse_expression("a?.b?[0] ?? c")
+        );
+        // `??` binds looser than `||`, and chains to the left.
+        assert_eq!(
+            infix(
+                infix(
+                    infix(ident("a"), InfixOperator::Or, ident("b")),
+                    InfixOperator::Coalesce,
+                    ident("c")
+                ),
+                InfixOperator::Coalesce,
+                infix(ident("d"), InfixOperator::Eq, ident("e"))
+            ),
+            parse_expression("a || b ?? c ?? d == e")
+        );
+
+        let mut parser = Parser::new(Lexer::new("a?.1; a?.b = 1; a?[0] += 1;"));
+        parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "1:4: expected identifier after `?.`, found int",
+                r#"1:12: cannot assign to `a?["b"]`, only to a name or an index"#,
+                "1:23: cannot assign to `a?[0]`, only to a name or an index",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_hash_literals() {
+        assert_eq!(Expression::Hash(vec![]), parse_expression("{}"));
+
+        assert_eq!(
+            Expression::Hash(vec![
+                (
+                    Expression::Str("name".to_string()),
+                    Expression::Str("John".to_string())
+                ),
+                (
+                    Expression::Int(1),
+                    infix(Expression::Int(1), InfixOperator::Plus, Expression::Int(2))
+                ),
+                (Expression::Bool(true), ident("x")),
+            ]),
+            parse_expression(r#"{"name": "John", 1: 1 + 2, true: x,}"#)
+        );
+
+        let mut parser = Parser::new(Lexer::new(r#"{"a" 1}; {"a": 1 "b": 2}"#));
+        parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "1:6: expected `:` after a hash key, found int",
+                "1:18: expected `,` between hash entries, found string",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_assign_expressions() {
+        let assign = |target, operator, value| Expression::Assign {
+            target: Box::new(target),
+            operator,
+            value: Box::new(value),
+            span: Span::default(),
+        };
+
+        assert_eq!(
+            assign(
+                ident("x"),
+                None,
+                infix(ident("x"), InfixOperator::Plus, Expression::Int(1))
+            ),
+            parse_expression("x = x + 1")
+        );
+        assert_eq!(
+            assign(
+                ident("a"),
+                None,
+                assign(ident("b"), Some(InfixOperator::Multiply), ident("c"))
+            ),
+            parse_expression("a = b *= c")
+        );
+        assert_eq!(
+            assign(
+                Expression::Index {
+                    left: Box::new(ident("xs")),
+                    index: Box::new(Expression::Int(0)),
+                    optional: false,
+                    span: Span::default(),
+                },
+                Some(InfixOperator::Minus),
+                Expression::Int(2)
+            ),
+            parse_expression("xs[0] -= 2")
+        );
+
+        let mut parser = Parser::new(Lexer::new("1 = 2; f() += 1; x = 1;"));
+        parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "1:3: cannot assign to `1`, only to a name or an index",
+                "1:12: cannot assign to `f()`, only to a name or an index",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_range_expressions() {
+        assert_eq!(
+            Expression::Range {
+                start: Some(Box::new(Expression::Int(0))),
+                end: Some(Box::new(infix(
+                    ident("n"),
+                    InfixOperator::Minus,
+                    Expression::Int(1)
+                ))),
+                inclusive: false,
+                span: Span::default(),
+            },
+            parse_expression("0..n - 1")
+        );
+
+        assert_eq!(
+            Expression::Range {
+                start: Some(Box::new(ident("a"))),
+                end: Some(Box::new(infix(ident("b"), InfixOperator::Or, ident("c")))),
+                inclusive: true,
+                span: Span::default(),
+            },
+            parse_expression("a..=b || c")
+        );
+
+        // A slice can leave out either bound, but only a slice.
+        let slice = |start, end| Expression::Index {
+            left: Box::new(ident("xs")),
+            index: Box::new(Expression::Range {
+                start,
+                end,
+                inclusive: false,
+                span: Span::default(),
+            }),
+            optional: false,
+            span: Span::default(),
+        };
+        let bound = |name| Some(Box::new(ident(name)));
+        assert_eq!(slice(None, bound("n")), parse_expression("xs[..n]"));
+        assert_eq!(slice(bound("i"), None), parse_expression("xs[i..]"));
+        assert_eq!(slice(None, None), parse_expression("xs[..]"));
+        assert_eq!(
+            Expression::Index {
+                left: Box::new(ident("xs")),
+                index: Box::new(Expression::Conditional {
+                    condition: Box::new(ident("c")),
+                    consequence: Box::new(ident("a")),
+                    alternative: Box::new(ident("b")),
+                }),
+                optional: false,
+                span: Span::default(),
+            },
+            parse_expression("xs[c ? a : b]")
+        );
+
+        for input in ["[1..]", "xs[1..=]", "(..2)"] {
+            let mut parser = Parser::new(Lexer::new(input));
+            parser.parse_program();
+            assert!(!parser.errors().is_empty(), "input={input}");
+        }
+    }
+
+    #[test]
+    fn test_expression_spans() {
+        let program = parse_with_spans("let y = -x;\nf(a + 1)[0] = 1..=2;");
+
+        let mut spans = Vec::new();
+        for statement in &program.statements {
+            let expression = match statement {
+                Statement::Let(statement) => &statement.value,
+                Statement::Expression(expression) => expression,
+                other => panic!("unexpected statement {other:?}"),
+            };
+            spans.push(expression.span());
+        }
+
+        let span = |line, column| Some(Span { line, column });
+        assert_eq!(vec![span(1, 9), span(2, 13)], spans);
+
+        let Statement::Expression(Expression::Assign { target, value, .. }) =
+            &program.statements[1]
+        else {
+            panic!("expected an assignment");
+        };
+        let Expression::Index { left, .. } = &**target else {
+            panic!("expected an index target");
+        };
+        let Expression::Call {
+            function,
+            arguments,
+            ..
+        } = &**left
+        else {
+            panic!("expected a call");
+        };
+
+        assert_eq!(
+            vec![span(2, 9), span(2, 2), span(2, 1), span(2, 5), span(2, 16)],
+            vec![
+                target.span(),
+                left.span(),
+                function.span(),
+                arguments[0].span(),
+                value.span(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let mut parser = Parser::new(Lexer::new("add(1, 2;\nfn(x y) { x };"));
+        parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "1:9: expected `)` after arguments, found `;`",
+                "2:6: expected `)` after parameters, found identifier",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_while_statements() {
+        let program = parse("while (x < 10) { if (x) { break; } continue }");
+
+        assert_eq!(
+            vec![Statement::While {
+                condition: infix(ident("x"), InfixOperator::LessThan, Expression::Int(10)),
+                body: BlockStatement {
+                    statements: vec![
+                        Statement::Expression(Expression::If {
+                            condition: Box::new(ident("x")),
+                            consequence: BlockStatement {
+                                statements: vec![Statement::Break],
+                            },
+                            alternative: None,
+                        }),
+                        Statement::Continue,
+                    ],
+                },
+            }],
+            program.statements
+        );
+    }
+
+    #[test]
+    fn test_for_statements() {
+        let program = parse("for (x in [1, 2]) { x }");
+
+        assert_eq!(
+            vec![Statement::For {
+                pattern: Pattern::Identifier("x".into()),
+                iterable: Expression::Array(vec![Expression::Int(1), Expression::Int(2)]),
+                body: BlockStatement {
+                    statements: vec![Statement::Expression(ident("x"))],
+                },
+            }],
+            program.statements
+        );
+
+        let mut parser = Parser::new(Lexer::new("for (x of xs) {}"));
+        parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec!["1:8: expected `in` after the loop variable, found identifier"],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_destructuring_patterns() {
+        let program = parse("let [a, [b], {name, age: years}] = x;");
+
+        let pattern = Pattern::Array(vec![
+            Pattern::Identifier("a".into()),
+            Pattern::Array(vec![Pattern::Identifier("b".into())]),
+            Pattern::Hash(vec![
+                ("name".to_string(), Pattern::Identifier("name".into())),
+                ("age".to_string(), Pattern::Identifier("years".into())),
+            ]),
+        ]);
+        assert_eq!(
+            vec![Statement::Let(LetStatement {
+                kind: DeclarationKind::Let,
+                pattern,
+                value: ident("x"),
+                docs: vec![],
+                annotation: None,
+                span: Span::default(),
+            })],
+            program.statements
+        );
+
+        let mut parser = Parser::new(Lexer::new(
+            "let [a, *] = x; let {a: } = x; let {\"a\"} = x;",
+        ));
+        parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "1:9: expected a name or pattern in an array pattern, found `*`",
+                "1:25: expected a name or pattern after `a:`, found `}`",
+                "1:37: expected identifier in a hash pattern, found string",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_nesting_depth_limit() {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+
+        parse(&nested(DEFAULT_MAX_DEPTH - 1));
+        parse(&format!("{}x", "-".repeat(DEFAULT_MAX_DEPTH - 1)));
+
+        let hostile = format!("{} let x = 1;", "(".repeat(100_000));
+        let mut parser = Parser::new(Lexer::new(&hostile));
+        let program = parser.parse_program();
+
+        assert_eq!(
+            vec![ParseError {
+                message: "expression nested too deeply (the limit is 256)".to_string(),
+                span: Span {
+                    line: 1,
+                    column: 257
+                },
+            }],
+            parser.errors()
+        );
+        assert_eq!("let x = 1;", program.to_string());
+
+        // A chain of operators doesn't nest, however long it is, and the
+        // program it makes can still be printed and dropped.
+        for chain in [" + 1", "()", "[0]", ".x", " |> f"] {
+            let program = parse(&format!("x{};", chain.repeat(100_000)));
+            assert!(program.to_string().len() > 100_000);
+        }
+        let hostile = format!("{};\nlet x = 1;", "1 + (".repeat(100_000));
+        let mut parser = Parser::new(Lexer::new(&hostile));
+        let program = parser.parse_program();
+
+        let errors: Vec<&str> = parser.errors().iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            vec!["expression nested too deeply (the limit is 256)"],
+            errors
+        );
+        assert_eq!("let x = 1;", program.to_string());
+
+        let mut parser = Parser::new(Lexer::new("if (a) { if (b) { [[c]] } }"));
+        parser.set_max_depth(4);
+        parser.parse_program();
+
+        let errors: Vec<String> = parser.errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec!["1:21: expression nested too deeply (the limit is 4)"],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        let tests = vec![
+            ("let x = 1 * (2 + 3);", "let x = 1 * (2 + 3);"),
+            ("a + b + c", "a + b + c;"),
+            ("a - (b - c)", "a - (b - c);"),
+            ("(((a)))", "a;"),
+            ("-(a + b) * !c", "-(a + b) * !c;"),
+            ("a || b && c", "a || b && c;"),
+            ("(a || b) && c", "(a || b) && c;"),
+            ("const f = 2.0; return;", "const f = 2.0;\nreturn;"),
+            ("\"a\\\"b\\n\" + 'c'", "\"a\\\"b\\n\" + 'c';"),
+            ("/// Docs.\nlet x = null;", "/// Docs.\nlet x = null;"),
+            (
+                "if (x < y) { x } else if (y) { y } else {}",
+                "if (x < y) { x; } else { if (y) { y; } else {}; };",
+            ),
+            (
+                "let add = fn(a, b) { a + b }; add(1, -f(2))",
+                "let add = fn(a, b) { a + b; };\nadd(1, -f(2));",
+            ),
+            ("fn() {}()", "fn() {}();"),
+            ("[1, [2]][0] + -xs[f(1)]", "[1, [2]][0] + -xs[f(1)];"),
+            ("(-xs)[0]", "(-xs)[0];"),
+            (r#"{"a": 1, b: [2]}["a"]"#, r#"{ "a": 1, b: [2] }["a"];"#),
+            ("{}", "{};"),
+            ("while (true) { break } x", "while (true) { break; }\nx;"),
+            ("for (x in xs) { x }", "for (x in xs) { x; }"),
+            (
+                "let [a, [], {b, c: [d]}] = x",
+                "let [a, [], { b, c: [d] }] = x;",
+            ),
+            ("for ([k, v] in h) {}", "for ([k, v] in h) {}"),
+            ("xs[1..n + 1]", "xs[1..n + 1];"),
+            ("a = b += c[0] /= 2", "a = b += c[0] /= 2;"),
+            ("(a = 1) + 2", "(a = 1) + 2;"),
+            ("(a..b)..=(c || d)", "(a..b)..=c || d;"),
+            ("a ? b : c ? d : e", "a ? b : c ? d : e;"),
+            ("(a ? b : c) ? d : e", "(a ? b : c) ? d : e;"),
+            ("x = a || b ? c = 1 : d", "x = a || b ? c = 1 : d;"),
+            ("(a ? b : c)[0]", "(a ? b : c)[0];"),
+            (
+                "import utils\nimport \"../lib/ünï.tofu\"",
+                "import utils;\nimport \"../lib/ünï.tofu\";",
+            ),
+            (
+                "fn(...xs) { [...xs, 1] }(...a)",
+                "fn(...xs) { [...xs, 1]; }(...a);",
+            ),
+            ("f(a, ...b ? c : d)", "f(a, ...b ? c : d);"),
+            (
+                "fn(a, b = [a], c = fn(d = 1) { d }) {}",
+                "fn(a, b = [a], c = fn(d = 1) { d; }) {};",
+            ),
+            (
+                "try { f() } catch ([e]) { throw e + 1 }",
+                "try { f(); } catch ([e]) { throw e + 1; };",
+            ),
+            ("f(a, b: c ? 1 : 2, d: [])", "f(a, b: c ? 1 : 2, d: []);"),
+            ("(a + b).f(c).g()[0]", "(a + b).f(c).g()[0];"),
+            ("a ? b : c |> f(d) |> g", "a ? b : g(f(c, d));"),
+            ("-a ** b ** -c", "-a ** b ** -c;"),
+            (
+                "(-a) ** (b ** c) * (d ** e) ** f",
+                "(-a) ** b ** c * (d ** e) ** f;",
+            ),
+            ("a ** f(b)[0]", "a ** f(b)[0];"),
+            ("1.abs(); 1.5.floor()", "1.abs();\n1.5.floor();"),
+            ("((1,), (a, b), ())", "((1,), (a, b), ());"),
+            ("let (a, (b,)) = t; t.0", "let (a, (b,)) = t;\nt[0];"),
+            (
+                "xs[..n] + xs[i + 1..] + xs[..] + xs[-2..=-1]",
+                "xs[..n] + xs[i + 1..] + xs[..] + xs[-2..=-1];",
+            ),
+            (
+                "struct P { x, y, }; struct E {}",
+                "struct P { x, y }\nstruct E {}",
+            ),
+            (
+                "enum S { C(r,), D, }; match s { C(r) => r, D => { 1; 2 } }",
+                "enum S { C(r), D }\nmatch s { C(r) => { r; }, D => { 1; 2; } };",
+            ),
+            (
+                "let P { x, y: [z] } = p; p.x",
+                "let P { x, y: [z] } = p;\np[\"x\"];",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let program = parse(input);
+            let printed = program.to_string();
+            assert_eq!(expected, printed, "input={input}");
+            assert_eq!(program, parse(&printed), "input={input}");
+        }
+    }
+}
@@ -0,0 +1,520 @@
+//! A peephole optimizer for compiled code, which `-O` runs after
+//! compiling. It rewrites short runs of instructions into fewer that do the
+//! same, over and over until nothing changes:
+//!
+//! - a value that's pushed only to be popped again isn't pushed at all;
+//! - comparisons of constants, `!` of `true`, `false` and `null`, and
+//!   conditional jumps on values known when compiling are worked out
+//!   ahead of time;
+//! - a jump to a jump goes straight to where the last one goes, and a jump
+//!   to the next instruction is dropped;
+//! - instructions no path reaches, like those after a `return` or a
+//!   `break`, are removed.
+//!
+//! Nothing it removes can fail or has an effect, so a program behaves the
+//! same optimized or not.
+
+use std::rc::Rc;
+
+use crate::code::{make, read_operands, Bytecode, CompiledFunction, Constant, Opcode};
+use crate::token::Span;
+
+/// Optimizes the code of the program and every function in it.
+pub fn optimize(bytecode: &mut Bytecode) {
+    bytecode.main = Rc::new(optimize_function(&bytecode.main, &bytecode.constants));
+
+    for index in 0..bytecode.constants.len() {
+        if let Constant::Function(function) = &bytecode.constants[index] {
+            let optimized = optimize_function(function, &bytecode.constants);
+            bytecode.constants[index] = Constant::Function(Rc::new(optimized));
+        }
+    }
+}
+
+/// An instruction being optimized. Jumps' operands are the indexes of the
+/// instructions they go to rather than offsets, so instructions can be
+/// removed without working out every offset again.
+#[derive(Debug)]
+struct Instruction {
+    opcode: Opcode,
+    operands: Vec<usize>,
+    span: Option<Span>,
+}
+
+impl Instruction {
+    fn new(opcode: Opcode, operands: Vec<usize>) -> Instruction {
+        Instruction {
+            opcode,
+            operands,
+            span: None,
+        }
+    }
+}
+
+fn is_jump(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::Jump
+            | Opcode::JumpIfFalse
+            | Opcode::JumpIfNull
+            | Opcode::JumpIfNotNull
+            | Opcode::IterNext
+    )
+}
+
+fn optimize_function(function: &CompiledFunction, constants: &[Constant]) -> CompiledFunction {
+    let mut instructions = decode(function);
+
+    loop {
+        let mut changed = remove_unreachable(&mut instructions);
+        changed |= thread_jumps(&mut instructions);
+        changed |= fold(&mut instructions, constants);
+        if !changed {
+            break;
+        }
+    }
+
+    let (code, spans) = encode(&instructions);
+    CompiledFunction {
+        code,
+        spans,
+        ..function.clone()
+    }
+}
+
+fn decode(function: &CompiledFunction) -> Vec<Instruction> {
+    let code = &function.code;
+    let mut offsets = Vec::new();
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+
+    while offset < code.len() {
+        let opcode = Opcode::from_byte(code[offset]).expect("compiled code only has opcodes");
+        let (operands, next) = read_operands(opcode, code, offset);
+        offsets.push(offset);
+        instructions.push(Instruction {
+            opcode,
+            operands,
+            span: function.span_at(offset),
+        });
+        offset = next;
+    }
+
+    for instruction in &mut instructions {
+        if is_jump(instruction.opcode) {
+            let target = &mut instruction.operands[0];
+            *target = offsets
+                .binary_search(target)
+                .expect("jumps go to the start of an instruction");
+        }
+    }
+
+    instructions
+}
+
+/// Encodes the instructions, giving the code and its spans.
+fn encode(instructions: &[Instruction]) -> (Vec<u8>, Vec<(usize, Span)>) {
+    let mut offsets = Vec::with_capacity(instructions.len());
+    let mut offset = 0;
+    for instruction in instructions {
+        offsets.push(offset);
+        offset += 1 + instruction.opcode.operand_widths().iter().sum::<usize>();
+    }
+
+    let mut code = Vec::with_capacity(offset);
+    let mut spans = Vec::new();
+    for (instruction, offset) in instructions.iter().zip(&offsets) {
+        let mut operands = instruction.operands.clone();
+        if is_jump(instruction.opcode) {
+            operands[0] = offsets[operands[0]];
+        }
+        if let Some(span) = instruction.span {
+            spans.push((*offset, span));
+        }
+        code.extend(make(instruction.opcode, &operands));
+    }
+
+    (code, spans)
+}
+
+/// Which instructions some jump goes to.
+fn jump_targets(instructions: &[Instruction]) -> Vec<bool> {
+    let mut targets = vec![false; instructions.len()];
+    for instruction in instructions {
+        if is_jump(instruction.opcode) {
+            targets[instruction.operands[0]] = true;
+        }
+    }
+    targets
+}
+
+/// Removes the instructions `keep` doesn't, sending jumps to a removed
+/// instruction to the next one that's kept instead. Returns whether any
+/// were removed.
+fn retain(instructions: &mut Vec<Instruction>, keep: &[bool]) -> bool {
+    if keep.iter().all(|keep| *keep) {
+        return false;
+    }
+
+    // The index each instruction will have if it's kept, which is the
+    // index the next kept one will have if it isn't.
+    let mut new_indexes = Vec::with_capacity(keep.len());
+    let mut kept = 0;
+    for keep in keep {
+        new_indexes.push(kept);
+        if *keep {
+            kept += 1;
+        }
+    }
+
+    let mut index = 0;
+    instructions.retain(|_| {
+        index += 1;
+        keep[index - 1]
+    });
+    for instruction in instructions.iter_mut() {
+        if is_jump(instruction.opcode) {
+            instruction.operands[0] = new_indexes[instruction.operands[0]];
+        }
+    }
+
+    true
+}
+
+/// Removes the instructions no path from the start of the function
+/// reaches.
+fn remove_unreachable(instructions: &mut Vec<Instruction>) -> bool {
+    let mut reached = vec![false; instructions.len()];
+    let mut pending = vec![0];
+
+    while let Some(index) = pending.pop() {
+        if index >= instructions.len() || reached[index] {
+            continue;
+        }
+        reached[index] = true;
+
+        let instruction = &instructions[index];
+        match instruction.opcode {
+            Opcode::Return => {}
+            Opcode::Jump => pending.push(instruction.operands[0]),
+            opcode if is_jump(opcode) => {
+                pending.push(instruction.operands[0]);
+                pending.push(index + 1);
+            }
+            _ => pending.push(index + 1),
+        }
+    }
+
+    retain(instructions, &reached)
+}
+
+/// Sends jumps that go to an unconditional jump straight to where it goes,
+/// then drops unconditional jumps to the next instruction.
+fn thread_jumps(instructions: &mut Vec<Instruction>) -> bool {
+    let mut changed = false;
+
+    for index in 0..instructions.len() {
+        if !is_jump(instructions[index].opcode) {
+            continue;
+        }
+        let mut target = instructions[index].operands[0];
+        // Jumps can go round in a loop, which stops where it started over.
+        let mut seen = vec![index, target];
+        while instructions[target].opcode == Opcode::Jump {
+            let next = instructions[target].operands[0];
+            if seen.contains(&next) {
+                break;
+            }
+            seen.push(next);
+            target = next;
+        }
+        if target != instructions[index].operands[0] {
+            instructions[index].operands[0] = target;
+            changed = true;
+        }
+    }
+
+    let keep: Vec<bool> = instructions
+        .iter()
+        .enumerate()
+        .map(|(index, instruction)| {
+            !(instruction.opcode == Opcode::Jump && instruction.operands[0] == index + 1)
+        })
+        .collect();
+    retain(instructions, &keep) || changed
+}
+
+/// Whether the instruction only pushes a value, so it can go if the value
+/// is popped straight away.
+fn only_pushes(instruction: &Instruction) -> bool {
+    matches!(
+        instruction.opcode,
+        Opcode::Constant
+            | Opcode::Null
+            | Opcode::True
+            | Opcode::False
+            | Opcode::GetLocal
+            | Opcode::GetFree
+            | Opcode::GetBuiltin
+            | Opcode::Closure
+    )
+}
+
+/// The value the instruction pushes, if it only pushes one known when
+/// compiling that's truthy or falsy whatever it is.
+fn known_truthiness(instruction: &Instruction) -> Option<bool> {
+    match instruction.opcode {
+        Opcode::True | Opcode::Constant | Opcode::Closure => Some(true),
+        Opcode::False | Opcode::Null => Some(false),
+        _ => None,
+    }
+}
+
+/// The result of comparing two constants with `opcode`, if it's a
+/// comparison of two of the same type that compare the way Rust does. Ints
+/// and floats compare with each other in their own way, so they're left to
+/// run.
+fn compare(opcode: Opcode, left: &Constant, right: &Constant) -> Option<bool> {
+    let ordering = match (left, right) {
+        (Constant::Int(left), Constant::Int(right)) => left.cmp(right),
+        (Constant::Str(left), Constant::Str(right)) => left.cmp(right),
+        (Constant::Char(left), Constant::Char(right)) => left.cmp(right),
+        _ => return None,
+    };
+
+    Some(match opcode {
+        Opcode::Equal => ordering.is_eq(),
+        Opcode::NotEqual => ordering.is_ne(),
+        Opcode::LessThan => ordering.is_lt(),
+        Opcode::GreaterThan => ordering.is_gt(),
+        Opcode::LessThanEqual => ordering.is_le(),
+        Opcode::GreaterThanEqual => ordering.is_ge(),
+        _ => return None,
+    })
+}
+
+fn bool_instruction(value: bool) -> Instruction {
+    Instruction::new(if value { Opcode::True } else { Opcode::False }, Vec::new())
+}
+
+/// Rewrites runs of instructions whose outcome is known when compiling.
+/// Only the first instruction of a run can be a jump target, since a jump
+/// into the middle would skip part of it.
+fn fold(instructions: &mut Vec<Instruction>, constants: &[Constant]) -> bool {
+    let targets = jump_targets(instructions);
+    let mut keep = vec![true; instructions.len()];
+    let mut index = 0;
+
+    let constant = |instruction: &Instruction| match instruction.opcode {
+        Opcode::Constant => Some(&constants[instruction.operands[0]]),
+        _ => None,
+    };
+
+    while index + 1 < instructions.len() {
+        let (first, second) = (&instructions[index], &instructions[index + 1]);
+        if targets[index + 1] {
+            index += 1;
+            continue;
+        }
+
+        if only_pushes(first) && second.opcode == Opcode::Pop {
+            keep[index] = false;
+            keep[index + 1] = false;
+            index += 2;
+            continue;
+        }
+
+        if second.opcode == Opcode::JumpIfFalse {
+            if let Some(truthy) = known_truthiness(first) {
+                if truthy {
+                    keep[index] = false;
+                } else {
+                    instructions[index] =
+                        Instruction::new(Opcode::Jump, instructions[index + 1].operands.clone());
+                }
+                keep[index + 1] = false;
+                index += 2;
+                continue;
+            }
+        }
+
+        if second.opcode == Opcode::Not
+            && matches!(first.opcode, Opcode::True | Opcode::False | Opcode::Null)
+        {
+            instructions[index] = bool_instruction(first.opcode != Opcode::True);
+            keep[index + 1] = false;
+            index += 2;
+            continue;
+        }
+
+        if index + 2 < instructions.len() && !targets[index + 2] {
+            let result = constant(first)
+                .zip(constant(second))
+                .and_then(|(left, right)| compare(instructions[index + 2].opcode, left, right));
+            if let Some(result) = result {
+                instructions[index] = bool_instruction(result);
+                keep[index + 1] = false;
+                keep[index + 2] = false;
+                index += 3;
+                continue;
+            }
+        }
+
+        index += 1;
+    }
+
+    retain(instructions, &keep)
+}
+
+#[cfg(test)]
+mod test {
+    use super::optimize;
+    use crate::builtins::Builtins;
+    use crate::code::{make, Bytecode, Opcode};
+    use crate::compiler::compile;
+    use crate::evaluator::Evaluator;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::vm;
+
+    fn compile_input(input: &str) -> Bytecode {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+
+        assert!(
+            parser.errors().is_empty(),
+            "parser had errors: {:?}",
+            parser.errors()
+        );
+
+        compile(&program, &Builtins::standard()).expect("the program compiles")
+    }
+
+    type Instruction = (Opcode, &'static [usize]);
+
+    fn instructions(instructions: &[Instruction]) -> Vec<u8> {
+        instructions
+            .iter()
+            .flat_map(|(opcode, operands)| make(*opcode, operands))
+            .collect()
+    }
+
+    #[test]
+    fn test_optimize() {
+        use Opcode::*;
+
+        let tests: Vec<(&str, Vec<Instruction>)> = vec![
+            // The pushed value is popped straight away.
+            ("1; 2", vec![(Constant, &[1]), (Return, &[])]),
+            ("1 < 2", vec![(True, &[]), (Return, &[])]),
+            ("\"a\" == \"b\"", vec![(False, &[]), (Return, &[])]),
+            ("'a' <= 'b'", vec![(True, &[]), (Return, &[])]),
+            ("!null", vec![(True, &[]), (Return, &[])]),
+            // Mixed types are left to run.
+            (
+                "1 == 1.0",
+                vec![
+                    (Constant, &[0]),
+                    (Constant, &[1]),
+                    (Equal, &[]),
+                    (Return, &[]),
+                ],
+            ),
+            // The condition is always truthy, so the `else` branch goes.
+            (
+                "if (1 < 2) { 3 } else { 4 }",
+                vec![(Constant, &[2]), (Return, &[])],
+            ),
+            (
+                "if (false) { 3 } else { 4 }",
+                vec![(Constant, &[1]), (Return, &[])],
+            ),
+            // An empty loop that runs forever jumps to itself.
+            ("while (true) {}", vec![(Jump, &[0])]),
+            // The code after `return` goes.
+            ("return 1; 2", vec![(Constant, &[0]), (Return, &[])]),
+        ];
+
+        for (input, expected) in tests {
+            let mut bytecode = compile_input(input);
+            optimize(&mut bytecode);
+            assert_eq!(instructions(&expected), bytecode.main.code, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_threads_jumps_and_keeps_spans() {
+        use Opcode::*;
+
+        let mut bytecode =
+            compile_input("let f = fn(a, b) { if (a) { if (b) { 1 } else { 2 } } else { c } }");
+        optimize(&mut bytecode);
+
+        let crate::code::Constant::Function(function) = &bytecode.constants[2] else {
+            panic!("expected a function");
+        };
+        assert_eq!(
+            instructions(&[
+                (GetLocal, &[0]),
+                (JumpIfFalse, &[24]),
+                (GetLocal, &[1]),
+                (JumpIfFalse, &[18]),
+                (Constant, &[0]),
+                // The inner `if`'s jump goes past the outer one's.
+                (Jump, &[27]),
+                (Constant, &[1]),
+                (Jump, &[27]),
+                (GetGlobal, &[0]),
+                (Return, &[]),
+            ]),
+            function.code
+        );
+        assert!(function.span_at(24).is_some());
+    }
+
+    /// Checks each program gives the same result optimized as it does not.
+    #[test]
+    fn test_same_results() {
+        let tests = [
+            "let s = 0; for (x in 0..10) { if (x == 3) { continue; } if (x > 6) { break; } s += x; } s",
+            "let i = 0; while (i < 10) { i += 1; if (i == 5) { break; } } i",
+            "let f = fn(n) { if (n < 2) { return n; } f(n - 1) + f(n - 2) }; f(10)",
+            "let c = fn() { let n = 0; fn() { n += 1; n } }(); c(); c()",
+            "let a = null; a ?? (1 < 2 && \"x\" != \"y\")",
+            "let xs = []; for (x in [1, 2, 3]) { push(xs, x * 2); null; } xs",
+            "if (!false) { 1; 2 } else { 3 }",
+            // The errors' spans move with the code.
+            "1; null; x",
+            "let f = fn() { 1; true; 2 < 3; y }; f()",
+        ];
+
+        for input in tests {
+            let bytecode = compile_input(input);
+            let mut optimized = bytecode.clone();
+            optimize(&mut optimized);
+
+            assert!(
+                optimized.main.code.len() <= bytecode.main.code.len(),
+                "{input}"
+            );
+            assert_eq!(
+                vm::run(&mut Evaluator::new(), &bytecode),
+                vm::run(&mut Evaluator::new(), &optimized),
+                "{input}"
+            );
+            assert_eq!(
+                Ok(&optimized),
+                Bytecode::decode(&optimized.encode()).as_ref()
+            );
+        }
+    }
+
+    #[test]
+    fn test_empty_loop_uses_fuel() {
+        let mut bytecode = compile_input("while (true) {}");
+        optimize(&mut bytecode);
+
+        let error = vm::run(&mut Evaluator::with_fuel(100), &bytecode).unwrap_err();
+        assert_eq!("out of fuel", error.message);
+    }
+}
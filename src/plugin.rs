@@ -0,0 +1,256 @@
+//! Plugins: compiled Rust crates that add builtins, shipped apart from the
+//! interpreter and loaded when it starts.
+//!
+//! A plugin is a `cdylib` crate depending on this one, with a type that
+//! implements [`Plugin`] and a [`declare_plugin!`] naming it:
+//!
+//! ```
+//! use tofu_interpreter::plugin::{Plugin, Registrar};
+//! use tofu_interpreter::{declare_plugin, Arity, Value};
+//!
+//! #[derive(Default)]
+//! struct Greeter;
+//!
+//! impl Plugin for Greeter {
+//!     fn name(&self) -> &str {
+//!         "greeter"
+//!     }
+//!
+//!     fn register(&self, registrar: &mut dyn Registrar) {
+//!         registrar.register(
+//!             "greet",
+//!             Arity::Exact(0),
+//!             Box::new(|_, _| Ok(Value::Str("hello from a plugin".to_string()))),
+//!         );
+//!     }
+//! }
+//!
+//! declare_plugin!(Greeter::default());
+//! ```
+//!
+//! With the `plugins` feature, [`load`] loads one from its library, and
+//! the binary loads every library in `~/.tofu/plugins` and each given with
+//! `--plugin=<path>`. A "module" is a builtin bound to a hash of functions,
+//! defined with [`Registrar::define`].
+//!
+//! A plugin's library has its own copy of this crate, with its own
+//! thread-locals, such as the table [`Symbol`](crate::symbol::Symbol)s are
+//! interned in. So a plugin adds its builtins through the interpreter's
+//! [`Registrar`] rather than its own copy of [`Builtins`], and its builtins
+//! should stick to the values they're given and make.
+//!
+//! Rust has no stable ABI, so a plugin has to be built with the same
+//! compiler as the interpreter, against the same version of this crate with
+//! the same features. [`load`] refuses it otherwise, rather than crash.
+
+use std::ffi::c_char;
+
+use crate::builtins::{Arity, BuiltinFunction, Builtins};
+use crate::value::Value;
+
+/// The version of the [`Plugin`] interface. It goes up whenever the trait
+/// or [`Declaration`] changes.
+pub const API_VERSION: u32 = 1;
+
+/// What must match between a plugin and the interpreter loading it: this
+/// crate's version, the compiler's, and the features that change how
+/// values are laid out. Ends with a NUL, for [`Declaration`].
+pub const BUILD: &str = concat!(env!("TOFU_BUILD"), "\0");
+
+/// A set of builtins from outside the interpreter.
+pub trait Plugin: Send + Sync {
+    /// What the plugin is called, for messages about it.
+    fn name(&self) -> &str;
+
+    /// Adds the plugin's builtins to `registrar`. It's called for each
+    /// evaluator made, so it can't assume it's only called once.
+    fn register(&self, registrar: &mut dyn Registrar);
+}
+
+/// Where a [`Plugin`] adds its builtins.
+pub trait Registrar {
+    /// See [`Builtins::register`].
+    fn register(&mut self, name: &str, arity: Arity, function: Box<BuiltinFunction>);
+
+    /// See [`Builtins::define`].
+    fn define(&mut self, name: &str, value: Value);
+}
+
+impl Registrar for Builtins {
+    fn register(&mut self, name: &str, arity: Arity, function: Box<BuiltinFunction>) {
+        Builtins::register(self, name, arity, function);
+    }
+
+    fn define(&mut self, name: &str, value: Value) {
+        Builtins::define(self, name, value);
+    }
+}
+
+/// What [`declare_plugin!`] exports from a plugin's library, for [`load`]
+/// to find. It starts with what says whether the rest can be trusted.
+#[repr(C)]
+pub struct Declaration {
+    pub api_version: u32,
+    /// [`BUILD`], as the plugin was built.
+    pub build: *const c_char,
+    pub create: fn() -> Box<dyn Plugin>,
+}
+
+// SAFETY: `build` points to a string constant, which is never written.
+unsafe impl Sync for Declaration {}
+
+/// Exports the [`Plugin`] that `$plugin` makes from a plugin's library.
+#[macro_export]
+macro_rules! declare_plugin {
+    ($plugin:expr) => {
+        #[no_mangle]
+        pub static TOFU_PLUGIN: $crate::plugin::Declaration = $crate::plugin::Declaration {
+            api_version: $crate::plugin::API_VERSION,
+            build: $crate::plugin::BUILD.as_ptr().cast(),
+            create: || Box::new($plugin),
+        };
+    };
+}
+
+#[cfg(feature = "plugins")]
+pub use loader::{load, load_dir, PluginError};
+
+#[cfg(feature = "plugins")]
+mod loader {
+    use std::ffi::CStr;
+    use std::fmt::Display;
+    use std::path::{Path, PathBuf};
+
+    use libloading::Library;
+
+    use super::{Declaration, Plugin, API_VERSION, BUILD};
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct PluginError {
+        pub path: PathBuf,
+        pub message: String,
+    }
+
+    impl Display for PluginError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(
+                f,
+                "could not load plugin `{}`: {}",
+                self.path.display(),
+                self.message
+            )
+        }
+    }
+
+    impl std::error::Error for PluginError {}
+
+    /// Loads the plugin in the library at `path`. The library stays loaded
+    /// until the process ends, since the builtins it adds run its code.
+    pub fn load(path: &Path) -> Result<&'static dyn Plugin, PluginError> {
+        let error = |message: String| PluginError {
+            path: path.to_path_buf(),
+            message,
+        };
+        // SAFETY: loading a library runs its initializers, which is what
+        // asking for a plugin to be loaded trusts it to do.
+        let library = unsafe { Library::new(path) }.map_err(|e| error(e.to_string()))?;
+        let library: &'static Library = Box::leak(Box::new(library));
+        // SAFETY: `TOFU_PLUGIN` is only exported by `declare_plugin!`, as a
+        // `Declaration`, whose first two fields are read before the rest is
+        // trusted.
+        let declaration = unsafe {
+            let symbol = library
+                .get::<*const Declaration>(b"TOFU_PLUGIN\0")
+                .map_err(|_| error("it isn't a Tofu plugin".to_string()))?;
+            &**symbol
+        };
+        if declaration.api_version != API_VERSION {
+            return Err(error(format!(
+                "it's for plugin interface version {}, not {API_VERSION}",
+                declaration.api_version
+            )));
+        }
+        // SAFETY: with the same interface version, `build` is a C string.
+        let build = unsafe { CStr::from_ptr(declaration.build) }.to_string_lossy();
+        let expected = BUILD.trim_end_matches('\0');
+        if build != expected {
+            return Err(error(format!(
+                "it was built for `{build}`, not `{expected}`"
+            )));
+        }
+        Ok(Box::leak((declaration.create)()))
+    }
+
+    /// Loads every library in `dir`, in order of their names. A directory
+    /// that doesn't exist has none.
+    pub fn load_dir(dir: &Path) -> Result<Vec<&'static dyn Plugin>, PluginError> {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return Ok(Vec::new());
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == std::env::consts::DLL_EXTENSION)
+            })
+            .collect();
+        paths.sort();
+        paths.iter().map(|path| load(path)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Plugin, Registrar};
+    use crate::builtins::Arity;
+    use crate::repl::Options;
+    use crate::value::Value;
+
+    struct Doubler;
+
+    impl Plugin for Doubler {
+        fn name(&self) -> &str {
+            "doubler"
+        }
+
+        fn register(&self, registrar: &mut dyn Registrar) {
+            registrar.register(
+                "double",
+                Arity::Exact(1),
+                Box::new(|_, arguments| match &arguments[0] {
+                    Value::Int(value) => Ok(Value::Int(value * 2)),
+                    _ => Ok(Value::Null),
+                }),
+            );
+        }
+    }
+
+    #[test]
+    fn test_plugin() {
+        let options = Options {
+            plugins: &[&Doubler],
+            ..Options::default()
+        };
+        let program = crate::parse("double(21)").unwrap();
+        assert_eq!(
+            Ok(Value::Int(42)),
+            options.evaluator().eval_program(&program)
+        );
+    }
+
+    #[cfg(feature = "plugins")]
+    #[test]
+    fn test_load() {
+        use std::path::Path;
+
+        let error = |path: &Path| super::load(path).map(|_| ()).unwrap_err();
+        let missing = Path::new("/nonexistent/libplugin.so");
+        assert_eq!(missing, error(missing).path);
+
+        // A file that isn't a library at all.
+        let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        assert_eq!(manifest, error(&manifest).path);
+
+        assert_eq!(0, super::load_dir(Path::new("/nonexistent")).unwrap().len());
+    }
+}
@@ -0,0 +1,191 @@
+//! Counts calls of Tofu functions and the time spent in them, for
+//! `--profile`. Functions are told apart by the name they were defined
+//! with, so every anonymous function shares one row of the report.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+const ANONYMOUS: &str = "anonymous function";
+
+/// What a function's calls added up to.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Entry {
+    pub calls: u64,
+    /// Time from the start of its outermost calls to their end, including
+    /// the functions they called. A recursive call's time is only counted
+    /// once, in the outermost call.
+    pub inclusive: Duration,
+    /// Time spent running its own code, leaving out the functions it
+    /// called.
+    pub exclusive: Duration,
+}
+
+/// A call in progress.
+struct Call {
+    function: String,
+    start: Instant,
+    /// Time spent in the functions it called so far.
+    callees: Duration,
+}
+
+/// Records the calls the evaluator or the VM makes, through
+/// [`Profiler::enter`] and [`Profiler::leave`], which must pair up.
+#[derive(Default)]
+pub struct Profiler {
+    entries: HashMap<String, Entry>,
+    calls: Vec<Call>,
+    /// How many calls of each function are in progress, so recursion isn't
+    /// counted twice in inclusive time.
+    active: HashMap<String, usize>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    /// Records the start of a call of the function named `function`.
+    pub fn enter(&mut self, function: Option<&str>) {
+        let function = function.unwrap_or(ANONYMOUS).to_string();
+        *self.active.entry(function.clone()).or_default() += 1;
+        self.calls.push(Call {
+            function,
+            start: Instant::now(),
+            callees: Duration::ZERO,
+        });
+    }
+
+    /// Records the end of the innermost call in progress.
+    pub fn leave(&mut self) {
+        let Some(call) = self.calls.pop() else {
+            return;
+        };
+        let elapsed = call.start.elapsed();
+        if let Some(caller) = self.calls.last_mut() {
+            caller.callees += elapsed;
+        }
+
+        let active = self
+            .active
+            .get_mut(&call.function)
+            .expect("a call in progress is active");
+        *active -= 1;
+        let outermost = *active == 0;
+
+        let entry = self.entries.entry(call.function).or_default();
+        entry.calls += 1;
+        entry.exclusive += elapsed.saturating_sub(call.callees);
+        if outermost {
+            entry.inclusive += elapsed;
+        }
+    }
+
+    /// What each function's calls added up to, the one with the most
+    /// exclusive time first.
+    pub fn entries(&self) -> Vec<(&str, &Entry)> {
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .map(|(function, entry)| (function.as_str(), entry))
+            .collect();
+        entries.sort_by(|(a, a_entry), (b, b_entry)| {
+            b_entry
+                .exclusive
+                .cmp(&a_entry.exclusive)
+                .then_with(|| a.cmp(b))
+        });
+        entries
+    }
+}
+
+/// A table of [`Profiler::entries`], with times in milliseconds.
+impl fmt::Display for Profiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>10}  {:>14}  {:>14}  function",
+            "calls", "inclusive ms", "exclusive ms"
+        )?;
+        for (function, entry) in self.entries() {
+            writeln!(
+                f,
+                "{:>10}  {:>14.3}  {:>14.3}  {function}",
+                entry.calls,
+                entry.inclusive.as_secs_f64() * 1000.0,
+                entry.exclusive.as_secs_f64() * 1000.0,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Profiler;
+    use crate::evaluator::Evaluator;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::vm;
+
+    fn calls(profiler: &Profiler) -> Vec<(&str, u64)> {
+        let mut calls: Vec<_> = profiler
+            .entries()
+            .into_iter()
+            .map(|(function, entry)| (function, entry.calls))
+            .collect();
+        calls.sort();
+        calls
+    }
+
+    const PROGRAM: &str = "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; \
+                           let twice = fn(f, x) { f(f(x)) }; \
+                           twice(fn(x) { fib(x) }, 5)";
+
+    #[test]
+    fn test_counts_calls() {
+        let mut parser = Parser::new(Lexer::new(PROGRAM));
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+        let expected = vec![("anonymous function", 2), ("fib", 30), ("twice", 1)];
+
+        let mut evaluator = Evaluator::new();
+        evaluator.set_profiling(true);
+        evaluator.eval_program(&program).unwrap();
+        assert_eq!(expected, calls(evaluator.profiler().unwrap()));
+
+        let bytecode = crate::compiler::compile(&program, Evaluator::new().builtins()).unwrap();
+        let mut evaluator = Evaluator::new();
+        evaluator.set_profiling(true);
+        vm::run(&mut evaluator, &bytecode).unwrap();
+        assert_eq!(expected, calls(evaluator.profiler().unwrap()));
+    }
+
+    #[test]
+    fn test_times() {
+        let mut profiler = Profiler::new();
+        profiler.enter(Some("outer"));
+        profiler.enter(Some("outer"));
+        profiler.enter(None);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        profiler.leave();
+        profiler.leave();
+        profiler.leave();
+
+        let entries = profiler.entries();
+        // The anonymous function spent the most time in its own code.
+        let [("anonymous function", anonymous), ("outer", outer)] = entries.as_slice() else {
+            panic!("expected the anonymous function then outer, got {entries:?}");
+        };
+        assert_eq!(2, outer.calls);
+        assert!(anonymous.exclusive >= std::time::Duration::from_millis(5));
+        assert!(outer.exclusive < anonymous.exclusive);
+        // Counted once, though two calls of it were running.
+        assert!(outer.inclusive >= anonymous.inclusive);
+        assert!(outer.inclusive < anonymous.inclusive * 2);
+
+        let report = profiler.to_string();
+        assert!(report.starts_with("     calls    inclusive ms    exclusive ms  function\n"));
+        assert_eq!(3, report.lines().count());
+    }
+}
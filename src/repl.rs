@@ -1,27 +1,870 @@
-use std::io::{Stdin, Stdout, Write};
+use std::borrow::Cow;
+use std::io::{self, BufRead, Empty, IsTerminal, Stdin, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Instant;
 
-use crate::{lexer::Lexer, token::TokenKind};
+use rustyline::completion::Completer;
+use rustyline::config::Configurer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Editor, Helper};
 
-pub fn start(stdin: Stdin, mut stdout: Stdout) {
-    loop {
-        write!(stdout, ">> ").expect("Uh-oh, failed to write.");
-        stdout.flush().expect("Should have flushed stdout 🚽");
+use crate::{
+    ast::Program,
+    builtins::Builtins,
+    compiler::compile,
+    diagnostic::{Diagnostic, Sources},
+    error::TofuError,
+    evaluator::{ErrorKind, Evaluator, Overflow, RuntimeError, DEFAULT_MAX_DEPTH},
+    highlight::highlight,
+    interrupt,
+    lexer::{LexErrorKind, Lexer},
+    parser::Parser,
+    peephole::optimize,
+    plugin::Plugin,
+    sexp,
+    symbol::Symbol,
+    token::TokenKind,
+    typecheck::{self, Type},
+    value::Value,
+};
+
+/// Settings that apply to every line the REPL runs, or to a whole script
+/// run with `--check`.
+#[derive(Clone, Copy)]
+pub struct Options {
+    /// Constant-fold each line before it runs.
+    pub fold_constants: bool,
+    /// Optimize compiled code. See [`crate::peephole`].
+    pub optimize: bool,
+    pub overflow: Overflow,
+    /// See [`Evaluator::set_max_depth`].
+    pub max_depth: usize,
+    /// See [`Evaluator::set_fuel`].
+    pub fuel: Option<u64>,
+    /// See [`Evaluator::set_max_memory`].
+    pub max_memory: Option<usize>,
+    /// See [`Evaluator::set_allow_files`].
+    pub allow_files: bool,
+    /// See [`Evaluator::set_allow_env`].
+    pub allow_env: bool,
+    /// See [`Evaluator::set_allow_exec`].
+    pub allow_exec: bool,
+    /// See [`Evaluator::set_allow_net`].
+    pub allow_net: bool,
+    /// Load the prelude. See [`Evaluator::load_prelude`].
+    pub prelude: bool,
+    /// See [`Evaluator::set_profiling`].
+    pub profile: bool,
+    /// Log what's evaluated to stderr. See [`Evaluator::set_trace`].
+    pub trace: bool,
+    /// Color code as it's typed, and errors. [`start_terminal`] and
+    /// `main` only do when they go to a terminal. See [`crate::highlight`]
+    /// and [`crate::diagnostic`].
+    pub color: bool,
+    /// What the REPL prompts for each line with.
+    pub prompt: &'static str,
+    /// How many lines the REPL keeps in its history.
+    pub history_size: usize,
+    /// What runs scripts. The REPL always evaluates the tree, so what
+    /// each line binds stays bound.
+    pub engine: Engine,
+    /// Plugins whose builtins every evaluator gets. See [`crate::plugin`].
+    pub plugins: &'static [&'static dyn Plugin],
+}
+
+/// The ways a program can be run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// Walk the syntax tree. See [`crate::evaluator`].
+    #[default]
+    Tree,
+    /// Compile to bytecode and run that. See [`crate::vm`].
+    Vm,
+}
+
+impl FromStr for Engine {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Engine, ()> {
+        match name {
+            "tree" => Ok(Engine::Tree),
+            "vm" => Ok(Engine::Vm),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            fold_constants: false,
+            optimize: false,
+            overflow: Overflow::default(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            fuel: None,
+            max_memory: None,
+            allow_files: true,
+            allow_env: true,
+            allow_exec: true,
+            allow_net: true,
+            prelude: true,
+            profile: false,
+            trace: false,
+            color: true,
+            prompt: ">> ",
+            history_size: 100,
+            engine: Engine::default(),
+            plugins: &[],
+        }
+    }
+}
+
+impl Options {
+    /// The standard builtins and the plugins', for compiling code that
+    /// runs in an [`Options::evaluator`].
+    pub fn builtins(&self) -> Builtins {
+        let mut builtins = Builtins::standard();
+        for plugin in self.plugins {
+            plugin.register(&mut builtins);
+        }
+        builtins
+    }
+
+    /// A new evaluator with these settings, which searches the directories
+    /// listed in `TOFU_PATH` for modules.
+    pub fn evaluator(&self) -> Evaluator {
+        let mut evaluator = Evaluator::new();
+        for plugin in self.plugins {
+            plugin.register(evaluator.builtins_mut());
+        }
+        // Before the limits are set, which could be too low for it.
+        if self.prelude {
+            evaluator.load_prelude();
+        }
+        if let Some(module_path) = std::env::var_os("TOFU_PATH") {
+            evaluator.set_module_path(std::env::split_paths(&module_path).collect());
+        }
+        evaluator.set_overflow(self.overflow);
+        evaluator.set_max_depth(self.max_depth);
+        evaluator.set_fuel(self.fuel);
+        // Only a host without the counting allocator can't limit memory.
+        if let Err(error) = evaluator.set_max_memory(self.max_memory) {
+            eprintln!("warning: {}; running without one", error.message);
+        }
+        evaluator.set_allow_files(self.allow_files);
+        evaluator.set_allow_env(self.allow_env);
+        evaluator.set_allow_exec(self.allow_exec);
+        evaluator.set_allow_net(self.allow_net);
+        // After the prelude, so only the program's calls are recorded.
+        evaluator.set_profiling(self.profile);
+        if self.trace {
+            evaluator.set_trace(Some(Box::new(std::io::stderr())));
+        }
+        evaluator
+    }
+}
+
+/// The commands a line can start with instead of code, with what they
+/// take and what they do, for `:help`.
+const COMMANDS: &[(&str, &str)] = &[
+    (":help", "list these commands"),
+    (":quit", "leave the REPL"),
+    (":env", "list the bindings made so far, with their values"),
+    (":reset", "forget every binding made so far"),
+    (":clear", "clear the screen"),
+    (":load <file>", "run a script, keeping what it binds"),
+    (
+        ":save <file>",
+        "write the lines that ran without errors to a script",
+    ),
+    (":disasm <code>", "print the bytecode <code> compiles to"),
+    (":time <code>", "run <code> and report how long it took"),
+    (
+        ":type <code>",
+        "print the type inferred for <code>, or of the value it evaluates to",
+    ),
+    (
+        ":tokens on|off",
+        "print the tokens of each line before running it",
+    ),
+    (
+        ":ast on|off",
+        "print how each line parsed before running it",
+    ),
+];
+
+/// What the REPL prompts for each line of input after the first with.
+const CONTINUATION_PROMPT: &str = ".. ";
+
+/// Where the REPL reads lines from: a line editor on the terminal, with
+/// history kept in `~/.tofu_history` across sessions, or stdin or any
+/// other input as it comes.
+enum Lines<R> {
+    Editor {
+        editor: Box<Editor<Colors, DefaultHistory>>,
+        history: Option<PathBuf>,
+    },
+    // Stdin is locked for each line rather than for the whole session, so
+    // `input()` can read from it too.
+    Stdin(Stdin),
+    Plain(R),
+}
+
+impl Lines<Empty> {
+    /// The line editor, or stdin as it comes if the editor can't start.
+    fn terminal(options: &Options) -> Self {
+        let Ok(mut editor) = Editor::new() else {
+            return Lines::Stdin(std::io::stdin());
+        };
+        if options.color {
+            editor.set_helper(Some(Colors));
+        }
+        // Before the history is loaded, so it's cut down to size.
+        let _ = editor.set_max_history_size(options.history_size);
+        let history =
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".tofu_history"));
+        if let Some(history) = &history {
+            // There's no history yet the first time.
+            let _ = editor.load_history(history);
+        }
+        Lines::Editor {
+            editor: Box::new(editor),
+            history,
+        }
+    }
+}
+
+impl<R: BufRead> Lines<R> {
+    /// Prompts for the next line and reads it, returning `None` once the
+    /// input ends.
+    fn read(&mut self, prompt: &str, output: &mut impl Write) -> Option<std::io::Result<String>> {
+        match self {
+            Lines::Editor { editor, history } => loop {
+                match editor.readline(prompt) {
+                    Ok(line) => {
+                        if !line.trim().is_empty() {
+                            let _ = editor.add_history_entry(line.as_str());
+                            if let Some(history) = history {
+                                // Losing the history isn't worth stopping for.
+                                let _ = editor.save_history(history);
+                            }
+                        }
+                        return Some(Ok(line));
+                    }
+                    // Ctrl-C throws away the line being typed.
+                    Err(ReadlineError::Interrupted) => continue,
+                    Err(ReadlineError::Eof) => return None,
+                    Err(ReadlineError::Io(error)) => return Some(Err(error)),
+                    Err(error) => return Some(Err(std::io::Error::other(error))),
+                }
+            },
+            Lines::Stdin(stdin) => read_plain(prompt, output, |line| stdin.read_line(line)),
+            Lines::Plain(input) => read_plain(prompt, output, |line| input.read_line(line)),
+        }
+    }
+}
+
+/// Prompts for a line on `output` and reads it with `read_line`.
+fn read_plain(
+    prompt: &str,
+    output: &mut impl Write,
+    read_line: impl FnOnce(&mut String) -> std::io::Result<usize>,
+) -> Option<std::io::Result<String>> {
+    if let Err(error) = write!(output, "{prompt}").and_then(|()| output.flush()) {
+        return Some(Err(error));
+    }
+
+    let mut line = String::new();
+    match read_line(&mut line) {
+        // End of input, e.g. Ctrl-D.
+        Ok(0) => None,
+        // Without the newline, as the line editor gives lines.
+        Ok(_) => {
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+            Some(Ok(line))
+        }
+        Err(error) => Some(Err(error)),
+    }
+}
+
+/// Highlights the line being edited.
+struct Colors;
+
+impl Highlighter for Colors {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        Cow::Owned(highlight(line))
+    }
+
+    // Any character can change how the rest of the line lexes.
+    fn highlight_char(&self, _line: &str, _pos: usize, _kind: CmdKind) -> bool {
+        true
+    }
+}
 
-        let mut input = String::new();
+impl Completer for Colors {
+    type Candidate = String;
+}
+
+impl Hinter for Colors {
+    type Hint = String;
+}
 
-        if let Err(e) = stdin.read_line(&mut input) {
-            writeln!(stdout, "Error {e}").expect("Should have written error.");
-            return;
+impl Validator for Colors {
+    fn validate(&self, context: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let input = context.input();
+        if is_incomplete(input) && !input.ends_with('\n') {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
         }
+    }
+}
+
+impl Helper for Colors {}
+
+/// Reads, evaluates and prints lines from `input` until it ends or
+/// `:quit`, prompting for each on `output`. Every line runs in the same
+/// global environment, so what one line binds the next can use. A line can
+/// start with one of the [`COMMANDS`] instead. What the lines `print`, and
+/// read with `input()`, still goes through stdout and stdin. Fails if
+/// reading a line or writing to `output` does.
+pub fn start<R: BufRead, W: Write>(input: R, output: W, options: Options) -> Result<(), TofuError> {
+    run_lines(Lines::Plain(input), output, options)
+}
 
-        let mut lexer = Lexer::new(&input);
+/// Runs [`start`] on the terminal, editing lines as they're typed, with
+/// color unless `--no-color` was given or stdout isn't a terminal. Ctrl-C
+/// stops the line that's running, rather than the REPL.
+pub fn start_terminal(mut options: Options) -> Result<(), TofuError> {
+    let output = std::io::stdout();
+    options.color &= output.is_terminal();
+    let lines = Lines::terminal(&options);
+    // After the editor is made, since it sets up a handler of its own. It
+    // reads Ctrl-C as a key while editing, so doesn't miss it.
+    interrupt::install();
+    run_lines(lines, output, options)
+}
+
+fn run_lines<R: BufRead, W: Write>(
+    mut lines: Lines<R>,
+    mut output: W,
+    options: Options,
+) -> Result<(), TofuError> {
+    let mut session = Session::new(options);
 
-        loop {
-            let token = lexer.next();
-            if token.kind == TokenKind::Eof {
+    while let Some(input) = lines.read(options.prompt, &mut output) {
+        let mut input = input?;
+        // Input with a bracket, string or comment left open carries on to
+        // the next line, until one closes it, or is blank to run it as it
+        // is.
+        let mut ended = false;
+        while is_incomplete(&input) {
+            let Some(line) = lines.read(CONTINUATION_PROMPT, &mut output) else {
+                ended = true;
+                break;
+            };
+            let line = line?;
+            input.push('\n');
+            input.push_str(&line);
+            if line.trim().is_empty() {
                 break;
             }
-            writeln!(stdout, "{token:?}").expect("Should have written token.");
         }
+        if !session.run(input, &mut output)? || ended {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Whether `input` leaves a bracket, string or block comment open, so
+/// there's more of it to come.
+fn is_incomplete(input: &str) -> bool {
+    let mut lexer = Lexer::new(input);
+    let mut open = 0;
+    loop {
+        match lexer.next_token().kind {
+            TokenKind::LeftParen
+            | TokenKind::LeftBracket
+            | TokenKind::QuestionBracket
+            | TokenKind::LeftBrace => open += 1,
+            TokenKind::RightParen | TokenKind::RightBracket | TokenKind::RightBrace => open -= 1,
+            TokenKind::Eof => break,
+            _ => {}
+        }
+    }
+    open > 0
+        || lexer.errors().iter().any(|error| {
+            matches!(
+                error.kind,
+                LexErrorKind::UnterminatedString | LexErrorKind::UnterminatedBlockComment
+            )
+        })
+}
+
+/// What the REPL keeps from one line to the next.
+struct Session {
+    options: Options,
+    // Without the line editor, `input()` reads stdin through the same
+    // buffer as the prompt loop, so a line it takes is never run as code,
+    // and none is lost.
+    evaluator: Evaluator,
+    /// The global bindings there were before any line ran, like the
+    /// prelude's, which `:env` leaves out.
+    initial: Vec<(Symbol, Value)>,
+    /// Print each line's tokens before running it.
+    show_tokens: bool,
+    /// Print each line's AST, as s-expressions, before running it.
+    show_ast: bool,
+    /// Everything run so far, for showing where errors happened. Each line
+    /// and script is lexed with its lines numbered after the last one's, so
+    /// an error in a function defined lines ago still points to it.
+    sources: Sources,
+    /// The number the next line run gets.
+    next_line: usize,
+    /// The number the next line typed goes by in errors, counting only
+    /// the lines typed.
+    typed_line: usize,
+    /// The lines that ran without errors, for `:save`.
+    transcript: Vec<String>,
+    color: bool,
+    /// Whether a line called `exit`, ending the session.
+    exited: bool,
+}
+
+impl Session {
+    fn new(options: Options) -> Session {
+        let mut evaluator = options.evaluator();
+        evaluator.set_interrupt(Some(&interrupt::CTRL_C));
+        let initial = evaluator.globals();
+        Session {
+            options,
+            evaluator,
+            initial,
+            show_tokens: false,
+            show_ast: false,
+            sources: Sources::new("<repl>"),
+            next_line: 1,
+            typed_line: 1,
+            transcript: Vec::new(),
+            color: options.color,
+            exited: false,
+        }
+    }
+
+    /// Runs a line, or the command it starts with, and returns whether the
+    /// session carries on.
+    fn run(&mut self, mut input: String, output: &mut impl Write) -> io::Result<bool> {
+        match take_command(&mut input).as_deref() {
+            None => self.eval(&input, false, output)?,
+            Some("time") => self.eval(&input, true, output)?,
+            Some("type") => self.type_of(&input, output)?,
+            Some("help") => {
+                for (command, help) in COMMANDS {
+                    writeln!(output, "{command:<16} {help}")?;
+                }
+            }
+            Some("quit") => return Ok(false),
+            Some("env") => {
+                for (name, value) in self.evaluator.globals() {
+                    if !self.initial.contains(&(name.clone(), value.clone())) {
+                        writeln!(output, "{name} = {value}")?;
+                    }
+                }
+            }
+            Some("reset") => {
+                let (show_tokens, show_ast) = (self.show_tokens, self.show_ast);
+                *self = Session {
+                    show_tokens,
+                    show_ast,
+                    ..Session::new(self.options)
+                };
+            }
+            Some("tokens") => set_flag(&mut self.show_tokens, "tokens", input.trim(), output)?,
+            Some("ast") => set_flag(&mut self.show_ast, "ast", input.trim(), output)?,
+            Some("clear") => {
+                write!(output, "\x1b[2J\x1b[H")?;
+                output.flush()?;
+            }
+            Some("disasm") => self.disassemble(&input, output)?,
+            Some("load") => self.load(input.trim(), output)?,
+            Some("save") => self.save(input.trim(), output)?,
+            Some(other) => writeln!(output, "Error unknown command `:{other}`, try `:help`")?,
+        }
+        Ok(!self.exited)
+    }
+
+    /// Parses what was typed, or the script at `path`, or prints why it
+    /// couldn't.
+    fn parse(
+        &mut self,
+        input: &str,
+        path: Option<&str>,
+        output: &mut impl Write,
+    ) -> io::Result<Option<Program>> {
+        let first_line = self.next_line;
+        self.next_line += input.split('\n').count();
+        match path {
+            Some(path) => self.sources.add_at(path, input, first_line),
+            None => {
+                self.sources
+                    .add_part("<repl>", input, first_line, self.typed_line);
+                self.typed_line += input.split('\n').count();
+            }
+        }
+
+        let mut lexer = Lexer::starting_at_line(input, first_line);
+        lexer.set_newline_terminators(true);
+
+        let mut parser = Parser::new(lexer);
+        let mut program = parser.parse_program();
+
+        if !parser.errors().is_empty() {
+            for error in parser.errors() {
+                self.report(error.into(), output)?;
+            }
+            return Ok(None);
+        }
+
+        if self.options.fold_constants {
+            program.fold_constants();
+        }
+        Ok(Some(program))
+    }
+
+    /// Runs a line, then reports how long it took, and how many steps, if
+    /// `timed` is set.
+    fn eval(&mut self, input: &str, timed: bool, output: &mut impl Write) -> io::Result<()> {
+        if self.show_tokens {
+            let mut lexer = Lexer::starting_at_line(input, self.next_line);
+            lexer.set_newline_terminators(true);
+            loop {
+                let token = lexer.next_token();
+                if token.kind == TokenKind::Eof {
+                    break;
+                }
+                writeln!(output, "{token:?}")?;
+            }
+        }
+
+        let Some(program) = self.parse(input, None, output)? else {
+            return Ok(());
+        };
+        if self.show_ast {
+            write!(output, "{}", sexp::program(&program))?;
+        }
+
+        // Steps are counted in the fuel they use up, so give the line as
+        // much as it can have when there's no limit.
+        let fuel = self.evaluator.fuel();
+        if timed {
+            self.evaluator.set_fuel(Some(fuel.unwrap_or(u64::MAX)));
+        }
+        // Ctrl-C pressed before the line started isn't meant for it.
+        interrupt::take(&interrupt::CTRL_C);
+        let start = Instant::now();
+        let result = self.evaluator.eval_program(&program);
+        let elapsed = start.elapsed();
+
+        match result {
+            Ok(value) => {
+                writeln!(output, "{value}")?;
+                if !input.trim().is_empty() {
+                    self.transcript.push(input.trim().to_string());
+                }
+            }
+            Err(error) => self.fail(error, output)?,
+        }
+        if timed {
+            let left = self.evaluator.fuel().unwrap_or_default();
+            let steps = fuel.unwrap_or(u64::MAX) - left;
+            self.evaluator.set_fuel(fuel.map(|_| left));
+            writeln!(output, "time: {elapsed:.3?}, {steps} steps")?;
+        }
+        Ok(())
+    }
+
+    /// Runs a line and prints the type inferred for it, or, if nothing
+    /// could be, the type of its value, with how many arguments it takes if
+    /// it's a function, instead of the value.
+    fn type_of(&mut self, input: &str, output: &mut impl Write) -> io::Result<()> {
+        if input.trim().is_empty() {
+            return writeln!(output, "Error `:type` needs an expression");
+        }
+        let Some(program) = self.parse(input, None, output)? else {
+            return Ok(());
+        };
+
+        let inferred = typecheck::infer(&program, self.evaluator.builtins()).value;
+        interrupt::take(&interrupt::CTRL_C);
+        match self.evaluator.eval_program(&program) {
+            Ok(value) => match value.arity() {
+                _ if inferred != Type::Any => writeln!(output, "{inferred}"),
+                Some(arity) => writeln!(output, "{}(arity={arity})", value.type_name()),
+                None => writeln!(output, "{}", value.type_name()),
+            },
+            Err(error) => self.fail(error, output),
+        }
+    }
+
+    /// Runs the script at `path` in the session, so what it binds stays
+    /// bound, and `import`s in it and in later lines look for modules next
+    /// to it.
+    fn load(&mut self, path: &str, output: &mut impl Write) -> io::Result<()> {
+        if path.is_empty() {
+            return writeln!(output, "Error `:load` needs a file");
+        }
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => return writeln!(output, "Error could not read {path}: {error}"),
+        };
+        let Some(program) = self.parse(&source, Some(path), output)? else {
+            return Ok(());
+        };
+
+        self.evaluator.set_source_path(path);
+        interrupt::take(&interrupt::CTRL_C);
+        match self.evaluator.eval_program(&program) {
+            Ok(_) => Ok(()),
+            Err(error) => self.fail(error, output),
+        }
+    }
+
+    /// Writes the lines that ran without errors to `path`, in order, as a
+    /// script that does what they did. Scripts run with `:load` aren't
+    /// included.
+    fn save(&self, path: &str, output: &mut impl Write) -> io::Result<()> {
+        if path.is_empty() {
+            return writeln!(output, "Error `:save` needs a file");
+        }
+        let script: String = self
+            .transcript
+            .iter()
+            .map(|line| format!("{line}\n"))
+            .collect();
+        match std::fs::write(path, script) {
+            Ok(()) => {
+                let lines = self.transcript.len();
+                let plural = if lines == 1 { "" } else { "s" };
+                writeln!(output, "saved {lines} line{plural} to {path}")
+            }
+            Err(error) => writeln!(output, "Error could not write {path}: {error}"),
+        }
+    }
+
+    /// Reports the error a line or script stopped with, or ends the
+    /// session if it called `exit`.
+    fn fail(&mut self, error: RuntimeError, output: &mut impl Write) -> io::Result<()> {
+        match error.kind {
+            ErrorKind::Exit(_) => {
+                self.exited = true;
+                Ok(())
+            }
+            // Off the line the terminal echoed `^C` on.
+            ErrorKind::Interrupted => {
+                writeln!(output)?;
+                self.report((&error).into(), output)
+            }
+            _ => self.report((&error).into(), output),
+        }
+    }
+
+    fn disassemble(&mut self, input: &str, output: &mut impl Write) -> io::Result<()> {
+        let Some(program) = self.parse(input, None, output)? else {
+            return Ok(());
+        };
+
+        match compile(&program, &self.options.builtins()) {
+            Ok(mut bytecode) => {
+                if self.options.optimize {
+                    optimize(&mut bytecode);
+                }
+                write!(output, "{}", bytecode.disassemble())
+            }
+            Err(error) => self.report((&error).into(), output),
+        }
+    }
+
+    /// Prints `diagnostic`, showing the line it points to.
+    fn report(&self, diagnostic: Diagnostic, output: &mut impl Write) -> io::Result<()> {
+        write!(output, "{}", diagnostic.render(&self.sources, self.color))
+    }
+}
+
+/// Turns `flag` on or off as `argument` says, or reports whether it's on
+/// without one.
+fn set_flag(
+    flag: &mut bool,
+    name: &str,
+    argument: &str,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    match argument {
+        "on" => *flag = true,
+        "off" => *flag = false,
+        "" => {
+            let state = if *flag { "on" } else { "off" };
+            writeln!(output, ":{name} is {state}")?;
+        }
+        _ => writeln!(output, "Error `:{name}` takes `on` or `off`")?,
+    }
+    Ok(())
+}
+
+/// Takes the `:command` a line starts with, if it does, and returns its
+/// name. It's blanked out rather than cut off, so error columns in the rest
+/// of the line still match the line typed.
+fn take_command(input: &mut String) -> Option<String> {
+    let rest = input.strip_prefix(':')?;
+    let length = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let name = rest[..length].to_string();
+    input.replace_range(..=length, &" ".repeat(length + 1));
+    Some(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{start, Options};
+    use crate::builtins::test::Closed;
+
+    /// What the REPL prints for `input`, prompts and all.
+    fn repl(input: &str) -> String {
+        let mut output = Vec::new();
+        let options = Options {
+            color: false,
+            ..Options::default()
+        };
+        start(input.as_bytes(), &mut output, options).expect("Should have run the lines.");
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_closed_output() {
+        let error = start("1 + 1\n".as_bytes(), Closed, Options::default()).unwrap_err();
+        assert!(error.is_broken_pipe(), "{error}");
+    }
+
+    #[test]
+    fn test_lines_share_bindings() {
+        assert_eq!(
+            ">> null\n>> 42\n>> [1, 2]\n>> ",
+            repl("let x = 2\nx * 21\nrange(1, 3)\n")
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            ">> error: type mismatch: Int + Bool\n \
+             --> <repl>:1:3\n  \
+               |\n\
+             1 | 1 + true\n  \
+               |   ^\n\
+             >> error: expected an expression, found `)`\n \
+             --> <repl>:2:6\n  \
+               |\n\
+             2 | (1 + )\n  \
+               |      ^\n\
+             >> 3\n>> ",
+            repl("1 + true\n(1 + )\n1 + 2\n")
+        );
+
+        // An error in a function points to the line that defined it.
+        let output = repl("let f = fn() { x }\n\n:time f()\n");
+        assert!(
+            output.contains(
+                "1 | let f = fn() { x }\n  |                ^\n    in f, called from <repl>:3:8\n"
+            ),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn test_multiline_input() {
+        assert_eq!(
+            ">> .. .. null\n>> 3\n>> .. a\nb\n>> ",
+            repl("let f = fn(x) {\n  x + 1\n}\nf(2)\n\"a\nb\"\n")
+        );
+
+        // A blank line runs what's open as it is.
+        assert_eq!(
+            ">> .. .. error: expected an expression, found end of input\n \
+             --> <repl>:3:1\n  \
+               |\n\
+             3 | \n  \
+               | ^\n\
+             >> error: identifier not found: x\n \
+             --> <repl>:4:1\n  \
+               |\n\
+             4 | x\n  \
+               | ^\n\
+             >> ",
+            repl("(1 +\n[\n\nx\n")
+        );
+    }
+
+    #[test]
+    fn test_commands() {
+        let output = repl(":help\n:quit\n1 + 1\n");
+        assert!(output.starts_with(">> :help "), "{output}");
+        assert!(
+            output.contains("\n:quit            leave the REPL\n"),
+            "{output}"
+        );
+        assert!(!output.contains("2\n"), "{output}");
+
+        assert_eq!(
+            ">> null\n>> null\n>> x = 1\ny = two\n>> >> error: identifier not found: x\n \
+             --> <repl>:1:1\n  \
+               |\n\
+             1 | x\n  \
+               | ^\n\
+             >> ",
+            repl("let x = 1\nlet y = \"two\"\n:env\n:reset\nx\n")
+        );
+
+        assert_eq!(
+            ">> Error unknown command `:nope`, try `:help`\n>> :ast is off\n>> >> (+ 1 2)\n3\n>> ",
+            repl(":nope\n:ast\n:ast on\n1 + 2\n")
+        );
+
+        assert_eq!(
+            ">> array<int>\n>> fn('a) -> 'a\n>> fn('a, 'b?) -> 'a\n\
+             >> Builtin(arity=at least 0)\n>> null\n>> Int\n>> fn(str) -> str\n\
+             >> Error `:type` needs an expression\n>> ",
+            repl(
+                ":type [1, 2, 3]\n:type fn(x) { x }\n:type fn(x, y = 1) { x }\n:type print\n\
+                 let n = 2\n:type n\n:type fn(s) { s + \"!\" }\n:type\n"
+            )
+        );
+
+        let path = std::env::temp_dir().join(format!("tofu_save_{}.tofu", std::process::id()));
+        let output = repl(&format!(
+            "let x = 2\nx + \"a\"\n:time let y = x * 3\n\n:save {}\n",
+            path.display()
+        ));
+        let saved = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            output.ends_with(&format!(">> saved 2 lines to {}\n>> ", path.display())),
+            "{output}"
+        );
+        assert_eq!("let x = 2\nlet y = x * 3\n", saved);
+
+        // `exit` ends the session, however it's reached.
+        assert_eq!(
+            ">> null\n>> ",
+            repl("let f = fn() { exit(3) }\ntry { f() } catch (e) {}\n1\n")
+        );
     }
 }
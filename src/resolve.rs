@@ -0,0 +1,468 @@
+//! Binds each name in a program to the declaration it refers to. A name no
+//! scope around it declares is marked as global, so the evaluator can look
+//! it up in the global environment, and then among the builtins, without
+//! searching every scope in between. Any other name gets the slot its
+//! declaration has in the environment the evaluator makes for that scope:
+//! how many environments out it is, and its place among the names declared
+//! there, in the order they're first declared.
+//!
+//! Whether a scope has bound a name yet can depend on the order its
+//! statements run in, so a name declared anywhere in a scope around it,
+//! before or after it, refers to that declaration, and the evaluator falls
+//! back to searching for it when the slot isn't filled yet.
+//!
+//! [`check`] reports what can be found wrong along the way: names declared
+//! twice in the same scope, and names that nothing declares.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashSet;
+
+use crate::ast::{BlockStatement, Expression, Pattern, Program, Slot, Statement};
+use crate::builtins::Builtins;
+use crate::evaluator::PRELUDE;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::symbol::{Symbol, SymbolMap};
+use crate::token::Span;
+use crate::visit::{walk_block, walk_expression, walk_program, walk_statement, Visit};
+
+/// Sets the `global` flag and the slot of every identifier in `program`.
+pub fn resolve(program: &Program) {
+    Resolver::default().visit_program(program);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolveError {
+    pub message: String,
+    /// Where the name is, if the syntax tree records it.
+    pub span: Option<Span>,
+}
+
+/// Resolves `program`, as [`resolve`] does, and returns the names it
+/// declares twice in the same scope and the names it uses that neither it
+/// nor the prelude declares and that aren't in `builtins`, in the order
+/// they appear.
+pub fn check(program: &Program, builtins: &Builtins) -> Vec<ResolveError> {
+    let mut resolver = Resolver::default();
+    resolver.visit_program(program);
+
+    let mut lexer = Lexer::new(PRELUDE);
+    lexer.set_newline_terminators(true);
+    let prelude = Parser::new(lexer).parse_program();
+    let mut known = top_level_names(&prelude);
+    known.extend(resolver.top_level.into_keys());
+
+    let mut errors: Vec<_> = resolver
+        .duplicates
+        .into_iter()
+        .map(|(name, span)| ResolveError {
+            message: format!("duplicate declaration of `{name}` in the same scope"),
+            span,
+        })
+        .chain(
+            resolver
+                .globals
+                .into_iter()
+                .filter(|(name, _)| !known.contains(name) && builtins.lookup(name).is_none())
+                .map(|(name, span)| ResolveError {
+                    message: format!("undefined variable `{name}`"),
+                    span: Some(span),
+                }),
+        )
+        .collect();
+    errors.sort_by_key(|error| error.span.map(|span| (span.line, span.column)));
+    errors
+}
+
+/// The names the statements at the top level of `program` declare.
+fn top_level_names(program: &Program) -> HashSet<Symbol> {
+    program
+        .statements
+        .iter()
+        .flat_map(|statement| match statement {
+            Statement::Let(statement) => pattern_names(&statement.pattern),
+            Statement::Import(import) => vec![import.name.clone()],
+            Statement::Struct { name, .. } => vec![name.clone()],
+            Statement::Enum { variants, .. } => variants
+                .iter()
+                .map(|variant| variant.name.clone())
+                .collect(),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct Resolver {
+    /// The names declared in each scope around the current node, outermost
+    /// first, each with its slot's index. The top level isn't one, since
+    /// it's the global environment.
+    scopes: Vec<SymbolMap<u32>>,
+    /// The names declared at the top level.
+    top_level: SymbolMap<()>,
+    /// Each declaration of a name its scope already declares.
+    duplicates: Vec<(Symbol, Option<Span>)>,
+    /// Each use of a name no scope around it declares.
+    globals: Vec<(Symbol, Span)>,
+}
+
+impl Resolver {
+    /// Runs `f` in a scope declaring `names`, each with where it's declared.
+    fn in_scope(&mut self, names: Vec<(Symbol, Option<Span>)>, f: impl FnOnce(&mut Self)) {
+        let mut scope = SymbolMap::default();
+        for (name, span) in names {
+            let index = scope.len() as u32;
+            match scope.entry(name) {
+                Entry::Occupied(entry) => self.duplicates.push((entry.key().clone(), span)),
+                Entry::Vacant(entry) => {
+                    entry.insert(index);
+                }
+            }
+        }
+        self.scopes.push(scope);
+        f(self);
+        self.scopes.pop();
+    }
+
+    /// The slot of the declaration `name` refers to, if a scope around it
+    /// declares it.
+    fn slot(&self, name: &Symbol) -> Option<Slot> {
+        self.scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, scope)| {
+                scope.get(name).map(|&index| Slot {
+                    depth: depth as u32,
+                    index,
+                })
+            })
+    }
+}
+
+/// The names `statements` declare, each with where it's declared.
+fn declared_names(statements: &[Statement]) -> Vec<(Symbol, Option<Span>)> {
+    let mut names = Vec::new();
+    for statement in statements {
+        match statement {
+            Statement::Let(statement) => names.extend(
+                pattern_names(&statement.pattern)
+                    .into_iter()
+                    .map(|name| (name, Some(statement.span))),
+            ),
+            Statement::Import(import) => names.push((import.name.clone(), Some(import.span))),
+            Statement::Struct { name, span, .. } => names.push((name.clone(), Some(*span))),
+            Statement::Enum { variants, span, .. } => names.extend(
+                variants
+                    .iter()
+                    .map(|variant| (variant.name.clone(), Some(*span))),
+            ),
+            _ => {}
+        }
+    }
+    names
+}
+
+/// The names `pattern` binds, where nothing records where.
+fn unplaced_names(pattern: &Pattern) -> Vec<(Symbol, Option<Span>)> {
+    pattern_names(pattern)
+        .into_iter()
+        .map(|name| (name, None))
+        .collect()
+}
+
+impl Visit for Resolver {
+    fn visit_program(&mut self, program: &Program) {
+        for (name, span) in declared_names(&program.statements) {
+            if self.top_level.insert(name.clone(), ()).is_some() {
+                self.duplicates.push((name, span));
+            }
+        }
+        walk_program(self, program);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::While { condition, body } => {
+                self.visit_expression(condition);
+                // The evaluator gives each iteration an environment of its
+                // own, as it does a `for` loop's, which declares nothing.
+                self.in_scope(Vec::new(), |this| this.visit_block(body));
+            }
+            Statement::For {
+                pattern,
+                iterable,
+                body,
+            } => {
+                self.visit_expression(iterable);
+                self.in_scope(unplaced_names(pattern), |this| this.visit_block(body));
+            }
+            statement => walk_statement(self, statement),
+        }
+    }
+
+    fn visit_block(&mut self, block: &BlockStatement) {
+        self.in_scope(declared_names(&block.statements), |this| {
+            walk_block(this, block)
+        });
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Identifier {
+                name,
+                span,
+                global,
+                slot,
+            } => {
+                slot.set(self.slot(name));
+                global.set(slot.get().is_none());
+                if global.get() {
+                    self.globals.push((name.clone(), *span));
+                }
+            }
+            Expression::Function {
+                parameters, body, ..
+            } => {
+                let names = parameters
+                    .positional
+                    .iter()
+                    .map(|parameter| (parameter.name.clone(), Some(parameter.span)))
+                    .chain(parameters.rest.clone().map(|name| (name, None)))
+                    .collect();
+                self.in_scope(names, |this| {
+                    for parameter in &parameters.positional {
+                        if let Some(default) = &parameter.default {
+                            this.visit_expression(default);
+                        }
+                    }
+                    this.visit_block(body);
+                });
+            }
+            Expression::Try {
+                body,
+                pattern,
+                handler,
+            } => {
+                self.visit_block(body);
+                self.in_scope(unplaced_names(pattern), |this| this.visit_block(handler));
+            }
+            Expression::Match { subject, arms, .. } => {
+                self.visit_expression(subject);
+                for arm in arms {
+                    self.in_scope(unplaced_names(&arm.pattern), |this| {
+                        this.visit_block(&arm.body)
+                    });
+                }
+            }
+            expression => walk_expression(self, expression),
+        }
+    }
+}
+
+/// The names `pattern` binds.
+pub(crate) fn pattern_names(pattern: &Pattern) -> Vec<Symbol> {
+    match pattern {
+        Pattern::Identifier(name) => vec![name.clone()],
+        Pattern::Wildcard | Pattern::Literal(_) => Vec::new(),
+        Pattern::Array(patterns)
+        | Pattern::Tuple(patterns)
+        | Pattern::Variant {
+            fields: patterns, ..
+        } => patterns.iter().flat_map(pattern_names).collect(),
+        Pattern::Hash(entries)
+        | Pattern::Struct {
+            fields: entries, ..
+        } => entries
+            .iter()
+            .flat_map(|(_, pattern)| pattern_names(pattern))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, resolve};
+    use crate::ast::{Expression, Program};
+    use crate::builtins::Builtins;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::visit::{walk_expression, Visit};
+
+    type Identifier = (String, bool, Option<(u32, u32)>);
+
+    /// Each identifier in `program` in order, with whether it's global and
+    /// the depth and index of its slot, if it has one.
+    fn identifiers(program: &Program) -> Vec<Identifier> {
+        struct Identifiers(Vec<Identifier>);
+
+        impl Visit for Identifiers {
+            fn visit_expression(&mut self, expression: &Expression) {
+                if let Expression::Identifier {
+                    name, global, slot, ..
+                } = expression
+                {
+                    let slot = slot.get().map(|slot| (slot.depth, slot.index));
+                    self.0.push((name.to_string(), global.get(), slot));
+                }
+                walk_expression(self, expression);
+            }
+        }
+
+        let mut identifiers = Identifiers(Vec::new());
+        identifiers.visit_program(program);
+        identifiers.0
+    }
+
+    fn parse(input: &str) -> Program {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+        program
+    }
+
+    fn resolved(input: &str) -> Vec<Identifier> {
+        let program = parse(input);
+        resolve(&program);
+        identifiers(&program)
+    }
+
+    #[test]
+    fn test_resolve() {
+        let tests = [
+            (
+                "let x = 1; x + len(x)",
+                vec![("x", true), ("len", true), ("x", true)],
+            ),
+            (
+                "let f = fn(a, b = a, ...rest) { a + b + c + rest }",
+                vec![
+                    ("a", false),
+                    ("a", false),
+                    ("b", false),
+                    ("c", true),
+                    ("rest", false),
+                ],
+            ),
+            // Declared later in the scope still counts, since `g` could be
+            // called after the declaration.
+            (
+                "let f = fn() { let g = fn() { y }; let y = 1; g() }; y",
+                vec![("y", false), ("g", false), ("y", true)],
+            ),
+            (
+                "for ([i, {v}] in xs) { i + v + w } i",
+                vec![
+                    ("xs", true),
+                    ("i", false),
+                    ("v", false),
+                    ("w", true),
+                    ("i", true),
+                ],
+            ),
+            (
+                "try { e } catch (e) { e }; if (t) { import m; m } else { m }",
+                vec![
+                    ("e", true),
+                    ("e", false),
+                    ("t", true),
+                    ("m", false),
+                    ("m", true),
+                ],
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let expected: Vec<_> = expected
+                .into_iter()
+                .map(|(name, global)| (name.to_string(), global))
+                .collect();
+            let actual: Vec<_> = resolved(input)
+                .into_iter()
+                .map(|(name, global, _)| (name, global))
+                .collect();
+            assert_eq!(expected, actual, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_slots() {
+        let tests = [
+            (
+                "let f = fn(a, b) { let [c, a] = b; fn() { a + c } }",
+                vec![
+                    ("b", Some((1, 1))),
+                    ("a", Some((2, 1))),
+                    ("c", Some((2, 0))),
+                ],
+            ),
+            // The function's body is a block inside the scope of its
+            // parameters.
+            (
+                "let f = fn(x, ...xs) { x + xs }",
+                vec![("x", Some((1, 0))), ("xs", Some((1, 1)))],
+            ),
+            // A `while` loop's body is in a scope of its own, inside the
+            // one each iteration gets.
+            (
+                "let f = fn(n) { while (n > 0) { let m = n; n = m - 1 } }",
+                vec![
+                    ("n", Some((1, 0))),
+                    ("n", Some((3, 0))),
+                    ("n", Some((3, 0))),
+                    ("m", Some((0, 0))),
+                ],
+            ),
+            (
+                "for (i in xs) { let j = i; j } g",
+                vec![
+                    ("xs", None),
+                    ("i", Some((1, 0))),
+                    ("j", Some((0, 0))),
+                    ("g", None),
+                ],
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let expected: Vec<_> = expected
+                .into_iter()
+                .map(|(name, slot)| (name.to_string(), slot))
+                .collect();
+            let actual: Vec<_> = resolved(input)
+                .into_iter()
+                .map(|(name, _, slot)| (name, slot))
+                .collect();
+            assert_eq!(expected, actual, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let tests = [
+            ("let x = 1; print(x + map([x], fn(y) { y }))", vec![]),
+            (
+                "let f = fn() { g() + h }; let g = fn() { 1 }",
+                vec!["1:22: undefined variable `h`"],
+            ),
+            (
+                "let x = 1; let f = fn(a, a) { let [b, b] = a; b }; let x = 2",
+                vec![
+                    "1:26: duplicate declaration of `a` in the same scope",
+                    "1:35: duplicate declaration of `b` in the same scope",
+                    "1:56: duplicate declaration of `x` in the same scope",
+                ],
+            ),
+            // Shadowing an outer declaration is fine.
+            ("let x = 1; if (x) { let x = 2; x }", vec![]),
+        ];
+
+        for (input, expected) in tests {
+            let errors: Vec<_> = check(&parse(input), &Builtins::standard())
+                .into_iter()
+                .map(|error| format!("{}: {}", error.span.unwrap(), error.message))
+                .collect();
+            assert_eq!(expected, errors, "{input}");
+        }
+    }
+}
@@ -0,0 +1,404 @@
+//! A compact s-expression rendering of the AST, for eyeballing how a
+//! program parsed: `let x = 1 + 2 * 3;` becomes `(let x (+ 1 (* 2 3)))`.
+
+use std::fmt::Write;
+
+use crate::ast::{
+    with_stack, BlockStatement, Expression, ImportSource, Pattern, Program, Statement,
+};
+
+/// Renders each top-level statement on its own line.
+pub fn program(program: &Program) -> String {
+    let mut out = String::new();
+
+    for statement in &program.statements {
+        write_statement(&mut out, statement);
+        out.push('\n');
+    }
+
+    out
+}
+
+fn write_statement(out: &mut String, statement: &Statement) {
+    match statement {
+        Statement::Let(statement) => {
+            write!(out, "({} ", statement.kind).unwrap();
+            write_pattern(out, &statement.pattern);
+            out.push(' ');
+            write_expression(out, &statement.value);
+            out.push(')');
+        }
+        Statement::Return(Some(value)) => {
+            out.push_str("(return ");
+            write_expression(out, value);
+            out.push(')');
+        }
+        Statement::Return(None) => out.push_str("(return)"),
+        Statement::Expression(expression) => write_expression(out, expression),
+        Statement::While { condition, body } => {
+            out.push_str("(while ");
+            write_expression(out, condition);
+            out.push(' ');
+            write_block(out, body);
+            out.push(')');
+        }
+        Statement::For {
+            pattern,
+            iterable,
+            body,
+        } => {
+            out.push_str("(for ");
+            write_pattern(out, pattern);
+            out.push(' ');
+            write_expression(out, iterable);
+            out.push(' ');
+            write_block(out, body);
+            out.push(')');
+        }
+        Statement::Break => out.push_str("(break)"),
+        Statement::Continue => out.push_str("(continue)"),
+        Statement::Import(import) => match &import.source {
+            ImportSource::Name(name) => write!(out, "(import {name})").unwrap(),
+            ImportSource::Path(path) => write!(out, "(import {path:?})").unwrap(),
+        },
+        Statement::Throw { value, .. } => {
+            out.push_str("(throw ");
+            write_expression(out, value);
+            out.push(')');
+        }
+        Statement::Struct { name, fields, .. } => {
+            write!(out, "(struct {name}").unwrap();
+            for field in fields {
+                write!(out, " {field}").unwrap();
+            }
+            out.push(')');
+        }
+        Statement::Enum { name, variants, .. } => {
+            write!(out, "(enum {name}").unwrap();
+            for variant in variants {
+                if variant.fields.is_empty() {
+                    write!(out, " {}", variant.name).unwrap();
+                    continue;
+                }
+                write!(out, " ({}", variant.name).unwrap();
+                for field in &variant.fields {
+                    write!(out, " {field}").unwrap();
+                }
+                out.push(')');
+            }
+            out.push(')');
+        }
+    }
+}
+
+fn write_pattern(out: &mut String, pattern: &Pattern) {
+    match pattern {
+        Pattern::Identifier(name) => out.push_str(name.as_str()),
+        Pattern::Array(patterns) => {
+            out.push_str("(array");
+            for pattern in patterns {
+                out.push(' ');
+                write_pattern(out, pattern);
+            }
+            out.push(')');
+        }
+        Pattern::Tuple(patterns) => {
+            out.push_str("(tuple");
+            for pattern in patterns {
+                out.push(' ');
+                write_pattern(out, pattern);
+            }
+            out.push(')');
+        }
+        Pattern::Hash(entries) => {
+            out.push_str("(hash");
+            write_entries(out, entries);
+        }
+        Pattern::Struct { name, fields } => {
+            write!(out, "(struct {name}").unwrap();
+            write_entries(out, fields);
+        }
+        Pattern::Variant { name, fields } => {
+            write!(out, "(variant {name}").unwrap();
+            for pattern in fields {
+                out.push(' ');
+                write_pattern(out, pattern);
+            }
+            out.push(')');
+        }
+        Pattern::Wildcard | Pattern::Literal(_) => write!(out, "{pattern}").unwrap(),
+    }
+}
+
+/// Writes a hash or struct pattern's entries and closes it.
+fn write_entries(out: &mut String, entries: &[(String, Pattern)]) {
+    for (key, pattern) in entries {
+        write!(out, " ({key} ").unwrap();
+        write_pattern(out, pattern);
+        out.push(')');
+    }
+    out.push(')');
+}
+
+fn write_block(out: &mut String, block: &BlockStatement) {
+    out.push_str("(block");
+    for statement in &block.statements {
+        out.push(' ');
+        write_statement(out, statement);
+    }
+    out.push(')');
+}
+
+fn write_expression(out: &mut String, expression: &Expression) {
+    with_stack(|| match expression {
+        Expression::Identifier { .. }
+        | Expression::Int(_)
+        | Expression::Float(_)
+        | Expression::Str(_)
+        | Expression::Char(_)
+        | Expression::Bool(_)
+        | Expression::Null => write!(out, "{expression}").unwrap(),
+        Expression::Prefix {
+            operator, right, ..
+        } => {
+            write!(out, "({operator} ").unwrap();
+            write_expression(out, right);
+            out.push(')');
+        }
+        Expression::Infix {
+            left,
+            operator,
+            right,
+            ..
+        } => write_list(out, &operator.to_string(), [&**left, &**right]),
+        Expression::If {
+            condition,
+            consequence,
+            alternative,
+        } => {
+            out.push_str("(if ");
+            write_expression(out, condition);
+            out.push(' ');
+            write_block(out, consequence);
+            if let Some(alternative) = alternative {
+                out.push(' ');
+                write_block(out, alternative);
+            }
+            out.push(')');
+        }
+        Expression::Spread(value) => {
+            out.push_str("(... ");
+            write_expression(out, value);
+            out.push(')');
+        }
+        Expression::Named { name, value } => {
+            write!(out, "({name}: ").unwrap();
+            write_expression(out, value);
+            out.push(')');
+        }
+        Expression::Try {
+            body,
+            pattern,
+            handler,
+        } => {
+            out.push_str("(try ");
+            write_block(out, body);
+            out.push_str(" (catch ");
+            write_pattern(out, pattern);
+            out.push(' ');
+            write_block(out, handler);
+            out.push_str("))");
+        }
+        Expression::Match { subject, arms, .. } => {
+            out.push_str("(match ");
+            write_expression(out, subject);
+            for arm in arms {
+                out.push_str(" (");
+                write_pattern(out, &arm.pattern);
+                out.push(' ');
+                write_block(out, &arm.body);
+                out.push(')');
+            }
+            out.push(')');
+        }
+        Expression::Conditional {
+            condition,
+            consequence,
+            alternative,
+        } => write_list(out, "?", [&**condition, &**consequence, &**alternative]),
+        Expression::Function {
+            parameters, body, ..
+        } => {
+            out.push_str("(fn (");
+            for (index, parameter) in parameters.positional.iter().enumerate() {
+                if index > 0 {
+                    out.push(' ');
+                }
+                match &parameter.default {
+                    Some(default) => {
+                        write!(out, "({} ", parameter.name).unwrap();
+                        write_expression(out, default);
+                        out.push(')');
+                    }
+                    None => out.push_str(parameter.name.as_str()),
+                }
+            }
+            if let Some(rest) = &parameters.rest {
+                let space = if parameters.positional.is_empty() {
+                    ""
+                } else {
+                    " "
+                };
+                write!(out, "{space}...{rest}").unwrap();
+            }
+            out.push_str(") ");
+            write_block(out, body);
+            out.push(')');
+        }
+        Expression::Call {
+            function,
+            arguments,
+            ..
+        } => write_list(
+            out,
+            "call",
+            std::iter::once(&**function).chain(arguments.iter()),
+        ),
+        Expression::MethodCall {
+            receiver,
+            method,
+            arguments,
+            ..
+        } => write_list(
+            out,
+            &format!(".{method}"),
+            std::iter::once(&**receiver).chain(arguments.iter()),
+        ),
+        Expression::Array(elements) => write_list(out, "array", elements),
+        Expression::Tuple(elements) => write_list(out, "tuple", elements),
+        Expression::Hash(pairs) => {
+            out.push_str("(hash");
+            for (key, value) in pairs {
+                out.push(' ');
+                write_list(out, "", [key, value]);
+            }
+            out.push(')');
+        }
+        Expression::Index {
+            left,
+            index,
+            optional,
+            ..
+        } => {
+            let head = if *optional { "index?" } else { "index" };
+            write_list(out, head, [&**left, &**index]);
+        }
+        Expression::Assign {
+            target,
+            operator,
+            value,
+            ..
+        } => {
+            let head = match operator {
+                Some(operator) => format!("{operator}="),
+                None => "=".to_string(),
+            };
+            write_list(out, &head, [&**target, &**value]);
+        }
+        Expression::Range {
+            start,
+            end,
+            inclusive,
+            ..
+        } => {
+            // A bound left out is `()`.
+            out.push_str(if *inclusive { "(..=" } else { "(.." });
+            for bound in [start, end] {
+                match bound {
+                    Some(bound) => {
+                        out.push(' ');
+                        write_expression(out, bound);
+                    }
+                    None => out.push_str(" ()"),
+                }
+            }
+            out.push(')');
+        }
+    })
+}
+
+/// Writes `(head item item ...)`, or just `(item item ...)` if `head` is
+/// empty.
+fn write_list<'a>(out: &mut String, head: &str, items: impl IntoIterator<Item = &'a Expression>) {
+    out.push('(');
+    out.push_str(head);
+    for (index, item) in items.into_iter().enumerate() {
+        if index > 0 || !head.is_empty() {
+            out.push(' ');
+        }
+        write_expression(out, item);
+    }
+    out.push(')');
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn sexp(input: &str) -> String {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+
+        super::program(&program)
+    }
+
+    #[test]
+    fn test_sexp() {
+        let tests = vec![
+            ("let x = 1 * (2 + 3);", "(let x (* 1 (+ 2 3)))\n"),
+            ("return; -a", "(return)\n(- a)\n"),
+            (
+                "if (a) { b } else { c = d += 1 }",
+                "(if a (block b) (block (= c (+= d 1))))\n",
+            ),
+            (
+                "const f = fn(x, y) { return [x, y][0..1]; }",
+                "(const f (fn (x y) (block (return (index (array x y) (.. 0 1))))))\n",
+            ),
+            (r#"f(1, {"a": 'b'})"#, "(call f 1 (hash (\"a\" 'b')))\n"),
+            (
+                "while (true) { break } for (i in 0..=2) { continue }",
+                "(while true (block (break)))\n(for i (..= 0 2) (block (continue)))\n",
+            ),
+            (
+                "let [a, {b, c: [d]}] = x; for ([k, v] in h) {}",
+                "(let (array a (hash (b b) (c (array d)))) x)\n(for (array k v) h (block))\n",
+            ),
+            (
+                r#"import m; import "lib/m.tofu""#,
+                "(import m)\n(import \"lib/m.tofu\")\n",
+            ),
+            (
+                "fn(a, ...b) { f(...b, a) }",
+                "(fn (a ...b) (block (call f (... b) a)))\n",
+            ),
+            ("fn(a, b = a + 1) {}", "(fn (a (b (+ a 1))) (block))\n"),
+            (
+                "try { throw 1 } catch (e) { e }",
+                "(try (block (throw 1)) (catch e (block e)))\n",
+            ),
+            ("f(a, b: 1)", "(call f a (b: 1))\n"),
+            ("xs.map(f).len()", "(.len (.map xs f))\n"),
+            (
+                "enum S { C(r), E } match s { C(r) => r, E => { 0 } }",
+                "(enum S (C r) E)\n(match s ((variant C r) (block r)) (E (block 0)))\n",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(expected, sexp(input), "input={input}");
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Values that can cross threads.
+//!
+//! A [`Value`] is for the one thread its interpreter runs on: arrays and
+//! hashes are shared and changed in place through `Rc<RefCell<...>>`, and
+//! functions hold the environment they closed over, so neither `Value` nor
+//! [`Interpreter`](crate::Interpreter) is `Send`. Interpreters on different
+//! threads are independent, though, with nothing global between them, so a
+//! host can run one on each of its threads.
+//!
+//! To pass data between them, a value is copied into a [`SharedValue`],
+//! which is immutable, `Send` and `Sync`, and cheap to clone, and copied
+//! back into a `Value` on the other side:
+//!
+//! ```
+//! use std::thread;
+//!
+//! use tofu_interpreter::shared::SharedValue;
+//! use tofu_interpreter::{Interpreter, Value};
+//!
+//! let config = thread::spawn(|| {
+//!     let mut interpreter = Interpreter::new();
+//!     let value = interpreter.eval("{\"workers\": 4, \"names\": [\"a\", \"b\"]}");
+//!     SharedValue::try_from(&value.unwrap()).unwrap()
+//! })
+//! .join()
+//! .unwrap();
+//!
+//! let mut interpreter = Interpreter::new();
+//! interpreter.set("config", Value::from(&config)).unwrap();
+//! assert_eq!(Value::Int(4), interpreter.eval("config[\"workers\"]").unwrap());
+//! ```
+//!
+//! Functions can't be shared, since what they closed over can't.
+
+use std::fmt::Display;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use indexmap::{IndexMap, IndexSet};
+#[cfg(feature = "bigint")]
+use num_bigint::BigInt;
+
+use crate::evaluator::RuntimeError;
+use crate::value::{HashKey, Range, StructType, Value, VariantType};
+
+/// An immutable copy of a [`Value`] without functions, which can be sent
+/// to and shared between threads.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SharedValue {
+    Int(i64),
+    #[cfg(feature = "bigint")]
+    BigInt(Arc<BigInt>),
+    Float(f64),
+    Bool(bool),
+    Str(Arc<str>),
+    Char(char),
+    Null,
+    Array(Arc<[SharedValue]>),
+    Hash(Arc<IndexMap<HashKey, SharedValue>>),
+    Set(Arc<IndexSet<HashKey>>),
+    Buffer(Arc<str>),
+    Tuple(Arc<[SharedValue]>),
+    /// A struct's name, and each of its fields' with its value.
+    Struct(Arc<str>, Arc<[(String, SharedValue)]>),
+    /// A value of an enum's variant.
+    Variant {
+        enum_name: Arc<str>,
+        name: Arc<str>,
+        fields: Arc<[(String, SharedValue)]>,
+    },
+    Range(Range),
+}
+
+impl SharedValue {
+    /// The name of the type of the value it's a copy of.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            SharedValue::Int(_) => "Int",
+            #[cfg(feature = "bigint")]
+            SharedValue::BigInt(_) => "Int",
+            SharedValue::Float(_) => "Float",
+            SharedValue::Bool(_) => "Bool",
+            SharedValue::Str(_) => "Str",
+            SharedValue::Char(_) => "Char",
+            SharedValue::Null => "Null",
+            SharedValue::Array(_) => "Array",
+            SharedValue::Hash(_) => "Hash",
+            SharedValue::Set(_) => "Set",
+            SharedValue::Buffer(_) => "Buffer",
+            SharedValue::Tuple(_) => "Tuple",
+            SharedValue::Struct(..) => "Struct",
+            SharedValue::Variant { .. } => "Enum",
+            SharedValue::Range(_) => "Range",
+        }
+    }
+
+    /// Copies `value`, with `enclosing` holding the arrays and hashes it's
+    /// inside, to catch one that contains itself.
+    fn copy(value: &Value, enclosing: &mut Vec<*const ()>) -> Result<SharedValue, RuntimeError> {
+        Ok(match value {
+            Value::Int(value) => SharedValue::Int(*value),
+            #[cfg(feature = "bigint")]
+            Value::BigInt(value) => SharedValue::BigInt(Arc::new((**value).clone())),
+            Value::Float(value) => SharedValue::Float(*value),
+            Value::Bool(value) => SharedValue::Bool(*value),
+            Value::Str(value) => SharedValue::Str(value.as_str().into()),
+            Value::Char(value) => SharedValue::Char(*value),
+            Value::Null => SharedValue::Null,
+            Value::Range(range) => SharedValue::Range(*range),
+            Value::Array(elements) => {
+                let address = Rc::as_ptr(elements).cast();
+                enter(enclosing, address)?;
+                let copies = elements
+                    .borrow()
+                    .iter()
+                    .map(|element| SharedValue::copy(element, enclosing))
+                    .collect::<Result<_, _>>()?;
+                enclosing.pop();
+                SharedValue::Array(copies)
+            }
+            Value::Hash(pairs) => {
+                let address = Rc::as_ptr(pairs).cast();
+                enter(enclosing, address)?;
+                let copies = pairs
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| Ok((key.clone(), SharedValue::copy(value, enclosing)?)))
+                    .collect::<Result<_, RuntimeError>>()?;
+                enclosing.pop();
+                SharedValue::Hash(Arc::new(copies))
+            }
+            // Its elements are keys, which can't contain anything.
+            Value::Set(elements) => SharedValue::Set(Arc::new(elements.borrow().clone())),
+            Value::Buffer(text) => SharedValue::Buffer(text.borrow().as_str().into()),
+            // A tuple can't contain itself, only an array or hash that does.
+            Value::Tuple(elements) => SharedValue::Tuple(
+                elements
+                    .iter()
+                    .map(|element| SharedValue::copy(element, enclosing))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Struct(instance) => {
+                let address = Rc::as_ptr(instance).cast();
+                enter(enclosing, address)?;
+                let copies = instance
+                    .ty
+                    .fields
+                    .iter()
+                    .zip(instance.fields.borrow().iter())
+                    .map(|(name, value)| Ok((name.clone(), SharedValue::copy(value, enclosing)?)))
+                    .collect::<Result<_, RuntimeError>>()?;
+                enclosing.pop();
+                SharedValue::Struct(instance.ty.name.as_str().into(), copies)
+            }
+            // Like a tuple, it can't contain itself.
+            Value::Variant(variant) => SharedValue::Variant {
+                enum_name: variant.ty.enum_name.as_str().into(),
+                name: variant.ty.name.as_str().into(),
+                fields: variant
+                    .ty
+                    .fields
+                    .iter()
+                    .zip(variant.values.iter())
+                    .map(|(name, value)| Ok((name.clone(), SharedValue::copy(value, enclosing)?)))
+                    .collect::<Result<_, RuntimeError>>()?,
+            },
+            Value::Function(_)
+            | Value::Closure(_)
+            | Value::Builtin(_)
+            | Value::StructType(_)
+            | Value::VariantType(_) => {
+                return Err(RuntimeError::new(format!(
+                    "can't share a {} between threads",
+                    value.type_name()
+                )))
+            }
+        })
+    }
+}
+
+fn enter(enclosing: &mut Vec<*const ()>, address: *const ()) -> Result<(), RuntimeError> {
+    if enclosing.contains(&address) {
+        return Err(RuntimeError::new(
+            "can't share a value that contains itself",
+        ));
+    }
+    enclosing.push(address);
+    Ok(())
+}
+
+/// Copies a value, failing if it is or contains a function, or contains
+/// itself.
+impl TryFrom<&Value> for SharedValue {
+    type Error = RuntimeError;
+
+    fn try_from(value: &Value) -> Result<SharedValue, RuntimeError> {
+        SharedValue::copy(value, &mut Vec::new())
+    }
+}
+
+impl TryFrom<Value> for SharedValue {
+    type Error = RuntimeError;
+
+    fn try_from(value: Value) -> Result<SharedValue, RuntimeError> {
+        SharedValue::try_from(&value)
+    }
+}
+
+/// A new value with the same contents, whose arrays and hashes can be
+/// changed without changing any other copy.
+impl From<&SharedValue> for Value {
+    fn from(value: &SharedValue) -> Value {
+        match value {
+            SharedValue::Int(value) => Value::Int(*value),
+            #[cfg(feature = "bigint")]
+            SharedValue::BigInt(value) => Value::BigInt(Rc::new((**value).clone())),
+            SharedValue::Float(value) => Value::Float(*value),
+            SharedValue::Bool(value) => Value::Bool(*value),
+            SharedValue::Str(value) => Value::Str(value.to_string()),
+            SharedValue::Char(value) => Value::Char(*value),
+            SharedValue::Null => Value::Null,
+            SharedValue::Range(range) => Value::Range(*range),
+            SharedValue::Array(elements) => {
+                Value::array(elements.iter().map(Value::from).collect())
+            }
+            SharedValue::Hash(pairs) => Value::hash(
+                pairs
+                    .iter()
+                    .map(|(key, value)| (key.clone(), Value::from(value)))
+                    .collect(),
+            ),
+            SharedValue::Set(elements) => Value::set((**elements).clone()),
+            SharedValue::Buffer(text) => Value::buffer(text.to_string()),
+            SharedValue::Tuple(elements) => {
+                Value::Tuple(elements.iter().map(Value::from).collect())
+            }
+            SharedValue::Struct(name, fields) => {
+                let ty = StructType {
+                    name: name.to_string(),
+                    fields: fields.iter().map(|(name, _)| name.clone()).collect(),
+                };
+                let values = fields.iter().map(|(_, value)| Value::from(value)).collect();
+                Value::instance(Rc::new(ty), values)
+            }
+            SharedValue::Variant {
+                enum_name,
+                name,
+                fields,
+            } => {
+                let ty = VariantType {
+                    enum_name: enum_name.to_string(),
+                    name: name.to_string(),
+                    fields: fields.iter().map(|(name, _)| name.clone()).collect(),
+                };
+                let values = fields.iter().map(|(_, value)| Value::from(value)).collect();
+                Value::variant(Rc::new(ty), values)
+            }
+        }
+    }
+}
+
+impl From<SharedValue> for Value {
+    fn from(value: SharedValue) -> Value {
+        Value::from(&value)
+    }
+}
+
+impl Display for SharedValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Value::from(self))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+    use std::thread;
+
+    use super::SharedValue;
+    use crate::evaluator::RuntimeError;
+    use crate::interpreter::Interpreter;
+    use crate::value::Value;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_shared_value() {
+        assert_send_sync::<SharedValue>();
+
+        // Each thread runs its own interpreter and sends back what it made.
+        let (sender, receiver) = mpsc::channel();
+        let workers: Vec<_> = (1..=4)
+            .map(|n| {
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    let mut interpreter = Interpreter::new();
+                    interpreter.set("n", Value::Int(n)).unwrap();
+                    let value = interpreter
+                        .eval("let squares = map(1..=n, fn(x) { x * x })\n{\"n\": n, \"squares\": squares}")
+                        .unwrap();
+                    sender.send(SharedValue::try_from(value).unwrap()).unwrap();
+                })
+            })
+            .collect();
+        drop(sender);
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        let mut results: Vec<SharedValue> = receiver.iter().collect();
+        results.sort_by_key(|result| result.to_string());
+        assert_eq!(
+            "{\"n\": 4, \"squares\": [1, 4, 9, 16]}",
+            results[3].to_string()
+        );
+
+        // Copies back into an interpreter are its own to change.
+        let mut interpreter = Interpreter::new();
+        interpreter.set("a", Value::from(&results[3])).unwrap();
+        interpreter.set("b", Value::from(&results[3])).unwrap();
+        assert_eq!(
+            Value::Int(4),
+            interpreter
+                .eval("push(a[\"squares\"], 25)\nlen(b[\"squares\"])")
+                .unwrap()
+        );
+
+        let error = |source: &str| {
+            let value = Interpreter::new().eval(source).unwrap();
+            SharedValue::try_from(&value).map(|_| ())
+        };
+        assert_eq!(
+            Err(RuntimeError::new("can't share a Function between threads")),
+            error("[1, fn() { 2 }]")
+        );
+        assert_eq!(
+            Err(RuntimeError::new(
+                "can't share a value that contains itself"
+            )),
+            error("let a = [1]\npush(a, a)\na")
+        );
+        assert_eq!(Ok(()), error("let a = [1]\n[a, a]"));
+    }
+}
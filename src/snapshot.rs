@@ -0,0 +1,578 @@
+//! Saving what an [`Interpreter`](crate::Interpreter) has bound, so a
+//! long-running program can checkpoint its scripts' state and pick up from
+//! it after a restart.
+//!
+//! A snapshot holds the global environment and the modules imported so far,
+//! with everything their values reach: arrays, hashes, sets and structs, and
+//! functions with the environments they closed over. Functions are kept as their
+//! source, and parsed again on restoring. Builtins are kept by name, so the
+//! program has to register its own functions again before restoring one
+//! that refers to them. Values shared between bindings stay shared.
+//!
+//! Snapshots are JSON, which [`VERSION`] says the layout of.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use indexmap::{IndexMap, IndexSet};
+use serde::{Deserialize, Serialize};
+
+use crate::ast::{DeclarationKind, Expression, Statement};
+use crate::environment::Environment;
+use crate::evaluator::{Evaluator, RuntimeError};
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::symbol::Symbol;
+use crate::value::{Function, Range, StructType, Value, VariantType};
+
+/// The version of the snapshot format. It goes up whenever the format
+/// changes, since a snapshot in one can't be restored from another.
+pub const VERSION: u32 = 7;
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    /// Every environment reached, each after the ones it's nested in. The
+    /// first is the global environment.
+    environments: Vec<SavedEnvironment>,
+    objects: Vec<SavedObject>,
+    modules: Vec<(PathBuf, SavedValue)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedEnvironment {
+    outer: Option<usize>,
+    bindings: Vec<(String, bool, SavedValue)>,
+}
+
+/// A value that's shared by reference, saved once however many places
+/// refer to it.
+#[derive(Serialize, Deserialize)]
+enum SavedObject {
+    Array(Vec<SavedValue>),
+    Hash(Vec<(SavedValue, SavedValue)>),
+    Set(Vec<SavedValue>),
+    Buffer(String),
+    Struct {
+        name: String,
+        fields: Vec<String>,
+        values: Vec<SavedValue>,
+    },
+    Function {
+        name: Option<String>,
+        source: String,
+        environment: usize,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+enum SavedValue {
+    Int(i64),
+    /// In decimal.
+    BigInt(String),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Char(char),
+    Null,
+    /// The bounds that were written, and whether the end is included.
+    Range(Option<i64>, Option<i64>, bool),
+    /// Saved in place, since a tuple can't change and so needn't be shared.
+    Tuple(Vec<SavedValue>),
+    /// A struct's constructor, saved in place like a tuple.
+    StructType {
+        name: String,
+        fields: Vec<String>,
+    },
+    /// An enum's variant with fields, saved in place like a tuple.
+    VariantType {
+        enum_name: String,
+        name: String,
+        fields: Vec<String>,
+    },
+    /// A value of an enum's variant, which can't change, like a tuple.
+    Variant {
+        enum_name: String,
+        name: String,
+        fields: Vec<String>,
+        values: Vec<SavedValue>,
+    },
+    Builtin(String),
+    /// An index into the snapshot's objects.
+    Object(usize),
+}
+
+/// The snapshot of `evaluator`'s globals and modules.
+pub(crate) fn save(evaluator: &Evaluator) -> Result<Vec<u8>, RuntimeError> {
+    let mut saver = Saver::default();
+    saver.environment(evaluator.global_environment())?;
+    let mut modules = Vec::new();
+    for (path, namespace) in evaluator.modules() {
+        modules.push((path.clone(), saver.value(namespace)?));
+    }
+    // Sorted, so the same state always makes the same snapshot.
+    modules.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let snapshot = Snapshot {
+        version: VERSION,
+        environments: saver.environments,
+        objects: saver.objects,
+        modules,
+    };
+    serde_json::to_vec(&snapshot)
+        .map_err(|error| RuntimeError::new(format!("could not save snapshot: {error}")))
+}
+
+/// Replaces `evaluator`'s globals and modules with the ones in `bytes`.
+pub(crate) fn restore(evaluator: &mut Evaluator, bytes: &[u8]) -> Result<(), RuntimeError> {
+    let snapshot: Snapshot = serde_json::from_slice(bytes)
+        .map_err(|error| RuntimeError::new(format!("invalid snapshot: {error}")))?;
+    if snapshot.version != VERSION {
+        return Err(RuntimeError::new(format!(
+            "snapshot is version {}, but this interpreter reads version {VERSION}",
+            snapshot.version
+        )));
+    }
+
+    let mut environments: Vec<Rc<RefCell<Environment>>> = Vec::new();
+    for saved in &snapshot.environments {
+        let environment = match saved.outer {
+            Some(outer) => {
+                let outer = environments.get(outer).ok_or_else(invalid)?;
+                Environment::enclosed(Rc::clone(outer))
+            }
+            None => Environment::new(),
+        };
+        environments.push(Rc::new(RefCell::new(environment)));
+    }
+    let globals = environments.first().cloned().ok_or_else(invalid)?;
+
+    // The objects are made before anything's put in them, since they can
+    // refer to each other in any order.
+    let mut loader = Loader {
+        evaluator,
+        environments,
+        objects: Vec::new(),
+    };
+    for saved in &snapshot.objects {
+        let object = match saved {
+            SavedObject::Array(_) => Value::array(Vec::new()),
+            SavedObject::Hash(_) => Value::hash(IndexMap::new()),
+            SavedObject::Set(_) => Value::set(IndexSet::new()),
+            SavedObject::Buffer(text) => Value::buffer(text.clone()),
+            SavedObject::Struct { name, fields, .. } => Value::instance(
+                Rc::new(StructType {
+                    name: name.clone(),
+                    fields: fields.clone(),
+                }),
+                Vec::new(),
+            ),
+            SavedObject::Function {
+                name,
+                source,
+                environment,
+            } => loader.function(name.clone(), source, *environment)?,
+        };
+        loader.objects.push(object);
+    }
+    for (saved, object) in snapshot.objects.iter().zip(loader.objects.clone()) {
+        match (saved, object) {
+            (SavedObject::Array(elements), Value::Array(array)) => {
+                let elements = elements
+                    .iter()
+                    .map(|element| loader.value(element))
+                    .collect::<Result<_, _>>()?;
+                *array.borrow_mut() = elements;
+            }
+            (SavedObject::Hash(pairs), Value::Hash(hash)) => {
+                for (key, value) in pairs {
+                    let key = loader.value(key)?.hash_key().ok_or_else(invalid)?;
+                    let value = loader.value(value)?;
+                    hash.borrow_mut().insert(key, value);
+                }
+            }
+            (SavedObject::Set(elements), Value::Set(set)) => {
+                for element in elements {
+                    let element = loader.value(element)?.hash_key().ok_or_else(invalid)?;
+                    set.borrow_mut().insert(element);
+                }
+            }
+            (SavedObject::Struct { fields, values, .. }, Value::Struct(instance)) => {
+                if values.len() != fields.len() {
+                    return Err(invalid());
+                }
+                let values = values
+                    .iter()
+                    .map(|value| loader.value(value))
+                    .collect::<Result<_, _>>()?;
+                *instance.fields.borrow_mut() = values;
+            }
+            _ => {}
+        }
+    }
+    for (saved, environment) in snapshot.environments.iter().zip(&loader.environments) {
+        for (name, constant, value) in &saved.bindings {
+            let kind = if *constant {
+                DeclarationKind::Const
+            } else {
+                DeclarationKind::Let
+            };
+            let value = loader.value(value)?;
+            environment
+                .borrow_mut()
+                .declare(&Symbol::intern(name), kind, value)?;
+        }
+    }
+    let mut modules = HashMap::new();
+    for (path, namespace) in &snapshot.modules {
+        modules.insert(path.clone(), loader.value(namespace)?);
+    }
+
+    evaluator.set_global_environment(globals);
+    evaluator.set_modules(modules);
+    Ok(())
+}
+
+fn invalid() -> RuntimeError {
+    RuntimeError::new("invalid snapshot")
+}
+
+#[derive(Default)]
+struct Saver {
+    environments: Vec<SavedEnvironment>,
+    objects: Vec<SavedObject>,
+    /// The index of each environment and object saved so far, by address.
+    indexes: HashMap<*const (), usize>,
+}
+
+impl Saver {
+    fn environment(
+        &mut self,
+        environment: &Rc<RefCell<Environment>>,
+    ) -> Result<usize, RuntimeError> {
+        let address = Rc::as_ptr(environment).cast();
+        if let Some(&index) = self.indexes.get(&address) {
+            return Ok(index);
+        }
+        let outer = match environment.borrow().outer() {
+            Some(outer) => Some(self.environment(outer)?),
+            None => None,
+        };
+        // Indexed before its bindings are saved, since functions bound in
+        // it refer back to it.
+        let index = self.environments.len();
+        self.indexes.insert(address, index);
+        self.environments.push(SavedEnvironment {
+            outer,
+            bindings: Vec::new(),
+        });
+
+        let mut bindings = Vec::new();
+        for (name, kind, value) in environment.borrow().entries() {
+            let constant = kind == DeclarationKind::Const;
+            bindings.push((name.to_string(), constant, self.value(value)?));
+        }
+        bindings.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+        self.environments[index].bindings = bindings;
+        Ok(index)
+    }
+
+    /// Saves `object` once, at `address`, filling it in with `fill` after
+    /// it's indexed, so it can refer to itself.
+    fn object(
+        &mut self,
+        address: *const (),
+        fill: impl FnOnce(&mut Saver) -> Result<SavedObject, RuntimeError>,
+    ) -> Result<SavedValue, RuntimeError> {
+        if let Some(&index) = self.indexes.get(&address) {
+            return Ok(SavedValue::Object(index));
+        }
+        let index = self.objects.len();
+        self.indexes.insert(address, index);
+        self.objects.push(SavedObject::Array(Vec::new()));
+        self.objects[index] = fill(self)?;
+        Ok(SavedValue::Object(index))
+    }
+
+    fn value(&mut self, value: &Value) -> Result<SavedValue, RuntimeError> {
+        Ok(match value {
+            Value::Int(value) => SavedValue::Int(*value),
+            #[cfg(feature = "bigint")]
+            Value::BigInt(value) => SavedValue::BigInt(value.to_string()),
+            Value::Float(value) => SavedValue::Float(*value),
+            Value::Bool(value) => SavedValue::Bool(*value),
+            Value::Str(value) => SavedValue::Str(value.clone()),
+            Value::Char(value) => SavedValue::Char(*value),
+            Value::Null => SavedValue::Null,
+            Value::Range(range) => SavedValue::Range(
+                (!range.open_start).then_some(range.start),
+                (!range.open_end).then_some(range.end),
+                range.inclusive,
+            ),
+            Value::Builtin(builtin) => SavedValue::Builtin(builtin.name.clone()),
+            Value::StructType(ty) => SavedValue::StructType {
+                name: ty.name.clone(),
+                fields: ty.fields.clone(),
+            },
+            Value::VariantType(ty) => SavedValue::VariantType {
+                enum_name: ty.enum_name.clone(),
+                name: ty.name.clone(),
+                fields: ty.fields.clone(),
+            },
+            Value::Variant(variant) => SavedValue::Variant {
+                enum_name: variant.ty.enum_name.clone(),
+                name: variant.ty.name.clone(),
+                fields: variant.ty.fields.clone(),
+                values: variant
+                    .values
+                    .iter()
+                    .map(|value| self.value(value))
+                    .collect::<Result<_, _>>()?,
+            },
+            Value::Tuple(elements) => SavedValue::Tuple(
+                elements
+                    .iter()
+                    .map(|element| self.value(element))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Array(elements) => self.object(Rc::as_ptr(elements).cast(), |saver| {
+                let elements = elements.borrow();
+                let elements = elements.iter().map(|element| saver.value(element));
+                Ok(SavedObject::Array(elements.collect::<Result<_, _>>()?))
+            })?,
+            Value::Hash(pairs) => self.object(Rc::as_ptr(pairs).cast(), |saver| {
+                let mut saved = Vec::new();
+                for (key, value) in pairs.borrow().iter() {
+                    saved.push((saver.value(&key.clone().into())?, saver.value(value)?));
+                }
+                Ok(SavedObject::Hash(saved))
+            })?,
+            Value::Set(elements) => self.object(Rc::as_ptr(elements).cast(), |saver| {
+                let elements = elements.borrow();
+                let elements = elements
+                    .iter()
+                    .map(|element| saver.value(&element.clone().into()));
+                Ok(SavedObject::Set(elements.collect::<Result<_, _>>()?))
+            })?,
+            Value::Buffer(text) => self.object(Rc::as_ptr(text).cast(), |_| {
+                Ok(SavedObject::Buffer(text.borrow().clone()))
+            })?,
+            Value::Struct(instance) => self.object(Rc::as_ptr(instance).cast(), |saver| {
+                let values = instance.fields.borrow();
+                let values = values.iter().map(|value| saver.value(value));
+                Ok(SavedObject::Struct {
+                    name: instance.ty.name.clone(),
+                    fields: instance.ty.fields.clone(),
+                    values: values.collect::<Result<_, _>>()?,
+                })
+            })?,
+            Value::Function(function) => self.object(Rc::as_ptr(function).cast(), |saver| {
+                Ok(SavedObject::Function {
+                    name: function.name.clone(),
+                    source: format!("fn({}) {}", function.parameters, function.body),
+                    environment: saver.environment(&function.env)?,
+                })
+            })?,
+            Value::Closure(_) => {
+                return Err(RuntimeError::new(
+                    "cannot snapshot a function compiled for the VM",
+                ))
+            }
+        })
+    }
+}
+
+struct Loader<'a> {
+    evaluator: &'a Evaluator,
+    environments: Vec<Rc<RefCell<Environment>>>,
+    objects: Vec<Value>,
+}
+
+impl Loader<'_> {
+    fn function(
+        &self,
+        name: Option<String>,
+        source: &str,
+        environment: usize,
+    ) -> Result<Value, RuntimeError> {
+        let mut parser = Parser::new(Lexer::new(source));
+        let program = parser.parse_program();
+        let [Statement::Expression(Expression::Function {
+            parameters, body, ..
+        })] = program.statements.as_slice()
+        else {
+            return Err(invalid());
+        };
+        if !parser.errors().is_empty() {
+            return Err(invalid());
+        }
+        let env = self.environments.get(environment).ok_or_else(invalid)?;
+        Ok(Value::Function(Rc::new(Function {
+            name,
+            parameters: parameters.clone(),
+            body: Rc::clone(body),
+            env: Rc::clone(env),
+        })))
+    }
+
+    fn value(&self, value: &SavedValue) -> Result<Value, RuntimeError> {
+        Ok(match value {
+            SavedValue::Int(value) => Value::Int(*value),
+            #[cfg(feature = "bigint")]
+            SavedValue::BigInt(value) => {
+                Value::BigInt(Rc::new(value.parse().map_err(|_| invalid())?))
+            }
+            #[cfg(not(feature = "bigint"))]
+            SavedValue::BigInt(_) => {
+                return Err(RuntimeError::new(
+                    "snapshot has a big integer, which needs the `bigint` feature",
+                ))
+            }
+            SavedValue::Float(value) => Value::Float(*value),
+            SavedValue::Bool(value) => Value::Bool(*value),
+            SavedValue::Str(value) => Value::Str(value.clone()),
+            SavedValue::Char(value) => Value::Char(*value),
+            SavedValue::Null => Value::Null,
+            SavedValue::Range(start, end, inclusive) => {
+                Value::Range(Range::new(*start, *end, *inclusive))
+            }
+            SavedValue::Tuple(elements) => Value::Tuple(
+                elements
+                    .iter()
+                    .map(|element| self.value(element))
+                    .collect::<Result<_, _>>()?,
+            ),
+            SavedValue::StructType { name, fields } => Value::StructType(Rc::new(StructType {
+                name: name.clone(),
+                fields: fields.clone(),
+            })),
+            SavedValue::VariantType {
+                enum_name,
+                name,
+                fields,
+            } => Value::VariantType(Rc::new(VariantType {
+                enum_name: enum_name.clone(),
+                name: name.clone(),
+                fields: fields.clone(),
+            })),
+            SavedValue::Variant {
+                enum_name,
+                name,
+                fields,
+                values,
+            } => {
+                let ty = VariantType {
+                    enum_name: enum_name.clone(),
+                    name: name.clone(),
+                    fields: fields.clone(),
+                };
+                let values = values.iter().map(|value| self.value(value));
+                Value::variant(Rc::new(ty), values.collect::<Result<_, _>>()?)
+            }
+            SavedValue::Builtin(name) => self.evaluator.builtins().get(name).ok_or_else(|| {
+                RuntimeError::new(format!("snapshot refers to unknown builtin `{name}`"))
+            })?,
+            SavedValue::Object(index) => self.objects.get(*index).cloned().ok_or_else(invalid)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::builtins::Arity;
+    use crate::error::TofuError;
+    use crate::interpreter::Interpreter;
+    use crate::value::Value;
+
+    fn interpreter() -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_fn("host", Arity::Exact(0), |_| Ok(Value::Int(7)));
+        interpreter
+    }
+
+    fn error(result: Result<(), TofuError>) -> String {
+        match result {
+            Err(TofuError::Runtime(error)) => error.message,
+            result => panic!("expected a runtime error, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut before = interpreter();
+        before
+            .eval(
+                "const limit = 10
+let make_counter = fn(start) {
+    let count = start
+    fn() { count += 1; count }
+}
+let counter = make_counter(5)
+counter()
+let shared = [1, 2]
+let both = {\"a\": shared, \"b\": shared, 3: 1..=4}
+let cycle = [0]
+push(cycle, cycle)
+let size = len
+let from_host = host
+let seen = set([\"b\", 1])
+let seen_too = seen
+struct Point { x, y }
+let origin = Point(0, [0])
+let also_origin = origin
+enum Shape { Circle(r), Dot }
+let shapes = [Circle(2), Dot]",
+            )
+            .unwrap();
+        let snapshot = before.snapshot().unwrap();
+        assert_eq!(snapshot, before.snapshot().unwrap());
+
+        let mut after = interpreter();
+        after.restore(&snapshot).unwrap();
+        let mut eval = |source| after.eval(source).unwrap();
+        assert_eq!(Value::Int(7), eval("counter()"));
+        assert_eq!(Value::Int(3), eval("push(shared, 3)\nlen(both[\"b\"])"));
+        assert_eq!(Value::Int(0), eval("push(cycle, 1)\ncycle[1][1][0]"));
+        assert_eq!(Value::Int(11), eval("size(both[3]) + from_host()"));
+        assert_eq!(
+            "set([\"b\", 1, 2])",
+            eval("seen.add(2)\nseen_too").to_string()
+        );
+        assert_eq!(
+            "Point { x: 0, y: [0, 1] }",
+            eval("push(origin.y, 1)\nalso_origin = Point(x: also_origin.x, y: also_origin.y)")
+                .to_string()
+        );
+        assert_eq!(Value::Bool(true), eval("origin == also_origin"));
+        assert_eq!(
+            "[Circle(2), Dot, Circle(3)]",
+            eval("push(shapes, Circle(3))\nshapes").to_string()
+        );
+        assert_eq!(
+            Value::Int(2),
+            eval("match shapes[0] { Circle(r) => r, Dot => 0 }")
+        );
+        assert_eq!(
+            "{\"a\": [1, 2, 3], \"b\": [1, 2, 3], 3: 1..=4}",
+            after.get("both").unwrap().to_string()
+        );
+        assert!(after.eval("limit = 1").is_err());
+
+        // The restored state is a copy, apart from the one it came from.
+        assert_eq!(Value::Int(7), before.eval("counter()").unwrap());
+        assert_eq!(Value::Int(2), before.eval("len(shared)").unwrap());
+
+        assert_eq!(
+            "invalid snapshot: missing field `version` at line 1 column 2",
+            error(after.restore(b"{}"))
+        );
+        // A failed restore leaves the state as it was.
+        assert_eq!(Value::Int(8), after.eval("counter()").unwrap());
+        assert_eq!(
+            "snapshot refers to unknown builtin `host`",
+            error(Interpreter::new().restore(&snapshot))
+        );
+    }
+}
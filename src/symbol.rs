@@ -0,0 +1,145 @@
+//! Interned names. The parser turns every identifier into a [`Symbol`], so
+//! each distinct name is stored once and two symbols are the same name
+//! exactly when they point at the same string. Environments key their
+//! bindings by symbol, which makes looking a name up hash and compare a
+//! pointer instead of the whole string.
+//!
+//! Interned names live as long as the thread, which is fine for the
+//! identifiers in source code but not for arbitrary runtime strings.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::rc::Rc;
+
+use serde::{Serialize, Serializer};
+
+thread_local! {
+    static SYMBOLS: RefCell<HashSet<Rc<str>>> = RefCell::new(HashSet::new());
+}
+
+/// An interned name.
+#[derive(Clone)]
+pub struct Symbol(Rc<str>);
+
+impl Symbol {
+    /// The symbol for `name`, which is the same symbol every time.
+    pub fn intern(name: &str) -> Symbol {
+        SYMBOLS.with(|symbols| {
+            let mut symbols = symbols.borrow_mut();
+            if let Some(symbol) = symbols.get(name) {
+                return Symbol(Rc::clone(symbol));
+            }
+
+            let symbol: Rc<str> = Rc::from(name);
+            symbols.insert(Rc::clone(&symbol));
+            Symbol(symbol)
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Symbol {
+        Symbol::intern(name)
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Symbol) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(Rc::as_ptr(&self.0) as *const u8 as usize);
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// A map keyed by symbols. A symbol hashes as a single pointer, which
+/// doesn't need the protection against crafted keys that the standard
+/// hasher spends time on.
+pub type SymbolMap<V> = HashMap<Symbol, V, BuildHasherDefault<SymbolHasher>>;
+
+/// Hashes a symbol's pointer by multiplying it by a large odd constant,
+/// which spreads the bits that vary between allocations into the high
+/// bits the hash table uses.
+#[derive(Default)]
+pub struct SymbolHasher(u64);
+
+impl Hasher for SymbolHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 =
+                (self.0.rotate_left(5) ^ u64::from(*byte)).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+        }
+    }
+
+    fn write_usize(&mut self, value: usize) {
+        self.0 = (self.0.rotate_left(5) ^ value as u64).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Symbol, SymbolMap};
+
+    #[test]
+    fn test_interning() {
+        let a = Symbol::intern("tofu");
+        let b = Symbol::from((String::from("to") + "fu").as_str());
+        let c = Symbol::intern("tempeh");
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(a, "tofu");
+        assert_eq!("tofu", a.to_string());
+
+        let mut map = SymbolMap::default();
+        map.insert(a, 1);
+        map.insert(c, 2);
+        assert_eq!(Some(&1), map.get(&Symbol::intern("tofu")));
+        assert_eq!(None, map.get(&Symbol::intern("seitan")));
+    }
+}
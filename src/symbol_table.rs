@@ -0,0 +1,394 @@
+//! Where the compiler finds each name's value. Resolving a name when
+//! compiling gives it a [`Binding`]: a scope and an index into that scope's
+//! slots, so the code reads the value straight out of a slot instead of
+//! looking the name up in a chain of hash maps on every use.
+//!
+//! Names declared at the top level of a program are globals. Everything
+//! else, including the top level's nested blocks and loop variables, is a
+//! local of the function it's declared in, with parameters in the first
+//! slots. A function that uses a local of a function it's nested in
+//! captures it as a free variable, and the builtins a program refers to are
+//! numbered in the order it first does.
+
+use crate::ast::DeclarationKind;
+use crate::symbol::{Symbol, SymbolMap};
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Scope {
+    Global,
+    Local,
+    /// A variable of an enclosing function, captured by the current one.
+    Free,
+    Builtin,
+}
+
+/// What a name resolves to.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Binding {
+    pub name: Symbol,
+    pub scope: Scope,
+    pub index: usize,
+    /// How the name was declared, or `None` for a builtin or a global
+    /// that's used before it's declared.
+    pub kind: Option<DeclarationKind>,
+}
+
+/// The names declared in a function being compiled, which is the program
+/// itself at the top level.
+#[derive(Default)]
+struct FunctionScope {
+    /// The blocks open in the function, innermost last, with the names
+    /// declared in each so far.
+    blocks: Vec<SymbolMap<Binding>>,
+    /// The first slot not taken by a block that's still open.
+    next_slot: usize,
+    /// How many slots the function needs, which is the most that were
+    /// taken at once.
+    slots: usize,
+    /// The bindings of enclosing functions it captures, in the order of
+    /// their indexes as free variables.
+    captures: Vec<Binding>,
+}
+
+impl FunctionScope {
+    fn lookup(&self, name: &Symbol) -> Option<&Binding> {
+        self.blocks.iter().rev().find_map(|block| block.get(name))
+    }
+}
+
+pub struct SymbolTable {
+    /// The functions being compiled, innermost last, with the top level
+    /// first.
+    functions: Vec<FunctionScope>,
+    /// Every global's name, by index.
+    globals: Vec<Symbol>,
+    /// The names that resolve to builtins when nothing else declares them.
+    known_builtins: SymbolMap<()>,
+    /// The builtins used so far, by index.
+    builtins: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    /// A table for the top level of a program that can use the builtins
+    /// in `builtins`.
+    pub fn new<'a>(builtins: impl IntoIterator<Item = &'a str>) -> SymbolTable {
+        SymbolTable {
+            functions: vec![FunctionScope {
+                blocks: vec![SymbolMap::default()],
+                ..FunctionScope::default()
+            }],
+            globals: Vec::new(),
+            known_builtins: builtins
+                .into_iter()
+                .map(|name| (Symbol::from(name), ()))
+                .collect(),
+            builtins: Vec::new(),
+        }
+    }
+
+    fn function(&mut self) -> &mut FunctionScope {
+        self.functions
+            .last_mut()
+            .expect("there is always a function")
+    }
+
+    /// Whether declarations here are globals.
+    pub fn at_top_level(&self) -> bool {
+        self.functions.len() == 1 && self.functions[0].blocks.len() == 1
+    }
+
+    /// Starts compiling a function nested in the current one, with a block
+    /// for its parameters.
+    pub fn enter_function(&mut self) {
+        self.functions.push(FunctionScope {
+            blocks: vec![SymbolMap::default()],
+            ..FunctionScope::default()
+        });
+    }
+
+    /// Finishes compiling the current function, returning how many local
+    /// slots it needs and the bindings it captures, which are bindings of
+    /// the function it's nested in.
+    pub fn leave_function(&mut self) -> (usize, Vec<Binding>) {
+        assert!(self.functions.len() > 1, "can't leave the top level");
+        let function = self.functions.pop().expect("checked above");
+        (function.slots, function.captures)
+    }
+
+    /// How many local slots the top level needs for its nested blocks.
+    pub fn top_level_slots(&self) -> usize {
+        self.functions[0].slots
+    }
+
+    pub fn enter_block(&mut self) {
+        self.function().blocks.push(SymbolMap::default());
+    }
+
+    /// Ends the innermost block. The slots of its locals can be reused
+    /// after it.
+    pub fn leave_block(&mut self) {
+        let function = self.function();
+        assert!(function.blocks.len() > 1, "can't leave a function's block");
+        let block = function.blocks.pop().expect("checked above");
+        function.next_slot -= block.len();
+    }
+
+    /// Whether `name` is already declared in the innermost block, so
+    /// declaring it again reuses its slot.
+    pub fn declared_in_block(&self, name: &Symbol) -> bool {
+        let function = self.functions.last().expect("there is always a function");
+        let block = function.blocks.last().expect("there is always a block");
+        block
+            .get(name)
+            .is_some_and(|binding| binding.kind.is_some())
+    }
+
+    /// Declares `name` in the innermost block. Declaring a name again in
+    /// the same block keeps its slot, unless it's a constant, which can't
+    /// be redeclared.
+    pub fn define(&mut self, name: &Symbol, kind: DeclarationKind) -> Result<Binding, String> {
+        let global = self.at_top_level();
+        let function = self
+            .functions
+            .last_mut()
+            .expect("there is always a function");
+        let block = function.blocks.last_mut().expect("there is always a block");
+
+        if let Some(binding) = block.get_mut(name) {
+            if binding.kind == Some(DeclarationKind::Const) {
+                return Err(format!("cannot redeclare constant `{name}`"));
+            }
+            binding.kind = Some(kind);
+            return Ok(binding.clone());
+        }
+
+        let binding = if global {
+            self.globals.push(name.clone());
+            Binding {
+                name: name.clone(),
+                scope: Scope::Global,
+                index: self.globals.len() - 1,
+                kind: Some(kind),
+            }
+        } else {
+            let binding = Binding {
+                name: name.clone(),
+                scope: Scope::Local,
+                index: function.next_slot,
+                kind: Some(kind),
+            };
+            function.next_slot += 1;
+            function.slots = function.slots.max(function.next_slot);
+            binding
+        };
+
+        block.insert(name.clone(), binding.clone());
+        Ok(binding)
+    }
+
+    /// What `name` refers to at this point. A name nothing declares yet is
+    /// taken to be a global declared later, which is an error at runtime
+    /// if it never is.
+    pub fn resolve(&mut self, name: &Symbol) -> Binding {
+        let innermost = self.functions.len() - 1;
+        if let Some(binding) = self.resolve_in(innermost, name) {
+            return binding;
+        }
+
+        if self.known_builtins.contains_key(name) {
+            let index = match self.builtins.iter().position(|builtin| builtin == name) {
+                Some(index) => index,
+                None => {
+                    self.builtins.push(name.clone());
+                    self.builtins.len() - 1
+                }
+            };
+            return Binding {
+                name: name.clone(),
+                scope: Scope::Builtin,
+                index,
+                kind: None,
+            };
+        }
+
+        self.globals.push(name.clone());
+        let binding = Binding {
+            name: name.clone(),
+            scope: Scope::Global,
+            index: self.globals.len() - 1,
+            kind: None,
+        };
+        self.functions[0].blocks[0].insert(name.clone(), binding.clone());
+        binding
+    }
+
+    /// Resolves `name` in the function at `function` and the ones it's
+    /// nested in, capturing it into each function on the way back in.
+    fn resolve_in(&mut self, function: usize, name: &Symbol) -> Option<Binding> {
+        if let Some(binding) = self.functions[function].lookup(name) {
+            return Some(binding.clone());
+        }
+        if function == 0 {
+            return None;
+        }
+
+        let outer = self.resolve_in(function - 1, name)?;
+        if outer.scope == Scope::Global {
+            return Some(outer);
+        }
+
+        let captures = &mut self.functions[function].captures;
+        let index = match captures.iter().position(|capture| capture.name == *name) {
+            Some(index) => index,
+            None => {
+                captures.push(outer.clone());
+                captures.len() - 1
+            }
+        };
+        Some(Binding {
+            scope: Scope::Free,
+            index,
+            ..outer
+        })
+    }
+
+    /// Every global's name, by index.
+    pub fn globals(&self) -> &[Symbol] {
+        &self.globals
+    }
+
+    /// The builtins used, by index.
+    pub fn builtins(&self) -> &[Symbol] {
+        &self.builtins
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Binding, Scope, SymbolTable};
+    use crate::ast::DeclarationKind::{Const, Let};
+    use crate::symbol::Symbol;
+
+    fn binding(name: &str, scope: Scope, index: usize) -> Binding {
+        Binding {
+            name: name.into(),
+            scope,
+            index,
+            kind: Some(Let),
+        }
+    }
+
+    #[test]
+    fn test_globals_and_locals() {
+        let mut table = SymbolTable::new([]);
+        let (a, b, c) = (Symbol::from("a"), Symbol::from("b"), Symbol::from("c"));
+
+        assert_eq!(Ok(binding("a", Scope::Global, 0)), table.define(&a, Let));
+        table.enter_block();
+        assert_eq!(Ok(binding("b", Scope::Local, 0)), table.define(&b, Let));
+        assert_eq!(Ok(binding("a", Scope::Local, 1)), table.define(&a, Let));
+        assert_eq!(binding("a", Scope::Local, 1), table.resolve(&a));
+        table.leave_block();
+
+        // The block's slots are free again, but the top level still needs
+        // two.
+        table.enter_block();
+        assert_eq!(Ok(binding("c", Scope::Local, 0)), table.define(&c, Let));
+        table.leave_block();
+        assert_eq!(2, table.top_level_slots());
+
+        assert_eq!(binding("a", Scope::Global, 0), table.resolve(&a));
+        assert_eq!(vec![a], table.globals());
+    }
+
+    #[test]
+    fn test_free_variables() {
+        let mut table = SymbolTable::new([]);
+        let names: Vec<Symbol> = ["g", "a", "b", "c"].map(Symbol::from).to_vec();
+        let [g, a, b, c] = &names[..] else {
+            unreachable!()
+        };
+
+        table.define(g, Let).unwrap();
+        table.enter_function();
+        table.define(a, Let).unwrap();
+        table.enter_function();
+        table.define(b, Let).unwrap();
+        table.enter_function();
+        table.define(c, Let).unwrap();
+
+        assert_eq!(binding("g", Scope::Global, 0), table.resolve(g));
+        assert_eq!(binding("b", Scope::Free, 0), table.resolve(b));
+        assert_eq!(binding("a", Scope::Free, 1), table.resolve(a));
+        assert_eq!(binding("c", Scope::Local, 0), table.resolve(c));
+        assert_eq!(binding("a", Scope::Free, 1), table.resolve(a));
+
+        // The innermost function captures `b` from the middle one, which
+        // captures `a` from the outermost one in turn.
+        let (slots, captures) = table.leave_function();
+        assert_eq!(1, slots);
+        assert_eq!(
+            vec![binding("b", Scope::Local, 0), binding("a", Scope::Free, 0)],
+            captures
+        );
+        let (_, captures) = table.leave_function();
+        assert_eq!(vec![binding("a", Scope::Local, 0)], captures);
+        let (_, captures) = table.leave_function();
+        assert_eq!(Vec::<Binding>::new(), captures);
+    }
+
+    #[test]
+    fn test_builtins_and_undeclared_names() {
+        let mut table = SymbolTable::new(["len", "print"]);
+        let (print, len, later) = (
+            Symbol::from("print"),
+            Symbol::from("len"),
+            Symbol::from("later"),
+        );
+
+        table.enter_function();
+        let builtin = |name: &str, index| Binding {
+            kind: None,
+            ..binding(name, Scope::Builtin, index)
+        };
+        assert_eq!(builtin("print", 0), table.resolve(&print));
+        assert_eq!(builtin("len", 1), table.resolve(&len));
+        assert_eq!(builtin("print", 0), table.resolve(&print));
+
+        let undeclared = Binding {
+            kind: None,
+            ..binding("later", Scope::Global, 0)
+        };
+        assert_eq!(undeclared, table.resolve(&later));
+        table.leave_function();
+
+        // Declaring the global later gives it the slot it was used with.
+        assert_eq!(
+            Ok(binding("later", Scope::Global, 0)),
+            table.define(&later, Let)
+        );
+        assert_eq!(vec![print, len], table.builtins());
+    }
+
+    #[test]
+    fn test_redeclaring() {
+        let mut table = SymbolTable::new([]);
+        let x = Symbol::from("x");
+
+        table.enter_block();
+        assert!(!table.declared_in_block(&x));
+        table.define(&x, Let).unwrap();
+        assert!(table.declared_in_block(&x));
+        assert_eq!(
+            Ok(Binding {
+                kind: Some(Const),
+                ..binding("x", Scope::Local, 0)
+            }),
+            table.define(&x, Const)
+        );
+        assert_eq!(
+            Err("cannot redeclare constant `x`".to_string()),
+            table.define(&x, Let)
+        );
+    }
+}
@@ -1,85 +1,255 @@
+use std::borrow::Cow;
 use std::fmt::Display;
 
-#[derive(PartialEq, Debug)]
-pub struct Token {
+use serde::Serialize;
+
+/// A lexed token. Kinds with a fixed spelling, like `+` or `let`, carry no
+/// literal of their own; use [`Token::literal_str`] to get the text either
+/// way.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Token<'a> {
     pub kind: TokenKind,
-    pub literal: String,
+    pub literal: Option<Cow<'a, str>>,
+    pub span: Span,
+}
+
+impl Token<'_> {
+    /// The token's text, falling back to its kind's fixed spelling.
+    pub fn literal_str(&self) -> &str {
+        match &self.literal {
+            Some(literal) => literal,
+            None => self.kind.spelling().unwrap_or_default(),
+        }
+    }
+
+    /// Detaches the token from the source it was lexed from.
+    pub fn into_owned(self) -> Token<'static> {
+        Token {
+            kind: self.kind,
+            literal: self.literal.map(|literal| Cow::Owned(literal.into_owned())),
+            span: self.span,
+        }
+    }
+}
+
+/// Where a token starts in the source, both 1-based.
+#[derive(PartialEq, Debug, Clone, Copy, Default, Serialize)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
 }
 
-#[derive(PartialEq, Debug)]
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum TokenKind {
     Illegal,
     Identifier,
     Int,
+    Float,
+    Str,
+    Char,
+    DocComment,
     Assign,
+    FatArrow,
+    /// `->`, before a function's return type.
+    Arrow,
+    PlusAssign,
+    MinusAssign,
+    AsteriskAssign,
+    SlashAssign,
     Eq,
     NotEq,
     Plus,
     Minus,
     Bang,
     Asterisk,
+    AsteriskAsterisk,
     Slash,
     LessThan,
     GreaterThan,
+    LessThanEq,
+    GreaterThanEq,
+    Ampersand,
+    Pipe,
+    PipeGreater,
+    Caret,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Or,
+    Question,
+    QuestionQuestion,
+    QuestionDot,
+    QuestionBracket,
     Comma,
+    Colon,
+    Dot,
+    DotDot,
+    DotDotEq,
+    DotDotDot,
     Semicolon,
     LeftParen,
     RightParen,
+    LeftBracket,
+    RightBracket,
     LeftBrace,
     RightBrace,
     If,
     Else,
     True,
     False,
+    Null,
     Return,
+    Match,
+    While,
+    For,
+    In,
+    Break,
+    Continue,
+    Try,
+    Catch,
+    Throw,
     Fn,
     Let,
+    Const,
+    Import,
+    Struct,
+    Enum,
     Eof,
 }
 
+impl TokenKind {
+    /// How a token of this kind is always written, or `None` for kinds like
+    /// identifiers whose text varies from token to token.
+    pub fn spelling(&self) -> Option<&'static str> {
+        let spelling = match self {
+            TokenKind::Assign => "=",
+            TokenKind::FatArrow => "=>",
+            TokenKind::Arrow => "->",
+            TokenKind::PlusAssign => "+=",
+            TokenKind::MinusAssign => "-=",
+            TokenKind::AsteriskAssign => "*=",
+            TokenKind::SlashAssign => "/=",
+            TokenKind::Eq => "==",
+            TokenKind::NotEq => "!=",
+            TokenKind::Plus => "+",
+            TokenKind::Minus => "-",
+            TokenKind::Bang => "!",
+            TokenKind::Asterisk => "*",
+            TokenKind::AsteriskAsterisk => "**",
+            TokenKind::Slash => "/",
+            TokenKind::LessThan => "<",
+            TokenKind::GreaterThan => ">",
+            TokenKind::LessThanEq => "<=",
+            TokenKind::GreaterThanEq => ">=",
+            TokenKind::Ampersand => "&",
+            TokenKind::Pipe => "|",
+            TokenKind::PipeGreater => "|>",
+            TokenKind::Caret => "^",
+            TokenKind::ShiftLeft => "<<",
+            TokenKind::ShiftRight => ">>",
+            TokenKind::And => "&&",
+            TokenKind::Or => "||",
+            TokenKind::Question => "?",
+            TokenKind::QuestionQuestion => "??",
+            TokenKind::QuestionDot => "?.",
+            TokenKind::QuestionBracket => "?[",
+            TokenKind::Comma => ",",
+            TokenKind::Colon => ":",
+            TokenKind::Dot => ".",
+            TokenKind::DotDot => "..",
+            TokenKind::DotDotEq => "..=",
+            TokenKind::DotDotDot => "...",
+            TokenKind::Semicolon => ";",
+            TokenKind::LeftParen => "(",
+            TokenKind::RightParen => ")",
+            TokenKind::LeftBracket => "[",
+            TokenKind::RightBracket => "]",
+            TokenKind::LeftBrace => "{",
+            TokenKind::RightBrace => "}",
+            TokenKind::If => "if",
+            TokenKind::Else => "else",
+            TokenKind::True => "true",
+            TokenKind::False => "false",
+            TokenKind::Null => "null",
+            TokenKind::Return => "return",
+            TokenKind::Match => "match",
+            TokenKind::While => "while",
+            TokenKind::For => "for",
+            TokenKind::In => "in",
+            TokenKind::Break => "break",
+            TokenKind::Continue => "continue",
+            TokenKind::Try => "try",
+            TokenKind::Catch => "catch",
+            TokenKind::Throw => "throw",
+            TokenKind::Fn => "fn",
+            TokenKind::Let => "let",
+            TokenKind::Const => "const",
+            TokenKind::Import => "import",
+            TokenKind::Struct => "struct",
+            TokenKind::Enum => "enum",
+            TokenKind::Illegal
+            | TokenKind::Identifier
+            | TokenKind::Int
+            | TokenKind::Float
+            | TokenKind::Str
+            | TokenKind::Char
+            | TokenKind::DocComment
+            | TokenKind::Eof => return None,
+        };
+
+        Some(spelling)
+    }
+}
+
 impl Display for TokenKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(spelling) = self.spelling() {
+            return write!(f, "{spelling}");
+        }
+
         match self {
             TokenKind::Illegal => write!(f, "illegal"),
             TokenKind::Identifier => write!(f, "identifier"),
             TokenKind::Int => write!(f, "int"),
-            TokenKind::Assign => write!(f, "="),
-            TokenKind::Eq => write!(f, "=="),
-            TokenKind::NotEq => write!(f, "!="),
-            TokenKind::Plus => write!(f, "+"),
-            TokenKind::Minus => write!(f, "-"),
-            TokenKind::Bang => write!(f, "!"),
-            TokenKind::Asterisk => write!(f, "*"),
-            TokenKind::Slash => write!(f, "/"),
-            TokenKind::LessThan => write!(f, "<"),
-            TokenKind::GreaterThan => write!(f, ">"),
-            TokenKind::Comma => write!(f, ","),
-            TokenKind::Semicolon => write!(f, ";"),
-            TokenKind::LeftParen => write!(f, "("),
-            TokenKind::RightParen => write!(f, ")"),
-            TokenKind::LeftBrace => write!(f, "{{"),
-            TokenKind::RightBrace => write!(f, "}}"),
-            TokenKind::If => write!(f, "if"),
-            TokenKind::Else => write!(f, "else"),
-            TokenKind::True => write!(f, "true"),
-            TokenKind::False => write!(f, "false"),
-            TokenKind::Return => write!(f, "return"),
-            TokenKind::Fn => write!(f, "fn"),
-            TokenKind::Let => write!(f, "let"),
+            TokenKind::Float => write!(f, "float"),
+            TokenKind::Str => write!(f, "string"),
+            TokenKind::Char => write!(f, "char"),
+            TokenKind::DocComment => write!(f, "doc comment"),
             TokenKind::Eof => write!(f, "Eof"),
+            _ => unreachable!("{self:?} has a fixed spelling"),
         }
     }
 }
 
-pub fn lookup_identifier(identifier: &String) -> TokenKind {
-    match identifier.as_str() {
+pub fn lookup_identifier(identifier: &str) -> TokenKind {
+    match identifier {
         "fn" => TokenKind::Fn,
         "let" => TokenKind::Let,
+        "const" => TokenKind::Const,
         "if" => TokenKind::If,
         "else" => TokenKind::Else,
         "true" => TokenKind::True,
         "false" => TokenKind::False,
+        "null" => TokenKind::Null,
         "return" => TokenKind::Return,
+        "match" => TokenKind::Match,
+        "while" => TokenKind::While,
+        "for" => TokenKind::For,
+        "in" => TokenKind::In,
+        "break" => TokenKind::Break,
+        "continue" => TokenKind::Continue,
+        "try" => TokenKind::Try,
+        "catch" => TokenKind::Catch,
+        "throw" => TokenKind::Throw,
+        "struct" => TokenKind::Struct,
+        "enum" => TokenKind::Enum,
+        "import" => TokenKind::Import,
         _ => TokenKind::Identifier,
     }
 }
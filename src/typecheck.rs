@@ -0,0 +1,1525 @@
+//! A static check of a program's types, for `tofu check --types` and
+//! [`Interpreter::check_types`](crate::Interpreter::check_types), which
+//! finds some of the errors running the program would raise without
+//! running it.
+//!
+//! Types are inferred in the style of Hindley and Milner: a binding
+//! without an annotation gets a type variable, which what's done with it
+//! pins down, by unifying the types it meets. So the `x` in
+//! `let double = fn(x) { x * 2 }` is known to be a number, and a
+//! `let`-bound function is generic in what it doesn't pin down, so
+//! `let id = fn(x) { x }` can be given anything and returns what it's
+//! given. Since an operator like
+//! `+` works on more than one type, a variable can be constrained to the
+//! types an operator works on before it's pinned to one.
+//!
+//! Where unifying would reject a program that runs fine, the checker
+//! gives up instead, with the type `any`, which never mismatches: the two
+//! branches of an `if` can have different types, and a `let` without an
+//! annotation that's assigned to again could be anything.
+
+use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
+
+use crate::ast::{
+    with_stack, BlockStatement, Expression, InfixOperator, LetStatement, Parameters, Pattern,
+    PrefixOperator, Program, Statement, TypeAnnotation,
+};
+use crate::builtins::{Arity, Builtins};
+use crate::resolve::pattern_names;
+use crate::symbol::{Symbol, SymbolMap};
+use crate::token::Span;
+use crate::value::Value;
+use crate::visit::{walk_expression, Visit};
+
+/// The type of a value, as far as it's known before the program runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    /// A value nothing is known about, which matches every type.
+    Any,
+    Int,
+    Float,
+    Bool,
+    Str,
+    Char,
+    Null,
+    Range,
+    Array(Box<Type>),
+    Hash(Box<Type>, Box<Type>),
+    Function {
+        parameters: Vec<Type>,
+        /// How many of the parameters have no default.
+        required: usize,
+        /// Whether a rest parameter takes any more arguments.
+        variadic: bool,
+        result: Box<Type>,
+    },
+    /// A type that's yet to be inferred, or that a generic function is
+    /// generic in, shown as `'a`, `'b` and so on.
+    Variable(u32),
+}
+
+impl Type {
+    /// The type an annotation names.
+    pub fn from_annotation(annotation: &TypeAnnotation) -> Result<Type, TypeError> {
+        match annotation {
+            TypeAnnotation::Named {
+                name,
+                arguments,
+                span,
+            } => {
+                let arguments = arguments
+                    .iter()
+                    .map(Type::from_annotation)
+                    .collect::<Result<Vec<Type>, TypeError>>()?;
+                let expected = match name.as_str() {
+                    "array" => 1,
+                    "hash" => 2,
+                    _ => 0,
+                };
+                if arguments.len() != expected {
+                    let plural = if expected == 1 { "" } else { "s" };
+                    return Err(TypeError::new(
+                        format!(
+                            "`{name}` takes {expected} type argument{plural}, but is given {}",
+                            arguments.len()
+                        ),
+                        Some(*span),
+                    ));
+                }
+                let mut arguments = arguments.into_iter().map(Box::new);
+                let mut argument = || {
+                    arguments
+                        .next()
+                        .expect("Should have counted the arguments.")
+                };
+                Ok(match name.as_str() {
+                    "any" => Type::Any,
+                    "int" => Type::Int,
+                    "float" => Type::Float,
+                    "bool" => Type::Bool,
+                    "str" => Type::Str,
+                    "char" => Type::Char,
+                    "null" => Type::Null,
+                    "range" => Type::Range,
+                    "array" => Type::Array(argument()),
+                    "hash" => Type::Hash(argument(), argument()),
+                    _ => {
+                        return Err(TypeError::new(
+                            format!("unknown type `{name}`"),
+                            Some(*span),
+                        ))
+                    }
+                })
+            }
+            TypeAnnotation::Function { parameters, result } => Ok(Type::Function {
+                parameters: parameters
+                    .iter()
+                    .map(Type::from_annotation)
+                    .collect::<Result<_, _>>()?,
+                required: parameters.len(),
+                variadic: false,
+                result: Box::new(Type::from_annotation(result)?),
+            }),
+        }
+    }
+
+    /// Whether a value of type `other` can be used where one of this type
+    /// is expected. An Int can be used as a Float, as the operators
+    /// convert it, and a type variable could be anything.
+    pub fn accepts(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Any | Type::Variable(_), _)
+            | (_, Type::Any | Type::Variable(_))
+            | (Type::Float, Type::Int) => true,
+            (Type::Array(expected), Type::Array(element)) => expected.accepts(element),
+            (Type::Hash(expected_key, expected_value), Type::Hash(key, value)) => {
+                expected_key.accepts(key) && expected_value.accepts(value)
+            }
+            (
+                Type::Function {
+                    parameters: expected_parameters,
+                    result: expected_result,
+                    ..
+                },
+                Type::Function {
+                    parameters, result, ..
+                },
+            ) => {
+                expected_parameters.len() == parameters.len()
+                    && expected_parameters
+                        .iter()
+                        .zip(parameters)
+                        .all(|(expected, parameter)| parameter.accepts(expected))
+                    && expected_result.accepts(result)
+            }
+            (expected, other) => expected == other,
+        }
+    }
+
+    /// The type without what's in it, if it's an array or hash, whose
+    /// elements can change.
+    fn shallow(self) -> Type {
+        match self {
+            Type::Array(_) => Type::Array(Box::new(Type::Any)),
+            Type::Hash(..) => Type::Hash(Box::new(Type::Any), Box::new(Type::Any)),
+            other => other,
+        }
+    }
+
+    fn is_number(&self) -> bool {
+        matches!(self, Type::Int | Type::Float)
+    }
+
+    /// Calls `f` with each type variable in the type, outermost and
+    /// leftmost first.
+    fn each_variable(&self, f: &mut impl FnMut(u32)) {
+        match self {
+            Type::Variable(variable) => f(*variable),
+            Type::Array(element) => element.each_variable(f),
+            Type::Hash(key, value) => {
+                key.each_variable(f);
+                value.each_variable(f);
+            }
+            Type::Function {
+                parameters, result, ..
+            } => {
+                for parameter in parameters {
+                    parameter.each_variable(f);
+                }
+                result.each_variable(f);
+            }
+            _ => {}
+        }
+    }
+
+    /// The type with each type variable replaced by what `f` makes of it.
+    fn map_variables(&self, f: &mut impl FnMut(u32) -> Type) -> Type {
+        match self {
+            Type::Variable(variable) => f(*variable),
+            Type::Array(element) => Type::Array(Box::new(element.map_variables(f))),
+            Type::Hash(key, value) => Type::Hash(
+                Box::new(key.map_variables(f)),
+                Box::new(value.map_variables(f)),
+            ),
+            Type::Function {
+                parameters,
+                required,
+                variadic,
+                result,
+            } => Type::Function {
+                parameters: parameters
+                    .iter()
+                    .map(|parameter| parameter.map_variables(f))
+                    .collect(),
+                required: *required,
+                variadic: *variadic,
+                result: Box::new(result.map_variables(f)),
+            },
+            other => other.clone(),
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "any"),
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::Str => write!(f, "str"),
+            Type::Char => write!(f, "char"),
+            Type::Null => write!(f, "null"),
+            Type::Range => write!(f, "range"),
+            Type::Array(element) => write!(f, "array<{element}>"),
+            Type::Hash(key, value) => write!(f, "hash<{key}, {value}>"),
+            Type::Function {
+                parameters,
+                required,
+                variadic,
+                result,
+            } => {
+                write!(f, "fn(")?;
+                for (index, parameter) in parameters.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{parameter}")?;
+                    if index >= *required {
+                        write!(f, "?")?;
+                    }
+                }
+                if *variadic {
+                    if !parameters.is_empty() {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "...")?;
+                }
+                write!(f, ") -> {result}")
+            }
+            Type::Variable(variable) => match u8::try_from(*variable) {
+                Ok(letter @ 0..26) => write!(f, "'{}", char::from(b'a' + letter)),
+                _ => write!(f, "'t{variable}"),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub message: String,
+    /// Where the problem is, if the syntax tree records where anything
+    /// near it is.
+    pub span: Option<Span>,
+}
+
+impl TypeError {
+    fn new(message: impl Into<String>, span: Option<Span>) -> TypeError {
+        TypeError {
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+impl Display for TypeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{span}: {}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+/// What [`infer`] works out about a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inference {
+    pub errors: Vec<TypeError>,
+    /// The type of each name a `let` or a function's parameter binds, in
+    /// the order they're bound, with where it's bound.
+    pub bindings: Vec<(Symbol, Span, Type)>,
+    /// The type of the program's value, the value of its last statement.
+    pub value: Type,
+}
+
+/// The type errors in `program`, which can call `builtins`.
+pub fn check(program: &Program, builtins: &Builtins) -> Vec<TypeError> {
+    infer(program, builtins).errors
+}
+
+/// Infers the types in `program`, which can call `builtins`. The type
+/// variables in each type it returns are numbered from `'a`.
+pub fn infer(program: &Program, builtins: &Builtins) -> Inference {
+    let mut assignments = Assignments::default();
+    assignments.visit_program(program);
+
+    let mut checker = Checker {
+        builtins,
+        scopes: vec![Scope::default()],
+        reassigned: assignments.names,
+        returns: Vec::new(),
+        variables: Vec::new(),
+        trail: Vec::new(),
+        bindings: Vec::new(),
+        errors: Vec::new(),
+    };
+    let value = checker.statements(&program.statements);
+
+    let bindings = std::mem::take(&mut checker.bindings)
+        .into_iter()
+        .map(|(name, span, binding)| {
+            let [binding] = checker.normalize([&binding]);
+            (name, span, binding)
+        })
+        .collect();
+    let [value] = checker.normalize([&value]);
+    Inference {
+        errors: checker.errors,
+        bindings,
+        value,
+    }
+}
+
+/// Finds the names assigned to after they're bound.
+#[derive(Default)]
+struct Assignments {
+    names: SymbolMap<()>,
+}
+
+impl Visit for Assignments {
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let Expression::Assign { target, .. } = expression {
+            if let Expression::Identifier { name, .. } = &**target {
+                self.names.insert(name.clone(), ());
+            }
+        }
+        walk_expression(self, expression);
+    }
+}
+
+/// The types a type variable can still be pinned to, each set a subset of
+/// the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Constraint {
+    /// An int or float, for arithmetic.
+    Number,
+    /// A number or str, for `+`.
+    Addable,
+    /// A number, str or char, for `<` and the like.
+    Comparable,
+    Unconstrained,
+}
+
+impl Constraint {
+    fn allows(self, other: &Type) -> bool {
+        match self {
+            _ if *other == Type::Any => true,
+            Constraint::Number => other.is_number(),
+            Constraint::Addable => other.is_number() || *other == Type::Str,
+            Constraint::Comparable => other.is_number() || matches!(other, Type::Str | Type::Char),
+            Constraint::Unconstrained => true,
+        }
+    }
+
+    /// How a type variable with the constraint is described in messages.
+    fn describe(self) -> Option<&'static str> {
+        match self {
+            Constraint::Number => Some("a number"),
+            Constraint::Addable => Some("a number or str"),
+            Constraint::Comparable => Some("a number, str or char"),
+            Constraint::Unconstrained => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Variable {
+    /// The type the variable has been unified with, if it has.
+    bound: Option<Type>,
+    constraint: Constraint,
+    /// Whether the variable is a parameter's type, which taking one
+    /// branch or another of an `if` says nothing about.
+    parameter: bool,
+}
+
+/// The type of a binding, generic in the `quantified` type variables,
+/// which each use of the binding replaces with fresh ones.
+#[derive(Clone)]
+struct Scheme {
+    quantified: Vec<u32>,
+    binding: Type,
+}
+
+#[derive(Default)]
+struct Scope {
+    bindings: SymbolMap<Scheme>,
+    /// The signatures of the functions the scope's `let`s bind that
+    /// haven't been checked yet, which calls before they're checked use.
+    pending: SymbolMap<Type>,
+}
+
+struct Checker<'b> {
+    builtins: &'b Builtins,
+    /// The bindings in each scope around the current node, outermost
+    /// first.
+    scopes: Vec<Scope>,
+    /// The names assigned to anywhere, whose `let` bindings without an
+    /// annotation can't be trusted to keep their value's type.
+    reassigned: SymbolMap<()>,
+    /// The types of what each function being checked returns with
+    /// `return`, innermost last.
+    returns: Vec<Vec<Type>>,
+    /// Every type variable made, by number.
+    variables: Vec<Variable>,
+    /// Each variable changed by unifying, with what it was before, for
+    /// undoing a unification that fails halfway.
+    trail: Vec<(u32, Variable)>,
+    bindings: Vec<(Symbol, Span, Type)>,
+    errors: Vec<TypeError>,
+}
+
+impl Checker<'_> {
+    fn error(&mut self, message: String, span: Option<Span>) {
+        self.errors.push(TypeError::new(message, span));
+    }
+
+    fn fresh(&mut self, constraint: Constraint, parameter: bool) -> Type {
+        let variable = u32::try_from(self.variables.len()).expect("Should have few variables.");
+        self.variables.push(Variable {
+            bound: None,
+            constraint,
+            parameter,
+        });
+        Type::Variable(variable)
+    }
+
+    fn set(&mut self, variable: u32, state: Variable) {
+        let old = std::mem::replace(&mut self.variables[variable as usize], state);
+        self.trail.push((variable, old));
+    }
+
+    /// The type, with the type variable it is, if it is one, followed to
+    /// what it's been unified with.
+    fn head(&self, mut of: Type) -> Type {
+        while let Type::Variable(variable) = of {
+            match &self.variables[variable as usize].bound {
+                Some(bound) => of = bound.clone(),
+                None => break,
+            }
+        }
+        of
+    }
+
+    /// The unbound type variable the type is, if it is one.
+    fn unbound(&self, of: &Type) -> Option<u32> {
+        match self.head(of.clone()) {
+            Type::Variable(variable) => Some(variable),
+            _ => None,
+        }
+    }
+
+    /// The type with every type variable in it that's been unified with
+    /// something replaced by that.
+    fn resolve(&self, of: &Type) -> Type {
+        of.map_variables(
+            &mut |variable| match &self.variables[variable as usize].bound {
+                Some(bound) => self.resolve(bound),
+                None => Type::Variable(variable),
+            },
+        )
+    }
+
+    /// Makes `expected` and `actual` the same type, if they can be, by
+    /// pinning down the type variables in them. A failure can leave some
+    /// pinned down, so it's for [`Checker::unify`] to call.
+    fn unify_types(&mut self, expected: &Type, actual: &Type) -> bool {
+        match (self.head(expected.clone()), self.head(actual.clone())) {
+            (Type::Any, _) | (_, Type::Any) | (Type::Float, Type::Int) => true,
+            (Type::Variable(left), Type::Variable(right)) if left == right => true,
+            (Type::Variable(variable), other) | (other, Type::Variable(variable)) => {
+                self.bind(variable, other)
+            }
+            (Type::Array(expected), Type::Array(element)) => self.unify_types(&expected, &element),
+            (Type::Hash(expected_key, expected_value), Type::Hash(key, value)) => {
+                self.unify_types(&expected_key, &key) && self.unify_types(&expected_value, &value)
+            }
+            (
+                Type::Function {
+                    parameters: expected_parameters,
+                    result: expected_result,
+                    ..
+                },
+                Type::Function {
+                    parameters, result, ..
+                },
+            ) => {
+                expected_parameters.len() == parameters.len()
+                    && expected_parameters
+                        .iter()
+                        .zip(&parameters)
+                        .all(|(expected, parameter)| self.unify_types(parameter, expected))
+                    && self.unify_types(&expected_result, &result)
+            }
+            (expected, actual) => expected == actual,
+        }
+    }
+
+    /// Binds the unbound type `variable` to `to`, if its constraint allows.
+    fn bind(&mut self, variable: u32, to: Type) -> bool {
+        let state = self.variables[variable as usize].clone();
+        if let Type::Variable(other) = to {
+            let mut merged = self.variables[other as usize].clone();
+            merged.constraint = merged.constraint.min(state.constraint);
+            merged.parameter |= state.parameter;
+            self.set(other, merged);
+        } else {
+            let mut occurs = false;
+            self.resolve(&to)
+                .each_variable(&mut |inner| occurs |= inner == variable);
+            if occurs || !state.constraint.allows(&to) {
+                return false;
+            }
+        }
+        self.set(
+            variable,
+            Variable {
+                bound: Some(to),
+                ..state
+            },
+        );
+        true
+    }
+
+    /// Makes `expected` and `actual` the same type if they can be, and
+    /// otherwise leaves them as they were.
+    fn unify(&mut self, expected: &Type, actual: &Type) -> bool {
+        let mark = self.trail.len();
+        if self.unify_types(expected, actual) {
+            return true;
+        }
+        while self.trail.len() > mark {
+            let (variable, old) = self.trail.pop().expect("Should be past the mark.");
+            self.variables[variable as usize] = old;
+        }
+        false
+    }
+
+    /// Narrows what the type, if it's an unbound type variable, can be.
+    fn constrain(&mut self, of: &Type, constraint: Constraint) {
+        if let Some(variable) = self.unbound(of) {
+            let mut state = self.variables[variable as usize].clone();
+            state.constraint = state.constraint.min(constraint);
+            self.set(variable, state);
+        }
+    }
+
+    /// The type of a value that could be of either type: the one they
+    /// unify to, unless that would pin down a parameter's type, or else
+    /// `any`.
+    fn join(&mut self, left: Type, right: Type) -> Type {
+        let pins_parameter = |checker: &Checker, of: &Type| {
+            checker
+                .unbound(of)
+                .is_some_and(|variable| checker.variables[variable as usize].parameter)
+        };
+        if self.resolve(&left) == self.resolve(&right) {
+            left
+        } else if self.head(left.clone()) == Type::Any
+            || self.head(right.clone()) == Type::Any
+            || pins_parameter(self, &left)
+            || pins_parameter(self, &right)
+            || !self.unify(&left, &right)
+        {
+            Type::Any
+        } else {
+            left
+        }
+    }
+
+    fn join_all(&mut self, types: Vec<Type>) -> Option<Type> {
+        types
+            .into_iter()
+            .reduce(|left, right| self.join(left, right))
+    }
+
+    /// The types resolved, with their unbound type variables renumbered
+    /// from `'a` in the order they appear.
+    fn normalize<const N: usize>(&self, types: [&Type; N]) -> [Type; N] {
+        let mut order = Vec::new();
+        types.map(|of| {
+            self.resolve(of).map_variables(&mut |variable| {
+                let index = match order.iter().position(|&seen| seen == variable) {
+                    Some(index) => index,
+                    None => {
+                        order.push(variable);
+                        order.len() - 1
+                    }
+                };
+                Type::Variable(u32::try_from(index).expect("Should have few variables."))
+            })
+        })
+    }
+
+    /// The types as messages show them, a constrained type variable by
+    /// what it can be.
+    fn describe<const N: usize>(&self, types: [&Type; N]) -> [String; N] {
+        let normalized = self.normalize(types);
+        let mut index = 0;
+        normalized.map(|of| {
+            let description = self
+                .unbound(types[index])
+                .and_then(|variable| self.variables[variable as usize].constraint.describe());
+            index += 1;
+            description.map_or_else(|| of.to_string(), str::to_string)
+        })
+    }
+
+    fn declare(&mut self, name: &Symbol, declared: Type) {
+        self.declare_scheme(
+            name,
+            Scheme {
+                quantified: Vec::new(),
+                binding: declared,
+            },
+        );
+    }
+
+    fn declare_scheme(&mut self, name: &Symbol, scheme: Scheme) {
+        self.scopes
+            .last_mut()
+            .expect("Should be in a scope.")
+            .bindings
+            .insert(name.clone(), scheme);
+    }
+
+    fn declare_pattern(&mut self, pattern: &Pattern, declared: Type) {
+        match pattern {
+            Pattern::Identifier(name) => self.declare(name, declared),
+            pattern => {
+                for name in pattern_names(pattern) {
+                    self.declare(&name, Type::Any);
+                }
+            }
+        }
+    }
+
+    /// The type of a use of `name`, with the type variables its binding
+    /// is generic in replaced by fresh ones.
+    fn lookup(&mut self, name: &Symbol) -> Type {
+        let Some(scheme) = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.bindings.get(name))
+            .cloned()
+        else {
+            return Type::Any;
+        };
+        let fresh: Vec<(u32, Type)> = scheme
+            .quantified
+            .iter()
+            .map(|&variable| {
+                let constraint = self.variables[variable as usize].constraint;
+                (variable, self.fresh(constraint, false))
+            })
+            .collect();
+        self.resolve(&scheme.binding)
+            .map_variables(&mut |variable| {
+                fresh
+                    .iter()
+                    .find(|(quantified, _)| *quantified == variable)
+                    .map_or(Type::Variable(variable), |(_, fresh)| fresh.clone())
+            })
+    }
+
+    /// The scheme for a function bound to `name` with type `binding`,
+    /// generic in the type variables nothing else in scope has.
+    fn generalize(&self, name: &Symbol, binding: Type) -> Scheme {
+        let mut free = HashSet::new();
+        let innermost = self.scopes.len() - 1;
+        for (depth, scope) in self.scopes.iter().enumerate() {
+            for (bound, scheme) in &scope.bindings {
+                if depth == innermost && bound == name {
+                    continue;
+                }
+                self.resolve(&scheme.binding)
+                    .each_variable(&mut |variable| {
+                        if !scheme.quantified.contains(&variable) {
+                            free.insert(variable);
+                        }
+                    });
+            }
+        }
+        let mut quantified = Vec::new();
+        self.resolve(&binding).each_variable(&mut |variable| {
+            if !free.contains(&variable) && !quantified.contains(&variable) {
+                quantified.push(variable);
+            }
+        });
+        Scheme {
+            quantified,
+            binding,
+        }
+    }
+
+    /// The type `annotation` names, or `any` if it names none.
+    fn annotation(&mut self, annotation: &TypeAnnotation) -> Type {
+        Type::from_annotation(annotation).unwrap_or_else(|error| {
+            self.errors.push(error);
+            Type::Any
+        })
+    }
+
+    /// Checks `statements`, returning the type of the value of the last.
+    fn statements(&mut self, statements: &[Statement]) -> Type {
+        // Functions can be called before they're defined, and call
+        // themselves, so their signatures are known from the start, as
+        // long as nothing else in the scope has their names.
+        let mut counts: SymbolMap<usize> = SymbolMap::default();
+        for statement in statements {
+            if let Statement::Let(statement) = statement {
+                for name in pattern_names(&statement.pattern) {
+                    *counts.entry(name).or_default() += 1;
+                }
+            }
+        }
+        for statement in statements {
+            if let Statement::Let(LetStatement {
+                pattern: Pattern::Identifier(name),
+                value: Expression::Function { parameters, .. },
+                annotation: None,
+                ..
+            }) = statement
+            {
+                if counts[name] == 1 && !self.reassigned.contains_key(name) {
+                    let signature = Type::Function {
+                        parameters: (0..parameters.positional.len())
+                            .map(|_| self.fresh(Constraint::Unconstrained, false))
+                            .collect(),
+                        required: parameters.required(),
+                        variadic: parameters.rest.is_some(),
+                        result: Box::new(self.fresh(Constraint::Unconstrained, false)),
+                    };
+                    self.declare(name, signature.clone());
+                    let scope = self.scopes.last_mut().expect("Should be in a scope.");
+                    scope.pending.insert(name.clone(), signature);
+                }
+            }
+        }
+
+        let mut last = Type::Null;
+        for statement in statements {
+            last = self.statement(statement);
+        }
+        last
+    }
+
+    fn block(&mut self, block: &BlockStatement) -> Type {
+        self.scopes.push(Scope::default());
+        let value = self.statements(&block.statements);
+        self.scopes.pop();
+        value
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Type {
+        match statement {
+            Statement::Let(statement) => {
+                self.let_statement(statement);
+                Type::Any
+            }
+            Statement::Return(value) => {
+                let value = match value {
+                    Some(value) => self.expression(value),
+                    None => Type::Null,
+                };
+                if let Some(returns) = self.returns.last_mut() {
+                    returns.push(value);
+                }
+                Type::Any
+            }
+            Statement::Expression(expression) => self.expression(expression),
+            Statement::While { condition, body } => {
+                self.expression(condition);
+                self.block(body);
+                Type::Any
+            }
+            Statement::For {
+                pattern,
+                iterable,
+                body,
+            } => {
+                let iterable = self.expression(iterable);
+                let element = match self.head(iterable) {
+                    Type::Array(element) => self.resolve(&element).shallow(),
+                    Type::Range => Type::Int,
+                    _ => Type::Any,
+                };
+                self.scopes.push(Scope::default());
+                self.declare_pattern(pattern, element);
+                self.statements(&body.statements);
+                self.scopes.pop();
+                Type::Any
+            }
+            Statement::Break | Statement::Continue => Type::Any,
+            Statement::Import(import) => {
+                self.declare(&import.name, Type::Any);
+                Type::Any
+            }
+            Statement::Struct { name, .. } => {
+                self.declare(name, Type::Any);
+                Type::Any
+            }
+            Statement::Enum { variants, .. } => {
+                for variant in variants {
+                    self.declare(&variant.name, Type::Any);
+                }
+                Type::Any
+            }
+            Statement::Throw { value, .. } => {
+                self.expression(value);
+                Type::Any
+            }
+        }
+    }
+
+    fn let_statement(&mut self, statement: &LetStatement) {
+        let (value, function) = match (&statement.pattern, &statement.value) {
+            (
+                Pattern::Identifier(name),
+                Expression::Function {
+                    parameters,
+                    return_type,
+                    body,
+                },
+            ) => {
+                let value = self.function(
+                    Some((name, statement.span)),
+                    parameters,
+                    return_type.as_ref(),
+                    body,
+                );
+                (value, true)
+            }
+            (_, value) => (self.expression(value), false),
+        };
+
+        let declared = match &statement.annotation {
+            Some(annotation) => {
+                let declared = self.annotation(annotation);
+                if !self.unify(&declared, &value) {
+                    let [declared, value] = self.describe([&declared, &value]);
+                    self.error(
+                        format!(
+                            "`{}` is declared as {declared}, but is given {value}",
+                            statement.pattern
+                        ),
+                        Some(statement.span),
+                    );
+                }
+                declared
+            }
+            None => match &statement.pattern {
+                Pattern::Identifier(name) if !self.reassigned.contains_key(name) => {
+                    self.resolve(&value).shallow()
+                }
+                _ => Type::Any,
+            },
+        };
+        match &statement.pattern {
+            Pattern::Identifier(name) => {
+                self.bindings
+                    .push((name.clone(), statement.span, declared.clone()));
+                if function {
+                    let scheme = self.generalize(name, declared);
+                    self.declare_scheme(name, scheme);
+                } else {
+                    self.declare(name, declared);
+                }
+            }
+            pattern => self.declare_pattern(pattern, declared),
+        }
+    }
+
+    /// Checks a function literal, bound to `name` by the `let` at `span` if
+    /// it's known, and returns its type.
+    fn function(
+        &mut self,
+        name: Option<(&Symbol, Span)>,
+        parameters: &Parameters,
+        return_type: Option<&TypeAnnotation>,
+        body: &BlockStatement,
+    ) -> Type {
+        let pending = name.and_then(|(name, _)| {
+            self.scopes
+                .last_mut()
+                .expect("Should be in a scope.")
+                .pending
+                .remove(name)
+        });
+
+        self.scopes.push(Scope::default());
+        let mut types = Vec::new();
+        for parameter in &parameters.positional {
+            if let Some(default) = &parameter.default {
+                self.expression(default);
+            }
+            let declared = match &parameter.annotation {
+                Some(annotation) => self.annotation(annotation),
+                None if self.reassigned.contains_key(&parameter.name) => Type::Any,
+                None => self.fresh(Constraint::Unconstrained, true),
+            };
+            self.bindings
+                .push((parameter.name.clone(), parameter.span, declared.clone()));
+            self.declare(&parameter.name, declared.clone());
+            types.push(declared);
+        }
+        if let Some(rest) = &parameters.rest {
+            self.declare(rest, Type::Array(Box::new(Type::Any)));
+        }
+        let declared = return_type.map(|annotation| self.annotation(annotation));
+        let result = match &declared {
+            Some(declared) => declared.clone(),
+            None => self.fresh(Constraint::Unconstrained, false),
+        };
+        let signature = Type::Function {
+            parameters: types,
+            required: parameters.required(),
+            variadic: parameters.rest.is_some(),
+            result: Box::new(result.clone()),
+        };
+        if let Some(pending) = pending {
+            self.unify(&signature, &pending);
+        }
+
+        self.returns.push(Vec::new());
+        let value = self.statements(&body.statements);
+        let mut results = self.returns.pop().expect("Should have pushed the returns.");
+        self.scopes.pop();
+        // A body that ends with `return` has no value of its own.
+        if !matches!(body.statements.last(), Some(Statement::Return(_))) {
+            results.push(value);
+        }
+
+        match declared {
+            Some(declared) => {
+                for returned in results {
+                    if !self.unify(&declared, &returned) {
+                        let function = match name {
+                            Some((name, _)) => format!("`{name}`"),
+                            None => "the function".to_string(),
+                        };
+                        let [declared, returned] = self.describe([&declared, &returned]);
+                        self.error(
+                            format!("{function} should return {declared}, not {returned}"),
+                            name.map(|(_, span)| span),
+                        );
+                    }
+                }
+            }
+            None => {
+                let returned = self.join_all(results).unwrap_or(Type::Null);
+                // `any` unifies with the result without pinning it down,
+                // and a recursive call can expect something else of it.
+                let returned = match self.head(returned.clone()) {
+                    Type::Any => None,
+                    _ => Some(returned),
+                };
+                if !returned.is_some_and(|returned| self.unify(&result, &returned)) {
+                    if let Some(variable) = self.unbound(&result) {
+                        self.bind(variable, Type::Any);
+                    }
+                }
+            }
+        }
+        signature
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Type {
+        with_stack(|| match expression {
+            Expression::Identifier { name, .. } => self.lookup(name),
+            Expression::Int(_) => Type::Int,
+            Expression::Float(_) => Type::Float,
+            Expression::Str(_) => Type::Str,
+            Expression::Char(_) => Type::Char,
+            Expression::Bool(_) => Type::Bool,
+            Expression::Null => Type::Null,
+            Expression::Prefix {
+                operator,
+                right,
+                span,
+            } => {
+                let right = self.expression(right);
+                match (operator, self.head(right.clone())) {
+                    (PrefixOperator::Bang, _) => Type::Bool,
+                    (PrefixOperator::Minus, Type::Any | Type::Int | Type::Float) => right,
+                    (PrefixOperator::Minus, Type::Variable(_)) => {
+                        self.constrain(&right, Constraint::Number);
+                        right
+                    }
+                    (PrefixOperator::Minus, _) => {
+                        let [right] = self.describe([&right]);
+                        self.error(format!("cannot negate {right}"), Some(*span));
+                        Type::Any
+                    }
+                }
+            }
+            Expression::Infix {
+                left,
+                operator,
+                right,
+                span,
+            } => {
+                let left = self.expression(left);
+                let right = self.expression(right);
+                self.infix(*operator, left, right, *span)
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.expression(condition);
+                let consequence = self.block(consequence);
+                match alternative {
+                    Some(alternative) => {
+                        let alternative = self.block(alternative);
+                        self.join(consequence, alternative)
+                    }
+                    None => Type::Any,
+                }
+            }
+            Expression::Function {
+                parameters,
+                return_type,
+                body,
+            } => self.function(None, parameters, return_type.as_ref(), body),
+            Expression::Call {
+                function,
+                arguments,
+                span,
+            } => self.call(function, arguments, *span),
+            Expression::MethodCall {
+                receiver,
+                arguments,
+                ..
+            } => {
+                self.expression(receiver);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+                Type::Any
+            }
+            Expression::Array(elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|element| self.expression(element))
+                    .collect();
+                let element = self.join_all(elements).unwrap_or(Type::Any);
+                Type::Array(Box::new(element))
+            }
+            Expression::Tuple(elements) => {
+                for element in elements {
+                    self.expression(element);
+                }
+                Type::Any
+            }
+            Expression::Hash(pairs) => {
+                let (mut keys, mut values) = (Vec::new(), Vec::new());
+                for (key, value) in pairs {
+                    keys.push(self.expression(key));
+                    values.push(self.expression(value));
+                }
+                let key = self.join_all(keys).unwrap_or(Type::Any);
+                let value = self.join_all(values).unwrap_or(Type::Any);
+                Type::Hash(Box::new(key), Box::new(value))
+            }
+            Expression::Try {
+                body,
+                pattern,
+                handler,
+            } => {
+                let body = self.block(body);
+                self.scopes.push(Scope::default());
+                self.declare_pattern(pattern, Type::Any);
+                let handler = self.statements(&handler.statements);
+                self.scopes.pop();
+                self.join(body, handler)
+            }
+            // An arm's pattern can be a variant of any enum, so what it
+            // binds isn't known.
+            Expression::Match { subject, arms, .. } => {
+                self.expression(subject);
+                let mut types = Vec::new();
+                for arm in arms {
+                    self.scopes.push(Scope::default());
+                    self.declare_pattern(&arm.pattern, Type::Any);
+                    types.push(self.statements(&arm.body.statements));
+                    self.scopes.pop();
+                }
+                self.join_all(types).unwrap_or(Type::Any)
+            }
+            // What's spread is elements, and a named argument's value is
+            // checked against no particular parameter.
+            Expression::Spread(value) | Expression::Named { value, .. } => {
+                self.expression(value);
+                Type::Any
+            }
+            Expression::Conditional {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.expression(condition);
+                let consequence = self.expression(consequence);
+                let alternative = self.expression(alternative);
+                self.join(consequence, alternative)
+            }
+            Expression::Index { left, index, .. } => {
+                let left = self.expression(left);
+                self.expression(index);
+                match self.head(left) {
+                    Type::Array(element) => *element,
+                    Type::Hash(_, value) => *value,
+                    _ => Type::Any,
+                }
+            }
+            Expression::Assign {
+                target,
+                operator,
+                value,
+                span,
+            } => {
+                let value = self.expression(value);
+                let Expression::Identifier { name, .. } = &**target else {
+                    self.expression(target);
+                    return value;
+                };
+                let declared = self.lookup(name);
+                let value = match operator {
+                    Some(operator) => self.infix(*operator, declared.clone(), value, *span),
+                    None => value,
+                };
+                if !self.unify(&declared, &value) {
+                    let [declared, value] = self.describe([&declared, &value]);
+                    self.error(
+                        format!("`{name}` is declared as {declared}, but is given {value}"),
+                        Some(*span),
+                    );
+                }
+                value
+            }
+            Expression::Range { start, end, .. } => {
+                for bound in [start, end].into_iter().flatten() {
+                    self.expression(bound);
+                }
+                Type::Range
+            }
+        })
+    }
+
+    /// The type of what `operator` makes of values of types `left` and
+    /// `right`, reporting it if it can't make anything of them.
+    fn infix(&mut self, operator: InfixOperator, left: Type, right: Type, span: Span) -> Type {
+        let arithmetic = matches!(
+            operator,
+            InfixOperator::Plus
+                | InfixOperator::Minus
+                | InfixOperator::Multiply
+                | InfixOperator::Divide
+                | InfixOperator::Power
+        );
+        // The operand that's an unbound type variable, when the other
+        // isn't.
+        let variable = match (self.unbound(&left), self.unbound(&right)) {
+            (Some(_), None) => left.clone(),
+            _ => right.clone(),
+        };
+        let result = match operator {
+            InfixOperator::Eq | InfixOperator::NotEq | InfixOperator::And | InfixOperator::Or => {
+                return Type::Bool
+            }
+            InfixOperator::Coalesce if self.head(left.clone()) == Type::Null => return right,
+            InfixOperator::Coalesce => return self.join(left, right),
+            _ if operator.is_comparison() => {
+                match (self.head(left.clone()), self.head(right.clone())) {
+                    (Type::Any, _) | (_, Type::Any) => Some(Type::Bool),
+                    (Type::Variable(_), Type::Variable(_)) => {
+                        self.constrain(&left, Constraint::Comparable);
+                        self.constrain(&right, Constraint::Comparable);
+                        Some(Type::Bool)
+                    }
+                    (Type::Variable(_), other) | (other, Type::Variable(_)) => {
+                        let fits = if other.is_number() {
+                            self.constrain(&variable, Constraint::Number);
+                            true
+                        } else {
+                            matches!(other, Type::Str | Type::Char) && self.unify(&variable, &other)
+                        };
+                        fits.then_some(Type::Bool)
+                    }
+                    (left, right) if left.is_number() && right.is_number() => Some(Type::Bool),
+                    (Type::Str, Type::Str) | (Type::Char, Type::Char) => Some(Type::Bool),
+                    _ => None,
+                }
+            }
+            _ => match (self.head(left.clone()), self.head(right.clone())) {
+                (Type::Any, _) | (_, Type::Any) => Some(Type::Any),
+                // Only ints have bits.
+                (Type::Variable(_), _) | (_, Type::Variable(_)) if !arithmetic => {
+                    (self.unify(&Type::Int, &left) && self.unify(&Type::Int, &right))
+                        .then_some(Type::Int)
+                }
+                (Type::Variable(_), Type::Variable(_)) => {
+                    let constraint = match operator {
+                        InfixOperator::Plus => Constraint::Addable,
+                        _ => Constraint::Number,
+                    };
+                    self.constrain(&left, constraint);
+                    self.constrain(&right, constraint);
+                    Some(match operator {
+                        InfixOperator::Power => Type::Any,
+                        _ => self.fresh(constraint, false),
+                    })
+                }
+                (Type::Variable(_), other) | (other, Type::Variable(_)) => match other {
+                    Type::Str if operator == InfixOperator::Plus => {
+                        self.unify(&variable, &Type::Str).then_some(Type::Str)
+                    }
+                    other if other.is_number() => {
+                        self.constrain(&variable, Constraint::Number);
+                        Some(match (operator, other) {
+                            (InfixOperator::Power, _) => Type::Any,
+                            (_, Type::Float) => Type::Float,
+                            _ => self.fresh(Constraint::Number, false),
+                        })
+                    }
+                    _ => None,
+                },
+                (Type::Str, Type::Str) if operator == InfixOperator::Plus => Some(Type::Str),
+                (Type::Int, Type::Int) => match operator {
+                    // A negative power is a fraction.
+                    InfixOperator::Power => Some(Type::Any),
+                    _ => Some(Type::Int),
+                },
+                (left, right) if left.is_number() && right.is_number() && arithmetic => {
+                    Some(Type::Float)
+                }
+                _ => None,
+            },
+        };
+        result.unwrap_or_else(|| {
+            let [left, right] = self.describe([&left, &right]);
+            let message = match operator {
+                InfixOperator::Plus => format!("cannot add {left} and {right}"),
+                InfixOperator::Minus => format!("cannot subtract {right} from {left}"),
+                InfixOperator::Multiply => format!("cannot multiply {left} and {right}"),
+                InfixOperator::Divide => format!("cannot divide {left} by {right}"),
+                _ if operator.is_comparison() => format!("cannot compare {left} and {right}"),
+                _ => format!("cannot apply `{operator}` to {left} and {right}"),
+            };
+            self.error(message, Some(span));
+            Type::Any
+        })
+    }
+
+    fn call(&mut self, function: &Expression, arguments: &[Expression], span: Span) -> Type {
+        let callee = self.expression(function);
+        let types: Vec<Type> = arguments
+            .iter()
+            .map(|argument| self.expression(argument))
+            .collect();
+        // Spread and named arguments don't line up with the parameters
+        // until the call is made.
+        let positional = !arguments
+            .iter()
+            .any(|argument| matches!(argument, Expression::Spread(_) | Expression::Named { .. }));
+        let name = match function {
+            Expression::Identifier { name, .. } => Some(name),
+            _ => None,
+        };
+        let described = match name {
+            Some(name) => format!("`{name}`"),
+            None => "the function".to_string(),
+        };
+
+        match self.head(callee) {
+            Type::Function {
+                parameters,
+                required,
+                variadic,
+                result,
+            } => {
+                if positional {
+                    let arity = match (variadic, parameters.len()) {
+                        (true, _) => Arity::AtLeast(required),
+                        (false, maximum) if maximum == required => Arity::Exact(required),
+                        (false, maximum) => Arity::Between(required, maximum),
+                    };
+                    if !arity.accepts(types.len()) {
+                        self.error(
+                            format!(
+                                "wrong number of arguments to {described}: expected {arity}, got {}",
+                                types.len()
+                            ),
+                            Some(span),
+                        );
+                    }
+                    for (index, (parameter, argument)) in parameters.iter().zip(&types).enumerate()
+                    {
+                        if !self.unify(parameter, argument) {
+                            let [parameter, argument] = self.describe([parameter, argument]);
+                            self.error(
+                                format!(
+                                    "argument {} to {described} should be {parameter}, not {argument}",
+                                    index + 1
+                                ),
+                                Some(span),
+                            );
+                        }
+                    }
+                }
+                *result
+            }
+            // A function passed in could take its arguments in more ways
+            // than this call shows.
+            Type::Variable(_) => Type::Any,
+            Type::Any => {
+                // A builtin, unless something in scope has its name.
+                let builtin = name
+                    .filter(|name| {
+                        !self
+                            .scopes
+                            .iter()
+                            .any(|scope| scope.bindings.contains_key(*name))
+                    })
+                    .and_then(|name| self.builtins.lookup(name));
+                if let Some(Value::Builtin(builtin)) = builtin {
+                    if positional && !builtin.arity.accepts(types.len()) {
+                        self.error(
+                            format!(
+                                "wrong number of arguments to {described}: expected {}, got {}",
+                                builtin.arity,
+                                types.len()
+                            ),
+                            Some(span),
+                        );
+                    }
+                }
+                Type::Any
+            }
+            callee => {
+                let [callee] = self.describe([&callee]);
+                self.error(format!("cannot call {callee}"), Some(span));
+                Type::Any
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, infer};
+    use crate::builtins::Builtins;
+    use crate::error::TofuError;
+    use crate::interpreter::Interpreter;
+
+    fn errors(source: &str) -> Vec<String> {
+        let program = crate::parse(source).unwrap();
+        check(&program, &Builtins::standard())
+            .iter()
+            .map(|error| error.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(vec!["1:3: cannot add int and str"], errors("1 + \"a\""));
+        assert_eq!(
+            vec![
+                "2:3: cannot compare str and bool",
+                "3:1: cannot negate str",
+                "4:5: cannot apply `&` to float and int",
+            ],
+            errors("let s = \"a\"\ns < (1 == 2)\n-s\n1.5 & 2")
+        );
+
+        let source = "\
+let add = fn(x: int, y: int = 1) -> int { x + y }
+add(1, 2, 3)
+add(\"one\")
+add(1) + \"two\"
+len([1], [2])
+let count: int = \"many\"
+let name = fn() -> str { return 1 }";
+        assert_eq!(
+            vec![
+                "2:4: wrong number of arguments to `add`: expected 1 or 2, got 3",
+                "3:4: argument 1 to `add` should be int, not str",
+                "4:8: cannot add int and str",
+                "5:4: wrong number of arguments to `len`: expected 1, got 2",
+                "6:5: `count` is declared as int, but is given str",
+                "7:5: `name` should return str, not int",
+                "8:15: unknown type `integer`",
+                "9:8: `array` takes 1 type argument, but is given 2",
+            ],
+            errors(&format!(
+                "{source}\nlet f = fn(x: integer) {{ x }}\nlet a: array<int, str> = []"
+            ))
+        );
+
+        // What could be anything at runtime isn't a mismatch.
+        let source = "\
+let x = 1
+x = \"a\"
+x + \"b\"
+let items = [1]
+items[0] = \"a\"
+items[0] + \"b\"
+let f = fn(a) { a }
+f(\"c\", 2)
+let total: float = 1 + 2.5 + 3";
+        assert_eq!(
+            vec!["8:2: wrong number of arguments to `f`: expected 1, got 2"],
+            errors(source)
+        );
+
+        let interpreter = Interpreter::new();
+        assert_eq!(Ok(()), interpreter.check_types("let x: int = 1\nx * 2"));
+        match interpreter.check_types("true - 1") {
+            Err(TofuError::Type(errors)) => {
+                assert_eq!("1:6: cannot subtract int from bool", errors[0].to_string())
+            }
+            other => panic!("expected a type error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_infer() {
+        let source = "\
+let double = fn(x) { x * 2 }
+let greet = fn(name) { \"hello \" + name }
+let id = fn(x) { x }
+let fact = fn(n) { if (n < 2) { 1 } else { n * fact(n - 1) } }
+let apply = fn(f, x) { f(x) }
+let pick = fn(a, b) { if (a > b) { a } else { b } }
+let first = fn(items: array<int>) { items[0] }
+let count = id(3)
+let word = id(\"three\")
+double(1.5)
+id";
+        let program = crate::parse(source).unwrap();
+        let inference = infer(&program, &Builtins::standard());
+        assert!(inference.errors.is_empty(), "{:?}", inference.errors);
+        let types: Vec<String> = inference
+            .bindings
+            .iter()
+            .map(|(name, _, binding)| format!("{name}: {binding}"))
+            .collect();
+        assert_eq!(
+            vec![
+                "x: 'a",
+                "double: fn('a) -> 'b",
+                "name: str",
+                "greet: fn(str) -> str",
+                "x: 'a",
+                "id: fn('a) -> 'a",
+                "n: 'a",
+                "fact: fn('a) -> int",
+                "f: 'a",
+                "x: 'a",
+                "apply: fn('a, 'b) -> any",
+                "a: 'a",
+                "b: 'a",
+                "pick: fn('a, 'b) -> any",
+                "items: array<int>",
+                "first: fn(array<int>) -> int",
+                "count: int",
+                "word: str",
+            ],
+            types
+        );
+        assert_eq!("fn('a) -> 'a", inference.value.to_string());
+
+        assert_eq!(
+            vec![
+                "4:7: argument 1 to `double` should be a number, not str",
+                "5:11: cannot add str and int",
+                "6:6: argument 1 to `greet` should be str, not int",
+                "7:26: cannot compare a number and str",
+            ],
+            errors(
+                "\
+let double = fn(x) { x * 2 }
+let greet = fn(name) { \"hello \" + name }
+let id = fn(x) { x }
+double(\"two\")
+id(\"one\") + 1
+greet(2)
+let less = fn(n) { n * 2 < \"z\" }"
+            )
+        );
+    }
+}
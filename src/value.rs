@@ -0,0 +1,612 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::rc::Rc;
+
+use indexmap::{IndexMap, IndexSet};
+#[cfg(feature = "bigint")]
+use num_bigint::BigInt;
+
+use crate::ast::{BlockStatement, Parameters};
+use crate::builtins::{Arity, Builtin};
+use crate::environment::Environment;
+use crate::vm::Closure;
+
+/// A value produced by evaluating Tofu code.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    /// An integer too big for an `Int`, with the `bigint` feature. See
+    /// [`crate::bigint`].
+    #[cfg(feature = "bigint")]
+    BigInt(Rc<BigInt>),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Char(char),
+    Null,
+    Function(Rc<Function>),
+    /// A function compiled to bytecode, run by [`crate::vm`].
+    Closure(Rc<Closure>),
+    Builtin(Rc<Builtin>),
+    /// Arrays are shared: copies of an array value all see the same
+    /// elements.
+    Array(Rc<RefCell<Vec<Value>>>),
+    /// Hashes are shared like arrays and remember the order their keys were
+    /// first inserted in.
+    Hash(Rc<RefCell<IndexMap<HashKey, Value>>>),
+    /// Sets are shared like arrays, hold only values that can be hash
+    /// keys, and remember the order their elements were first added in.
+    /// Two sets are equal if they have the same elements, in any order.
+    Set(Rc<RefCell<IndexSet<HashKey>>>),
+    /// A string being built up by `buf_push`, which appends in place, so
+    /// building one piece by piece takes time in proportion to its length
+    /// rather than its square. Shared like arrays.
+    Buffer(Rc<RefCell<String>>),
+    /// Tuples can't change once made, so copies needn't share anything.
+    /// Two tuples are equal if their elements are, in order.
+    Tuple(Rc<[Value]>),
+    /// A `struct` declaration, which makes instances of the struct when
+    /// called.
+    StructType(Rc<StructType>),
+    /// Structs are shared like arrays.
+    Struct(Rc<Struct>),
+    /// A variant of an `enum` declaration with fields, which makes values
+    /// of the variant when called.
+    VariantType(Rc<VariantType>),
+    /// A value of one of an enum's variants. Like tuples, they can't
+    /// change once made.
+    Variant(Rc<Variant>),
+    Range(Range),
+}
+
+/// A range of integers, `start..end` or `start..=end`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+    pub inclusive: bool,
+    /// Whether the start was left out, as in `..5`, so it's shown that way.
+    pub open_start: bool,
+    /// Whether the end was left out, as in `5..`.
+    pub open_end: bool,
+}
+
+impl Range {
+    /// A range with the bounds that were written. A slice with a bound left
+    /// out starts at the first element, or ends at the last, counting back
+    /// from the end.
+    pub fn new(start: Option<i64>, end: Option<i64>, inclusive: bool) -> Range {
+        Range {
+            start: start.unwrap_or(0),
+            end: end.unwrap_or(-1),
+            inclusive: inclusive || end.is_none(),
+            open_start: start.is_none(),
+            open_end: end.is_none(),
+        }
+    }
+
+    /// The range's end as an exclusive bound, which can only overflow for an
+    /// inclusive range ending at `i64::MAX`.
+    pub fn end_exclusive(&self) -> Option<i64> {
+        if self.inclusive {
+            self.end.checked_add(1)
+        } else {
+            Some(self.end)
+        }
+    }
+}
+
+/// The subset of values that can be used as hash keys.
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub enum HashKey {
+    Int(i64),
+    #[cfg(feature = "bigint")]
+    BigInt(BigInt),
+    Bool(bool),
+    Str(String),
+    Tuple(Vec<HashKey>),
+}
+
+impl Display for Range {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.open_start {
+            write!(f, "{}", self.start)?;
+        }
+        if self.open_end {
+            return write!(f, "..");
+        }
+        let dots = if self.inclusive { "..=" } else { ".." };
+        write!(f, "{dots}{}", self.end)
+    }
+}
+
+/// Steps through the values a `for` loop visits: an array's elements, a
+/// hash's entries as `[key, value]` arrays, a range's integers, or a
+/// string's characters. The underlying collection is re-read at each step,
+/// so changes made by the loop body are seen.
+pub enum ValueIter {
+    Array {
+        elements: Rc<RefCell<Vec<Value>>>,
+        index: usize,
+    },
+    Hash {
+        pairs: Rc<RefCell<IndexMap<HashKey, Value>>>,
+        index: usize,
+    },
+    Set {
+        elements: Rc<RefCell<IndexSet<HashKey>>>,
+        index: usize,
+    },
+    Range {
+        next: Option<i64>,
+        range: Range,
+    },
+    /// Strings can't change, so there's nothing to re-read.
+    Str(std::vec::IntoIter<char>),
+}
+
+impl Iterator for ValueIter {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        match self {
+            ValueIter::Array { elements, index } => {
+                let element = elements.borrow().get(*index).cloned()?;
+                *index += 1;
+                Some(element)
+            }
+            ValueIter::Hash { pairs, index } => {
+                let (key, value) = pairs
+                    .borrow()
+                    .get_index(*index)
+                    .map(|(key, value)| (key.clone(), value.clone()))?;
+                *index += 1;
+                Some(Value::array(vec![key.into(), value]))
+            }
+            ValueIter::Set { elements, index } => {
+                let element = elements.borrow().get_index(*index).cloned()?;
+                *index += 1;
+                Some(element.into())
+            }
+            ValueIter::Range { next, range } => {
+                let current = next.filter(|current| {
+                    *current < range.end || (range.inclusive && *current == range.end)
+                })?;
+                *next = current.checked_add(1);
+                Some(Value::Int(current))
+            }
+            ValueIter::Str(chars) => chars.next().map(Value::Char),
+        }
+    }
+
+    /// Bounded above for ranges. Only an upper bound, so collecting a huge
+    /// range doesn't try to reserve room for all of it up front. An array
+    /// or hash can change while it's iterated over, so there's no telling
+    /// for them.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            ValueIter::Range {
+                next: Some(next),
+                range,
+            } => {
+                let end = i128::from(range.end) + i128::from(range.inclusive);
+                let remaining =
+                    usize::try_from((end - i128::from(*next)).max(0)).unwrap_or(usize::MAX);
+                (0, Some(remaining))
+            }
+            ValueIter::Range { next: None, .. } => (0, Some(0)),
+            ValueIter::Str(chars) => chars.size_hint(),
+            ValueIter::Array { .. } | ValueIter::Hash { .. } | ValueIter::Set { .. } => (0, None),
+        }
+    }
+}
+
+impl From<HashKey> for Value {
+    fn from(key: HashKey) -> Value {
+        match key {
+            HashKey::Int(value) => Value::Int(value),
+            #[cfg(feature = "bigint")]
+            HashKey::BigInt(value) => Value::BigInt(Rc::new(value)),
+            HashKey::Bool(value) => Value::Bool(value),
+            HashKey::Str(value) => Value::Str(value),
+            HashKey::Tuple(elements) => {
+                Value::Tuple(elements.into_iter().map(Value::from).collect())
+            }
+        }
+    }
+}
+
+/// What a `struct` declaration declares: the struct's name and its fields,
+/// in order. Two are the same type if they have the same name and fields.
+#[derive(PartialEq, Debug)]
+pub struct StructType {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+impl StructType {
+    /// Where `field` is among the struct's fields, if it has one so called.
+    pub fn position(&self, field: &str) -> Option<usize> {
+        self.fields.iter().position(|name| name == field)
+    }
+}
+
+/// An instance of a struct, with a value for each of its type's fields.
+#[derive(PartialEq, Debug)]
+pub struct Struct {
+    pub ty: Rc<StructType>,
+    pub fields: RefCell<Vec<Value>>,
+}
+
+/// A variant an `enum` declaration declares: the enum's name, the
+/// variant's, and its fields, in order. Two are the same variant if they
+/// have the same names and fields.
+#[derive(PartialEq, Debug)]
+pub struct VariantType {
+    pub enum_name: String,
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+/// A value of an enum's variant, with a value for each of its fields.
+#[derive(PartialEq, Debug)]
+pub struct Variant {
+    pub ty: Rc<VariantType>,
+    pub values: Vec<Value>,
+}
+
+/// A user-defined function, closed over the environment it was defined in.
+/// Two function values are equal only if they are the same function, not
+/// merely functions with the same source.
+pub struct Function {
+    /// The name the function was bound to where it was defined, if any.
+    pub name: Option<String>,
+    pub parameters: Parameters,
+    pub body: Rc<BlockStatement>,
+    pub env: Rc<RefCell<Environment>>,
+}
+
+impl std::fmt::Debug for Function {
+    // The environment is left out, since it can hold this very function.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Function")
+            .field("name", &self.name)
+            .field("parameters", &self.parameters)
+            .field("body", &self.body)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for Function {
+    fn eq(&self, other: &Function) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Value {
+    /// The name of the value's type, as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "Int",
+            #[cfg(feature = "bigint")]
+            Value::BigInt(_) => "Int",
+            Value::Float(_) => "Float",
+            Value::Bool(_) => "Bool",
+            Value::Str(_) => "Str",
+            Value::Char(_) => "Char",
+            Value::Null => "Null",
+            Value::Function(_) | Value::Closure(_) => "Function",
+            Value::Builtin(_) => "Builtin",
+            Value::StructType(_) => "Function",
+            Value::Struct(_) => "Struct",
+            Value::VariantType(_) => "Function",
+            Value::Variant(_) => "Enum",
+            Value::Array(_) => "Array",
+            Value::Hash(_) => "Hash",
+            Value::Set(_) => "Set",
+            Value::Buffer(_) => "Buffer",
+            Value::Tuple(_) => "Tuple",
+            Value::Range(_) => "Range",
+        }
+    }
+
+    /// How many arguments the value takes, if it can be called.
+    pub fn arity(&self) -> Option<Arity> {
+        match self {
+            Value::Function(function) => {
+                let parameters = &function.parameters;
+                let (required, allowed) = (parameters.required(), parameters.positional.len());
+                Some(match parameters.rest {
+                    Some(_) => Arity::AtLeast(required),
+                    None if required == allowed => Arity::Exact(required),
+                    None => Arity::Between(required, allowed),
+                })
+            }
+            Value::Closure(closure) => Some(Arity::Exact(closure.function.parameters.len())),
+            Value::Builtin(builtin) => Some(builtin.arity),
+            Value::StructType(ty) => Some(Arity::Exact(ty.fields.len())),
+            Value::VariantType(ty) => Some(Arity::Exact(ty.fields.len())),
+            _ => None,
+        }
+    }
+
+    /// Only `false` and `null` are falsy; every other value, including `0`
+    /// and `""`, is truthy.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Bool(false) | Value::Null)
+    }
+
+    pub fn array(elements: Vec<Value>) -> Value {
+        Value::Array(Rc::new(RefCell::new(elements)))
+    }
+
+    pub fn hash(pairs: IndexMap<HashKey, Value>) -> Value {
+        Value::Hash(Rc::new(RefCell::new(pairs)))
+    }
+
+    pub fn set(elements: IndexSet<HashKey>) -> Value {
+        Value::Set(Rc::new(RefCell::new(elements)))
+    }
+
+    pub fn buffer(text: String) -> Value {
+        Value::Buffer(Rc::new(RefCell::new(text)))
+    }
+
+    pub fn instance(ty: Rc<StructType>, fields: Vec<Value>) -> Value {
+        Value::Struct(Rc::new(Struct {
+            ty,
+            fields: RefCell::new(fields),
+        }))
+    }
+
+    pub fn variant(ty: Rc<VariantType>, values: Vec<Value>) -> Value {
+        Value::Variant(Rc::new(Variant { ty, values }))
+    }
+
+    /// An iterator over the value, or `None` if it isn't iterable.
+    pub fn iter(&self) -> Option<ValueIter> {
+        match self {
+            Value::Array(elements) => Some(ValueIter::Array {
+                elements: Rc::clone(elements),
+                index: 0,
+            }),
+            Value::Hash(pairs) => Some(ValueIter::Hash {
+                pairs: Rc::clone(pairs),
+                index: 0,
+            }),
+            Value::Set(elements) => Some(ValueIter::Set {
+                elements: Rc::clone(elements),
+                index: 0,
+            }),
+            Value::Range(range) => Some(ValueIter::Range {
+                next: Some(range.start),
+                range: *range,
+            }),
+            Value::Str(value) => Some(ValueIter::Str(
+                value.chars().collect::<Vec<_>>().into_iter(),
+            )),
+            _ => None,
+        }
+    }
+
+    /// The key this value stands for in a hash, or `None` if it can't be
+    /// used as one.
+    pub fn hash_key(&self) -> Option<HashKey> {
+        match self {
+            Value::Int(value) => Some(HashKey::Int(*value)),
+            #[cfg(feature = "bigint")]
+            Value::BigInt(value) => Some(HashKey::BigInt((**value).clone())),
+            Value::Bool(value) => Some(HashKey::Bool(*value)),
+            Value::Str(value) => Some(HashKey::Str(value.clone())),
+            // Only if all of its elements can be.
+            Value::Tuple(elements) => elements
+                .iter()
+                .map(Value::hash_key)
+                .collect::<Option<_>>()
+                .map(HashKey::Tuple),
+            _ => None,
+        }
+    }
+
+    /// Whether the values are equal, like `==`, given that each pair of
+    /// arrays, hashes or structs in `comparing` is, so comparing ones that
+    /// contain themselves ends.
+    fn equals(&self, other: &Value, comparing: &mut HashSet<(usize, usize)>) -> bool {
+        let all = |a: &[Value], b: &[Value], comparing: &mut HashSet<_>| {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.equals(b, comparing))
+        };
+
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            #[cfg(feature = "bigint")]
+            (Value::BigInt(a), Value::BigInt(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::Null, Value::Null) => true,
+            (Value::Function(a), Value::Function(b)) => a == b,
+            (Value::Closure(a), Value::Closure(b)) => a == b,
+            (Value::Builtin(a), Value::Builtin(b)) => a == b,
+            (Value::Array(a), Value::Array(b)) => {
+                Rc::ptr_eq(a, b)
+                    || !comparing.insert((address(a), address(b)))
+                    || all(&a.borrow(), &b.borrow(), comparing)
+            }
+            // In any order.
+            (Value::Hash(a), Value::Hash(b)) => {
+                Rc::ptr_eq(a, b)
+                    || !comparing.insert((address(a), address(b)))
+                    || a.borrow().len() == b.borrow().len()
+                        && a.borrow().iter().all(|(key, a)| {
+                            b.borrow().get(key).is_some_and(|b| a.equals(b, comparing))
+                        })
+            }
+            (Value::Set(a), Value::Set(b)) => a == b,
+            (Value::Buffer(a), Value::Buffer(b)) => a == b,
+            (Value::Tuple(a), Value::Tuple(b)) => all(a, b, comparing),
+            (Value::StructType(a), Value::StructType(b)) => a == b,
+            (Value::Struct(a), Value::Struct(b)) => {
+                Rc::ptr_eq(a, b)
+                    || !comparing.insert((address(a), address(b)))
+                    || a.ty == b.ty && all(&a.fields.borrow(), &b.fields.borrow(), comparing)
+            }
+            (Value::VariantType(a), Value::VariantType(b)) => a == b,
+            (Value::Variant(a), Value::Variant(b)) => {
+                a.ty == b.ty && all(&a.values, &b.values, comparing)
+            }
+            (Value::Range(a), Value::Range(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Writes the value as it appears inside a collection, where strings
+    /// and chars are quoted so `["a, b"]` isn't mistaken for two elements.
+    fn fmt_nested(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Str(value) => write!(f, "{value:?}"),
+            Value::Char(value) => write!(f, "{value:?}"),
+            value => write!(f, "{value}"),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.equals(other, &mut HashSet::new())
+    }
+}
+
+/// Identifies what an `Rc` points to while it's alive.
+pub(crate) fn address<T: ?Sized>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as *const () as usize
+}
+
+thread_local! {
+    /// The arrays, hashes and structs being displayed, by address.
+    static DISPLAYING: RefCell<HashSet<usize>> = RefCell::new(HashSet::new());
+}
+
+/// Displays a collection with `write`, or as `placeholder` if it's already
+/// being displayed, because it contains itself.
+fn display_once(
+    f: &mut std::fmt::Formatter<'_>,
+    address: usize,
+    placeholder: &str,
+    write: impl FnOnce(&mut std::fmt::Formatter<'_>) -> std::fmt::Result,
+) -> std::fmt::Result {
+    if !DISPLAYING.with(|displaying| displaying.borrow_mut().insert(address)) {
+        return write!(f, "{placeholder}");
+    }
+    let result = write(f);
+    DISPLAYING.with(|displaying| displaying.borrow_mut().remove(&address));
+    result
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{value}"),
+            #[cfg(feature = "bigint")]
+            Value::BigInt(value) => write!(f, "{value}"),
+            Value::Float(value) => write!(f, "{value:?}"),
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Str(value) => write!(f, "{value}"),
+            Value::Char(value) => write!(f, "{value}"),
+            Value::Null => write!(f, "null"),
+            Value::Function(function) => {
+                write!(f, "fn({}) {}", function.parameters, function.body)
+            }
+            Value::Closure(closure) => write!(f, "{closure}"),
+            Value::Builtin(builtin) => write!(f, "builtin {}", builtin.name),
+            Value::Array(elements) => display_once(f, address(elements), "[...]", |f| {
+                write!(f, "[")?;
+                for (index, element) in elements.borrow().iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.fmt_nested(f)?;
+                }
+                write!(f, "]")
+            }),
+            Value::Hash(pairs) => display_once(f, address(pairs), "{...}", |f| {
+                write!(f, "{{")?;
+                for (index, (key, value)) in pairs.borrow().iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    Value::from(key.clone()).fmt_nested(f)?;
+                    write!(f, ": ")?;
+                    value.fmt_nested(f)?;
+                }
+                write!(f, "}}")
+            }),
+            // As the call that would make it.
+            Value::Set(elements) => {
+                write!(f, "set([")?;
+                for (index, element) in elements.borrow().iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    Value::from(element.clone()).fmt_nested(f)?;
+                }
+                write!(f, "])")
+            }
+            Value::Buffer(text) => write!(f, "buf_new({:?})", text.borrow()),
+            // With a trailing comma for one element, so `(1,)` isn't read
+            // as a grouped `1`.
+            Value::Tuple(elements) => {
+                write!(f, "(")?;
+                for (index, element) in elements.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.fmt_nested(f)?;
+                }
+                if elements.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            // As the declaration that made it.
+            Value::StructType(ty) if ty.fields.is_empty() => write!(f, "struct {} {{}}", ty.name),
+            Value::StructType(ty) => write!(f, "struct {} {{ {} }}", ty.name, ty.fields.join(", ")),
+            Value::Struct(instance) => {
+                let placeholder = format!("{} {{...}}", instance.ty.name);
+                display_once(f, address(instance), &placeholder, |f| {
+                    write!(f, "{} {{", instance.ty.name)?;
+                    let fields = instance.fields.borrow();
+                    for (index, (name, value)) in
+                        instance.ty.fields.iter().zip(fields.iter()).enumerate()
+                    {
+                        let separator = if index > 0 { ", " } else { " " };
+                        write!(f, "{separator}{name}: ")?;
+                        value.fmt_nested(f)?;
+                    }
+                    if !fields.is_empty() {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "}}")
+                })
+            }
+            Value::VariantType(ty) => write!(f, "variant {}({})", ty.name, ty.fields.join(", ")),
+            // As the call that would make it, or the name for a variant
+            // without fields.
+            Value::Variant(variant) if variant.values.is_empty() => {
+                write!(f, "{}", variant.ty.name)
+            }
+            Value::Variant(variant) => {
+                write!(f, "{}(", variant.ty.name)?;
+                for (index, value) in variant.values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    value.fmt_nested(f)?;
+                }
+                write!(f, ")")
+            }
+            Value::Range(range) => write!(f, "{range}"),
+        }
+    }
+}
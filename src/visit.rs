@@ -0,0 +1,487 @@
+//! Traversal of the AST. A pass implements [`Visit`] (or [`VisitMut`] to
+//! rewrite the tree in place), overrides the methods for the nodes it cares
+//! about, and calls the matching `walk_*` function from an override to keep
+//! descending into that node's children. The default methods just walk, so
+//! `visitor.visit_program(&program)` reaches every node.
+
+use std::rc::Rc;
+
+use crate::ast::{with_stack, BlockStatement, Expression, Pattern, Program, Statement};
+
+pub trait Visit {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_block(&mut self, block: &BlockStatement) {
+        walk_block(self, block);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression);
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        walk_pattern(self, pattern);
+    }
+}
+
+pub fn walk_program<V: Visit + ?Sized>(visitor: &mut V, program: &Program) {
+    for statement in &program.statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_statement<V: Visit + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match statement {
+        Statement::Let(statement) => {
+            visitor.visit_pattern(&statement.pattern);
+            visitor.visit_expression(&statement.value);
+        }
+        Statement::Return(Some(value))
+        | Statement::Expression(value)
+        | Statement::Throw { value, .. } => {
+            visitor.visit_expression(value);
+        }
+        Statement::Return(None)
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Import(_)
+        | Statement::Struct { .. }
+        | Statement::Enum { .. } => {}
+        Statement::While { condition, body } => {
+            visitor.visit_expression(condition);
+            visitor.visit_block(body);
+        }
+        Statement::For {
+            pattern,
+            iterable,
+            body,
+        } => {
+            visitor.visit_pattern(pattern);
+            visitor.visit_expression(iterable);
+            visitor.visit_block(body);
+        }
+    }
+}
+
+pub fn walk_block<V: Visit + ?Sized>(visitor: &mut V, block: &BlockStatement) {
+    for statement in &block.statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_expression<V: Visit + ?Sized>(visitor: &mut V, expression: &Expression) {
+    with_stack(|| match expression {
+        Expression::Identifier { .. }
+        | Expression::Int(_)
+        | Expression::Float(_)
+        | Expression::Str(_)
+        | Expression::Char(_)
+        | Expression::Bool(_)
+        | Expression::Null => {}
+        Expression::Prefix { right, .. }
+        | Expression::Spread(right)
+        | Expression::Named { value: right, .. } => visitor.visit_expression(right),
+        Expression::Infix { left, right, .. } => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(right);
+        }
+        Expression::If {
+            condition,
+            consequence,
+            alternative,
+        } => {
+            visitor.visit_expression(condition);
+            visitor.visit_block(consequence);
+            if let Some(alternative) = alternative {
+                visitor.visit_block(alternative);
+            }
+        }
+        Expression::Try {
+            body,
+            pattern,
+            handler,
+        } => {
+            visitor.visit_block(body);
+            visitor.visit_pattern(pattern);
+            visitor.visit_block(handler);
+        }
+        Expression::Match { subject, arms, .. } => {
+            visitor.visit_expression(subject);
+            for arm in arms {
+                visitor.visit_pattern(&arm.pattern);
+                visitor.visit_block(&arm.body);
+            }
+        }
+        Expression::Conditional {
+            condition,
+            consequence,
+            alternative,
+        } => {
+            visitor.visit_expression(condition);
+            visitor.visit_expression(consequence);
+            visitor.visit_expression(alternative);
+        }
+        Expression::Function {
+            parameters, body, ..
+        } => {
+            for parameter in parameters.positional.iter() {
+                if let Some(default) = &parameter.default {
+                    visitor.visit_expression(default);
+                }
+            }
+            visitor.visit_block(body);
+        }
+        Expression::Call {
+            function,
+            arguments,
+            ..
+        } => {
+            visitor.visit_expression(function);
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+        }
+        Expression::MethodCall {
+            receiver,
+            arguments,
+            ..
+        } => {
+            visitor.visit_expression(receiver);
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+        }
+        Expression::Array(elements) | Expression::Tuple(elements) => {
+            for element in elements {
+                visitor.visit_expression(element);
+            }
+        }
+        Expression::Hash(pairs) => {
+            for (key, value) in pairs {
+                visitor.visit_expression(key);
+                visitor.visit_expression(value);
+            }
+        }
+        Expression::Index { left, index, .. } => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(index);
+        }
+        Expression::Assign { target, value, .. } => {
+            visitor.visit_expression(target);
+            visitor.visit_expression(value);
+        }
+        Expression::Range { start, end, .. } => {
+            for bound in [start, end].into_iter().flatten() {
+                visitor.visit_expression(bound);
+            }
+        }
+    })
+}
+
+pub fn walk_pattern<V: Visit + ?Sized>(visitor: &mut V, pattern: &Pattern) {
+    match pattern {
+        Pattern::Identifier(_) | Pattern::Wildcard | Pattern::Literal(_) => {}
+        Pattern::Array(patterns)
+        | Pattern::Tuple(patterns)
+        | Pattern::Variant {
+            fields: patterns, ..
+        } => {
+            for pattern in patterns {
+                visitor.visit_pattern(pattern);
+            }
+        }
+        Pattern::Hash(entries)
+        | Pattern::Struct {
+            fields: entries, ..
+        } => {
+            for (_, pattern) in entries {
+                visitor.visit_pattern(pattern);
+            }
+        }
+    }
+}
+
+/// Like [`Visit`], with mutable access to each node.
+pub trait VisitMut {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program);
+    }
+
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut BlockStatement) {
+        walk_block_mut(self, block);
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression);
+    }
+
+    fn visit_pattern_mut(&mut self, pattern: &mut Pattern) {
+        walk_pattern_mut(self, pattern);
+    }
+}
+
+pub fn walk_program_mut<V: VisitMut + ?Sized>(visitor: &mut V, program: &mut Program) {
+    for statement in &mut program.statements {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_statement_mut<V: VisitMut + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+    match statement {
+        Statement::Let(statement) => {
+            visitor.visit_pattern_mut(&mut statement.pattern);
+            visitor.visit_expression_mut(&mut statement.value);
+        }
+        Statement::Return(Some(value))
+        | Statement::Expression(value)
+        | Statement::Throw { value, .. } => {
+            visitor.visit_expression_mut(value);
+        }
+        Statement::Return(None)
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Import(_)
+        | Statement::Struct { .. }
+        | Statement::Enum { .. } => {}
+        Statement::While { condition, body } => {
+            visitor.visit_expression_mut(condition);
+            visitor.visit_block_mut(body);
+        }
+        Statement::For {
+            pattern,
+            iterable,
+            body,
+        } => {
+            visitor.visit_pattern_mut(pattern);
+            visitor.visit_expression_mut(iterable);
+            visitor.visit_block_mut(body);
+        }
+    }
+}
+
+pub fn walk_block_mut<V: VisitMut + ?Sized>(visitor: &mut V, block: &mut BlockStatement) {
+    for statement in &mut block.statements {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_expression_mut<V: VisitMut + ?Sized>(visitor: &mut V, expression: &mut Expression) {
+    with_stack(|| match expression {
+        Expression::Identifier { .. }
+        | Expression::Int(_)
+        | Expression::Float(_)
+        | Expression::Str(_)
+        | Expression::Char(_)
+        | Expression::Bool(_)
+        | Expression::Null => {}
+        Expression::Prefix { right, .. }
+        | Expression::Spread(right)
+        | Expression::Named { value: right, .. } => visitor.visit_expression_mut(right),
+        Expression::Infix { left, right, .. } => {
+            visitor.visit_expression_mut(left);
+            visitor.visit_expression_mut(right);
+        }
+        Expression::If {
+            condition,
+            consequence,
+            alternative,
+        } => {
+            visitor.visit_expression_mut(condition);
+            visitor.visit_block_mut(consequence);
+            if let Some(alternative) = alternative {
+                visitor.visit_block_mut(alternative);
+            }
+        }
+        Expression::Try {
+            body,
+            pattern,
+            handler,
+        } => {
+            visitor.visit_block_mut(body);
+            visitor.visit_pattern_mut(pattern);
+            visitor.visit_block_mut(handler);
+        }
+        Expression::Match { subject, arms, .. } => {
+            visitor.visit_expression_mut(subject);
+            for arm in arms {
+                visitor.visit_pattern_mut(&mut arm.pattern);
+                visitor.visit_block_mut(&mut arm.body);
+            }
+        }
+        Expression::Conditional {
+            condition,
+            consequence,
+            alternative,
+        } => {
+            visitor.visit_expression_mut(condition);
+            visitor.visit_expression_mut(consequence);
+            visitor.visit_expression_mut(alternative);
+        }
+        Expression::Function {
+            parameters, body, ..
+        } => {
+            for parameter in parameters.positional.iter_mut() {
+                if let Some(default) = &mut parameter.default {
+                    visitor.visit_expression_mut(default);
+                }
+            }
+            visitor.visit_block_mut(Rc::make_mut(body));
+        }
+        Expression::Call {
+            function,
+            arguments,
+            ..
+        } => {
+            visitor.visit_expression_mut(function);
+            for argument in arguments {
+                visitor.visit_expression_mut(argument);
+            }
+        }
+        Expression::MethodCall {
+            receiver,
+            arguments,
+            ..
+        } => {
+            visitor.visit_expression_mut(receiver);
+            for argument in arguments {
+                visitor.visit_expression_mut(argument);
+            }
+        }
+        Expression::Array(elements) | Expression::Tuple(elements) => {
+            for element in elements {
+                visitor.visit_expression_mut(element);
+            }
+        }
+        Expression::Hash(pairs) => {
+            for (key, value) in pairs {
+                visitor.visit_expression_mut(key);
+                visitor.visit_expression_mut(value);
+            }
+        }
+        Expression::Index { left, index, .. } => {
+            visitor.visit_expression_mut(left);
+            visitor.visit_expression_mut(index);
+        }
+        Expression::Assign { target, value, .. } => {
+            visitor.visit_expression_mut(target);
+            visitor.visit_expression_mut(value);
+        }
+        Expression::Range { start, end, .. } => {
+            for bound in [start, end].into_iter().flatten() {
+                visitor.visit_expression_mut(bound);
+            }
+        }
+    })
+}
+
+pub fn walk_pattern_mut<V: VisitMut + ?Sized>(visitor: &mut V, pattern: &mut Pattern) {
+    match pattern {
+        Pattern::Identifier(_) | Pattern::Wildcard | Pattern::Literal(_) => {}
+        Pattern::Array(patterns)
+        | Pattern::Tuple(patterns)
+        | Pattern::Variant {
+            fields: patterns, ..
+        } => {
+            for pattern in patterns {
+                visitor.visit_pattern_mut(pattern);
+            }
+        }
+        Pattern::Hash(entries)
+        | Pattern::Struct {
+            fields: entries, ..
+        } => {
+            for (_, pattern) in entries {
+                visitor.visit_pattern_mut(pattern);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{walk_expression, walk_pattern, Visit, VisitMut};
+    use crate::ast::{Expression, Pattern, Program};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(input: &str) -> Program {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+
+        assert!(
+            parser.errors().is_empty(),
+            "parser had errors: {:?}",
+            parser.errors()
+        );
+
+        program
+    }
+
+    /// Collects every name that is bound or used, in visiting order.
+    #[derive(Default)]
+    struct Names(Vec<String>);
+
+    impl Visit for Names {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if let Expression::Identifier { name, .. } = expression {
+                self.0.push(name.to_string());
+            }
+            walk_expression(self, expression);
+        }
+
+        fn visit_pattern(&mut self, pattern: &Pattern) {
+            if let Pattern::Identifier(name) = pattern {
+                self.0.push(name.to_string());
+            }
+            walk_pattern(self, pattern);
+        }
+    }
+
+    /// Renames `x` to `y` wherever it appears as an expression.
+    struct Rename;
+
+    impl VisitMut for Rename {
+        fn visit_expression_mut(&mut self, expression: &mut Expression) {
+            match expression {
+                Expression::Identifier { name, .. } if name == "x" => *name = "y".into(),
+                expression => super::walk_expression_mut(self, expression),
+            }
+        }
+    }
+
+    #[test]
+    fn test_visit() {
+        let program =
+            parse("let [a, {b}] = f(c); for (i in 0..n) { if (i) { g[i] = fn(p) { p + q }; } }");
+
+        let mut names = Names::default();
+        names.visit_program(&program);
+
+        assert_eq!(
+            vec!["a", "b", "f", "c", "i", "n", "i", "g", "i", "p", "q"],
+            names.0
+        );
+    }
+
+    #[test]
+    fn test_visit_mut() {
+        let mut program = parse("let x = x + 1; while (x) { [x, {x: -x}]; }");
+
+        Rename.visit_program_mut(&mut program);
+
+        assert_eq!(
+            "let x = y + 1;\nwhile (y) { [y, { y: -y }]; }",
+            program.to_string()
+        );
+    }
+}
@@ -0,0 +1,994 @@
+//! A stack machine that runs [`Bytecode`]. Compiled functions are
+//! [`Value::Closure`]s, which the evaluator can call like its own
+//! functions, so builtins like `map` take them, and compiled code calls the
+//! evaluator's functions, like the prelude's, through it. The evaluator
+//! also provides the builtins, the output and the limits on depth, fuel and
+//! memory.
+//!
+//! Each call has its own local slots, and each local lives in a cell of
+//! its own, so a function that captures a variable shares it with the code
+//! that declared it. Declaring a local makes a new cell, which gives each
+//! iteration of a loop its own variables, like it does in the evaluator.
+//! As there too, a call in tail position takes the place of the call it's
+//! made from, so tail recursion takes no more depth however long it runs.
+
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::rc::Rc;
+
+use indexmap::IndexMap;
+
+use crate::ast::{InfixOperator, PrefixOperator};
+use crate::code::{
+    read_u16, Bytecode, Capture, CompiledFunction, Constant, Opcode, RANGE_OPEN_END,
+    RANGE_OPEN_START,
+};
+use crate::evaluator::{
+    eval_index_expression, eval_infix_expression, eval_prefix_expression, one_line, set_index,
+    Evaluator, Frame as TraceFrame, RuntimeError,
+};
+use crate::symbol::Symbol;
+use crate::token::Span;
+use crate::value::{Range, Value, ValueIter};
+
+/// What the functions of a compiled program share.
+pub(crate) struct Module {
+    pub(crate) constants: Vec<Constant>,
+    /// The value in each global slot, or `None` until it's declared.
+    pub(crate) globals: RefCell<Vec<Option<Value>>>,
+    global_names: Vec<Symbol>,
+    /// The builtins the program uses, by index.
+    builtins: Vec<Value>,
+    #[cfg(feature = "jit")]
+    pub(crate) jit: RefCell<crate::jit::Jit>,
+}
+
+/// A compiled function with the variables it captured.
+pub struct Closure {
+    pub function: Rc<CompiledFunction>,
+    pub(crate) module: Rc<Module>,
+    free: Vec<Rc<RefCell<Value>>>,
+}
+
+impl Closure {
+    /// The variables the function captured, in the order of its
+    /// [`CompiledFunction::captures`].
+    pub(crate) fn free(&self) -> &[Rc<RefCell<Value>>] {
+        &self.free
+    }
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Closure) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl std::fmt::Debug for Closure {
+    // The captured variables are left out, since they can hold this very
+    // closure.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Closure")
+            .field("function", &self.function)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Display for Closure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parameters: Vec<&str> = self
+            .function
+            .parameters
+            .iter()
+            .map(Symbol::as_str)
+            .collect();
+        write!(f, "fn({}) {{ ... }}", parameters.join(", "))
+    }
+}
+
+/// Runs a compiled program with `evaluator`'s builtins and limits, and
+/// returns the value of its last statement, or the value passed to a
+/// top-level `return`. Globals the evaluator has, like the prelude's
+/// functions, start out declared.
+pub fn run(evaluator: &mut Evaluator, bytecode: &Bytecode) -> Result<Value, RuntimeError> {
+    let builtins = bytecode
+        .builtins
+        .iter()
+        .map(|name| {
+            evaluator.builtins().get(name.as_str()).ok_or_else(|| {
+                RuntimeError::new(format!("the program uses an unknown builtin `{name}`"))
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    let globals = bytecode
+        .globals
+        .iter()
+        .map(|name| evaluator.global(name))
+        .collect();
+
+    let module = Rc::new(Module {
+        constants: bytecode.constants.clone(),
+        globals: RefCell::new(globals),
+        global_names: bytecode.globals.clone(),
+        builtins,
+        #[cfg(feature = "jit")]
+        jit: RefCell::new(crate::jit::Jit::new()),
+    });
+    let main = Rc::new(Closure {
+        function: Rc::clone(&bytecode.main),
+        module: Rc::clone(&module),
+        free: Vec::new(),
+    });
+
+    let result = Machine::new(evaluator).execute(main, Vec::new(), None);
+    // Functions in globals hold the module, so it's only freed once
+    // they're gone.
+    module.globals.borrow_mut().clear();
+    result
+}
+
+/// Calls a compiled function from outside compiled code, as a call made at
+/// `call_site`.
+pub(crate) fn call(
+    evaluator: &mut Evaluator,
+    closure: &Rc<Closure>,
+    arguments: Vec<Value>,
+    call_site: Span,
+) -> Result<Value, RuntimeError> {
+    Machine::new(evaluator).execute(Rc::clone(closure), arguments, Some(call_site))
+}
+
+/// A call in progress.
+struct Frame {
+    closure: Rc<Closure>,
+    /// The offset of the next instruction.
+    ip: usize,
+    locals: Vec<Rc<RefCell<Value>>>,
+    /// The iterators of the `for` loops running, innermost last.
+    iterators: Vec<ValueIter>,
+    /// How high the stack was when the call started.
+    base: usize,
+    /// Where the call was made, or `None` for a program's code.
+    call_site: Option<Span>,
+}
+
+struct Machine<'a> {
+    evaluator: &'a mut Evaluator,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+}
+
+impl Machine<'_> {
+    fn new(evaluator: &mut Evaluator) -> Machine<'_> {
+        Machine {
+            evaluator,
+            stack: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Calls `closure` and runs until it returns.
+    fn execute(
+        &mut self,
+        closure: Rc<Closure>,
+        arguments: Vec<Value>,
+        call_site: Option<Span>,
+    ) -> Result<Value, RuntimeError> {
+        if let Err(error) = self.push_frame(closure, arguments, call_site) {
+            return Err(match call_site {
+                Some(call_site) => error.at(call_site),
+                None => error,
+            });
+        }
+
+        loop {
+            let frame = self.frames.last_mut().expect("a call is running");
+            let code = &frame.closure.function.code;
+            let start = frame.ip;
+            let opcode = Opcode::from_byte(code[start]).expect("compiled code only has opcodes");
+            let (first, second) = match opcode.operand_widths() {
+                [] => (0, 0),
+                [1] => (usize::from(code[start + 1]), 0),
+                [2] => (read_u16(code, start + 1), 0),
+                _ => (read_u16(code, start + 1), usize::from(code[start + 3])),
+            };
+            frame.ip = start + 1 + opcode.operand_widths().iter().sum::<usize>();
+
+            let traced = self.evaluator.is_tracing().then(|| {
+                let name = frame.closure.function.name.as_deref().unwrap_or("main");
+                let mut text = format!("{name} {start:04} {opcode}");
+                for (operand, _) in [first, second].iter().zip(opcode.operand_widths()) {
+                    text.push_str(&format!(" {operand}"));
+                }
+                (self.evaluator.depth(), text)
+            });
+
+            match self.instruction(opcode, first, second, start) {
+                Ok(Some(value)) => return Ok(value),
+                Ok(None) => {}
+                Err(error) => return Err(self.unwind(error, start)),
+            }
+
+            if let Some((depth, mut text)) = traced {
+                if let Some(top) = self.stack.last() {
+                    text.push_str(&format!(" => {}", one_line(&top.to_string())));
+                }
+                self.evaluator.trace(depth, &text);
+            }
+        }
+    }
+
+    fn push_frame(
+        &mut self,
+        closure: Rc<Closure>,
+        arguments: Vec<Value>,
+        call_site: Option<Span>,
+    ) -> Result<(), RuntimeError> {
+        check_arguments(&closure.function, &arguments)?;
+        if call_site.is_some() {
+            self.evaluator.step()?;
+            self.evaluator
+                .enter_call(closure.function.name.as_deref())?;
+        }
+        self.start_frame(closure, arguments, call_site);
+        Ok(())
+    }
+
+    /// Calls `closure` in place of the current call, which returns what it
+    /// does, so the current call's frame and depth are its.
+    fn tail_call(
+        &mut self,
+        closure: Rc<Closure>,
+        arguments: Vec<Value>,
+        call_site: Span,
+    ) -> Result<(), RuntimeError> {
+        #[cfg(feature = "jit")]
+        if let Some(value) = self.call_native(&closure, &arguments) {
+            self.stack.push(value);
+            return Ok(());
+        }
+
+        check_arguments(&closure.function, &arguments)?;
+        self.evaluator.step()?;
+
+        let frame = self.frames.pop().expect("a call is running");
+        self.stack.truncate(frame.base);
+        self.evaluator.leave_call();
+        // Having just left a call, there's room for this one.
+        self.evaluator
+            .enter_call(closure.function.name.as_deref())?;
+        self.start_frame(closure, arguments, Some(call_site));
+        Ok(())
+    }
+
+    fn start_frame(
+        &mut self,
+        closure: Rc<Closure>,
+        arguments: Vec<Value>,
+        call_site: Option<Span>,
+    ) {
+        let function = &closure.function;
+        let mut locals: Vec<_> = arguments
+            .into_iter()
+            .map(|argument| Rc::new(RefCell::new(argument)))
+            .collect();
+        locals.resize_with(function.locals, || Rc::new(RefCell::new(Value::Null)));
+
+        self.frames.push(Frame {
+            closure,
+            ip: 0,
+            locals,
+            iterators: Vec::new(),
+            base: self.stack.len(),
+            call_site,
+        });
+    }
+
+    /// Ends every call in progress because of `error`, which the
+    /// instruction at `offset` in the innermost one raised, recording where
+    /// it happened and the calls it unwound through.
+    fn unwind(&mut self, mut error: RuntimeError, offset: usize) -> RuntimeError {
+        let innermost = self.frames.last().expect("a call is running");
+        if let Some(span) = innermost.closure.function.span_at(offset) {
+            error = error.at(span);
+        }
+
+        while let Some(frame) = self.frames.pop() {
+            if let Some(call_site) = frame.call_site {
+                error.trace.push(TraceFrame {
+                    function: frame.closure.function.name.clone(),
+                    call_site,
+                });
+                self.evaluator.leave_call();
+            }
+        }
+        error
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("compiled code balances the stack")
+    }
+
+    fn top(&self) -> &Value {
+        self.stack.last().expect("compiled code balances the stack")
+    }
+
+    /// Runs one instruction, returning the value the outermost call
+    /// returned once it has.
+    fn instruction(
+        &mut self,
+        opcode: Opcode,
+        operand: usize,
+        second_operand: usize,
+        offset: usize,
+    ) -> Result<Option<Value>, RuntimeError> {
+        let frame = self.frames.last_mut().expect("a call is running");
+        let module = Rc::clone(&frame.closure.module);
+
+        match opcode {
+            Opcode::Constant => {
+                let value = match &module.constants[operand] {
+                    Constant::Int(value) => Value::Int(*value),
+                    Constant::Float(value) => Value::Float(*value),
+                    Constant::Str(value) => Value::Str(value.clone()),
+                    Constant::Char(value) => Value::Char(*value),
+                    Constant::Name(_) | Constant::Function(_) => {
+                        unreachable!("`Constant` only loads values")
+                    }
+                };
+                self.stack.push(value);
+            }
+            Opcode::Null => self.stack.push(Value::Null),
+            Opcode::True => self.stack.push(Value::Bool(true)),
+            Opcode::False => self.stack.push(Value::Bool(false)),
+            Opcode::Pop => drop(self.pop()),
+            Opcode::Dup2 => {
+                let pair = self.stack[self.stack.len() - 2..].to_vec();
+                self.stack.extend(pair);
+            }
+            Opcode::Add
+            | Opcode::Subtract
+            | Opcode::Multiply
+            | Opcode::Divide
+            | Opcode::Power
+            | Opcode::BitAnd
+            | Opcode::BitOr
+            | Opcode::BitXor
+            | Opcode::ShiftLeft
+            | Opcode::ShiftRight
+            | Opcode::Equal
+            | Opcode::NotEqual
+            | Opcode::LessThan
+            | Opcode::GreaterThan
+            | Opcode::LessThanEqual
+            | Opcode::GreaterThanEqual => {
+                let right = self.pop();
+                let left = self.pop();
+                let result = eval_infix_expression(
+                    infix_operator(opcode),
+                    left,
+                    right,
+                    self.evaluator.overflow(),
+                )?;
+                self.stack.push(result);
+            }
+            Opcode::Minus | Opcode::Not => {
+                let operator = if opcode == Opcode::Minus {
+                    PrefixOperator::Minus
+                } else {
+                    PrefixOperator::Bang
+                };
+                let right = self.pop();
+                let result = eval_prefix_expression(operator, right, self.evaluator.overflow())?;
+                self.stack.push(result);
+            }
+            Opcode::Truthy => {
+                let value = self.pop();
+                self.stack.push(Value::Bool(value.is_truthy()));
+            }
+            Opcode::Jump => {
+                // Jumping back is how loops loop, so it's where a program
+                // that runs forever uses up its fuel. An optimized empty
+                // loop jumps to itself.
+                if operand <= offset {
+                    self.evaluator.step()?;
+                }
+                frame.ip = operand;
+            }
+            Opcode::JumpIfFalse => {
+                let condition = self.stack.pop().expect("compiled code balances the stack");
+                if !condition.is_truthy() {
+                    frame.ip = operand;
+                }
+            }
+            Opcode::JumpIfNull => {
+                if *self.top() == Value::Null {
+                    self.frames.last_mut().expect("a call is running").ip = operand;
+                }
+            }
+            Opcode::JumpIfNotNull => {
+                if *self.top() == Value::Null {
+                    self.pop();
+                } else {
+                    self.frames.last_mut().expect("a call is running").ip = operand;
+                }
+            }
+            Opcode::GetGlobal => {
+                let value = module.globals.borrow()[operand].clone().ok_or_else(|| {
+                    RuntimeError::new(format!(
+                        "identifier not found: {}",
+                        module.global_names[operand]
+                    ))
+                })?;
+                self.stack.push(value);
+            }
+            Opcode::SetGlobal => {
+                let value = self.top().clone();
+                match &mut module.globals.borrow_mut()[operand] {
+                    Some(global) => *global = value,
+                    None => {
+                        return Err(RuntimeError::new(format!(
+                            "cannot assign to undeclared variable `{}`",
+                            module.global_names[operand]
+                        )))
+                    }
+                }
+            }
+            Opcode::DefineGlobal => {
+                let value = self.pop();
+                module.globals.borrow_mut()[operand] = Some(value);
+            }
+            Opcode::GetLocal => {
+                let value = frame.locals[operand].borrow().clone();
+                self.stack.push(value);
+            }
+            Opcode::SetLocal => {
+                let value = self.stack.last().expect("compiled code balances the stack");
+                *frame.locals[operand].borrow_mut() = value.clone();
+            }
+            Opcode::DefineLocal => {
+                let value = self.stack.pop().expect("compiled code balances the stack");
+                frame.locals[operand] = Rc::new(RefCell::new(value));
+            }
+            Opcode::GetFree => {
+                let value = frame.closure.free[operand].borrow().clone();
+                self.stack.push(value);
+            }
+            Opcode::SetFree => {
+                let value = self.stack.last().expect("compiled code balances the stack");
+                *frame.closure.free[operand].borrow_mut() = value.clone();
+            }
+            Opcode::GetBuiltin => self.stack.push(module.builtins[operand].clone()),
+            Opcode::Array => {
+                let elements = self.stack.split_off(self.stack.len() - operand);
+                self.stack.push(Value::array(elements));
+            }
+            Opcode::Hash => {
+                let mut values = self
+                    .stack
+                    .split_off(self.stack.len() - 2 * operand)
+                    .into_iter();
+                let mut hash = IndexMap::new();
+                while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                    let key = key.hash_key().ok_or_else(|| {
+                        RuntimeError::new(format!("unusable as hash key: {}", key.type_name()))
+                    })?;
+                    hash.insert(key, value);
+                }
+                self.stack.push(Value::hash(hash));
+            }
+            Opcode::Range | Opcode::RangeInclusive => {
+                let end = self.pop();
+                let start = self.pop();
+                let (Value::Int(start), Value::Int(end)) = (&start, &end) else {
+                    return Err(RuntimeError::new(format!(
+                        "range bounds must be Int, got {} and {}",
+                        start.type_name(),
+                        end.type_name()
+                    )));
+                };
+                self.stack.push(Value::Range(Range::new(
+                    (operand & RANGE_OPEN_START == 0).then_some(*start),
+                    (operand & RANGE_OPEN_END == 0).then_some(*end),
+                    opcode == Opcode::RangeInclusive,
+                )));
+            }
+            Opcode::Index => {
+                let index = self.pop();
+                let left = self.pop();
+                self.stack.push(eval_index_expression(left, index)?);
+            }
+            Opcode::SetIndex => {
+                let value = self.pop();
+                let index = self.pop();
+                let left = self.pop();
+                set_index(&left, index, value.clone())?;
+                self.evaluator.track_store(&left, &value);
+                self.stack.push(value);
+            }
+            Opcode::Call => {
+                let call_site = frame.closure.function.span_at(offset).unwrap_or_default();
+                let arguments = self.stack.split_off(self.stack.len() - operand);
+                let function = self.pop();
+                self.call(function, arguments, call_site)?;
+            }
+            Opcode::TailCall => {
+                // A program's own code isn't a call to replace.
+                let in_call = frame.call_site.is_some();
+                let call_site = frame.closure.function.span_at(offset).unwrap_or_default();
+                let arguments = self.stack.split_off(self.stack.len() - operand);
+                match self.pop() {
+                    Value::Closure(closure) if in_call => {
+                        self.tail_call(closure, arguments, call_site)?
+                    }
+                    function => self.call(function, arguments, call_site)?,
+                }
+            }
+            Opcode::CallMethod => {
+                let call_site = frame.closure.function.span_at(offset).unwrap_or_default();
+                let Constant::Name(method) = &module.constants[operand] else {
+                    unreachable!("methods are called by name")
+                };
+                let mut arguments = self.stack.split_off(self.stack.len() - second_operand);
+                let receiver = self.pop();
+                let (function, receiver) = self
+                    .evaluator
+                    .builtins()
+                    .method_call(receiver, method.as_str())?;
+                if let Some(receiver) = receiver {
+                    arguments.insert(0, receiver);
+                }
+                self.call(function, arguments, call_site)?;
+            }
+            Opcode::Return => {
+                let value = self.pop();
+                let frame = self.frames.pop().expect("a call is running");
+                self.stack.truncate(frame.base);
+                if frame.call_site.is_some() {
+                    self.evaluator.leave_call();
+                }
+                if self.frames.is_empty() {
+                    return Ok(Some(value));
+                }
+                self.stack.push(value);
+            }
+            Opcode::Closure => {
+                let Constant::Function(function) = &module.constants[operand] else {
+                    unreachable!("closures are made from functions")
+                };
+                let free = function
+                    .captures
+                    .iter()
+                    .map(|capture| match capture {
+                        Capture::Local(slot) => Rc::clone(&frame.locals[*slot]),
+                        Capture::Free(index) => Rc::clone(&frame.closure.free[*index]),
+                    })
+                    .collect();
+                let closure = Rc::new(Closure {
+                    function: Rc::clone(function),
+                    module: Rc::clone(&module),
+                    free,
+                });
+                if !closure.free.is_empty() {
+                    self.evaluator.track_closure(&closure);
+                }
+                self.stack.push(Value::Closure(closure));
+            }
+            Opcode::GetIter => {
+                let iterable = self.stack.pop().expect("compiled code balances the stack");
+                let iterator = iterable.iter().ok_or_else(|| {
+                    RuntimeError::new(format!("cannot iterate over {}", iterable.type_name()))
+                })?;
+                frame.iterators.push(iterator);
+            }
+            Opcode::IterNext => {
+                let iterator = frame.iterators.last_mut().expect("a loop is running");
+                match iterator.next() {
+                    Some(item) => self.stack.push(item),
+                    None => {
+                        frame.iterators.pop();
+                        frame.ip = operand;
+                    }
+                }
+            }
+            Opcode::PopIter => drop(frame.iterators.pop()),
+        }
+
+        Ok(None)
+    }
+
+    /// Calls `function` from the current call, pushing what it returns, or
+    /// for a compiled function starting a call to it.
+    fn call(
+        &mut self,
+        function: Value,
+        arguments: Vec<Value>,
+        call_site: Span,
+    ) -> Result<(), RuntimeError> {
+        match function {
+            #[cfg(feature = "jit")]
+            Value::Closure(closure) => match self.call_native(&closure, &arguments) {
+                Some(value) => {
+                    self.stack.push(value);
+                    Ok(())
+                }
+                None => self.push_frame(closure, arguments, Some(call_site)),
+            },
+            #[cfg(not(feature = "jit"))]
+            Value::Closure(closure) => self.push_frame(closure, arguments, Some(call_site)),
+            function => {
+                let value = self.evaluator.call_at(function, arguments, call_site)?;
+                self.stack.push(value);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "jit")]
+impl Machine<'_> {
+    /// Calls `closure` as native code, if it has some, returning `None`
+    /// if the VM has to run it instead.
+    fn call_native(&mut self, closure: &Closure, arguments: &[Value]) -> Option<Value> {
+        if self.evaluator.profiler().is_some() || self.evaluator.is_tracing() {
+            return None;
+        }
+        let (value, fuel) = crate::jit::call(
+            &closure.module,
+            &closure.function,
+            arguments,
+            self.evaluator.fuel(),
+            self.evaluator.calls_left(),
+        )?;
+        if fuel.is_some() {
+            self.evaluator.set_fuel(fuel);
+        }
+        Some(value)
+    }
+}
+
+/// Checks `function` was given as many arguments as it takes.
+fn check_arguments(function: &CompiledFunction, arguments: &[Value]) -> Result<(), RuntimeError> {
+    if arguments.len() != function.parameters.len() {
+        return Err(RuntimeError::new(format!(
+            "wrong number of arguments: expected {}, got {}",
+            function.parameters.len(),
+            arguments.len()
+        )));
+    }
+    Ok(())
+}
+
+fn infix_operator(opcode: Opcode) -> InfixOperator {
+    match opcode {
+        Opcode::Add => InfixOperator::Plus,
+        Opcode::Subtract => InfixOperator::Minus,
+        Opcode::Multiply => InfixOperator::Multiply,
+        Opcode::Divide => InfixOperator::Divide,
+        Opcode::Power => InfixOperator::Power,
+        Opcode::BitAnd => InfixOperator::BitAnd,
+        Opcode::BitOr => InfixOperator::BitOr,
+        Opcode::BitXor => InfixOperator::BitXor,
+        Opcode::ShiftLeft => InfixOperator::ShiftLeft,
+        Opcode::ShiftRight => InfixOperator::ShiftRight,
+        Opcode::Equal => InfixOperator::Eq,
+        Opcode::NotEqual => InfixOperator::NotEq,
+        Opcode::LessThan => InfixOperator::LessThan,
+        Opcode::GreaterThan => InfixOperator::GreaterThan,
+        Opcode::LessThanEqual => InfixOperator::LessThanEq,
+        Opcode::GreaterThanEqual => InfixOperator::GreaterThanEq,
+        _ => unreachable!("{opcode} isn't an infix operator"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::run;
+    use crate::builtins::test::{eval as evaluate, parse};
+    use crate::code::Bytecode;
+    use crate::compiler::compile;
+    use crate::evaluator::{Evaluator, RuntimeError};
+    use crate::value::Value;
+
+    fn eval_with(evaluator: &mut Evaluator, input: &str) -> Result<Value, RuntimeError> {
+        let bytecode = compile(&parse(input), evaluator.builtins()).expect("the program compiles");
+        run(evaluator, &bytecode)
+    }
+
+    fn eval(input: &str) -> Result<Value, RuntimeError> {
+        eval_with(&mut Evaluator::new(), input)
+    }
+
+    /// Checks each program gives the same result compiled as it does
+    /// evaluated, and that it's the one expected.
+    fn assert_same_as_evaluator(tests: Vec<(&str, &str)>) {
+        for (input, expected) in tests {
+            let compiled = eval(input).map(|value| value.to_string());
+            let evaluated = evaluate(input).map(|value| value.to_string());
+            assert_eq!(Ok(expected.to_string()), compiled, "{input}");
+            assert_eq!(evaluated, compiled, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_expressions() {
+        assert_same_as_evaluator(vec![
+            ("1 + 2 * 3 - 4 / 2", "5"),
+            ("2 ** 10 - 1000", "24"),
+            ("-(1.5) * 2", "-3.0"),
+            ("!true == false", "true"),
+            ("1 < 2 && 2 > 3 || \"s\"", "true"),
+            ("null && x", "false"),
+            ("null ?? 1 ?? 2", "1"),
+            ("false ?? 1", "false"),
+            ("let a = null; a?[x]", "null"),
+            ("[1, [2, 3]][1][0]", "2"),
+            ("{\"a\": 1, 2: [3]}[2]", "[3]"),
+            ("\"tofu\"[1]", "o"),
+            ("[1, 2, 3][-1] + [1, 2, 3][..-1][-1]", "5"),
+            ("\"tofu\"[2..] + \"tofu\"[..1]", "fut"),
+            ("0..3", "0..3"),
+            ("if (1 > 2) { 1 } else if (false) { 2 } else { 3 }", "3"),
+            ("if (false) { 1 }", "null"),
+            ("true ? 1 : 2", "1"),
+            ("6 & 3 | 8 ^ 1 << 2", "14"),
+            ("[1, 2, 3].len()", "3"),
+            ("len(\"abc\") + abs(-1)", "4"),
+            ("math.sqrt(4) + math.pi - pi", "2.0"),
+        ]);
+    }
+
+    #[test]
+    fn test_bindings_and_assignment() {
+        assert_same_as_evaluator(vec![
+            ("let a = 1; let b = a + 1; b", "2"),
+            ("let a = 1; if (true) { let a = 2; a }", "2"),
+            ("let a = 1; if (true) { let a = 2; } a", "1"),
+            ("let a = 1; if (true) { a = 2; } a", "2"),
+            ("let a = 5; a *= 2; a -= 1", "9"),
+            ("let xs = [1, 2]; xs[0] += 10; xs", "[11, 2]"),
+            ("let h = {}; h[\"k\"] = 1; h", "{\"k\": 1}"),
+            ("let x = 1; let x = x + 1; x", "2"),
+            ("if (true) { let x = 1; let x = x + 1; x }", "2"),
+        ]);
+    }
+
+    #[test]
+    fn test_loops() {
+        assert_same_as_evaluator(vec![
+            ("let i = 0; while (i < 5) { i += 1; } i", "5"),
+            (
+                "let i = 0; let s = 0; while (true) { i += 1; if (i > 5) { break; } \
+                 if (i == 2 || i == 4) { continue; } s += i; } s",
+                "9",
+            ),
+            ("let s = 0; for (x in [1, 2, 3]) { s += x; } s", "6"),
+            ("let s = 0; for (x in 1..=4) { s += x; } s", "10"),
+            ("let s = 0; for (_ in 1..=4) { s += 1; } let _ = s; s", "4"),
+            (
+                "let s = []; for (pair in {\"a\": 1, \"b\": 2}) { push(s, pair[0]); } s",
+                "[\"a\", \"b\"]",
+            ),
+            (
+                "let s = 0; for (x in 0..10) { for (y in 0..10) { if (y > x) { break; } \
+                 s += 1; } } s",
+                "55",
+            ),
+            (
+                "let s = 0; for (x in 0..10) { if (x == 3) { break; } s += x; } s",
+                "3",
+            ),
+            ("for (x in []) {}", "null"),
+        ]);
+    }
+
+    #[test]
+    fn test_functions_and_closures() {
+        assert_same_as_evaluator(vec![
+            ("let add = fn(a, b) { a + b }; add(1, 2)", "3"),
+            ("fn(x) { return x * 2; x }(21)", "42"),
+            (
+                "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15)",
+                "610",
+            ),
+            (
+                "let counter = fn() { let n = 0; fn() { n += 1 } }; let c = counter(); c(); c()",
+                "2",
+            ),
+            (
+                "let adder = fn(a) { fn(b) { fn(c) { a + b + c } } }; adder(1)(2)(3)",
+                "6",
+            ),
+            (
+                "let fs = []; for (i in 0..3) { push(fs, fn() { i }); } map(fs, fn(f) { f() })",
+                "[0, 1, 2]",
+            ),
+            (
+                "let even = fn(n) { n == 0 ? true : odd(n - 1) }; \
+                 let odd = fn(n) { n == 0 ? false : even(n - 1) }; even(7)",
+                "false",
+            ),
+            (
+                "fn() { let fact = fn(n) { n < 2 ? 1 : n * fact(n - 1) }; fact(5) }()",
+                "120",
+            ),
+            // Local functions can call the ones declared after them, as
+            // their globals can.
+            (
+                "fn() { let even = fn(n) { n == 0 ? true : odd(n - 1) }; \
+                 let odd = fn(n) { n == 0 ? false : even(n - 1) }; even(10) }()",
+                "true",
+            ),
+            (
+                "let fs = []; for (i in 0..3) { let a = fn() { b() }; let b = fn() { i }; \
+                 push(fs, a); } map(fs, fn(f) { f() })",
+                "[0, 1, 2]",
+            ),
+            (
+                "if (true) { const f = fn(n) { n == 0 ? 0 : g(n - 1) }; \
+                 const g = fn(n) { f(n) }; f(3) }",
+                "0",
+            ),
+            ("filter([1, 2, 3, 4], fn(x) { x > 2 })", "[3, 4]"),
+            ("reduce([1, 2, 3], 0, fn(a, b) { a + b })", "6"),
+            ("let f = fn() {}; f()", "null"),
+            ("type(fn() {})", "Function"),
+        ]);
+    }
+
+    #[test]
+    fn test_prelude() {
+        let mut evaluator = Evaluator::new();
+        evaluator.load_prelude();
+
+        assert_eq!(
+            Ok(Value::Int(6)),
+            eval_with(&mut evaluator, "sum([1, 2, 3])")
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let tests = vec![
+            ("1 + true", "1:3: type mismatch: Int + Bool"),
+            ("x", "1:1: identifier not found: x"),
+            ("x = 1", "1:3: cannot assign to undeclared variable `x`"),
+            (
+                "let f = fn() { later }; f()",
+                "1:16: identifier not found: later\n    in f, called from 1:26",
+            ),
+            (
+                "let f = fn(a) { a }; f(1, 2)",
+                "1:23: wrong number of arguments: expected 1, got 2",
+            ),
+            ("5()", "1:2: not a function: Int"),
+            ("1.upper()", "1:2: Int has no method `upper`"),
+            ("math.cbrt(8)", "1:5: Hash has no method `cbrt`"),
+            ("0 ** -1", "1:3: `**` is undefined for 0 and -1"),
+            ("for (x in 5) {}", "cannot iterate over Int"),
+            ("1..\"a\"", "1:2: range bounds must be Int, got Int and Str"),
+            (
+                "[1, 2, 3][5..]",
+                "1:10: slice 5.. out of bounds for an array of length 3",
+            ),
+            (
+                "\"abc\"[..=-5]",
+                "1:6: slice ..=-5 out of bounds for a string of length 3",
+            ),
+            (
+                "map([1], fn(x) { x + null })",
+                "1:20: type mismatch: Int + Null\n    in anonymous function, called from 1:4",
+            ),
+            (
+                "let f = fn(n) { 1 + f(n + 1) }; f(0)",
+                "1:22: maximum recursion depth 1000 exceeded\n    in f, called from 1:22",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let error = eval(input).expect_err(input);
+            let message = error.to_string();
+            assert!(
+                message.starts_with(expected),
+                "{input}: expected {expected:?}, got {message:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_deep_recursion() {
+        // Calls are frames on the VM's own stack, so recursing far deeper
+        // than a test thread's stack allows only needs the limit raised.
+        let mut evaluator = Evaluator::new();
+        evaluator.set_max_depth(1_000_000);
+        let input = "let count = fn(n) { n == 0 ? 0 : 1 + count(n - 1) }; \
+                     let even = fn(n) { n == 0 ? true : !even(n - 1) }; \
+                     [count(200000), even(100001)]";
+        assert_eq!(
+            "[200000, false]",
+            eval_with(&mut evaluator, input).unwrap().to_string()
+        );
+
+        evaluator.set_max_depth(100_000);
+        let error = eval_with(&mut evaluator, "let f = fn(n) { f(n + 1) + 1 }; f(0)").unwrap_err();
+        assert!(
+            error
+                .message
+                .starts_with("maximum recursion depth 100000 exceeded"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_long_operator_chains() {
+        let sum = format!("1{}", " + 1".repeat(20_000));
+        let pipeline = format!("let f = fn(x) {{ x + 1 }}; 0{}", " |> f".repeat(20_000));
+        assert_same_as_evaluator(vec![(&sum, "20001"), (&pipeline, "20000")]);
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        // With a limit of one call at a time, only tail calls can go on,
+        // as they do in the evaluator.
+        let tests = vec![
+            (
+                "let sum = fn(n, acc) { if (n == 0) { acc } else { sum(n - 1, acc + n) } }; \
+                 sum(100000, 0)",
+                "5000050000",
+            ),
+            (
+                "let even = fn(n) { if (n == 0) { return true; } return odd(n - 1); }; \
+                 let odd = fn(n) { if (n == 0) { false } else { even(n - 1) } }; even(100001)",
+                "false",
+            ),
+            (
+                "let f = fn(n) { n > 0 ? f(n - 1) : len(\"done\") }; f(100000)",
+                "4",
+            ),
+        ];
+        for (input, expected) in tests {
+            let mut evaluator = Evaluator::new();
+            evaluator.set_max_depth(1);
+            let value = eval_with(&mut evaluator, input).map(|value| value.to_string());
+            assert_eq!(Ok(expected.to_string()), value, "{input}");
+        }
+
+        // Calls whose result is still needed aren't tail calls, and a
+        // failed tail call is reported where it was made.
+        let mut evaluator = Evaluator::new();
+        evaluator.set_max_depth(1);
+        let error = eval_with(
+            &mut evaluator,
+            "let f = fn(n) { if (n > 0) { 1 + f(n - 1) } }; f(1)",
+        );
+        assert_eq!(
+            "maximum recursion depth 1 exceeded",
+            error.unwrap_err().message
+        );
+        assert_eq!(
+            "1:20: not a function: Int\n    in f, called from 1:27",
+            eval("let f = fn() { 1; 2() }; f()")
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_fuel() {
+        let mut evaluator = Evaluator::with_fuel(1000);
+
+        let error = eval_with(&mut evaluator, "while (true) {}").unwrap_err();
+        assert_eq!("out of fuel", error.message);
+    }
+
+    #[test]
+    fn test_decoded_bytecode() {
+        let program =
+            parse("let sq = fn(x) { x * x }; let s = 0; for (i in 0..4) { s += sq(i); } s");
+        let bytecode = compile(&program, Evaluator::new().builtins()).unwrap();
+
+        let decoded = Bytecode::decode(&bytecode.encode()).unwrap();
+        assert_eq!(bytecode, decoded);
+        assert_eq!(Ok(Value::Int(14)), run(&mut Evaluator::new(), &decoded));
+    }
+}
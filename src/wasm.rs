@@ -0,0 +1,107 @@
+//! The JavaScript API of the WebAssembly build, for running Tofu in a
+//! browser, like in a playground. Build it with
+//! `wasm-pack build --features wasm`, then:
+//!
+//! ```js
+//! import init, { Tofu } from "./pkg/tofu_interpreter.js";
+//!
+//! await init();
+//! const tofu = new Tofu();
+//! tofu.eval('let greet = fn(name) { println("hi " + name) }');
+//! tofu.eval('greet("web")');  // "null"
+//! tofu.takeOutput();          // "hi web\n"
+//! ```
+//!
+//! There's no stdin or stdout in a browser, so what programs print is kept
+//! for [`Tofu::take_output`], and `input()` reads nothing.
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::interpreter::Interpreter;
+
+/// What the program printed and the caller hasn't taken yet.
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An [`Interpreter`] for JavaScript, keeping what each piece of source
+/// binds for the pieces run after it, as the REPL does.
+#[wasm_bindgen]
+pub struct Tofu {
+    interpreter: Interpreter,
+    output: Output,
+}
+
+impl Default for Tofu {
+    fn default() -> Tofu {
+        Tofu::new()
+    }
+}
+
+#[wasm_bindgen]
+impl Tofu {
+    /// An interpreter with the standard builtins and the prelude loaded.
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Tofu {
+        let output = Output::default();
+        let mut interpreter = Interpreter::new();
+        let evaluator = interpreter.evaluator();
+        evaluator.set_output(Box::new(output.clone()));
+        evaluator.set_input(Box::new(std::io::empty()));
+        Tofu {
+            interpreter,
+            output,
+        }
+    }
+
+    /// Runs `source` and returns its value as the REPL would show it, or
+    /// throws an `Error` saying what went wrong.
+    pub fn eval(&mut self, source: &str) -> Result<String, JsError> {
+        self.interpreter
+            .eval(source)
+            .map(|value| value.to_string())
+            .map_err(|error| JsError::new(&error.to_string()))
+    }
+
+    /// What the programs run so far have printed since the last call.
+    #[wasm_bindgen(js_name = takeOutput)]
+    pub fn take_output(&mut self) -> String {
+        let bytes = std::mem::take(&mut *self.output.0.borrow_mut());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Tofu;
+
+    // Errors are JavaScript objects, which only exist in WebAssembly, so
+    // only what succeeds can be tested natively.
+    #[test]
+    fn test_tofu() {
+        let mut tofu = Tofu::new();
+        assert_eq!(
+            "null",
+            tofu.eval("let greet = fn(name) { println(\"hi \" + name) }")
+                .unwrap()
+        );
+        assert_eq!("null", tofu.eval("greet(\"web\")").unwrap());
+        assert_eq!("[1, 2]", tofu.eval("print(1); [1, 2]").unwrap());
+        assert_eq!("hi web\n1", tofu.take_output());
+        assert_eq!("", tofu.take_output());
+        assert_eq!("null", tofu.eval("input()").unwrap());
+    }
+}
@@ -0,0 +1,22 @@
+use std::process::Command;
+
+fn tofu(args: &[&str]) -> (bool, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_tofu_interpreter"))
+        .args(args)
+        .output()
+        .expect("the interpreter runs");
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+#[test]
+fn test_vm_with_a_high_recursion_limit() {
+    let program = "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } }; println(f(150000))";
+    assert_eq!(
+        (true, "150000\n".to_string(), String::new()),
+        tofu(&["--engine=vm", "--max-depth=200000", "-e", program])
+    );
+}
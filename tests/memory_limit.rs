@@ -0,0 +1,14 @@
+//! A test binary without the counting allocator, as a host application
+//! might be.
+
+use tofu_interpreter::Evaluator;
+
+#[test]
+fn test_memory_limit_without_the_counting_allocator() {
+    let mut evaluator = Evaluator::new();
+    assert_eq!(
+        "a memory limit needs `heap::CountingAllocator` as the global allocator",
+        evaluator.set_max_memory(Some(1 << 20)).unwrap_err().message
+    );
+    assert_eq!(Ok(()), evaluator.set_max_memory(None));
+}